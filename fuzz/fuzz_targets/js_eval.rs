@@ -0,0 +1,21 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use worthless_js_rt::{Context, Runtime};
+
+/// How many jobs a script may queue before it's cut off.
+const MAX_JOBS: usize = 1000;
+
+// scripts that never finish are reported as timeouts by libFuzzer
+fuzz_target!(|source: &str| {
+    let rt = Runtime::new().unwrap();
+    let ctx = Context::new(&rt).unwrap();
+    if let Ok(value) = ctx.eval(source) {
+        value.to_string_lossy();
+    }
+    for _ in 0..MAX_JOBS {
+        if !matches!(rt.run_pending_job(), Ok(true)) {
+            break;
+        }
+    }
+    rt.run_gc();
+});
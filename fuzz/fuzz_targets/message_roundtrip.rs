@@ -0,0 +1,40 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use worthless_bridge::{Encoding, Request, Response};
+
+// writes messages back to back and reads them again
+fuzz_target!(|messages: (Vec<Request>, Vec<Response>)| {
+    let (requests, responses) = messages;
+
+    let mut buf = Vec::new();
+    for req in &requests {
+        req.serialize_to(&mut buf).unwrap();
+    }
+    let mut rest = &buf[..];
+    for req in &requests {
+        let (size, envelope) = Request::peek(rest).unwrap();
+        assert_eq!(envelope.endpoint(), req.endpoint());
+        let decoded = Request::deserialize(&rest[..size]).unwrap();
+        assert_eq!(decoded.serialize().unwrap(), req.serialize().unwrap());
+        let canonical = req.serialize_with(Encoding::Canonical).unwrap();
+        let decoded = Request::deserialize(&canonical).unwrap();
+        assert_eq!(
+            decoded.serialize_with(Encoding::Canonical).unwrap(),
+            canonical
+        );
+        rest = &rest[size..];
+    }
+    assert!(rest.is_empty());
+
+    let mut buf = Vec::new();
+    for resp in &responses {
+        resp.serialize_to(&mut buf).unwrap();
+    }
+    let mut reader = &buf[..];
+    for resp in &responses {
+        let decoded = Response::deserialize_from(&mut reader).unwrap();
+        assert_eq!(decoded.kind(), resp.kind());
+        assert_eq!(decoded.serialize().unwrap(), resp.serialize().unwrap());
+    }
+    assert!(reader.is_empty());
+});
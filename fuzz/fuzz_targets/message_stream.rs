@@ -0,0 +1,21 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use worthless_bridge::{Request, Response};
+
+// reads a stream of messages the way the host and guest do
+fuzz_target!(|data: &[u8]| {
+    let mut rest = data;
+    while let Ok((size, envelope)) = Request::peek(rest) {
+        assert!(size > 0 && size <= rest.len());
+        let mut reader = rest;
+        if let Ok(req) = Request::deserialize_from(&mut reader) {
+            assert_eq!(rest.len() - reader.len(), size);
+            assert_eq!(req.endpoint(), envelope.endpoint());
+            assert_eq!(req.request_id(), envelope.request_id());
+        }
+        rest = &rest[size..];
+    }
+
+    let mut reader = data;
+    while Response::deserialize_from(&mut reader).is_ok() {}
+});
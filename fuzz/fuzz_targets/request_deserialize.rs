@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use worthless_bridge::{Request, Response};
+
+// whatever decodes has to encode again and come back the same
+fuzz_target!(|data: &[u8]| {
+    if let Ok(req) = Request::deserialize(data) {
+        let bytes = req.serialize().expect("failed to serialize request");
+        let again = Request::deserialize(&bytes).expect("failed to deserialize request");
+        assert_eq!(again.serialize().unwrap(), bytes);
+    }
+    if let Ok(resp) = Response::deserialize(data) {
+        let bytes = resp.serialize().expect("failed to serialize response");
+        let again = Response::deserialize(&bytes).expect("failed to deserialize response");
+        assert_eq!(again.serialize().unwrap(), bytes);
+    }
+});
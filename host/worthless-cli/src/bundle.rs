@@ -0,0 +1,77 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context as _, Error};
+use worthless_host::sections::{append_custom_section, canonicalize, find_custom_section};
+use worthless_host::{EmbeddedBundle, BUNDLE_SECTION};
+use worthless_js_rt::{Context, Runtime};
+
+use crate::utils::js_error;
+
+/// The environment variable with the path to the prebuilt runtime.
+pub const RUNTIME_ENV: &str = "WORTHLESS_RUNTIME";
+
+/// Creates a plugin from a JavaScript bundle.
+///
+/// The plugin is a copy of the prebuilt runtime (`worthless-runtime`) with
+/// the bundle stored in a custom section.
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// The JavaScript entry point.
+    pub path: PathBuf,
+    /// Where to write the plugin.
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// The prebuilt runtime.  Defaults to `$WORTHLESS_RUNTIME`.
+    #[arg(long)]
+    pub runtime: Option<PathBuf>,
+    /// Embeds bytecode instead of the source.
+    #[arg(long)]
+    pub bytecode: bool,
+    /// Writes the sections in a fixed order and drops debug info so the same
+    /// bundle always gives the same plugin.
+    #[arg(long)]
+    pub reproducible: bool,
+}
+
+pub fn execute(args: Args) -> Result<i32, Error> {
+    let runtime_path = args
+        .runtime
+        .or_else(|| env::var_os(RUNTIME_ENV).map(PathBuf::from))
+        .ok_or_else(|| anyhow!("no runtime given, pass --runtime or set {}", RUNTIME_ENV))?;
+    let mut module = fs::read(&runtime_path)
+        .with_context(|| format!("cannot read runtime {}", runtime_path.display()))?;
+    if find_custom_section(&module, BUNDLE_SECTION)?.is_some() {
+        bail!("{} already contains a bundle", runtime_path.display());
+    }
+
+    let source =
+        fs::read(&args.path).with_context(|| format!("cannot read {}", args.path.display()))?;
+    let name = args
+        .path
+        .file_name()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let data = if args.bytecode {
+        let source = String::from_utf8(source).context("bundle is not valid UTF-8")?;
+        let rt = Runtime::new().map_err(js_error)?;
+        let ctx = Context::new(&rt).map_err(js_error)?;
+        ctx.compile(&source, &name).map_err(js_error)?
+    } else {
+        source
+    };
+
+    let bundle = EmbeddedBundle {
+        name,
+        bytecode: args.bytecode,
+        data,
+    };
+    append_custom_section(&mut module, BUNDLE_SECTION, &bundle.to_section());
+    if args.reproducible {
+        module = canonicalize(&module)?;
+    }
+    fs::write(&args.output, module)
+        .with_context(|| format!("cannot write {}", args.output.display()))?;
+    Ok(0)
+}
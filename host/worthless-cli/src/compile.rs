@@ -0,0 +1,41 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context as _, Error};
+use worthless_js_rt::{Context, Runtime};
+
+use crate::utils::js_error;
+
+/// Compiles a JavaScript bundle to QuickJS bytecode.
+///
+/// The output can be embedded with `worthless_build::embed_bytecode` or is
+/// produced directly by `worthless_build::embed_compiled_bundle`.  QuickJS
+/// only loads bytecode from the same version and configuration so the guest
+/// must be built with the default features.
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// The bundle to compile.
+    pub path: PathBuf,
+    /// Where to write the bytecode.
+    #[arg(short, long)]
+    pub output: PathBuf,
+}
+
+pub fn execute(args: Args) -> Result<i32, Error> {
+    let source = fs::read_to_string(&args.path)
+        .with_context(|| format!("cannot read {}", args.path.display()))?;
+    // the guest evaluates bundles under their file name, so the bytecode
+    // refers to them the same way in stack traces.
+    let filename = args
+        .path
+        .file_name()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let rt = Runtime::new().map_err(js_error)?;
+    let ctx = Context::new(&rt).map_err(js_error)?;
+    let bytecode = ctx.compile(&source, &filename).map_err(js_error)?;
+    fs::write(&args.output, bytecode)
+        .with_context(|| format!("cannot write {}", args.output.display()))?;
+    Ok(0)
+}
@@ -0,0 +1,37 @@
+use worthless_js_rt::{Context, Error, Primitive, Value};
+
+/// Installs a console that writes to stdout and stderr.
+///
+/// `console.warn` and `console.error` go to stderr, everything else to
+/// stdout.
+pub fn install(ctx: &Context) -> Result<(), Error> {
+    let console = Value::new_object(ctx);
+    console.set_property("log", Value::from_func(ctx, "log", log)?)?;
+    console.set_property("info", Value::from_func(ctx, "info", log)?)?;
+    console.set_property("debug", Value::from_func(ctx, "debug", log)?)?;
+    console.set_property("warn", Value::from_func(ctx, "warn", error)?)?;
+    console.set_property("error", Value::from_func(ctx, "error", error)?)?;
+    ctx.with_global(|global| global.set_property("console", console))
+}
+
+/// Formats the arguments of a console call.
+pub fn format_args(args: &[Value]) -> String {
+    let mut buf = String::new();
+    for (idx, arg) in args.iter().enumerate() {
+        if idx > 0 {
+            buf.push(' ');
+        }
+        buf.push_str(&arg.to_string_lossy());
+    }
+    buf
+}
+
+fn log(ctx: &Context, _this: &Value, args: &[Value]) -> Result<Value, Error> {
+    println!("{}", format_args(args));
+    Ok(Value::from_primitive(ctx, Primitive::Undefined))
+}
+
+fn error(ctx: &Context, _this: &Value, args: &[Value]) -> Result<Value, Error> {
+    eprintln!("{}", format_args(args));
+    Ok(Value::from_primitive(ctx, Primitive::Undefined))
+}
@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context as _, Error};
+use worthless_bridge::PROTOCOL_SECTION;
+use worthless_host::sections::{custom_sections, exports, imports, ExternKind};
+use worthless_host::{
+    EmbeddedBundle, HostConfig, Plugin, Quota, QuotaRegistry, WasiPolicy, WasiRule, BUNDLE_SECTION,
+};
+
+use crate::utils::host_error;
+
+/// The function every plugin exports to receive requests.
+const ENTRY_POINT: &str = "worthless_handle_request";
+
+/// The import module of the WASI functions.
+const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+/// The fuel the plugin may use to list its endpoints unless `--fuel` is
+/// given.
+const DEFAULT_FUEL: u64 = 1_000_000_000;
+
+/// Prints what a plugin contains and what it requires from the host.
+///
+/// The endpoints are only known once the plugin registered its handlers, so
+/// the plugin is loaded and initialized to list them.  As the plugin might
+/// not be trusted it runs without access to files, on clocks that stand
+/// still and with a limited amount of fuel.
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// The plugin to inspect.
+    pub plugin: PathBuf,
+    /// The fuel the plugin may use to list its endpoints.
+    #[arg(long, default_value_t = DEFAULT_FUEL)]
+    pub fuel: u64,
+}
+
+pub fn execute(args: Args) -> Result<i32, Error> {
+    let module =
+        fs::read(&args.plugin).with_context(|| format!("cannot read {}", args.plugin.display()))?;
+    let exports = exports(&module).context("invalid module")?;
+    let imports = imports(&module).context("invalid module")?;
+    let sections = custom_sections(&module).context("invalid module")?;
+
+    println!("plugin: {} ({} bytes)", args.plugin.display(), module.len());
+    let is_plugin = exports
+        .iter()
+        .any(|x| x.name == ENTRY_POINT && x.kind == ExternKind::Func);
+    println!(
+        "entry point: {}",
+        if is_plugin { ENTRY_POINT } else { "missing" }
+    );
+
+    match sections.iter().find(|(name, _)| *name == PROTOCOL_SECTION) {
+        Some((_, payload)) => match <[u8; 4]>::try_from(*payload) {
+            Ok(version) => println!("protocol version: {}", u32::from_le_bytes(version)),
+            Err(_) => println!("protocol version: invalid"),
+        },
+        None => println!("protocol version: unknown"),
+    }
+
+    match sections.iter().find(|(name, _)| *name == BUNDLE_SECTION) {
+        Some((_, payload)) => match EmbeddedBundle::parse(payload) {
+            Some(bundle) => println!(
+                "bundle: {} ({}, {} bytes)",
+                bundle.name,
+                bundle.format(),
+                bundle.data.len()
+            ),
+            None => println!("bundle: invalid"),
+        },
+        None => println!("bundle: compiled into the module"),
+    }
+
+    if is_plugin {
+        match HostConfig::new()
+            .consume_fuel(true)
+            .build_engine()
+            .and_then(|engine| Plugin::from_path(&engine, &args.plugin))
+            .and_then(|plugin| {
+                plugin.set_wasi_policy(
+                    WasiPolicy::new()
+                        .clocks(WasiRule::Stub)
+                        .random(WasiRule::Stub)
+                        .files(WasiRule::Deny)
+                        .clone(),
+                )?;
+                let quotas = Arc::new(QuotaRegistry::new());
+                quotas.set_quota("inspect", *Quota::new().fuel(args.fuel));
+                plugin.set_quota_account(Some((quotas, "inspect".into())));
+                plugin.list_endpoints()
+            }) {
+            Ok(endpoints) => {
+                println!("endpoints:");
+                for endpoint in endpoints {
+                    println!("  {}", endpoint);
+                }
+            }
+            Err(err) => println!("endpoints: unavailable ({:#})", host_error(err)),
+        }
+    }
+
+    println!("host functions:");
+    for import in imports.iter().filter(|x| x.module != WASI_MODULE) {
+        println!("  {}.{} ({:?})", import.module, import.name, import.kind);
+    }
+    println!("wasi functions:");
+    for import in imports.iter().filter(|x| x.module == WASI_MODULE) {
+        println!("  {}", import.name);
+    }
+    println!("custom sections:");
+    for (name, payload) in &sections {
+        println!("  {} ({} bytes)", name, payload.len());
+    }
+    Ok(0)
+}
@@ -0,0 +1,128 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context as _, Error};
+use worthless_bridge::Request;
+use worthless_host::{HostConfig, Plugin, ProfileFormat, VirtualClock};
+
+use crate::utils::host_error;
+
+/// Exit code for an invocation that produced an error response.
+const EXIT_ERROR_RESPONSE: i32 = 1;
+
+/// Exit code when the plugin could not be loaded or invoked.
+const EXIT_HOST_FAILURE: i32 = 2;
+
+/// Sends a request to a plugin and prints the response.
+///
+/// Exits with 1 if the plugin responded with an error and with 2 if the
+/// plugin could not be loaded or invoked, which includes payloads that
+/// cannot be read and profiles that cannot be written.
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// The plugin to load.
+    pub plugin: PathBuf,
+    /// The endpoint to invoke.
+    pub endpoint: String,
+    /// A JSON file with the payload or `-` to read it from stdin.
+    #[arg(long)]
+    pub payload: Option<PathBuf>,
+    /// Sets a config value for this invocation.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub config: Vec<(String, String)>,
+    /// Sets a config value in the environment of the plugin.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub env_config: Vec<(String, String)>,
+    /// Profiles the invocation and writes the profile to a file.
+    ///
+    /// Files ending in `.json` are written in the speedscope format, others
+    /// as collapsed stacks for flamegraph tools.
+    #[arg(long, value_name = "PATH")]
+    pub profile: Option<PathBuf>,
+    /// Runs the plugin on a virtual clock that stands still at this Unix
+    /// time, so that invocations are reproducible.
+    #[arg(long, value_name = "SECONDS")]
+    pub fixed_clock: Option<u64>,
+}
+
+fn parse_key_value(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{}'", arg))
+}
+
+pub fn execute(args: Args) -> Result<i32, Error> {
+    // exit code 1 is reserved for error responses of the plugin
+    invoke(args).or_else(|err| {
+        eprintln!("error: {:#}", err);
+        Ok(EXIT_HOST_FAILURE)
+    })
+}
+
+fn invoke(args: Args) -> Result<i32, Error> {
+    let payload: serde_json::Value = match args.payload {
+        Some(ref path) if path.as_os_str() == "-" => {
+            let mut buf = Vec::new();
+            io::stdin().read_to_end(&mut buf)?;
+            serde_json::from_slice(&buf).context("invalid payload")?
+        }
+        Some(ref path) => serde_json::from_slice(
+            &fs::read(path).with_context(|| format!("cannot read {}", path.display()))?,
+        )
+        .context("invalid payload")?,
+        None => serde_json::Value::Null,
+    };
+    let mut builder = Request::build(args.endpoint);
+    builder
+        .payload(&payload)
+        .map_err(|err| anyhow::anyhow!("invalid payload: {}", err))?;
+    for (key, value) in args.config {
+        builder.config(key, value);
+    }
+    let req = builder.build();
+
+    let profile_format = match args.profile {
+        Some(ref path) if path.extension().is_some_and(|ext| ext == "json") => {
+            Some(ProfileFormat::Speedscope)
+        }
+        Some(_) => Some(ProfileFormat::Collapsed),
+        None => None,
+    };
+
+    let (response, profile) = HostConfig::new()
+        .build_engine()
+        .and_then(|engine| Plugin::from_path(&engine, &args.plugin))
+        .and_then(|plugin| {
+            for (key, value) in &args.env_config {
+                plugin.set_env_config(key, value)?;
+            }
+            if let Some(seconds) = args.fixed_clock {
+                plugin.set_virtual_clock(Some(&VirtualClock::from_unix_seconds(seconds)));
+            }
+            if profile_format.is_some() {
+                plugin.start_profiling(Duration::from_millis(1))?;
+            }
+            let response = plugin.send_request(req)?;
+            let profile = profile_format
+                .map(|format| plugin.stop_profiling(format))
+                .transpose()?;
+            Ok((response, profile))
+        })
+        .map_err(host_error)?;
+    if let (Some(path), Some(profile)) = (&args.profile, profile) {
+        fs::write(path, profile).with_context(|| format!("cannot write {}", path.display()))?;
+    }
+
+    match response.deserialize_payload::<serde_json::Value>() {
+        Ok(payload) => {
+            println!("{}", serde_json::to_string_pretty(&payload)?);
+            Ok(0)
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            Ok(EXIT_ERROR_RESPONSE)
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::process;
+
+use clap::{Parser, Subcommand};
+
+mod bundle;
+mod compile;
+mod console;
+mod inspect;
+mod invoke;
+mod pipe;
+mod repl;
+mod run;
+#[cfg(feature = "server")]
+mod serve;
+mod test;
+mod utils;
+mod worker;
+
+/// Command line tool for worthless plugins.
+#[derive(Parser, Debug)]
+#[command(name = "worthless", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Runs a JavaScript file.
+    Run(run::Args),
+    /// Starts an interactive session.
+    Repl(repl::Args),
+    /// Sends a request to a plugin and prints the response.
+    Invoke(invoke::Args),
+    /// Pipes stdin through a plugin to stdout.
+    Pipe(pipe::Args),
+    /// Compiles a JavaScript bundle to bytecode.
+    Compile(compile::Args),
+    /// Creates a plugin from a JavaScript bundle.
+    Bundle(bundle::Args),
+    /// Prints what a plugin contains and requires.
+    Inspect(inspect::Args),
+    /// Runs JavaScript test files.
+    Test(test::Args),
+    /// Serves the endpoints of plugins over HTTP.
+    #[cfg(feature = "server")]
+    Serve(serve::Args),
+    /// Serves a plugin to a parent process.
+    #[command(hide = true)]
+    Worker(worker::Args),
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let rv = match cli.command {
+        Command::Run(args) => run::execute(args),
+        Command::Repl(args) => repl::execute(args),
+        Command::Invoke(args) => invoke::execute(args),
+        Command::Pipe(args) => pipe::execute(args),
+        Command::Compile(args) => compile::execute(args),
+        Command::Bundle(args) => bundle::execute(args),
+        Command::Inspect(args) => inspect::execute(args),
+        Command::Test(args) => test::execute(args),
+        #[cfg(feature = "server")]
+        Command::Serve(args) => serve::execute(args),
+        Command::Worker(args) => worker::execute(args),
+    };
+    match rv {
+        Ok(code) => process::exit(code),
+        Err(err) => {
+            eprintln!("error: {:#}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::Error;
+use worthless_host::{HostConfig, Plugin};
+
+use crate::utils::host_error;
+
+/// Pipes stdin through a plugin to stdout.
+///
+/// The plugin runs as a command and its exit code is passed on.
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// The plugin to run.
+    pub plugin: PathBuf,
+}
+
+pub fn execute(args: Args) -> Result<i32, Error> {
+    let engine = HostConfig::new().build_engine().map_err(host_error)?;
+    let plugin = Plugin::from_path(&engine, &args.plugin).map_err(host_error)?;
+    plugin.pipe(io::stdin(), io::stdout()).map_err(host_error)
+}
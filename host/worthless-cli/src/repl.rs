@@ -0,0 +1,88 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Error;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use worthless_js_rt::{Context, Runtime, Value, ValueKind};
+
+use crate::console;
+use crate::utils::{format_js_error, js_error, register_source};
+
+const HELP: &str = "\
+.load <file>  evaluate a file in the current context
+.help         show this help
+.exit         leave the repl";
+
+/// Starts an interactive session.
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Files to evaluate before the session starts.
+    pub preload: Vec<std::path::PathBuf>,
+}
+
+pub fn execute(args: Args) -> Result<i32, Error> {
+    let rt = Runtime::new().map_err(js_error)?;
+    let ctx = Context::new(&rt).map_err(js_error)?;
+    console::install(&ctx).map_err(js_error)?;
+    for path in &args.preload {
+        load(&rt, &ctx, path)?;
+    }
+
+    let mut editor = Editor::<()>::new()?;
+    loop {
+        let line = match editor.readline(">> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line);
+
+        if let Some(command) = line.strip_prefix('.') {
+            let (command, arg) = command.split_once(' ').unwrap_or((command, ""));
+            match command {
+                "exit" => break,
+                "help" => println!("{}", HELP),
+                "load" if !arg.trim().is_empty() => {
+                    if let Err(err) = load(&rt, &ctx, Path::new(arg.trim())) {
+                        eprintln!("error: {:#}", err);
+                    }
+                }
+                _ => eprintln!("unknown command, try .help"),
+            }
+            continue;
+        }
+
+        ctx.register_source("<repl>", line);
+        match ctx
+            .eval_with_filename(line, "<repl>")
+            .and_then(|rv| rt.run_pending_jobs().map(|_| rv))
+        {
+            Ok(rv) => print_value(&rv),
+            Err(err) => eprintln!("{}", format_js_error(&err)),
+        }
+    }
+    Ok(0)
+}
+
+fn load(rt: &Runtime, ctx: &Context, path: &Path) -> Result<(), Error> {
+    let source =
+        fs::read(path).map_err(|err| anyhow::anyhow!("cannot read {}: {}", path.display(), err))?;
+    let filename = path.display().to_string();
+    register_source(ctx, &filename, &source);
+    ctx.eval_bytes_with_filename(&source, &filename)
+        .map_err(js_error)?;
+    rt.run_pending_jobs().map_err(js_error)?;
+    Ok(())
+}
+
+fn print_value(value: &Value) {
+    if value.kind() != ValueKind::Undefined {
+        println!("{:#?}", value);
+    }
+}
@@ -0,0 +1,30 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context as _, Error};
+use worthless_js_rt::{Context, Runtime};
+
+use crate::console;
+use crate::utils::{js_error, register_source};
+
+/// Runs a JavaScript file.
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// The script to run.
+    pub path: PathBuf,
+}
+
+pub fn execute(args: Args) -> Result<i32, Error> {
+    let source =
+        fs::read(&args.path).with_context(|| format!("cannot read {}", args.path.display()))?;
+    let rt = Runtime::new().map_err(js_error)?;
+    let ctx = Context::new(&rt).map_err(js_error)?;
+    console::install(&ctx).map_err(js_error)?;
+
+    let filename = args.path.display().to_string();
+    register_source(&ctx, &filename, &source);
+    ctx.eval_bytes_with_filename(&source, &filename)
+        .map_err(js_error)?;
+    rt.run_pending_jobs().map_err(js_error)?;
+    Ok(0)
+}
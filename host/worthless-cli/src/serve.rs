@@ -0,0 +1,124 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context as _, Error};
+use worthless_host::{Backend, HostConfig, PluginInstance, PoolingLimits};
+use worthless_host_server::{AuthError, PluginRegistry, RouteLimits, ServerConfig};
+
+use crate::utils::host_error;
+
+/// Serves the endpoints of plugins over HTTP.
+///
+/// Every plugin is exposed under `POST /plugins/{name}/{endpoint}` with the
+/// payload as JSON body.
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// A plugin to serve under a name.
+    #[arg(long = "plugin", value_name = "NAME=PATH", value_parser = parse_plugin, required = true)]
+    pub plugins: Vec<(String, PathBuf)>,
+    /// The address to listen on.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub listen: SocketAddr,
+    /// Runs every plugin in its own child process.
+    #[arg(long)]
+    pub out_of_process: bool,
+    /// Requires this bearer token in the `Authorization` header.
+    #[arg(long)]
+    pub token: Option<String>,
+    /// The largest request body in bytes.
+    #[arg(long)]
+    pub max_body_size: Option<usize>,
+    /// How long to wait for a plugin to respond in milliseconds.
+    #[arg(long, value_name = "MS")]
+    pub timeout: Option<u64>,
+    /// Preallocates memory for this many plugin instances.
+    ///
+    /// The memory of every instance is sized after what the plugins declare.
+    #[arg(long, value_name = "INSTANCES", conflicts_with = "out_of_process")]
+    pub pool: Option<u32>,
+}
+
+fn parse_plugin(arg: &str) -> Result<(String, PathBuf), String> {
+    arg.split_once('=')
+        .map(|(name, path)| (name.to_string(), PathBuf::from(path)))
+        .ok_or_else(|| format!("expected NAME=PATH, got '{}'", arg))
+}
+
+/// Compares two byte strings without exiting early on the first difference.
+///
+/// This keeps the time a comparison takes from telling how much of a token
+/// was guessed right, only its length shows.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+pub fn execute(args: Args) -> Result<i32, Error> {
+    let backend = if args.out_of_process {
+        Backend::Process(std::env::current_exe()?)
+    } else {
+        Backend::InProcess
+    };
+    let mut host_config = HostConfig::new();
+    if let Some(instances) = args.pool {
+        let modules = args
+            .plugins
+            .iter()
+            .map(|(_, path)| {
+                fs::read(path).with_context(|| format!("cannot read {}", path.display()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let limits = PoolingLimits {
+            instances,
+            ..PoolingLimits::default()
+        };
+        host_config.pooling(
+            limits
+                .for_modules(modules.iter().map(|x| &x[..]))
+                .map_err(host_error)?,
+        );
+    }
+    let engine = host_config.build_engine().map_err(host_error)?;
+    let registry = Arc::new(PluginRegistry::new());
+    for (name, path) in &args.plugins {
+        let plugin = PluginInstance::load(&engine, path, &backend)
+            .map_err(host_error)
+            .with_context(|| format!("cannot load {}", path.display()))?;
+        plugin.set_name(name);
+        registry.register(name, plugin);
+    }
+
+    let mut limits = RouteLimits::new();
+    if let Some(size) = args.max_body_size {
+        limits.max_body_size(size);
+    }
+    if let Some(timeout) = args.timeout {
+        limits.timeout(Duration::from_millis(timeout));
+    }
+    let mut config = ServerConfig::new();
+    config.default_limits(limits);
+    if let Some(token) = args.token {
+        let expected = format!("Bearer {}", token);
+        config.auth(move |req, _| {
+            match req
+                .headers
+                .get("authorization")
+                .and_then(|x| x.to_str().ok())
+            {
+                Some(value) if constant_time_eq(value.as_bytes(), expected.as_bytes()) => Ok(()),
+                _ => Err(AuthError::unauthorized("missing or invalid token")),
+            }
+        });
+    }
+    let router = config.build(registry);
+
+    eprintln!("listening on http://{}", args.listen);
+    tokio::runtime::Runtime::new()?.block_on(async {
+        axum::Server::bind(&args.listen)
+            .serve(router.into_make_service())
+            .await
+    })?;
+    Ok(0)
+}
@@ -0,0 +1,111 @@
+// The globals available to test files: `test` registers a test and `assert`
+// checks conditions.  Tests can be async.  This is imported before the test
+// file and the runner invokes the `test.run` endpoint once the file was
+// evaluated, which responds with the results.
+(function (global) {
+  "use strict";
+
+  const tests = [];
+
+  class AssertionError extends Error {
+    constructor(message) {
+      super(message);
+      this.name = "AssertionError";
+    }
+  }
+
+  function format(value) {
+    try {
+      const rv = JSON.stringify(value);
+      return rv === undefined ? String(value) : rv;
+    } catch (e) {
+      return String(value);
+    }
+  }
+
+  function deepEqual(a, b) {
+    if (Object.is(a, b)) {
+      return true;
+    }
+    if (typeof a !== "object" || typeof b !== "object" || a === null || b === null) {
+      return false;
+    }
+    if (Array.isArray(a) !== Array.isArray(b)) {
+      return false;
+    }
+    const keys = Object.keys(a);
+    if (keys.length !== Object.keys(b).length) {
+      return false;
+    }
+    return keys.every((key) => Object.prototype.hasOwnProperty.call(b, key) && deepEqual(a[key], b[key]));
+  }
+
+  function assert(condition, message) {
+    if (!condition) {
+      throw new AssertionError(message || "assertion failed");
+    }
+  }
+
+  assert.equal = function (actual, expected, message) {
+    if (!Object.is(actual, expected)) {
+      throw new AssertionError(message || `expected ${format(expected)}, got ${format(actual)}`);
+    }
+  };
+
+  assert.notEqual = function (actual, expected, message) {
+    if (Object.is(actual, expected)) {
+      throw new AssertionError(message || `expected a value other than ${format(expected)}`);
+    }
+  };
+
+  assert.deepEqual = function (actual, expected, message) {
+    if (!deepEqual(actual, expected)) {
+      throw new AssertionError(message || `expected ${format(expected)}, got ${format(actual)}`);
+    }
+  };
+
+  assert.throws = function (func, message) {
+    try {
+      func();
+    } catch (e) {
+      return e;
+    }
+    throw new AssertionError(message || "expected function to throw");
+  };
+
+  assert.rejects = async function (promise, message) {
+    try {
+      await (typeof promise === "function" ? promise() : promise);
+    } catch (e) {
+      return e;
+    }
+    throw new AssertionError(message || "expected promise to reject");
+  };
+
+  function test(name, func) {
+    tests.push({ name: String(name), func });
+  }
+
+  async function runTests() {
+    const results = [];
+    for (const { name, func } of tests) {
+      try {
+        await func();
+        results.push({ name, ok: true });
+      } catch (e) {
+        results.push({
+          name,
+          ok: false,
+          message: e instanceof Error ? `${e.name}: ${e.message}` : String(e),
+          stack: e instanceof Error && e.stack ? String(e.stack) : null,
+        });
+      }
+    }
+    return results;
+  }
+
+  global.AssertionError = AssertionError;
+  global.assert = assert;
+  global.test = test;
+  worthless.register("test.run", runTests);
+})(globalThis);
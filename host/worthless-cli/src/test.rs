@@ -0,0 +1,345 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context as _, Error};
+use worthless_bridge::{
+    Error as BridgeError, ErrorKind, Request, Value, BUNDLE_ENDPOINT, COVERAGE_ENDPOINT,
+    MODULE_ENDPOINT,
+};
+use worthless_host::{Engine, HostConfig, Plugin, Quota, QuotaRegistry, WasiPolicy};
+use worthless_js_rt::{Coverage, FunctionCoverage, LineCoverage, ScriptCoverage};
+
+use crate::bundle::RUNTIME_ENV;
+use crate::utils::host_error;
+
+const TEST_JS: &str = include_str!("test.js");
+
+/// The module that installs the `test` and `assert` globals.
+const TEST_MODULE: &str = "<worthless:test>";
+
+/// The endpoint the test module registers to run the tests.
+const TEST_ENDPOINT: &str = "test.run";
+
+/// The fuel a test file may use unless `--fuel` is given.
+const DEFAULT_FUEL: u64 = 10_000_000_000;
+
+/// Runs JavaScript test files.
+///
+/// Every file runs as a module in a fresh instance of the prebuilt runtime
+/// (`worthless-runtime`) with the `test` and `assert` globals, sandboxed and
+/// limited like a plugin.  Test files import the code under test with
+/// relative paths, modules are only loaded from the working directory.
+/// Directories are searched for `.js` files.  Exits with 1 if a test failed.
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// The test files or directories.
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+    /// The prebuilt runtime.  Defaults to `$WORTHLESS_RUNTIME`.
+    #[arg(long)]
+    pub runtime: Option<PathBuf>,
+    /// A JSON file with the WASI policy the tests run under.
+    #[arg(long, value_name = "PATH")]
+    pub policy: Option<PathBuf>,
+    /// The fuel every test file may use.
+    #[arg(long, default_value_t = DEFAULT_FUEL)]
+    pub fuel: u64,
+    /// Reports how many lines and functions of the test files and the modules
+    /// they import ran.
+    #[arg(long)]
+    pub coverage: bool,
+    /// Writes the coverage as an lcov tracefile, implies `--coverage`.
+    #[arg(long, value_name = "PATH")]
+    pub lcov: Option<PathBuf>,
+}
+
+/// The outcome of a single test.
+struct TestResult {
+    name: String,
+    failure: Option<(String, Option<String>)>,
+}
+
+/// What every test file runs in.
+struct Sandbox {
+    engine: Engine,
+    runtime: Vec<u8>,
+    policy: WasiPolicy,
+    quotas: Arc<QuotaRegistry>,
+    fuel: u64,
+    root: PathBuf,
+}
+
+pub fn execute(args: Args) -> Result<i32, Error> {
+    let mut files = Vec::new();
+    for path in &args.paths {
+        collect_files(path, &mut files)?;
+    }
+
+    let runtime_path = args
+        .runtime
+        .or_else(|| env::var_os(RUNTIME_ENV).map(PathBuf::from))
+        .ok_or_else(|| anyhow!("no runtime given, pass --runtime or set {}", RUNTIME_ENV))?;
+    let policy = match args.policy {
+        Some(ref path) => serde_json::from_slice(
+            &fs::read(path).with_context(|| format!("cannot read {}", path.display()))?,
+        )
+        .context("invalid policy")?,
+        None => WasiPolicy::default(),
+    };
+    let sandbox = Sandbox {
+        engine: HostConfig::new()
+            .consume_fuel(true)
+            .build_engine()
+            .map_err(host_error)?,
+        runtime: fs::read(&runtime_path)
+            .with_context(|| format!("cannot read runtime {}", runtime_path.display()))?,
+        policy,
+        quotas: Arc::new(QuotaRegistry::new()),
+        fuel: args.fuel,
+        root: env::current_dir()?.canonicalize()?,
+    };
+
+    let mut coverage = (args.coverage || args.lcov.is_some()).then(Coverage::default);
+    let mut passed = 0;
+    let mut failures = Vec::new();
+    for file in &files {
+        println!("{}", file.display());
+        let results = match run_file(&sandbox, file, coverage.as_mut())? {
+            Ok(results) => results,
+            Err(err) => {
+                println!("  error");
+                failures.push((file.display().to_string(), err, None));
+                continue;
+            }
+        };
+        for result in results {
+            match result.failure {
+                None => {
+                    println!("  {} ... ok", result.name);
+                    passed += 1;
+                }
+                Some((message, stack)) => {
+                    println!("  {} ... FAILED", result.name);
+                    failures.push((
+                        format!("{} > {}", file.display(), result.name),
+                        message,
+                        stack,
+                    ));
+                }
+            }
+        }
+    }
+
+    for (name, message, stack) in &failures {
+        println!("\n{}\n  {}", name, message);
+        for line in stack.iter().flat_map(|x| x.lines()) {
+            println!("  {}", line.trim_end());
+        }
+    }
+    println!("\n{} passed, {} failed", passed, failures.len());
+
+    if let Some(coverage) = coverage {
+        print_coverage(&coverage);
+        if let Some(ref path) = args.lcov {
+            fs::write(path, coverage.to_lcov())
+                .with_context(|| format!("cannot write {}", path.display()))?;
+        }
+    }
+    Ok(if failures.is_empty() { 0 } else { 1 })
+}
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)
+        .with_context(|| format!("cannot read {}", path.display()))?
+        .map(|entry| entry.map(|x| x.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() || entry.extension() == Some(OsStr::new("js")) {
+            collect_files(&entry, files)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_coverage(coverage: &Coverage) {
+    let percent = |covered: usize, total: usize| {
+        if total == 0 {
+            100.0
+        } else {
+            covered as f64 * 100.0 / total as f64
+        }
+    };
+    println!("\ncoverage");
+    for script in coverage.scripts() {
+        let lines = script.covered_lines();
+        let functions = script.covered_functions();
+        println!(
+            "  {}  {}/{} lines ({:.1}%)  {}/{} functions ({:.1}%)",
+            script.filename,
+            lines,
+            script.lines.len(),
+            percent(lines, script.lines.len()),
+            functions,
+            script.functions.len(),
+            percent(functions, script.functions.len()),
+        );
+    }
+}
+
+/// Runs the tests of a file.
+///
+/// The outer error is for failures of the runner, the inner one for files
+/// that fail to evaluate or exceed their limits.  The coverage of the file
+/// is added to `coverage` if given.
+fn run_file(
+    sandbox: &Sandbox,
+    path: &Path,
+    coverage: Option<&mut Coverage>,
+) -> Result<Result<Vec<TestResult>, String>, Error> {
+    let filename = path.display().to_string();
+    let plugin = Plugin::from_bytes(&sandbox.engine, &sandbox.runtime).map_err(host_error)?;
+    plugin
+        .set_wasi_policy(sandbox.policy.clone())
+        .map_err(host_error)?;
+    sandbox
+        .quotas
+        .set_quota(&filename, *Quota::new().fuel(sandbox.fuel));
+    plugin.set_quota_account(Some((sandbox.quotas.clone(), filename.clone())));
+    if coverage.is_some() {
+        plugin.enable_coverage().map_err(host_error)?;
+    }
+
+    // imports are evaluated in order, so the globals exist for the test file
+    let entry = format!("import {:?};\nimport {:?};\n", TEST_MODULE, filename);
+    let bundle = Value::Map(vec![
+        ("name".into(), "<worthless:entry>".into()),
+        ("format".into(), "module".into()),
+        ("data".into(), Value::Bytes(entry.into_bytes())),
+    ]);
+    plugin.register_endpoint(BUNDLE_ENDPOINT, move |_| Ok(bundle.clone()));
+    let root = sandbox.root.clone();
+    plugin.register_endpoint(MODULE_ENDPOINT, move |req| load_module(&root, req));
+
+    let results = match plugin.send_request(Request::new(TEST_ENDPOINT, Value::Null)) {
+        Ok(response) => match response.deserialize_payload::<serde_json::Value>() {
+            Ok(results) => Ok(read_results(&results)),
+            Err(err) => Err(err.to_string()),
+        },
+        Err(err) => Err(format!("{:#}", host_error(err))),
+    };
+    if let Some(coverage) = coverage {
+        // a plugin that trapped or ran out of fuel has no coverage to report
+        if let Ok(Ok(payload)) = plugin
+            .send_request(Request::new(COVERAGE_ENDPOINT, Value::Null))
+            .map(|response| response.deserialize_payload::<serde_json::Value>())
+        {
+            coverage.merge(&read_coverage(&payload));
+        }
+    }
+    Ok(results)
+}
+
+/// Serves the source of a module the runtime imports.
+///
+/// Only files beneath the root are served.
+fn load_module(root: &Path, req: &Request) -> Result<Value, BridgeError> {
+    let name = match req.payload() {
+        Value::Map(items) => {
+            items
+                .iter()
+                .find_map(|(key, value)| match (key.as_text(), value.as_text()) {
+                    (Some("name"), Some(name)) => Some(name),
+                    _ => None,
+                })
+        }
+        _ => None,
+    }
+    .ok_or_else(|| BridgeError::new(ErrorKind::InternalError, "module name missing"))?;
+    let source = if name == TEST_MODULE {
+        Some(TEST_JS.to_string())
+    } else {
+        fs::canonicalize(name)
+            .ok()
+            .filter(|path| path.starts_with(root))
+            .and_then(|path| fs::read_to_string(path).ok())
+    };
+    match source {
+        Some(source) => Ok(Value::Map(vec![("source".into(), source.into())])),
+        None => Err(BridgeError::new(
+            ErrorKind::InternalError,
+            format!("cannot load module '{}'", name),
+        )),
+    }
+}
+
+fn read_results(results: &serde_json::Value) -> Vec<TestResult> {
+    let text = |result: &serde_json::Value, name: &str| {
+        result
+            .get(name)
+            .and_then(|x| x.as_str())
+            .map(|x| x.to_string())
+    };
+    let results = results.as_array().map(|x| &x[..]).unwrap_or_default();
+    results
+        .iter()
+        .map(|result| TestResult {
+            name: text(result, "name").unwrap_or_default(),
+            failure: if result.get("ok") == Some(&serde_json::Value::Bool(true)) {
+                None
+            } else {
+                Some((
+                    text(result, "message").unwrap_or_default(),
+                    text(result, "stack"),
+                ))
+            },
+        })
+        .collect()
+}
+
+fn read_coverage(payload: &serde_json::Value) -> Coverage {
+    let entries = |value: &serde_json::Value, name: &str| {
+        value
+            .get(name)
+            .and_then(|x| x.as_array())
+            .cloned()
+            .unwrap_or_default()
+    };
+    let number = |entry: &serde_json::Value, idx: usize| entry.get(idx)?.as_u64();
+    entries(payload, "scripts")
+        .iter()
+        .map(|script| ScriptCoverage {
+            filename: script
+                .get("filename")
+                .and_then(|x| x.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            lines: entries(script, "lines")
+                .iter()
+                .filter_map(|entry| {
+                    Some(LineCoverage {
+                        line: u32::try_from(number(entry, 0)?).ok()?,
+                        hits: number(entry, 1)?,
+                    })
+                })
+                .collect(),
+            functions: entries(script, "functions")
+                .iter()
+                .filter_map(|entry| {
+                    Some(FunctionCoverage {
+                        name: entry.get(0)?.as_str()?.to_string(),
+                        line: u32::try_from(number(entry, 1)?).ok()?,
+                        hits: number(entry, 2)?,
+                    })
+                })
+                .collect(),
+        })
+        .collect()
+}
@@ -0,0 +1,34 @@
+use anyhow::anyhow;
+use worthless_js_rt::Context;
+
+/// Converts a runtime error into an error that can leave the runtime.
+///
+/// JavaScript exceptions are rendered with their message, an excerpt of the
+/// source if it was registered and the stack.
+pub fn js_error(err: worthless_js_rt::Error) -> anyhow::Error {
+    anyhow!(format_js_error(&err))
+}
+
+/// Formats a runtime error the way it's shown to the user.
+pub fn format_js_error(err: &worthless_js_rt::Error) -> String {
+    match err {
+        worthless_js_rt::Error::JsException(exc) => format!("Uncaught {}", exc.render()),
+        other => other.to_string(),
+    }
+}
+
+/// Registers the source of a file so that exceptions show an excerpt of it.
+///
+/// Sources that are not valid UTF-8 are skipped.
+pub fn register_source(ctx: &Context, filename: &str, source: &[u8]) {
+    if let Ok(source) = std::str::from_utf8(source) {
+        ctx.register_source(filename, source);
+    }
+}
+
+/// Converts a host error into an error with its causes in the message.
+///
+/// Host errors can carry bridge errors which cannot be sent across threads.
+pub fn host_error(err: worthless_host::HostError) -> anyhow::Error {
+    anyhow!(worthless_host::error_chain(&err))
+}
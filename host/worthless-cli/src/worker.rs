@@ -0,0 +1,23 @@
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::Error;
+use worthless_host::serve_worker;
+
+use crate::utils::host_error;
+
+/// Serves a plugin to a parent process over stdin and stdout.
+///
+/// This is started by hosts that run plugins out of process and is not
+/// meant to be used directly.  The host sends the configuration of the
+/// plugin over stdin first.
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// The plugin to serve.
+    pub plugin: PathBuf,
+}
+
+pub fn execute(args: Args) -> Result<i32, Error> {
+    serve_worker(&args.plugin, io::stdin(), io::stdout()).map_err(host_error)?;
+    Ok(0)
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::{HeaderMap, StatusCode};
+use axum::Router;
+use worthless_bridge::{RateLimiter, RequestBuilder};
+use worthless_host::ExecutorConfig;
+
+use crate::handler;
+use crate::registry::PluginRegistry;
+
+/// The largest request body accepted by default.
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// What an [`AuthHook`] decides on.
+pub struct AuthRequest<'a> {
+    /// The name of the plugin.
+    pub plugin: &'a str,
+    /// The endpoint of the plugin.
+    pub endpoint: &'a str,
+    /// The headers of the HTTP request.
+    pub headers: &'a HeaderMap,
+}
+
+/// Rejects an HTTP request in an [`AuthHook`].
+#[derive(Debug, Clone)]
+pub struct AuthError {
+    status: StatusCode,
+    message: String,
+}
+
+impl AuthError {
+    /// Creates an error that is sent with the given status.
+    pub fn new<S: Into<String>>(status: StatusCode, message: S) -> AuthError {
+        AuthError {
+            status,
+            message: message.into(),
+        }
+    }
+
+    /// Creates an error for missing or invalid credentials.
+    pub fn unauthorized<S: Into<String>>(message: S) -> AuthError {
+        AuthError::new(StatusCode::UNAUTHORIZED, message)
+    }
+
+    /// Creates an error for credentials that lack access.
+    pub fn forbidden<S: Into<String>>(message: S) -> AuthError {
+        AuthError::new(StatusCode::FORBIDDEN, message)
+    }
+
+    /// Returns the status the error is sent with.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Returns the message of the error.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+/// Decides whether an HTTP request may invoke a plugin.
+///
+/// The hook can attach meta to the bridge request, eg: the tenant the
+/// credentials belong to for a rate limiter split by meta key.
+pub type AuthHook =
+    dyn Fn(&AuthRequest<'_>, &mut RequestBuilder) -> Result<(), AuthError> + Send + Sync;
+
+/// Limits for the requests to a route.
+#[derive(Clone)]
+pub struct RouteLimits {
+    pub(crate) max_body_size: usize,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl Default for RouteLimits {
+    fn default() -> RouteLimits {
+        RouteLimits {
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            timeout: None,
+            rate_limiter: None,
+        }
+    }
+}
+
+impl RouteLimits {
+    /// Creates the default limits.
+    ///
+    /// By default bodies of up to 1 MiB are accepted and neither the time
+    /// nor the rate of requests is limited.
+    pub fn new() -> RouteLimits {
+        RouteLimits::default()
+    }
+
+    /// Sets the largest body accepted, larger ones fail with 413.
+    pub fn max_body_size(&mut self, size: usize) -> &mut RouteLimits {
+        self.max_body_size = size;
+        self
+    }
+
+    /// Sets how long to wait for the response, after that it fails with 504.
+    ///
+    /// The invocation itself cannot be interrupted and runs to completion.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut RouteLimits {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Limits the rate of requests, requests over it fail with 429.
+    pub fn rate_limit(&mut self, limiter: RateLimiter) -> &mut RouteLimits {
+        self.rate_limiter = Some(Arc::new(limiter));
+        self
+    }
+}
+
+/// Configures the HTTP server in front of a [`PluginRegistry`].
+#[derive(Clone, Default)]
+pub struct ServerConfig {
+    pub(crate) default_limits: RouteLimits,
+    pub(crate) plugin_limits: BTreeMap<String, RouteLimits>,
+    pub(crate) route_limits: BTreeMap<(String, String), RouteLimits>,
+    pub(crate) auth: Option<Arc<AuthHook>>,
+    pub(crate) executor: ExecutorConfig,
+}
+
+impl ServerConfig {
+    /// Creates the default configuration.
+    ///
+    /// By default every request is allowed with the default [`RouteLimits`].
+    pub fn new() -> ServerConfig {
+        ServerConfig::default()
+    }
+
+    /// Sets the limits of routes without more specific ones.
+    pub fn default_limits(&mut self, limits: RouteLimits) -> &mut ServerConfig {
+        self.default_limits = limits;
+        self
+    }
+
+    /// Sets the limits of all endpoints of a plugin.
+    pub fn plugin_limits(&mut self, plugin: &str, limits: RouteLimits) -> &mut ServerConfig {
+        self.plugin_limits.insert(plugin.to_string(), limits);
+        self
+    }
+
+    /// Sets the limits of one endpoint of a plugin.
+    pub fn route_limits(
+        &mut self,
+        plugin: &str,
+        endpoint: &str,
+        limits: RouteLimits,
+    ) -> &mut ServerConfig {
+        self.route_limits
+            .insert((plugin.to_string(), endpoint.to_string()), limits);
+        self
+    }
+
+    /// Sets the hook that authorizes requests before they are sent.
+    pub fn auth<F>(&mut self, f: F) -> &mut ServerConfig
+    where
+        F: Fn(&AuthRequest<'_>, &mut RequestBuilder) -> Result<(), AuthError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.auth = Some(Arc::new(f));
+        self
+    }
+
+    /// Configures the executor the plugins run on.
+    pub fn executor(&mut self, config: ExecutorConfig) -> &mut ServerConfig {
+        self.executor = config;
+        self
+    }
+
+    /// Returns the limits that apply to an endpoint of a plugin.
+    pub(crate) fn limits(&self, plugin: &str, endpoint: &str) -> &RouteLimits {
+        self.route_limits
+            .get(&(plugin.to_string(), endpoint.to_string()))
+            .or_else(|| self.plugin_limits.get(plugin))
+            .unwrap_or(&self.default_limits)
+    }
+
+    /// Builds the router serving the plugins of the registry.
+    ///
+    /// This starts the executor, which shuts down when the router is dropped.
+    pub fn build(&self, registry: Arc<PluginRegistry>) -> Router {
+        handler::router(registry, self.clone())
+    }
+}
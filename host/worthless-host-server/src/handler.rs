@@ -0,0 +1,261 @@
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde_json::json;
+use worthless_bridge::{Error, ErrorKind, Request};
+use worthless_host::{error_chain, HostError, PluginExecutor};
+
+use crate::config::{AuthRequest, ServerConfig};
+use crate::registry::PluginRegistry;
+
+/// The header a request ID is taken from.
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+struct ServerState {
+    registry: Arc<PluginRegistry>,
+    config: ServerConfig,
+    executor: PluginExecutor,
+}
+
+pub fn router(registry: Arc<PluginRegistry>, config: ServerConfig) -> Router {
+    // bodies are checked against the limits of the route once the route is
+    // known, this only bounds what is read at all
+    let body_limit = config
+        .plugin_limits
+        .values()
+        .chain(config.route_limits.values())
+        .map(|limits| limits.max_body_size)
+        .fold(config.default_limits.max_body_size, usize::max);
+    let state = Arc::new(ServerState {
+        registry,
+        executor: PluginExecutor::new(&config.executor),
+        config,
+    });
+    Router::new()
+        .route("/plugins/:plugin/:endpoint", post(invoke))
+        .layer(DefaultBodyLimit::max(body_limit))
+        .with_state(state)
+}
+
+async fn invoke(
+    State(state): State<Arc<ServerState>>,
+    Path((plugin_name, endpoint)): Path<(String, String)>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    // unauthenticated clients must not learn which plugins exist
+    let mut builder = Request::build(endpoint.clone());
+    if let Some(request_id) = headers.get(REQUEST_ID_HEADER).and_then(|x| x.to_str().ok()) {
+        builder.request_id(request_id);
+    }
+    if let Some(ref auth) = state.config.auth {
+        let auth_req = AuthRequest {
+            plugin: &plugin_name,
+            endpoint: &endpoint,
+            headers: &headers,
+        };
+        if let Err(err) = auth(&auth_req, &mut builder) {
+            return error_response(err.status(), "access_denied", err.message());
+        }
+    }
+    let plugin = match state.registry.get(&plugin_name) {
+        Some(plugin) if !endpoint.starts_with("__") => plugin,
+        _ => return error_response(StatusCode::NOT_FOUND, "not_found", "no such endpoint"),
+    };
+    let limits = state.config.limits(&plugin_name, &endpoint).clone();
+    if body.len() > limits.max_body_size {
+        return error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            "request body is too large",
+        );
+    }
+    let payload: serde_json::Value = if body.is_empty() {
+        serde_json::Value::Null
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(payload) => payload,
+            Err(err) => {
+                return error_response(StatusCode::BAD_REQUEST, "invalid_payload", &err.to_string())
+            }
+        }
+    };
+    if let Err(err) = builder.payload(&payload) {
+        return bridge_error_response(&err);
+    }
+    let req = builder.build();
+    if let Some(ref limiter) = limits.rate_limiter {
+        if let Err(err) = limiter.check(&req) {
+            return bridge_error_response(&err);
+        }
+    }
+
+    // responses and host errors are not thread safe, so the response travels
+    // in serialized form and errors are turned into responses right away
+    let pending = match state.executor.execute(&plugin_name, move || {
+        let mut buf = Vec::new();
+        plugin
+            .send_request(req)?
+            .serialize_to(&mut buf)
+            .map_err(HostError::ProtocolError)?;
+        Ok(buf)
+    }) {
+        Ok(pending) => pending,
+        Err(err) => return host_error_response(&err),
+    };
+    let rv = match limits.timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, pending).await {
+            Ok(rv) => rv,
+            Err(_) => {
+                return error_response(
+                    StatusCode::GATEWAY_TIMEOUT,
+                    "timeout",
+                    "plugin did not respond in time",
+                )
+            }
+        },
+        None => pending.await,
+    };
+    let buf = match rv {
+        Ok(buf) => buf,
+        Err(err) => return host_error_response(&err),
+    };
+    let response = match worthless_bridge::Response::deserialize_from(&mut &buf[..]) {
+        Ok(response) => response,
+        Err(err) => return bridge_error_response(&err),
+    };
+    match response.deserialize_payload::<serde_json::Value>() {
+        Ok(payload) => Json(payload).into_response(),
+        Err(err) => bridge_error_response(&err),
+    }
+}
+
+/// Maps the kind of a bridge error to an HTTP status.
+fn status_for_kind(kind: ErrorKind) -> StatusCode {
+    let code = match kind {
+        ErrorKind::UnknownEndpoint => 404,
+        ErrorKind::PayloadTooLarge => 413,
+        ErrorKind::RateLimited => 429,
+        ErrorKind::Other(code) => code,
+        _ => 500,
+    };
+    u16::try_from(code)
+        .ok()
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .filter(|status| status.is_client_error() || status.is_server_error())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn bridge_error_response(err: &Error) -> Response {
+    let mut body = json!({
+        "kind": serde_json::to_value(err.kind()).unwrap_or_default(),
+        "description": err.description(),
+    });
+    if let Some(detail) = err.detail().and_then(|x| x.deserialized().ok()) {
+        body["detail"] = detail;
+    }
+    (status_for_kind(err.kind()), Json(json!({ "error": body }))).into_response()
+}
+
+fn host_error_response(err: &HostError) -> Response {
+    let status = match err {
+        HostError::ExecutorBusy | HostError::QuotaExceeded(..) => StatusCode::SERVICE_UNAVAILABLE,
+        HostError::ExecutorTimeout => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    error_response(status, "host_error", &error_chain(err))
+}
+
+fn error_response(status: StatusCode, kind: &str, description: &str) -> Response {
+    let body = json!({ "error": { "kind": kind, "description": description } });
+    (status, Json(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::body::Body;
+    use axum::http::Request as HttpRequest;
+    use tower::ServiceExt;
+
+    use crate::config::AuthError;
+
+    fn server(registry: PluginRegistry) -> Router {
+        let mut config = ServerConfig::new();
+        config.auth(|req, _| match req.headers.get("authorization") {
+            Some(value) if value == "Bearer secret" => Ok(()),
+            _ => Err(AuthError::unauthorized("missing or invalid token")),
+        });
+        router(Arc::new(registry), config)
+    }
+
+    async fn post(router: Router, uri: &str, token: Option<&str>) -> StatusCode {
+        let mut req = HttpRequest::post(uri);
+        if let Some(token) = token {
+            req = req.header("authorization", format!("Bearer {}", token));
+        }
+        let response = router
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        response.status()
+    }
+
+    #[tokio::test]
+    async fn test_auth_before_lookup() {
+        let router = server(PluginRegistry::new());
+        // unknown plugins look the same as known ones without credentials
+        assert_eq!(
+            post(router.clone(), "/plugins/missing/echo", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            post(router.clone(), "/plugins/missing/echo", Some("wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            post(router, "/plugins/missing/echo", Some("secret")).await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_control_endpoints_hidden() {
+        let router = server(PluginRegistry::new());
+        assert_eq!(
+            post(router.clone(), "/plugins/missing/__tick", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            post(router, "/plugins/missing/__tick", Some("secret")).await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn test_status_for_kind() {
+        assert_eq!(
+            status_for_kind(ErrorKind::UnknownEndpoint),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status_for_kind(ErrorKind::RateLimited),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            status_for_kind(ErrorKind::Other(418)),
+            StatusCode::IM_A_TEAPOT
+        );
+        // only error statuses are passed through
+        assert_eq!(
+            status_for_kind(ErrorKind::Other(200)),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
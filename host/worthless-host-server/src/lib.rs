@@ -0,0 +1,23 @@
+//! Exposes the endpoints of plugins over HTTP.
+//!
+//! Plugins are added to a [`PluginRegistry`] by name and a [`ServerConfig`]
+//! builds an axum router which maps `POST /plugins/{name}/{endpoint}` to
+//! invocations of the plugin.  The JSON body becomes the payload of the
+//! request and the payload of the response is sent back as JSON.  Error
+//! responses of the plugin are mapped to HTTP status codes by their kind.
+//!
+//! The plugins run on a [`PluginExecutor`](worthless_host::PluginExecutor)
+//! so invocations do not block the async runtime.  Control endpoints of the
+//! guest (starting with `__`) are never exposed.
+//!
+//! The HTTP front-end and its dependencies are behind the default `http`
+//! feature, without it only the [`PluginRegistry`] is available.
+#[cfg(feature = "http")]
+mod config;
+#[cfg(feature = "http")]
+mod handler;
+mod registry;
+
+#[cfg(feature = "http")]
+pub use self::config::{AuthError, AuthHook, AuthRequest, RouteLimits, ServerConfig};
+pub use self::registry::PluginRegistry;
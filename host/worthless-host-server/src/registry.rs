@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use worthless_bridge::{Request, Response};
+use worthless_host::{HostError, Observers, PluginInstance};
+
+/// The plugins a server exposes by name.
+///
+/// Plugins can be added and removed while the server is running.  The
+/// registered plugins report to the [`observers`](Self::observers) of the
+/// registry.
+#[derive(Default)]
+pub struct PluginRegistry {
+    plugins: RwLock<BTreeMap<String, Arc<PluginInstance>>>,
+    observers: Observers,
+}
+
+impl PluginRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> PluginRegistry {
+        PluginRegistry::default()
+    }
+
+    /// Returns the observers the plugins of the registry report to.
+    pub fn observers(&self) -> &Observers {
+        &self.observers
+    }
+
+    /// Registers a plugin under a name, replacing the one registered before.
+    ///
+    /// The name is also the one the observers see for the plugin.
+    pub fn register(&self, name: &str, plugin: PluginInstance) {
+        plugin.set_name(name);
+        plugin.set_observers(self.observers.clone());
+        self.plugins
+            .write()
+            .unwrap()
+            .insert(name.to_string(), Arc::new(plugin));
+    }
+
+    /// Removes a plugin, invocations in flight still complete.
+    pub fn remove(&self, name: &str) -> Option<Arc<PluginInstance>> {
+        self.plugins.write().unwrap().remove(name)
+    }
+
+    /// Looks up a plugin by name.
+    pub fn get(&self, name: &str) -> Option<Arc<PluginInstance>> {
+        self.plugins.read().unwrap().get(name).cloned()
+    }
+
+    /// Returns the names of all registered plugins.
+    pub fn names(&self) -> Vec<String> {
+        self.plugins.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Sends a request to a plugin on the current thread.
+    ///
+    /// Returns `None` if no plugin is registered under the name.
+    pub fn invoke(&self, name: &str, req: Request) -> Option<Result<Response, HostError>> {
+        self.get(name).map(|plugin| plugin.send_request(req))
+    }
+}
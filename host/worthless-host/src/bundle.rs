@@ -0,0 +1,76 @@
+use std::collections::BTreeMap;
+
+use worthless_bridge::Value;
+
+/// The custom section that holds the bundle of a plugin.
+///
+/// Plugins created with `worthless bundle` consist of the prebuilt runtime and
+/// this section.  The runtime requests the bundle from the host with
+/// [`BUNDLE_ENDPOINT`](worthless_bridge::BUNDLE_ENDPOINT) on startup.
+pub const BUNDLE_SECTION: &str = "worthless-bundle";
+
+/// A JavaScript bundle stored in a plugin.
+///
+/// The section is encoded as the format, the name and the data separated by
+/// NUL bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmbeddedBundle {
+    /// The name of the bundle as it shows up in stack traces.
+    pub name: String,
+    /// `true` if the data is QuickJS bytecode rather than source.
+    pub bytecode: bool,
+    /// The source or bytecode.
+    pub data: Vec<u8>,
+}
+
+impl EmbeddedBundle {
+    /// Parses the payload of a bundle section.
+    pub fn parse(payload: &[u8]) -> Option<EmbeddedBundle> {
+        let mut parts = payload.splitn(3, |x| *x == 0);
+        let format = parts.next()?;
+        let name = std::str::from_utf8(parts.next()?).ok()?;
+        let data = parts.next()?;
+        Some(EmbeddedBundle {
+            name: name.to_string(),
+            bytecode: match format {
+                b"source" => false,
+                b"bytecode" => true,
+                _ => return None,
+            },
+            data: data.to_vec(),
+        })
+    }
+
+    /// Returns the payload for the bundle section.
+    pub fn to_section(&self) -> Vec<u8> {
+        let mut rv = Vec::with_capacity(self.data.len() + self.name.len() + 10);
+        rv.extend_from_slice(self.format().as_bytes());
+        rv.push(0);
+        rv.extend_from_slice(self.name.as_bytes());
+        rv.push(0);
+        rv.extend_from_slice(&self.data);
+        rv
+    }
+
+    /// Returns the format as `source` or `bytecode`.
+    pub fn format(&self) -> &'static str {
+        if self.bytecode {
+            "bytecode"
+        } else {
+            "source"
+        }
+    }
+
+    /// Converts the bundle into the payload the runtime expects.
+    pub(crate) fn to_value(&self) -> Value {
+        let mut rv = BTreeMap::new();
+        rv.insert("name", Value::Text(self.name.clone()));
+        rv.insert("format", Value::Text(self.format().into()));
+        rv.insert("data", Value::Bytes(self.data.clone()));
+        Value::Map(
+            rv.into_iter()
+                .map(|(key, value)| (Value::Text(key.into()), value))
+                .collect(),
+        )
+    }
+}
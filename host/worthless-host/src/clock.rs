@@ -0,0 +1,108 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use wasi_common::clocks::{WasiClocks, WasiMonotonicClock, WasiSystemClock};
+
+/// A clock for plugins that only moves when the host advances it.
+///
+/// Plugins running on a virtual clock see the realtime clock start at the
+/// epoch and the monotonic clock start at zero.  Both move together by
+/// exactly what is passed to [`advance`](Self::advance), so runs that
+/// advance the clock the same way observe the same times.  Clones share
+/// the time, which lets the host keep a handle to a clock it gave to a
+/// plugin.
+///
+/// Sleeping in the plugin is not virtualized and still waits in real time.
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    epoch: SystemTime,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl VirtualClock {
+    /// Creates a clock that starts at the given time.
+    pub fn new(epoch: SystemTime) -> VirtualClock {
+        VirtualClock {
+            epoch,
+            elapsed: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    /// Creates a clock that starts at the given number of seconds after the
+    /// Unix epoch.
+    pub fn from_unix_seconds(seconds: u64) -> VirtualClock {
+        VirtualClock::new(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    /// Moves the clock forward.
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+
+    /// Moves the clock forward to the given time since it was created.
+    ///
+    /// The clock never moves backwards.
+    pub(crate) fn advance_to(&self, elapsed: Duration) {
+        let mut current = self.elapsed.lock().unwrap();
+        *current = (*current).max(elapsed);
+    }
+
+    /// Returns the time the clock advanced by since it was created.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+
+    /// Returns the current realtime of the clock.
+    pub fn now(&self) -> SystemTime {
+        self.epoch + self.elapsed()
+    }
+
+    /// Creates the WASI clocks backed by this clock.
+    pub(crate) fn to_wasi_clocks(&self) -> WasiClocks {
+        // the monotonic clock reports the time since its creation, so any
+        // instant works as the base as long as both sides use the same one
+        let base = cap_std::time::Instant::from_std(std::time::Instant::now());
+        WasiClocks {
+            system: Box::new(VirtualSystemClock(self.clone())),
+            monotonic: Box::new(VirtualMonotonicClock {
+                clock: self.clone(),
+                base,
+            }),
+            creation_time: base,
+        }
+    }
+}
+
+impl Default for VirtualClock {
+    /// Creates a clock that starts at the Unix epoch.
+    fn default() -> VirtualClock {
+        VirtualClock::new(UNIX_EPOCH)
+    }
+}
+
+struct VirtualSystemClock(VirtualClock);
+
+impl WasiSystemClock for VirtualSystemClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self, _precision: Duration) -> cap_std::time::SystemTime {
+        cap_std::time::SystemTime::from_std(self.0.now())
+    }
+}
+
+struct VirtualMonotonicClock {
+    clock: VirtualClock,
+    base: cap_std::time::Instant,
+}
+
+impl WasiMonotonicClock for VirtualMonotonicClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self, _precision: Duration) -> cap_std::time::Instant {
+        self.base + self.clock.elapsed()
+    }
+}
@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+use wasmtime::{Config, Engine, InstanceAllocationStrategy, PoolingAllocationConfig};
+
+use crate::error::HostError;
+use crate::sections::{self, Limits};
+
+/// WASM pages are 64KiB.
+const WASM_PAGE_SIZE: u64 = 65536;
+
+/// Limits of the pooling instance allocator.
+///
+/// Every slot of the pool reserves the maximum memory and table size up front
+/// so the limits should be kept close to what the plugins actually need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolingLimits {
+    /// The number of instances that can exist concurrently.
+    pub instances: u32,
+    /// The maximum number of linear memory pages per instance.
+    pub memory_pages: u64,
+    /// The maximum number of table elements per instance.
+    pub table_elements: u32,
+}
+
+impl Default for PoolingLimits {
+    fn default() -> PoolingLimits {
+        PoolingLimits {
+            instances: 100,
+            // 64MiB is plenty for QuickJS with a moderately sized bundle
+            memory_pages: 1024,
+            table_elements: 10_000,
+        }
+    }
+}
+
+impl PoolingLimits {
+    /// Fits the limits to the memories and tables the given modules declare.
+    ///
+    /// The declared maximum is used where available.  Memories and tables
+    /// that can grow without bounds get the size of these limits instead, so
+    /// they must leave room for the heap of QuickJS.  The limits are raised
+    /// to the initial size if needed and cover all modules.
+    pub fn for_modules<'a, I>(&self, modules: I) -> Result<PoolingLimits, HostError>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut memory_pages = None;
+        let mut table_elements = None;
+        for module in modules {
+            let invalid = |err: std::io::Error| HostError::InvalidConfig(err.into());
+            for limits in sections::memories(module).map_err(invalid)? {
+                let pages = fit(limits, self.memory_pages);
+                memory_pages = memory_pages.max(Some(pages));
+            }
+            for limits in sections::tables(module).map_err(invalid)? {
+                let elements = fit(limits, self.table_elements.into());
+                table_elements = table_elements.max(Some(elements));
+            }
+        }
+        Ok(PoolingLimits {
+            instances: self.instances,
+            memory_pages: memory_pages.unwrap_or(self.memory_pages),
+            table_elements: match table_elements {
+                Some(elements) => u32::try_from(elements).map_err(|_| {
+                    HostError::InvalidConfig(anyhow::anyhow!("table is too large for pooling"))
+                })?,
+                None => self.table_elements,
+            },
+        })
+    }
+
+    /// Returns the memory reserved per instance in bytes.
+    pub fn memory_size(&self) -> u64 {
+        self.memory_pages * WASM_PAGE_SIZE
+    }
+}
+
+/// Returns the size a memory or table may grow to.
+fn fit(limits: Limits, unbounded: u64) -> u64 {
+    limits.maximum.unwrap_or(unbounded).max(limits.minimum)
+}
+
+/// Configures the engine that plugins are loaded into.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HostConfig {
+    pooling: Option<PoolingLimits>,
+    consume_fuel: bool,
+}
+
+impl HostConfig {
+    /// Creates the default configuration.
+    pub fn new() -> HostConfig {
+        HostConfig::default()
+    }
+
+    /// Enables the pooling instance allocator.
+    ///
+    /// With pooling, instances are carved out of memory that was reserved
+    /// when the engine was created which makes instantiation very cheap.
+    /// This pays off when plugins are instantiated frequently.
+    pub fn pooling(&mut self, limits: PoolingLimits) -> &mut HostConfig {
+        self.pooling = Some(limits);
+        self
+    }
+
+    /// Enables fuel metering of WASM code.
+    ///
+    /// Metering makes execution a bit slower but is needed to account and
+    /// limit fuel with a [`QuotaRegistry`](crate::QuotaRegistry).
+    pub fn consume_fuel(&mut self, yes: bool) -> &mut HostConfig {
+        self.consume_fuel = yes;
+        self
+    }
+
+    /// Returns the pooling limits if pooling is enabled.
+    pub fn pooling_limits(&self) -> Option<&PoolingLimits> {
+        self.pooling.as_ref()
+    }
+
+    /// Creates an engine from the configuration.
+    pub fn build_engine(&self) -> Result<Engine, HostError> {
+        let mut config = Config::new();
+        config.consume_fuel(self.consume_fuel);
+        if let Some(limits) = self.pooling {
+            let mut pooling = PoolingAllocationConfig::default();
+            pooling
+                .instance_count(limits.instances)
+                .instance_memories(1)
+                .instance_memory_pages(limits.memory_pages)
+                .instance_tables(1)
+                .instance_table_elements(limits.table_elements);
+            config.allocation_strategy(InstanceAllocationStrategy::Pooling(pooling));
+        }
+        let engine = Engine::new(&config).map_err(HostError::EngineInitFailed)?;
+        #[cfg(feature = "metrics")]
+        if let Some(limits) = self.pooling {
+            crate::metrics::pool_slots(limits.instances);
+        }
+        Ok(engine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasmtime::{Module, Store};
+
+    #[test]
+    fn test_for_modules() {
+        let bounded = wat::parse_str("(module (memory 2 4) (table 1 8 funcref))").unwrap();
+        let unbounded = wat::parse_str("(module (memory 17))").unwrap();
+        let defaults = PoolingLimits {
+            instances: 4,
+            memory_pages: 16,
+            table_elements: 100,
+        };
+
+        let limits = defaults.for_modules([&bounded[..]]).unwrap();
+        assert_eq!(limits.instances, 4);
+        assert_eq!(limits.memory_pages, 4);
+        assert_eq!(limits.table_elements, 8);
+
+        // unbounded memories get the configured size, at least their initial one
+        let limits = defaults
+            .for_modules([&bounded[..], &unbounded[..]])
+            .unwrap();
+        assert_eq!(limits.memory_pages, 17);
+        let limits = defaults
+            .for_modules([&wat::parse_str("(module (memory 1))").unwrap()[..]])
+            .unwrap();
+        assert_eq!(limits.memory_pages, 16);
+    }
+
+    #[test]
+    fn test_pooling() {
+        let module = wat::parse_str("(module (memory 1))").unwrap();
+        let limits = PoolingLimits {
+            instances: 1,
+            ..PoolingLimits::default()
+        };
+        let engine = HostConfig::new()
+            .pooling(limits.for_modules([&module[..]]).unwrap())
+            .build_engine()
+            .unwrap();
+        let module = Module::new(&engine, &module).unwrap();
+        let mut first = Store::new(&engine, ());
+        wasmtime::Instance::new(&mut first, &module, &[]).unwrap();
+
+        // the only slot of the pool is taken until the store is dropped
+        let mut second = Store::new(&engine, ());
+        assert!(wasmtime::Instance::new(&mut second, &module, &[]).is_err());
+        drop(first);
+        wasmtime::Instance::new(&mut second, &module, &[]).unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use worthless_bridge::{Error, Request, Value};
+
+/// A frame of the stack of a paused plugin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugFrame {
+    /// The name of the function or `<anonymous>`.
+    pub function: String,
+    /// The filename if the frame is not native code.
+    pub file: Option<String>,
+    /// The line number if known.
+    pub line: Option<u32>,
+    /// The column number if known.
+    pub column: Option<u32>,
+}
+
+/// A paused plugin as reported to a [`DebugSession`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebugPause {
+    /// Why the plugin paused: `breakpoint`, `step`, `requested` or `exception`.
+    pub reason: String,
+    /// The file that is paused in.
+    pub file: String,
+    /// The line of the statement that runs next.
+    pub line: u32,
+    /// The stack of the paused code, the innermost frame comes first.
+    pub stack: Vec<DebugFrame>,
+    /// The variables in scope with previews of their values.
+    pub scope: Vec<(String, String)>,
+    /// A preview of the thrown value when pausing on an exception.
+    pub exception: Option<String>,
+    /// The result or error of the last command that kept the plugin paused.
+    ///
+    /// Evaluations result in a preview of the value as text, the other
+    /// commands in the response of the `__debug` endpoint.
+    pub result: Option<Result<Value, String>>,
+}
+
+/// What a paused plugin does next.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugCommand {
+    /// Runs until the next breakpoint.
+    Continue,
+    /// Pauses at the next statement of the function or its callers.
+    StepOver,
+    /// Pauses at the next statement, including those of called functions.
+    StepInto,
+    /// Pauses at the next statement once the function returned.
+    StepOut,
+    /// Evaluates an expression in the paused scope and stays paused.
+    Evaluate(String),
+    /// Sets a breakpoint on a line of a file and stays paused.
+    SetBreakpoint(String, u32),
+    /// Removes a breakpoint and stays paused.
+    RemoveBreakpoint(String, u32),
+    /// Removes all breakpoints and stays paused.
+    ClearBreakpoints,
+    /// Enables or disables pausing on exceptions and stays paused.
+    PauseOnExceptions(bool),
+}
+
+impl DebugPause {
+    /// Reads a pause from the payload of the debug pause endpoint.
+    pub(crate) fn from_value(value: &Value) -> DebugPause {
+        let mut rv = DebugPause::default();
+        let mut result = None;
+        let mut error = None;
+        for (key, value) in map_items(value) {
+            match key {
+                "reason" => rv.reason = text(value).unwrap_or_default(),
+                "file" => rv.file = text(value).unwrap_or_default(),
+                "line" => rv.line = number(value).unwrap_or(0),
+                "stack" => {
+                    if let Value::Array(frames) = value {
+                        rv.stack = frames.iter().map(DebugFrame::from_value).collect();
+                    }
+                }
+                "scope" => {
+                    rv.scope = map_items(value)
+                        .map(|(name, value)| (name.to_string(), text(value).unwrap_or_default()))
+                        .collect();
+                }
+                "exception" => rv.exception = text(value),
+                "result" => result = Some(value.clone()),
+                "error" => error = text(value),
+                _ => {}
+            }
+        }
+        rv.result = match (result, error) {
+            (_, Some(error)) => Some(Err(error)),
+            (Some(result), None) => Some(Ok(result)),
+            (None, None) => None,
+        };
+        rv
+    }
+}
+
+impl DebugFrame {
+    fn from_value(value: &Value) -> DebugFrame {
+        let mut rv = DebugFrame {
+            function: String::new(),
+            file: None,
+            line: None,
+            column: None,
+        };
+        for (key, value) in map_items(value) {
+            match key {
+                "function" => rv.function = text(value).unwrap_or_default(),
+                "file" => rv.file = text(value),
+                "line" => rv.line = number(value),
+                "column" => rv.column = number(value),
+                _ => {}
+            }
+        }
+        rv
+    }
+}
+
+impl DebugCommand {
+    /// Converts the command into the response of the debug pause endpoint.
+    pub(crate) fn to_value(&self) -> Value {
+        let action = match self {
+            DebugCommand::Continue => "continue",
+            DebugCommand::StepOver => "step_over",
+            DebugCommand::StepInto => "step_into",
+            DebugCommand::StepOut => "step_out",
+            DebugCommand::Evaluate(_) => "evaluate",
+            DebugCommand::SetBreakpoint(..) => "set_breakpoint",
+            DebugCommand::RemoveBreakpoint(..) => "remove_breakpoint",
+            DebugCommand::ClearBreakpoints => "clear_breakpoints",
+            DebugCommand::PauseOnExceptions(_) => "pause_on_exceptions",
+        };
+        let mut rv = vec![("action".into(), action.into())];
+        match self {
+            DebugCommand::Evaluate(expression) => {
+                rv.push(("expression".into(), expression.as_str().into()));
+            }
+            DebugCommand::SetBreakpoint(file, line)
+            | DebugCommand::RemoveBreakpoint(file, line) => {
+                rv.push(("file".into(), file.as_str().into()));
+                rv.push(("line".into(), (*line).into()));
+            }
+            DebugCommand::PauseOnExceptions(enabled) => {
+                rv.push(("enabled".into(), (*enabled).into()));
+            }
+            _ => {}
+        }
+        Value::Map(rv)
+    }
+}
+
+#[derive(Default)]
+struct SessionState {
+    /// The pause that waits for a command.
+    pause: Option<DebugPause>,
+    /// The command for the pause, taken by the paused plugin.
+    command: Option<DebugCommand>,
+    closed: bool,
+}
+
+#[derive(Default)]
+struct Session {
+    state: Mutex<SessionState>,
+    changed: Condvar,
+}
+
+/// A debugging session with a plugin.
+///
+/// The plugin reports its pauses to the session over the `debug.paused`
+/// endpoint and stays paused until a command resumes it.  The plugin pauses
+/// on the thread that invokes it, so the session is driven from another
+/// one.  Dropping the session lets a paused plugin continue and keeps it
+/// from pausing again.
+pub struct DebugSession {
+    session: Arc<Session>,
+}
+
+impl DebugSession {
+    pub(crate) fn new() -> DebugSession {
+        DebugSession {
+            session: Arc::new(Session::default()),
+        }
+    }
+
+    /// Returns the function that serves the `debug.paused` endpoint.
+    pub(crate) fn endpoint(&self) -> impl Fn(&Request) -> Result<Value, Error> + Send + Sync {
+        let session = self.session.clone();
+        move |req| {
+            let mut state = session.state.lock().unwrap();
+            if state.closed {
+                return Ok(DebugCommand::Continue.to_value());
+            }
+            state.pause = Some(DebugPause::from_value(req.payload()));
+            state.command = None;
+            session.changed.notify_all();
+            loop {
+                if let Some(command) = state.command.take() {
+                    return Ok(command.to_value());
+                }
+                if state.closed {
+                    state.pause = None;
+                    return Ok(DebugCommand::Continue.to_value());
+                }
+                state = session.changed.wait(state).unwrap();
+            }
+        }
+    }
+
+    /// Waits until the plugin is paused and returns the pause.
+    ///
+    /// Returns `None` if the plugin did not pause within the timeout.  Until
+    /// a command is sent the same pause is returned again.
+    pub fn wait_for_pause(&self, timeout: Duration) -> Option<DebugPause> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.session.state.lock().unwrap();
+        loop {
+            if let Some(ref pause) = state.pause {
+                return Some(pause.clone());
+            }
+            let left = deadline.checked_duration_since(Instant::now())?;
+            state = self.session.changed.wait_timeout(state, left).unwrap().0;
+        }
+    }
+
+    /// Sends a command to the paused plugin.
+    ///
+    /// Commands that keep the plugin paused report their result with the
+    /// next pause.  Returns `false` if the plugin is not paused.
+    pub fn send(&self, command: DebugCommand) -> bool {
+        let mut state = self.session.state.lock().unwrap();
+        if state.pause.is_none() {
+            return false;
+        }
+        state.pause = None;
+        state.command = Some(command);
+        self.session.changed.notify_all();
+        true
+    }
+}
+
+impl Drop for DebugSession {
+    fn drop(&mut self) {
+        self.session.state.lock().unwrap().closed = true;
+        self.session.changed.notify_all();
+    }
+}
+
+fn map_items(value: &Value) -> impl Iterator<Item = (&str, &Value)> {
+    let items = match value {
+        Value::Map(items) => &items[..],
+        _ => &[],
+    };
+    items
+        .iter()
+        .filter_map(|(key, value)| Some((key.as_text()?, value)))
+}
+
+fn text(value: &Value) -> Option<String> {
+    value.as_text().map(|x| x.to_string())
+}
+
+fn number(value: &Value) -> Option<u32> {
+    value.as_integer().and_then(|x| u32::try_from(x).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use worthless_bridge::DEBUG_PAUSED_ENDPOINT;
+
+    fn paused(line: u32, result: Option<Value>) -> Request {
+        let mut payload = vec![
+            ("reason".into(), "breakpoint".into()),
+            ("file".into(), "lib.js".into()),
+            ("line".into(), line.into()),
+        ];
+        if let Some(result) = result {
+            payload.push(("result".into(), result));
+        }
+        Request::new(DEBUG_PAUSED_ENDPOINT, Value::Map(payload))
+    }
+
+    #[test]
+    fn test_session() {
+        let session = DebugSession::new();
+        let endpoint = session.endpoint();
+        assert!(session.wait_for_pause(Duration::ZERO).is_none());
+        assert!(!session.send(DebugCommand::Continue));
+
+        let guest = std::thread::spawn(move || {
+            let mut actions = Vec::new();
+            let mut result = None;
+            loop {
+                let command = endpoint(&paused(2, result.take())).unwrap();
+                let action = match &command {
+                    Value::Map(items) => items[0].1.as_text().unwrap().to_string(),
+                    _ => unreachable!(),
+                };
+                actions.push(action.clone());
+                match action.as_str() {
+                    "evaluate" => result = Some("3".into()),
+                    "set_breakpoint" => result = Some(Value::Map(vec![("line".into(), 4.into())])),
+                    _ => break,
+                }
+            }
+            // the session is gone, so the plugin does not pause again
+            let command = endpoint(&paused(5, None)).unwrap();
+            (actions, command)
+        });
+
+        let pause = session.wait_for_pause(Duration::from_secs(10)).unwrap();
+        assert_eq!(
+            (pause.file.as_str(), pause.line, pause.result),
+            ("lib.js", 2, None)
+        );
+        assert!(session.send(DebugCommand::Evaluate("a + b".into())));
+        let pause = session.wait_for_pause(Duration::from_secs(10)).unwrap();
+        assert_eq!(pause.result, Some(Ok("3".into())));
+        assert!(session.send(DebugCommand::SetBreakpoint("lib.js".into(), 3)));
+        let pause = session.wait_for_pause(Duration::from_secs(10)).unwrap();
+        assert_eq!(
+            pause.result,
+            Some(Ok(Value::Map(vec![("line".into(), 4.into())])))
+        );
+        assert!(session.send(DebugCommand::StepOver));
+        drop(session);
+
+        let (actions, command) = guest.join().unwrap();
+        assert_eq!(actions, ["evaluate", "set_breakpoint", "step_over"]);
+        assert_eq!(command, DebugCommand::Continue.to_value());
+    }
+}
@@ -0,0 +1,483 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::io::{Cursor, Seek};
+use std::pin::pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use worthless_bridge::{
+    Error, ErrorKind, Handler, Request, Response, Value, SUBSCRIBE_ENDPOINT, UNSUBSCRIBE_ENDPOINT,
+};
+
+use crate::observer::{BridgeMessage, Direction, PluginName};
+use crate::transport::Transport;
+
+/// The endpoint the console of the guest writes to.
+pub const LOG_ENDPOINT: &str = "log.emit";
+
+/// The signature of host side endpoints the guest can invoke.
+pub type EndpointFunc = dyn Fn(&Request) -> Result<Value, Error> + Send + Sync;
+
+/// An endpoint as stored, functions and handlers alike produce responses.
+type Route = dyn Fn(&Request) -> Response + Send + Sync;
+
+/// The endpoints the host exposes to a plugin.
+#[derive(Clone)]
+pub(crate) struct Endpoints {
+    map: Arc<RwLock<BTreeMap<String, Arc<Route>>>>,
+    // when set host calls go through this transport instead
+    transport: Arc<RwLock<Option<Arc<dyn Transport>>>>,
+    // the topics the guest subscribed to
+    topics: Arc<RwLock<BTreeSet<String>>>,
+    // the name of the plugin for observers
+    name: PluginName,
+}
+
+impl Endpoints {
+    /// Creates the endpoints with the default endpoints registered.
+    pub fn new() -> Endpoints {
+        let rv = Endpoints {
+            map: Default::default(),
+            transport: Default::default(),
+            topics: Default::default(),
+            name: Default::default(),
+        };
+        rv.register(LOG_ENDPOINT, log_emit);
+        rv
+    }
+
+    /// Registers or replaces an endpoint.
+    pub fn register<F>(&self, endpoint: &str, f: F)
+    where
+        F: Fn(&Request) -> Result<Value, Error> + Send + Sync + 'static,
+    {
+        self.map.write().unwrap().insert(
+            endpoint.to_string(),
+            Arc::new(move |req: &Request| Response::new(Default::default(), f(req))),
+        );
+    }
+
+    /// Registers or replaces an endpoint served by an async handler.
+    pub fn register_handler<H: Handler + 'static>(&self, endpoint: &str, handler: H) {
+        self.map.write().unwrap().insert(
+            endpoint.to_string(),
+            Arc::new(move |req: &Request| block_on_handler(handler.handle(req.clone()))),
+        );
+    }
+
+    /// Returns the name of the plugin the endpoints belong to.
+    pub fn name(&self) -> &PluginName {
+        &self.name
+    }
+
+    /// Sets or clears the transport host calls are sent through.
+    pub fn set_transport(&self, transport: Option<Arc<dyn Transport>>) {
+        *self.transport.write().unwrap() = transport;
+    }
+
+    /// Returns the transport host calls are sent through if one is set.
+    pub fn transport(&self) -> Option<Arc<dyn Transport>> {
+        self.transport.read().unwrap().clone()
+    }
+
+    /// Sets or clears the transport and returns the one it replaced.
+    pub fn replace_transport(
+        &self,
+        transport: Option<Arc<dyn Transport>>,
+    ) -> Option<Arc<dyn Transport>> {
+        std::mem::replace(&mut *self.transport.write().unwrap(), transport)
+    }
+
+    /// Returns the topics the guest subscribed to.
+    pub fn topics(&self) -> Vec<String> {
+        self.topics.read().unwrap().iter().cloned().collect()
+    }
+
+    /// Checks if the guest subscribed to a topic.
+    pub fn is_subscribed(&self, topic: &str) -> bool {
+        self.topics.read().unwrap().contains(topic)
+    }
+
+    /// Forgets all subscriptions, eg: when the guest state is reset.
+    pub fn clear_topics(&self) {
+        self.topics.write().unwrap().clear();
+    }
+
+    /// Replaces the subscriptions, eg: with those of a restored snapshot.
+    pub fn set_topics(&self, topics: &[String]) {
+        *self.topics.write().unwrap() = topics.iter().cloned().collect();
+    }
+
+    /// Handles the subscription control messages of the guest.
+    ///
+    /// They never reach the registered endpoints or the transport.
+    fn handle_subscription(&self, req: &Request) -> Option<Response> {
+        let subscribe = match req.endpoint() {
+            SUBSCRIBE_ENDPOINT => true,
+            UNSUBSCRIBE_ENDPOINT => false,
+            _ => return None,
+        };
+        let topic = match req.payload() {
+            Value::Map(items) => {
+                items
+                    .iter()
+                    .find_map(|(key, value)| match (key.as_text(), value.as_text()) {
+                        (Some("topic"), Some(topic)) => Some(topic),
+                        _ => None,
+                    })
+            }
+            _ => None,
+        };
+        let rv = match topic {
+            Some(topic) => {
+                let mut topics = self.topics.write().unwrap();
+                if subscribe {
+                    topics.insert(topic.to_string());
+                } else {
+                    topics.remove(topic);
+                }
+                Ok(Value::Null)
+            }
+            None => Err(Error::new(
+                ErrorKind::InternalError,
+                "subscription without a topic",
+            )),
+        };
+        Some(Response::new(Default::default(), rv))
+    }
+
+    /// Handles a single request from the guest with the registered endpoints.
+    pub fn dispatch(&self, req: &Request) -> Response {
+        let func = self.map.read().unwrap().get(req.endpoint()).cloned();
+        match func {
+            Some(func) => func(req),
+            None => Response::new(
+                Default::default(),
+                Err(Error::new(
+                    ErrorKind::UnknownEndpoint,
+                    format!("unknown host endpoint '{}'", req.endpoint()),
+                )),
+            ),
+        }
+    }
+
+    /// Handles all requests the guest placed into the pipe.
+    ///
+    /// Responses for requests that are not fire and forget are written into
+    /// the output pipe.
+    pub fn handle_pipe(
+        &self,
+        pipe_in: &RwLock<Cursor<Vec<u8>>>,
+        pipe_out: &RwLock<Cursor<Vec<u8>>>,
+    ) -> Result<(), Error> {
+        let mut buf = {
+            let mut pipe = pipe_in.write().unwrap();
+            let buf = std::mem::take(pipe.get_mut());
+            pipe.rewind().unwrap();
+            buf
+        };
+        let transport = self.transport.read().unwrap().clone();
+        let mut pipe = pipe_out.write().unwrap();
+        pipe.get_mut().clear();
+        let mut rest = &buf[..];
+        while !rest.is_empty() {
+            let req = Request::deserialize_from(&mut rest)?;
+            self.name.notify(|observer, name| {
+                observer.on_bridge_message(name, Direction::ToHost, BridgeMessage::Request(&req))
+            });
+            let response = match (self.handle_subscription(&req), &transport) {
+                (Some(response), _) => response,
+                (None, Some(transport)) => transport.handle(&req),
+                (None, None) => self.dispatch(&req),
+            };
+            self.name.notify(|observer, name| {
+                observer.on_bridge_message(
+                    name,
+                    Direction::ToGuest,
+                    BridgeMessage::Response(&response),
+                )
+            });
+            if !req.fire_and_forget() {
+                response.serialize_to(pipe.get_mut())?;
+            }
+        }
+        pipe.rewind().unwrap();
+
+        // hand the buffer back so the next call can reuse the allocation
+        buf.clear();
+        let mut pipe = pipe_in.write().unwrap();
+        if pipe.get_ref().is_empty() {
+            *pipe.get_mut() = buf;
+        }
+        Ok(())
+    }
+}
+
+impl Transport for Endpoints {
+    fn handle(&self, req: &Request) -> Response {
+        self.dispatch(req)
+    }
+}
+
+/// Wakes the thread that blocks on a future.
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Waits for the response of an async handler.
+///
+/// Plugins that are invoked on a thread of a tokio runtime block that thread.
+/// With the `tokio` feature a multi threaded runtime is told so, which moves
+/// its other tasks to the remaining threads.  A current thread runtime has
+/// no other thread to run the tasks the handler might wait for, so such
+/// calls fail instead of deadlocking.  Without the feature this cannot be
+/// detected and plugins must not be invoked from async code directly.
+fn block_on_handler<F: Future<Output = Response>>(fut: F) -> Response {
+    #[cfg(feature = "tokio")]
+    {
+        use tokio::runtime::{Handle, RuntimeFlavor};
+
+        if let Ok(handle) = Handle::try_current() {
+            return match handle.runtime_flavor() {
+                RuntimeFlavor::CurrentThread => Response::new(
+                    Default::default(),
+                    Err(Error::new(
+                        ErrorKind::InternalError,
+                        "async host endpoints cannot be called from a current thread runtime",
+                    )),
+                ),
+                _ => tokio::task::block_in_place(|| block_on(fut)),
+            };
+        }
+    }
+    block_on(fut)
+}
+
+/// Drives a future to completion on the current thread.
+///
+/// The guest is blocked while a host call runs anyway, so the future of an
+/// async endpoint is simply waited for.
+fn block_on<F: Future>(fut: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut fut = pin!(fut);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(rv) => return rv,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+/// The default logging endpoint which forwards to stderr.
+///
+/// The payload has the `level` and the `message` of the console call.
+pub fn log_emit(req: &Request) -> Result<Value, Error> {
+    let payload = req.payload();
+    let field = |name: &str| match payload {
+        Value::Map(items) => items.iter().find_map(|(key, value)| match (key, value) {
+            (Value::Text(key), Value::Text(value)) if key == name => Some(value.as_str()),
+            _ => None,
+        }),
+        _ => None,
+    };
+    match req.request_id() {
+        Some(request_id) => eprintln!(
+            "[plugin:{}] ({}) {}",
+            field("level").unwrap_or("info"),
+            request_id,
+            field("message").unwrap_or("")
+        ),
+        None => eprintln!(
+            "[plugin:{}] {}",
+            field("level").unwrap_or("info"),
+            field("message").unwrap_or("")
+        ),
+    }
+    Ok(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use crate::observer::{InvokeObserver, Observers};
+
+    fn subscription(endpoint: &str, topic: &str) -> Request {
+        Request::new(endpoint, Value::Map(vec![("topic".into(), topic.into())]))
+    }
+
+    #[test]
+    fn test_topics() {
+        let endpoints = Endpoints::new();
+        assert!(endpoints
+            .handle_subscription(&subscription(SUBSCRIBE_ENDPOINT, "config"))
+            .is_some());
+        assert!(endpoints.is_subscribed("config"));
+        endpoints.handle_subscription(&subscription(UNSUBSCRIBE_ENDPOINT, "config"));
+        assert!(!endpoints.is_subscribed("config"));
+        assert!(endpoints
+            .handle_subscription(&Request::new("echo", Value::Null))
+            .is_none());
+
+        endpoints.set_topics(&["config".into(), "shutdown".into()]);
+        assert_eq!(endpoints.topics(), vec!["config", "shutdown"]);
+        endpoints.clear_topics();
+        assert!(endpoints.topics().is_empty());
+    }
+
+    /// Records the bridge messages with the name of the plugin.
+    #[derive(Default)]
+    struct Recorder {
+        messages: Mutex<Vec<(Option<String>, Direction, String)>>,
+    }
+
+    impl InvokeObserver for Recorder {
+        fn on_bridge_message(
+            &self,
+            plugin: Option<&str>,
+            direction: Direction,
+            message: BridgeMessage<'_>,
+        ) {
+            let endpoint = match message {
+                BridgeMessage::Request(req) => req.endpoint().to_string(),
+                BridgeMessage::Response(_) => "response".to_string(),
+            };
+            self.messages
+                .lock()
+                .unwrap()
+                .push((plugin.map(Into::into), direction, endpoint));
+        }
+    }
+
+    #[test]
+    fn test_observers_see_plugin_name() {
+        let recorder = Arc::new(Recorder::default());
+        let observers = Observers::new();
+        observers.register(recorder.clone());
+
+        let pipe = |req: &Request| RwLock::new(Cursor::new(req.serialize().unwrap()));
+        let output = RwLock::new(Cursor::new(Vec::new()));
+        let req = Request::new("echo", Value::Null);
+        let unobserved = Endpoints::new();
+        unobserved.register("echo", |req| Ok(req.payload().clone()));
+        unobserved.handle_pipe(&pipe(&req), &output).unwrap();
+
+        let endpoints = Endpoints::new();
+        endpoints.register("echo", |req| Ok(req.payload().clone()));
+        endpoints.name().set_observers(observers.clone());
+        endpoints.name().set(Some("observed"));
+        endpoints.handle_pipe(&pipe(&req), &output).unwrap();
+        let name = Some("observed".to_string());
+        assert_eq!(
+            *recorder.messages.lock().unwrap(),
+            [
+                (name.clone(), Direction::ToHost, "echo".to_string()),
+                (name, Direction::ToGuest, "response".to_string())
+            ]
+        );
+
+        // clones of the list share the observers
+        let observer: Arc<dyn InvokeObserver> = recorder.clone();
+        assert!(observers.remove(&observer));
+        assert!(endpoints.name().observers().is_empty());
+        endpoints.handle_pipe(&pipe(&req), &output).unwrap();
+        assert_eq!(recorder.messages.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_scoped_observer() {
+        let recorder = Arc::new(Recorder::default());
+        let pipe = |req: &Request| RwLock::new(Cursor::new(req.serialize().unwrap()));
+        let output = RwLock::new(Cursor::new(Vec::new()));
+        let req = Request::new("echo", Value::Null);
+        let endpoints = Endpoints::new();
+        endpoints.register("echo", |req| Ok(req.payload().clone()));
+        let other = Endpoints::new();
+        other.register("echo", |req| Ok(req.payload().clone()));
+
+        let scope = endpoints.name().scope(recorder.clone());
+        other.handle_pipe(&pipe(&req), &output).unwrap();
+        thread::scope(|s| {
+            s.spawn(|| {
+                let output = RwLock::new(Cursor::new(Vec::new()));
+                endpoints.handle_pipe(&pipe(&req), &output).unwrap();
+            });
+        });
+        assert!(recorder.messages.lock().unwrap().is_empty());
+        endpoints.handle_pipe(&pipe(&req), &output).unwrap();
+        assert_eq!(recorder.messages.lock().unwrap().len(), 2);
+
+        drop(scope);
+        endpoints.handle_pipe(&pipe(&req), &output).unwrap();
+        assert_eq!(recorder.messages.lock().unwrap().len(), 2);
+    }
+
+    /// Returns endpoints with an async `answer` endpoint that waits for a
+    /// task it spawns.
+    fn async_endpoints<S>(spawn: S) -> Endpoints
+    where
+        S: Fn() -> tokio::sync::oneshot::Receiver<i32> + Send + Sync + 'static,
+    {
+        let endpoints = Endpoints::new();
+        let spawn = Arc::new(spawn);
+        endpoints.register_handler(
+            "answer",
+            worthless_bridge::handler_fn(move |_req| {
+                let rx = spawn();
+                async move { Ok(Value::from(rx.await.unwrap())) }
+            }),
+        );
+        endpoints
+    }
+
+    #[test]
+    fn test_async_handler() {
+        let endpoints = async_endpoints(|| {
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            thread::spawn(move || tx.send(42));
+            rx
+        });
+        let response = endpoints.dispatch(&Request::new("answer", Value::Null));
+        assert_eq!(response.into_payload().unwrap(), Value::from(42));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_async_handler_in_runtime() {
+        let endpoints = async_endpoints(|| {
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            tokio::spawn(async move { tx.send(42) });
+            rx
+        });
+        let req = Request::new("answer", Value::Null);
+
+        // the spawned task runs on another thread of the runtime
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .build()
+            .unwrap();
+        let response = rt.block_on(async { endpoints.dispatch(&req) });
+        assert_eq!(response.into_payload().unwrap(), Value::from(42));
+
+        // there is no other thread that could run the task
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let response = rt.block_on(async { endpoints.dispatch(&req) });
+        let err = response.error_ref().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InternalError);
+        assert!(
+            err.to_string().contains("current thread runtime"),
+            "{}",
+            err
+        );
+    }
+}
@@ -4,6 +4,8 @@ use thiserror::Error;
 #[derive(Error, Debug)]
 #[error("Host error")]
 pub enum HostError {
+    #[error("WASM engine initialization failed")]
+    EngineInitFailed(#[source] anyhow::Error),
     #[error("WASM module load failed")]
     WasmModuleLoadFailed(#[source] anyhow::Error),
     #[error("WASM module linking failed")]
@@ -14,4 +16,32 @@ pub enum HostError {
     ProtocolError(#[source] worthless_bridge::Error),
     #[error("bridge i/o error")]
     BridgeIoError(#[source] std::io::Error),
+    #[error("recording failed")]
+    RecordingFailed(#[source] anyhow::Error),
+    #[error("invalid config")]
+    InvalidConfig(#[source] anyhow::Error),
+    #[error("snapshot failed")]
+    SnapshotFailed(#[source] anyhow::Error),
+    #[error("tenant '{0}' exceeded its {1} quota")]
+    QuotaExceeded(String, &'static str),
+    #[error("executor queue is full")]
+    ExecutorBusy,
+    #[error("timed out waiting for the executor")]
+    ExecutorTimeout,
+    #[error("execution failed")]
+    ExecutionFailed(#[source] anyhow::Error),
+    #[error("plugin process failed")]
+    ProcessFailed(#[source] anyhow::Error),
+}
+
+/// Formats an error with all of its sources.
+pub fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut rv = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        rv.push_str(": ");
+        rv.push_str(&err.to_string());
+        source = err.source();
+    }
+    rv
 }
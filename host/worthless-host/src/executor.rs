@@ -0,0 +1,420 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use worthless_bridge::{Request, Response};
+
+use crate::error::{error_chain, HostError};
+use crate::plugin::Plugin;
+
+/// Configures a [`PluginExecutor`].
+#[derive(Debug, Clone)]
+pub struct ExecutorConfig {
+    workers: usize,
+    queue_capacity: usize,
+    queue_timeout: Option<Duration>,
+}
+
+impl Default for ExecutorConfig {
+    fn default() -> ExecutorConfig {
+        ExecutorConfig {
+            workers: thread::available_parallelism().map_or(4, |x| x.get()),
+            queue_capacity: 1024,
+            queue_timeout: None,
+        }
+    }
+}
+
+impl ExecutorConfig {
+    /// Creates the default configuration.
+    ///
+    /// By default there is one worker per CPU and up to 1024 jobs are queued.
+    pub fn new() -> ExecutorConfig {
+        ExecutorConfig::default()
+    }
+
+    /// Sets the number of worker threads.
+    pub fn workers(&mut self, workers: usize) -> &mut ExecutorConfig {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Sets how many jobs may wait for a worker before new ones are rejected.
+    pub fn queue_capacity(&mut self, capacity: usize) -> &mut ExecutorConfig {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    /// Sets how long a job may wait for a worker before it fails.
+    pub fn queue_timeout(&mut self, timeout: Duration) -> &mut ExecutorConfig {
+        self.queue_timeout = Some(timeout);
+        self
+    }
+
+    /// Starts an executor with this configuration.
+    pub fn build(&self) -> PluginExecutor {
+        PluginExecutor::new(self)
+    }
+}
+
+/// Why a job did not produce a result.
+enum Failure {
+    Timeout,
+    Error(anyhow::Error),
+}
+
+type Task = Box<dyn FnOnce(Option<Failure>) + Send>;
+
+struct Job {
+    enqueued: Instant,
+    task: Task,
+}
+
+/// The queued jobs grouped by their fairness key.
+#[derive(Default)]
+struct Queue {
+    lanes: VecDeque<(String, VecDeque<Job>)>,
+    len: usize,
+    shutdown: bool,
+}
+
+impl Queue {
+    fn push(&mut self, key: &str, job: Job) {
+        match self.lanes.iter_mut().find(|(lane, _)| lane == key) {
+            Some((_, jobs)) => jobs.push_back(job),
+            None => self
+                .lanes
+                .push_back((key.to_string(), VecDeque::from([job]))),
+        }
+        self.len += 1;
+    }
+
+    /// Takes the next job round robin over the keys.
+    fn pop(&mut self) -> Option<Job> {
+        let (key, mut jobs) = self.lanes.pop_front()?;
+        let job = jobs.pop_front();
+        if !jobs.is_empty() {
+            self.lanes.push_back((key, jobs));
+        }
+        self.len -= 1;
+        job
+    }
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    available: Condvar,
+    queue_capacity: usize,
+    queue_timeout: Option<Duration>,
+}
+
+/// Runs blocking plugin invocations on a bounded pool of threads.
+///
+/// Invoking a plugin blocks the calling thread until the guest is done, which
+/// stalls the executor of an async application.  The plugin executor moves
+/// these calls to dedicated threads and hands out a [`Pending`] result that
+/// can be awaited or waited on.
+///
+/// Jobs are submitted with a key, usually the plugin or tenant.  Workers pick
+/// jobs round robin over the keys so that one busy key cannot starve the
+/// others.  Jobs that waited longer than the queue timeout fail with
+/// [`HostError::ExecutorTimeout`] instead of running, a job that already runs
+/// cannot be interrupted.
+pub struct PluginExecutor {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl PluginExecutor {
+    /// Starts an executor.
+    pub fn new(config: &ExecutorConfig) -> PluginExecutor {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue::default()),
+            available: Condvar::new(),
+            queue_capacity: config.queue_capacity,
+            queue_timeout: config.queue_timeout,
+        });
+        let workers = (0..config.workers)
+            .map(|idx| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("worthless-executor-{}", idx))
+                    .spawn(move || run_worker(&shared))
+                    .expect("failed to spawn executor thread")
+            })
+            .collect();
+        #[cfg(feature = "metrics")]
+        crate::metrics::executor_workers(config.workers as isize);
+        PluginExecutor { shared, workers }
+    }
+
+    /// Runs a function on a worker thread.
+    ///
+    /// Fails with [`HostError::ExecutorBusy`] if the queue is full.  Errors
+    /// of the function are passed on as their message only since host errors
+    /// cannot move between threads.
+    pub fn execute<F, T>(&self, key: &str, f: F) -> Result<Pending<T>, HostError>
+    where
+        F: FnOnce() -> Result<T, HostError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let slot = Arc::new(Slot::default());
+        let task: Task = {
+            let slot = slot.clone();
+            Box::new(move |failure| {
+                let result = match failure {
+                    Some(failure) => Err(failure),
+                    None => match panic::catch_unwind(AssertUnwindSafe(f)) {
+                        Ok(result) => {
+                            result.map_err(|err| Failure::Error(anyhow!(error_chain(&err))))
+                        }
+                        Err(_) => Err(Failure::Error(anyhow!("job panicked"))),
+                    },
+                };
+                slot.fill(result);
+            })
+        };
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.len >= self.shared.queue_capacity {
+            #[cfg(feature = "metrics")]
+            crate::metrics::executor_rejected();
+            return Err(HostError::ExecutorBusy);
+        }
+        queue.push(
+            key,
+            Job {
+                enqueued: Instant::now(),
+                task,
+            },
+        );
+        drop(queue);
+        #[cfg(feature = "metrics")]
+        crate::metrics::executor_queued(1);
+        self.shared.available.notify_one();
+        Ok(Pending { slot })
+    }
+
+    /// Sends a request to a plugin on a worker thread.
+    ///
+    /// The name of the endpoint is used as key.
+    pub fn send_request(
+        &self,
+        plugin: &Arc<Plugin>,
+        req: Request,
+    ) -> Result<PendingResponse, HostError> {
+        let key = req.endpoint().to_string();
+        self.send_request_with_key(&key, plugin, req)
+    }
+
+    /// Sends a request to a plugin on a worker thread with a fairness key.
+    pub fn send_request_with_key(
+        &self,
+        key: &str,
+        plugin: &Arc<Plugin>,
+        req: Request,
+    ) -> Result<PendingResponse, HostError> {
+        let plugin = plugin.clone();
+        // responses are not thread safe so they travel in serialized form,
+        // the buffer goes to the waiting thread and can't be reused
+        let pending = self.execute(key, move || {
+            let mut buf = Vec::new();
+            plugin
+                .send_request(req)?
+                .serialize_to(&mut buf)
+                .map_err(HostError::ProtocolError)?;
+            Ok(buf)
+        })?;
+        Ok(PendingResponse(pending))
+    }
+
+    /// Returns the number of jobs waiting for a worker.
+    pub fn queued(&self) -> usize {
+        self.shared.queue.lock().unwrap().len
+    }
+}
+
+impl Drop for PluginExecutor {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().shutdown = true;
+        self.shared.available.notify_all();
+        #[cfg(feature = "metrics")]
+        crate::metrics::executor_workers(-(self.workers.len() as isize));
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
+
+fn run_worker(shared: &Shared) {
+    loop {
+        // jobs still queued on shutdown are failed rather than run
+        let (job, shutdown) = {
+            let mut queue = shared.queue.lock().unwrap();
+            loop {
+                if let Some(job) = queue.pop() {
+                    break (job, queue.shutdown);
+                }
+                if queue.shutdown {
+                    return;
+                }
+                queue = shared.available.wait(queue).unwrap();
+            }
+        };
+        let failure = if shutdown {
+            Some(Failure::Error(anyhow!("executor shut down")))
+        } else if shared
+            .queue_timeout
+            .map_or(false, |timeout| job.enqueued.elapsed() > timeout)
+        {
+            #[cfg(feature = "metrics")]
+            crate::metrics::executor_timed_out();
+            Some(Failure::Timeout)
+        } else {
+            None
+        };
+        #[cfg(feature = "metrics")]
+        {
+            crate::metrics::executor_queued(-1);
+            crate::metrics::executor_busy(1);
+        }
+        (job.task)(failure);
+        #[cfg(feature = "metrics")]
+        crate::metrics::executor_busy(-1);
+    }
+}
+
+struct SlotState<T> {
+    result: Option<Result<T, Failure>>,
+    waker: Option<Waker>,
+}
+
+struct Slot<T> {
+    state: Mutex<SlotState<T>>,
+    filled: Condvar,
+}
+
+impl<T> Default for Slot<T> {
+    fn default() -> Slot<T> {
+        Slot {
+            state: Mutex::new(SlotState {
+                result: None,
+                waker: None,
+            }),
+            filled: Condvar::new(),
+        }
+    }
+}
+
+impl<T> Slot<T> {
+    fn fill(&self, result: Result<T, Failure>) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            state.result = Some(result);
+            state.waker.take()
+        };
+        self.filled.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// The result of a job running on a [`PluginExecutor`].
+///
+/// It can be awaited from any async runtime or waited on from a thread.
+pub struct Pending<T> {
+    slot: Arc<Slot<T>>,
+}
+
+impl<T> Pending<T> {
+    /// Blocks until the job is done.
+    pub fn wait(self) -> Result<T, HostError> {
+        let mut state = self.slot.state.lock().unwrap();
+        loop {
+            if let Some(result) = state.result.take() {
+                return result.map_err(into_host_error);
+            }
+            state = self.slot.filled.wait(state).unwrap();
+        }
+    }
+
+    /// Blocks until the job is done or the timeout elapsed.
+    ///
+    /// On timeout the job keeps running but its result is discarded.
+    pub fn wait_timeout(self, timeout: Duration) -> Result<T, HostError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.slot.state.lock().unwrap();
+        loop {
+            if let Some(result) = state.result.take() {
+                return result.map_err(into_host_error);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(HostError::ExecutorTimeout);
+            }
+            state = self
+                .slot
+                .filled
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+impl<T> Future for Pending<T> {
+    type Output = Result<T, HostError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.slot.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result.map_err(into_host_error)),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn into_host_error(failure: Failure) -> HostError {
+    match failure {
+        Failure::Timeout => HostError::ExecutorTimeout,
+        Failure::Error(err) => HostError::ExecutionFailed(err),
+    }
+}
+
+/// The response to a request sent through a [`PluginExecutor`].
+pub struct PendingResponse(Pending<Vec<u8>>);
+
+impl PendingResponse {
+    /// Blocks until the response arrived.
+    pub fn wait(self) -> Result<Response, HostError> {
+        decode_response(self.0.wait()?)
+    }
+
+    /// Blocks until the response arrived or the timeout elapsed.
+    pub fn wait_timeout(self, timeout: Duration) -> Result<Response, HostError> {
+        decode_response(self.0.wait_timeout(timeout)?)
+    }
+}
+
+impl Future for PendingResponse {
+    type Output = Result<Response, HostError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0)
+            .poll(cx)
+            .map(|result| result.and_then(decode_response))
+    }
+}
+
+fn decode_response(buf: Vec<u8>) -> Result<Response, HostError> {
+    Response::deserialize_from(&mut &buf[..]).map_err(HostError::ProtocolError)
+}
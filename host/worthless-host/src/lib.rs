@@ -1,5 +1,48 @@
+mod bundle;
+mod clock;
+mod config;
+mod debugger;
+mod endpoints;
 mod error;
+mod executor;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod observer;
 mod plugin;
+mod policy;
+mod process;
+mod quota;
+mod recording;
+pub mod sections;
+mod shared_memory;
+mod snapshot;
+mod transport;
 
-pub use self::error::HostError;
-pub use self::plugin::Plugin;
+pub use self::bundle::{EmbeddedBundle, BUNDLE_SECTION};
+pub use self::clock::VirtualClock;
+pub use self::config::{HostConfig, PoolingLimits};
+pub use self::debugger::{DebugCommand, DebugFrame, DebugPause, DebugSession};
+pub use self::endpoints::{log_emit, EndpointFunc, LOG_ENDPOINT};
+pub use self::error::{error_chain, HostError};
+pub use self::executor::{ExecutorConfig, Pending, PendingResponse, PluginExecutor};
+#[cfg(feature = "prometheus")]
+pub use self::metrics::install_prometheus;
+#[cfg(feature = "metrics")]
+pub use self::metrics::{register_metrics, MetricsObserver};
+pub use self::observer::{
+    BridgeMessage, Direction, InvokeObserver, InvokePhase, Observers, ScopedObserver,
+};
+pub use self::plugin::{GcStats, Plugin, ProfileFormat, StreamStatus};
+pub use self::policy::{WasiPolicy, WasiRule};
+pub use self::process::{
+    serve_process, serve_worker, Backend, PluginInstance, PluginProcess, ProcessConfig,
+};
+pub use self::quota::{Quota, QuotaRegistry, ResourceUsage};
+pub use self::recording::{
+    InvocationCall, InvocationRecording, RecordedCall, RecordedInvocation, RecordingTransport,
+    ReplayTransport,
+};
+pub use self::snapshot::Snapshot;
+pub use self::transport::Transport;
+
+pub use wasmtime::Engine;
@@ -0,0 +1,242 @@
+//! Reports what plugins are doing as metrics.
+//!
+//! The metrics are emitted through the `metrics` facade, so they end up in
+//! whatever recorder the process installed.  With the `prometheus` feature
+//! [`install_prometheus`] installs a recorder that renders them in the
+//! Prometheus text format.
+use std::sync::Arc;
+use std::time::Duration;
+
+use metrics::{
+    decrement_gauge, describe_counter, describe_gauge, describe_histogram, histogram,
+    increment_counter, increment_gauge, Unit,
+};
+
+use crate::error::HostError;
+use crate::observer::{InvokeObserver, Observers};
+
+const LOADS: &str = "worthless_plugin_load_seconds";
+const INVOCATIONS: &str = "worthless_invocations_total";
+const REQUESTS: &str = "worthless_requests_total";
+const INVOCATION_ERRORS: &str = "worthless_invocation_errors_total";
+const INVOCATION_DURATION: &str = "worthless_invocation_duration_seconds";
+const TRAPS: &str = "worthless_traps_total";
+const FUEL: &str = "worthless_fuel_consumed";
+const WASI_DENIED: &str = "worthless_wasi_denied_total";
+const EXECUTOR_QUEUED: &str = "worthless_executor_queued_jobs";
+const EXECUTOR_BUSY: &str = "worthless_executor_busy_workers";
+const EXECUTOR_REJECTED: &str = "worthless_executor_rejected_total";
+const EXECUTOR_TIMEOUTS: &str = "worthless_executor_timeouts_total";
+const EXECUTOR_WORKERS: &str = "worthless_executor_workers";
+const POOL_SLOTS: &str = "worthless_pool_slots";
+const INSTANCES: &str = "worthless_plugin_instances";
+
+/// An observer that records the invocations of plugins as metrics.
+///
+/// The metrics of plugins carry the name of the plugin as `plugin` label,
+/// see [`Plugin::set_name`](crate::Plugin::set_name).  Register it with
+/// [`register_metrics`] so that it is only registered once per list of
+/// observers.
+#[derive(Debug, Default)]
+pub struct MetricsObserver;
+
+impl InvokeObserver for MetricsObserver {
+    fn on_load(&self, plugin: Option<&str>, duration: Duration) {
+        histogram!(LOADS, duration.as_secs_f64(), "plugin" => label(plugin));
+    }
+
+    fn on_invoke_start(&self, plugin: Option<&str>, requests: usize) {
+        increment_counter!(INVOCATIONS, "plugin" => label(plugin));
+        metrics::counter!(REQUESTS, requests as u64, "plugin" => label(plugin));
+    }
+
+    fn on_invoke_end(&self, plugin: Option<&str>, duration: Duration, error: Option<&HostError>) {
+        histogram!(
+            INVOCATION_DURATION,
+            duration.as_secs_f64(),
+            "plugin" => label(plugin)
+        );
+        if let Some(err) = error {
+            increment_counter!(
+                INVOCATION_ERRORS,
+                "plugin" => label(plugin),
+                "kind" => error_kind(err)
+            );
+        }
+    }
+
+    fn on_trap(&self, plugin: Option<&str>, _trap: &anyhow::Error) {
+        increment_counter!(TRAPS, "plugin" => label(plugin));
+    }
+
+    fn on_fuel_consumed(&self, plugin: Option<&str>, fuel: u64) {
+        histogram!(FUEL, fuel as f64, "plugin" => label(plugin));
+    }
+
+    fn on_wasi_denied(&self, plugin: Option<&str>, function: &str, _fd: Option<u32>) {
+        increment_counter!(
+            WASI_DENIED,
+            "plugin" => label(plugin),
+            "function" => function.to_string()
+        );
+    }
+}
+
+/// Returns the label for the name of a plugin.
+fn label(plugin: Option<&str>) -> String {
+    plugin.unwrap_or("unknown").to_string()
+}
+
+/// Describes the metrics and starts recording the invocations of the
+/// plugins that report to the observers.
+///
+/// The recorder has to be installed before, calling this more than once
+/// with the same observers counts everything multiple times.
+pub fn register_metrics(observers: &Observers) {
+    describe_histogram!(LOADS, Unit::Seconds, "Time it took to load a plugin.");
+    describe_counter!(INVOCATIONS, Unit::Count, "Batches sent to plugins.");
+    describe_counter!(REQUESTS, Unit::Count, "Requests sent to plugins.");
+    describe_counter!(
+        INVOCATION_ERRORS,
+        Unit::Count,
+        "Invocations that failed by the kind of failure."
+    );
+    describe_histogram!(
+        INVOCATION_DURATION,
+        Unit::Seconds,
+        "Time it took plugins to handle a batch."
+    );
+    describe_counter!(TRAPS, Unit::Count, "Traps of the WASM code of plugins.");
+    describe_histogram!(FUEL, Unit::Count, "Fuel consumed per invocation.");
+    describe_counter!(
+        WASI_DENIED,
+        Unit::Count,
+        "WASI calls denied by the policy of a plugin."
+    );
+    describe_gauge!(
+        EXECUTOR_QUEUED,
+        Unit::Count,
+        "Jobs waiting for a worker of an executor."
+    );
+    describe_gauge!(
+        EXECUTOR_BUSY,
+        Unit::Count,
+        "Executor workers running a job."
+    );
+    describe_counter!(
+        EXECUTOR_REJECTED,
+        Unit::Count,
+        "Jobs rejected because the queue was full."
+    );
+    describe_counter!(
+        EXECUTOR_TIMEOUTS,
+        Unit::Count,
+        "Jobs that waited too long for a worker."
+    );
+    describe_gauge!(
+        EXECUTOR_WORKERS,
+        Unit::Count,
+        "Workers of executors, busy or not."
+    );
+    describe_gauge!(
+        POOL_SLOTS,
+        Unit::Count,
+        "Instance slots of engines with the pooling allocator."
+    );
+    describe_gauge!(
+        INSTANCES,
+        Unit::Count,
+        "Plugins instantiated in the process, each takes a slot of a pool."
+    );
+    observers.register(Arc::new(MetricsObserver));
+}
+
+/// Installs a Prometheus recorder and registers the metrics on the
+/// observers, see [`register_metrics`].
+///
+/// The returned handle renders the metrics for a scrape endpoint.
+#[cfg(feature = "prometheus")]
+pub fn install_prometheus(
+    observers: &Observers,
+) -> Result<metrics_exporter_prometheus::PrometheusHandle, HostError> {
+    use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Suffix("_seconds".into()),
+            &[
+                0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+            ],
+        )
+        .and_then(|builder| {
+            builder.set_buckets_for_metric(
+                Matcher::Full(FUEL.into()),
+                &[1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10],
+            )
+        })
+        .and_then(|builder| builder.install_recorder())
+        .map_err(|err| HostError::InvalidConfig(err.into()))?;
+    register_metrics(observers);
+    Ok(handle)
+}
+
+/// Returns the label for the kind of a failed invocation.
+fn error_kind(err: &HostError) -> &'static str {
+    match err {
+        HostError::WasmInvokeFailed(_) => "invoke_failed",
+        HostError::ProtocolError(_) => "protocol_error",
+        HostError::BridgeIoError(_) => "bridge_io_error",
+        HostError::QuotaExceeded(..) => "quota_exceeded",
+        HostError::SnapshotFailed(_) => "snapshot_failed",
+        HostError::ProcessFailed(_) => "process_failed",
+        HostError::ExecutionFailed(_) => "execution_failed",
+        _ => "other",
+    }
+}
+
+/// Records that a job was queued on or taken off an executor.
+pub(crate) fn executor_queued(delta: isize) {
+    match delta {
+        delta if delta >= 0 => increment_gauge!(EXECUTOR_QUEUED, delta as f64),
+        delta => decrement_gauge!(EXECUTOR_QUEUED, -delta as f64),
+    }
+}
+
+/// Records that a worker of an executor started or finished a job.
+pub(crate) fn executor_busy(delta: isize) {
+    match delta {
+        delta if delta >= 0 => increment_gauge!(EXECUTOR_BUSY, delta as f64),
+        delta => decrement_gauge!(EXECUTOR_BUSY, -delta as f64),
+    }
+}
+
+/// Records that a job was rejected because the queue was full.
+pub(crate) fn executor_rejected() {
+    increment_counter!(EXECUTOR_REJECTED);
+}
+
+/// Records that a job timed out waiting for a worker.
+pub(crate) fn executor_timed_out() {
+    increment_counter!(EXECUTOR_TIMEOUTS);
+}
+
+/// Records that executor workers were started or stopped.
+pub(crate) fn executor_workers(delta: isize) {
+    match delta {
+        delta if delta >= 0 => increment_gauge!(EXECUTOR_WORKERS, delta as f64),
+        delta => decrement_gauge!(EXECUTOR_WORKERS, -delta as f64),
+    }
+}
+
+/// Records the slots of an engine that was built with a pooling allocator.
+pub(crate) fn pool_slots(slots: u32) {
+    increment_gauge!(POOL_SLOTS, slots as f64);
+}
+
+/// Records that a plugin was instantiated or dropped.
+pub(crate) fn instances(delta: isize) {
+    match delta {
+        delta if delta >= 0 => increment_gauge!(INSTANCES, delta as f64),
+        delta => decrement_gauge!(INSTANCES, -delta as f64),
+    }
+}
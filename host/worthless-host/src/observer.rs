@@ -0,0 +1,292 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use worthless_bridge::{Request, Response};
+
+use crate::error::HostError;
+
+thread_local! {
+    /// The observers scoped to this thread by the plugin they observe, see
+    /// [`PluginName::scope`].
+    static SCOPED: RefCell<Vec<(usize, Arc<dyn InvokeObserver>)>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+/// The direction a message travels over the bridge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the host to the guest.
+    ToGuest,
+    /// From the guest to the host.
+    ToHost,
+}
+
+/// A message that travels over the bridge.
+#[derive(Clone, Copy)]
+pub enum BridgeMessage<'a> {
+    /// A request, either to the plugin or a host call.
+    Request(&'a Request),
+    /// A response, either from the plugin or to a host call.
+    Response(&'a Response),
+}
+
+/// A phase of an invocation of a plugin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvokePhase {
+    /// The requests are serialized for the guest.
+    Serialize,
+    /// The guest handles the requests, including the host calls it makes.
+    Dispatch,
+    /// The responses of the guest are deserialized.
+    Deserialize,
+}
+
+impl InvokePhase {
+    /// Returns the name of the phase.
+    pub fn name(self) -> &'static str {
+        match self {
+            InvokePhase::Serialize => "serialize",
+            InvokePhase::Dispatch => "dispatch",
+            InvokePhase::Deserialize => "deserialize",
+        }
+    }
+}
+
+/// Observes what plugins are doing.
+///
+/// Observers are registered on [`Observers`] which are handed to the
+/// plugins to observe, usually by the registry that holds the plugins.
+/// Every method gets the name of the plugin, see
+/// [`Plugin::set_name`](crate::Plugin::set_name), so that observers can tell
+/// plugins apart.  All methods do nothing by default.  They are invoked on
+/// the thread that uses the plugin so they should return quickly.
+pub trait InvokeObserver: Send + Sync {
+    /// Called after a plugin was loaded.
+    ///
+    /// Observers handed to a plugin that is loaded already are told about
+    /// the load right away, see [`Plugin::set_observers`](crate::Plugin::set_observers).
+    fn on_load(&self, plugin: Option<&str>, duration: Duration) {
+        let _ = (plugin, duration);
+    }
+
+    /// Called before a batch of requests is sent to a plugin.
+    fn on_invoke_start(&self, plugin: Option<&str>, requests: usize) {
+        let _ = (plugin, requests);
+    }
+
+    /// Called after a batch of requests was handled or failed.
+    fn on_invoke_end(&self, plugin: Option<&str>, duration: Duration, error: Option<&HostError>) {
+        let _ = (plugin, duration, error);
+    }
+
+    /// Called when a phase of an invocation starts.
+    ///
+    /// Phases do not overlap and every phase that started also ends, even if
+    /// the invocation fails.
+    fn on_phase_start(&self, plugin: Option<&str>, phase: InvokePhase) {
+        let _ = (plugin, phase);
+    }
+
+    /// Called when a phase of an invocation ended.
+    fn on_phase_end(&self, plugin: Option<&str>, phase: InvokePhase) {
+        let _ = (plugin, phase);
+    }
+
+    /// Called when the WASM code of a plugin trapped.
+    fn on_trap(&self, plugin: Option<&str>, trap: &anyhow::Error) {
+        let _ = (plugin, trap);
+    }
+
+    /// Called after an invocation with the fuel it consumed.
+    ///
+    /// This is only called if the engine meters fuel.
+    fn on_fuel_consumed(&self, plugin: Option<&str>, fuel: u64) {
+        let _ = (plugin, fuel);
+    }
+
+    /// Called for every message that is sent over the bridge.
+    fn on_bridge_message(
+        &self,
+        plugin: Option<&str>,
+        direction: Direction,
+        message: BridgeMessage<'_>,
+    ) {
+        let _ = (plugin, direction, message);
+    }
+
+    /// Called when the [`WasiPolicy`](crate::WasiPolicy) of a plugin denied
+    /// a WASI call, with the descriptor for operations on descriptors.
+    fn on_wasi_denied(&self, plugin: Option<&str>, function: &str, fd: Option<u32>) {
+        let _ = (plugin, function, fd);
+    }
+}
+
+/// A list of [`InvokeObserver`]s that plugins report to.
+///
+/// Clones share the list, so a registry can hand its observers to all of
+/// its plugins and observers registered later see them as well.
+#[derive(Clone, Default)]
+pub struct Observers(Arc<RwLock<Vec<Arc<dyn InvokeObserver>>>>);
+
+impl Observers {
+    /// Creates an empty list.
+    pub fn new() -> Observers {
+        Observers::default()
+    }
+
+    /// Registers an observer.
+    pub fn register(&self, observer: Arc<dyn InvokeObserver>) {
+        self.0.write().unwrap().push(observer);
+    }
+
+    /// Removes an observer, returns `false` if it was not registered.
+    pub fn remove(&self, observer: &Arc<dyn InvokeObserver>) -> bool {
+        let mut observers = self.0.write().unwrap();
+        let len = observers.len();
+        observers.retain(|x| !Arc::ptr_eq(x, observer));
+        observers.len() != len
+    }
+
+    /// Removes all observers.
+    pub fn clear(&self) {
+        self.0.write().unwrap().clear();
+    }
+
+    /// Returns `true` if no observer is registered.
+    pub fn is_empty(&self) -> bool {
+        self.0.read().unwrap().is_empty()
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Observers")
+            .field("len", &self.0.read().unwrap().len())
+            .finish()
+    }
+}
+
+/// The name of a plugin and the observers it is reported to.
+///
+/// Clones share both, so everything that notifies about a plugin sees when
+/// it is renamed or handed other observers.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PluginName {
+    name: Arc<RwLock<Option<Arc<str>>>>,
+    observers: Arc<RwLock<Observers>>,
+}
+
+impl PluginName {
+    /// Sets or clears the name.
+    pub fn set(&self, name: Option<&str>) {
+        *self.name.write().unwrap() = name.map(Arc::from);
+    }
+
+    /// Returns the name.
+    pub fn get(&self) -> Option<Arc<str>> {
+        self.name.read().unwrap().clone()
+    }
+
+    /// Replaces the observers the plugin is reported to.
+    pub fn set_observers(&self, observers: Observers) {
+        *self.observers.write().unwrap() = observers;
+    }
+
+    /// Returns the observers the plugin is reported to.
+    pub fn observers(&self) -> Observers {
+        self.observers.read().unwrap().clone()
+    }
+
+    /// Notifies an observer about what the plugin does on the current thread
+    /// until the returned guard is dropped.
+    pub fn scope(&self, observer: Arc<dyn InvokeObserver>) -> ScopedObserver {
+        SCOPED.with(|scoped| scoped.borrow_mut().push((self.key(), observer.clone())));
+        ScopedObserver {
+            plugin: self.clone(),
+            observer,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Identifies the plugin among the scoped observers.
+    fn key(&self) -> usize {
+        Arc::as_ptr(&self.name) as *const () as usize
+    }
+
+    /// Tells observers that a phase of an invocation starts.
+    ///
+    /// They are told that it ended once the returned guard is dropped.
+    pub fn phase(&self, phase: InvokePhase) -> PhaseGuard<'_> {
+        self.notify(|observer, name| observer.on_phase_start(name, phase));
+        PhaseGuard { name: self, phase }
+    }
+
+    /// Invokes a callback for every registered observer with the name.
+    ///
+    /// The observers scoped to the current thread come last.
+    pub fn notify<F: Fn(&dyn InvokeObserver, Option<&str>)>(&self, f: F) {
+        let key = self.key();
+        // scoped observers might invoke plugins themselves, so they are
+        // collected before they are called
+        let scoped = SCOPED.with(|scoped| {
+            scoped
+                .borrow()
+                .iter()
+                .filter(|(scoped_key, _)| *scoped_key == key)
+                .map(|(_, observer)| observer.clone())
+                .collect::<Vec<_>>()
+        });
+        let observers = self.observers();
+        let observers = observers.0.read().unwrap();
+        if observers.is_empty() && scoped.is_empty() {
+            return;
+        }
+        let name = self.get();
+        for observer in observers.iter().chain(scoped.iter()) {
+            f(&**observer, name.as_deref());
+        }
+    }
+}
+
+/// Stops notifying an observer scoped to an invocation when dropped.
+///
+/// Created by [`Plugin::observe_scoped`](crate::Plugin::observe_scoped).
+#[must_use = "the observer is only notified while the guard is alive"]
+pub struct ScopedObserver {
+    // keeps the key of the plugin from being reused
+    plugin: PluginName,
+    observer: Arc<dyn InvokeObserver>,
+    // the observer is registered with the current thread
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for ScopedObserver {
+    fn drop(&mut self) {
+        let key = self.plugin.key();
+        SCOPED.with(|scoped| {
+            let mut scoped = scoped.borrow_mut();
+            if let Some(idx) = scoped.iter().rposition(|(scoped_key, observer)| {
+                *scoped_key == key && Arc::ptr_eq(observer, &self.observer)
+            }) {
+                scoped.remove(idx);
+            }
+        });
+    }
+}
+
+/// Ends a phase of an invocation when dropped, see [`PluginName::phase`].
+pub(crate) struct PhaseGuard<'a> {
+    name: &'a PluginName,
+    phase: InvokePhase,
+}
+
+impl Drop for PhaseGuard<'_> {
+    fn drop(&mut self) {
+        let phase = self.phase;
+        self.name
+            .notify(|observer, name| observer.on_phase_end(name, phase));
+    }
+}
@@ -1,27 +1,143 @@
-use std::collections::BTreeMap;
-use std::io::{Cursor, Read, Seek, Write};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Cursor, Read, Seek, Write};
+use std::mem;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use cap_rand::rngs::StdRng;
+use cap_rand::SeedableRng;
 use serde::{Deserialize, Serialize};
-use wasi_common::file::FileCaps;
+use wasi_common::file::{FileCaps, WasiFile};
 use wasi_common::pipe::{ReadPipe, WritePipe};
+use wasi_common::StringArray;
 use wasmtime::{Engine, Linker, Module, Store};
-use wasmtime_wasi::sync::WasiCtxBuilder;
-use wasmtime_wasi::WasiCtx;
-use worthless_bridge::{Request, Response};
+use wasmtime_wasi::sync::{clocks_ctx, stdio, WasiCtxBuilder};
+use wasmtime_wasi::{I32Exit, WasiCtx};
+use worthless_bridge::{
+    Error, ErrorKind, Handler, Request, Response, Value, BUNDLE_ENDPOINT, CONFIG_ENV_PREFIX,
+    CONFIG_TOPIC, COVERAGE_ENV_VAR, DEBUG_ENDPOINT, DEBUG_ENV_VAR, DEBUG_PAUSED_ENDPOINT,
+    ENDPOINTS_ENDPOINT, GC_ENDPOINT, MAX_REQUEST_SIZE_ENV_VAR, PANIC_META_KEY, PROFILE_ENDPOINT,
+    PUBLISH_ENDPOINT, SHUTDOWN_TOPIC, STREAM_ENDPOINT, TICK_ENDPOINT,
+};
 
-use crate::error::HostError;
+use crate::bundle::{EmbeddedBundle, BUNDLE_SECTION};
+use crate::clock::VirtualClock;
+use crate::debugger::DebugSession;
+use crate::endpoints::Endpoints;
+use crate::error::{error_chain, HostError};
+use crate::observer::{
+    BridgeMessage, Direction, InvokeObserver, InvokePhase, Observers, PluginName, ScopedObserver,
+};
+use crate::policy::{self, WasiPolicy};
+use crate::quota::{QuotaRegistry, ResourceUsage};
+use crate::recording::{InvocationRecorder, InvocationRecording, ReplayTransport};
+use crate::sections;
+use crate::shared_memory::SharedMemory;
+use crate::snapshot::{ModuleBytes, Snapshot};
+use crate::transport::Transport;
+
+/// The fuel an invocation gets if fuel is metered but not limited.
+const UNLIMITED_FUEL: u64 = i64::MAX as u64;
+
+type Pipe = Arc<RwLock<Cursor<Vec<u8>>>>;
 
 /// Represents a WASM plugin
 pub struct Plugin {
-    pipe_in: Arc<RwLock<Cursor<Vec<u8>>>>,
-    pipe_out: Arc<RwLock<Cursor<Vec<u8>>>>,
-    store: Mutex<Store<WasiCtx>>,
+    pipe_in: Pipe,
+    pipe_out: Pipe,
+    host_pipe_in: Pipe,
+    host_pipe_out: Pipe,
+    /// The buffer of the last batch, reused to serialize the next one.
+    input_buf: Mutex<Vec<u8>>,
+    endpoints: Endpoints,
+    instance: Mutex<Instance>,
     module: Module,
+    module_bytes: Option<ModuleBytes>,
+    created: Instant,
+    load_duration: Duration,
+    reset_snapshot: RwLock<Option<Arc<Snapshot>>>,
+    quota_account: RwLock<Option<(Arc<QuotaRegistry>, String)>>,
+    shared_memory_threshold: RwLock<Option<usize>>,
+    max_request_size: RwLock<Option<usize>>,
+    wasi_policy: Arc<RwLock<WasiPolicy>>,
+    stdout_to_stderr: AtomicBool,
+}
+
+/// A store with the module of a plugin instantiated in it.
+struct Instance {
+    store: Store<WasiCtx>,
     linker: Linker<WasiCtx>,
+    /// The WASI functions that check the policy.
+    wasi_shims: BTreeSet<String>,
+    /// The environment of the plugin in the order the variables were set.
+    env: Vec<(String, String)>,
+}
+
+impl Instance {
+    /// Sets or removes a variable in the environment of the plugin.
+    fn set_env(&mut self, var: &str, value: Option<&str>) -> Result<(), HostError> {
+        match value {
+            Some(value) => match self.env.iter_mut().find(|(key, _)| key == var) {
+                Some(entry) => entry.1 = value.to_string(),
+                None => self.env.push((var.to_string(), value.to_string())),
+            },
+            None => self.env.retain(|(key, _)| key != var),
+        }
+        // the WASI environment can only be appended to, so it's rebuilt
+        let wasi = self.store.data_mut();
+        wasi.env = StringArray::new();
+        for (key, value) in &self.env {
+            wasi.push_env(key, value)
+                .map_err(|err| HostError::InvalidConfig(anyhow::anyhow!("{:?}", err)))?;
+        }
+        Ok(())
+    }
+}
+
+/// The memory held by the runtime of a plugin around a garbage collection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcStats {
+    /// The bytes held before the collection.
+    pub before: u64,
+    /// The bytes held after the collection.
+    pub after: u64,
+}
+
+/// The state of a guest stream after the host pushed into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamStatus {
+    /// How many more chunks the stream wants, hold off while it's zero or less.
+    pub desired_size: i64,
+    /// Whether the plugin cancelled the stream.
+    pub cancelled: bool,
+}
+
+/// The format a plugin exports its profile in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProfileFormat {
+    /// Collapsed stacks, one line per stack with the number of samples.
+    ///
+    /// This is read by `flamegraph.pl`, inferno and speedscope.
+    #[default]
+    Collapsed,
+    /// The JSON file format of speedscope.
+    Speedscope,
+}
+
+impl ProfileFormat {
+    fn name(self) -> &'static str {
+        match self {
+            ProfileFormat::Collapsed => "collapsed",
+            ProfileFormat::Speedscope => "speedscope",
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -32,11 +148,55 @@ pub struct Message<T> {
 
 impl Plugin {
     pub fn from_path<P: AsRef<Path>>(engine: &Engine, path: P) -> Result<Plugin, HostError> {
-        let module = Module::from_file(&engine, path).map_err(HostError::WasmModuleLoadFailed)?;
-        Plugin::from_module(engine, module)
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|err| HostError::WasmModuleLoadFailed(err.into()))?;
+        Plugin::load(engine, &bytes, path.file_stem().and_then(|x| x.to_str()))
     }
 
+    /// Loads a plugin from the bytes of a module.
+    ///
+    /// If the module carries a bundle section the bundle is served to the
+    /// runtime via [`BUNDLE_ENDPOINT`].
+    pub fn from_bytes(engine: &Engine, bytes: &[u8]) -> Result<Plugin, HostError> {
+        Plugin::load(engine, bytes, None)
+    }
+
+    /// Loads a plugin named after its module or the given fallback.
+    fn load(engine: &Engine, bytes: &[u8], name: Option<&str>) -> Result<Plugin, HostError> {
+        let started = Instant::now();
+        let bundle = sections::find_custom_section(bytes, BUNDLE_SECTION)
+            .map_err(|err| HostError::WasmModuleLoadFailed(err.into()))?
+            .map(|payload| {
+                EmbeddedBundle::parse(payload).ok_or_else(|| {
+                    HostError::WasmModuleLoadFailed(anyhow::anyhow!("invalid bundle section"))
+                })
+            })
+            .transpose()?;
+        let module = Module::new(engine, bytes).map_err(HostError::WasmModuleLoadFailed)?;
+        let plugin = Plugin::link(engine, module, Some(ModuleBytes::new(bytes)), started)?;
+        if plugin.module.name().is_none() {
+            plugin.endpoints.name().set(name);
+        }
+        if let Some(bundle) = bundle {
+            let payload = bundle.to_value();
+            plugin.register_endpoint(BUNDLE_ENDPOINT, move |_req| Ok(payload.clone()));
+        }
+        Ok(plugin)
+    }
+
+    /// Creates a plugin from a compiled module.
+    ///
+    /// Without the bytes of the module such plugins cannot be snapshotted.
     pub fn from_module(engine: &Engine, module: Module) -> Result<Plugin, HostError> {
+        Plugin::link(engine, module, None, Instant::now())
+    }
+
+    fn link(
+        engine: &Engine,
+        module: Module,
+        module_bytes: Option<ModuleBytes>,
+        started: Instant,
+    ) -> Result<Plugin, HostError> {
         let mut wasi = WasiCtxBuilder::new().inherit_stdio().build();
         let pipe_in = Arc::new(RwLock::new(Cursor::new(Vec::new())));
         let pipe_out = Arc::new(RwLock::new(Cursor::new(Vec::new())));
@@ -50,48 +210,970 @@ impl Plugin {
             Box::new(WritePipe::from_shared(pipe_out.clone())),
             FileCaps::all(),
         );
+        let host_pipe_in = Arc::new(RwLock::new(Cursor::new(Vec::new())));
+        let host_pipe_out = Arc::new(RwLock::new(Cursor::new(Vec::new())));
+        wasi.insert_file(
+            6,
+            Box::new(WritePipe::from_shared(host_pipe_in.clone())),
+            FileCaps::all(),
+        );
+        wasi.insert_file(
+            7,
+            Box::new(ReadPipe::from_shared(host_pipe_out.clone())),
+            FileCaps::all(),
+        );
+        let endpoints = Endpoints::new();
+        endpoints.name().set(module.name());
+        let wasi_policy = Arc::new(RwLock::new(WasiPolicy::new()));
         let mut store = Store::new(&engine, wasi);
-        let mut linker = Linker::new(&engine);
-        wasmtime_wasi::add_to_linker(&mut linker, |s| s)
-            .map_err(HostError::WasmModuleLinkingFailed)?;
-        linker
-            .module(&mut store, "plugin", &module)
-            .map_err(HostError::WasmModuleLinkingFailed)?;
+        let (linker, wasi_shims) = instantiate(
+            &mut store,
+            &module,
+            &endpoints,
+            (&host_pipe_in, &host_pipe_out),
+            &wasi_policy,
+        )?;
+        #[cfg(feature = "metrics")]
+        crate::metrics::instances(1);
         Ok(Plugin {
             pipe_in,
             pipe_out,
-            store: Mutex::new(store),
+            host_pipe_in,
+            host_pipe_out,
+            input_buf: Mutex::new(Vec::new()),
+            endpoints,
+            instance: Mutex::new(Instance {
+                store,
+                linker,
+                wasi_shims,
+                env: Vec::new(),
+            }),
             module,
-            linker,
+            module_bytes,
+            created: Instant::now(),
+            load_duration: started.elapsed(),
+            reset_snapshot: RwLock::new(None),
+            quota_account: RwLock::new(None),
+            shared_memory_threshold: RwLock::new(None),
+            max_request_size: RwLock::new(None),
+            wasi_policy,
+            stdout_to_stderr: AtomicBool::new(false),
         })
     }
 
-    pub fn send_request(&self, req: Request) -> Result<Response, HostError> {
-        let bytes = req.serialize().map_err(HostError::ProtocolError)?;
+    /// Sets the name observers see for the plugin.
+    ///
+    /// It defaults to the name of the module or, for plugins loaded with
+    /// [`from_path`](Self::from_path), the file name without the extension.
+    /// Registries should set the name they know the plugin by so that
+    /// [`InvokeObserver`](crate::InvokeObserver)s can tell plugins apart.
+    pub fn set_name(&self, name: &str) {
+        self.endpoints.name().set(Some(name));
+    }
+
+    /// Returns the name observers see for the plugin.
+    pub fn name(&self) -> Option<String> {
+        self.endpoints.name().get().map(|x| x.to_string())
+    }
+
+    /// Hands the plugin the observers to report to.
+    ///
+    /// Plugins start out with an empty list of their own.  Registries pass
+    /// the list they share among their plugins, best after setting the
+    /// name.  The observers are told about the load of the plugin right
+    /// away, so this should only be done once per list.
+    pub fn set_observers(&self, observers: Observers) {
+        let name = self.endpoints.name();
+        name.set_observers(observers);
+        let duration = self.load_duration;
+        name.notify(|observer, name| observer.on_load(name, duration));
+    }
+
+    /// Returns the observers the plugin reports to.
+    pub fn observers(&self) -> Observers {
+        self.endpoints.name().observers()
+    }
+
+    /// Reports the invocations of the plugin on the current thread to an
+    /// observer until the returned guard is dropped.
+    ///
+    /// Unlike the [`observers`](Self::observers) of the plugin this observer
+    /// does not see invocations from other threads, so wrapping a single
+    /// invocation in the guard scopes the observer to it, including the
+    /// host calls made from it.
+    pub fn observe_scoped(&self, observer: Arc<dyn InvokeObserver>) -> ScopedObserver {
+        self.endpoints.name().scope(observer)
+    }
+
+    /// Registers a host endpoint the plugin can invoke.
+    ///
+    /// By default a `log.emit` endpoint is registered which forwards the
+    /// console output of the plugin to stderr.  Registering an endpoint with
+    /// the same name replaces it.
+    pub fn register_endpoint<F>(&self, endpoint: &str, f: F)
+    where
+        F: Fn(&Request) -> Result<Value, Error> + Send + Sync + 'static,
+    {
+        self.endpoints.register(endpoint, f);
+    }
+
+    /// Registers a host endpoint served by an async [`Handler`].
+    ///
+    /// The plugin waits for the response of the handler.  The future is
+    /// polled on the thread that invokes the plugin without a runtime, so
+    /// handlers that need one (eg: for tokio I/O) have to spawn their work
+    /// onto it and await the result.  When the plugin is invoked on a
+    /// thread of a tokio runtime this needs the `tokio` feature, with it
+    /// calls from a current thread runtime fail instead of deadlocking.
+    pub fn register_handler<H: Handler + 'static>(&self, endpoint: &str, handler: H) {
+        self.endpoints.register_handler(endpoint, handler);
+    }
+
+    /// Replaces the transport host calls of the plugin are sent through.
+    ///
+    /// With `None` host calls are dispatched to the registered endpoints
+    /// again.
+    pub fn set_transport(&self, transport: Option<Arc<dyn Transport>>) {
+        self.endpoints.set_transport(transport);
+    }
+
+    /// Returns a transport that dispatches to the registered endpoints.
+    ///
+    /// This is the transport to wrap when only observing host calls, eg:
+    /// with a [`RecordingTransport`](crate::RecordingTransport).
+    pub fn endpoints(&self) -> Arc<dyn Transport> {
+        Arc::new(self.endpoints.clone())
+    }
+
+    /// Captures the linear memory and globals of the plugin.
+    ///
+    /// The guest runtime initializes itself on the first request, so send
+    /// it one (eg: a [`tick`](Self::tick)) before to capture it initialized.
+    /// This compiles the module once more with the captured state, which
+    /// makes restoring it cheap.  Only plugins loaded from bytes can be
+    /// snapshotted.
+    pub fn snapshot(&self) -> Result<Snapshot, HostError> {
+        let mut instance = self.instance.lock().unwrap();
+        let Instance { store, linker, .. } = &mut *instance;
+        Snapshot::capture(
+            store,
+            linker,
+            &self.module,
+            self.module_bytes.as_ref(),
+            self.endpoints.topics(),
+        )
+    }
+
+    /// Restores the linear memory and globals of the plugin from a snapshot.
+    ///
+    /// The snapshot must have been taken of a plugin loaded from the same
+    /// bytes.  The topics the plugin subscribed to are restored as well.
+    pub fn restore(&self, snapshot: &Snapshot) -> Result<(), HostError> {
+        self.restore_snapshot(&mut self.instance.lock().unwrap(), snapshot)
+    }
+
+    fn restore_snapshot(
+        &self,
+        instance: &mut Instance,
+        snapshot: &Snapshot,
+    ) -> Result<(), HostError> {
+        let image = snapshot.image(self.module_bytes.as_ref())?;
+        self.reinstantiate(instance, image)?;
+        self.endpoints.set_topics(snapshot.topics());
+        Ok(())
+    }
+
+    /// Replaces the instance of the plugin with a fresh one of a module.
+    ///
+    /// The WASI context moves over, so the pipes, environment and clocks
+    /// stay as they are.  The subscriptions of the old instance are gone.
+    fn reinstantiate(&self, instance: &mut Instance, module: &Module) -> Result<(), HostError> {
+        self.endpoints.clear_topics();
+        let engine = instance.store.engine().clone();
+        // the old store goes first to free its slot in a pooling allocator
+        let old = mem::replace(
+            &mut instance.store,
+            Store::new(&engine, WasiCtxBuilder::new().build()),
+        );
+        instance.linker = Linker::new(&engine);
+        let mut store = Store::new(&engine, old.into_data());
+        let rv = instantiate(
+            &mut store,
+            module,
+            &self.endpoints,
+            (&self.host_pipe_in, &self.host_pipe_out),
+            &self.wasi_policy,
+        );
+        instance.store = store;
+        (instance.linker, instance.wasi_shims) = rv?;
+        Ok(())
+    }
+
+    /// Sets or clears the snapshot that is restored before every invocation.
+    ///
+    /// This isolates invocations from each other like a fresh instance per
+    /// call would, without paying for initialization.  Requests sent in one
+    /// batch still share their state, and batches of only internal requests
+    /// such as [`tick`](Self::tick) or stream chunks run on the state the
+    /// last invocation left behind.
+    pub fn set_reset_snapshot(&self, snapshot: Option<Arc<Snapshot>>) {
+        *self.reset_snapshot.write().unwrap() = snapshot;
+    }
+
+    /// Sets or clears the tenant the resources of this plugin are accounted to.
+    ///
+    /// Invocations fail with [`HostError::QuotaExceeded`] once the tenant
+    /// used up its quota.
+    pub fn set_quota_account(&self, account: Option<(Arc<QuotaRegistry>, String)>) {
+        *self.quota_account.write().unwrap() = account;
+    }
+
+    /// Sets the policy that decides which WASI calls of the plugin go through.
+    ///
+    /// Only the WASI functions a policy restricts are checked, so plugins
+    /// without a policy call into WASI directly.  If the new policy restricts
+    /// functions the current one did not, the plugin is instantiated anew
+    /// and loses its state, so the policy is best set right after loading.
+    /// Otherwise it applies to all calls from then on, including the ones of
+    /// an invocation that is running.
+    pub fn set_wasi_policy(&self, policy: WasiPolicy) -> Result<(), HostError> {
+        let restricted = policy::restricted_imports(&policy, &self.module);
+        *self.wasi_policy.write().unwrap() = policy;
+        let mut instance = self.instance.lock().unwrap();
+        if restricted.is_subset(&instance.wasi_shims) {
+            return Ok(());
+        }
+        match *self.reset_snapshot.read().unwrap() {
+            Some(ref snapshot) => self.restore_snapshot(&mut instance, snapshot),
+            None => self.reinstantiate(&mut instance, &self.module),
+        }
+    }
+
+    /// Passes batches of at least the given size through the memory of the
+    /// plugin instead of the pipes.
+    ///
+    /// This saves copying the requests and responses once more, which pays
+    /// off for payloads of several megabytes.  Plugins that do not export the
+    /// functions for it keep using the pipes.  Host calls of the plugin
+    /// always go through the pipes.
+    pub fn set_shared_memory_threshold(&self, threshold: Option<usize>) {
+        *self.shared_memory_threshold.write().unwrap() = threshold;
+    }
+
+    /// Sets or clears the maximum size of a request in bytes.
+    ///
+    /// Larger requests are answered with a payload too large error without
+    /// being sent to the plugin.  The limit is also passed to the guest in
+    /// its environment, so that it holds when the plugin is fed through
+    /// other channels, the guest only picks it up before the first
+    /// invocation.
+    pub fn set_max_request_size(&self, limit: Option<usize>) -> Result<(), HostError> {
+        self.instance.lock().unwrap().set_env(
+            MAX_REQUEST_SIZE_ENV_VAR,
+            limit.map(|x| x.to_string()).as_deref(),
+        )?;
+        *self.max_request_size.write().unwrap() = limit;
+        Ok(())
+    }
+
+    /// Sets a config value in the environment of the plugin.
+    ///
+    /// The guest reads its environment config when it handles its first
+    /// request, so this only has an effect on plugins that were not invoked
+    /// yet.  Config that changes per call is better sent along with the
+    /// request, see [`RequestBuilder::config`](worthless_bridge::RequestBuilder::config).
+    /// Plugins that subscribed to [`CONFIG_TOPIC`] are sent the `key` and
+    /// `value` of the change.
+    ///
+    /// Environment variables are uppercase and the guest lowercases them
+    /// again, so keys must be snake case like `tenant_id`.  Setting a key
+    /// again replaces its value.
+    pub fn set_env_config(&self, key: &str, value: &str) -> Result<(), HostError> {
+        if key.is_empty()
+            || !key
+                .bytes()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'_')
         {
-            let mut pipe = self.pipe_in.write().unwrap();
-            pipe.write_all(&bytes).map_err(HostError::BridgeIoError)?;
-            pipe.rewind().unwrap();
+            return Err(HostError::InvalidConfig(anyhow::anyhow!(
+                "config key '{}' is not snake case",
+                key
+            )));
         }
+        let var = format!("{}{}", CONFIG_ENV_PREFIX, key.to_ascii_uppercase());
+        self.instance.lock().unwrap().set_env(&var, Some(value))?;
+        self.publish(
+            CONFIG_TOPIC,
+            Value::Map(vec![
+                ("key".into(), key.into()),
+                ("value".into(), value.into()),
+            ]),
+        )?;
+        Ok(())
+    }
 
-        let mut store = self.store.lock().unwrap();
-        let symbol = self
-            .linker
-            .get(&mut *store, "plugin", "worthless_handle_request")
-            .unwrap();
-        let func = symbol.into_func().unwrap();
-        func.typed::<(), ()>(&*store)
-            .map_err(HostError::WasmInvokeFailed)?
-            .call(&mut *store, ())
-            .map_err(HostError::WasmInvokeFailed)?;
+    /// Backs the WASI clocks of the plugin with a virtual clock.
+    ///
+    /// The plugin then only sees time pass when the host advances the
+    /// clock, which makes runs reproducible.  With `None` the plugin uses
+    /// the clocks of the system again.  Switching clocks makes the
+    /// monotonic clock jump, so this is best done before the first
+    /// invocation.
+    pub fn set_virtual_clock(&self, clock: Option<&VirtualClock>) {
+        self.instance.lock().unwrap().store.data_mut().clocks = match clock {
+            Some(clock) => clock.to_wasi_clocks(),
+            None => clocks_ctx(),
+        };
+    }
+
+    /// Sends what the plugin writes to stdout to stderr instead.
+    ///
+    /// This keeps stdout free for the frames of [`serve_process`](crate::serve_process).
+    pub(crate) fn redirect_stdout_to_stderr(&self) {
+        self.stdout_to_stderr.store(true, Ordering::Relaxed);
+        let mut instance = self.instance.lock().unwrap();
+        instance.store.data_mut().set_stdout(self.stdout());
+    }
+
+    /// Returns where the plugin writes to stdout outside of [`pipe`](Self::pipe).
+    fn stdout(&self) -> Box<dyn WasiFile> {
+        if self.stdout_to_stderr.load(Ordering::Relaxed) {
+            Box::new(stdio::stderr())
+        } else {
+            Box::new(stdio::stdout())
+        }
+    }
 
+    /// Sends a single request to the plugin and returns the response.
+    ///
+    /// Fire and forget requests do not produce a response and need to be
+    /// sent with [`send_requests`](Self::send_requests).
+    pub fn send_request(&self, req: Request) -> Result<Response, HostError> {
+        self.send_requests(Some(req))?.pop().ok_or_else(|| {
+            HostError::ProtocolError(Error::new(
+                ErrorKind::InternalError,
+                "plugin did not respond",
+            ))
+        })
+    }
+
+    /// Sends a batch of requests to the plugin in one invocation.
+    ///
+    /// The guest handles the requests in order and the responses for all
+    /// requests that are not fire and forget are returned in the same order.
+    pub fn send_requests<I>(&self, reqs: I) -> Result<Vec<Response>, HostError>
+    where
+        I: IntoIterator<Item = Request>,
+    {
+        let reqs: Vec<Request> = reqs.into_iter().collect();
+        let name = self.endpoints.name();
+        name.notify(|observer, name| observer.on_invoke_start(name, reqs.len()));
+        let started = Instant::now();
+        let rv = self.invoke_batch(reqs);
+        let duration = started.elapsed();
+        name.notify(|observer, name| observer.on_invoke_end(name, duration, rv.as_ref().err()));
+        rv
+    }
+
+    /// Invokes the plugin and records what is needed to replay it exactly.
+    ///
+    /// For the invocation the plugin draws random numbers from a seeded
+    /// generator and runs on a [`VirtualClock`] that starts at the current
+    /// time and catches up with the real time only when the plugin calls the
+    /// host.  The host calls go through the endpoints or transport as usual
+    /// and are recorded together with the seed, the clock and the responses.
+    ///
+    /// A failing invocation can then be replayed elsewhere with
+    /// [`invoke_replayed`](Self::invoke_replayed), given that the plugin
+    /// starts out in the same state, eg: fresh or from the same snapshot.
+    /// If the invocation fails the recording is returned nonetheless with
+    /// the calls made up to the failure and the error in
+    /// [`error`](InvocationRecording::error).  Only failing to record fails
+    /// this function.
+    pub fn invoke_recorded<I>(&self, reqs: I) -> Result<InvocationRecording, HostError>
+    where
+        I: IntoIterator<Item = Request>,
+    {
+        let requests: Vec<Request> = reqs.into_iter().collect();
+        let seed = RandomState::new().build_hasher().finish();
+        let epoch_nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_nanos() as u64);
+        let clock = VirtualClock::new(UNIX_EPOCH + Duration::from_nanos(epoch_nanos));
+        let inner = self
+            .endpoints
+            .transport()
+            .unwrap_or_else(|| self.endpoints());
+        let recorder = Arc::new(InvocationRecorder::new(inner, clock.clone()));
+        let result = self.invoke_deterministic(&clock, seed, recorder.clone(), requests.clone());
+        let (responses, error) = match result {
+            Ok(responses) => (responses, None),
+            Err(err) => (Vec::new(), Some(error_chain(&err))),
+        };
+        Ok(InvocationRecording {
+            seed,
+            epoch_nanos,
+            requests,
+            calls: recorder.calls()?,
+            responses,
+            error,
+        })
+    }
+
+    /// Replays an invocation recorded with [`invoke_recorded`](Self::invoke_recorded).
+    ///
+    /// The plugin sees the same random numbers, times and host responses as
+    /// during the recording, the registered endpoints are not called.  The
+    /// returned responses match the recorded ones unless the plugin or its
+    /// state differ, host calls that diverge from the recording fail.
+    pub fn invoke_replayed(
+        &self,
+        recording: &InvocationRecording,
+    ) -> Result<Vec<Response>, HostError> {
+        let clock = VirtualClock::new(recording.epoch());
+        let replay = ReplayTransport::for_invocation(recording, clock.clone())?;
+        self.invoke_deterministic(
+            &clock,
+            recording.seed,
+            Arc::new(replay),
+            recording.requests.clone(),
+        )
+    }
+
+    /// Invokes the plugin with a virtual clock, seeded random numbers and a
+    /// transport that are reverted afterwards.
+    fn invoke_deterministic(
+        &self,
+        clock: &VirtualClock,
+        seed: u64,
+        transport: Arc<dyn Transport>,
+        reqs: Vec<Request>,
+    ) -> Result<Vec<Response>, HostError> {
+        let (clocks, random) = {
+            let mut instance = self.instance.lock().unwrap();
+            let wasi = instance.store.data_mut();
+            (
+                mem::replace(&mut wasi.clocks, clock.to_wasi_clocks()),
+                mem::replace(&mut wasi.random, Box::new(StdRng::seed_from_u64(seed))),
+            )
+        };
+        let transport = self.endpoints.replace_transport(Some(transport));
+        let rv = self.send_requests(reqs);
+        self.endpoints.set_transport(transport);
+        let mut instance = self.instance.lock().unwrap();
+        let wasi = instance.store.data_mut();
+        wasi.clocks = clocks;
+        wasi.random = random;
+        rv
+    }
+
+    fn invoke_batch(&self, reqs: Vec<Request>) -> Result<Vec<Response>, HostError> {
+        let account = self.quota_account.read().unwrap().clone();
+        if let Some((ref registry, ref tenant)) = account {
+            registry.check(tenant)?;
+        }
+        let reset = reqs.iter().any(|req| !req.endpoint().starts_with("__"));
+        let limit = *self.max_request_size.read().unwrap();
+        let threshold = *self.shared_memory_threshold.read().unwrap();
+        let mut expected = 0;
+        let mut rejected = Vec::new();
+        let mut input = mem::take(&mut *self.input_buf.lock().unwrap());
+        // with a threshold the requests might be written straight into the
+        // memory of the plugin, so they are only measured for now
+        let mut unwritten = Vec::new();
+        let mut bytes_in = 0;
+        let serialize = self.endpoints.name().phase(InvokePhase::Serialize);
+        for req in reqs {
+            let start = input.len();
+            let size = match threshold {
+                Some(_) => serialized_size(&req)?,
+                None => {
+                    req.serialize_to(&mut input)
+                        .map_err(HostError::ProtocolError)?;
+                    input.len() - start
+                }
+            };
+            if limit.map_or(false, |limit| size > limit) {
+                input.truncate(start);
+                if !req.fire_and_forget() {
+                    rejected.push((expected + rejected.len(), request_too_large(&req, size)));
+                }
+                continue;
+            }
+            if !req.fire_and_forget() {
+                expected += 1;
+            }
+            self.endpoints.name().notify(|observer, name| {
+                observer.on_bridge_message(name, Direction::ToGuest, BridgeMessage::Request(&req))
+            });
+            bytes_in += size;
+            if threshold.is_some() {
+                unwritten.push(req);
+            }
+        }
+
+        let mut instance = self.instance.lock().unwrap();
+        if reset {
+            if let Some(ref snapshot) = *self.reset_snapshot.read().unwrap() {
+                self.restore_snapshot(&mut instance, snapshot)?;
+            }
+        }
+        let Instance { store, linker, .. } = &mut *instance;
+        let shared = match threshold {
+            Some(threshold) if bytes_in >= threshold => SharedMemory::lookup(store, linker),
+            _ => None,
+        };
+        if shared.is_none() {
+            input.reserve_exact(bytes_in - input.len());
+            for req in &unwritten {
+                req.serialize_to(&mut input)
+                    .map_err(HostError::ProtocolError)?;
+            }
+        }
+        drop(serialize);
+        *self.pipe_in.write().unwrap() = Cursor::new(input);
         {
-            let mut buf = Vec::new();
             let mut pipe = self.pipe_out.write().unwrap();
+            pipe.get_mut().clear();
             pipe.rewind().unwrap();
-            pipe.read_to_end(&mut buf).unwrap();
-            Ok(Response::deserialize(&buf).map_err(HostError::ProtocolError)?)
         }
+        let func = linker
+            .get(&mut *store, "plugin", "worthless_handle_request")
+            .and_then(|symbol| symbol.into_func())
+            .ok_or_else(|| {
+                HostError::WasmInvokeFailed(anyhow::anyhow!(
+                    "plugin does not export worthless_handle_request"
+                ))
+            })?
+            .typed::<(), ()>(&*store)
+            .map_err(HostError::WasmInvokeFailed)?;
+
+        let fuel_before = store.fuel_consumed();
+        if fuel_before.is_some() {
+            refuel(store, fuel_budget(&account))?;
+        }
+        let dispatch = self.endpoints.name().phase(InvokePhase::Dispatch);
+        let started = Instant::now();
+        let result = match shared {
+            Some(ref shared) => shared.call(store, &unwritten, bytes_in).map(Some),
+            None => func.call(&mut *store, ()).map(|()| None),
+        };
+        let cpu_time = started.elapsed();
+        drop(dispatch);
+
+        let pipe = self.pipe_out.read().unwrap();
+        let output = match (&shared, &result) {
+            (Some(shared), Ok(Some(location))) => shared.responses(store, *location),
+            _ => &pipe.get_ref()[..],
+        };
+        let fuel = match (fuel_before, store.fuel_consumed()) {
+            (Some(before), Some(after)) => {
+                let fuel = after.saturating_sub(before);
+                self.endpoints
+                    .name()
+                    .notify(|observer, name| observer.on_fuel_consumed(name, fuel));
+                fuel
+            }
+            _ => 0,
+        };
+        if let Some((registry, tenant)) = account {
+            registry.record(
+                &tenant,
+                ResourceUsage {
+                    invocations: 1,
+                    fuel,
+                    cpu_time,
+                    bridge_bytes: (bytes_in + output.len()) as u64,
+                },
+            );
+        }
+        let location = match result {
+            Ok(location) => location,
+            Err(err) => {
+                self.endpoints
+                    .name()
+                    .notify(|observer, name| observer.on_trap(name, &err));
+                return Err(HostError::WasmInvokeFailed(match find_panic(output) {
+                    Some(description) => err.context(description),
+                    None => err,
+                }));
+            }
+        };
+        let deserialize = self.endpoints.name().phase(InvokePhase::Deserialize);
+        let rv = read_responses(output, expected, rejected, self.endpoints.name());
+        drop(deserialize);
+        let mut input = mem::take(self.pipe_in.write().unwrap().get_mut());
+        input.clear();
+        *self.input_buf.lock().unwrap() = input;
+        if let (Some(shared), Some(location)) = (shared, location) {
+            shared
+                .free(store, location)
+                .map_err(HostError::WasmInvokeFailed)?;
+        }
+        rv
+    }
+
+    /// Runs the plugin as a command that filters a byte stream.
+    ///
+    /// The default export (`_start`) is invoked with the input as stdin and
+    /// the output as stdout, the bridge is not involved.  The plugin pulls
+    /// input as it reads and output is written through as the plugin writes,
+    /// so neither side is buffered in full.  Returns the exit code of the
+    /// plugin.
+    pub fn pipe<R, W>(&self, input: R, output: W) -> Result<i32, HostError>
+    where
+        R: Read + Send + Sync + 'static,
+        W: Write + Send + Sync + 'static,
+    {
+        let mut instance = self.instance.lock().unwrap();
+        let Instance { store, linker, .. } = &mut *instance;
+        store.data_mut().set_stdin(Box::new(ReadPipe::new(input)));
+        store
+            .data_mut()
+            .set_stdout(Box::new(WritePipe::new(output)));
+        if store.fuel_consumed().is_some() {
+            refuel(store, fuel_budget(&self.quota_account.read().unwrap()))?;
+        }
+        let result = linker
+            .get_default(&mut *store, "plugin")
+            .and_then(|func| func.typed::<(), ()>(&*store))
+            .and_then(|func| func.call(&mut *store, ()));
+        // back to the stdio the plugin was linked with, which keeps stdout
+        // redirected for `serve_process`
+        store.data_mut().set_stdin(Box::new(stdio::stdin()));
+        store.data_mut().set_stdout(self.stdout());
+        match result {
+            Ok(()) => Ok(0),
+            Err(err) => match err.downcast_ref::<I32Exit>() {
+                Some(exit) => Ok(exit.0),
+                None => {
+                    self.endpoints
+                        .name()
+                        .notify(|observer, name| observer.on_trap(name, &err));
+                    Err(HostError::WasmInvokeFailed(err))
+                }
+            },
+        }
+    }
+
+    /// Fires the timers of the plugin that are due.
+    ///
+    /// The guest only runs `setTimeout` and `setInterval` callbacks when it is
+    /// ticked.  The time is measured from the creation of the plugin.  Returns
+    /// how long to wait until the next tick or `None` if no timer is pending.
+    pub fn tick(&self) -> Result<Option<Duration>, HostError> {
+        let now = self.created.elapsed().as_millis() as u64;
+        let req = Request::new(TICK_ENDPOINT, Value::Map(vec![("now".into(), now.into())]));
+        let payload = self
+            .send_request(req)?
+            .into_payload()
+            .map_err(HostError::ProtocolError)?;
+        let next_deadline = match payload {
+            Value::Map(items) => items.into_iter().find_map(|(key, value)| {
+                match (key.as_text(), value.as_integer()) {
+                    (Some("next_deadline"), Some(deadline)) => u64::try_from(deadline).ok(),
+                    _ => None,
+                }
+            }),
+            _ => None,
+        };
+        Ok(next_deadline.map(|deadline| Duration::from_millis(deadline.saturating_sub(now))))
+    }
+
+    /// Collects garbage in the runtime of the plugin.
+    ///
+    /// Call this between invocations of long lived plugins to keep their
+    /// memory bounded.
+    pub fn collect_garbage(&self) -> Result<GcStats, HostError> {
+        self.send_gc(Vec::new())
+    }
+
+    /// Sets the watermark above which the plugin collects garbage on its own.
+    ///
+    /// The guest checks the watermark every 16 requests it processed as
+    /// computing its memory usage is not free.  This also collects garbage
+    /// right away.
+    pub fn set_gc_watermark(&self, watermark: Option<u64>) -> Result<GcStats, HostError> {
+        let watermark = watermark.map_or(Value::Null, Value::from);
+        self.send_gc(vec![("watermark".into(), watermark)])
+    }
+
+    fn send_gc(&self, payload: Vec<(Value, Value)>) -> Result<GcStats, HostError> {
+        let payload = self
+            .send_request(Request::new(GC_ENDPOINT, Value::Map(payload)))?
+            .into_payload()
+            .map_err(HostError::ProtocolError)?;
+        let mut stats = GcStats {
+            before: 0,
+            after: 0,
+        };
+        if let Value::Map(items) = payload {
+            for (key, value) in items {
+                let value = value.as_integer().and_then(|x| u64::try_from(x).ok());
+                match (key.as_text(), value) {
+                    (Some("before"), Some(value)) => stats.before = value,
+                    (Some("after"), Some(value)) => stats.after = value,
+                    _ => {}
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Returns the topics the plugin subscribed to with `worthless.subscribe`.
+    pub fn subscriptions(&self) -> Vec<String> {
+        self.endpoints.topics()
+    }
+
+    /// Publishes an event to the listeners of a topic in the plugin.
+    ///
+    /// The plugin is only invoked if it subscribed to the topic, so events
+    /// such as [`CONFIG_TOPIC`](worthless_bridge::CONFIG_TOPIC) can be
+    /// published unconditionally.  Returns the number of listeners the event
+    /// was delivered to.
+    pub fn publish<V: Into<Value>>(&self, topic: &str, payload: V) -> Result<usize, HostError> {
+        if !self.endpoints.is_subscribed(topic) {
+            return Ok(0);
+        }
+        let req = Request::new(
+            PUBLISH_ENDPOINT,
+            Value::Map(vec![
+                ("topic".into(), topic.into()),
+                ("payload".into(), payload.into()),
+            ]),
+        );
+        let payload = self
+            .send_request(req)?
+            .into_payload()
+            .map_err(HostError::ProtocolError)?;
+        let delivered = match payload {
+            Value::Map(items) => items.into_iter().find_map(|(key, value)| {
+                match (key.as_text(), value.as_integer()) {
+                    (Some("delivered"), Some(delivered)) => usize::try_from(delivered).ok(),
+                    _ => None,
+                }
+            }),
+            _ => None,
+        };
+        Ok(delivered.unwrap_or(0))
+    }
+
+    /// Tells the plugin that it's about to be shut down.
+    ///
+    /// This publishes an event on [`SHUTDOWN_TOPIC`] so the plugin can flush
+    /// what it buffered.  Returns the number of listeners the event was
+    /// delivered to.
+    pub fn shutdown(&self) -> Result<usize, HostError> {
+        self.publish(SHUTDOWN_TOPIC, Value::Null)
+    }
+
+    /// Lists the endpoints the plugin registered handlers for.
+    ///
+    /// Handlers are registered when the plugin initializes, so this runs the
+    /// plugin if it was not invoked yet.
+    pub fn list_endpoints(&self) -> Result<Vec<String>, HostError> {
+        let payload = self
+            .send_request(Request::new(ENDPOINTS_ENDPOINT, Value::Null))?
+            .into_payload()
+            .map_err(HostError::ProtocolError)?;
+        let endpoints = match payload {
+            Value::Map(items) => {
+                items
+                    .into_iter()
+                    .find_map(|(key, value)| match (key.as_text(), value) {
+                        (Some("endpoints"), Value::Array(endpoints)) => Some(endpoints),
+                        _ => None,
+                    })
+            }
+            _ => None,
+        };
+        Ok(endpoints
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|endpoint| endpoint.into_text().ok())
+            .collect())
+    }
+
+    /// Pushes a chunk of bytes into a stream of the plugin.
+    ///
+    /// The plugin reads the stream with `worthless.stream(id)`.  Chunks pushed
+    /// before the plugin opened the stream are buffered until the
+    /// `desired_size` drops to zero, pushing more then fails.
+    pub fn push_stream_chunk(&self, stream: u64, chunk: &[u8]) -> Result<StreamStatus, HostError> {
+        self.send_stream(vec![
+            ("stream".into(), stream.into()),
+            ("chunk".into(), chunk.into()),
+        ])
+    }
+
+    /// Closes a stream of the plugin, failing it if an error is given.
+    pub fn close_stream(
+        &self,
+        stream: u64,
+        error: Option<&str>,
+    ) -> Result<StreamStatus, HostError> {
+        let end = match error {
+            Some(error) => ("error".into(), error.into()),
+            None => ("done".into(), true.into()),
+        };
+        self.send_stream(vec![("stream".into(), stream.into()), end])
+    }
+
+    /// Starts sampling where the JavaScript code of the plugin spends time.
+    ///
+    /// A stack is recorded whenever at least the interval passed since the
+    /// last one.  Starting the profiler again discards the samples.
+    pub fn start_profiling(&self, interval: Duration) -> Result<(), HostError> {
+        let interval = u64::try_from(interval.as_micros()).unwrap_or(u64::MAX);
+        self.send_request(Request::new(
+            PROFILE_ENDPOINT,
+            Value::Map(vec![
+                ("action".into(), "start".into()),
+                ("interval".into(), interval.into()),
+            ]),
+        ))?
+        .into_payload()
+        .map_err(HostError::ProtocolError)?;
+        Ok(())
+    }
+
+    /// Stops the profiler of the plugin and exports the profile.
+    ///
+    /// The profile can be written to a file and viewed in the tools of the
+    /// format.
+    pub fn stop_profiling(&self, format: ProfileFormat) -> Result<String, HostError> {
+        let payload = self
+            .send_request(Request::new(
+                PROFILE_ENDPOINT,
+                Value::Map(vec![
+                    ("action".into(), "stop".into()),
+                    ("format".into(), format.name().into()),
+                ]),
+            ))?
+            .into_payload()
+            .map_err(HostError::ProtocolError)?;
+        let profile = match payload {
+            Value::Map(items) => {
+                items
+                    .into_iter()
+                    .find_map(|(key, value)| match (key.as_text(), value) {
+                        (Some("profile"), Value::Text(profile)) => Some(profile),
+                        _ => None,
+                    })
+            }
+            _ => None,
+        };
+        Ok(profile.unwrap_or_default())
+    }
+
+    /// Attaches a debugger to the JavaScript runtime of the plugin.
+    ///
+    /// Like [`set_env_config`](Self::set_env_config) this only has an effect
+    /// on plugins that were not invoked yet, code that ran before cannot be
+    /// debugged.  The debugger pauses once a session was started with
+    /// [`debug_session`](Self::debug_session).
+    pub fn enable_debugging(&self) -> Result<(), HostError> {
+        self.instance
+            .lock()
+            .unwrap()
+            .set_env(DEBUG_ENV_VAR, Some("1"))
+    }
+
+    /// Records the coverage of the JavaScript code of the plugin.
+    ///
+    /// Like [`enable_debugging`](Self::enable_debugging) this only covers
+    /// code evaluated after the plugin was first invoked.  The coverage is
+    /// collected with a request to
+    /// [`COVERAGE_ENDPOINT`](worthless_bridge::COVERAGE_ENDPOINT).
+    pub fn enable_coverage(&self) -> Result<(), HostError> {
+        self.instance
+            .lock()
+            .unwrap()
+            .set_env(COVERAGE_ENV_VAR, Some("1"))
+    }
+
+    /// Starts a debugging session that the debugger of the plugin pauses
+    /// into.
+    ///
+    /// The plugin stays paused on the invoking thread until the session
+    /// resumes it, so the session is driven from another thread.  The plugin
+    /// cannot be invoked while paused, breakpoints are changed with
+    /// [`DebugCommand`](crate::DebugCommand)s then.  A new session replaces
+    /// the previous one.
+    pub fn debug_session(&self) -> DebugSession {
+        let session = DebugSession::new();
+        self.register_endpoint(DEBUG_PAUSED_ENDPOINT, session.endpoint());
+        session
+    }
+
+    /// Sets a breakpoint on a line of a file of the plugin.
+    ///
+    /// Breakpoints on lines without a statement move to the next statement.
+    /// Returns the line the breakpoint moved to if the file was loaded.
+    pub fn set_breakpoint(&self, file: &str, line: u32) -> Result<Option<u32>, HostError> {
+        let payload = self.send_debug("set_breakpoint", Some((file, line)))?;
+        Ok(payload_field(&payload, "line")
+            .and_then(|x| x.as_integer())
+            .and_then(|x| u32::try_from(x).ok()))
+    }
+
+    /// Removes a breakpoint, returns `false` if there was none.
+    pub fn remove_breakpoint(&self, file: &str, line: u32) -> Result<bool, HostError> {
+        let payload = self.send_debug("remove_breakpoint", Some((file, line)))?;
+        Ok(payload_field(&payload, "removed")
+            .and_then(|x| x.as_bool())
+            .unwrap_or(false))
+    }
+
+    /// Enables or disables pausing when an exception is thrown out of a
+    /// function.
+    pub fn set_pause_on_exceptions(&self, yes: bool) -> Result<(), HostError> {
+        self.send_request(Request::new(
+            DEBUG_ENDPOINT,
+            Value::Map(vec![
+                ("action".into(), "pause_on_exceptions".into()),
+                ("enabled".into(), yes.into()),
+            ]),
+        ))?
+        .into_payload()
+        .map_err(HostError::ProtocolError)?;
+        Ok(())
+    }
+
+    fn send_debug(&self, action: &str, location: Option<(&str, u32)>) -> Result<Value, HostError> {
+        let mut payload = vec![("action".into(), action.into())];
+        if let Some((file, line)) = location {
+            payload.push(("file".into(), file.into()));
+            payload.push(("line".into(), line.into()));
+        }
+        self.send_request(Request::new(DEBUG_ENDPOINT, Value::Map(payload)))?
+            .into_payload()
+            .map_err(HostError::ProtocolError)
+    }
+
+    fn send_stream(&self, payload: Vec<(Value, Value)>) -> Result<StreamStatus, HostError> {
+        let payload = self
+            .send_request(Request::new(STREAM_ENDPOINT, Value::Map(payload)))?
+            .into_payload()
+            .map_err(HostError::ProtocolError)?;
+        let mut status = StreamStatus {
+            desired_size: 0,
+            cancelled: false,
+        };
+        if let Value::Map(items) = payload {
+            for (key, value) in items {
+                match key.as_text() {
+                    Some("desired_size") => {
+                        status.desired_size = value
+                            .as_integer()
+                            .and_then(|x| i64::try_from(x).ok())
+                            .unwrap_or(0)
+                    }
+                    Some("cancelled") => status.cancelled = value.as_bool().unwrap_or(false),
+                    _ => {}
+                }
+            }
+        }
+        Ok(status)
     }
 
     /*
@@ -124,3 +1206,471 @@ impl Plugin {
         Ok(())
     }*/
 }
+
+#[cfg(feature = "metrics")]
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        crate::metrics::instances(-1);
+    }
+}
+
+/// Links WASI, the policy and the host calls and instantiates the module.
+fn instantiate(
+    store: &mut Store<WasiCtx>,
+    module: &Module,
+    endpoints: &Endpoints,
+    (host_pipe_in, host_pipe_out): (&Pipe, &Pipe),
+    wasi_policy: &Arc<RwLock<WasiPolicy>>,
+) -> Result<(Linker<WasiCtx>, BTreeSet<String>), HostError> {
+    let mut linker = Linker::new(store.engine());
+    wasmtime_wasi::add_to_linker(&mut linker, |s| s).map_err(HostError::WasmModuleLinkingFailed)?;
+    let wasi_shims = policy::install(
+        &mut linker,
+        store,
+        module,
+        wasi_policy.clone(),
+        endpoints.name().clone(),
+    )?;
+    linker
+        .func_wrap("worthless", "host_call", {
+            let endpoints = endpoints.clone();
+            let host_pipe_in = host_pipe_in.clone();
+            let host_pipe_out = host_pipe_out.clone();
+            move || -> anyhow::Result<()> {
+                // bridge errors are not thread safe so they are converted
+                // into a message for the trap.
+                endpoints
+                    .handle_pipe(&host_pipe_in, &host_pipe_out)
+                    .map_err(|err| anyhow::anyhow!("host call failed: {}", err))
+            }
+        })
+        .map_err(HostError::WasmModuleLinkingFailed)?;
+    // reactors run their initializer right away
+    if store.fuel_consumed().is_some() {
+        refuel(store, UNLIMITED_FUEL)?;
+    }
+    linker
+        .module(&mut *store, "plugin", module)
+        .map_err(HostError::WasmModuleLinkingFailed)?;
+    Ok((linker, wasi_shims))
+}
+
+/// Deserializes the responses of the plugin and puts the responses to
+/// rejected requests in between at their index.
+fn read_responses(
+    mut output: &[u8],
+    expected: usize,
+    rejected: Vec<(usize, Response)>,
+    name: &PluginName,
+) -> Result<Vec<Response>, HostError> {
+    let mut rejected = rejected.into_iter().peekable();
+    let total = expected + rejected.len();
+    let mut rv = Vec::with_capacity(total);
+    while rv.len() < total {
+        if let Some((_, response)) = rejected.next_if(|(index, _)| *index == rv.len()) {
+            rv.push(response);
+            continue;
+        }
+        let response = Response::deserialize_from(&mut output).map_err(HostError::ProtocolError)?;
+        name.notify(|observer, name| {
+            observer.on_bridge_message(name, Direction::ToHost, BridgeMessage::Response(&response))
+        });
+        rv.push(response);
+    }
+    Ok(rv)
+}
+
+/// Returns the size of a request on the wire.
+fn serialized_size(req: &Request) -> Result<usize, HostError> {
+    struct Counter(usize);
+
+    impl Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    req.serialize_to(&mut counter)
+        .map_err(HostError::ProtocolError)?;
+    Ok(counter.0)
+}
+
+/// Returns the response to a request above the size limit.
+fn request_too_large(req: &Request, size: usize) -> Response {
+    let mut builder = Response::builder();
+    if let Some(request_id) = req.request_id() {
+        builder.request_id(request_id);
+    }
+    builder
+        .error(Error::new(
+            ErrorKind::PayloadTooLarge,
+            format!(
+                "request to '{}' has {} bytes, which is above the limit",
+                req.endpoint(),
+                size
+            ),
+        ))
+        .build()
+}
+
+/// Returns the fuel an invocation gets, limited by the quota of the tenant.
+fn fuel_budget(account: &Option<(Arc<QuotaRegistry>, String)>) -> u64 {
+    account
+        .as_ref()
+        .and_then(|(registry, tenant)| registry.remaining_fuel(tenant))
+        .unwrap_or(UNLIMITED_FUEL)
+}
+
+/// Sets the fuel left in the store to exactly `fuel`.
+fn refuel(store: &mut Store<WasiCtx>, fuel: u64) -> Result<(), HostError> {
+    let remaining = store.consume_fuel(0).map_err(HostError::WasmInvokeFailed)?;
+    if fuel > remaining {
+        store.add_fuel(fuel - remaining)
+    } else {
+        store.consume_fuel(remaining - fuel).map(|_| ())
+    }
+    .map_err(HostError::WasmInvokeFailed)
+}
+
+/// Looks for the response a panicking guest sends before it aborts.
+///
+/// Returns the description of the panic.
+fn find_panic(mut output: &[u8]) -> Option<String> {
+    while let Ok(response) = Response::deserialize_from(&mut output) {
+        if response.meta().get(PANIC_META_KEY) == Some(&Value::Bool(true)) {
+            return response
+                .error_ref()
+                .map(|err| err.description().to_string());
+        }
+    }
+    None
+}
+
+/// Returns a field of a map payload.
+fn payload_field<'a>(payload: &'a Value, key: &str) -> Option<&'a Value> {
+    match payload {
+        Value::Map(items) => items
+            .iter()
+            .find(|(k, _)| k.as_text() == Some(key))
+            .map(|(_, value)| value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::HostConfig;
+
+    /// Burns some fuel in a loop.
+    const SPIN: &str = r#"
+        (local i32)
+        (loop
+            (local.set 0 (i32.add (local.get 0) (i32.const 1)))
+            (br_if 0 (i32.lt_u (local.get 0) (i32.const 1000))))
+    "#;
+
+    fn metered_engine() -> Engine {
+        HostConfig::new().consume_fuel(true).build_engine().unwrap()
+    }
+
+    #[test]
+    fn test_fuel_for_initialize() {
+        let module = format!(r#"(module (func (export "_initialize") {}))"#, SPIN);
+        let module = wat::parse_str(module).unwrap();
+        Plugin::from_bytes(&metered_engine(), &module).unwrap();
+    }
+
+    #[test]
+    fn test_fuel_for_pipe() {
+        let module = format!(r#"(module (func (export "_start") {}))"#, SPIN);
+        let module = wat::parse_str(module).unwrap();
+        let plugin = Plugin::from_bytes(&metered_engine(), &module).unwrap();
+        assert_eq!(plugin.pipe(io::empty(), io::sink()).unwrap(), 0);
+    }
+
+    /// Collects what is written to it.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pipe() {
+        let module = wat::parse_str(
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_read"
+                    (func $read (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $write (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 128) "\n")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 64))
+                    (loop
+                        (i32.store (i32.const 4) (i32.const 64))
+                        (drop (call $read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 16)))
+                        (if (i32.load (i32.const 16))
+                            (then
+                                (i32.store (i32.const 4) (i32.load (i32.const 16)))
+                                (drop (call $write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 20)))
+                                (br 1))))
+                    (call $exit (i32.const 3)))
+                (func (export "newline")
+                    (i32.store (i32.const 8) (i32.const 128))
+                    (i32.store (i32.const 12) (i32.const 1))
+                    (drop (call $write (i32.const 1) (i32.const 8) (i32.const 1) (i32.const 20)))))"#,
+        )
+        .unwrap();
+        let plugin = Plugin::from_bytes(&Engine::default(), &module).unwrap();
+        plugin.redirect_stdout_to_stderr();
+
+        let output = Output::default();
+        let input = "hello world ".repeat(20);
+        assert_eq!(
+            plugin
+                .pipe(Cursor::new(input.clone()), output.clone())
+                .unwrap(),
+            3
+        );
+        assert_eq!(*output.0.lock().unwrap(), input.as_bytes());
+
+        // stdout goes back to stderr and no longer to the output
+        let mut instance = plugin.instance.lock().unwrap();
+        let Instance { store, linker, .. } = &mut *instance;
+        let newline = linker.get(&mut *store, "plugin", "newline").unwrap();
+        let newline = newline
+            .into_func()
+            .unwrap()
+            .typed::<(), ()>(&*store)
+            .unwrap();
+        newline.call(&mut *store, ()).unwrap();
+        assert_eq!(output.0.lock().unwrap().len(), input.len());
+    }
+
+    #[test]
+    fn test_max_request_size() {
+        let module = wat::parse_str(
+            r#"(module (func (export "worthless_handle_request")) (memory (export "memory") 1))"#,
+        )
+        .unwrap();
+        let plugin = Plugin::from_bytes(&Engine::default(), &module).unwrap();
+        plugin.set_max_request_size(Some(256)).unwrap();
+        let mut too_large = Request::build("upload".into());
+        too_large.request_id("big").raw_payload("x".repeat(1024));
+        let mut ignored = Request::build("upload".into());
+        ignored.fire_and_forget(true).raw_payload("x".repeat(1024));
+        let responses = plugin
+            .send_requests([too_large.build(), ignored.build()])
+            .unwrap();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].request_id(), Some("big"));
+        let err = responses[0].error_ref().unwrap();
+        assert_eq!(err.kind(), ErrorKind::PayloadTooLarge);
+    }
+
+    #[test]
+    fn test_max_request_size_changed() {
+        // reports the number and size of the environment variables
+        let module = wat::parse_str(
+            r#"(module
+                (import "wasi_snapshot_preview1" "environ_sizes_get"
+                    (func $sizes (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "worthless_handle_request"))
+                (func (export "env_sizes") (result i64)
+                    (drop (call $sizes (i32.const 0) (i32.const 4)))
+                    (i64.or
+                        (i64.shl (i64.load32_u (i32.const 0)) (i64.const 32))
+                        (i64.load32_u (i32.const 4)))))"#,
+        )
+        .unwrap();
+        let plugin = Plugin::from_bytes(&Engine::default(), &module).unwrap();
+        let env_sizes = || {
+            let mut instance = plugin.instance.lock().unwrap();
+            let Instance { store, linker, .. } = &mut *instance;
+            let func = linker.get(&mut *store, "plugin", "env_sizes").unwrap();
+            let func = func.into_func().unwrap().typed::<(), i64>(&*store).unwrap();
+            let sizes = func.call(&mut *store, ()).unwrap();
+            ((sizes >> 32) as u32, sizes as u32)
+        };
+        let var = |limit: &str| format!("{}={}", MAX_REQUEST_SIZE_ENV_VAR, limit).len() as u32 + 1;
+        let send = |size: usize| {
+            let mut req = Request::build("upload".into());
+            req.raw_payload("x".repeat(size));
+            plugin.send_requests([req.build()]).unwrap()
+        };
+
+        plugin.set_max_request_size(Some(256)).unwrap();
+        plugin.set_max_request_size(Some(4096)).unwrap();
+        assert_eq!(env_sizes(), (1, var("4096")));
+        // the plugin never responds, so only rejected requests come back
+        assert!(send(1024).is_empty());
+        let responses = send(8192);
+        assert_eq!(
+            responses[0].error_ref().unwrap().kind(),
+            ErrorKind::PayloadTooLarge
+        );
+
+        plugin.set_max_request_size(None).unwrap();
+        assert_eq!(env_sizes(), (0, 0));
+    }
+
+    #[test]
+    fn test_request_buffer_reused() {
+        let module = wat::parse_str(
+            r#"(module (func (export "worthless_handle_request")) (memory (export "memory") 1))"#,
+        )
+        .unwrap();
+        let plugin = Plugin::from_bytes(&Engine::default(), &module).unwrap();
+        let mut req = Request::build("upload".into());
+        req.fire_and_forget(true).raw_payload("x".repeat(1024));
+        let req = req.build();
+        plugin.send_requests([req.clone()]).unwrap();
+        let ptr = {
+            let input = plugin.input_buf.lock().unwrap();
+            assert!(input.is_empty() && input.capacity() > 1024);
+            input.as_ptr()
+        };
+        plugin.send_requests([req]).unwrap();
+        assert_eq!(plugin.input_buf.lock().unwrap().as_ptr(), ptr);
+    }
+
+    #[test]
+    fn test_env_config() {
+        let module = wat::parse_str(
+            r#"(module (func (export "worthless_handle_request")) (memory (export "memory") 1))"#,
+        )
+        .unwrap();
+        let plugin = Plugin::from_bytes(&Engine::default(), &module).unwrap();
+        plugin.set_env_config("tenant_id", "a").unwrap();
+        plugin.set_env_config("mode", "test").unwrap();
+        plugin.set_env_config("tenant_id", "b").unwrap();
+        assert_eq!(
+            plugin.instance.lock().unwrap().env,
+            [
+                ("WORTHLESS_CONFIG_TENANT_ID".to_string(), "b".to_string()),
+                ("WORTHLESS_CONFIG_MODE".to_string(), "test".to_string()),
+            ]
+        );
+
+        // the guest would see `tenantid`
+        let err = plugin.set_env_config("tenantId", "c").unwrap_err();
+        assert!(matches!(err, HostError::InvalidConfig(_)));
+        assert!(plugin.set_env_config("", "c").is_err());
+    }
+
+    /// Returns a plugin whose shared memory exports answer with `responses`
+    /// and count how often a buffer was freed.
+    fn shared_memory_plugin(responses: &[u8]) -> Plugin {
+        let data: String = responses.iter().map(|x| format!("\\{:02x}", x)).collect();
+        let module = format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (global $next (mut i32) (i32.const 4096))
+                (global (export "freed") (mut i32) (i32.const 0))
+                (data (i32.const 1024) "{data}")
+                (func (export "worthless_handle_request"))
+                (func (export "worthless_alloc") (param i32) (result i32)
+                    (global.get $next)
+                    (global.set $next (i32.add (global.get $next) (local.get 0))))
+                (func (export "worthless_free") (param i32 i32)
+                    (global.set 1 (i32.add (global.get 1) (i32.const 1))))
+                (func (export "worthless_handle_shared") (param i32 i32) (result i64)
+                    (i64.const {location})))"#,
+            data = data,
+            location = (1024u64 << 32) | responses.len() as u64,
+        );
+        let plugin =
+            Plugin::from_bytes(&Engine::default(), &wat::parse_str(module).unwrap()).unwrap();
+        plugin.set_shared_memory_threshold(Some(0));
+        plugin
+    }
+
+    fn freed(plugin: &Plugin) -> i32 {
+        let mut instance = plugin.instance.lock().unwrap();
+        let Instance { store, linker, .. } = &mut *instance;
+        let freed = linker.get(&mut *store, "plugin", "freed").unwrap();
+        freed.into_global().unwrap().get(&mut *store).unwrap_i32()
+    }
+
+    #[test]
+    fn test_shared_memory() {
+        let response = Response::builder().raw_payload(42).build();
+        let plugin = shared_memory_plugin(&response.serialize().unwrap());
+        let response = plugin
+            .send_request(Request::new("echo", "x".repeat(1024)))
+            .unwrap();
+        assert_eq!(response.into_payload().unwrap(), Value::from(42));
+        assert_eq!(freed(&plugin), 1);
+
+        // the responses are freed even if they cannot be read
+        let plugin = shared_memory_plugin(b"\xff\xff\xff\xff");
+        assert!(plugin.send_request(Request::new("echo", 1)).is_err());
+        assert_eq!(freed(&plugin), 1);
+    }
+
+    #[test]
+    fn test_find_panic() {
+        let mut output = Vec::new();
+        Response::builder()
+            .raw_payload(1)
+            .build()
+            .serialize_to(&mut output)
+            .unwrap();
+        Response::builder()
+            .error(Error::new(ErrorKind::InternalError, "panicked at 'oops'"))
+            .meta(PANIC_META_KEY, true)
+            .build()
+            .serialize_to(&mut output)
+            .unwrap();
+        assert_eq!(find_panic(&output).as_deref(), Some("panicked at 'oops'"));
+
+        // a response cut off by the trap ends the search
+        assert_eq!(find_panic(&output[..output.len() - 1]), None);
+        assert_eq!(find_panic(b""), None);
+    }
+
+    #[test]
+    fn test_topics() {
+        let response = Response::builder()
+            .raw_payload(Value::Map(vec![("delivered".into(), 1.into())]))
+            .build();
+        let plugin = shared_memory_plugin(&response.serialize().unwrap());
+
+        // nothing is sent unless the plugin subscribed
+        plugin.set_env_config("debug", "1").unwrap();
+        assert_eq!(plugin.shutdown().unwrap(), 0);
+        assert_eq!(freed(&plugin), 0);
+
+        plugin
+            .endpoints
+            .set_topics(&[CONFIG_TOPIC.into(), SHUTDOWN_TOPIC.into()]);
+        plugin.set_env_config("debug", "0").unwrap();
+        assert_eq!(freed(&plugin), 1);
+        assert_eq!(plugin.shutdown().unwrap(), 1);
+
+        // snapshots carry the subscriptions of their memory
+        let snapshot = plugin.snapshot().unwrap();
+        plugin.endpoints.clear_topics();
+        plugin.restore(&snapshot).unwrap();
+        assert!(plugin.endpoints.is_subscribed(SHUTDOWN_TOPIC));
+    }
+}
@@ -0,0 +1,374 @@
+//! Restricts what plugins can do through WASI beyond what preopens express.
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex, RwLock};
+
+use cap_rand::rngs::StdRng;
+use cap_rand::{RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
+use wasmtime::{Caller, Extern, ExternType, Linker, Module, Store, Val};
+
+use crate::error::HostError;
+use crate::observer::PluginName;
+
+/// The module plugins import the WASI functions from.
+const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+/// Reported for descriptors the plugin may not use, as if they were closed.
+const ERRNO_BADF: i32 = 8;
+
+/// Reported for functions the plugin may not call.
+const ERRNO_NOTCAPABLE: i32 = 76;
+
+/// The size of a subscription passed to `poll_oneoff`.
+const SUBSCRIPTION_SIZE: usize = 48;
+
+/// The size of an event returned by `poll_oneoff`.
+const EVENT_SIZE: usize = 32;
+
+/// The type of subscriptions and events for clocks.
+const EVENTTYPE_CLOCK: u8 = 0;
+
+/// The descriptors of stdio and the bridge pipes which are always allowed.
+const BRIDGE_FDS: [u32; 7] = [0, 1, 2, 4, 5, 6, 7];
+
+/// What happens when a plugin uses a WASI capability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum WasiRule {
+    /// The call goes through to the WASI context of the plugin.
+    #[default]
+    Allow,
+    /// The call quietly gets a fixed answer.
+    ///
+    /// Clocks stand at zero, random numbers come from a fixed seed and
+    /// descriptors look like they are closed.
+    Stub,
+    /// The call fails and is reported to
+    /// [`InvokeObserver::on_wasi_denied`](crate::InvokeObserver::on_wasi_denied).
+    Deny,
+}
+
+/// Decides which WASI calls of a plugin go through.
+///
+/// By default everything is allowed.  Stdio and the bridge pipes are never
+/// restricted, so the plugin keeps working with any policy.  Install it with
+/// [`Plugin::set_wasi_policy`](crate::Plugin::set_wasi_policy).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WasiPolicy {
+    clocks: WasiRule,
+    random: WasiRule,
+    files: WasiRule,
+    denied: BTreeSet<String>,
+}
+
+impl WasiPolicy {
+    /// Creates a policy that allows everything.
+    pub fn new() -> WasiPolicy {
+        WasiPolicy::default()
+    }
+
+    /// Sets the rule for reading the realtime and monotonic clocks.
+    ///
+    /// This covers waiting for a clock with `poll_oneoff`, which is what
+    /// sleeping comes down to.  Stubbed clocks stand still, so such waits are
+    /// over at once.
+    ///
+    /// To let the plugin see time pass under the control of the host use a
+    /// [`VirtualClock`](crate::VirtualClock) instead.
+    pub fn clocks(&mut self, rule: WasiRule) -> &mut WasiPolicy {
+        self.clocks = rule;
+        self
+    }
+
+    /// Sets the rule for drawing random numbers.
+    pub fn random(&mut self, rule: WasiRule) -> &mut WasiPolicy {
+        self.random = rule;
+        self
+    }
+
+    /// Sets the rule for operations on descriptors other than stdio and the
+    /// bridge pipes, such as preopened directories and sockets.
+    pub fn files(&mut self, rule: WasiRule) -> &mut WasiPolicy {
+        self.files = rule;
+        self
+    }
+
+    /// Denies a WASI function by its name, eg: `poll_oneoff`.
+    ///
+    /// Denied functions fail with `ENOTCAPABLE`, functions without a result
+    /// such as `proc_exit` trap instead.
+    pub fn deny(&mut self, function: &str) -> &mut WasiPolicy {
+        self.denied.insert(function.to_string());
+        self
+    }
+
+    /// Checks if calls to a WASI function have to be checked against the
+    /// policy.
+    pub(crate) fn restricts(&self, function: &str) -> bool {
+        if self.denied.contains(function) {
+            return true;
+        }
+        match function {
+            "clock_time_get" | "clock_res_get" | "poll_oneoff" => self.clocks != WasiRule::Allow,
+            "random_get" => self.random != WasiRule::Allow,
+            _ if is_fd_function(function) => self.files != WasiRule::Allow,
+            _ => false,
+        }
+    }
+
+    /// Returns the rule for a call and the descriptor it operates on.
+    fn rule(&self, function: &str, params: &[Val]) -> (WasiRule, Option<u32>) {
+        if self.denied.contains(function) {
+            return (WasiRule::Deny, None);
+        }
+        match function {
+            "clock_time_get" | "clock_res_get" => (self.clocks, None),
+            // polls that do not wait for a clock are let through on the call
+            "poll_oneoff" => (self.clocks, None),
+            "random_get" => (self.random, None),
+            _ if is_fd_function(function) => {
+                let fd = params.first().and_then(Val::i32).map(|fd| fd as u32);
+                match fd {
+                    Some(fd) if !BRIDGE_FDS.contains(&fd) => {
+                        // libc probes for preopens when the plugin starts
+                        // which is not worth reporting
+                        if function == "fd_prestat_get" && self.files == WasiRule::Deny {
+                            (WasiRule::Stub, Some(fd))
+                        } else {
+                            (self.files, Some(fd))
+                        }
+                    }
+                    _ => (WasiRule::Allow, fd),
+                }
+            }
+            _ => (WasiRule::Allow, None),
+        }
+    }
+}
+
+/// Checks if the first parameter of a WASI function is a descriptor.
+fn is_fd_function(function: &str) -> bool {
+    ["fd_", "path_", "sock_"]
+        .iter()
+        .any(|prefix| function.starts_with(prefix))
+}
+
+/// Returns the WASI functions a module imports that a policy restricts.
+pub(crate) fn restricted_imports(policy: &WasiPolicy, module: &Module) -> BTreeSet<String> {
+    module
+        .imports()
+        .filter(|import| import.module() == WASI_MODULE)
+        .filter(|import| matches!(import.ty(), ExternType::Func(_)))
+        .filter(|import| policy.restricts(import.name()))
+        .map(|import| import.name().to_string())
+        .collect()
+}
+
+/// Shadows the WASI functions a module imports with ones enforcing a policy.
+///
+/// Only the functions the policy restricts are shadowed, the others call
+/// into WASI directly.  The shadowed ones consult the policy on every call,
+/// so it can be changed at any time as long as it does not restrict other
+/// functions.  Returns the functions that were shadowed.  Must be called
+/// after WASI was added to the linker and before the module is linked.
+pub(crate) fn install<T: 'static>(
+    linker: &mut Linker<T>,
+    store: &mut Store<T>,
+    module: &Module,
+    policy: Arc<RwLock<WasiPolicy>>,
+    name: PluginName,
+) -> Result<BTreeSet<String>, HostError> {
+    let restricted = restricted_imports(&policy.read().unwrap(), module);
+    if restricted.is_empty() {
+        return Ok(restricted);
+    }
+    linker.allow_shadowing(true);
+    for import in module.imports() {
+        let ty = match (import.module(), import.ty()) {
+            (WASI_MODULE, ExternType::Func(ty)) if restricted.contains(import.name()) => ty,
+            _ => continue,
+        };
+        let name = import.name();
+        let function = name.to_string();
+        let original = match linker
+            .get(&mut *store, WASI_MODULE, name)
+            .and_then(Extern::into_func)
+        {
+            Some(original) => original,
+            None => continue,
+        };
+        let policy = policy.clone();
+        let name = name.clone();
+        let rng = Mutex::new(StdRng::seed_from_u64(0));
+        linker
+            .func_new(WASI_MODULE, name, ty, move |mut caller, params, results| {
+                let (rule, fd) = policy.read().unwrap().rule(&function, params);
+                let mut clocks = Vec::new();
+                if function == "poll_oneoff"
+                    && rule != WasiRule::Allow
+                    && !policy.read().unwrap().denied.contains(&function)
+                {
+                    clocks = poll_clocks(&mut caller, params)?;
+                    if clocks.is_empty() {
+                        return original.call(&mut caller, params, results);
+                    }
+                }
+                match rule {
+                    WasiRule::Allow => {
+                        return original.call(&mut caller, params, results);
+                    }
+                    WasiRule::Deny => {
+                        name.notify(|observer, name| observer.on_wasi_denied(name, &function, fd));
+                    }
+                    WasiRule::Stub => {}
+                }
+                let errno = match (rule, fd, function.as_str()) {
+                    (_, Some(_), _) => ERRNO_BADF,
+                    (WasiRule::Stub, None, "clock_time_get") => {
+                        write_memory(&mut caller, &params[2], &0u64.to_le_bytes())?;
+                        0
+                    }
+                    (WasiRule::Stub, None, "clock_res_get") => {
+                        write_memory(&mut caller, &params[1], &1u64.to_le_bytes())?;
+                        0
+                    }
+                    (WasiRule::Stub, None, "poll_oneoff") => {
+                        // clocks stand still, so waiting for them is over at once
+                        let mut events = Vec::with_capacity(clocks.len() * EVENT_SIZE);
+                        for userdata in &clocks {
+                            let mut event = [0; EVENT_SIZE];
+                            event[..8].copy_from_slice(&userdata.to_le_bytes());
+                            events.extend_from_slice(&event);
+                        }
+                        write_memory(&mut caller, &params[1], &events)?;
+                        let count = clocks.len() as u32;
+                        write_memory(&mut caller, &params[3], &count.to_le_bytes())?;
+                        0
+                    }
+                    (WasiRule::Stub, None, "random_get") => {
+                        let len = params[1].i32().unwrap_or(0) as u32 as usize;
+                        let mut buf = vec![0; len];
+                        rng.lock().unwrap().fill_bytes(&mut buf);
+                        write_memory(&mut caller, &params[0], &buf)?;
+                        0
+                    }
+                    _ => ERRNO_NOTCAPABLE,
+                };
+                match results.first_mut() {
+                    Some(result) => *result = Val::I32(errno),
+                    None => {
+                        anyhow::bail!("plugin called denied WASI function {}", function);
+                    }
+                }
+                Ok(())
+            })
+            .map_err(HostError::WasmModuleLinkingFailed)?;
+    }
+    linker.allow_shadowing(false);
+    Ok(restricted)
+}
+
+/// Returns the user data of the subscriptions of a `poll_oneoff` call that
+/// wait for a clock.
+fn poll_clocks<T>(caller: &mut Caller<'_, T>, params: &[Val]) -> anyhow::Result<Vec<u64>> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| anyhow::anyhow!("plugin exports no memory"))?;
+    let offset = params[0].i32().unwrap_or(0) as u32 as usize;
+    let count = params[2].i32().unwrap_or(0) as u32 as usize;
+    let mut subscriptions = vec![0; count * SUBSCRIPTION_SIZE];
+    memory.read(caller, offset, &mut subscriptions)?;
+    Ok(subscriptions
+        .chunks(SUBSCRIPTION_SIZE)
+        .filter(|subscription| subscription[8] == EVENTTYPE_CLOCK)
+        .map(|subscription| u64::from_le_bytes(subscription[..8].try_into().unwrap()))
+        .collect())
+}
+
+/// Writes into the memory of the plugin at a pointer it passed.
+fn write_memory<T>(caller: &mut Caller<'_, T>, ptr: &Val, bytes: &[u8]) -> anyhow::Result<()> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| anyhow::anyhow!("plugin exports no memory"))?;
+    let offset = ptr.i32().unwrap_or(0) as u32 as usize;
+    memory.write(caller, offset, bytes)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io;
+    use std::time::{Duration, Instant};
+
+    use wasmtime::Engine;
+
+    use crate::Plugin;
+
+    /// Sleeps for an hour on a clock subscription with user data 7 and exits
+    /// with the errno or the user data of the event.
+    const SLEEP: &str = r#"
+        (module
+            (import "wasi_snapshot_preview1" "poll_oneoff"
+                (func $poll (param i32 i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+            (import "wasi_snapshot_preview1" "fd_write"
+                (func $write (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (func (export "_start")
+                (local $errno i32)
+                (i64.store (i32.const 0) (i64.const 7))
+                (i32.store (i32.const 16) (i32.const 1))
+                (i64.store (i32.const 24) (i64.const 3600000000000))
+                (local.set $errno
+                    (call $poll (i32.const 0) (i32.const 64) (i32.const 1) (i32.const 128)))
+                (if (local.get $errno)
+                    (then (call $exit (local.get $errno))))
+                (call $exit (i32.wrap_i64 (i64.load (i32.const 64))))))
+    "#;
+
+    fn sleep_plugin(policy: &WasiPolicy) -> Plugin {
+        let plugin =
+            Plugin::from_bytes(&Engine::default(), &wat::parse_str(SLEEP).unwrap()).unwrap();
+        plugin.set_wasi_policy(policy.clone()).unwrap();
+        plugin
+    }
+
+    #[test]
+    fn test_restricted_imports() {
+        let module = Module::new(&Engine::default(), wat::parse_str(SLEEP).unwrap()).unwrap();
+        let restricted = |policy: &WasiPolicy| {
+            restricted_imports(policy, &module)
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+        assert!(restricted(&WasiPolicy::new()).is_empty());
+        assert_eq!(
+            restricted(WasiPolicy::new().clocks(WasiRule::Stub)),
+            ["poll_oneoff"]
+        );
+        assert_eq!(
+            restricted(WasiPolicy::new().files(WasiRule::Deny)),
+            ["fd_write"]
+        );
+        assert_eq!(
+            restricted(WasiPolicy::new().deny("proc_exit")),
+            ["proc_exit"]
+        );
+    }
+
+    #[test]
+    fn test_poll_clocks() {
+        let started = Instant::now();
+        let plugin = sleep_plugin(WasiPolicy::new().clocks(WasiRule::Stub));
+        assert_eq!(plugin.pipe(io::empty(), io::sink()).unwrap(), 7);
+        let plugin = sleep_plugin(WasiPolicy::new().clocks(WasiRule::Deny));
+        assert_eq!(
+            plugin.pipe(io::empty(), io::sink()).unwrap(),
+            ERRNO_NOTCAPABLE
+        );
+        assert!(started.elapsed() < Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,840 @@
+//! Runs plugins in a child process for crash and memory isolation.
+//!
+//! The parent and the child exchange frames over the stdin and stdout of the
+//! child.  Every frame starts with a tag byte followed by bridge messages:
+//! batches of requests go to the child, host calls of the plugin come back
+//! to the parent and are answered until the child sends the responses.
+//! The configuration of the plugin is sent once before the first batch.
+use std::ffi::OsString;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use wasmtime::Engine;
+use worthless_bridge::{
+    Error, ErrorKind, Handler, Request, Response, Value, PUBLISH_ENDPOINT, SHUTDOWN_TOPIC,
+};
+
+use crate::config::HostConfig;
+use crate::endpoints::Endpoints;
+use crate::error::{error_chain, HostError};
+use crate::observer::Observers;
+use crate::plugin::Plugin;
+use crate::policy::WasiPolicy;
+use crate::quota::{Quota, QuotaRegistry};
+use crate::transport::Transport;
+
+/// A batch of requests for the child, followed by their count and the requests.
+const FRAME_REQUESTS: u8 = 1;
+
+/// The response of the parent to a host call.
+const FRAME_HOST_RESPONSE: u8 = 2;
+
+/// A host call of the plugin in the child.
+const FRAME_HOST_CALL: u8 = 3;
+
+/// The responses to a batch, followed by their count and the responses.
+const FRAME_RESPONSES: u8 = 4;
+
+/// A batch failed in the child, followed by an error response.
+const FRAME_FAILED: u8 = 5;
+
+/// The configuration for the child, followed by its length and JSON.
+const FRAME_CONFIG: u8 = 6;
+
+/// The tenant a child accounts the resources of its plugin to.
+const PROCESS_TENANT: &str = "process";
+
+/// Where a plugin runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// In the process of the host, isolated by the WASM sandbox only.
+    #[default]
+    InProcess,
+    /// In a child process started from the given `worthless` executable.
+    ///
+    /// A crash or runaway memory use of the plugin cannot take down the
+    /// host this way.
+    Process(PathBuf),
+}
+
+/// Configures a plugin that runs in a child process.
+///
+/// The child sets up its engine and the plugin from this, as neither can
+/// be shared with the host.  A child started after a crash gets the same
+/// configuration.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProcessConfig {
+    host: HostConfig,
+    wasi_policy: Option<WasiPolicy>,
+    env_config: Vec<(String, String)>,
+    max_request_size: Option<usize>,
+    quota: Option<Quota>,
+    memory_limit: Option<u64>,
+    timeout: Option<Duration>,
+}
+
+impl ProcessConfig {
+    /// Creates the default configuration.
+    pub fn new() -> ProcessConfig {
+        ProcessConfig::default()
+    }
+
+    /// Sets the configuration of the engine in the child.
+    pub fn host_config(&mut self, config: HostConfig) -> &mut ProcessConfig {
+        self.host = config;
+        self
+    }
+
+    /// Sets the WASI policy of the plugin, see [`Plugin::set_wasi_policy`].
+    pub fn wasi_policy(&mut self, policy: WasiPolicy) -> &mut ProcessConfig {
+        self.wasi_policy = Some(policy);
+        self
+    }
+
+    /// Sets a config value in the environment of the plugin, see
+    /// [`Plugin::set_env_config`].
+    pub fn env_config(&mut self, key: &str, value: &str) -> &mut ProcessConfig {
+        match self.env_config.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value.to_string(),
+            None => self.env_config.push((key.to_string(), value.to_string())),
+        }
+        self
+    }
+
+    /// Sets the maximum size of a request in bytes, see
+    /// [`Plugin::set_max_request_size`].
+    pub fn max_request_size(&mut self, limit: usize) -> &mut ProcessConfig {
+        self.max_request_size = Some(limit);
+        self
+    }
+
+    /// Limits the resources the plugin may use.
+    ///
+    /// The usage is accounted in the child, so it starts over with every
+    /// fresh child.  Fuel is only limited if it is metered, see
+    /// [`HostConfig::consume_fuel`].
+    pub fn quota(&mut self, quota: Quota) -> &mut ProcessConfig {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Limits the memory the child can allocate in bytes.
+    ///
+    /// Allocations beyond the limit fail in the child, which usually makes
+    /// the invocation fail.  This is only supported on Unix.
+    pub fn memory_limit(&mut self, bytes: u64) -> &mut ProcessConfig {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Kills the child if it does not respond to a batch in time.
+    ///
+    /// The invocation fails with [`HostError::ProcessFailed`] and the next
+    /// one starts a fresh child.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut ProcessConfig {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Configures a plugin loaded in the child.
+    fn apply(&self, plugin: &Plugin) -> Result<(), HostError> {
+        if let Some(ref policy) = self.wasi_policy {
+            plugin.set_wasi_policy(policy.clone())?;
+        }
+        plugin.set_max_request_size(self.max_request_size)?;
+        for (key, value) in &self.env_config {
+            plugin.set_env_config(key, value)?;
+        }
+        if let Some(quota) = self.quota {
+            let registry = Arc::new(QuotaRegistry::new());
+            registry.set_quota(PROCESS_TENANT, quota);
+            plugin.set_quota_account(Some((registry, PROCESS_TENANT.to_string())));
+        }
+        Ok(())
+    }
+}
+
+/// A plugin loaded with one of the [`Backend`]s.
+pub enum PluginInstance {
+    /// A plugin in the process of the host.
+    InProcess(Plugin),
+    /// A plugin in a child process.
+    Process(PluginProcess),
+}
+
+impl PluginInstance {
+    /// Loads a plugin from a path with the given backend.
+    ///
+    /// The engine is only used in process, child processes bring their own
+    /// with the default configuration.
+    pub fn load<P: AsRef<Path>>(
+        engine: &Engine,
+        path: P,
+        backend: &Backend,
+    ) -> Result<PluginInstance, HostError> {
+        Ok(match backend {
+            Backend::InProcess => PluginInstance::InProcess(Plugin::from_path(engine, path)?),
+            Backend::Process(program) => {
+                PluginInstance::Process(PluginProcess::spawn(program, path)?)
+            }
+        })
+    }
+
+    /// Sets the name observers see for the plugin.
+    pub fn set_name(&self, name: &str) {
+        match self {
+            PluginInstance::InProcess(plugin) => plugin.set_name(name),
+            PluginInstance::Process(process) => process.set_name(name),
+        }
+    }
+
+    /// Hands the plugin the observers to report to, see [`Plugin::set_observers`].
+    pub fn set_observers(&self, observers: Observers) {
+        match self {
+            PluginInstance::InProcess(plugin) => plugin.set_observers(observers),
+            PluginInstance::Process(process) => process.set_observers(observers),
+        }
+    }
+
+    /// Registers a host endpoint the plugin can invoke.
+    pub fn register_endpoint<F>(&self, endpoint: &str, f: F)
+    where
+        F: Fn(&Request) -> Result<Value, Error> + Send + Sync + 'static,
+    {
+        match self {
+            PluginInstance::InProcess(plugin) => plugin.register_endpoint(endpoint, f),
+            PluginInstance::Process(process) => process.register_endpoint(endpoint, f),
+        }
+    }
+
+    /// Registers a host endpoint served by an async [`Handler`].
+    pub fn register_handler<H: Handler + 'static>(&self, endpoint: &str, handler: H) {
+        match self {
+            PluginInstance::InProcess(plugin) => plugin.register_handler(endpoint, handler),
+            PluginInstance::Process(process) => process.register_handler(endpoint, handler),
+        }
+    }
+
+    /// Replaces the transport host calls of the plugin are sent through.
+    pub fn set_transport(&self, transport: Option<Arc<dyn Transport>>) {
+        match self {
+            PluginInstance::InProcess(plugin) => plugin.set_transport(transport),
+            PluginInstance::Process(process) => process.set_transport(transport),
+        }
+    }
+
+    /// Sends a single request to the plugin and returns the response.
+    pub fn send_request(&self, req: Request) -> Result<Response, HostError> {
+        match self {
+            PluginInstance::InProcess(plugin) => plugin.send_request(req),
+            PluginInstance::Process(process) => process.send_request(req),
+        }
+    }
+
+    /// Sends a batch of requests to the plugin in one invocation.
+    pub fn send_requests<I>(&self, reqs: I) -> Result<Vec<Response>, HostError>
+    where
+        I: IntoIterator<Item = Request>,
+    {
+        match self {
+            PluginInstance::InProcess(plugin) => plugin.send_requests(reqs),
+            PluginInstance::Process(process) => process.send_requests(reqs),
+        }
+    }
+
+    /// Tells the plugin that it's about to be shut down.
+    ///
+    /// See [`Plugin::shutdown`], child processes are always sent the event.
+    pub fn shutdown(&self) -> Result<(), HostError> {
+        match self {
+            PluginInstance::InProcess(plugin) => plugin.shutdown().map(|_| ()),
+            PluginInstance::Process(process) => process
+                .send_request(Request::new(
+                    PUBLISH_ENDPOINT,
+                    Value::Map(vec![
+                        ("topic".into(), SHUTDOWN_TOPIC.into()),
+                        ("payload".into(), Value::Null),
+                    ]),
+                ))?
+                .into_payload()
+                .map(|_| ())
+                .map_err(HostError::ProtocolError),
+        }
+    }
+}
+
+/// A running child process and the pipes to it.
+struct Worker {
+    // shared with the watchdog of an invocation
+    child: Arc<Mutex<Child>>,
+    input: BufWriter<ChildStdin>,
+    output: BufReader<ChildStdout>,
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let mut child = self.child.lock().unwrap();
+        child.kill().ok();
+        child.wait().ok();
+    }
+}
+
+/// A plugin running in a child process.
+///
+/// The child is the `worker` command of the `worthless` executable, which
+/// loads the plugin and serves it with [`serve_process`].  Host calls of the
+/// plugin are sent back and handled by the endpoints registered here.  If
+/// the child dies the invocation fails and the next one starts a fresh
+/// child, so the state of the plugin is lost.
+///
+/// Only requests are supported, the child cannot be piped, snapshotted or
+/// debugged.
+pub struct PluginProcess {
+    program: PathBuf,
+    args: Vec<OsString>,
+    config: ProcessConfig,
+    endpoints: Endpoints,
+    worker: Mutex<Option<Worker>>,
+    // how long the last child took to start
+    load_duration: Mutex<Duration>,
+}
+
+impl PluginProcess {
+    /// Starts a plugin in a child process of the `worthless` executable.
+    ///
+    /// The plugin is set up with the default configuration.
+    pub fn spawn<P: AsRef<Path>, M: AsRef<Path>>(
+        program: P,
+        module: M,
+    ) -> Result<PluginProcess, HostError> {
+        PluginProcess::spawn_with_config(program, module, ProcessConfig::new())
+    }
+
+    /// Starts a plugin in a child process with the given configuration.
+    pub fn spawn_with_config<P: AsRef<Path>, M: AsRef<Path>>(
+        program: P,
+        module: M,
+        config: ProcessConfig,
+    ) -> Result<PluginProcess, HostError> {
+        let module = module.as_ref();
+        let process = PluginProcess {
+            program: program.as_ref().to_path_buf(),
+            args: vec!["worker".into(), module.into()],
+            config,
+            endpoints: Endpoints::new(),
+            worker: Mutex::new(None),
+            load_duration: Mutex::new(Duration::ZERO),
+        };
+        process
+            .endpoints
+            .name()
+            .set(module.file_stem().and_then(|x| x.to_str()));
+        *process.worker.lock().unwrap() = Some(process.start()?);
+        Ok(process)
+    }
+
+    fn start(&self) -> Result<Worker, HostError> {
+        let started = Instant::now();
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|err| HostError::ProcessFailed(err.into()))?;
+        let mut input = BufWriter::new(child.stdin.take().unwrap());
+        let output = BufReader::new(child.stdout.take().unwrap());
+        write_config(&mut input, &self.config)?;
+        let duration = started.elapsed();
+        *self.load_duration.lock().unwrap() = duration;
+        self.endpoints
+            .name()
+            .notify(|observer, name| observer.on_load(name, duration));
+        Ok(Worker {
+            child: Arc::new(Mutex::new(child)),
+            input,
+            output,
+        })
+    }
+
+    /// Sets the name observers see for the plugin, see [`Plugin::set_name`].
+    ///
+    /// It defaults to the file name of the module without the extension.
+    pub fn set_name(&self, name: &str) {
+        self.endpoints.name().set(Some(name));
+    }
+
+    /// Returns the name observers see for the plugin.
+    pub fn name(&self) -> Option<String> {
+        self.endpoints.name().get().map(|x| x.to_string())
+    }
+
+    /// Hands the plugin the observers to report to, see [`Plugin::set_observers`].
+    ///
+    /// They are told about every fresh child that is started from then on.
+    pub fn set_observers(&self, observers: Observers) {
+        let name = self.endpoints.name();
+        name.set_observers(observers);
+        let duration = *self.load_duration.lock().unwrap();
+        name.notify(|observer, name| observer.on_load(name, duration));
+    }
+
+    /// Returns the observers the plugin reports to.
+    pub fn observers(&self) -> Observers {
+        self.endpoints.name().observers()
+    }
+
+    /// Registers a host endpoint the plugin can invoke.
+    ///
+    /// The `log.emit` endpoint is registered by default like for in
+    /// process plugins.
+    pub fn register_endpoint<F>(&self, endpoint: &str, f: F)
+    where
+        F: Fn(&Request) -> Result<Value, Error> + Send + Sync + 'static,
+    {
+        self.endpoints.register(endpoint, f);
+    }
+
+    /// Registers a host endpoint served by an async [`Handler`].
+    pub fn register_handler<H: Handler + 'static>(&self, endpoint: &str, handler: H) {
+        self.endpoints.register_handler(endpoint, handler);
+    }
+
+    /// Replaces the transport host calls of the plugin are sent through.
+    pub fn set_transport(&self, transport: Option<Arc<dyn Transport>>) {
+        self.endpoints.set_transport(transport);
+    }
+
+    /// Returns the process ID of the child if it is running.
+    pub fn id(&self) -> Option<u32> {
+        let worker = self.worker.lock().unwrap();
+        worker.as_ref().map(|x| x.child.lock().unwrap().id())
+    }
+
+    /// Kills the child, the next invocation starts a fresh one.
+    pub fn kill(&self) {
+        self.worker.lock().unwrap().take();
+    }
+
+    /// Sends a single request to the plugin and returns the response.
+    pub fn send_request(&self, req: Request) -> Result<Response, HostError> {
+        self.send_requests(Some(req))?.pop().ok_or_else(|| {
+            HostError::ProtocolError(Error::new(
+                ErrorKind::InternalError,
+                "plugin did not respond",
+            ))
+        })
+    }
+
+    /// Sends a batch of requests to the plugin in one invocation.
+    pub fn send_requests<I>(&self, reqs: I) -> Result<Vec<Response>, HostError>
+    where
+        I: IntoIterator<Item = Request>,
+    {
+        let reqs: Vec<Request> = reqs.into_iter().collect();
+        let name = self.endpoints.name();
+        name.notify(|observer, name| observer.on_invoke_start(name, reqs.len()));
+        let started = Instant::now();
+        let rv = self.invoke_batch(&reqs);
+        let duration = started.elapsed();
+        name.notify(|observer, name| observer.on_invoke_end(name, duration, rv.as_ref().err()));
+        rv
+    }
+
+    fn invoke_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, HostError> {
+        let mut guard = self.worker.lock().unwrap();
+        if guard.is_none() {
+            *guard = Some(self.start()?);
+        }
+        let worker = guard.as_mut().unwrap();
+        let rv = match self.config.timeout {
+            Some(timeout) => {
+                let child = worker.child.clone();
+                match with_deadline(&child, timeout, || self.exchange(worker, reqs)) {
+                    Some(rv) => rv,
+                    None => {
+                        guard.take();
+                        return Err(HostError::ProcessFailed(anyhow!(
+                            "plugin process did not respond within {:?}",
+                            timeout
+                        )));
+                    }
+                }
+            }
+            None => self.exchange(worker, reqs),
+        };
+        if let Err(HostError::ProcessFailed(_) | HostError::ProtocolError(_)) = rv {
+            // the pipes are out of sync or the child is gone, start over
+            let worker = guard.take().unwrap();
+            let status = worker.child.lock().unwrap().try_wait();
+            if let Ok(Some(status)) = status {
+                return Err(HostError::ProcessFailed(anyhow!(
+                    "plugin process exited with {}",
+                    status
+                )));
+            }
+        }
+        rv
+    }
+
+    /// Sends a batch to the child and answers its host calls until it
+    /// responds.
+    fn exchange(&self, worker: &mut Worker, reqs: &[Request]) -> Result<Vec<Response>, HostError> {
+        write_frame(&mut worker.input, FRAME_REQUESTS, |w| {
+            write_count(w, reqs.len())?;
+            for req in reqs {
+                req.serialize_to(&mut *w)
+                    .map_err(HostError::ProtocolError)?;
+            }
+            Ok(())
+        })?;
+        loop {
+            match read_tag(&mut worker.output)? {
+                Some(FRAME_HOST_CALL) => {
+                    let req = Request::deserialize_from(&mut worker.output)
+                        .map_err(HostError::ProtocolError)?;
+                    let response = match self.endpoints.transport() {
+                        Some(transport) => transport.handle(&req),
+                        None => self.endpoints.dispatch(&req),
+                    };
+                    if !req.fire_and_forget() {
+                        write_frame(&mut worker.input, FRAME_HOST_RESPONSE, |w| {
+                            response.serialize_to(w).map_err(HostError::ProtocolError)
+                        })?;
+                    }
+                }
+                Some(FRAME_RESPONSES) => {
+                    let count = read_count(&mut worker.output)?;
+                    return (0..count)
+                        .map(|_| {
+                            Response::deserialize_from(&mut worker.output)
+                                .map_err(HostError::ProtocolError)
+                        })
+                        .collect();
+                }
+                Some(FRAME_FAILED) => {
+                    let response = Response::deserialize_from(&mut worker.output)
+                        .map_err(HostError::ProtocolError)?;
+                    let message = match response.into_payload() {
+                        Err(err) => err.to_string(),
+                        Ok(_) => "invocation failed".to_string(),
+                    };
+                    return Err(HostError::ExecutionFailed(anyhow!(message)));
+                }
+                Some(tag) => {
+                    return Err(HostError::ProcessFailed(anyhow!(
+                        "unexpected frame {} from plugin process",
+                        tag
+                    )))
+                }
+                None => {
+                    return Err(HostError::ProcessFailed(anyhow!(
+                        "plugin process closed the pipe"
+                    )))
+                }
+            }
+        }
+    }
+}
+
+/// Runs `f` and kills the child if it does not return within `timeout`.
+///
+/// Returns `None` if the child was killed.
+fn with_deadline<T, F: FnOnce() -> T>(
+    child: &Arc<Mutex<Child>>,
+    timeout: Duration,
+    f: F,
+) -> Option<T> {
+    let (done, deadline) = mpsc::channel::<()>();
+    let watchdog = thread::spawn({
+        let child = child.clone();
+        move || match deadline.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => {
+                child.lock().unwrap().kill().ok();
+                true
+            }
+            _ => false,
+        }
+    });
+    let rv = f();
+    drop(done);
+    match watchdog.join() {
+        Ok(true) => None,
+        _ => Some(rv),
+    }
+}
+
+/// Loads a plugin in the child process and serves it to the parent.
+///
+/// This is what the `worker` command runs.  The parent sends the
+/// [`ProcessConfig`] first, the engine and the plugin are set up with it
+/// before the plugin is served with [`serve_process`].
+pub fn serve_worker<P, R, W>(module: P, input: R, output: W) -> Result<(), HostError>
+where
+    P: AsRef<Path>,
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let mut input = BufReader::new(input);
+    let config = match read_tag(&mut input)? {
+        Some(FRAME_CONFIG) => read_config(&mut input)?,
+        Some(tag) => {
+            return Err(HostError::ProcessFailed(anyhow!(
+                "unexpected frame {} from parent process",
+                tag
+            )))
+        }
+        None => return Ok(()),
+    };
+    if let Some(bytes) = config.memory_limit {
+        limit_memory(bytes)?;
+    }
+    let engine = config.host.build_engine()?;
+    let plugin = Plugin::from_path(&engine, module)?;
+    config.apply(&plugin)?;
+    serve_process(&plugin, input, output)
+}
+
+fn write_config<W: Write>(w: &mut W, config: &ProcessConfig) -> Result<(), HostError> {
+    let config = serde_json::to_vec(config).map_err(|err| HostError::InvalidConfig(err.into()))?;
+    write_frame(w, FRAME_CONFIG, |w| {
+        write_count(w, config.len())?;
+        w.write_all(&config).map_err(process_io_error)
+    })
+}
+
+fn read_config<R: Read>(r: &mut R) -> Result<ProcessConfig, HostError> {
+    let mut config = vec![0; read_count(r)?];
+    r.read_exact(&mut config).map_err(process_io_error)?;
+    serde_json::from_slice(&config).map_err(|err| HostError::InvalidConfig(err.into()))
+}
+
+/// Limits the memory the process can allocate in bytes.
+///
+/// This sets `RLIMIT_DATA` rather than `RLIMIT_AS`, as the latter would also
+/// count the address space wasmtime reserves for linear memories up front.
+#[cfg(unix)]
+fn limit_memory(bytes: u64) -> Result<(), HostError> {
+    let limit = libc::rlimit {
+        rlim_cur: bytes as libc::rlim_t,
+        rlim_max: bytes as libc::rlim_t,
+    };
+    // SAFETY: the limit is only read for the duration of the call
+    if unsafe { libc::setrlimit(libc::RLIMIT_DATA, &limit) } != 0 {
+        return Err(HostError::ProcessFailed(io::Error::last_os_error().into()));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn limit_memory(_bytes: u64) -> Result<(), HostError> {
+    Err(HostError::InvalidConfig(anyhow!(
+        "memory limits are only supported on unix"
+    )))
+}
+
+/// Serves a plugin to the parent process over a pair of pipes.
+///
+/// This is the other end of [`PluginProcess`] which runs in the child.  Host
+/// calls of the plugin are sent to the parent, the transport of the plugin
+/// is replaced for that.  Output the plugin writes to stdout goes to stderr
+/// so it does not mix with the frames.  Returns when the parent closes the
+/// input.
+pub fn serve_process<R, W>(plugin: &Plugin, input: R, output: W) -> Result<(), HostError>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let pipes = Arc::new(Mutex::new(ParentPipes {
+        input: BufReader::new(Box::new(input) as Box<dyn Read + Send>),
+        output: BufWriter::new(Box::new(output) as Box<dyn Write + Send>),
+    }));
+    plugin.redirect_stdout_to_stderr();
+    plugin.set_transport(Some(Arc::new(ParentTransport(pipes.clone()))));
+    loop {
+        let reqs = {
+            let mut pipes = pipes.lock().unwrap();
+            match read_tag(&mut pipes.input)? {
+                Some(FRAME_REQUESTS) => {}
+                Some(tag) => {
+                    return Err(HostError::ProcessFailed(anyhow!(
+                        "unexpected frame {} from parent process",
+                        tag
+                    )))
+                }
+                None => return Ok(()),
+            }
+            let count = read_count(&mut pipes.input)?;
+            (0..count)
+                .map(|_| Request::deserialize_from(&mut pipes.input))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(HostError::ProtocolError)?
+        };
+        // the pipes are unlocked while the plugin runs so that host calls
+        // can use them
+        let rv = plugin.send_requests(reqs);
+        let mut pipes = pipes.lock().unwrap();
+        match rv {
+            Ok(responses) => write_frame(&mut pipes.output, FRAME_RESPONSES, |w| {
+                write_count(w, responses.len())?;
+                for response in &responses {
+                    response
+                        .serialize_to(&mut *w)
+                        .map_err(HostError::ProtocolError)?;
+                }
+                Ok(())
+            })?,
+            Err(err) => {
+                let response = Response::new(
+                    Default::default(),
+                    Err(Error::new(ErrorKind::InternalError, error_chain(&err))),
+                );
+                write_frame(&mut pipes.output, FRAME_FAILED, |w| {
+                    response.serialize_to(w).map_err(HostError::ProtocolError)
+                })?;
+            }
+        }
+    }
+}
+
+/// The pipes of a child process to its parent.
+struct ParentPipes {
+    input: BufReader<Box<dyn Read + Send>>,
+    output: BufWriter<Box<dyn Write + Send>>,
+}
+
+/// Sends the host calls of a plugin in a child process to the parent.
+struct ParentTransport(Arc<Mutex<ParentPipes>>);
+
+impl ParentTransport {
+    fn call(&self, req: &Request) -> Result<Response, HostError> {
+        let mut pipes = self.0.lock().unwrap();
+        write_frame(&mut pipes.output, FRAME_HOST_CALL, |w| {
+            req.serialize_to(w).map_err(HostError::ProtocolError)
+        })?;
+        if req.fire_and_forget() {
+            return Ok(Response::new(Default::default(), Ok(Value::Null)));
+        }
+        match read_tag(&mut pipes.input)? {
+            Some(FRAME_HOST_RESPONSE) => {
+                Response::deserialize_from(&mut pipes.input).map_err(HostError::ProtocolError)
+            }
+            _ => Err(HostError::ProcessFailed(anyhow!(
+                "parent process did not answer the host call"
+            ))),
+        }
+    }
+}
+
+impl Transport for ParentTransport {
+    fn handle(&self, req: &Request) -> Response {
+        self.call(req).unwrap_or_else(|err| {
+            Response::new(
+                Default::default(),
+                Err(Error::new(ErrorKind::InternalError, error_chain(&err))),
+            )
+        })
+    }
+}
+
+/// Writes a frame and flushes it.
+fn write_frame<W, F>(w: &mut W, tag: u8, f: F) -> Result<(), HostError>
+where
+    W: Write,
+    F: FnOnce(&mut W) -> Result<(), HostError>,
+{
+    w.write_all(&[tag]).map_err(process_io_error)?;
+    f(&mut *w)?;
+    w.flush().map_err(process_io_error)
+}
+
+/// Reads the tag of the next frame, returns `None` at the end of the pipe.
+fn read_tag<R: Read>(r: &mut R) -> Result<Option<u8>, HostError> {
+    let mut tag = [0];
+    match r.read_exact(&mut tag) {
+        Ok(()) => Ok(Some(tag[0])),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(process_io_error(err)),
+    }
+}
+
+fn write_count<W: Write>(w: &mut W, count: usize) -> Result<(), HostError> {
+    let count = u32::try_from(count).map_err(|_| anyhow!("too many messages"));
+    w.write_all(&count.map_err(HostError::ProcessFailed)?.to_be_bytes())
+        .map_err(process_io_error)
+}
+
+fn read_count<R: Read>(r: &mut R) -> Result<usize, HostError> {
+    let mut count = [0; 4];
+    r.read_exact(&mut count).map_err(process_io_error)?;
+    Ok(u32::from_be_bytes(count) as usize)
+}
+
+fn process_io_error(err: io::Error) -> HostError {
+    HostError::ProcessFailed(err.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    #[test]
+    fn test_config_frame() {
+        let mut config = ProcessConfig::new();
+        config
+            .env_config("mode", "debug")
+            .env_config("mode", "test")
+            .max_request_size(1024)
+            .quota(*Quota::new().fuel(1000))
+            .timeout(Duration::from_secs(1));
+        let mut frame = Vec::new();
+        write_config(&mut frame, &config).unwrap();
+        let mut frame = &frame[..];
+        assert_eq!(read_tag(&mut frame).unwrap(), Some(FRAME_CONFIG));
+        let read = read_config(&mut frame).unwrap();
+        assert_eq!(format!("{:?}", read), format!("{:?}", config));
+        assert_eq!(read.env_config, [("mode".to_string(), "test".to_string())]);
+        assert!(frame.is_empty());
+    }
+
+    /// Writes a script that starts fine but never responds.
+    #[cfg(unix)]
+    fn unresponsive_program() -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let path =
+            std::env::temp_dir().join(format!("worthless-unresponsive-{}", std::process::id()));
+        fs::write(&path, "#!/bin/sh\nexec sleep 60\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_timeout() {
+        let program = unresponsive_program();
+        let mut config = ProcessConfig::new();
+        config.timeout(Duration::from_millis(50));
+        let process = PluginProcess::spawn_with_config(&program, "plugin.wasm", config).unwrap();
+        let first = process.id().unwrap();
+
+        let started = Instant::now();
+        let err = process.send_request(Request::new("echo", 1)).unwrap_err();
+        assert!(matches!(err, HostError::ProcessFailed(_)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(process.id(), None);
+
+        // the next invocation starts a fresh child which times out again
+        assert!(process.send_request(Request::new("echo", 1)).is_err());
+        assert_ne!(process.id(), Some(first));
+        fs::remove_file(program).ok();
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::ops::AddAssign;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::HostError;
+
+/// Limits on the resources a tenant may use in total.
+///
+/// Every limit is optional, a quota without limits only accounts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    fuel: Option<u64>,
+    cpu_time: Option<Duration>,
+    bridge_bytes: Option<u64>,
+}
+
+impl Quota {
+    /// Creates a quota without limits.
+    pub fn new() -> Quota {
+        Quota::default()
+    }
+
+    /// Limits the fuel consumed by WASM code.
+    ///
+    /// Fuel is only metered if it was enabled with
+    /// [`HostConfig::consume_fuel`](crate::HostConfig::consume_fuel).
+    pub fn fuel(&mut self, limit: u64) -> &mut Quota {
+        self.fuel = Some(limit);
+        self
+    }
+
+    /// Limits the time spent in invocations of plugins.
+    pub fn cpu_time(&mut self, limit: Duration) -> &mut Quota {
+        self.cpu_time = Some(limit);
+        self
+    }
+
+    /// Limits the bytes of requests and responses sent over the bridge.
+    pub fn bridge_bytes(&mut self, limit: u64) -> &mut Quota {
+        self.bridge_bytes = Some(limit);
+        self
+    }
+
+    /// Returns the fuel limit.
+    pub fn fuel_limit(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Returns the time limit.
+    pub fn cpu_time_limit(&self) -> Option<Duration> {
+        self.cpu_time
+    }
+
+    /// Returns the bridge bytes limit.
+    pub fn bridge_bytes_limit(&self) -> Option<u64> {
+        self.bridge_bytes
+    }
+
+    /// Returns the name of the first resource the usage exhausted.
+    fn exhausted(&self, usage: &ResourceUsage) -> Option<&'static str> {
+        if self.fuel.map_or(false, |limit| usage.fuel >= limit) {
+            Some("fuel")
+        } else if self.cpu_time.map_or(false, |limit| usage.cpu_time >= limit) {
+            Some("cpu_time")
+        } else if self
+            .bridge_bytes
+            .map_or(false, |limit| usage.bridge_bytes >= limit)
+        {
+            Some("bridge_bytes")
+        } else {
+            None
+        }
+    }
+}
+
+/// The resources used by a tenant.
+///
+/// The CPU time is the wall time of the invocations.  WASM code runs on the
+/// thread that invokes the plugin, so this is close to the CPU time unless
+/// host calls block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// The number of invocations.
+    pub invocations: u64,
+    /// The fuel consumed by WASM code.
+    pub fuel: u64,
+    /// The time spent in invocations.
+    pub cpu_time: Duration,
+    /// The bytes of requests and responses sent over the bridge.
+    pub bridge_bytes: u64,
+}
+
+impl AddAssign for ResourceUsage {
+    fn add_assign(&mut self, other: ResourceUsage) {
+        self.invocations += other.invocations;
+        self.fuel += other.fuel;
+        self.cpu_time += other.cpu_time;
+        self.bridge_bytes += other.bridge_bytes;
+    }
+}
+
+#[derive(Default)]
+struct Account {
+    quota: Quota,
+    usage: ResourceUsage,
+}
+
+/// Accounts the resources used by plugins per tenant.
+///
+/// Plugins are attached to a tenant with
+/// [`Plugin::set_quota_account`](crate::Plugin::set_quota_account).  Once a
+/// tenant used up a resource its plugins refuse further invocations with
+/// [`HostError::QuotaExceeded`] until the usage is reset.  The invocation
+/// that crosses a limit still completes, except for fuel which traps.
+#[derive(Default)]
+pub struct QuotaRegistry {
+    accounts: Mutex<HashMap<String, Account>>,
+}
+
+impl QuotaRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> QuotaRegistry {
+        QuotaRegistry::default()
+    }
+
+    /// Sets the quota of a tenant.
+    pub fn set_quota(&self, tenant: &str, quota: Quota) {
+        self.accounts
+            .lock()
+            .unwrap()
+            .entry(tenant.to_string())
+            .or_default()
+            .quota = quota;
+    }
+
+    /// Returns the quota of a tenant.
+    pub fn quota(&self, tenant: &str) -> Quota {
+        self.accounts
+            .lock()
+            .unwrap()
+            .get(tenant)
+            .map(|account| account.quota)
+            .unwrap_or_default()
+    }
+
+    /// Returns the resources a tenant used.
+    pub fn usage(&self, tenant: &str) -> ResourceUsage {
+        self.accounts
+            .lock()
+            .unwrap()
+            .get(tenant)
+            .map(|account| account.usage)
+            .unwrap_or_default()
+    }
+
+    /// Returns the usage of all tenants that are known to the registry.
+    pub fn usages(&self) -> Vec<(String, ResourceUsage)> {
+        let mut rv: Vec<_> = self
+            .accounts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(tenant, account)| (tenant.clone(), account.usage))
+            .collect();
+        rv.sort_by(|a, b| a.0.cmp(&b.0));
+        rv
+    }
+
+    /// Resets the usage of a tenant and keeps the quota.
+    pub fn reset(&self, tenant: &str) {
+        if let Some(account) = self.accounts.lock().unwrap().get_mut(tenant) {
+            account.usage = ResourceUsage::default();
+        }
+    }
+
+    /// Fails if the tenant used up any of its resources.
+    pub fn check(&self, tenant: &str) -> Result<(), HostError> {
+        let accounts = self.accounts.lock().unwrap();
+        match accounts
+            .get(tenant)
+            .and_then(|account| account.quota.exhausted(&account.usage))
+        {
+            Some(resource) => Err(HostError::QuotaExceeded(tenant.to_string(), resource)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the fuel a tenant has left, `None` if fuel is not limited.
+    pub fn remaining_fuel(&self, tenant: &str) -> Option<u64> {
+        let accounts = self.accounts.lock().unwrap();
+        let account = accounts.get(tenant)?;
+        let limit = account.quota.fuel?;
+        Some(limit.saturating_sub(account.usage.fuel))
+    }
+
+    /// Adds to the resources a tenant used.
+    pub fn record(&self, tenant: &str, usage: ResourceUsage) {
+        self.accounts
+            .lock()
+            .unwrap()
+            .entry(tenant.to_string())
+            .or_default()
+            .usage += usage;
+    }
+}
@@ -0,0 +1,563 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use worthless_bridge::{Error, ErrorKind, Request, Response};
+
+use crate::clock::VirtualClock;
+use crate::error::HostError;
+use crate::plugin::Plugin;
+use crate::transport::Transport;
+
+/// A single host call in a recording.
+#[derive(Serialize, Deserialize)]
+pub struct RecordedCall {
+    /// Seconds since the UNIX epoch when the call was made.
+    pub timestamp: f64,
+    /// The request of the guest.
+    pub request: Request,
+    /// The response the host gave.
+    pub response: Response,
+}
+
+/// A single invocation of the plugin in a recording.
+#[derive(Serialize, Deserialize)]
+pub struct RecordedInvocation {
+    /// Seconds since the UNIX epoch when the plugin was invoked.
+    pub timestamp: f64,
+    /// The requests the plugin was invoked with.
+    pub requests: Vec<Request>,
+    /// The responses of the plugin.
+    pub responses: Vec<Response>,
+}
+
+/// An entry of a recording, in the order the messages were exchanged.
+#[derive(Serialize, Deserialize)]
+enum RecordedEntry {
+    /// The plugin called the host.
+    Call(RecordedCall),
+    /// The host invoked the plugin.
+    Invocation(RecordedInvocation),
+}
+
+/// A borrowed [`RecordedEntry`] that serializes the same way.
+#[derive(Serialize)]
+enum EntryRef<'a> {
+    Call(&'a RecordedCall),
+    Invocation(&'a RecordedInvocation),
+}
+
+/// A transport that records all traffic of a plugin.
+///
+/// The host calls are forwarded to the inner transport and written to the
+/// writer together with the invocations of the plugin made through
+/// [`send_requests`](Self::send_requests).  The recording is loaded again
+/// with [`ReplayTransport::from_path`].
+///
+/// Failing to record never changes the response a plugin sees.  The first
+/// error is kept and returned by the next [`send_requests`](Self::send_requests)
+/// or [`check`](Self::check) instead.
+pub struct RecordingTransport {
+    inner: Arc<dyn Transport>,
+    writer: Mutex<Box<dyn Write + Send>>,
+    error: Mutex<Option<anyhow::Error>>,
+}
+
+impl RecordingTransport {
+    /// Records the traffic of `inner` into a writer.
+    pub fn new<W: Write + Send + 'static>(
+        inner: Arc<dyn Transport>,
+        writer: W,
+    ) -> RecordingTransport {
+        RecordingTransport {
+            inner,
+            writer: Mutex::new(Box::new(writer)),
+            error: Mutex::new(None),
+        }
+    }
+
+    /// Records the traffic of `inner` into a file.
+    pub fn create<P: AsRef<Path>>(
+        inner: Arc<dyn Transport>,
+        path: P,
+    ) -> Result<RecordingTransport, HostError> {
+        let file = File::create(path).map_err(|err| HostError::RecordingFailed(err.into()))?;
+        Ok(RecordingTransport::new(inner, BufWriter::new(file)))
+    }
+
+    /// Sends requests to a plugin and records them with the responses.
+    ///
+    /// The transport needs to be set on the plugin with
+    /// [`Plugin::set_transport`] for the host calls to be recorded too.
+    pub fn send_requests<I>(&self, plugin: &Plugin, reqs: I) -> Result<Vec<Response>, HostError>
+    where
+        I: IntoIterator<Item = Request>,
+    {
+        let timestamp = now();
+        let requests: Vec<Request> = reqs.into_iter().collect();
+        let responses = plugin.send_requests(requests.clone())?;
+        let invocation = RecordedInvocation {
+            timestamp,
+            requests,
+            responses,
+        };
+        self.check()?;
+        self.record(EntryRef::Invocation(&invocation))
+            .map_err(HostError::RecordingFailed)?;
+        Ok(invocation.responses)
+    }
+
+    /// Returns the first error recording a host call failed with.
+    ///
+    /// The error is only returned once.
+    pub fn check(&self) -> Result<(), HostError> {
+        match self.error.lock().unwrap().take() {
+            Some(err) => Err(HostError::RecordingFailed(err)),
+            None => Ok(()),
+        }
+    }
+
+    fn record(&self, entry: EntryRef<'_>) -> Result<(), anyhow::Error> {
+        let mut writer = self.writer.lock().unwrap();
+        ciborium::ser::into_writer(&entry, &mut *writer)
+            .map_err(|err| anyhow::anyhow!("{}", err))?;
+        // flush every entry so that the recording survives a crash
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn handle(&self, req: &Request) -> Response {
+        let timestamp = now();
+        let response = self.inner.handle(req);
+        let call = RecordedCall {
+            timestamp,
+            request: req.clone(),
+            response,
+        };
+        if let Err(err) = self.record(EntryRef::Call(&call)) {
+            self.error.lock().unwrap().get_or_insert(err);
+        }
+        call.response
+    }
+}
+
+/// Returns the seconds since the UNIX epoch.
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |x| x.as_secs_f64())
+}
+
+/// A transport that serves recorded responses.
+///
+/// Requests are answered with the responses of the recorded calls in order.
+/// A request to a different endpoint than the next recorded call, or one
+/// made after the recording was exhausted, is answered with an error.  The
+/// recorded invocations of the plugin are available from
+/// [`invocations`](Self::invocations) to send them again.
+pub struct ReplayTransport {
+    calls: Mutex<VecDeque<ReplayedCall>>,
+    // kept encoded as bridge errors are not thread safe
+    invocations: Vec<Vec<u8>>,
+    // replays of invocations move the clock of the plugin before each call
+    clock: Option<VirtualClock>,
+}
+
+struct ReplayedCall {
+    endpoint: String,
+    elapsed: Option<Duration>,
+    // responses are kept serialized as bridge errors are not thread safe
+    response: Vec<u8>,
+}
+
+impl ReplayTransport {
+    /// Creates a replay from recorded calls.
+    pub fn new<I: IntoIterator<Item = RecordedCall>>(
+        calls: I,
+    ) -> Result<ReplayTransport, HostError> {
+        let calls = calls
+            .into_iter()
+            .map(|call| {
+                let response = call
+                    .response
+                    .serialize()
+                    .map_err(HostError::ProtocolError)?;
+                Ok(ReplayedCall {
+                    endpoint: call.request.endpoint().to_string(),
+                    elapsed: None,
+                    response,
+                })
+            })
+            .collect::<Result<_, HostError>>()?;
+        Ok(ReplayTransport {
+            calls: Mutex::new(calls),
+            invocations: Vec::new(),
+            clock: None,
+        })
+    }
+
+    /// Creates a replay of the host calls of an invocation.
+    pub(crate) fn for_invocation(
+        recording: &InvocationRecording,
+        clock: VirtualClock,
+    ) -> Result<ReplayTransport, HostError> {
+        let calls = recording
+            .calls
+            .iter()
+            .map(|call| {
+                let response = call
+                    .response
+                    .serialize()
+                    .map_err(HostError::ProtocolError)?;
+                Ok(ReplayedCall {
+                    endpoint: call.request.endpoint().to_string(),
+                    elapsed: Some(Duration::from_nanos(call.elapsed_nanos)),
+                    response,
+                })
+            })
+            .collect::<Result<_, HostError>>()?;
+        Ok(ReplayTransport {
+            calls: Mutex::new(calls),
+            invocations: Vec::new(),
+            clock: Some(clock),
+        })
+    }
+
+    /// Loads a recording written by a [`RecordingTransport`].
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<ReplayTransport, HostError> {
+        let bytes = fs::read(path).map_err(|err| HostError::RecordingFailed(err.into()))?;
+        ReplayTransport::from_bytes(&bytes)
+    }
+
+    /// Loads a recording from its bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<ReplayTransport, HostError> {
+        let mut rest = bytes;
+        let mut calls = Vec::new();
+        let mut invocations = Vec::new();
+        while !rest.is_empty() {
+            match ciborium::de::from_reader::<RecordedEntry, _>(&mut rest)
+                .map_err(|err| HostError::RecordingFailed(anyhow::anyhow!("{}", err)))?
+            {
+                RecordedEntry::Call(call) => calls.push(call),
+                RecordedEntry::Invocation(invocation) => {
+                    let mut buf = Vec::new();
+                    ciborium::ser::into_writer(&invocation, &mut buf)
+                        .map_err(|err| HostError::RecordingFailed(anyhow::anyhow!("{}", err)))?;
+                    invocations.push(buf);
+                }
+            }
+        }
+        let mut replay = ReplayTransport::new(calls)?;
+        replay.invocations = invocations;
+        Ok(replay)
+    }
+
+    /// Returns the recorded invocations of the plugin in order.
+    pub fn invocations(&self) -> Result<Vec<RecordedInvocation>, HostError> {
+        self.invocations
+            .iter()
+            .map(|buf| {
+                ciborium::de::from_reader(&buf[..])
+                    .map_err(|err| HostError::RecordingFailed(anyhow::anyhow!("{}", err)))
+            })
+            .collect()
+    }
+
+    /// Returns the number of recorded calls that were not replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.calls.lock().unwrap().len()
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn handle(&self, req: &Request) -> Response {
+        let mut calls = self.calls.lock().unwrap();
+        let payload = match calls.front() {
+            Some(call) if call.endpoint == req.endpoint() => {
+                let call = calls.pop_front().unwrap();
+                if let (Some(clock), Some(elapsed)) = (&self.clock, call.elapsed) {
+                    clock.advance_to(elapsed);
+                }
+                match Response::deserialize(&call.response) {
+                    Ok(response) => return response,
+                    Err(err) => Err(err),
+                }
+            }
+            Some(call) => Err(Error::new(
+                ErrorKind::InternalError,
+                format!(
+                    "replay mismatch: expected call to '{}', got '{}'",
+                    call.endpoint,
+                    req.endpoint()
+                ),
+            )),
+            None => Err(Error::new(
+                ErrorKind::InternalError,
+                format!("replay exhausted: unexpected call to '{}'", req.endpoint()),
+            )),
+        };
+        Response::new(Default::default(), payload)
+    }
+}
+
+/// A host call made during a recorded invocation.
+#[derive(Serialize, Deserialize)]
+pub struct InvocationCall {
+    /// How far the clocks of the plugin had advanced when the call was made.
+    pub elapsed_nanos: u64,
+    /// The request of the guest.
+    pub request: Request,
+    /// The response the host gave.
+    pub response: Response,
+}
+
+/// Everything needed to replay an invocation of a plugin.
+///
+/// Created by [`Plugin::invoke_recorded`](crate::Plugin::invoke_recorded)
+/// and replayed by [`Plugin::invoke_replayed`](crate::Plugin::invoke_replayed).
+#[derive(Serialize, Deserialize)]
+pub struct InvocationRecording {
+    /// The seed of the random numbers the plugin read.
+    pub seed: u64,
+    /// Nanoseconds since the UNIX epoch the clocks of the plugin started at.
+    pub epoch_nanos: u64,
+    /// The requests the plugin was invoked with.
+    pub requests: Vec<Request>,
+    /// The host calls the plugin made in order.
+    pub calls: Vec<InvocationCall>,
+    /// The responses of the plugin.
+    pub responses: Vec<Response>,
+    /// Why the invocation failed, the responses are empty then.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl InvocationRecording {
+    /// Loads a recording written by [`write_to_path`](Self::write_to_path).
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<InvocationRecording, HostError> {
+        let bytes = fs::read(path).map_err(|err| HostError::RecordingFailed(err.into()))?;
+        InvocationRecording::from_bytes(&bytes)
+    }
+
+    /// Loads a recording from its CBOR encoding.
+    pub fn from_bytes(bytes: &[u8]) -> Result<InvocationRecording, HostError> {
+        ciborium::de::from_reader(bytes)
+            .map_err(|err| HostError::RecordingFailed(anyhow::anyhow!("{}", err)))
+    }
+
+    /// Writes the recording to a file.
+    pub fn write_to_path<P: AsRef<Path>>(&self, path: P) -> Result<(), HostError> {
+        fs::write(path, self.to_bytes()?).map_err(|err| HostError::RecordingFailed(err.into()))
+    }
+
+    /// Encodes the recording as CBOR.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, HostError> {
+        let mut rv = Vec::new();
+        ciborium::ser::into_writer(self, &mut rv)
+            .map_err(|err| HostError::RecordingFailed(anyhow::anyhow!("{}", err)))?;
+        Ok(rv)
+    }
+
+    /// Returns the time the clocks of the plugin started at.
+    pub fn epoch(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_nanos(self.epoch_nanos)
+    }
+}
+
+/// Records the host calls of an invocation.
+///
+/// Before every call the clock of the plugin is moved to the real time that
+/// passed since the invocation started, so time only moves in the plugin
+/// when it calls the host.
+pub(crate) struct InvocationRecorder {
+    inner: Arc<dyn Transport>,
+    clock: VirtualClock,
+    started: Instant,
+    // responses are kept serialized as bridge errors are not thread safe
+    calls: Mutex<Vec<PendingCall>>,
+}
+
+struct PendingCall {
+    elapsed_nanos: u64,
+    request: Request,
+    // a response that fails to serialize fails the recording later
+    response: Result<Vec<u8>, String>,
+}
+
+impl InvocationRecorder {
+    pub fn new(inner: Arc<dyn Transport>, clock: VirtualClock) -> InvocationRecorder {
+        InvocationRecorder {
+            inner,
+            clock,
+            started: Instant::now(),
+            calls: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the calls recorded so far.
+    pub fn calls(&self) -> Result<Vec<InvocationCall>, HostError> {
+        self.calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|call| {
+                let response = call
+                    .response
+                    .as_ref()
+                    .map_err(|err| HostError::RecordingFailed(anyhow::anyhow!("{}", err)))?;
+                Ok(InvocationCall {
+                    elapsed_nanos: call.elapsed_nanos,
+                    request: call.request.clone(),
+                    response: Response::deserialize(response).map_err(HostError::ProtocolError)?,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Transport for InvocationRecorder {
+    fn handle(&self, req: &Request) -> Response {
+        let elapsed = self.started.elapsed();
+        self.clock.advance_to(elapsed);
+        let response = self.inner.handle(req);
+        self.calls.lock().unwrap().push(PendingCall {
+            elapsed_nanos: elapsed.as_nanos() as u64,
+            request: req.clone(),
+            response: response.serialize().map_err(|err| err.to_string()),
+        });
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use worthless_bridge::Value;
+
+    struct Echo;
+
+    impl Transport for Echo {
+        fn handle(&self, req: &Request) -> Response {
+            Response::builder()
+                .raw_payload(req.payload().clone())
+                .build()
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn text(response: Response) -> Option<String> {
+        response.into_payload().ok()?.as_text().map(str::to_string)
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let buffer = Buffer::default();
+        let recording = RecordingTransport::new(Arc::new(Echo), buffer.clone());
+        let response = recording.handle(&Request::new("echo", "first"));
+        assert_eq!(text(response).as_deref(), Some("first"));
+        let invocation = RecordedInvocation {
+            timestamp: now(),
+            requests: vec![Request::new("run", "input")],
+            responses: vec![Response::builder().payload(&"output").unwrap().build()],
+        };
+        recording.record(EntryRef::Invocation(&invocation)).unwrap();
+        recording.handle(&Request::new("echo", "second"));
+        recording.check().unwrap();
+
+        let replay = ReplayTransport::from_bytes(&buffer.0.lock().unwrap()).unwrap();
+        assert_eq!(replay.remaining(), 2);
+        let invocations = replay.invocations().unwrap();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].requests[0].endpoint(), "run");
+        let response = invocations.into_iter().next().unwrap().responses.pop();
+        assert_eq!(response.and_then(text).as_deref(), Some("output"));
+        let response = replay.handle(&Request::new("echo", "other"));
+        assert_eq!(text(response).as_deref(), Some("first"));
+        let response = replay.handle(&Request::new("echo", "other"));
+        assert_eq!(text(response).as_deref(), Some("second"));
+    }
+
+    #[test]
+    fn test_recording_error() {
+        let recording = RecordingTransport::new(Arc::new(Echo), Broken);
+        // the plugin still sees the response of the host
+        let response = recording.handle(&Request::new("echo", "first"));
+        assert_eq!(text(response).as_deref(), Some("first"));
+        recording.handle(&Request::new("echo", "second"));
+        assert!(matches!(
+            recording.check(),
+            Err(HostError::RecordingFailed(_))
+        ));
+        assert!(recording.check().is_ok());
+    }
+
+    #[test]
+    fn test_replay_invocation() {
+        let recorder = InvocationRecorder::new(Arc::new(Echo), VirtualClock::default());
+        for payload in ["first", "second"] {
+            recorder.handle(&Request::new("echo", payload));
+        }
+        let recording = InvocationRecording {
+            seed: 42,
+            epoch_nanos: 0,
+            requests: vec![Request::new("run", Value::Null)],
+            calls: recorder.calls().unwrap(),
+            responses: Vec::new(),
+            error: Some("plugin trapped".into()),
+        };
+        let recording = InvocationRecording::from_bytes(&recording.to_bytes().unwrap()).unwrap();
+        assert_eq!(recording.error.as_deref(), Some("plugin trapped"));
+        assert_eq!(recording.calls.len(), 2);
+
+        let clock = VirtualClock::default();
+        let replay = ReplayTransport::for_invocation(&recording, clock.clone()).unwrap();
+        let response = replay.handle(&Request::new("echo", "other"));
+        assert_eq!(text(response).as_deref(), Some("first"));
+        assert_eq!(
+            clock.elapsed(),
+            Duration::from_nanos(recording.calls[0].elapsed_nanos)
+        );
+        assert!(replay
+            .handle(&Request::new("delete", "second"))
+            .into_payload()
+            .is_err());
+        assert_eq!(replay.remaining(), 1);
+        let response = replay.handle(&Request::new("echo", "second"));
+        assert_eq!(text(response).as_deref(), Some("second"));
+        assert!(replay
+            .handle(&Request::new("echo", "third"))
+            .into_payload()
+            .is_err());
+    }
+}
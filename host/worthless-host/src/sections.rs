@@ -0,0 +1,788 @@
+//! Reading and writing sections of WASM modules.
+//!
+//! Plugins carry data for the host such as bundles in custom sections.  Only
+//! the outer structure of the module and the imports and exports are parsed
+//! here, everything else is left to wasmtime.
+
+use std::io;
+
+const WASM_MAGIC: &[u8] = b"\0asm";
+const CUSTOM_SECTION_ID: u8 = 0;
+const IMPORT_SECTION_ID: u8 = 2;
+const TABLE_SECTION_ID: u8 = 4;
+const MEMORY_SECTION_ID: u8 = 5;
+const GLOBAL_SECTION_ID: u8 = 6;
+const EXPORT_SECTION_ID: u8 = 7;
+const START_SECTION_ID: u8 = 8;
+const DATA_SECTION_ID: u8 = 11;
+const DATA_COUNT_SECTION_ID: u8 = 12;
+const PAGE_SIZE: usize = 65536;
+
+/// The kind of an import or export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExternKind {
+    Func,
+    Table,
+    Memory,
+    Global,
+    Tag,
+}
+
+impl ExternKind {
+    fn from_byte(byte: u8) -> io::Result<ExternKind> {
+        Ok(match byte {
+            0 => ExternKind::Func,
+            1 => ExternKind::Table,
+            2 => ExternKind::Memory,
+            3 => ExternKind::Global,
+            4 => ExternKind::Tag,
+            _ => return Err(invalid("invalid external kind")),
+        })
+    }
+}
+
+/// An import of a module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import<'a> {
+    pub module: &'a str,
+    pub name: &'a str,
+    pub kind: ExternKind,
+}
+
+/// An export of a module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export<'a> {
+    pub name: &'a str,
+    pub kind: ExternKind,
+}
+
+/// The initial and maximum size of a memory or table.
+///
+/// Memories are measured in pages, tables in elements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub minimum: u64,
+    pub maximum: Option<u64>,
+}
+
+/// The value of a global of a module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlobalValue {
+    I32(i32),
+    I64(i64),
+    /// The bits of an `f32`.
+    F32(u32),
+    /// The bits of an `f64`.
+    F64(u64),
+    V128(u128),
+}
+
+/// Returns all sections of a module as `(id, contents)`.
+fn sections(module: &[u8]) -> io::Result<Vec<(u8, &[u8])>> {
+    if module.len() < 8 || &module[..4] != WASM_MAGIC {
+        return Err(invalid("not a WASM module"));
+    }
+    let mut rest = &module[8..];
+    let mut rv = Vec::new();
+    while !rest.is_empty() {
+        let id = rest[0];
+        rest = &rest[1..];
+        let size = read_leb128(&mut rest)? as usize;
+        if size > rest.len() {
+            return Err(invalid("truncated section"));
+        }
+        let (section, tail) = rest.split_at(size);
+        rest = tail;
+        rv.push((id, section));
+    }
+    Ok(rv)
+}
+
+/// Returns the imports of a module.
+pub fn imports(module: &[u8]) -> io::Result<Vec<Import<'_>>> {
+    let mut rv = Vec::new();
+    for (_, section) in sections(module)?
+        .into_iter()
+        .filter(|(id, _)| *id == IMPORT_SECTION_ID)
+    {
+        rv.extend(read_imports(section)?.into_iter().map(|(import, _)| import));
+    }
+    Ok(rv)
+}
+
+/// Returns the imports in an import section with the limits of tables and
+/// memories.
+fn read_imports(mut section: &[u8]) -> io::Result<Vec<(Import<'_>, Option<Limits>)>> {
+    let mut rv = Vec::new();
+    for _ in 0..read_leb128(&mut section)? {
+        let module = read_name(&mut section)?;
+        let name = read_name(&mut section)?;
+        let kind = ExternKind::from_byte(read_byte(&mut section)?)?;
+        let mut limits = None;
+        match kind {
+            ExternKind::Func => {
+                read_leb128(&mut section)?;
+            }
+            ExternKind::Table => {
+                read_byte(&mut section)?;
+                limits = Some(read_limits(&mut section)?);
+            }
+            ExternKind::Memory => limits = Some(read_limits(&mut section)?),
+            ExternKind::Global => {
+                read_byte(&mut section)?;
+                read_byte(&mut section)?;
+            }
+            ExternKind::Tag => {
+                read_byte(&mut section)?;
+                read_leb128(&mut section)?;
+            }
+        }
+        rv.push((Import { module, name, kind }, limits));
+    }
+    Ok(rv)
+}
+
+/// Returns the limits of the memories of a module, imported ones first.
+pub fn memories(module: &[u8]) -> io::Result<Vec<Limits>> {
+    limits(module, ExternKind::Memory)
+}
+
+/// Returns the limits of the tables of a module, imported ones first.
+pub fn tables(module: &[u8]) -> io::Result<Vec<Limits>> {
+    limits(module, ExternKind::Table)
+}
+
+fn limits(module: &[u8], kind: ExternKind) -> io::Result<Vec<Limits>> {
+    let mut rv = Vec::new();
+    for (id, mut section) in sections(module)? {
+        match (id, kind) {
+            (IMPORT_SECTION_ID, _) => rv.extend(
+                read_imports(section)?
+                    .into_iter()
+                    .filter(|(import, _)| import.kind == kind)
+                    .filter_map(|(_, limits)| limits),
+            ),
+            (TABLE_SECTION_ID, ExternKind::Table) => {
+                for _ in 0..read_leb128(&mut section)? {
+                    read_byte(&mut section)?;
+                    rv.push(read_limits(&mut section)?);
+                }
+            }
+            (MEMORY_SECTION_ID, ExternKind::Memory) => {
+                for _ in 0..read_leb128(&mut section)? {
+                    rv.push(read_limits(&mut section)?);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(rv)
+}
+
+/// Returns the exports of a module.
+pub fn exports(module: &[u8]) -> io::Result<Vec<Export<'_>>> {
+    let mut rv = Vec::new();
+    for (_, section) in sections(module)?
+        .into_iter()
+        .filter(|(id, _)| *id == EXPORT_SECTION_ID)
+    {
+        rv.extend(
+            read_exports(section)?
+                .into_iter()
+                .map(|(export, _, _)| export),
+        );
+    }
+    Ok(rv)
+}
+
+/// Returns the exports in an export section with their index and encoding.
+fn read_exports(mut section: &[u8]) -> io::Result<Vec<(Export<'_>, u32, &[u8])>> {
+    let mut rv = Vec::new();
+    for _ in 0..read_leb128(&mut section)? {
+        let start = section;
+        let name = read_name(&mut section)?;
+        let kind = ExternKind::from_byte(read_byte(&mut section)?)?;
+        let index = read_leb128(&mut section)? as u32;
+        let encoded = &start[..start.len() - section.len()];
+        rv.push((Export { name, kind }, index, encoded));
+    }
+    Ok(rv)
+}
+
+/// Returns the custom sections of a module as `(name, payload)`.
+pub fn custom_sections(module: &[u8]) -> io::Result<Vec<(&str, &[u8])>> {
+    let mut rv = Vec::new();
+    for (_, mut section) in sections(module)?
+        .into_iter()
+        .filter(|(id, _)| *id == CUSTOM_SECTION_ID)
+    {
+        let name = read_name(&mut section)?;
+        rv.push((name, section));
+    }
+    Ok(rv)
+}
+
+/// Returns the payload of the first custom section with the given name.
+pub fn find_custom_section<'a>(module: &'a [u8], name: &str) -> io::Result<Option<&'a [u8]>> {
+    Ok(custom_sections(module)?
+        .into_iter()
+        .find(|(section_name, _)| *section_name == name)
+        .map(|(_, payload)| payload))
+}
+
+/// Appends a custom section to the end of a module.
+pub fn append_custom_section(module: &mut Vec<u8>, name: &str, payload: &[u8]) {
+    let mut name_len = Vec::new();
+    write_leb128(&mut name_len, name.len() as u64);
+    module.push(CUSTOM_SECTION_ID);
+    write_leb128(module, (name_len.len() + name.len() + payload.len()) as u64);
+    module.extend_from_slice(&name_len);
+    module.extend_from_slice(name.as_bytes());
+    module.extend_from_slice(payload);
+}
+
+/// Rewrites a module so that modules with the same contents are equal bytes.
+///
+/// Debug info sections (`.debug_*`) are dropped as they record paths of the
+/// build machine.  The remaining custom sections move behind all other
+/// sections sorted by name, the order of everything else is kept.
+pub fn canonicalize(module: &[u8]) -> io::Result<Vec<u8>> {
+    let mut other = Vec::new();
+    let mut custom = Vec::new();
+    for (id, contents) in sections(module)? {
+        if id != CUSTOM_SECTION_ID {
+            other.push((id, contents));
+            continue;
+        }
+        let mut payload = contents;
+        let name = read_name(&mut payload)?;
+        if !name.starts_with(".debug_") {
+            custom.push((name, contents));
+        }
+    }
+    custom.sort_by_key(|(name, _)| *name);
+
+    let mut rv = Vec::with_capacity(module.len());
+    rv.extend_from_slice(&module[..8]);
+    let custom = custom
+        .into_iter()
+        .map(|(_, contents)| (CUSTOM_SECTION_ID, contents));
+    for (id, contents) in other.into_iter().chain(custom) {
+        rv.push(id);
+        write_leb128(&mut rv, contents.len() as u64);
+        rv.extend_from_slice(contents);
+    }
+    Ok(rv)
+}
+
+/// Rewrites a module so that its instances start out with the given memory
+/// and globals.
+///
+/// Memories and globals are looked up by their export name and must be
+/// defined by the module.  The active data segments become passive as the
+/// memory contents replace them.  The start function and `_initialize` are
+/// removed since their effects are part of the state already.  wasmtime maps
+/// the memory contents of the result copy-on-write when instantiating it, so
+/// a fresh instance in this state is cheap no matter the size of the memory.
+pub fn preinitialize(
+    module: &[u8],
+    memories: &[(&str, &[u8])],
+    globals: &[(&str, GlobalValue)],
+) -> io::Result<Vec<u8>> {
+    let sections = sections(module)?;
+    let mut imported_memories = 0;
+    let mut imported_globals = 0;
+    for import in imports(module)? {
+        match import.kind {
+            ExternKind::Memory => imported_memories += 1,
+            ExternKind::Global => imported_globals += 1,
+            _ => {}
+        }
+    }
+    let exports = match sections.iter().find(|(id, _)| *id == EXPORT_SECTION_ID) {
+        Some((_, section)) => read_exports(section)?,
+        None => Vec::new(),
+    };
+    let defined = |name: &str, kind: ExternKind, imported: u32| {
+        let index = exports
+            .iter()
+            .find(|(export, _, _)| export.name == name && export.kind == kind)
+            .map(|(_, index, _)| *index)
+            .ok_or_else(|| invalid("no such export"))?;
+        index
+            .checked_sub(imported)
+            .ok_or_else(|| invalid("imports cannot be preinitialized"))
+    };
+    let memories = memories
+        .iter()
+        .map(|(name, data)| Ok((defined(name, ExternKind::Memory, imported_memories)?, *data)))
+        .collect::<io::Result<Vec<_>>>()?;
+    let globals = globals
+        .iter()
+        .map(|(name, value)| Ok((defined(name, ExternKind::Global, imported_globals)?, *value)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut images = Vec::new();
+    for (index, data) in &memories {
+        append_memory_image(&mut images, *index, data);
+    }
+    let image_segments = images.len() as u64;
+    let mut rewritten = Vec::new();
+    let mut has_data = false;
+    for (id, contents) in &sections {
+        let contents = match *id {
+            MEMORY_SECTION_ID => rewrite_memories(contents, &memories)?,
+            GLOBAL_SECTION_ID => rewrite_globals(contents, &globals)?,
+            EXPORT_SECTION_ID => {
+                let mut rv = Vec::new();
+                let kept: Vec<_> = exports
+                    .iter()
+                    .filter(|(export, _, _)| export.name != "_initialize")
+                    .collect();
+                write_leb128(&mut rv, kept.len() as u64);
+                for (_, _, encoded) in kept {
+                    rv.extend_from_slice(encoded);
+                }
+                rv
+            }
+            START_SECTION_ID => continue,
+            DATA_SECTION_ID => {
+                has_data = true;
+                rewrite_data(contents, &images)?
+            }
+            DATA_COUNT_SECTION_ID => {
+                let mut count = *contents;
+                let count = read_leb128(&mut count)? + image_segments;
+                let mut rv = Vec::new();
+                write_leb128(&mut rv, count);
+                rv
+            }
+            _ => contents.to_vec(),
+        };
+        rewritten.push((*id, contents));
+    }
+    if !has_data && !images.is_empty() {
+        // the data section is the last of the known sections
+        let position = rewritten
+            .iter()
+            .rposition(|(id, _)| *id != CUSTOM_SECTION_ID)
+            .map_or(rewritten.len(), |x| x + 1);
+        rewritten.insert(position, (DATA_SECTION_ID, rewrite_data(&[0], &images)?));
+    }
+
+    let mut rv = Vec::with_capacity(module.len());
+    rv.extend_from_slice(&module[..8]);
+    for (id, contents) in rewritten {
+        rv.push(id);
+        write_leb128(&mut rv, contents.len() as u64);
+        rv.extend_from_slice(&contents);
+    }
+    Ok(rv)
+}
+
+/// Appends an active data segment for every run of pages that are not zero.
+fn append_memory_image(segments: &mut Vec<Vec<u8>>, memory: u32, data: &[u8]) {
+    let mut pages = data.chunks(PAGE_SIZE).enumerate().peekable();
+    while let Some((start, page)) = pages.next() {
+        if page.iter().all(|&x| x == 0) {
+            continue;
+        }
+        let mut end = start + 1;
+        while let Some((index, _)) = pages.next_if(|(_, page)| page.iter().any(|&x| x != 0)) {
+            end = index + 1;
+        }
+        let bytes = &data[start * PAGE_SIZE..(end * PAGE_SIZE).min(data.len())];
+        let mut segment = Vec::with_capacity(bytes.len() + 16);
+        if memory == 0 {
+            segment.push(0);
+        } else {
+            segment.push(2);
+            write_leb128(&mut segment, memory.into());
+        }
+        segment.push(0x41);
+        write_sleb128(&mut segment, (start * PAGE_SIZE) as u32 as i32 as i64);
+        segment.push(0x0b);
+        write_leb128(&mut segment, bytes.len() as u64);
+        segment.extend_from_slice(bytes);
+        segments.push(segment);
+    }
+}
+
+/// Raises the initial size of memories to fit their contents.
+fn rewrite_memories(mut section: &[u8], memories: &[(u32, &[u8])]) -> io::Result<Vec<u8>> {
+    let count = read_leb128(&mut section)?;
+    let mut rv = Vec::with_capacity(section.len() + 8);
+    write_leb128(&mut rv, count);
+    for index in 0..count as u32 {
+        let flags = read_byte(&mut section)?;
+        let mut minimum = read_leb128(&mut section)?;
+        if let Some((_, data)) = memories.iter().find(|(x, _)| *x == index) {
+            if flags & 0x04 != 0 {
+                return Err(invalid("64-bit memories cannot be preinitialized"));
+            }
+            minimum = minimum.max(((data.len() + PAGE_SIZE - 1) / PAGE_SIZE) as u64);
+        }
+        rv.push(flags);
+        write_leb128(&mut rv, minimum);
+        if flags & 1 != 0 {
+            write_leb128(&mut rv, read_leb128(&mut section)?);
+        }
+    }
+    Ok(rv)
+}
+
+/// Replaces the initializers of globals with constants.
+fn rewrite_globals(mut section: &[u8], globals: &[(u32, GlobalValue)]) -> io::Result<Vec<u8>> {
+    let count = read_leb128(&mut section)?;
+    let mut rv = Vec::with_capacity(section.len());
+    write_leb128(&mut rv, count);
+    for index in 0..count as u32 {
+        rv.push(read_byte(&mut section)?);
+        rv.push(read_byte(&mut section)?);
+        let start = section;
+        skip_const_expr(&mut section)?;
+        match globals.iter().find(|(x, _)| *x == index) {
+            Some((_, value)) => write_const_expr(&mut rv, *value),
+            None => rv.extend_from_slice(&start[..start.len() - section.len()]),
+        }
+    }
+    Ok(rv)
+}
+
+/// Turns the active data segments passive and appends the memory images.
+///
+/// The segments keep their index so `memory.init` and `data.drop` still
+/// refer to the right ones.
+fn rewrite_data(mut section: &[u8], images: &[Vec<u8>]) -> io::Result<Vec<u8>> {
+    let count = read_leb128(&mut section)?;
+    let mut rv = Vec::new();
+    write_leb128(&mut rv, count + images.len() as u64);
+    for _ in 0..count {
+        match read_leb128(&mut section)? {
+            0 => skip_const_expr(&mut section)?,
+            1 => {}
+            2 => {
+                read_leb128(&mut section)?;
+                skip_const_expr(&mut section)?;
+            }
+            _ => return Err(invalid("invalid data segment")),
+        }
+        let len = read_leb128(&mut section)? as usize;
+        if len > section.len() {
+            return Err(invalid("truncated data segment"));
+        }
+        let (bytes, rest) = section.split_at(len);
+        section = rest;
+        rv.push(1);
+        write_leb128(&mut rv, len as u64);
+        rv.extend_from_slice(bytes);
+    }
+    for image in images {
+        rv.extend_from_slice(image);
+    }
+    Ok(rv)
+}
+
+fn skip_const_expr(buf: &mut &[u8]) -> io::Result<()> {
+    loop {
+        match read_byte(buf)? {
+            0x0b => return Ok(()),
+            // i32.const, i64.const
+            0x41 | 0x42 => {
+                read_leb128(buf)?;
+            }
+            // f32.const, f64.const
+            0x43 => skip_bytes(buf, 4)?,
+            0x44 => skip_bytes(buf, 8)?,
+            // global.get, ref.func
+            0x23 | 0xd2 => {
+                read_leb128(buf)?;
+            }
+            // ref.null
+            0xd0 => {
+                read_byte(buf)?;
+            }
+            // v128.const
+            0xfd if read_leb128(buf)? == 12 => skip_bytes(buf, 16)?,
+            // extended constant arithmetic
+            0x6a | 0x6b | 0x6c | 0x7c | 0x7d | 0x7e => {}
+            _ => return Err(invalid("unsupported constant expression")),
+        }
+    }
+}
+
+fn write_const_expr(buf: &mut Vec<u8>, value: GlobalValue) {
+    match value {
+        GlobalValue::I32(x) => {
+            buf.push(0x41);
+            write_sleb128(buf, x.into());
+        }
+        GlobalValue::I64(x) => {
+            buf.push(0x42);
+            write_sleb128(buf, x);
+        }
+        GlobalValue::F32(bits) => {
+            buf.push(0x43);
+            buf.extend_from_slice(&bits.to_le_bytes());
+        }
+        GlobalValue::F64(bits) => {
+            buf.push(0x44);
+            buf.extend_from_slice(&bits.to_le_bytes());
+        }
+        GlobalValue::V128(bits) => {
+            buf.extend_from_slice(&[0xfd, 12]);
+            buf.extend_from_slice(&bits.to_le_bytes());
+        }
+    }
+    buf.push(0x0b);
+}
+
+fn skip_bytes(buf: &mut &[u8], len: usize) -> io::Result<()> {
+    if len > buf.len() {
+        return Err(invalid("unexpected end of section"));
+    }
+    *buf = &buf[len..];
+    Ok(())
+}
+
+fn read_byte(buf: &mut &[u8]) -> io::Result<u8> {
+    let (byte, rest) = buf
+        .split_first()
+        .ok_or_else(|| invalid("unexpected end of section"))?;
+    *buf = rest;
+    Ok(*byte)
+}
+
+fn read_name<'a>(buf: &mut &'a [u8]) -> io::Result<&'a str> {
+    let len = read_leb128(buf)? as usize;
+    if len > buf.len() {
+        return Err(invalid("truncated name"));
+    }
+    let (name, rest) = buf.split_at(len);
+    *buf = rest;
+    std::str::from_utf8(name).map_err(|_| invalid("invalid name"))
+}
+
+fn read_limits(buf: &mut &[u8]) -> io::Result<Limits> {
+    let flags = read_byte(buf)?;
+    let minimum = read_leb128(buf)?;
+    let maximum = match flags & 1 {
+        0 => None,
+        _ => Some(read_leb128(buf)?),
+    };
+    Ok(Limits { minimum, maximum })
+}
+
+fn read_leb128(buf: &mut &[u8]) -> io::Result<u64> {
+    let mut rv = 0u64;
+    let mut shift = 0;
+    loop {
+        let (byte, rest) = buf
+            .split_first()
+            .ok_or_else(|| invalid("truncated integer"))?;
+        *buf = rest;
+        rv |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(rv);
+        }
+        shift += 7;
+        if shift >= 64 {
+            return Err(invalid("integer too large"));
+        }
+    }
+}
+
+fn write_leb128(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn write_sleb128(buf: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasmparser::{DataKind, Operator, Parser, Payload};
+
+    const MODULE: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (global $counter (export "counter") (mut i32) (i32.const 0))
+            (global $base (mut i64) (i64.const 7))
+            (data (i32.const 16) "initial")
+            (data $passive "passive")
+            (func $init (global.set $counter (i32.const 1)))
+            (func (export "run") (memory.init $passive (i32.const 0) (i32.const 0) (i32.const 7)))
+            (export "_initialize" (func $init))
+            (start $init))
+    "#;
+
+    fn offset(kind: &DataKind<'_>) -> Option<i32> {
+        match kind {
+            DataKind::Active { offset_expr, .. } => {
+                match offset_expr.get_operators_reader().read().unwrap() {
+                    Operator::I32Const { value } => Some(value),
+                    _ => None,
+                }
+            }
+            DataKind::Passive => None,
+        }
+    }
+
+    #[test]
+    fn test_preinitialize() {
+        let module = wat::parse_str(MODULE).unwrap();
+        let mut memory = vec![0; 3 * PAGE_SIZE];
+        memory[10] = 1;
+        memory[2 * PAGE_SIZE + 5] = 2;
+        let rv = preinitialize(
+            &module,
+            &[("memory", &memory)],
+            &[("counter", GlobalValue::I32(-42))],
+        )
+        .unwrap();
+        wasmparser::validate(&rv).unwrap();
+
+        let names: Vec<_> = exports(&rv).unwrap().iter().map(|x| x.name).collect();
+        assert_eq!(names, ["memory", "counter", "run"]);
+
+        let mut segments = Vec::new();
+        let mut initial_pages = None;
+        let mut globals = Vec::new();
+        for payload in Parser::new(0).parse_all(&rv) {
+            match payload.unwrap() {
+                Payload::StartSection { .. } => panic!("start function was kept"),
+                Payload::MemorySection(reader) => {
+                    initial_pages = Some(reader.into_iter().next().unwrap().unwrap().initial);
+                }
+                Payload::GlobalSection(reader) => {
+                    for global in reader {
+                        let mut ops = global.unwrap().init_expr.get_operators_reader();
+                        globals.push(format!("{:?}", ops.read().unwrap()));
+                    }
+                }
+                Payload::DataSection(reader) => {
+                    for data in reader {
+                        let data = data.unwrap();
+                        segments.push((offset(&data.kind), data.data.len()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(initial_pages, Some(3));
+        assert_eq!(
+            globals,
+            ["I32Const { value: -42 }", "I64Const { value: 7 }"]
+        );
+        assert_eq!(
+            segments,
+            [
+                (None, 7),
+                (None, 7),
+                (Some(0), PAGE_SIZE),
+                (Some(2 * PAGE_SIZE as i32), PAGE_SIZE)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_preinitialize_adds_data_section() {
+        let module =
+            wat::parse_str(r#"(module (memory (export "memory") 1) (@custom "x" "y"))"#).unwrap();
+        let rv = preinitialize(&module, &[("memory", &[1, 2, 3])], &[]).unwrap();
+        wasmparser::validate(&rv).unwrap();
+        let ids: Vec<_> = sections(&rv).unwrap().iter().map(|(id, _)| *id).collect();
+        assert_eq!(
+            ids,
+            [MEMORY_SECTION_ID, EXPORT_SECTION_ID, DATA_SECTION_ID, 0]
+        );
+    }
+
+    #[test]
+    fn test_preinitialize_rejects_imports() {
+        let module = wat::parse_str(
+            r#"(module (import "env" "memory" (memory 1)) (export "memory" (memory 0)))"#,
+        )
+        .unwrap();
+        assert!(preinitialize(&module, &[("memory", &[1])], &[]).is_err());
+        assert!(preinitialize(&module, &[("missing", &[1])], &[]).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let module = wat::parse_str(
+            r#"(module
+                (import "env" "memory" (memory 1 8))
+                (import "env" "table" (table 2 funcref))
+                (memory 3)
+                (table 4 16 funcref))"#,
+        )
+        .unwrap();
+        let limits = |minimum, maximum| Limits { minimum, maximum };
+        assert_eq!(
+            memories(&module).unwrap(),
+            [limits(1, Some(8)), limits(3, None)]
+        );
+        assert_eq!(
+            tables(&module).unwrap(),
+            [limits(2, None), limits(4, Some(16))]
+        );
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let module = wat::parse_str(MODULE).unwrap();
+        let sections = [
+            ("worthless.b", &b"second"[..]),
+            (".debug_info", b"/home/me/src"),
+            ("producers", b"\0"),
+            ("worthless.a", b"first"),
+        ];
+        let build = |order: &[usize]| {
+            let mut module = module.clone();
+            for &i in order {
+                append_custom_section(&mut module, sections[i].0, sections[i].1);
+            }
+            module
+        };
+
+        let rv = canonicalize(&build(&[0, 1, 2, 3])).unwrap();
+        wasmparser::validate(&rv).unwrap();
+        let names: Vec<_> = custom_sections(&rv)
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, ["name", "producers", "worthless.a", "worthless.b"]);
+
+        // canonical modules stay the same and the order of custom sections
+        // does not matter
+        assert_eq!(canonicalize(&rv).unwrap(), rv);
+        for order in [[3, 2, 1, 0], [1, 3, 0, 2], [2, 0, 3, 1]] {
+            assert_eq!(canonicalize(&build(&order)).unwrap(), rv);
+        }
+    }
+}
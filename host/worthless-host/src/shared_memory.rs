@@ -0,0 +1,93 @@
+//! Passes batches of requests through the linear memory of a plugin.
+use anyhow::anyhow;
+use wasmtime::{Linker, Memory, Store, TypedFunc};
+use wasmtime_wasi::WasiCtx;
+use worthless_bridge::Request;
+
+/// The exports of a plugin that handle requests in its memory.
+///
+/// The host allocates a buffer in the plugin, serializes the requests into
+/// it and the plugin returns the responses in another buffer which the host
+/// deserializes in place and then frees.  This saves a copy of the payloads
+/// in each direction compared to the pipes.
+pub(crate) struct SharedMemory {
+    memory: Memory,
+    alloc: TypedFunc<u32, u32>,
+    free: TypedFunc<(u32, u32), ()>,
+    handle: TypedFunc<(u32, u32), u64>,
+}
+
+impl SharedMemory {
+    /// Looks up the exports, returns `None` if the plugin lacks any of them.
+    pub fn lookup(store: &mut Store<WasiCtx>, linker: &Linker<WasiCtx>) -> Option<SharedMemory> {
+        let mut func = |name: &str| linker.get(&mut *store, "plugin", name)?.into_func();
+        let (alloc, free, handle) = (
+            func("worthless_alloc")?,
+            func("worthless_free")?,
+            func("worthless_handle_shared")?,
+        );
+        Some(SharedMemory {
+            memory: linker.get(&mut *store, "plugin", "memory")?.into_memory()?,
+            alloc: alloc.typed(&*store).ok()?,
+            free: free.typed(&*store).ok()?,
+            handle: handle.typed(&*store).ok()?,
+        })
+    }
+
+    /// Serializes the requests into the plugin and handles them.
+    ///
+    /// `len` is the size of the serialized requests.  Returns the location
+    /// of the responses in the memory of the plugin.
+    pub fn call(
+        &self,
+        store: &mut Store<WasiCtx>,
+        reqs: &[Request],
+        len: usize,
+    ) -> anyhow::Result<(u32, u32)> {
+        let len = u32::try_from(len).map_err(|_| anyhow!("requests are too large"))?;
+        let ptr = self.alloc.call(&mut *store, len)?;
+        if ptr == 0 && len > 0 {
+            return Err(anyhow!("plugin failed to allocate {} bytes", len));
+        }
+        if let Err(err) = self.write(store, reqs, (ptr, len)) {
+            self.free(store, (ptr, len))?;
+            return Err(err);
+        }
+        // the plugin takes ownership of the buffer with the requests
+        let rv = self.handle.call(&mut *store, (ptr, len))?;
+        Ok(((rv >> 32) as u32, rv as u32))
+    }
+
+    fn write(
+        &self,
+        store: &mut Store<WasiCtx>,
+        reqs: &[Request],
+        (ptr, len): (u32, u32),
+    ) -> anyhow::Result<()> {
+        let mut buf = self
+            .memory
+            .data_mut(&mut *store)
+            .get_mut(ptr as usize..ptr as usize + len as usize)
+            .ok_or_else(|| anyhow!("plugin allocated a buffer out of bounds"))?;
+        for req in reqs {
+            req.serialize_to(&mut buf)?;
+        }
+        if !buf.is_empty() {
+            return Err(anyhow!("requests are smaller than announced"));
+        }
+        Ok(())
+    }
+
+    /// Returns the responses written by [`call`](Self::call).
+    pub fn responses<'a>(&self, store: &'a Store<WasiCtx>, (ptr, len): (u32, u32)) -> &'a [u8] {
+        self.memory
+            .data(store)
+            .get(ptr as usize..ptr as usize + len as usize)
+            .unwrap_or_default()
+    }
+
+    /// Frees the responses once they were read.
+    pub fn free(&self, store: &mut Store<WasiCtx>, (ptr, len): (u32, u32)) -> anyhow::Result<()> {
+        self.free.call(&mut *store, (ptr, len))
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use anyhow::anyhow;
+use wasmtime::{ExternType, Linker, Module, Mutability, Store, Val};
+use wasmtime_wasi::WasiCtx;
+
+use crate::error::HostError;
+use crate::sections::{self, GlobalValue};
+
+/// The bytes of the module a plugin was created from.
+pub(crate) struct ModuleBytes {
+    hash: u64,
+    bytes: Box<[u8]>,
+}
+
+impl ModuleBytes {
+    pub(crate) fn new(bytes: &[u8]) -> ModuleBytes {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        ModuleBytes {
+            hash: hasher.finish(),
+            bytes: bytes.into(),
+        }
+    }
+}
+
+/// The linear memory and globals of a plugin at one point in time.
+///
+/// A snapshot covers the exported memories and the exported mutable globals
+/// of the plugin.  The shadow stack pointer is not exported but it is back at
+/// its initial value whenever the plugin is not running.  A snapshot can be
+/// restored into every plugin created from the same module bytes.
+///
+/// The state is baked into a copy of the module which is instantiated anew
+/// on restore, so pages are only copied once the plugin writes to them.
+#[derive(Clone)]
+pub struct Snapshot {
+    module_hash: u64,
+    memory_size: usize,
+    image: Module,
+    topics: Vec<String>,
+}
+
+impl fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("module_hash", &self.module_hash)
+            .field("memory_size", &self.memory_size)
+            .field("topics", &self.topics)
+            .finish()
+    }
+}
+
+impl Snapshot {
+    /// Returns the size of the captured memory in bytes.
+    pub fn memory_size(&self) -> usize {
+        self.memory_size
+    }
+
+    /// Captures the state of the plugin and compiles it into a module.
+    pub(crate) fn capture(
+        store: &mut Store<WasiCtx>,
+        linker: &Linker<WasiCtx>,
+        module: &Module,
+        bytes: Option<&ModuleBytes>,
+        topics: Vec<String>,
+    ) -> Result<Snapshot, HostError> {
+        let bytes = bytes.ok_or_else(|| {
+            HostError::SnapshotFailed(anyhow!(
+                "plugins created from a compiled module cannot be snapshotted"
+            ))
+        })?;
+        let mut memories = Vec::new();
+        let mut globals = Vec::new();
+        for export in module.exports() {
+            let name = export.name();
+            match export.ty() {
+                ExternType::Memory(_) => {
+                    let memory = get_export(store, linker, name)?
+                        .into_memory()
+                        .ok_or_else(|| snapshot_error(name))?;
+                    memories.push((name, memory.data(&*store).to_vec()));
+                }
+                ExternType::Global(ty) if ty.mutability() == Mutability::Var => {
+                    let global = get_export(store, linker, name)?
+                        .into_global()
+                        .ok_or_else(|| snapshot_error(name))?;
+                    if let Some(value) = global_value(&global.get(&mut *store)) {
+                        globals.push((name, value));
+                    }
+                }
+                _ => {}
+            }
+        }
+        let memory_refs: Vec<_> = memories
+            .iter()
+            .map(|(name, data)| (*name, &data[..]))
+            .collect();
+        let image = sections::preinitialize(&bytes.bytes, &memory_refs, &globals)
+            .map_err(|err| HostError::SnapshotFailed(err.into()))?;
+        Ok(Snapshot {
+            module_hash: bytes.hash,
+            memory_size: memories.iter().map(|(_, data)| data.len()).sum(),
+            image: Module::new(module.engine(), image).map_err(HostError::SnapshotFailed)?,
+            topics,
+        })
+    }
+
+    /// Returns the topics the plugin was subscribed to when it was captured.
+    pub(crate) fn topics(&self) -> &[String] {
+        &self.topics
+    }
+
+    /// Returns the module to instantiate to restore the snapshot.
+    ///
+    /// Fails if the snapshot was taken of a plugin with different bytes.
+    pub(crate) fn image(&self, bytes: Option<&ModuleBytes>) -> Result<&Module, HostError> {
+        match bytes {
+            Some(bytes) if bytes.hash == self.module_hash => Ok(&self.image),
+            _ => Err(HostError::SnapshotFailed(anyhow!(
+                "snapshot was taken of a different module"
+            ))),
+        }
+    }
+}
+
+fn get_export(
+    store: &mut Store<WasiCtx>,
+    linker: &Linker<WasiCtx>,
+    name: &str,
+) -> Result<wasmtime::Extern, HostError> {
+    linker
+        .get(&mut *store, "plugin", name)
+        .ok_or_else(|| snapshot_error(name))
+}
+
+fn snapshot_error(name: &str) -> HostError {
+    HostError::SnapshotFailed(anyhow!("plugin has no matching export '{}'", name))
+}
+
+/// Converts numeric values, references cannot be written as constants.
+fn global_value(value: &Val) -> Option<GlobalValue> {
+    Some(match *value {
+        Val::I32(x) => GlobalValue::I32(x),
+        Val::I64(x) => GlobalValue::I64(x),
+        Val::F32(bits) => GlobalValue::F32(bits),
+        Val::F64(bits) => GlobalValue::F64(bits),
+        Val::V128(bits) => GlobalValue::V128(bits),
+        _ => return None,
+    })
+}
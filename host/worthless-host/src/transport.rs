@@ -0,0 +1,19 @@
+use worthless_bridge::{Request, Response};
+
+/// Carries the host calls of a plugin to their handler.
+///
+/// By default host calls are dispatched to the registered endpoints.  A
+/// transport set with [`Plugin::set_transport`](crate::Plugin::set_transport)
+/// can observe or replace them, eg: to record and replay the host behavior a
+/// plugin saw.
+pub trait Transport: Send + Sync {
+    /// Handles a single request of the guest.
+    fn handle(&self, req: &Request) -> Response;
+}
+
+#[cfg(feature = "testing")]
+impl Transport for worthless_bridge::testing::MockRouter {
+    fn handle(&self, req: &Request) -> Response {
+        worthless_bridge::testing::MockRouter::handle(self, req)
+    }
+}
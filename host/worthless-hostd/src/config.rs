@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use worthless_host::{Backend, Engine, HostError, InvokeObserver, Observers};
+
+use crate::daemon::Daemon;
+
+/// How long a drain waits for requests in flight by default.
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many connections are served at once by default.
+const DEFAULT_MAX_CONNECTIONS: usize = 256;
+
+/// Is told about a plugin that failed to shut down, with its name.
+pub type ShutdownErrorHook = dyn Fn(&str, &HostError) + Send + Sync;
+
+/// Configures a [`Daemon`] before it starts listening.
+#[derive(Clone)]
+pub struct DaemonConfig {
+    pub(crate) socket: PathBuf,
+    pub(crate) plugins: BTreeMap<String, PathBuf>,
+    pub(crate) backend: Backend,
+    pub(crate) drain_timeout: Duration,
+    pub(crate) max_connections: usize,
+    pub(crate) observers: Observers,
+    pub(crate) shutdown_error: Option<Arc<ShutdownErrorHook>>,
+}
+
+impl fmt::Debug for DaemonConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DaemonConfig")
+            .field("socket", &self.socket)
+            .field("plugins", &self.plugins)
+            .field("backend", &self.backend)
+            .field("drain_timeout", &self.drain_timeout)
+            .field("max_connections", &self.max_connections)
+            .field("observers", &self.observers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DaemonConfig {
+    /// Creates the configuration of a daemon listening on a socket path.
+    ///
+    /// By default no plugins are loaded, they run in process, up to 256
+    /// connections are served and a drain waits 30 seconds for requests in
+    /// flight.
+    pub fn new<P: AsRef<Path>>(socket: P) -> DaemonConfig {
+        DaemonConfig {
+            socket: socket.as_ref().to_path_buf(),
+            plugins: BTreeMap::new(),
+            backend: Backend::InProcess,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            observers: Observers::new(),
+            shutdown_error: None,
+        }
+    }
+
+    /// Loads a plugin under a name when the daemon starts.
+    pub fn plugin<P: AsRef<Path>>(&mut self, name: &str, path: P) -> &mut DaemonConfig {
+        self.plugins
+            .insert(name.to_string(), path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets where plugins run, this also applies to plugins loaded later.
+    pub fn backend(&mut self, backend: Backend) -> &mut DaemonConfig {
+        self.backend = backend;
+        self
+    }
+
+    /// Sets how long a drain waits for requests in flight.
+    ///
+    /// Connections still busy after that are abandoned.
+    pub fn drain_timeout(&mut self, timeout: Duration) -> &mut DaemonConfig {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Sets how many connections are served at once.
+    ///
+    /// Every connection is served by its own thread.  Connections over the
+    /// limit are closed right away.
+    pub fn max_connections(&mut self, max: usize) -> &mut DaemonConfig {
+        self.max_connections = max;
+        self
+    }
+
+    /// Registers an observer for the plugins of the daemon.
+    ///
+    /// This also applies to plugins loaded later.
+    pub fn observer(&mut self, observer: Arc<dyn InvokeObserver>) -> &mut DaemonConfig {
+        self.observers.register(observer);
+        self
+    }
+
+    /// Sets the function that is told about plugins that fail to shut down.
+    ///
+    /// Plugins are shut down when they are replaced, unloaded or the daemon
+    /// drained, none of which can fail because of it.  Without a hook the
+    /// errors are ignored.
+    pub fn on_shutdown_error<F>(&mut self, f: F) -> &mut DaemonConfig
+    where
+        F: Fn(&str, &HostError) + Send + Sync + 'static,
+    {
+        self.shutdown_error = Some(Arc::new(f));
+        self
+    }
+
+    /// Loads the plugins and binds the socket.
+    ///
+    /// A socket file left behind by a daemon that is no longer running is
+    /// replaced, other files at the path are not.  The socket is only
+    /// accessible to the user running the daemon.
+    pub fn bind(&self, engine: &Engine) -> Result<Daemon, HostError> {
+        Daemon::bind(engine, self)
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use worthless_bridge::{Error, ErrorKind, Request, Value};
+use worthless_host::error_chain;
+
+use crate::daemon::DaemonHandle;
+
+#[derive(Serialize)]
+struct PluginInfo {
+    name: String,
+    path: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct LoadPayload {
+    name: String,
+    path: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct UnloadPayload {
+    name: String,
+}
+
+#[derive(Deserialize, Default)]
+struct ReloadPayload {
+    #[serde(default)]
+    names: Option<Vec<String>>,
+}
+
+/// Handles a request to the daemon itself.
+pub(crate) fn handle(daemon: &DaemonHandle, req: &Request) -> Result<Value, Error> {
+    match req.endpoint() {
+        "plugins" => to_value(
+            &daemon
+                .plugins()
+                .into_iter()
+                .map(|(name, path)| PluginInfo { name, path })
+                .collect::<Vec<_>>(),
+        ),
+        "load" => {
+            let payload: LoadPayload = req.deserialize_payload()?;
+            daemon.load(&payload.name, &payload.path).map_err(|err| {
+                Error::new(
+                    ErrorKind::InternalError,
+                    format!("cannot load '{}': {}", payload.name, error_chain(&err)),
+                )
+            })?;
+            Ok(Value::Null)
+        }
+        "unload" => {
+            let payload: UnloadPayload = req.deserialize_payload()?;
+            Ok(Value::Bool(daemon.unload(&payload.name)))
+        }
+        "reload" => {
+            let payload: Option<ReloadPayload> = req.deserialize_payload()?;
+            let payload = payload.unwrap_or_default();
+            daemon.reload(payload.names.as_deref()).map_err(|err| {
+                Error::new(
+                    ErrorKind::InternalError,
+                    format!("cannot reload: {}", error_chain(&err)),
+                )
+            })?;
+            Ok(Value::Null)
+        }
+        "drain" => {
+            daemon.drain();
+            Ok(Value::Null)
+        }
+        endpoint => Err(Error::new(
+            ErrorKind::UnknownEndpoint,
+            format!("the daemon has no endpoint '{}'", endpoint),
+        )),
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> Result<Value, Error> {
+    Value::serialized(value).map_err(|err| {
+        Error::new(ErrorKind::SerializationError, "failed to convert payload").with_source(err)
+    })
+}
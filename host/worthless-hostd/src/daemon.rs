@@ -0,0 +1,579 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use worthless_bridge::{Error, ErrorKind, Request, Response, Value};
+use worthless_host::{error_chain, Backend, Engine, HostError, Observers, PluginInstance};
+
+use crate::config::{DaemonConfig, ShutdownErrorHook};
+use crate::control;
+
+/// The meta key of a request that names the plugin it goes to.
+pub const PLUGIN_META_KEY: &str = "plugin";
+
+/// How long to back off after accepting a connection failed.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(50);
+
+struct LoadedPlugin {
+    path: PathBuf,
+    instance: Arc<PluginInstance>,
+}
+
+#[derive(Default)]
+struct Connections {
+    draining: bool,
+    next_id: u64,
+    streams: BTreeMap<u64, UnixStream>,
+}
+
+struct State {
+    engine: Engine,
+    backend: Backend,
+    socket: PathBuf,
+    max_connections: usize,
+    observers: Observers,
+    shutdown_error: Option<Arc<ShutdownErrorHook>>,
+    plugins: RwLock<BTreeMap<String, LoadedPlugin>>,
+    connections: Mutex<Connections>,
+    idle: Condvar,
+}
+
+/// A daemon serving plugins on a Unix socket.
+///
+/// Created by [`DaemonConfig::bind`].
+pub struct Daemon {
+    listener: UnixListener,
+    state: Arc<State>,
+    drain_timeout: Duration,
+}
+
+/// Controls a running [`Daemon`] from other threads.
+#[derive(Clone)]
+pub struct DaemonHandle {
+    state: Arc<State>,
+}
+
+impl Daemon {
+    pub(crate) fn bind(engine: &Engine, config: &DaemonConfig) -> Result<Daemon, HostError> {
+        let state = State {
+            engine: engine.clone(),
+            backend: config.backend.clone(),
+            socket: config.socket.clone(),
+            max_connections: config.max_connections,
+            observers: config.observers.clone(),
+            shutdown_error: config.shutdown_error.clone(),
+            plugins: RwLock::default(),
+            connections: Mutex::default(),
+            idle: Condvar::new(),
+        };
+        for (name, path) in &config.plugins {
+            state.load(name, path)?;
+        }
+        remove_stale_socket(&config.socket);
+        let listener = bind_private(&config.socket).map_err(HostError::BridgeIoError)?;
+        Ok(Daemon {
+            listener,
+            state: Arc::new(state),
+            drain_timeout: config.drain_timeout,
+        })
+    }
+
+    /// Returns a handle to control the daemon while it runs.
+    pub fn handle(&self) -> DaemonHandle {
+        DaemonHandle {
+            state: self.state.clone(),
+        }
+    }
+
+    /// Serves connections until the daemon is drained.
+    ///
+    /// Returns once the connections are closed or the drain timeout passed,
+    /// the socket file is removed by then and the plugins were told to shut
+    /// down.
+    pub fn run(self) {
+        for stream in self.listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if !self.state.clone().accept(stream) {
+                        break;
+                    }
+                }
+                // eg: out of file descriptors, which might pass
+                Err(_) => thread::sleep(ACCEPT_BACKOFF),
+            }
+        }
+        drop(self.listener);
+        fs::remove_file(&self.state.socket).ok();
+
+        let connections = self.state.connections.lock().unwrap();
+        drop(
+            self.state
+                .idle
+                .wait_timeout_while(connections, self.drain_timeout, |connections| {
+                    !connections.streams.is_empty()
+                })
+                .unwrap(),
+        );
+        let plugins = std::mem::take(&mut *self.state.plugins.write().unwrap());
+        for (name, plugin) in plugins {
+            self.state.shut_down(&name, &plugin);
+        }
+    }
+}
+
+impl DaemonHandle {
+    /// Returns the names and paths of the loaded plugins.
+    pub fn plugins(&self) -> Vec<(String, PathBuf)> {
+        self.state.plugins()
+    }
+
+    /// Loads a plugin under a name, replacing the plugin loaded before.
+    pub fn load<P: AsRef<Path>>(&self, name: &str, path: P) -> Result<(), HostError> {
+        self.state.load(name, path.as_ref())
+    }
+
+    /// Removes a plugin, returns `false` if none was loaded under the name.
+    pub fn unload(&self, name: &str) -> bool {
+        self.state.unload(name)
+    }
+
+    /// Loads plugins from their paths again, all of them if `names` is `None`.
+    ///
+    /// Either all plugins are replaced or none of them.  Unknown names are
+    /// ignored.
+    pub fn reload(&self, names: Option<&[String]>) -> Result<(), HostError> {
+        self.state.reload(names)
+    }
+
+    /// Stops accepting connections and lets [`Daemon::run`] return once the
+    /// requests in flight are answered.
+    pub fn drain(&self) {
+        self.state.drain();
+    }
+}
+
+impl State {
+    fn plugins(&self) -> Vec<(String, PathBuf)> {
+        self.plugins
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, plugin)| (name.clone(), plugin.path.clone()))
+            .collect()
+    }
+
+    fn load(&self, name: &str, path: &Path) -> Result<(), HostError> {
+        let instance = PluginInstance::load(&self.engine, path, &self.backend)?;
+        instance.set_name(name);
+        instance.set_observers(self.observers.clone());
+        let replaced = self.plugins.write().unwrap().insert(
+            name.to_string(),
+            LoadedPlugin {
+                path: path.to_path_buf(),
+                instance: Arc::new(instance),
+            },
+        );
+        if let Some(replaced) = replaced {
+            self.shut_down(name, &replaced);
+        }
+        Ok(())
+    }
+
+    fn unload(&self, name: &str) -> bool {
+        let removed = self.plugins.write().unwrap().remove(name);
+        if let Some(ref removed) = removed {
+            self.shut_down(name, removed);
+        }
+        removed.is_some()
+    }
+
+    fn reload(&self, names: Option<&[String]>) -> Result<(), HostError> {
+        let mut loaded = Vec::new();
+        for (name, path) in self.plugins() {
+            if let Some(names) = names {
+                if !names.contains(&name) {
+                    continue;
+                }
+            }
+            let instance = PluginInstance::load(&self.engine, &path, &self.backend)?;
+            instance.set_name(&name);
+            instance.set_observers(self.observers.clone());
+            loaded.push((name, path, instance));
+        }
+        let mut replaced = Vec::new();
+        let mut plugins = self.plugins.write().unwrap();
+        for (name, path, instance) in loaded {
+            let plugin = LoadedPlugin {
+                path,
+                instance: Arc::new(instance),
+            };
+            if let Some(old) = plugins.insert(name.clone(), plugin) {
+                replaced.push((name, old));
+            }
+        }
+        drop(plugins);
+        for (name, plugin) in replaced {
+            self.shut_down(&name, &plugin);
+        }
+        Ok(())
+    }
+
+    /// Tells a plugin it's being replaced or unloaded.
+    ///
+    /// Requests in flight keep the instance alive until they are answered.
+    fn shut_down(&self, name: &str, plugin: &LoadedPlugin) {
+        if let Err(err) = plugin.instance.shutdown() {
+            if let Some(ref hook) = self.shutdown_error {
+                hook(name, &err);
+            }
+        }
+    }
+
+    fn drain(&self) {
+        let mut connections = self.connections.lock().unwrap();
+        if connections.draining {
+            return;
+        }
+        connections.draining = true;
+        // blocked reads return, so connections close after their current
+        // request
+        for stream in connections.streams.values() {
+            stream.shutdown(Shutdown::Read).ok();
+        }
+        drop(connections);
+        // the accept loop only notices the drain with the next connection
+        UnixStream::connect(&self.socket).ok();
+    }
+
+    /// Starts serving a connection, returns `false` while draining.
+    ///
+    /// Connections over the limit are closed right away.
+    fn accept(self: Arc<Self>, stream: UnixStream) -> bool {
+        let id = {
+            let mut connections = self.connections.lock().unwrap();
+            if connections.draining {
+                return false;
+            }
+            if connections.streams.len() >= self.max_connections {
+                return true;
+            }
+            let Ok(clone) = stream.try_clone() else {
+                return true;
+            };
+            connections.next_id += 1;
+            let id = connections.next_id;
+            connections.streams.insert(id, clone);
+            id
+        };
+        thread::spawn(move || {
+            self.serve_connection(stream);
+            let mut connections = self.connections.lock().unwrap();
+            connections.streams.remove(&id);
+            self.idle.notify_all();
+        });
+        true
+    }
+
+    fn serve_connection(self: &Arc<Self>, stream: UnixStream) {
+        let Ok(input) = stream.try_clone() else {
+            return;
+        };
+        let mut reader = BufReader::new(input);
+        let mut writer = BufWriter::new(stream);
+        loop {
+            match reader.fill_buf() {
+                Ok(buf) if !buf.is_empty() => {}
+                _ => return,
+            }
+            let (response, fire_and_forget) = match Request::deserialize_from(&mut reader) {
+                Ok(req) => {
+                    let fire_and_forget = req.fire_and_forget();
+                    (self.handle(req), fire_and_forget)
+                }
+                // the stream is out of sync, respond once and hang up
+                Err(err) => {
+                    Response::builder()
+                        .error(err)
+                        .build()
+                        .serialize_to(&mut writer)
+                        .ok();
+                    writer.flush().ok();
+                    return;
+                }
+            };
+            if !fire_and_forget
+                && (response.serialize_to(&mut writer).is_err() || writer.flush().is_err())
+            {
+                return;
+            }
+        }
+    }
+
+    fn handle(self: &Arc<Self>, req: Request) -> Response {
+        let request_id = req.request_id().unwrap_or_default().to_string();
+        let rv = match req.meta().get(PLUGIN_META_KEY) {
+            None => {
+                let handle = DaemonHandle {
+                    state: self.clone(),
+                };
+                control::handle(&handle, &req)
+            }
+            Some(Value::Text(name)) => return self.invoke(name.clone(), req),
+            Some(_) => Err(Error::new(
+                ErrorKind::UnknownEndpoint,
+                "plugin name must be a string",
+            )),
+        };
+        let mut builder = Response::builder();
+        builder.request_id(request_id);
+        match rv {
+            Ok(payload) => builder.raw_payload(payload),
+            Err(err) => builder.error(err),
+        };
+        builder.build()
+    }
+
+    fn invoke(&self, name: String, req: Request) -> Response {
+        let request_id = req.request_id().unwrap_or_default().to_string();
+        let plugin = self
+            .plugins
+            .read()
+            .unwrap()
+            .get(&name)
+            .map(|plugin| plugin.instance.clone());
+        let err = match plugin {
+            Some(plugin) if !req.endpoint().starts_with("__") => {
+                match plugin.send_requests(Some(req)) {
+                    Ok(mut responses) => match responses.pop() {
+                        Some(response) => return response,
+                        None => Error::new(ErrorKind::InternalError, "plugin did not respond"),
+                    },
+                    Err(err) => Error::new(ErrorKind::InternalError, error_chain(&err)),
+                }
+            }
+            Some(_) => Error::new(ErrorKind::UnknownEndpoint, "no such endpoint"),
+            None => Error::new(
+                ErrorKind::UnknownEndpoint,
+                format!("no plugin named '{}'", name),
+            ),
+        };
+        Response::builder()
+            .request_id(request_id)
+            .error(err)
+            .build()
+    }
+}
+
+/// Binds a socket only the user running the daemon can connect to.
+///
+/// The socket controls what the daemon loads.  It's created with the
+/// permissions of the umask, so it's bound in a directory only the user can
+/// enter and linked into place once its permissions are restricted.  Like
+/// binding, linking fails if the path exists.
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "socket path has no file name")
+    })?;
+    let mut dir_name = std::ffi::OsString::from(".");
+    dir_name.push(file_name);
+    dir_name.push(format!(".{}", std::process::id()));
+    let dir = path.with_file_name(dir_name);
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let private = dir.join("socket");
+    let rv = UnixListener::bind(&private).and_then(|listener| {
+        fs::set_permissions(&private, fs::Permissions::from_mode(0o600))?;
+        fs::hard_link(&private, path)?;
+        Ok(listener)
+    });
+    fs::remove_file(&private).ok();
+    fs::remove_dir(&dir).ok();
+    rv
+}
+
+/// Removes the socket file of a daemon that is gone.
+///
+/// Other files are left alone, binding the socket fails on them.
+fn remove_stale_socket(path: &Path) {
+    let is_socket = matches!(fs::symlink_metadata(path), Ok(meta) if meta.file_type().is_socket());
+    if is_socket && UnixStream::connect(path).is_err() {
+        fs::remove_file(path).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Returns a fresh directory for the socket and files of a test.
+    fn test_dir() -> PathBuf {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "worthless-hostd-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes a stand-in for the worker command that hangs up right away.
+    fn fake_worker(dir: &Path) -> PathBuf {
+        let path = dir.join("worker");
+        fs::write(&path, "#!/bin/sh\nexec cat > /dev/null\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn config(dir: &Path) -> DaemonConfig {
+        let mut config = DaemonConfig::new(dir.join("hostd.sock"));
+        config
+            .backend(Backend::Process(fake_worker(dir)))
+            .drain_timeout(Duration::from_secs(5));
+        config
+    }
+
+    fn call(stream: &UnixStream, endpoint: &str) -> Response {
+        Request::new(endpoint, Value::Null)
+            .serialize_to(stream)
+            .unwrap();
+        Response::deserialize_from(stream).unwrap()
+    }
+
+    fn is_closed(mut stream: &UnixStream) -> bool {
+        matches!(stream.read(&mut [0; 1]), Ok(0))
+    }
+
+    #[test]
+    fn test_load_unload_reload() {
+        let dir = test_dir();
+        let daemon = config(&dir).bind(&Engine::default()).unwrap();
+        let handle = daemon.handle();
+        let plugin = dir.join("plugin.wasm");
+        handle.load("a", &plugin).unwrap();
+        handle.load("b", &plugin).unwrap();
+        assert_eq!(
+            handle.plugins(),
+            vec![("a".into(), plugin.clone()), ("b".into(), plugin.clone())]
+        );
+
+        // unknown names are skipped
+        handle
+            .reload(Some(&["a".to_string(), "missing".to_string()]))
+            .unwrap();
+        handle.reload(None).unwrap();
+        assert_eq!(handle.plugins().len(), 2);
+
+        assert!(handle.unload("a"));
+        assert!(!handle.unload("a"));
+        assert_eq!(handle.plugins(), vec![("b".into(), plugin)]);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_shutdown_error() {
+        let dir = test_dir();
+        let failed = Arc::new(Mutex::new(Vec::new()));
+        let mut config = config(&dir);
+        config.on_shutdown_error({
+            let failed = failed.clone();
+            move |name, _| failed.lock().unwrap().push(name.to_string())
+        });
+        let daemon = config.bind(&Engine::default()).unwrap();
+        let handle = daemon.handle();
+        // the worker hangs up, so the plugin cannot be told to shut down
+        handle.load("a", dir.join("plugin.wasm")).unwrap();
+        assert!(handle.unload("a"));
+        assert_eq!(*failed.lock().unwrap(), ["a"]);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_load_failure() {
+        let dir = test_dir();
+        let mut config = config(&dir);
+        config.backend(Backend::Process(dir.join("missing")));
+        let daemon = config.bind(&Engine::default()).unwrap();
+        let handle = daemon.handle();
+        assert!(handle.load("a", dir.join("plugin.wasm")).is_err());
+        assert!(handle.plugins().is_empty());
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_socket() {
+        let dir = test_dir();
+        let socket = dir.join("hostd.sock");
+
+        // files other than sockets are not replaced
+        fs::write(&socket, "").unwrap();
+        assert!(config(&dir).bind(&Engine::default()).is_err());
+        assert!(socket.is_file());
+        fs::remove_file(&socket).unwrap();
+
+        // sockets nobody listens on are
+        drop(UnixListener::bind(&socket).unwrap());
+        let daemon = config(&dir).bind(&Engine::default()).unwrap();
+        let mode = fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // the directory the socket was bound in is gone
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["hostd.sock", "worker"]);
+        UnixStream::connect(&socket).unwrap();
+        drop(daemon);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_drain() {
+        let dir = test_dir();
+        let socket = dir.join("hostd.sock");
+        let daemon = config(&dir).bind(&Engine::default()).unwrap();
+        let handle = daemon.handle();
+        let server = thread::spawn(move || daemon.run());
+
+        let client = UnixStream::connect(&socket).unwrap();
+        let response = call(&client, "plugins");
+        assert_eq!(response.into_payload().unwrap(), Value::Array(vec![]));
+
+        handle.drain();
+        server.join().unwrap();
+        assert!(is_closed(&client));
+        assert!(!socket.exists());
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let dir = test_dir();
+        let socket = dir.join("hostd.sock");
+        let mut config = config(&dir);
+        config.max_connections(1);
+        let daemon = config.bind(&Engine::default()).unwrap();
+        let handle = daemon.handle();
+        let server = thread::spawn(move || daemon.run());
+
+        let first = UnixStream::connect(&socket).unwrap();
+        call(&first, "plugins").into_payload().unwrap();
+        let second = UnixStream::connect(&socket).unwrap();
+        assert!(is_closed(&second));
+        call(&first, "plugins").into_payload().unwrap();
+
+        handle.drain();
+        server.join().unwrap();
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,29 @@
+//! A long running service that runs plugins for other processes.
+//!
+//! The daemon listens on a Unix socket and keeps its plugins loaded between
+//! requests.  Clients write bridge requests to the socket and read the
+//! responses back in the same order, both in the self delimiting wire format
+//! of the bridge.  The [`PLUGIN_META_KEY`] of a request names the plugin it
+//! goes to.  Requests without it are handled by the daemon itself:
+//!
+//! * `plugins` responds with the `name` and `path` of every loaded plugin.
+//! * `load` loads the plugin at `path` under `name`, replacing the plugin
+//!   loaded under that name before.
+//! * `unload` removes the plugin loaded under `name`.
+//! * `reload` loads the plugins from their paths again, all of them or the
+//!   ones listed in `names`.  Nothing is replaced if one fails to load.
+//! * `drain` stops accepting connections and shuts the daemon down once the
+//!   requests in flight are answered.
+//!
+//! Requests in flight always complete with the plugin they started on.
+//! Every connection is served by its own thread one request at a time, so
+//! clients that want to send requests in parallel open more connections, up
+//! to [`DaemonConfig::max_connections`].  The socket is only accessible to
+//! the user running the daemon.
+//! Control endpoints of the guest (starting with `__`) are never exposed.
+mod config;
+mod control;
+mod daemon;
+
+pub use self::config::{DaemonConfig, ShutdownErrorHook};
+pub use self::daemon::{Daemon, DaemonHandle, PLUGIN_META_KEY};
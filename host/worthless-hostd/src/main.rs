@@ -0,0 +1,108 @@
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context as _, Error};
+use clap::{Parser, Subcommand};
+use worthless_host::{error_chain, serve_worker, Backend, HostConfig, HostError};
+use worthless_hostd::DaemonConfig;
+
+/// Runs plugins for other processes behind a Unix socket.
+#[derive(Parser, Debug)]
+#[command(
+    name = "worthless-hostd",
+    version,
+    about,
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Serves a plugin to a parent process.
+    #[command(hide = true)]
+    Worker {
+        /// The plugin to serve.
+        plugin: PathBuf,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct Args {
+    /// The path of the socket to listen on.
+    #[arg(long, default_value = "worthless-hostd.sock")]
+    socket: PathBuf,
+    /// A plugin to load under a name.
+    #[arg(long = "plugin", value_name = "NAME=PATH", value_parser = parse_plugin)]
+    plugins: Vec<(String, PathBuf)>,
+    /// Runs every plugin in its own child process.
+    #[arg(long)]
+    out_of_process: bool,
+    /// How long to wait for requests in flight when draining in seconds.
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    drain_timeout: u64,
+    /// How many connections to serve at once.
+    #[arg(long, value_name = "COUNT", default_value_t = 256)]
+    max_connections: usize,
+}
+
+fn parse_plugin(arg: &str) -> Result<(String, PathBuf), String> {
+    arg.split_once('=')
+        .map(|(name, path)| (name.to_string(), PathBuf::from(path)))
+        .ok_or_else(|| format!("expected NAME=PATH, got '{}'", arg))
+}
+
+fn host_error(err: HostError) -> Error {
+    anyhow!(error_chain(&err))
+}
+
+fn serve(args: Args) -> Result<(), Error> {
+    let mut config = DaemonConfig::new(&args.socket);
+    config
+        .drain_timeout(Duration::from_secs(args.drain_timeout))
+        .max_connections(args.max_connections)
+        .on_shutdown_error(|name, err| {
+            eprintln!("plugin {} failed to shut down: {}", name, error_chain(err))
+        });
+    if args.out_of_process {
+        // child processes are started with the worker command of this binary
+        config.backend(Backend::Process(std::env::current_exe()?));
+    }
+    for (name, path) in &args.plugins {
+        config.plugin(name, path);
+    }
+    let engine = HostConfig::new().build_engine().map_err(host_error)?;
+    let daemon = config
+        .bind(&engine)
+        .map_err(host_error)
+        .with_context(|| format!("cannot start daemon on {}", args.socket.display()))?;
+
+    let handle = daemon.handle();
+    ctrlc::set_handler(move || handle.drain())?;
+    eprintln!("listening on {}", args.socket.display());
+    daemon.run();
+    eprintln!("drained");
+    Ok(())
+}
+
+fn worker(plugin: PathBuf) -> Result<(), Error> {
+    serve_worker(plugin, io::stdin(), io::stdout()).map_err(host_error)
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let rv = match cli.command {
+        Some(Command::Worker { plugin }) => worker(plugin),
+        None => serve(cli.args),
+    };
+    if let Err(err) = rv {
+        eprintln!("error: {:#}", err);
+        process::exit(1);
+    }
+}
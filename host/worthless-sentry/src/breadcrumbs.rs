@@ -0,0 +1,45 @@
+use std::collections::BTreeMap;
+
+use sentry_core::protocol::{Breadcrumb, Level};
+use worthless_bridge::{Error, Request, Value};
+use worthless_host::{log_emit, Plugin, LOG_ENDPOINT};
+
+/// Converts a console line of a plugin into a breadcrumb.
+pub fn log_to_breadcrumb(req: &Request) -> Breadcrumb {
+    let field = |name: &str| match req.payload() {
+        Value::Map(items) => items.iter().find_map(|(key, value)| match (key, value) {
+            (Value::Text(key), Value::Text(value)) if key == name => Some(value.as_str()),
+            _ => None,
+        }),
+        _ => None,
+    };
+    let mut data = BTreeMap::new();
+    if let Some(request_id) = req.request_id() {
+        data.insert("request_id".into(), request_id.into());
+    }
+    Breadcrumb {
+        ty: "debug".into(),
+        category: Some("console".into()),
+        level: match field("level") {
+            Some("debug") => Level::Debug,
+            Some("warn") => Level::Warning,
+            Some("error") => Level::Error,
+            _ => Level::Info,
+        },
+        message: field("message").map(Into::into),
+        data,
+        ..Default::default()
+    }
+}
+
+/// Records the console output of a plugin as breadcrumbs.
+///
+/// The output is still forwarded to stderr.  The breadcrumbs go to the
+/// current scope which is the scope of the invocation when requests are sent
+/// with [`send_request`](crate::send_request).
+pub fn install_breadcrumbs(plugin: &Plugin) {
+    plugin.register_endpoint(LOG_ENDPOINT, |req: &Request| -> Result<Value, Error> {
+        sentry_core::add_breadcrumb(log_to_breadcrumb(req));
+        log_emit(req)
+    });
+}
@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+
+use sentry_core::protocol::{Event, Exception, Frame, Level, Stacktrace};
+use worthless_bridge::{Error, Meta, Value};
+
+/// Looks up a key in a bridge map.
+fn get<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    match value {
+        Value::Map(items) => items.iter().find_map(|(k, v)| match k {
+            Value::Text(k) if k == key => Some(v),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+fn as_u64(value: Option<&Value>) -> Option<u64> {
+    match value {
+        Some(Value::Integer(value)) => u64::try_from(i128::from(*value)).ok(),
+        _ => None,
+    }
+}
+
+/// Converts the frames in the detail of an error into Sentry frames.
+///
+/// The guest sends the innermost frame first while Sentry expects the
+/// outermost one first.  The filenames are sent as `abs_path` so that Sentry
+/// can apply uploaded source maps.
+pub fn frames_from_detail(detail: &Value) -> Vec<Frame> {
+    let frames = match get(detail, "frames") {
+        Some(Value::Array(frames)) => frames,
+        _ => return Vec::new(),
+    };
+    frames
+        .iter()
+        .rev()
+        .map(|frame| {
+            let filename = match get(frame, "filename") {
+                Some(Value::Text(filename)) => Some(filename.clone()),
+                _ => None,
+            };
+            Frame {
+                function: match get(frame, "function") {
+                    Some(Value::Text(function)) => Some(function.clone()),
+                    _ => None,
+                },
+                filename: filename.clone(),
+                abs_path: filename.clone(),
+                lineno: as_u64(get(frame, "lineno")),
+                colno: as_u64(get(frame, "colno")),
+                in_app: Some(matches!(filename, Some(ref x) if !x.starts_with("<worthless:"))),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Converts bridge meta into tags.
+///
+/// Only scalar values are converted.
+pub fn meta_to_tags(meta: &Meta) -> BTreeMap<String, String> {
+    meta.iter()
+        .filter_map(|(key, value)| {
+            let value = match value {
+                Value::Text(value) => value.clone(),
+                Value::Bool(value) => value.to_string(),
+                Value::Integer(value) => i128::from(*value).to_string(),
+                Value::Float(value) => value.to_string(),
+                _ => return None,
+            };
+            Some((format!("worthless.{}", key), value))
+        })
+        .collect()
+}
+
+/// Creates a Sentry event from an error returned by a plugin.
+///
+/// Errors that originate from JavaScript exceptions carry the message in the
+/// form `Type: message` and the stack frames in their detail.
+pub fn error_to_event(err: &Error, meta: &Meta) -> Event<'static> {
+    let message = match err.detail().and_then(|x| get(x, "message")) {
+        Some(Value::Text(message)) => message.clone(),
+        _ => err.description().to_string(),
+    };
+    let (ty, value) = match message.split_once(": ") {
+        Some((ty, value)) if !ty.is_empty() && !ty.contains(' ') => {
+            (ty.to_string(), value.to_string())
+        }
+        _ => ("Error".to_string(), message),
+    };
+    let frames = err.detail().map(frames_from_detail).unwrap_or_default();
+
+    let mut tags = meta_to_tags(meta);
+    tags.insert("worthless.error_kind".into(), err.kind().to_string());
+    Event {
+        level: Level::Error,
+        platform: "javascript".into(),
+        exception: vec![Exception {
+            ty,
+            value: Some(value),
+            stacktrace: if frames.is_empty() {
+                None
+            } else {
+                Some(Stacktrace {
+                    frames,
+                    ..Default::default()
+                })
+            },
+            ..Default::default()
+        }]
+        .into(),
+        tags,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use worthless_bridge::ErrorKind;
+
+    fn frame(function: &str, filename: &str, lineno: i64) -> Value {
+        Value::Map(vec![
+            ("function".into(), function.into()),
+            ("filename".into(), filename.into()),
+            ("lineno".into(), lineno.into()),
+            ("colno".into(), 3.into()),
+        ])
+    }
+
+    fn detail(message: &str) -> Value {
+        Value::Map(vec![
+            ("message".into(), message.into()),
+            (
+                "frames".into(),
+                Value::Array(vec![
+                    frame("inner", "plugin.js", 10),
+                    frame("dispatch", "<worthless:runtime>", 20),
+                    frame("outer", "plugin.js", 30),
+                ]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_frames_reversed() {
+        let frames = frames_from_detail(&detail("Error: boom"));
+        let functions: Vec<_> = frames
+            .iter()
+            .map(|frame| frame.function.as_deref().unwrap())
+            .collect();
+        assert_eq!(functions, ["outer", "dispatch", "inner"]);
+        assert_eq!(frames[0].filename.as_deref(), Some("plugin.js"));
+        assert_eq!(frames[0].abs_path.as_deref(), Some("plugin.js"));
+        assert_eq!(frames[0].lineno, Some(30));
+        assert_eq!(frames[0].colno, Some(3));
+    }
+
+    #[test]
+    fn test_frames_in_app() {
+        let frames = frames_from_detail(&detail("Error: boom"));
+        let in_app: Vec<_> = frames.iter().map(|frame| frame.in_app).collect();
+        assert_eq!(in_app, [Some(true), Some(false), Some(true)]);
+
+        // frames without a filename are not attributed to the plugin
+        let detail = Value::Map(vec![(
+            "frames".into(),
+            Value::Array(vec![Value::Map(vec![("function".into(), "f".into())])]),
+        )]);
+        assert_eq!(frames_from_detail(&detail)[0].in_app, Some(false));
+        assert!(frames_from_detail(&Value::Null).is_empty());
+    }
+
+    #[test]
+    fn test_meta_to_tags() {
+        let mut meta = Meta::new();
+        meta.insert("tenant".into(), "acme".into());
+        meta.insert("retry".into(), true.into());
+        meta.insert("attempt".into(), 2.into());
+        meta.insert("ratio".into(), 0.5.into());
+        meta.insert("nested".into(), Value::Array(vec![]));
+        let tags = meta_to_tags(&meta);
+        assert_eq!(tags.len(), 4);
+        assert_eq!(tags["worthless.tenant"], "acme");
+        assert_eq!(tags["worthless.retry"], "true");
+        assert_eq!(tags["worthless.attempt"], "2");
+        assert_eq!(tags["worthless.ratio"], "0.5");
+    }
+
+    #[test]
+    fn test_error_to_event() {
+        let err = Error::new(ErrorKind::InternalError, "uncaught exception")
+            .with_detail(detail("TypeError: x is not a function"));
+        let mut meta = Meta::new();
+        meta.insert("tenant".into(), "acme".into());
+        let event = error_to_event(&err, &meta);
+        let exception = &event.exception[0];
+        assert_eq!(exception.ty, "TypeError");
+        assert_eq!(exception.value.as_deref(), Some("x is not a function"));
+        assert_eq!(exception.stacktrace.as_ref().unwrap().frames.len(), 3);
+        assert_eq!(event.tags["worthless.tenant"], "acme");
+        assert_eq!(
+            event.tags["worthless.error_kind"],
+            ErrorKind::InternalError.to_string()
+        );
+    }
+
+    #[test]
+    fn test_error_to_event_without_type() {
+        // a message that is not in the form `Type: message` is kept whole
+        let err = Error::new(ErrorKind::InternalError, "request failed: for a reason");
+        let event = error_to_event(&err, &Meta::new());
+        let exception = &event.exception[0];
+        assert_eq!(exception.ty, "Error");
+        assert_eq!(exception.value.as_deref(), Some("request failed: for a reason"));
+        assert!(exception.stacktrace.is_none());
+
+        let err = Error::new(ErrorKind::InternalError, "boom");
+        let event = error_to_event(&err, &Meta::new());
+        assert_eq!(event.exception[0].ty, "Error");
+        assert_eq!(event.exception[0].value.as_deref(), Some("boom"));
+    }
+}
@@ -0,0 +1,66 @@
+//! Reports failing plugin invocations to Sentry.
+//!
+//! The integration is opt-in by depending on this crate, which is the
+//! feature gate: `worthless-host` has no Sentry feature and never depends
+//! on Sentry itself, so hosts that don't add this crate don't build it.  It
+//! uses the currently bound Sentry hub so the client has to be configured
+//! by the host program.
+mod breadcrumbs;
+mod event;
+mod spans;
+
+use sentry_core::types::Uuid;
+use worthless_bridge::{Error, Meta, Request, Response};
+use worthless_host::{HostError, Plugin};
+
+pub use self::breadcrumbs::{install_breadcrumbs, log_to_breadcrumb};
+pub use self::event::{error_to_event, frames_from_detail, meta_to_tags};
+pub use self::spans::{load_plugin, traced_endpoint};
+
+/// Captures an error returned by a plugin.
+///
+/// The meta of the request is attached as tags.
+pub fn capture_error(err: &Error, meta: &Meta) -> Uuid {
+    sentry_core::capture_event(error_to_event(err, meta))
+}
+
+/// Sends a request to a plugin and reports failures to Sentry.
+///
+/// The invocation runs in its own scope so breadcrumbs recorded by
+/// [`install_breadcrumbs`] only show up on events of this invocation.  Error
+/// responses are captured as events with the JavaScript stack of the guest,
+/// failures of the host itself are captured as regular errors.
+///
+/// Each invocation is recorded as a `worthless.invoke` transaction named after
+/// the endpoint with the `worthless.serialize`, `worthless.dispatch` and
+/// `worthless.deserialize` phases as child spans.  Host endpoints wrapped
+/// with [`traced_endpoint`] add spans to the dispatch.
+pub fn send_request(plugin: &Plugin, req: Request) -> Result<Response, HostError> {
+    let meta = req.meta().clone();
+    let transaction = spans::start_invoke(&req);
+    let rv = sentry_core::with_scope(
+        |scope| {
+            for (key, value) in meta_to_tags(&meta) {
+                scope.set_tag(&key, value);
+            }
+            scope.set_span(Some(transaction.clone()));
+        },
+        || {
+            let _phases = spans::observe_phases(plugin, &transaction);
+            match plugin.send_request(req) {
+                Ok(response) => {
+                    if let Some(err) = response.error_ref() {
+                        capture_error(err, &meta);
+                    }
+                    Ok(response)
+                }
+                Err(err) => {
+                    sentry_core::capture_error(&err);
+                    Err(err)
+                }
+            }
+        },
+    );
+    spans::finish_invoke(transaction, &rv);
+    rv
+}
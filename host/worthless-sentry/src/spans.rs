@@ -0,0 +1,111 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use sentry_core::protocol::SpanStatus;
+use sentry_core::{TransactionContext, TransactionOrSpan};
+use worthless_bridge::{Error, Request, Response, Value};
+use worthless_host::{Engine, HostError, InvokeObserver, InvokePhase, Plugin, ScopedObserver};
+
+/// Records the phases of one invocation as children of its transaction.
+///
+/// It is scoped to the invocation on the thread that sends it, which runs
+/// within the scope of [`send_request`](crate::send_request).  While a phase
+/// runs its span is the span of that scope, so host calls traced with
+/// [`traced_endpoint`] show up within the dispatch.
+struct PhaseSpans {
+    transaction: TransactionOrSpan,
+    // the spans of the running phases with the spans they replaced
+    phases: Mutex<Vec<(TransactionOrSpan, Option<TransactionOrSpan>)>>,
+}
+
+impl InvokeObserver for PhaseSpans {
+    fn on_phase_start(&self, plugin: Option<&str>, phase: InvokePhase) {
+        let span: TransactionOrSpan = self
+            .transaction
+            .start_child(
+                &format!("worthless.{}", phase.name()),
+                plugin.unwrap_or_default(),
+            )
+            .into();
+        let parent = sentry_core::configure_scope(|scope| {
+            let parent = scope.get_span();
+            scope.set_span(Some(span.clone()));
+            parent
+        });
+        self.phases.lock().unwrap().push((span, parent));
+    }
+
+    fn on_phase_end(&self, _plugin: Option<&str>, _phase: InvokePhase) {
+        if let Some((span, parent)) = self.phases.lock().unwrap().pop() {
+            sentry_core::configure_scope(|scope| scope.set_span(parent));
+            span.finish();
+        }
+    }
+}
+
+/// Loads a plugin within a `worthless.load` transaction.
+///
+/// The transaction covers compiling and instantiating the module.
+pub fn load_plugin<P: AsRef<Path>>(engine: &Engine, path: P) -> Result<Plugin, HostError> {
+    let path = path.as_ref();
+    let transaction = sentry_core::start_transaction(TransactionContext::new(
+        &path.display().to_string(),
+        "worthless.load",
+    ));
+    let rv = Plugin::from_path(engine, path);
+    transaction.set_status(match rv {
+        Ok(_) => SpanStatus::Ok,
+        Err(_) => SpanStatus::InternalError,
+    });
+    transaction.finish();
+    rv
+}
+
+/// Wraps a host endpoint so each call is recorded as a span.
+///
+/// The span is a child of the invocation the guest made the call from, so
+/// the time spent in the host shows up separately from the time spent
+/// executing JavaScript.
+pub fn traced_endpoint<F>(f: F) -> impl Fn(&Request) -> Result<Value, Error> + Send + Sync + 'static
+where
+    F: Fn(&Request) -> Result<Value, Error> + Send + Sync + 'static,
+{
+    move |req| {
+        let span = sentry_core::configure_scope(|scope| scope.get_span())
+            .map(|parent| parent.start_child("worthless.host_call", req.endpoint()));
+        let rv = f(req);
+        if let Some(span) = span {
+            span.set_status(match rv {
+                Ok(_) => SpanStatus::Ok,
+                Err(_) => SpanStatus::InternalError,
+            });
+            span.finish();
+        }
+        rv
+    }
+}
+
+/// Starts the transaction for sending a request to a plugin.
+pub(crate) fn start_invoke(req: &Request) -> TransactionOrSpan {
+    sentry_core::start_transaction(TransactionContext::new(req.endpoint(), "worthless.invoke"))
+        .into()
+}
+
+/// Records the phases of the invocation the current thread makes as spans
+/// of the transaction until the returned guard is dropped.
+pub(crate) fn observe_phases(plugin: &Plugin, transaction: &TransactionOrSpan) -> ScopedObserver {
+    plugin.observe_scoped(Arc::new(PhaseSpans {
+        transaction: transaction.clone(),
+        phases: Mutex::new(Vec::new()),
+    }))
+}
+
+/// Finishes the transaction of an invocation.
+pub(crate) fn finish_invoke(transaction: TransactionOrSpan, rv: &Result<Response, HostError>) {
+    transaction.set_status(match rv {
+        Ok(response) if response.error_ref().is_none() => SpanStatus::Ok,
+        Ok(_) => SpanStatus::UnknownError,
+        Err(_) => SpanStatus::InternalError,
+    });
+    transaction.finish();
+}
@@ -0,0 +1,212 @@
+//! Correlates responses with the requests in flight over one transport.
+//!
+//! A [`Correlator`] does no I/O.  The side sending requests tells it about
+//! every request it sends with [`track`](Correlator::track), hands it every
+//! response it receives with [`route`](Correlator::route) and waits for a
+//! request by polling its events with [`poll`](Correlator::poll).  Responses
+//! can arrive in any order, interleaved with progress messages of other
+//! requests.  Requests are told apart by their ID, so they need one:
+//!
+//! ```
+//! use worthless_bridge::{Correlator, Event, Request, Response, ResponseKind, Value};
+//!
+//! let mut correlator = Correlator::new();
+//! let request = |key: &str| {
+//!     let mut builder = Request::build("kv.get".into());
+//!     builder.raw_payload(key).random_request_id();
+//!     builder.build()
+//! };
+//! let first = request("a");
+//! let second = request("b");
+//! let first_id = correlator.track(&first).unwrap();
+//! let second_id = correlator.track(&second).unwrap();
+//!
+//! let progress = Response::builder()
+//!     .request_id(&first_id)
+//!     .kind(ResponseKind::Progress)
+//!     .raw_payload(50)
+//!     .build();
+//! let done = Response::builder().request_id(&second_id).raw_payload("b").build();
+//! correlator.route(done).unwrap();
+//! correlator.route(progress).unwrap();
+//!
+//! assert!(matches!(correlator.poll(&first_id), Some(Event::Progress(_))));
+//! assert!(correlator.poll(&first_id).is_none());
+//! match correlator.poll(&second_id) {
+//!     Some(Event::Completed(response)) => {
+//!         assert_eq!(response.into_payload().unwrap(), Value::from("b"))
+//!     }
+//!     _ => panic!("expected the response"),
+//! }
+//! assert_eq!(correlator.in_flight(), 1);
+//! ```
+use std::collections::{HashMap, VecDeque};
+
+use crate::types::{Error, ErrorKind, Request, Response, ResponseKind, Value, CANCEL_ENDPOINT};
+
+/// The number of cancelled requests whose late responses are ignored.
+const MAX_CANCELLED: usize = 1024;
+
+/// Something that happened to a request in flight.
+#[cfg_attr(feature = "debug", derive(Debug))]
+pub enum Event {
+    /// A progress message with its payload.
+    Progress(Value),
+    /// The final response, the request is done.
+    Completed(Response),
+    /// The request was cancelled, no response follows.
+    Cancelled,
+}
+
+/// The events of a request that were not polled yet.
+#[derive(Default)]
+struct Waiter {
+    events: VecDeque<Event>,
+    done: bool,
+}
+
+/// Tracks requests in flight and routes responses to them.
+///
+/// See the [module documentation](self) for an example.
+#[derive(Default)]
+pub struct Correlator {
+    waiters: HashMap<String, Waiter>,
+    // cancelled requests the other side may still answer, by the number of
+    // the cancellation
+    cancelled: HashMap<String, u64>,
+    // the cancellations from old to new, the oldest is forgotten first
+    cancellations: VecDeque<(String, u64)>,
+    next_cancellation: u64,
+}
+
+impl Correlator {
+    /// Creates a correlator without requests in flight.
+    pub fn new() -> Correlator {
+        Correlator::default()
+    }
+
+    /// Starts tracking a request that is about to be sent.
+    ///
+    /// Returns the ID of the request.  Requests without an ID, fire and
+    /// forget requests and requests with the ID of another request in
+    /// flight are rejected.
+    pub fn track(&mut self, req: &Request) -> Result<String, Error> {
+        if req.fire_and_forget() {
+            return Err(Error::new(
+                ErrorKind::InternalError,
+                "fire and forget requests get no response",
+            ));
+        }
+        let request_id = req
+            .request_id()
+            .ok_or_else(|| Error::new(ErrorKind::InternalError, "request has no request ID"))?;
+        if self.waiters.contains_key(request_id) || self.cancelled.contains_key(request_id) {
+            return Err(Error::new(
+                ErrorKind::InternalError,
+                format!("request '{}' is already in flight", request_id),
+            ));
+        }
+        self.waiters
+            .insert(request_id.to_string(), Waiter::default());
+        Ok(request_id.to_string())
+    }
+
+    /// Routes a received response to the request it answers.
+    ///
+    /// Returns the ID of the request.  Responses to requests that were
+    /// cancelled locally are dropped.  Responses without a request ID, to
+    /// unknown requests or to requests that are done fail.
+    pub fn route(&mut self, response: Response) -> Result<String, Error> {
+        let request_id = response
+            .request_id()
+            .ok_or_else(|| Error::new(ErrorKind::InternalError, "response has no request ID"))?
+            .to_string();
+        let kind = response.kind();
+        if self.cancelled.contains_key(&request_id) {
+            if kind != ResponseKind::Progress {
+                self.cancelled.remove(&request_id);
+            }
+            return Ok(request_id);
+        }
+        let waiter = match self.waiters.get_mut(&request_id) {
+            Some(waiter) if !waiter.done => waiter,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InternalError,
+                    format!("response to unknown request '{}'", request_id),
+                ))
+            }
+        };
+        waiter.events.push_back(match kind {
+            ResponseKind::Progress => {
+                Event::Progress(response.into_payload().unwrap_or(Value::Null))
+            }
+            ResponseKind::Final => Event::Completed(response),
+            ResponseKind::Cancelled => Event::Cancelled,
+        });
+        waiter.done = kind != ResponseKind::Progress;
+        Ok(request_id)
+    }
+
+    /// Takes the next event of a request.
+    ///
+    /// Once the final response or the cancellation is taken the request is
+    /// no longer tracked.
+    pub fn poll(&mut self, request_id: &str) -> Option<Event> {
+        let waiter = self.waiters.get_mut(request_id)?;
+        let event = waiter.events.pop_front();
+        if waiter.done && waiter.events.is_empty() {
+            self.waiters.remove(request_id);
+        }
+        event
+    }
+
+    /// Cancels a request in flight.
+    ///
+    /// Events that were not polled yet are dropped and the request is left
+    /// with a [`Event::Cancelled`].  Later responses of the other side are
+    /// ignored until its final response arrives.  As the other side might
+    /// never answer, only the last 1024 cancellations are remembered, late
+    /// responses to older ones fail like responses to unknown requests.
+    /// Returns the request to [`CANCEL_ENDPOINT`] that tells the other side,
+    /// or `None` if the request is not in flight.
+    pub fn cancel(&mut self, request_id: &str) -> Option<Request> {
+        let waiter = self.waiters.get_mut(request_id)?;
+        if waiter.done {
+            return None;
+        }
+        waiter.events.clear();
+        waiter.events.push_back(Event::Cancelled);
+        waiter.done = true;
+        self.remember_cancelled(request_id);
+        Some(Request::new(
+            CANCEL_ENDPOINT,
+            Value::Map(vec![("request_id".into(), request_id.into())]),
+        ))
+    }
+
+    fn remember_cancelled(&mut self, request_id: &str) {
+        let cancellation = self.next_cancellation;
+        self.next_cancellation += 1;
+        self.cancelled.insert(request_id.to_string(), cancellation);
+        self.cancellations
+            .push_back((request_id.to_string(), cancellation));
+        if self.cancellations.len() > MAX_CANCELLED {
+            let (request_id, cancellation) = self.cancellations.pop_front().unwrap();
+            // the ID might have been reused and cancelled again since
+            if self.cancelled.get(&request_id) == Some(&cancellation) {
+                self.cancelled.remove(&request_id);
+            }
+        }
+    }
+
+    /// Checks if a request is tracked and has events or awaits them.
+    pub fn is_tracked(&self, request_id: &str) -> bool {
+        self.waiters.contains_key(request_id)
+    }
+
+    /// Returns the number of requests that still await a response.
+    pub fn in_flight(&self) -> usize {
+        self.waiters.values().filter(|waiter| !waiter.done).count()
+    }
+}
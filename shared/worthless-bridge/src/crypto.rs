@@ -0,0 +1,114 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::types::{Error, ErrorKind, Meta, Value};
+use crate::utils::{deserialize_from_cbor, serialize_to_cbor};
+
+/// The meta key that flags an encrypted payload.
+///
+/// The value is the name of the algorithm, currently always
+/// [`ENCRYPTION_ALGORITHM`].
+pub const ENCRYPTION_META_KEY: &str = "encryption";
+
+/// The algorithm payloads are encrypted with.
+pub const ENCRYPTION_ALGORITHM: &str = "xchacha20poly1305";
+
+const NONCE_SIZE: usize = 24;
+
+/// A symmetric key for encrypting payloads.
+///
+/// The key is exchanged out of band, both sides of the bridge need to use the
+/// same key.  Payloads are encrypted with XChaCha20-Poly1305 using a random
+/// nonce that is prepended to the ciphertext.  The meta of the message (and
+/// with it the request ID and the encryption flag) as well as the endpoint of
+/// requests are authenticated along with the payload, so they cannot be
+/// changed or stripped without failing decryption.
+#[derive(Clone)]
+pub struct PayloadKey {
+    cipher: XChaCha20Poly1305,
+}
+
+impl PayloadKey {
+    /// Creates a key from 32 bytes of key material.
+    pub fn from_bytes(key: &[u8; 32]) -> PayloadKey {
+        PayloadKey {
+            cipher: XChaCha20Poly1305::new(key.into()),
+        }
+    }
+
+    /// Encrypts a value into the bytes of an encrypted payload.
+    pub(crate) fn encrypt<T: Serialize>(
+        &self,
+        value: &T,
+        associated_data: &[u8],
+    ) -> Result<Value, Error> {
+        let plaintext = serialize_to_cbor(value, "payload")?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plaintext,
+                    aad: associated_data,
+                },
+            )
+            .map_err(|_| Error::new(ErrorKind::EncryptionError, "failed to encrypt payload"))?;
+        let mut rv = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        rv.extend_from_slice(&nonce);
+        rv.extend_from_slice(&ciphertext);
+        Ok(Value::Bytes(rv))
+    }
+
+    /// Decrypts the bytes of an encrypted payload.
+    pub(crate) fn decrypt<T: DeserializeOwned>(
+        &self,
+        value: &Value,
+        associated_data: &[u8],
+    ) -> Result<T, Error> {
+        let bytes = match value {
+            Value::Bytes(bytes) if bytes.len() >= NONCE_SIZE => bytes,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::EncryptionError,
+                    "encrypted payload is malformed",
+                ))
+            }
+        };
+        let (nonce, ciphertext) = bytes.split_at(NONCE_SIZE);
+        let plaintext = self
+            .cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: associated_data,
+                },
+            )
+            .map_err(|_| Error::new(ErrorKind::EncryptionError, "failed to decrypt payload"))?;
+        deserialize_from_cbor(&plaintext, "payload")
+    }
+}
+
+/// Returns the data that is authenticated along with an encrypted payload.
+pub(crate) fn associated_data(
+    endpoint: Option<&str>,
+    fire_and_forget: bool,
+    meta: &Meta,
+) -> Result<Vec<u8>, Error> {
+    serialize_to_cbor(&(endpoint, fire_and_forget, meta), "meta")
+}
+
+/// Checks if the meta flags the payload as encrypted.
+pub(crate) fn is_encrypted(meta: &Meta) -> Result<bool, Error> {
+    match meta.get(ENCRYPTION_META_KEY) {
+        None => Ok(false),
+        Some(Value::Text(algorithm)) if algorithm == ENCRYPTION_ALGORITHM => Ok(true),
+        Some(_) => Err(Error::new(
+            ErrorKind::EncryptionError,
+            "unsupported payload encryption",
+        )),
+    }
+}
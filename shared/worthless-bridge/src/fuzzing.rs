@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::types::{Error, ErrorKind, Meta, Request, Response, ResponseKind, Value};
+
+/// How deep arbitrary values nest at most.
+const MAX_DEPTH: usize = 8;
+
+/// How many items arbitrary arrays, maps and metas have at most.
+const MAX_ITEMS: usize = 16;
+
+/// Creates an arbitrary value.
+///
+/// Values nest up to a fixed depth, floats can be NaN and integers cover
+/// the full range of CBOR integers.  Tags can be anything but the bignum
+/// tags 2 and 3, which would not round trip.
+pub fn arbitrary_value(u: &mut Unstructured<'_>) -> Result<Value> {
+    arbitrary_value_impl(u, 0)
+}
+
+fn arbitrary_value_impl(u: &mut Unstructured<'_>, depth: usize) -> Result<Value> {
+    let max_choice = if depth >= MAX_DEPTH { 6 } else { 9 };
+    Ok(match u.int_in_range(0..=max_choice)? {
+        0 => Value::Null,
+        1 => Value::Bool(u.arbitrary()?),
+        2 => Value::Integer(u.arbitrary::<i64>()?.into()),
+        3 => Value::Integer(u.arbitrary::<u64>()?.into()),
+        4 => Value::Float(u.arbitrary()?),
+        5 => Value::Text(u.arbitrary()?),
+        6 => Value::Bytes(u.arbitrary()?),
+        7 => {
+            // bignums are read back as integers, so their tags are skipped
+            let tag = match u.arbitrary()? {
+                tag @ (2 | 3) => tag + 2,
+                tag => tag,
+            };
+            Value::Tag(tag, Box::new(arbitrary_value_impl(u, depth + 1)?))
+        }
+        8 => {
+            let mut items = Vec::new();
+            for _ in 0..u.int_in_range(0..=MAX_ITEMS)? {
+                items.push(arbitrary_value_impl(u, depth + 1)?);
+            }
+            Value::Array(items)
+        }
+        _ => {
+            let mut items = Vec::new();
+            for _ in 0..u.int_in_range(0..=MAX_ITEMS)? {
+                items.push((
+                    arbitrary_value_impl(u, depth + 1)?,
+                    arbitrary_value_impl(u, depth + 1)?,
+                ));
+            }
+            Value::Map(items)
+        }
+    })
+}
+
+fn arbitrary_meta(u: &mut Unstructured<'_>) -> Result<Meta> {
+    let mut meta = BTreeMap::new();
+    for _ in 0..u.int_in_range(0..=MAX_ITEMS)? {
+        meta.insert(u.arbitrary()?, arbitrary_value(u)?);
+    }
+    Ok(meta)
+}
+
+impl<'a> Arbitrary<'a> for ErrorKind {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<ErrorKind> {
+        // known kinds are picked directly, random codes would hardly ever
+        // hit them
+        if u.arbitrary()? {
+            Ok(*u.choose(&[
+                ErrorKind::UnknownEndpoint,
+                ErrorKind::PayloadTooLarge,
+                ErrorKind::RateLimited,
+                ErrorKind::InternalError,
+                ErrorKind::EncryptionError,
+                ErrorKind::SerializationError,
+            ])?)
+        } else {
+            Ok(ErrorKind::Other(u.arbitrary()?))
+        }
+    }
+}
+
+impl<'a> Arbitrary<'a> for ResponseKind {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<ResponseKind> {
+        Ok(*u.choose(&[
+            ResponseKind::Final,
+            ResponseKind::Progress,
+            ResponseKind::Cancelled,
+        ])?)
+    }
+}
+
+impl<'a> Arbitrary<'a> for Error {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Error> {
+        let mut err = Error::new(u.arbitrary()?, u.arbitrary::<String>()?);
+        if u.arbitrary()? {
+            err = err.with_detail(arbitrary_value(u)?);
+        }
+        Ok(err)
+    }
+}
+
+impl<'a> Arbitrary<'a> for Request {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Request> {
+        let mut builder = Request::build(u.arbitrary()?);
+        builder
+            .raw_payload(arbitrary_value(u)?)
+            .fire_and_forget(u.arbitrary()?);
+        for (key, value) in arbitrary_meta(u)? {
+            builder.meta(key, value);
+        }
+        Ok(builder.build())
+    }
+}
+
+impl<'a> Arbitrary<'a> for Response {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Response> {
+        let mut builder = Response::builder();
+        if u.arbitrary()? {
+            builder.raw_payload(arbitrary_value(u)?);
+        } else {
+            builder.error(u.arbitrary()?);
+        }
+        builder.kind(u.arbitrary()?);
+        for (key, value) in arbitrary_meta(u)? {
+            builder.meta(key, value);
+        }
+        Ok(builder.build())
+    }
+}
@@ -0,0 +1,124 @@
+//! Async handlers for requests on the bridge.
+//!
+//! A [`Handler`] answers a request with a future, so endpoints can be
+//! implemented as async functions with [`handler_fn`].  A [`Layer`] wraps a
+//! handler into another one, which is how middleware such as the
+//! [`RateLimiter`] composes with handlers:
+//!
+//! ```
+//! use std::time::Duration;
+//! use worthless_bridge::{handler_fn, Handler, RateLimiter, Request, Value};
+//!
+//! let handler = handler_fn(|req: Request| async move {
+//!     Ok(Value::from(format!("hello {}", req.payload().as_text().unwrap_or("?"))))
+//! })
+//! .with_layer(&RateLimiter::new(10, Duration::from_secs(1)).into_layer());
+//! ```
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::rate_limit::RateLimiter;
+use crate::types::{Error, Request, Response, Value};
+
+/// Answers requests asynchronously.
+///
+/// Implementations can use `async fn handle(&self, req: Request) -> Response`.
+pub trait Handler: Send + Sync {
+    /// Handles a request and produces its response.
+    fn handle(&self, req: Request) -> impl Future<Output = Response> + Send;
+
+    /// Wraps the handler with a layer.
+    fn with_layer<L: Layer<Self>>(self, layer: &L) -> L::Handler
+    where
+        Self: Sized,
+    {
+        layer.layer(self)
+    }
+}
+
+impl<H: Handler> Handler for Arc<H> {
+    fn handle(&self, req: Request) -> impl Future<Output = Response> + Send {
+        (**self).handle(req)
+    }
+}
+
+/// Wraps a handler into another handler, eg: to add middleware.
+pub trait Layer<H> {
+    /// The handler produced by the layer.
+    type Handler: Handler;
+
+    /// Wraps a handler.
+    fn layer(&self, inner: H) -> Self::Handler;
+}
+
+/// A handler created with [`handler_fn`].
+#[derive(Clone)]
+pub struct HandlerFn<F> {
+    f: F,
+}
+
+/// Creates a handler from an async function.
+///
+/// The function resolves to the payload or error of the response like the
+/// functions registered as endpoints.
+pub fn handler_fn<F, Fut>(f: F) -> HandlerFn<F>
+where
+    F: Fn(Request) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Value, Error>> + Send,
+{
+    HandlerFn { f }
+}
+
+impl<F, Fut> Handler for HandlerFn<F>
+where
+    F: Fn(Request) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Value, Error>> + Send,
+{
+    async fn handle(&self, req: Request) -> Response {
+        Response::new(Default::default(), (self.f)(req).await)
+    }
+}
+
+/// A layer that rate limits requests, see [`RateLimiter::into_layer`].
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limiter: Arc<RateLimiter>,
+}
+
+/// A handler wrapped by a [`RateLimitLayer`].
+pub struct RateLimited<H> {
+    limiter: Arc<RateLimiter>,
+    inner: H,
+}
+
+impl RateLimiter {
+    /// Turns the limiter into a layer for handlers.
+    ///
+    /// All handlers wrapped by the layer share the buckets of the limiter.
+    pub fn into_layer(self) -> RateLimitLayer {
+        RateLimitLayer {
+            limiter: Arc::new(self),
+        }
+    }
+}
+
+impl<H: Handler> Layer<H> for RateLimitLayer {
+    type Handler = RateLimited<H>;
+
+    fn layer(&self, inner: H) -> RateLimited<H> {
+        RateLimited {
+            limiter: self.limiter.clone(),
+            inner,
+        }
+    }
+}
+
+impl<H: Handler> Handler for RateLimited<H> {
+    async fn handle(&self, req: Request) -> Response {
+        // the error is not `Send` so it must not live across the await
+        if let Err(err) = self.limiter.check(&req) {
+            return Response::new(Default::default(), Err(err));
+        }
+        self.inner.handle(req).await
+    }
+}
@@ -1,6 +1,28 @@
+mod correlator;
+#[cfg(feature = "encryption")]
+mod crypto;
+#[cfg(feature = "arbitrary")]
+mod fuzzing;
+mod handler;
+mod rate_limit;
+#[cfg(feature = "testing")]
+pub mod testing;
 mod types;
 mod utils;
 
+pub use self::correlator::{Correlator, Event};
+#[cfg(feature = "encryption")]
+pub use self::crypto::{PayloadKey, ENCRYPTION_ALGORITHM, ENCRYPTION_META_KEY};
+#[cfg(feature = "arbitrary")]
+pub use self::fuzzing::arbitrary_value;
+pub use self::handler::{handler_fn, Handler, HandlerFn, Layer, RateLimitLayer, RateLimited};
+pub use self::rate_limit::RateLimiter;
 pub use self::types::{
-    Error, ErrorKind, Meta, Request, RequestBuilder, Response, ResponseBuilder, Value,
+    Error, ErrorKind, Meta, Request, RequestBuilder, Response, ResponseBuilder, ResponseKind,
+    Value, BUNDLE_ENDPOINT, CANCEL_ENDPOINT, CONFIG_ENV_PREFIX, CONFIG_META_KEY, CONFIG_TOPIC,
+    COVERAGE_ENDPOINT, COVERAGE_ENV_VAR, DEBUG_ENDPOINT, DEBUG_ENV_VAR, DEBUG_PAUSED_ENDPOINT,
+    ENDPOINTS_ENDPOINT, GC_ENDPOINT, MAX_REQUEST_SIZE_ENV_VAR, MODULE_ENDPOINT, PANIC_META_KEY,
+    PROFILE_ENDPOINT, PROTOCOL_SECTION, PROTOCOL_VERSION, PUBLISH_ENDPOINT, SHUTDOWN_TOPIC,
+    STREAM_ENDPOINT, SUBSCRIBE_ENDPOINT, TICK_ENDPOINT, UNSUBSCRIBE_ENDPOINT,
 };
+pub use self::utils::{to_canonical_cbor, Encoding};
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::types::{Error, ErrorKind, Request, Value};
+
+/// A token bucket rate limiter for requests.
+///
+/// Each bucket holds up to `capacity` tokens and is refilled at a steady rate
+/// so that `capacity` requests are admitted per period.  Requests that find
+/// their bucket empty are rejected with [`ErrorKind::RateLimited`].  By
+/// default all requests share one bucket, buckets can be split by endpoint
+/// and by a meta key such as a tenant ID.
+///
+/// The limiter works on plain requests so it can guard host endpoints (see
+/// [`wrap`](Self::wrap)) as well as the router of the guest.
+pub struct RateLimiter {
+    capacity: f64,
+    period: Duration,
+    refill_per_sec: f64,
+    per_endpoint: bool,
+    meta_key: Option<String>,
+    buckets: Mutex<Buckets>,
+}
+
+/// The endpoint and the meta value a bucket is for.
+type BucketKey = (Option<String>, Option<String>);
+
+struct Buckets {
+    map: HashMap<BucketKey, Bucket>,
+    swept: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter that admits `capacity` requests per `period`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the period is zero.
+    pub fn new(capacity: u32, period: Duration) -> RateLimiter {
+        assert!(!period.is_zero(), "rate limit period must not be zero");
+        RateLimiter {
+            capacity: capacity as f64,
+            period,
+            refill_per_sec: capacity as f64 / period.as_secs_f64(),
+            per_endpoint: false,
+            meta_key: None,
+            buckets: Mutex::new(Buckets {
+                map: HashMap::new(),
+                swept: Instant::now(),
+            }),
+        }
+    }
+
+    /// Uses a separate bucket per endpoint.
+    pub fn per_endpoint(&mut self, yes: bool) -> &mut Self {
+        self.per_endpoint = yes;
+        self
+    }
+
+    /// Uses a separate bucket per value of a meta key.
+    ///
+    /// Requests without the meta key share a bucket.
+    pub fn per_meta_key(&mut self, key: &str) -> &mut Self {
+        self.meta_key = Some(key.to_string());
+        self
+    }
+
+    /// Returns the number of buckets that are tracked.
+    ///
+    /// Buckets that were not used for a whole period are full again and are
+    /// dropped, so this stays bounded by the keys seen within a period.
+    pub fn buckets(&self) -> usize {
+        self.buckets.lock().unwrap().map.len()
+    }
+
+    /// Takes a token for a request.
+    pub fn check(&self, req: &Request) -> Result<(), Error> {
+        let now = Instant::now();
+        let key = (
+            self.per_endpoint.then(|| req.endpoint().to_string()),
+            self.meta_key
+                .as_ref()
+                .and_then(|key| req.meta().get(key))
+                .map(|value| match value {
+                    Value::Text(value) => value.clone(),
+                    other => format!("{:?}", other),
+                }),
+        );
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.saturating_duration_since(buckets.swept) >= self.period {
+            let period = self.period;
+            buckets
+                .map
+                .retain(|_, bucket| now.saturating_duration_since(bucket.updated) < period);
+            buckets.swept = now;
+        }
+        let bucket = buckets.map.entry(key).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::RateLimited,
+                format!("rate limit exceeded for '{}'", req.endpoint()),
+            ))
+        }
+    }
+
+    /// Wraps an endpoint function so requests are rate limited.
+    pub fn wrap<F>(self, f: F) -> impl Fn(&Request) -> Result<Value, Error> + Send + Sync
+    where
+        F: Fn(&Request) -> Result<Value, Error> + Send + Sync,
+    {
+        move |req| {
+            self.check(req)?;
+            f(req)
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Utilities for testing endpoint code without a WASM runtime.
+//!
+//! A [`MockRouter`] answers requests from a list of expectations and a
+//! [`MemoryTransport`] connects a handler through the wire format so that
+//! serialization issues show up in tests as well:
+//!
+//! ```
+//! use worthless_bridge::testing::{MemoryTransport, MockRouter};
+//! use worthless_bridge::{Request, Value};
+//!
+//! let mut router = MockRouter::new();
+//! router.expect_request("kv.get").respond("value");
+//! let transport = MemoryTransport::new(|req: &Request| router.handle(req));
+//! let response = transport.send(&Request::new("kv.get", "key")).unwrap();
+//! assert_eq!(response.into_payload().unwrap(), Value::from("value"));
+//! router.assert_done();
+//! ```
+use std::sync::Mutex;
+
+use crate::types::{Error, ErrorKind, Request, Response, Value};
+
+type Responder = dyn Fn(&Request) -> Result<Value, Error> + Send + Sync;
+
+/// An expected request registered with [`MockRouter::expect_request`].
+pub struct Expectation {
+    endpoint: String,
+    payload: Option<Value>,
+    responder: Box<Responder>,
+}
+
+impl Expectation {
+    /// Only matches requests with this payload.
+    pub fn with_payload<V: Into<Value>>(&mut self, payload: V) -> &mut Self {
+        self.payload = Some(payload.into());
+        self
+    }
+
+    /// Responds with a payload.
+    pub fn respond<V: Into<Value>>(&mut self, payload: V) -> &mut Self {
+        let payload = payload.into();
+        self.responder = Box::new(move |_| Ok(payload.clone()));
+        self
+    }
+
+    /// Responds with an error.
+    pub fn respond_error<S: Into<String>>(&mut self, kind: ErrorKind, description: S) -> &mut Self {
+        let description = description.into();
+        self.responder = Box::new(move |_| Err(Error::new(kind, description.clone())));
+        self
+    }
+
+    /// Responds with the result of a function.
+    pub fn respond_with<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&Request) -> Result<Value, Error> + Send + Sync + 'static,
+    {
+        self.responder = Box::new(f);
+        self
+    }
+
+    fn check(&self, req: &Request) -> Result<(), String> {
+        if req.endpoint() != self.endpoint {
+            return Err(format!(
+                "expected request to '{}', got '{}'",
+                self.endpoint,
+                req.endpoint()
+            ));
+        }
+        match self.payload {
+            Some(ref payload) if payload != req.payload() => Err(format!(
+                "unexpected payload for '{}': expected {:?}, got {:?}",
+                self.endpoint,
+                payload,
+                req.payload()
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Answers requests from a list of expectations.
+///
+/// Requests have to arrive in the order the expectations were registered.
+/// Unexpected requests are answered with an error and reported by
+/// [`assert_done`](Self::assert_done).
+#[derive(Default)]
+pub struct MockRouter {
+    expectations: Vec<Expectation>,
+    state: Mutex<RouterState>,
+}
+
+#[derive(Default)]
+struct RouterState {
+    matched: usize,
+    received: Vec<Request>,
+    failures: Vec<String>,
+}
+
+impl MockRouter {
+    /// Creates a router without expectations.
+    pub fn new() -> MockRouter {
+        MockRouter::default()
+    }
+
+    /// Expects a request to an endpoint.
+    ///
+    /// Without further configuration the request is answered with `null`.
+    pub fn expect_request(&mut self, endpoint: &str) -> &mut Expectation {
+        self.expectations.push(Expectation {
+            endpoint: endpoint.to_string(),
+            payload: None,
+            responder: Box::new(|_| Ok(Value::Null)),
+        });
+        self.expectations.last_mut().unwrap()
+    }
+
+    /// Handles a request.
+    pub fn handle(&self, req: &Request) -> Response {
+        let mut state = self.state.lock().unwrap();
+        state.received.push(req.clone());
+        let result = match self.expectations.get(state.matched) {
+            Some(expectation) => match expectation.check(req) {
+                Ok(()) => {
+                    state.matched += 1;
+                    Ok(expectation)
+                }
+                Err(msg) => Err(msg),
+            },
+            None => Err(format!("unexpected request to '{}'", req.endpoint())),
+        };
+        let payload = match result {
+            Ok(expectation) => (expectation.responder)(req),
+            Err(msg) => {
+                state.failures.push(msg.clone());
+                Err(Error::new(ErrorKind::InternalError, msg))
+            }
+        };
+        Response::new(Default::default(), payload)
+    }
+
+    /// Returns the requests the router received so far.
+    pub fn received(&self) -> Vec<Request> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    /// Panics unless all expectations were met and no unexpected request
+    /// was received.
+    #[track_caller]
+    pub fn assert_done(&self) {
+        let state = self.state.lock().unwrap();
+        if let Some(msg) = state.failures.first() {
+            panic!("mock router failed: {}", msg);
+        }
+        if let Some(expectation) = self.expectations.get(state.matched) {
+            panic!(
+                "mock router failed: expected request to '{}' was not received",
+                expectation.endpoint
+            );
+        }
+    }
+}
+
+/// An in-memory transport that passes messages through the wire format.
+///
+/// Requests are serialized, handed to the handler on the other side and the
+/// response takes the same route back.
+pub struct MemoryTransport<F> {
+    handler: F,
+}
+
+impl<F> MemoryTransport<F>
+where
+    F: Fn(&Request) -> Response,
+{
+    /// Connects a handler.
+    pub fn new(handler: F) -> MemoryTransport<F> {
+        MemoryTransport { handler }
+    }
+
+    /// Sends a request and returns the response.
+    pub fn send(&self, req: &Request) -> Result<Response, Error> {
+        let req = Request::deserialize(&req.serialize()?)?;
+        let response = (self.handler)(&req);
+        Response::deserialize(&response.serialize()?)
+    }
+}
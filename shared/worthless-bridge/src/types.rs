@@ -1,10 +1,28 @@
 use std::collections::BTreeMap;
 use std::fmt;
+use std::io::{Read, Write};
 
 use serde::de::DeserializeOwned;
-use serde::{Deserialize, Serialize};
-
-use crate::utils::{deserialize_from_cbor, serialize_to_cbor};
+use serde::{Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
+
+#[cfg(feature = "encryption")]
+use crate::crypto::{
+    associated_data, is_encrypted, PayloadKey, ENCRYPTION_ALGORITHM, ENCRYPTION_META_KEY,
+};
+use crate::utils::{
+    deserialize_from_cbor, deserialize_from_reader, serialize_to_cbor, serialize_to_cbor_with,
+    serialize_to_writer, Encoding,
+};
+
+/// The version of the protocol spoken by this side of the bridge.
+///
+/// Every message carries the version of its sender, messages from before
+/// versioning was introduced count as version `0`.  Receivers accept
+/// messages of any version and skip fields they don't know, so new features
+/// can be added without breaking older peers.  The version tells a side what
+/// the other one understands.
+pub const PROTOCOL_VERSION: u32 = 1;
 
 /// The type for arbitrary values.
 pub type Value = ciborium::value::Value;
@@ -12,17 +30,191 @@ pub type Value = ciborium::value::Value;
 /// Represents the meta part of the protocol.
 pub type Meta = BTreeMap<String, Value>;
 
+/// The meta key that holds the ID of a request.
+///
+/// Responses carry the ID of the request they answer in the same key.
+const REQUEST_ID_KEY: &str = "request_id";
+
+/// The meta key of a response that marks it as an intermediate message.
+const RESPONSE_KIND_KEY: &str = "response_kind";
+
+/// The meta key of a request that holds per-invocation config.
+///
+/// The config is a map that the guest overlays over the config from its
+/// environment.
+pub const CONFIG_META_KEY: &str = "config";
+
+/// Environment variables with this prefix are exposed as config.
+pub const CONFIG_ENV_PREFIX: &str = "WORTHLESS_CONFIG_";
+
+/// The control endpoint the host invokes to drive the timers of the guest.
+///
+/// The payload carries the current time of the host in milliseconds as `now`
+/// and the guest responds with the time of the next due timer as
+/// `next_deadline`.
+pub const TICK_ENDPOINT: &str = "__tick";
+
+/// The control endpoint the host invokes to collect garbage in the guest.
+///
+/// If the payload carries a `watermark` the guest also collects garbage on
+/// its own whenever the memory of the runtime exceeds that many bytes after
+/// a request, `null` turns that off.  The guest responds with the memory in
+/// use `before` and `after` the collection.
+pub const GC_ENDPOINT: &str = "__gc";
+
+/// The control endpoint the host invokes to push data into a guest stream.
+///
+/// The payload carries the id of the `stream` and either a `chunk` of bytes,
+/// `done` to close the stream or an `error` message to fail it.  The guest
+/// responds with the `desired_size` of the stream, the host should hold off
+/// while it's zero or less, and whether the plugin `cancelled` the stream.
+pub const STREAM_ENDPOINT: &str = "__stream";
+
+/// The control endpoint the host invokes to profile the guest.
+///
+/// The payload carries the `action`.  `start` starts sampling the JavaScript
+/// code every `interval` microseconds, `stop` stops it and responds with the
+/// number of `samples` and the `profile` exported in the requested `format`
+/// which is either `collapsed` (the default) or `speedscope`.
+pub const PROFILE_ENDPOINT: &str = "__profile";
+
+/// The control endpoint the host invokes to drive the debugger of the guest.
+///
+/// The debugger is only attached if the guest was started with
+/// [`DEBUG_ENV_VAR`] set.  The payload carries the `action`:
+/// `set_breakpoint` and `remove_breakpoint` with a `file` and `line`,
+/// `clear_breakpoints`, `pause_on_exceptions` with `enabled` and `pause`
+/// which pauses at the next statement.  Setting a breakpoint responds with
+/// the `line` it moved to if the file was loaded already.
+pub const DEBUG_ENDPOINT: &str = "__debug";
+
+/// The host endpoint the guest invokes whenever its debugger pauses.
+///
+/// The payload carries the `reason`, the `file` and `line` that is paused
+/// at, the `stack` as a list of frames with `function`, `file`, `line` and
+/// `column`, the variables in `scope` and the `exception` when pausing on
+/// one.  Values are previews formatted as text.  The host responds with the
+/// `action` to take: `continue`, `step_over`, `step_into`, `step_out`,
+/// `evaluate` with an `expression` or any action of [`DEBUG_ENDPOINT`]
+/// other than `pause`.  After the actions that do not resume the guest
+/// stays paused and invokes the endpoint again with the `result` or
+/// `error`, evaluations result in a preview.
+pub const DEBUG_PAUSED_ENDPOINT: &str = "debug.paused";
+
+/// The environment variable that makes the guest attach a debugger.
+///
+/// Only code that is evaluated after the debugger was attached can be
+/// debugged, so it has to be set before the plugin is first invoked.
+pub const DEBUG_ENV_VAR: &str = "WORTHLESS_DEBUG";
+
+/// The environment variable that makes the guest record coverage.
+///
+/// Like [`DEBUG_ENV_VAR`] it only covers code evaluated after it was read,
+/// so it has to be set before the plugin is first invoked.
+pub const COVERAGE_ENV_VAR: &str = "WORTHLESS_COVERAGE";
+
+/// The control endpoint the host invokes to collect the coverage of the
+/// guest.
+///
+/// The guest responds with the `scripts` it recorded coverage for since
+/// [`COVERAGE_ENV_VAR`] was read.  Every script has its `filename`, the
+/// `lines` as pairs of line and hits and the `functions` as triples of name,
+/// line and hits.
+pub const COVERAGE_ENDPOINT: &str = "__coverage";
+
+/// The environment variable with the maximum size of a request in bytes.
+///
+/// The guest reads it when it handles its first request and answers larger
+/// requests with a payload too large error.
+pub const MAX_REQUEST_SIZE_ENV_VAR: &str = "WORTHLESS_MAX_REQUEST_SIZE";
+
+/// The control endpoint either side invokes to cancel a request in flight.
+///
+/// The payload carries the `request_id` of the request.  The other side
+/// should stop working on it and may answer the request with a
+/// [`ResponseKind::Cancelled`] response.  See
+/// [`Correlator::cancel`](crate::Correlator::cancel).
+pub const CANCEL_ENDPOINT: &str = "__cancel";
+
+/// The host endpoint the guest invokes to subscribe to a topic.
+///
+/// The fire and forget request carries the `topic`.  The host only publishes
+/// events of topics the plugin subscribed to, see [`PUBLISH_ENDPOINT`].
+pub const SUBSCRIBE_ENDPOINT: &str = "__subscribe";
+
+/// The host endpoint the guest invokes to unsubscribe from a topic.
+///
+/// The fire and forget request carries the `topic`.
+pub const UNSUBSCRIBE_ENDPOINT: &str = "__unsubscribe";
+
+/// The control endpoint the host invokes to publish an event to the guest.
+///
+/// The payload carries the `topic` and the `payload` of the event.  The guest
+/// passes the event to the listeners subscribed to the topic and responds
+/// with the number of listeners it was `delivered` to.
+pub const PUBLISH_ENDPOINT: &str = "__publish";
+
+/// The topic the host publishes changes of the config on.
+///
+/// The payload carries the `key` and the `value` that was set.
+pub const CONFIG_TOPIC: &str = "config";
+
+/// The topic the host publishes on before it shuts a plugin down.
+///
+/// The payload is null.
+pub const SHUTDOWN_TOPIC: &str = "shutdown";
+
+/// The control endpoint the host invokes to list the endpoints of the guest.
+///
+/// The guest responds with the sorted names of the `endpoints` its handlers
+/// are registered for.
+pub const ENDPOINTS_ENDPOINT: &str = "__endpoints";
+
+/// The custom section the guest records its [`PROTOCOL_VERSION`] in.
+///
+/// The version is stored as a little endian `u32` so that tools can read it
+/// without running the plugin.
+pub const PROTOCOL_SECTION: &str = "worthless-protocol";
+
+/// The host endpoint the runtime loads its bundle from.
+///
+/// It's served by the host for plugins that carry their bundle in a custom
+/// section.  The response has the `name`, `format` and `data` of the bundle.
+pub const BUNDLE_ENDPOINT: &str = "bundle.load";
+
+/// The host endpoint the runtime loads the modules of a bundle from.
+///
+/// Bundles in the `module` format are evaluated as a module and import
+/// other modules through this endpoint.  The payload carries the `name` of
+/// the module with relative imports resolved and the host responds with its
+/// `source`.
+pub const MODULE_ENDPOINT: &str = "module.load";
+
+/// The meta key of the response the guest sends when it panics.
+///
+/// Before the guest aborts it answers the request in flight with an error
+/// that has the panic `message`, `file`, `line` and `column` in its detail
+/// and this key set to `true`.  The host finds it after the trap to report
+/// the panic.
+pub const PANIC_META_KEY: &str = "panic";
+
 /// Represents the request to an endpoint on the bridge.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[cfg_attr(feature = "debug", derive(Debug))]
 pub struct Request {
+    /// The protocol version of the sender.
+    #[serde(default)]
+    version: u32,
     /// key/value pairs of meta information.
+    #[serde(default)]
     meta: BTreeMap<String, Value>,
     /// When flipped tells the remote side that no response is requested.
+    #[serde(default)]
     fire_and_forget: bool,
     /// The name of the endpoint to invoke.
     endpoint: String,
     /// The request payload.
+    #[serde(default = "null")]
     payload: Value,
 }
 
@@ -35,7 +227,11 @@ pub struct RequestBuilder {
 #[derive(Serialize, Deserialize)]
 #[cfg_attr(feature = "debug", derive(Debug))]
 pub struct Response {
+    /// The protocol version of the sender.
+    #[serde(default)]
+    version: u32,
     /// Meta information not contained in the payload.
+    #[serde(default)]
     meta: Meta,
     /// The response payload.
     payload: Result<Value, Error>,
@@ -56,16 +252,37 @@ pub struct Error {
     /// The general kind of error
     kind: ErrorKind,
     /// A human readable description of the error.
+    #[serde(default)]
     description: String,
     /// Optional detail information about the error.
+    #[serde(default)]
     detail: Option<Value>,
     /// A source error.
     #[serde(skip)]
     source: Option<Box<dyn std::error::Error>>,
 }
 
+/// Indicates what a response means for the request it answers.
+///
+/// Many responses can be sent for one request: any number of progress
+/// messages followed by either the final response or the acknowledgement of
+/// a cancellation.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "debug", derive(Debug))]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseKind {
+    /// The final response that completes the request.
+    Final,
+    /// An intermediate message, the payload reports progress.
+    Progress,
+    /// The request was cancelled and will not complete.
+    Cancelled,
+}
+
 /// Indicates the kind of an error.
-#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+///
+/// Kinds added by newer peers are received as [`ErrorKind::InternalError`].
+#[derive(Serialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[cfg_attr(feature = "debug", derive(Debug))]
 #[repr(u32)]
 #[serde(rename_all = "snake_case")]
@@ -73,9 +290,18 @@ pub enum ErrorKind {
     /// The request went to an unknown endpoint.
     UnknownEndpoint = 404,
 
+    /// The request was larger than the receiver accepts.
+    PayloadTooLarge = 413,
+
+    /// The request was rejected by a rate limiter.
+    RateLimited = 429,
+
     /// An internal error
     InternalError = 500,
 
+    /// Unable to encrypt or decrypt a payload.
+    EncryptionError = 998,
+
     /// Unable to serialize a request or response.
     SerializationError = 999,
 
@@ -85,12 +311,16 @@ pub enum ErrorKind {
 
 impl Request {
     /// Creates a basic request to an endpoint
+    ///
+    /// The request has no ID, use [`RequestBuilder::random_request_id`] to
+    /// correlate log messages with it.
     pub fn new<S, V>(endpoint: S, payload: V) -> Request
     where
         S: Into<String>,
         V: Into<Value>,
     {
         Request {
+            version: PROTOCOL_VERSION,
             meta: BTreeMap::new(),
             fire_and_forget: false,
             endpoint: endpoint.into(),
@@ -110,16 +340,69 @@ impl Request {
         serialize_to_cbor(self, "request")
     }
 
+    /// Serializes a request with a specific encoding.
+    pub fn serialize_with(&self, encoding: Encoding) -> Result<Vec<u8>, Error> {
+        serialize_to_cbor_with(self, "request", encoding)
+    }
+
+    /// Serializes a request in the wire format into a writer.
+    pub fn serialize_to<W: Write>(&self, writer: W) -> Result<(), Error> {
+        serialize_to_writer(self, writer, "request")
+    }
+
+    /// Serializes a request into a reusable buffer.
+    ///
+    /// The buffer is cleared first, so the same allocation can be used for
+    /// many messages.
+    pub fn serialize_into(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
+        buf.clear();
+        serialize_to_writer(self, buf, "request")
+    }
+
     /// Deserializes the request from the wire format.
     pub fn deserialize(bytes: &[u8]) -> Result<Request, Error> {
         deserialize_from_cbor(bytes, "request")
     }
 
+    /// Deserializes a single request from a reader.
+    ///
+    /// As the wire format is self delimiting, this can be called repeatedly
+    /// to read a sequence of requests from a stream.
+    pub fn deserialize_from<R: Read>(reader: R) -> Result<Request, Error> {
+        deserialize_from_reader(reader, "request")
+    }
+
+    /// Returns the protocol version of the sender.
+    ///
+    /// Requests built on this side carry [`PROTOCOL_VERSION`].
+    ///
+    /// ```
+    /// use worthless_bridge::{Request, Value, PROTOCOL_VERSION};
+    ///
+    /// let req = Request::new("ping", Value::Null);
+    /// let req = Request::deserialize(&req.serialize().unwrap()).unwrap();
+    /// assert_eq!(req.version(), PROTOCOL_VERSION);
+    /// ```
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
     /// Returns the meta object of the request.
     pub fn meta(&self) -> &BTreeMap<String, Value> {
         &self.meta
     }
 
+    /// Returns the ID of the request.
+    ///
+    /// The ID is used to correlate messages that were caused by a request
+    /// (eg: log messages) with the request.
+    pub fn request_id(&self) -> Option<&str> {
+        match self.meta.get(REQUEST_ID_KEY) {
+            Some(Value::Text(request_id)) => Some(request_id.as_str()),
+            _ => None,
+        }
+    }
+
     /// Returns the endpoint targeted by the request.
     pub fn endpoint(&self) -> &str {
         self.endpoint.as_ref()
@@ -145,6 +428,43 @@ impl Request {
     pub fn fire_and_forget(&self) -> bool {
         self.fire_and_forget
     }
+
+    /// Encrypts the payload with a key.
+    ///
+    /// The payload is replaced by the encrypted bytes and flagged in the meta.
+    /// The endpoint and meta are authenticated, so they must not change
+    /// until the payload is decrypted.
+    #[cfg(feature = "encryption")]
+    pub fn encrypt_payload(&mut self, key: &PayloadKey) -> Result<(), Error> {
+        if is_encrypted(&self.meta)? {
+            return Ok(());
+        }
+        self.meta.insert(
+            ENCRYPTION_META_KEY.to_string(),
+            Value::Text(ENCRYPTION_ALGORITHM.to_string()),
+        );
+        let aad = associated_data(Some(&self.endpoint), self.fire_and_forget, &self.meta)?;
+        self.payload = key.encrypt(&self.payload, &aad)?;
+        Ok(())
+    }
+
+    /// Decrypts the payload.
+    ///
+    /// Whoever holds a key only accepts encrypted requests, so this fails if
+    /// the payload is not encrypted or the request was tampered with.
+    #[cfg(feature = "encryption")]
+    pub fn decrypt_payload(&mut self, key: &PayloadKey) -> Result<(), Error> {
+        if !is_encrypted(&self.meta)? {
+            return Err(Error::new(
+                ErrorKind::EncryptionError,
+                "request payload is not encrypted",
+            ));
+        }
+        let aad = associated_data(Some(&self.endpoint), self.fire_and_forget, &self.meta)?;
+        self.payload = key.decrypt(&self.payload, &aad)?;
+        self.meta.remove(ENCRYPTION_META_KEY);
+        Ok(())
+    }
 }
 
 impl RequestBuilder {
@@ -176,6 +496,19 @@ impl RequestBuilder {
         self
     }
 
+    /// Sets the ID of the request.
+    pub fn request_id<S: Into<String>>(&mut self, request_id: S) -> &mut RequestBuilder {
+        self.meta(REQUEST_ID_KEY, request_id.into())
+    }
+
+    /// Sets a random UUID as the ID of the request.
+    ///
+    /// The ID is used to correlate messages that were caused by the request
+    /// (eg: log messages) with it.
+    pub fn random_request_id(&mut self) -> &mut RequestBuilder {
+        self.request_id(Uuid::new_v4().to_string())
+    }
+
     /// Inserts a key/value pair into the meta dictionary.
     pub fn meta<K, V>(&mut self, key: K, value: V) -> &mut RequestBuilder
     where
@@ -186,6 +519,31 @@ impl RequestBuilder {
         self
     }
 
+    /// Sets a config value for this invocation.
+    ///
+    /// The value is added to the map in the [`CONFIG_META_KEY`] meta key,
+    /// replacing a value with the same key.
+    pub fn config<K, V>(&mut self, key: K, value: V) -> &mut RequestBuilder
+    where
+        K: Into<String>,
+        V: Into<Value>,
+    {
+        let key = key.into();
+        let config = self
+            .request_mut()
+            .meta
+            .entry(CONFIG_META_KEY.to_string())
+            .or_insert_with(|| Value::Map(Vec::new()));
+        if !config.is_map() {
+            *config = Value::Map(Vec::new());
+        }
+        if let Value::Map(items) = config {
+            items.retain(|(item_key, _)| item_key.as_text() != Some(key.as_str()));
+            items.push((Value::Text(key), value.into()));
+        }
+        self
+    }
+
     /// Creates a request out of the builder.
     ///
     /// The builder at this point is no longer usable and will panic if it's
@@ -198,7 +556,11 @@ impl RequestBuilder {
 impl Response {
     /// Creates a new response.
     pub fn new(meta: BTreeMap<String, Value>, payload: Result<Value, Error>) -> Response {
-        Response { meta, payload }
+        Response {
+            version: PROTOCOL_VERSION,
+            meta,
+            payload,
+        }
     }
 
     /// Create a response builder for more complex responses.
@@ -208,11 +570,34 @@ impl Response {
         }
     }
 
+    /// Returns the protocol version of the sender.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
     /// Returns the meta dictionary.
     pub fn meta(&self) -> &Meta {
         &self.meta
     }
 
+    /// Returns the ID of the request the response answers.
+    pub fn request_id(&self) -> Option<&str> {
+        match self.meta.get(REQUEST_ID_KEY) {
+            Some(Value::Text(request_id)) => Some(request_id.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Returns the kind of the response.
+    ///
+    /// Responses without a kind are final.
+    pub fn kind(&self) -> ResponseKind {
+        match self.meta.get(RESPONSE_KIND_KEY) {
+            Some(Value::Text(kind)) => serde_plain::from_str(kind).unwrap_or(ResponseKind::Final),
+            _ => ResponseKind::Final,
+        }
+    }
+
     /// Consumes the response and returns the payload.  If the
     /// response carries an error it's returned here.
     pub fn into_payload(self) -> Result<Value, Error> {
@@ -242,15 +627,88 @@ impl Response {
         self.payload.as_ref().err()
     }
 
+    /// Encrypts the payload with a key.
+    ///
+    /// Errors are encrypted as well, on the wire the response carries the
+    /// encrypted bytes as its payload either way.  The meta is authenticated,
+    /// so it must not change until the payload is decrypted.
+    #[cfg(feature = "encryption")]
+    pub fn encrypt_payload(&mut self, key: &PayloadKey) -> Result<(), Error> {
+        if is_encrypted(&self.meta)? {
+            return Ok(());
+        }
+        self.meta.insert(
+            ENCRYPTION_META_KEY.to_string(),
+            Value::Text(ENCRYPTION_ALGORITHM.to_string()),
+        );
+        let aad = associated_data(None, false, &self.meta)?;
+        self.payload = Ok(key.encrypt(&self.payload, &aad)?);
+        Ok(())
+    }
+
+    /// Decrypts the payload, which may turn out to be an error.
+    ///
+    /// Whoever holds a key only accepts encrypted responses, so this fails if
+    /// the payload is not encrypted or the response was tampered with.
+    #[cfg(feature = "encryption")]
+    pub fn decrypt_payload(&mut self, key: &PayloadKey) -> Result<(), Error> {
+        if !is_encrypted(&self.meta)? {
+            return Err(Error::new(
+                ErrorKind::EncryptionError,
+                "response payload is not encrypted",
+            ));
+        }
+        let aad = associated_data(None, false, &self.meta)?;
+        let encrypted = match self.payload {
+            Ok(ref payload) => payload,
+            Err(_) => {
+                return Err(Error::new(
+                    ErrorKind::EncryptionError,
+                    "encrypted payload is malformed",
+                ))
+            }
+        };
+        self.payload = key.decrypt(encrypted, &aad)?;
+        self.meta.remove(ENCRYPTION_META_KEY);
+        Ok(())
+    }
+
     /// Serializes a response into the wire format.
     pub fn serialize(&self) -> Result<Vec<u8>, Error> {
         serialize_to_cbor(self, "response")
     }
 
+    /// Serializes a response with a specific encoding.
+    pub fn serialize_with(&self, encoding: Encoding) -> Result<Vec<u8>, Error> {
+        serialize_to_cbor_with(self, "response", encoding)
+    }
+
+    /// Serializes a response in the wire format into a writer.
+    pub fn serialize_to<W: Write>(&self, writer: W) -> Result<(), Error> {
+        serialize_to_writer(self, writer, "response")
+    }
+
+    /// Serializes a response into a reusable buffer.
+    ///
+    /// The buffer is cleared first, so the same allocation can be used for
+    /// many messages.
+    pub fn serialize_into(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
+        buf.clear();
+        serialize_to_writer(self, buf, "response")
+    }
+
     /// Deserializes the response from the wire format.
     pub fn deserialize(bytes: &[u8]) -> Result<Response, Error> {
         deserialize_from_cbor(bytes, "response")
     }
+
+    /// Deserializes a single response from a reader.
+    ///
+    /// As the wire format is self delimiting, this can be called repeatedly
+    /// to read a sequence of responses from a stream.
+    pub fn deserialize_from<R: Read>(reader: R) -> Result<Response, Error> {
+        deserialize_from_reader(reader, "response")
+    }
 }
 
 impl ResponseBuilder {
@@ -282,6 +740,24 @@ impl ResponseBuilder {
         self
     }
 
+    /// Sets the ID of the request the response answers.
+    pub fn request_id<S: Into<String>>(&mut self, request_id: S) -> &mut ResponseBuilder {
+        self.meta(REQUEST_ID_KEY, request_id.into())
+    }
+
+    /// Sets the kind of the response.
+    ///
+    /// Final responses leave the kind out of the meta dictionary.
+    pub fn kind(&mut self, kind: ResponseKind) -> &mut ResponseBuilder {
+        match kind {
+            ResponseKind::Final => {
+                self.response_mut().meta.remove(RESPONSE_KIND_KEY);
+                self
+            }
+            kind => self.meta(RESPONSE_KIND_KEY, serde_plain::to_string(&kind).unwrap()),
+        }
+    }
+
     /// Inserts a key/value pair into the meta dictionary.
     pub fn meta<K, V>(&mut self, key: K, value: V) -> &mut ResponseBuilder
     where
@@ -333,13 +809,96 @@ impl Error {
     pub fn description(&self) -> &str {
         &self.description
     }
+
+    /// Returns the detail information if available.
+    pub fn detail(&self) -> Option<&Value> {
+        self.detail.as_ref()
+    }
+}
+
+impl ErrorKind {
+    /// Returns the kind for a numeric error code.
+    ///
+    /// ```
+    /// use worthless_bridge::ErrorKind;
+    ///
+    /// assert_eq!(ErrorKind::from_code(429), ErrorKind::RateLimited);
+    /// assert_eq!(ErrorKind::from_code(418), ErrorKind::Other(418));
+    /// ```
+    pub fn from_code(code: u32) -> ErrorKind {
+        match code {
+            404 => ErrorKind::UnknownEndpoint,
+            413 => ErrorKind::PayloadTooLarge,
+            429 => ErrorKind::RateLimited,
+            500 => ErrorKind::InternalError,
+            998 => ErrorKind::EncryptionError,
+            999 => ErrorKind::SerializationError,
+            code => ErrorKind::Other(code),
+        }
+    }
+
+    /// Returns the numeric code of the kind.
+    pub fn code(self) -> u32 {
+        match self {
+            ErrorKind::UnknownEndpoint => 404,
+            ErrorKind::PayloadTooLarge => 413,
+            ErrorKind::RateLimited => 429,
+            ErrorKind::InternalError => 500,
+            ErrorKind::EncryptionError => 998,
+            ErrorKind::SerializationError => 999,
+            ErrorKind::Other(code) => code,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<ErrorKind> {
+        Some(match name {
+            "unknown_endpoint" => ErrorKind::UnknownEndpoint,
+            "payload_too_large" => ErrorKind::PayloadTooLarge,
+            "rate_limited" => ErrorKind::RateLimited,
+            "internal_error" => ErrorKind::InternalError,
+            "encryption_error" => ErrorKind::EncryptionError,
+            "serialization_error" => ErrorKind::SerializationError,
+            _ => return None,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for ErrorKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ErrorKind, D::Error> {
+        // kinds are matched by hand so that unknown ones don't fail the
+        // entire message
+        let value = Value::deserialize(deserializer)?;
+        Ok(match value {
+            Value::Text(ref name) => ErrorKind::from_name(name),
+            Value::Integer(code) => u32::try_from(code).ok().map(ErrorKind::from_code),
+            Value::Map(ref items) => match &items[..] {
+                [(Value::Text(name), Value::Integer(code))] if name == "other" => {
+                    u32::try_from(*code).ok().map(ErrorKind::Other)
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+        .unwrap_or(ErrorKind::InternalError))
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}: {}", self.kind, self.description)?;
-        if let Some(Value::Text(ref detail)) = self.detail {
-            write!(f, "\n{}", detail)?;
+        match self.detail {
+            Some(Value::Text(ref detail)) => write!(f, "\n{}", detail)?,
+            // errors from JavaScript exceptions carry the stack in the detail
+            Some(Value::Map(ref items)) => {
+                for (key, value) in items {
+                    if let (Value::Text(key), Value::Text(stack)) = (key, value) {
+                        if key == "stack" {
+                            write!(f, "\n{}", stack)?;
+                        }
+                    }
+                }
+            }
+            _ => {}
         }
         Ok(())
     }
@@ -352,3 +911,7 @@ impl std::error::Error for Error {
 }
 
 serde_plain::derive_display_from_serialize!(ErrorKind);
+
+fn null() -> Value {
+    Value::Null
+}
@@ -1,25 +1,110 @@
+use std::io::{Read, Write};
+
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use crate::types::{Error, ErrorKind};
+use crate::types::{Error, ErrorKind, Value};
+
+/// Controls how values are encoded into CBOR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// Encodes maps in the order of their entries.
+    #[default]
+    Default,
+    /// Deterministic encoding as described in RFC 8949 section 4.2.
+    ///
+    /// Map keys are sorted by their encoded bytes.  Integers, floats and
+    /// lengths always use the shortest form which is also the case for the
+    /// default encoding.  Equal values thus always encode to the same bytes
+    /// which makes the output usable for hashing and signatures.
+    Canonical,
+}
 
 pub fn serialize_to_cbor<T: Serialize>(value: &T, ty_name: &'static str) -> Result<Vec<u8>, Error> {
+    serialize_to_cbor_with(value, ty_name, Encoding::Default)
+}
+
+pub fn serialize_to_cbor_with<T: Serialize>(
+    value: &T,
+    ty_name: &'static str,
+    encoding: Encoding,
+) -> Result<Vec<u8>, Error> {
     let mut rv = Vec::<u8>::new();
-    ciborium::ser::into_writer(value, &mut rv).map_err(|err| {
+    match encoding {
+        Encoding::Default => serialize_to_writer(value, &mut rv, ty_name)?,
+        Encoding::Canonical => {
+            let mut value = Value::serialized(value).map_err(|err| {
+                Error::new(
+                    ErrorKind::SerializationError,
+                    format!("failed to serialize {}", ty_name),
+                )
+                .with_source(err)
+            })?;
+            canonicalize(&mut value, ty_name)?;
+            serialize_to_writer(&value, &mut rv, ty_name)?;
+        }
+    }
+    Ok(rv)
+}
+
+/// Sorts the map keys of a value by their encoded bytes.
+fn canonicalize(value: &mut Value, ty_name: &'static str) -> Result<(), Error> {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                canonicalize(item, ty_name)?;
+            }
+        }
+        Value::Map(items) => {
+            let mut keyed = Vec::with_capacity(items.len());
+            for (mut key, mut value) in items.drain(..) {
+                canonicalize(&mut key, ty_name)?;
+                canonicalize(&mut value, ty_name)?;
+                keyed.push((serialize_to_cbor(&key, ty_name)?, key, value));
+            }
+            keyed.sort_by(|a, b| a.0.cmp(&b.0));
+            items.extend(keyed.into_iter().map(|(_, key, value)| (key, value)));
+        }
+        Value::Tag(_, inner) => canonicalize(inner, ty_name)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Serializes a value into canonical CBOR.
+///
+/// See [`Encoding::Canonical`].
+pub fn to_canonical_cbor<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
+    serialize_to_cbor_with(value, "value", Encoding::Canonical)
+}
+
+pub fn serialize_to_writer<T, W>(value: &T, writer: W, ty_name: &'static str) -> Result<(), Error>
+where
+    T: Serialize,
+    W: Write,
+{
+    ciborium::ser::into_writer(value, writer).map_err(|err| {
         Error::new(
             ErrorKind::SerializationError,
             format!("failed to serialize {}", ty_name),
         )
         .with_source(err)
-    })?;
-    Ok(rv)
+    })
 }
 
 pub fn deserialize_from_cbor<T>(bytes: &[u8], ty_name: &'static str) -> Result<T, Error>
 where
     T: DeserializeOwned,
 {
-    ciborium::de::from_reader(bytes).map_err(|err| {
+    deserialize_from_reader(bytes, ty_name)
+}
+
+pub fn deserialize_from_reader<T, R>(reader: R, ty_name: &'static str) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+    R: Read,
+{
+    ciborium::de::from_reader(reader).map_err(|err| {
         Error::new(
             ErrorKind::SerializationError,
             format!("failed to deserialize {}", ty_name),
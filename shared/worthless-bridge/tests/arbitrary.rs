@@ -0,0 +1,59 @@
+//! Checks that arbitrary values survive a round trip over the wire.
+#![cfg(feature = "arbitrary")]
+use arbitrary::Unstructured;
+use worthless_bridge::{arbitrary_value, Request, Value};
+
+/// Returns pseudo random bytes that are mostly zero.
+///
+/// Zeros make small integers likely, which is where the special tags are.
+fn sparse_bytes(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            if state % 3 == 0 {
+                (state >> 32) as u8 % 10
+            } else {
+                0
+            }
+        })
+        .collect()
+}
+
+/// Collects the tags in a value.
+fn collect_tags(value: &Value, tags: &mut Vec<u64>) {
+    match value {
+        Value::Tag(tag, value) => {
+            tags.push(*tag);
+            collect_tags(value, tags);
+        }
+        Value::Array(items) => items.iter().for_each(|x| collect_tags(x, tags)),
+        Value::Map(items) => items.iter().for_each(|(key, value)| {
+            collect_tags(key, tags);
+            collect_tags(value, tags);
+        }),
+        _ => {}
+    }
+}
+
+#[test]
+fn test_arbitrary_value_round_trip() {
+    let mut small_tags = 0;
+    for seed in 0..20_000 {
+        let bytes = sparse_bytes(seed, 64);
+        let value = arbitrary_value(&mut Unstructured::new(&bytes)).unwrap();
+        let mut tags = Vec::new();
+        collect_tags(&value, &mut tags);
+        // bignums come back as integers
+        assert!(!tags.contains(&2) && !tags.contains(&3), "{:?}", value);
+        small_tags += tags.iter().filter(|&&tag| tag < 8).count();
+
+        let req = Request::new("echo", value);
+        let serialized = req.serialize().unwrap();
+        let decoded = Request::deserialize(&serialized).unwrap();
+        assert_eq!(decoded.serialize().unwrap(), serialized);
+    }
+    assert!(small_tags > 0);
+}
@@ -0,0 +1,120 @@
+//! Checks the deterministic encoding of [`to_canonical_cbor`].
+use std::collections::HashMap;
+
+use worthless_bridge::{to_canonical_cbor, Value};
+
+fn text(value: &str) -> Value {
+    Value::Text(value.to_string())
+}
+
+fn int(value: i64) -> Value {
+    Value::Integer(value.into())
+}
+
+fn encode(value: &Value) -> Vec<u8> {
+    to_canonical_cbor(value).unwrap()
+}
+
+#[test]
+fn test_text_keys_sorted_by_encoding() {
+    // shorter keys have a smaller header and sort first
+    let value = Value::Map(vec![
+        (text("aa"), int(1)),
+        (text("b"), int(2)),
+        (text("a"), int(3)),
+    ]);
+    assert_eq!(
+        encode(&value),
+        [0xa3, 0x61, b'a', 0x03, 0x61, b'b', 0x02, 0x62, b'a', b'a', 0x01]
+    );
+}
+
+#[test]
+fn test_integer_keys_sorted_by_encoding() {
+    // positive integers sort before negative ones, small before large
+    let value = Value::Map(vec![
+        (int(-1), Value::Null),
+        (int(1000), Value::Null),
+        (int(10), Value::Null),
+    ]);
+    assert_eq!(
+        encode(&value),
+        [0xa3, 0x0a, 0xf6, 0x19, 0x03, 0xe8, 0xf6, 0x20, 0xf6]
+    );
+}
+
+#[test]
+fn test_mixed_keys_sorted_by_major_type() {
+    let value = Value::Map(vec![
+        (text("a"), Value::Null),
+        (Value::Bool(false), Value::Null),
+        (int(-1), Value::Null),
+        (int(1), Value::Null),
+    ]);
+    assert_eq!(
+        encode(&value),
+        [0xa4, 0x01, 0xf6, 0x20, 0xf6, 0x61, b'a', 0xf6, 0xf4, 0xf6]
+    );
+}
+
+#[test]
+fn test_nested_maps_sorted() {
+    let value = Value::Array(vec![Value::Map(vec![(
+        text("z"),
+        Value::Map(vec![(text("b"), int(1)), (text("a"), int(2))]),
+    )])]);
+    assert_eq!(
+        encode(&value),
+        [0x81, 0xa1, 0x61, b'z', 0xa2, 0x61, b'a', 0x02, 0x61, b'b', 0x01]
+    );
+}
+
+#[test]
+fn test_entry_order_does_not_matter() {
+    let map: HashMap<String, u32> = (0..32).map(|idx| (format!("key{}", idx), idx)).collect();
+    let mut entries: Vec<_> = map.clone().into_iter().collect();
+    entries.reverse();
+    let reversed: Value = Value::Map(
+        entries
+            .into_iter()
+            .map(|(key, value)| (text(&key), int(value.into())))
+            .collect(),
+    );
+    assert_eq!(to_canonical_cbor(&map).unwrap(), encode(&reversed));
+}
+
+#[test]
+fn test_floats_shortened() {
+    let cases: &[(f64, &[u8])] = &[
+        (0.0, &[0xf9, 0x00, 0x00]),
+        (-0.0, &[0xf9, 0x80, 0x00]),
+        (1.5, &[0xf9, 0x3e, 0x00]),
+        (65504.0, &[0xf9, 0x7b, 0xff]),
+        (100000.0, &[0xfa, 0x47, 0xc3, 0x50, 0x00]),
+        (1.1, &[0xfb, 0x3f, 0xf1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a]),
+        (f64::INFINITY, &[0xf9, 0x7c, 0x00]),
+        (f64::NEG_INFINITY, &[0xf9, 0xfc, 0x00]),
+        (f64::NAN, &[0xf9, 0x7e, 0x00]),
+    ];
+    for (value, expected) in cases {
+        assert_eq!(
+            encode(&Value::Float(*value)),
+            *expected,
+            "encoding of {}",
+            value
+        );
+    }
+}
+
+#[test]
+fn test_float_keys_shortened_before_sorting() {
+    // 1.5 fits into a half float and sorts before the single precision key
+    let value = Value::Map(vec![
+        (Value::Float(100000.0), Value::Null),
+        (Value::Float(1.5), Value::Null),
+    ]);
+    assert_eq!(
+        encode(&value),
+        [0xa2, 0xf9, 0x3e, 0x00, 0xf6, 0xfa, 0x47, 0xc3, 0x50, 0x00, 0xf6]
+    );
+}
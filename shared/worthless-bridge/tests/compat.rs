@@ -0,0 +1,173 @@
+//! Checks that messages stay readable across protocol versions.
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use worthless_bridge::{to_canonical_cbor, ErrorKind, Request, Response, Value, PROTOCOL_VERSION};
+
+fn map(items: Vec<(&str, Value)>) -> Value {
+    Value::Map(
+        items
+            .into_iter()
+            .map(|(key, value)| (Value::Text(key.to_string()), value))
+            .collect(),
+    )
+}
+
+fn encode(value: &Value) -> Vec<u8> {
+    to_canonical_cbor(value).unwrap()
+}
+
+/// A request as it was sent before versioning.
+fn legacy_request() -> Value {
+    map(vec![
+        (
+            "meta",
+            map(vec![("request_id", Value::Text("legacy".into()))]),
+        ),
+        ("fire_and_forget", Value::Bool(false)),
+        ("endpoint", Value::Text("echo".into())),
+        ("payload", Value::Integer(42.into())),
+    ])
+}
+
+/// The request as read by a peer from before versioning.
+#[derive(Deserialize)]
+struct LegacyRequest {
+    meta: BTreeMap<String, Value>,
+    fire_and_forget: bool,
+    endpoint: String,
+    payload: Value,
+}
+
+#[test]
+fn test_legacy_request() {
+    let req = Request::deserialize(&encode(&legacy_request())).unwrap();
+    assert_eq!(req.version(), 0);
+    assert_eq!(req.endpoint(), "echo");
+    assert_eq!(req.request_id(), Some("legacy"));
+    assert_eq!(req.payload(), &Value::Integer(42.into()));
+}
+
+#[test]
+fn test_minimal_request() {
+    let req = Request::deserialize(&encode(&map(vec![(
+        "endpoint",
+        Value::Text("ping".into()),
+    )])))
+    .unwrap();
+    assert_eq!(req.endpoint(), "ping");
+    assert!(req.meta().is_empty());
+    assert!(!req.fire_and_forget());
+    assert_eq!(req.payload(), &Value::Null);
+}
+
+#[test]
+fn test_request_from_newer_peer() {
+    let mut msg = legacy_request();
+    if let Value::Map(ref mut items) = msg {
+        items.push((Value::Text("version".into()), Value::Integer(99.into())));
+        items.push((
+            Value::Text("attachments".into()),
+            Value::Array(vec![Value::Bytes(vec![1, 2, 3])]),
+        ));
+    }
+    let req = Request::deserialize(&encode(&msg)).unwrap();
+    assert_eq!(req.version(), 99);
+    assert_eq!(req.endpoint(), "echo");
+}
+
+#[test]
+fn test_request_to_legacy_peer() {
+    let mut builder = Request::build("echo".into());
+    builder
+        .raw_payload("hello")
+        .fire_and_forget(true)
+        .random_request_id();
+    let bytes = builder.build().serialize().unwrap();
+    let req: LegacyRequest = ciborium::de::from_reader(&bytes[..]).unwrap();
+    assert_eq!(req.endpoint, "echo");
+    assert!(req.fire_and_forget);
+    assert!(req.meta.contains_key("request_id"));
+    assert_eq!(req.payload, Value::Text("hello".into()));
+}
+
+#[test]
+fn test_request_id_is_opt_in() {
+    let req = Request::new("echo", "hello");
+    assert_eq!(req.request_id(), None);
+    assert_eq!(
+        req.serialize().unwrap(),
+        Request::new("echo", "hello").serialize().unwrap()
+    );
+
+    let ids = (0..2)
+        .map(|_| {
+            let mut builder = Request::build("echo".into());
+            builder.random_request_id();
+            builder.build().request_id().unwrap().to_string()
+        })
+        .collect::<Vec<_>>();
+    assert_ne!(ids[0], ids[1]);
+}
+
+#[test]
+fn test_response_version() {
+    let resp = Response::builder().raw_payload(true).build();
+    let resp = Response::deserialize(&resp.serialize().unwrap()).unwrap();
+    assert_eq!(resp.version(), PROTOCOL_VERSION);
+
+    let legacy = map(vec![
+        ("meta", map(vec![])),
+        ("payload", map(vec![("Ok", Value::Bool(true))])),
+    ]);
+    let resp = Response::deserialize(&encode(&legacy)).unwrap();
+    assert_eq!(resp.version(), 0);
+    assert_eq!(resp.into_payload().unwrap(), Value::Bool(true));
+}
+
+#[test]
+fn test_unknown_error_kind() {
+    let msg = map(vec![
+        ("meta", map(vec![])),
+        (
+            "payload",
+            map(vec![(
+                "Err",
+                map(vec![
+                    ("kind", Value::Text("attachment_too_large".into())),
+                    ("description", Value::Text("too large".into())),
+                    ("retry_after", Value::Integer(5.into())),
+                ]),
+            )]),
+        ),
+    ]);
+    let err = Response::deserialize(&encode(&msg))
+        .unwrap()
+        .into_payload()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InternalError);
+    assert_eq!(err.description(), "too large");
+    assert_eq!(err.detail(), None);
+}
+
+#[test]
+fn test_error_kinds() {
+    let cases = [
+        (Value::Text("rate_limited".into()), ErrorKind::RateLimited),
+        (Value::Integer(413.into()), ErrorKind::PayloadTooLarge),
+        (Value::Integer(418.into()), ErrorKind::Other(418)),
+        (
+            map(vec![("other", Value::Integer(451.into()))]),
+            ErrorKind::Other(451),
+        ),
+        (Value::Integer((-1).into()), ErrorKind::InternalError),
+    ];
+    for (value, kind) in cases {
+        let decoded: ErrorKind = value.deserialized().unwrap();
+        assert_eq!(decoded, kind);
+    }
+    for kind in [ErrorKind::SerializationError, ErrorKind::Other(451)] {
+        let value = Value::serialized(&kind).unwrap();
+        assert_eq!(value.deserialized::<ErrorKind>().unwrap(), kind);
+    }
+}
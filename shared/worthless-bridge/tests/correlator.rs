@@ -0,0 +1,105 @@
+//! Checks how the correlator routes responses to requests in flight.
+use worthless_bridge::{
+    Correlator, ErrorKind, Event, Request, Response, ResponseKind, Value, CANCEL_ENDPOINT,
+};
+
+fn request(request_id: &str) -> Request {
+    let mut req = Request::build("kv.get".into());
+    req.request_id(request_id).raw_payload(Value::Null);
+    req.build()
+}
+
+fn response(request_id: &str, kind: ResponseKind) -> Response {
+    Response::builder()
+        .request_id(request_id)
+        .kind(kind)
+        .raw_payload(Value::Null)
+        .build()
+}
+
+#[test]
+fn test_track() {
+    let mut correlator = Correlator::new();
+    assert_eq!(correlator.track(&request("a")).unwrap(), "a");
+    let err = correlator.track(&request("a")).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InternalError);
+
+    let mut fire_and_forget = Request::build("log".into());
+    fire_and_forget.request_id("b").fire_and_forget(true);
+    assert!(correlator.track(&fire_and_forget.build()).is_err());
+
+    let err = correlator
+        .route(response("unknown", ResponseKind::Final))
+        .unwrap_err();
+    assert!(err.to_string().contains("unknown request"), "{}", err);
+}
+
+#[test]
+fn test_poll() {
+    let mut correlator = Correlator::new();
+    correlator.track(&request("a")).unwrap();
+    correlator
+        .route(response("a", ResponseKind::Progress))
+        .unwrap();
+    correlator
+        .route(response("a", ResponseKind::Final))
+        .unwrap();
+    assert_eq!(correlator.in_flight(), 0);
+    assert!(correlator
+        .route(response("a", ResponseKind::Final))
+        .is_err());
+
+    assert!(matches!(correlator.poll("a"), Some(Event::Progress(_))));
+    assert!(matches!(correlator.poll("a"), Some(Event::Completed(_))));
+    assert!(!correlator.is_tracked("a"));
+    assert!(correlator.poll("a").is_none());
+}
+
+#[test]
+fn test_cancel() {
+    let mut correlator = Correlator::new();
+    correlator.track(&request("a")).unwrap();
+    correlator
+        .route(response("a", ResponseKind::Progress))
+        .unwrap();
+    let cancel = correlator.cancel("a").unwrap();
+    assert_eq!(cancel.endpoint(), CANCEL_ENDPOINT);
+    assert!(correlator.cancel("a").is_none());
+    assert!(matches!(correlator.poll("a"), Some(Event::Cancelled)));
+    assert!(correlator.poll("a").is_none());
+
+    // late responses are dropped until the final one and the ID is not
+    // reused in the meantime
+    correlator
+        .route(response("a", ResponseKind::Progress))
+        .unwrap();
+    assert!(correlator.track(&request("a")).is_err());
+    correlator
+        .route(response("a", ResponseKind::Final))
+        .unwrap();
+    assert!(correlator
+        .route(response("a", ResponseKind::Final))
+        .is_err());
+    correlator.track(&request("a")).unwrap();
+}
+
+#[test]
+fn test_cancelled_requests_are_bounded() {
+    let mut correlator = Correlator::new();
+    for i in 0..1025 {
+        let request_id = i.to_string();
+        correlator.track(&request(&request_id)).unwrap();
+        correlator.cancel(&request_id).unwrap();
+        correlator.poll(&request_id).unwrap();
+    }
+
+    // the oldest cancellation is forgotten, the others are still ignored
+    assert!(correlator
+        .route(response("0", ResponseKind::Final))
+        .is_err());
+    correlator.track(&request("0")).unwrap();
+    correlator
+        .route(response("1", ResponseKind::Final))
+        .unwrap();
+    assert!(correlator.track(&request("2")).is_err());
+}
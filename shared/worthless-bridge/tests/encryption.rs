@@ -0,0 +1,131 @@
+//! Checks that encrypted payloads round-trip and tampering is detected.
+#![cfg(feature = "encryption")]
+use worthless_bridge::{
+    Error, ErrorKind, PayloadKey, Request, Response, Value, ENCRYPTION_META_KEY,
+};
+
+fn key() -> PayloadKey {
+    PayloadKey::from_bytes(&[7; 32])
+}
+
+fn encrypted_request() -> Request {
+    let mut req = Request::build("echo".into())
+        .request_id("req-1")
+        .raw_payload("secret")
+        .build();
+    req.encrypt_payload(&key()).unwrap();
+    Request::deserialize(&req.serialize().unwrap()).unwrap()
+}
+
+/// Copies a request to an endpoint with the meta changed by `f`.
+fn tampered<F>(endpoint: &str, req: &Request, payload: Value, f: F) -> Request
+where
+    F: FnOnce(&mut Vec<(String, Value)>),
+{
+    let mut meta: Vec<_> = req
+        .meta()
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    f(&mut meta);
+    let mut builder = Request::build(endpoint.into());
+    for (key, value) in meta {
+        builder.meta(key, value);
+    }
+    builder.raw_payload(payload).build()
+}
+
+fn assert_encryption_error(result: Result<(), Error>) {
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::EncryptionError);
+}
+
+#[test]
+fn test_request_round_trip() {
+    let mut req = encrypted_request();
+    assert!(matches!(req.payload(), Value::Bytes(_)));
+    req.decrypt_payload(&key()).unwrap();
+    assert_eq!(req.payload(), &Value::Text("secret".into()));
+    assert_eq!(req.request_id(), Some("req-1"));
+    assert!(req.meta().get(ENCRYPTION_META_KEY).is_none());
+}
+
+#[test]
+fn test_request_tampering() {
+    let req = encrypted_request();
+    let payload = req.payload().clone();
+
+    let mut other_id = tampered("echo", &req, payload.clone(), |meta| {
+        meta.retain(|(key, _)| key != "request_id");
+        meta.push(("request_id".into(), "req-2".into()));
+    });
+    assert_encryption_error(other_id.decrypt_payload(&key()));
+
+    let mut other_endpoint = tampered("delete", &req, payload.clone(), |_| {});
+    assert_encryption_error(other_endpoint.decrypt_payload(&key()));
+
+    let mut downgraded = tampered("echo", &req, payload.clone(), |meta| {
+        meta.retain(|(key, _)| key != ENCRYPTION_META_KEY);
+    });
+    assert_encryption_error(downgraded.decrypt_payload(&key()));
+
+    let mut bytes = match payload {
+        Value::Bytes(bytes) => bytes,
+        _ => unreachable!(),
+    };
+    *bytes.last_mut().unwrap() ^= 1;
+    let mut flipped = tampered("echo", &req, Value::Bytes(bytes), |_| {});
+    assert_encryption_error(flipped.decrypt_payload(&key()));
+
+    let mut wrong_key = req.clone();
+    assert_encryption_error(wrong_key.decrypt_payload(&PayloadKey::from_bytes(&[8; 32])));
+}
+
+#[test]
+fn test_response_round_trip() {
+    let mut ok = Response::builder()
+        .request_id("req-1")
+        .raw_payload(42)
+        .build();
+    ok.encrypt_payload(&key()).unwrap();
+    let mut ok = Response::deserialize(&ok.serialize().unwrap()).unwrap();
+    ok.decrypt_payload(&key()).unwrap();
+    assert_eq!(ok.into_payload().unwrap(), Value::Integer(42.into()));
+
+    let mut err = Response::builder()
+        .request_id("req-1")
+        .error(Error::new(
+            ErrorKind::InternalError,
+            "database password is hunter2",
+        ))
+        .build();
+    err.encrypt_payload(&key()).unwrap();
+    let bytes = err.serialize().unwrap();
+    assert!(!bytes.windows(7).any(|x| x == b"hunter2"));
+    let mut err = Response::deserialize(&bytes).unwrap();
+    assert!(err.error_ref().is_none());
+    err.decrypt_payload(&key()).unwrap();
+    let err = err.into_payload().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InternalError);
+    assert_eq!(err.description(), "database password is hunter2");
+}
+
+#[test]
+fn test_response_tampering() {
+    let mut resp = Response::builder()
+        .request_id("req-1")
+        .raw_payload(42)
+        .build();
+    resp.encrypt_payload(&key()).unwrap();
+    let payload = resp.payload_ref().unwrap().clone();
+
+    let mut meta = resp.meta().clone();
+    meta.insert("request_id".into(), "req-2".into());
+    assert_encryption_error(Response::new(meta, Ok(payload.clone())).decrypt_payload(&key()));
+
+    let mut meta = resp.meta().clone();
+    meta.remove(ENCRYPTION_META_KEY);
+    assert_encryption_error(Response::new(meta, Ok(payload)).decrypt_payload(&key()));
+
+    let mut plain = Response::builder().raw_payload(42).build();
+    assert_encryption_error(plain.decrypt_payload(&key()));
+}
@@ -0,0 +1,55 @@
+//! Checks the token buckets of the rate limiter.
+use std::thread;
+use std::time::Duration;
+
+use worthless_bridge::{ErrorKind, RateLimiter, Request, Value};
+
+fn request(endpoint: &str, tenant: &str) -> Request {
+    let mut req = Request::build(endpoint.into());
+    req.meta("tenant", tenant).raw_payload(Value::Null);
+    req.build()
+}
+
+#[test]
+fn test_capacity() {
+    let limiter = RateLimiter::new(2, Duration::from_secs(60));
+    let req = request("echo", "a");
+    assert!(limiter.check(&req).is_ok());
+    assert!(limiter.check(&req).is_ok());
+    let err = limiter.check(&req).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::RateLimited);
+}
+
+#[test]
+fn test_buckets() {
+    let mut limiter = RateLimiter::new(1, Duration::from_secs(60));
+    limiter.per_endpoint(true).per_meta_key("tenant");
+    assert!(limiter.check(&request("echo", "a")).is_ok());
+    assert!(limiter.check(&request("echo", "b")).is_ok());
+    assert!(limiter.check(&request("ping", "a")).is_ok());
+    assert!(limiter.check(&request("echo", "a")).is_err());
+    assert_eq!(limiter.buckets(), 3);
+}
+
+#[test]
+fn test_refill_and_evict_idle_buckets() {
+    let mut limiter = RateLimiter::new(1, Duration::from_millis(50));
+    limiter.per_meta_key("tenant");
+    for tenant in ["a", "b", "c"] {
+        assert!(limiter.check(&request("echo", tenant)).is_ok());
+    }
+    assert!(limiter.check(&request("echo", "a")).is_err());
+    assert_eq!(limiter.buckets(), 3);
+
+    // after a period the buckets are full again, so only the one in use
+    // is kept
+    thread::sleep(Duration::from_millis(60));
+    assert!(limiter.check(&request("echo", "a")).is_ok());
+    assert_eq!(limiter.buckets(), 1);
+}
+
+#[test]
+#[should_panic(expected = "must not be zero")]
+fn test_zero_period() {
+    RateLimiter::new(1, Duration::ZERO);
+}
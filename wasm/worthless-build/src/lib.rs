@@ -0,0 +1,105 @@
+//! Worthless-Build contains helpers for the build scripts of plugins.
+//!
+//! The helpers place artifacts into `OUT_DIR` and expose their location to
+//! the plugin crate via environment variables which the macros in
+//! `worthless-guest` pick up.
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+
+/// The name of the bundle within `OUT_DIR`.
+const BUNDLE_FILENAME: &str = "worthless-bundle";
+
+/// The compiler that is used if `WORTHLESS_COMPILER` is not set.
+const DEFAULT_COMPILER: &str = "worthless";
+
+/// Embeds a JavaScript bundle into the plugin.
+///
+/// The file is copied into `OUT_DIR` and cargo is instructed to rebuild the
+/// plugin whenever it changes.  The bundle is evaluated on initialization by
+/// `worthless_guest::export_bundle!` and is available as
+/// `worthless_guest::include_bundle!`.
+///
+/// # Panics
+///
+/// This panics if the bundle cannot be read or `OUT_DIR` is not set which
+/// means that it's not invoked from a build script.
+pub fn embed_bundle<P: AsRef<Path>>(path: P) {
+    let path = path.as_ref();
+    let out_path = out_dir().join(BUNDLE_FILENAME);
+    // the trailing NUL lets the runtime pass the source to the engine without
+    // copying it.
+    let result = fs::read(path).and_then(|mut source| {
+        source.push(0);
+        fs::write(&out_path, source)
+    });
+    if let Err(err) = result {
+        panic!("cannot embed bundle {}: {}", path.display(), err);
+    }
+    register_bundle(path, &out_path, "source");
+}
+
+/// Embeds precompiled QuickJS bytecode as bundle into the plugin.
+///
+/// This works like [`embed_bundle`] but the file must contain bytecode that
+/// was compiled by the same QuickJS version the plugin is built with.
+pub fn embed_bytecode<P: AsRef<Path>>(path: P) {
+    let path = path.as_ref();
+    let out_path = out_dir().join(BUNDLE_FILENAME);
+    if let Err(err) = fs::copy(path, &out_path) {
+        panic!("cannot embed bytecode {}: {}", path.display(), err);
+    }
+    register_bundle(path, &out_path, "bytecode");
+}
+
+/// Compiles a JavaScript bundle to bytecode and embeds it into the plugin.
+///
+/// Loading bytecode skips parsing at startup.  The compiler is invoked as
+/// `<compiler> compile <input> -o <output>`.  It defaults to `worthless` and
+/// can be overridden with the `WORTHLESS_COMPILER` environment variable.
+///
+/// # Panics
+///
+/// This panics if the compiler cannot be invoked or fails.
+pub fn embed_compiled_bundle<P: AsRef<Path>>(path: P) {
+    let path = path.as_ref();
+    let out_path = out_dir().join(BUNDLE_FILENAME);
+    let compiler = env::var_os("WORTHLESS_COMPILER").unwrap_or_else(|| DEFAULT_COMPILER.into());
+    println!("cargo:rerun-if-env-changed=WORTHLESS_COMPILER");
+
+    let status = Command::new(&compiler)
+        .arg("compile")
+        .arg(path)
+        .arg("-o")
+        .arg(&out_path)
+        .status();
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => panic!("failed to compile bundle {}: {}", path.display(), status),
+        Err(err) => panic!(
+            "cannot invoke bundle compiler {}: {}",
+            compiler.to_string_lossy(),
+            err
+        ),
+    }
+    register_bundle(path, &out_path, "bytecode");
+}
+
+fn register_bundle(path: &Path, out_path: &Path, format: &str) {
+    println!("cargo:rerun-if-changed={}", path.display());
+    println!(
+        "cargo:rustc-env=WORTHLESS_BUNDLE_PATH={}",
+        out_path.display()
+    );
+    println!("cargo:rustc-env=WORTHLESS_BUNDLE_FORMAT={}", format);
+    println!(
+        "cargo:rustc-env=WORTHLESS_BUNDLE_NAME={}",
+        path.file_name()
+            .map(|x| x.to_string_lossy())
+            .unwrap_or_default()
+    );
+}
+
+fn out_dir() -> PathBuf {
+    PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR not set, not running in build script"))
+}
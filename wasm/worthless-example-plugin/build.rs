@@ -0,0 +1,3 @@
+fn main() {
+    worthless_build::embed_bundle("js/plugin.js");
+}
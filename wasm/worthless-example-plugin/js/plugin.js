@@ -0,0 +1,8 @@
+worthless.register("hello", (payload) => {
+  console.log("saying hello to", payload.name);
+  return { greeting: `Hello ${payload.name}!` };
+});
+
+worthless.register("sum", (payload) => {
+  return payload.reduce((a, b) => a + b, 0);
+});
@@ -0,0 +1,2 @@
+//! An example plugin that is entirely defined by its embedded JavaScript bundle.
+worthless_guest::export_bundle!();
@@ -0,0 +1,109 @@
+use worthless_js_rt::{Context, Value};
+
+use crate::error::Error;
+
+/// A JavaScript bundle embedded into the plugin.
+///
+/// Bundles are usually created with [`include_bundle!`](crate::include_bundle)
+/// from a file that the build script registered with
+/// `worthless_build::embed_bundle`.
+#[derive(Debug, Clone, Copy)]
+pub struct Bundle {
+    name: &'static str,
+    source: &'static [u8],
+    bytecode: bool,
+}
+
+impl Bundle {
+    /// Creates a bundle from its name and source.
+    ///
+    /// Sources that end in a NUL byte are evaluated without being copied.
+    pub const fn new(name: &'static str, source: &'static [u8]) -> Bundle {
+        Bundle {
+            name,
+            source,
+            bytecode: false,
+        }
+    }
+
+    /// Creates a bundle from precompiled QuickJS bytecode.
+    ///
+    /// Evaluating bytecode skips parsing the source at startup.
+    pub const fn from_bytecode(name: &'static str, bytecode: &'static [u8]) -> Bundle {
+        Bundle {
+            name,
+            source: bytecode,
+            bytecode: true,
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn __embedded(format: &str, name: &'static str, data: &'static [u8]) -> Bundle {
+        match format {
+            "bytecode" => Bundle::from_bytecode(name, data),
+            _ => Bundle::new(name, data),
+        }
+    }
+
+    /// Returns the name of the bundle as it shows up in stack traces.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the source of the bundle.
+    ///
+    /// For bytecode bundles this is the bytecode.
+    pub fn source(&self) -> &'static [u8] {
+        if self.bytecode {
+            return self.source;
+        }
+        self.source.strip_suffix(b"\0").unwrap_or(self.source)
+    }
+
+    /// Returns `true` if the bundle is precompiled bytecode.
+    pub fn is_bytecode(&self) -> bool {
+        self.bytecode
+    }
+
+    /// Evaluates the bundle in the given context.
+    pub fn eval(&self, ctx: &Context) -> Result<Value, Error> {
+        if self.bytecode {
+            return Ok(ctx.eval_bytecode(self.source)?);
+        }
+        std::str::from_utf8(self.source())
+            .map_err(|err| Error::Runtime(worthless_js_rt::Error::Utf8Error(err)))?;
+        // embedded sources end in a NUL byte and are evaluated without a copy
+        Ok(ctx.eval_bytes_with_filename(self.source, self.name)?)
+    }
+}
+
+/// Includes the bundle registered by the build script.
+///
+/// This requires that the build script of the crate invoked one of the embed
+/// functions of `worthless_build`.
+#[macro_export]
+macro_rules! include_bundle {
+    () => {
+        $crate::Bundle::__embedded(
+            env!("WORTHLESS_BUNDLE_FORMAT"),
+            env!("WORTHLESS_BUNDLE_NAME"),
+            include_bytes!(env!("WORTHLESS_BUNDLE_PATH")),
+        )
+    };
+}
+
+/// Exports the entry points of a plugin that is defined by its bundle.
+///
+/// The bundle registered by the build script is evaluated on initialization
+/// and is expected to register the handlers with `worthless.register`.
+#[macro_export]
+macro_rules! export_bundle {
+    () => {
+        fn __worthless_init(ctx: &$crate::__private::Context) -> Result<(), $crate::Error> {
+            $crate::include_bundle!().eval(ctx)?;
+            Ok(())
+        }
+
+        $crate::export_plugin!(__worthless_init);
+    };
+}
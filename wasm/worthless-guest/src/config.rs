@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+use std::env;
+
+use worthless_bridge::Value as BridgeValue;
+
+pub use worthless_bridge::{CONFIG_ENV_PREFIX, CONFIG_META_KEY};
+
+/// Loads the config from the environment of the WASI instance.
+///
+/// The prefix is stripped from the variable names and the rest is lowercased,
+/// so `WORTHLESS_CONFIG_TENANT_ID` becomes `tenant_id`.
+pub fn load_env_config() -> BTreeMap<String, BridgeValue> {
+    env::vars()
+        .filter_map(|(key, value)| {
+            key.strip_prefix(CONFIG_ENV_PREFIX)
+                .filter(|key| !key.is_empty())
+                .map(|key| (key.to_ascii_lowercase(), BridgeValue::Text(value)))
+        })
+        .collect()
+}
+
+/// Merges the config of the request meta over the base config.
+///
+/// Only entries of a map with text keys are merged, everything else in the
+/// config meta key is ignored.
+pub fn merge_config(
+    base: &BTreeMap<String, BridgeValue>,
+    meta: &BTreeMap<String, BridgeValue>,
+) -> BridgeValue {
+    let mut rv = base.clone();
+    if let Some(BridgeValue::Map(items)) = meta.get(CONFIG_META_KEY) {
+        for (key, value) in items {
+            if let BridgeValue::Text(key) = key {
+                rv.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    BridgeValue::Map(
+        rv.into_iter()
+            .map(|(key, value)| (BridgeValue::Text(key), value))
+            .collect(),
+    )
+}
+
+/// Converts the meta of a request into a bridge value.
+pub fn meta_to_value(meta: &BTreeMap<String, BridgeValue>) -> BridgeValue {
+    BridgeValue::Map(
+        meta.iter()
+            .map(|(key, value)| (BridgeValue::Text(key.clone()), value.clone()))
+            .collect(),
+    )
+}
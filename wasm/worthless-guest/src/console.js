@@ -0,0 +1,288 @@
+// Implements `console.dir` and `console.table` on top of `console.log`.  The
+// output follows the format of Node closely but without colors.  Evaluating
+// this returns a function that installs both into a console object.
+(function () {
+  "use strict";
+
+  const MAX_LINE_WIDTH = 72;
+  const IDENTIFIER = /^[A-Za-z_$][A-Za-z0-9_$]*$/;
+
+  function quote(string) {
+    const escaped = String(string)
+      .replace(/\\/g, "\\\\")
+      .replace(/\n/g, "\\n")
+      .replace(/'/g, "\\'");
+    return `'${escaped}'`;
+  }
+
+  function formatKey(key, hidden) {
+    let rv;
+    if (typeof key === "symbol") {
+      rv = `[${key.toString()}]`;
+    } else if (IDENTIFIER.test(key)) {
+      rv = key;
+    } else {
+      rv = quote(key);
+    }
+    return hidden ? `[${rv}]` : rv;
+  }
+
+  function constructorName(value) {
+    const proto = Object.getPrototypeOf(value);
+    if (proto === null) {
+      return "[Object: null prototype]";
+    }
+    const ctor = proto.constructor;
+    return typeof ctor === "function" && ctor.name ? ctor.name : "Object";
+  }
+
+  function formatPrimitive(value) {
+    switch (typeof value) {
+      case "string":
+        return quote(value);
+      case "bigint":
+        return `${value}n`;
+      case "symbol":
+        return value.toString();
+      case "number":
+        return Object.is(value, -0) ? "-0" : String(value);
+      default:
+        return String(value);
+    }
+  }
+
+  function formatFunction(value) {
+    const source = Function.prototype.toString.call(value);
+    if (source.startsWith("class")) {
+      return `[class ${value.name || "(anonymous)"}]`;
+    }
+    return value.name ? `[Function: ${value.name}]` : "[Function (anonymous)]";
+  }
+
+  function ownKeys(value, options) {
+    const keys = options.showHidden
+      ? Reflect.ownKeys(value)
+      : Object.keys(value).concat(
+          Object.getOwnPropertySymbols(value).filter(
+            (symbol) => Object.getOwnPropertyDescriptor(value, symbol).enumerable
+          )
+        );
+    if (options.sorted) {
+      keys.sort((a, b) => String(a).localeCompare(String(b)));
+    }
+    return keys;
+  }
+
+  function propertyEntries(value, keys, options, depth, seen, skipIndices) {
+    const rv = [];
+    for (const key of keys) {
+      if (skipIndices && typeof key === "string" && /^(0|[1-9][0-9]*)$/.test(key)) {
+        continue;
+      }
+      const descriptor = Object.getOwnPropertyDescriptor(value, key);
+      const hidden = !descriptor.enumerable;
+      let formatted;
+      if (descriptor.get || descriptor.set) {
+        if (descriptor.get && descriptor.set) {
+          formatted = "[Getter/Setter]";
+        } else {
+          formatted = descriptor.get ? "[Getter]" : "[Setter]";
+        }
+      } else {
+        formatted = formatValue(descriptor.value, options, depth + 1, seen);
+      }
+      rv.push(`${formatKey(key, hidden)}: ${formatted}`);
+    }
+    return rv;
+  }
+
+  function wrap(prefix, open, entries, close, depth) {
+    if (entries.length === 0) {
+      return `${prefix}${open}${close}`;
+    }
+    const line = `${prefix}${open} ${entries.join(", ")} ${close}`;
+    if (line.length + depth * 2 <= MAX_LINE_WIDTH && !line.includes("\n")) {
+      return line;
+    }
+    const indent = "  ".repeat(depth + 1);
+    const body = entries
+      .map((entry) => indent + entry.replace(/\n/g, `\n${indent}`))
+      .join(",\n");
+    return `${prefix}${open}\n${body}\n${"  ".repeat(depth)}${close}`;
+  }
+
+  function formatValue(value, options, depth, seen) {
+    if (value === null || (typeof value !== "object" && typeof value !== "function")) {
+      return formatPrimitive(value);
+    }
+    if (typeof value === "function" && ownKeys(value, options).length === 0) {
+      return formatFunction(value);
+    }
+    if (seen.includes(value)) {
+      return "[Circular]";
+    }
+    if (value instanceof Date) {
+      return isNaN(value.getTime()) ? "Invalid Date" : value.toISOString();
+    }
+    if (value instanceof RegExp) {
+      return value.toString();
+    }
+    if (value instanceof Error) {
+      const message = `${value.name}: ${value.message}`;
+      return value.stack ? `${message}\n${value.stack}`.trimEnd() : message;
+    }
+
+    const name = constructorName(value);
+    if (options.depth !== null && depth > options.depth) {
+      return Array.isArray(value) ? "[Array]" : `[${name}]`;
+    }
+
+    seen.push(value);
+    try {
+      const keys = ownKeys(value, options);
+      if (Array.isArray(value) || ArrayBuffer.isView(value)) {
+        const items = [];
+        for (let idx = 0; idx < value.length; idx++) {
+          items.push(formatValue(value[idx], options, depth + 1, seen));
+        }
+        const rest = keys.filter((key) => key !== "length");
+        items.push(...propertyEntries(value, rest, options, depth, seen, true));
+        const prefix =
+          Array.isArray(value) && name === "Array" ? "" : `${name}(${value.length}) `;
+        return wrap(prefix, "[", items, "]", depth);
+      }
+      if (value instanceof Map) {
+        const items = [];
+        for (const [key, item] of value) {
+          const formattedKey = formatValue(key, options, depth + 1, seen);
+          items.push(`${formattedKey} => ${formatValue(item, options, depth + 1, seen)}`);
+        }
+        items.push(...propertyEntries(value, keys, options, depth, seen, false));
+        return wrap(`Map(${value.size}) `, "{", items, "}", depth);
+      }
+      if (value instanceof Set) {
+        const items = [];
+        for (const item of value) {
+          items.push(formatValue(item, options, depth + 1, seen));
+        }
+        items.push(...propertyEntries(value, keys, options, depth, seen, false));
+        return wrap(`Set(${value.size}) `, "{", items, "}", depth);
+      }
+      const entries = propertyEntries(value, keys, options, depth, seen, false);
+      let prefix = name === "Object" ? "" : `${name} `;
+      if (typeof value === "function") {
+        prefix = `${formatFunction(value)} `;
+      }
+      return wrap(prefix, "{", entries, "}", depth);
+    } finally {
+      seen.pop();
+    }
+  }
+
+  function inspect(value, options) {
+    options = Object.assign(
+      { depth: 2, showHidden: false, sorted: false },
+      options || {}
+    );
+    if (options.depth === Infinity) {
+      options.depth = null;
+    }
+    return formatValue(value, options, 0, []);
+  }
+
+  // Cells show nested objects only one level deep.
+  function formatCell(value) {
+    return formatValue(value, { depth: 0, showHidden: false, sorted: false }, 1, []);
+  }
+
+  function renderTable(header, rows) {
+    const widths = header.map((cell, idx) =>
+      Math.max(cell.length, ...rows.map((row) => row[idx].length))
+    );
+    const line = (left, middle, right) =>
+      left + widths.map((width) => "─".repeat(width + 2)).join(middle) + right;
+    const row = (cells) =>
+      "│" +
+      cells.map((cell, idx) => ` ${cell.padEnd(widths[idx])} `).join("│") +
+      "│";
+    return [
+      line("┌", "┬", "┐"),
+      row(header),
+      line("├", "┼", "┤"),
+      ...rows.map(row),
+      line("└", "┴", "┘"),
+    ].join("\n");
+  }
+
+  function table(data, properties) {
+    if (data === null || typeof data !== "object") {
+      return null;
+    }
+    const indices = [];
+    const values = [];
+    if (data instanceof Map) {
+      for (const [key, value] of data) {
+        indices.push(formatCell(key));
+        values.push(value);
+      }
+    } else if (data instanceof Set) {
+      let idx = 0;
+      for (const value of data) {
+        indices.push(String(idx++));
+        values.push(value);
+      }
+    } else {
+      for (const key of Object.keys(data)) {
+        indices.push(key);
+        values.push(data[key]);
+      }
+    }
+
+    const columns = [];
+    let hasPrimitives = false;
+    for (const value of values) {
+      if (value !== null && typeof value === "object") {
+        for (const key of Object.keys(value)) {
+          if (!columns.includes(key)) {
+            columns.push(key);
+          }
+        }
+      } else {
+        hasPrimitives = true;
+      }
+    }
+    const shown = Array.isArray(properties) ? properties.map(String) : columns;
+
+    const header = ["(index)", ...shown];
+    if (hasPrimitives) {
+      header.push("Values");
+    }
+    const rows = values.map((value, idx) => {
+      const isObject = value !== null && typeof value === "object";
+      const cells = [indices[idx]];
+      for (const key of shown) {
+        cells.push(isObject && key in value ? formatCell(value[key]) : "");
+      }
+      if (hasPrimitives) {
+        cells.push(isObject ? "" : formatCell(value));
+      }
+      return cells;
+    });
+    return renderTable(header, rows);
+  }
+
+  return function (console) {
+    const log = console.log;
+    console.dir = function (value, options) {
+      log.call(console, inspect(value, options));
+    };
+    console.table = function (data, properties) {
+      const rendered = table(data, properties);
+      if (rendered === null) {
+        log.call(console, data);
+      } else {
+        log.call(console, rendered);
+      }
+    };
+  };
+})();
@@ -0,0 +1,64 @@
+use worthless_bridge::{Request, Value as BridgeValue};
+use worthless_js_rt::{Context, Primitive, Value};
+
+use crate::dispatcher::Dispatcher;
+use crate::host::emit_to_host;
+
+const CONSOLE_JS: &str = include_str!("console.js");
+
+/// Creates a console object that forwards to the host's `log.emit` endpoint.
+///
+/// `console.dir` and `console.table` render their output in JavaScript and
+/// log it at the info level.
+pub fn make_bridge_console(ctx: &Context) -> Result<Value, worthless_js_rt::Error> {
+    let rv = Value::new_object(ctx);
+    rv.set_property(
+        "debug",
+        Value::from_func(ctx, "debug", |ctx, _this, args| emit(ctx, "debug", args))?,
+    )?;
+    rv.set_property(
+        "log",
+        Value::from_func(ctx, "log", |ctx, _this, args| emit(ctx, "info", args))?,
+    )?;
+    rv.set_property(
+        "info",
+        Value::from_func(ctx, "info", |ctx, _this, args| emit(ctx, "info", args))?,
+    )?;
+    rv.set_property(
+        "warn",
+        Value::from_func(ctx, "warn", |ctx, _this, args| emit(ctx, "warn", args))?,
+    )?;
+    rv.set_property(
+        "error",
+        Value::from_func(ctx, "error", |ctx, _this, args| emit(ctx, "error", args))?,
+    )?;
+    let install = ctx.eval_with_filename(CONSOLE_JS, "<worthless:console>")?;
+    let this = Value::from_primitive(ctx, Primitive::Undefined);
+    install.call(&this, std::slice::from_ref(&rv))?;
+    Ok(rv)
+}
+
+fn emit(ctx: &Context, level: &str, args: &[Value]) -> Result<Value, worthless_js_rt::Error> {
+    let mut message = String::new();
+    for (idx, arg) in args.iter().enumerate() {
+        if idx > 0 {
+            message.push(' ');
+        }
+        message.push_str(&arg.to_string_lossy());
+    }
+
+    let mut builder = Request::build("log.emit".into());
+    builder
+        .raw_payload(BridgeValue::Map(vec![
+            ("level".into(), level.into()),
+            ("message".into(), message.into()),
+        ]))
+        .fire_and_forget(true);
+    if let Some(request_id) = Dispatcher::current().and_then(|x| x.current_request_id()) {
+        builder.request_id(request_id);
+    }
+
+    // console output must never fail the caller, so errors are swallowed
+    let _ = emit_to_host(&builder.build());
+    Ok(Value::from_primitive(ctx, Primitive::Undefined))
+}
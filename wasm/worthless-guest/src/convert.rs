@@ -0,0 +1,180 @@
+use worthless_bridge::Value as BridgeValue;
+use worthless_js_rt::{Context, Primitive, PropertyFilter, Value, ValueKind};
+
+use crate::error::Error;
+
+/// The maximum depth of nested arrays and objects that can be converted.
+const MAX_DEPTH: usize = 128;
+
+/// Converts a value from the bridge into a JavaScript value.
+///
+/// Maps become objects (keys must be strings or integers), byte strings
+/// become arrays of numbers and tags are discarded.
+pub fn to_js(ctx: &Context, value: &BridgeValue) -> Result<Value, Error> {
+    to_js_impl(ctx, value, 0)
+}
+
+fn to_js_impl(ctx: &Context, value: &BridgeValue, depth: usize) -> Result<Value, Error> {
+    if depth > MAX_DEPTH {
+        return Err(Error::RecursionLimit);
+    }
+    Ok(match value {
+        BridgeValue::Null => Value::from_primitive(ctx, Primitive::Null),
+        BridgeValue::Bool(value) => Value::from_primitive(ctx, *value),
+        BridgeValue::Integer(value) => match i64::try_from(i128::from(*value)) {
+            Ok(value) => Value::from_primitive(ctx, value),
+            Err(_) => Value::from_primitive(ctx, i128::from(*value) as f64),
+        },
+        BridgeValue::Float(value) => Value::from_primitive(ctx, *value),
+        BridgeValue::Text(value) => ctx.intern(value),
+        BridgeValue::Bytes(value) => Value::from_iter(ctx, value.iter().map(|x| *x as i32)),
+        BridgeValue::Tag(_, value) => to_js_impl(ctx, value, depth + 1)?,
+        BridgeValue::Array(items) => {
+            let rv = Value::new_array(ctx);
+            for item in items {
+                rv.append(to_js_impl(ctx, item, depth + 1)?)?;
+            }
+            rv
+        }
+        BridgeValue::Map(items) => {
+            let rv = Value::new_object(ctx);
+            for (key, value) in items {
+                let value = to_js_impl(ctx, value, depth + 1)?;
+                match key {
+                    BridgeValue::Text(key) => rv.set_property(key, value)?,
+                    BridgeValue::Integer(key) => {
+                        rv.set_property(&i128::from(*key).to_string(), value)?
+                    }
+                    _ => return Err(Error::UnsupportedValue),
+                }
+            }
+            rv
+        }
+        _ => return Err(Error::UnsupportedValue),
+    })
+}
+
+/// Converts a JavaScript value into a value for the bridge.
+///
+/// `undefined` is converted into null, arrays and objects are converted
+/// recursively.  `ArrayBuffer`s and typed arrays become byte strings.
+/// Functions and symbols cannot be converted.
+pub fn from_js(value: &Value) -> Result<BridgeValue, Error> {
+    from_js_impl(value, 0, None)
+}
+
+/// Converts a JavaScript value the script is done with for the bridge.
+///
+/// This is like [`from_js`] but moves the bytes out of the `ArrayBuffer`s
+/// and typed arrays contained in the `transferable` set (a `WeakSet`)
+/// instead of copying them, which leaves them detached.  Other buffers are
+/// copied.
+pub fn transfer_from_js(value: &Value, transferable: &Value) -> Result<BridgeValue, Error> {
+    from_js_impl(value, 0, Some(transferable))
+}
+
+fn from_js_impl(
+    value: &Value,
+    depth: usize,
+    transferable: Option<&Value>,
+) -> Result<BridgeValue, Error> {
+    if depth > MAX_DEPTH {
+        return Err(Error::RecursionLimit);
+    }
+    match value.kind() {
+        ValueKind::Undefined | ValueKind::Null => Ok(BridgeValue::Null),
+        ValueKind::Symbol | ValueKind::Exception => Err(Error::UnsupportedValue),
+        ValueKind::Object if value.is_function() => Err(Error::UnsupportedValue),
+        ValueKind::Object if value.is_array() => {
+            let mut rv = Vec::new();
+            for idx in 0..value.len().unwrap_or(0) {
+                rv.push(from_js_impl(
+                    &value.get_by_index(idx)?,
+                    depth + 1,
+                    transferable,
+                )?);
+            }
+            Ok(BridgeValue::Array(rv))
+        }
+        ValueKind::Object if value.is_array_buffer() => {
+            let transfer = match transferable {
+                Some(set) => set.call_method("has", [value])?.is_true(),
+                None => false,
+            };
+            Ok(BridgeValue::Bytes(if transfer {
+                value.transfer_bytes()?
+            } else {
+                value.to_bytes()?
+            }))
+        }
+        ValueKind::Object => {
+            let mut rv = Vec::new();
+            for (key, value) in value.iter_properties_with(&PropertyFilter::json()) {
+                rv.push((
+                    BridgeValue::Text(key.to_string_lossy().to_string()),
+                    from_js_impl(&value, depth + 1, transferable)?,
+                ));
+            }
+            Ok(BridgeValue::Map(rv))
+        }
+        ValueKind::Boolean | ValueKind::Number | ValueKind::String => {
+            Ok(match value.as_primitive() {
+                Some(Primitive::Bool(value)) => value.into(),
+                Some(Primitive::I32(value)) => value.into(),
+                Some(Primitive::I64(value)) => value.into(),
+                Some(Primitive::F64(value)) => value.into(),
+                Some(Primitive::Str(value)) => value.into(),
+                Some(Primitive::InvalidStr(value)) => value.into(),
+                Some(Primitive::JsStr(value)) => value.as_str().into(),
+                _ => BridgeValue::Null,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use worthless_bridge::Value as BridgeValue;
+    use worthless_js_rt::Context;
+
+    use super::{from_js, transfer_from_js};
+
+    #[test]
+    fn test_transfer_buffers() {
+        Context::run(|ctx| {
+            let transferable = ctx.eval("globalThis.transferable = new WeakSet(); transferable")?;
+            let bytes = |value| transfer_from_js(value, &transferable).unwrap();
+
+            // buffers a handler keeps returning are copied every time
+            let cached = ctx.eval("globalThis.cached = new Uint8Array([1, 2, 3]); cached")?;
+            for _ in 0..2 {
+                assert_eq!(bytes(&cached), BridgeValue::Bytes(vec![1, 2, 3]));
+            }
+            assert_eq!(ctx.eval("cached.length")?.as_i32(), Some(3));
+
+            // once marked the bytes are moved out
+            ctx.eval("transferable.add(cached)")?;
+            assert_eq!(bytes(&cached), BridgeValue::Bytes(vec![1, 2, 3]));
+            assert_eq!(ctx.eval("cached.length")?.as_i32(), Some(0));
+
+            // a marked view of a part of a buffer leaves the buffer alone
+            let view = ctx.eval(
+                "globalThis.parent = new Uint8Array([1, 2, 3, 4]);
+                 globalThis.view = parent.subarray(1, 3);
+                 transferable.add(view);
+                 view",
+            )?;
+            assert_eq!(bytes(&view), BridgeValue::Bytes(vec![2, 3]));
+            assert_eq!(ctx.eval("parent.length")?.as_i32(), Some(4));
+            assert_eq!(ctx.eval("view.length")?.as_i32(), Some(2));
+
+            // plain conversions never transfer
+            let buffer = ctx
+                .eval("globalThis.buffer = new ArrayBuffer(2); transferable.add(buffer); buffer")?;
+            assert_eq!(from_js(&buffer).unwrap(), BridgeValue::Bytes(vec![0, 0]));
+            assert_eq!(ctx.eval("buffer.byteLength")?.as_i32(), Some(2));
+            Ok(())
+        })
+        .unwrap();
+    }
+}
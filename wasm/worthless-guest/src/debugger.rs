@@ -0,0 +1,189 @@
+use worthless_bridge::{ErrorKind, Request, Value, DEBUG_PAUSED_ENDPOINT};
+use worthless_js_rt::{Context, Debugger, Pause, PauseReason, Resume, DEFAULT_DEBUG_DEPTH};
+
+use crate::dispatcher::Dispatcher;
+use crate::error::Error;
+use crate::host::call_host;
+
+/// Attaches a debugger that bridges its pauses to the host.
+pub(crate) fn attach_debugger(ctx: &Context) -> Result<Debugger, Error> {
+    let debugger = Debugger::attach(ctx)?;
+    debugger.set_pause_handler(on_pause);
+    Ok(debugger)
+}
+
+/// Handles an action of the debug control endpoint.
+pub(crate) fn control_debugger(
+    debugger: Option<&Debugger>,
+    payload: &Value,
+) -> Result<Value, worthless_bridge::Error> {
+    let debugger = debugger.ok_or_else(|| {
+        worthless_bridge::Error::new(
+            ErrorKind::InternalError,
+            "the plugin was not started for debugging",
+        )
+    })?;
+    let mut action = None;
+    let mut file = None;
+    let mut line = None;
+    let mut enabled = false;
+    if let Value::Map(items) = payload {
+        for (key, value) in items {
+            match key.as_text() {
+                Some("action") => action = value.as_text(),
+                Some("file") => file = value.as_text(),
+                Some("line") => line = value.as_integer().and_then(|x| u32::try_from(x).ok()),
+                Some("enabled") => enabled = value.as_bool().unwrap_or(false),
+                _ => {}
+            }
+        }
+    }
+    let location = || {
+        file.zip(line).ok_or_else(|| {
+            worthless_bridge::Error::new(
+                ErrorKind::InternalError,
+                "breakpoints need a file and a line",
+            )
+        })
+    };
+    match action {
+        Some("set_breakpoint") => {
+            let (file, line) = location()?;
+            Ok(Value::Map(vec![(
+                "line".into(),
+                debugger
+                    .set_breakpoint(file, line)
+                    .map_or(Value::Null, |x| x.into()),
+            )]))
+        }
+        Some("remove_breakpoint") => {
+            let (file, line) = location()?;
+            Ok(Value::Map(vec![(
+                "removed".into(),
+                debugger.remove_breakpoint(file, line).into(),
+            )]))
+        }
+        Some("clear_breakpoints") => {
+            debugger.clear_breakpoints();
+            Ok(Value::Null)
+        }
+        Some("pause_on_exceptions") => {
+            debugger.set_pause_on_exceptions(enabled);
+            Ok(Value::Null)
+        }
+        Some("pause") => {
+            debugger.pause();
+            Ok(Value::Null)
+        }
+        _ => Err(worthless_bridge::Error::new(
+            ErrorKind::InternalError,
+            "unknown debug action",
+        )),
+    }
+}
+
+/// Reports a pause to the host until it asks to resume.
+///
+/// While paused the host can evaluate expressions and use the actions of the
+/// debug control endpoint, their results are reported with the next pause.
+fn on_pause(pause: &Pause) -> Resume {
+    let mut result = None;
+    loop {
+        let req = Request::new(DEBUG_PAUSED_ENDPOINT, pause_to_value(pause, result.take()));
+        let payload = match call_host(&req) {
+            Ok(response) => response.into_payload().map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        let payload = match payload {
+            Ok(payload) => payload,
+            Err(err) => {
+                eprintln!("[worthless] debugger cannot report pause: {}", err);
+                return Resume::Continue;
+            }
+        };
+        let mut action = None;
+        let mut expression = None;
+        if let Value::Map(ref items) = payload {
+            for (key, value) in items {
+                match (key.as_text(), value) {
+                    (Some("action"), Value::Text(value)) => action = Some(value.as_str()),
+                    (Some("expression"), Value::Text(value)) => expression = Some(value.as_str()),
+                    _ => {}
+                }
+            }
+        }
+        result = Some(match action {
+            Some("continue") | None => return Resume::Continue,
+            Some("step_over") => return Resume::StepOver,
+            Some("step_into") => return Resume::StepInto,
+            Some("step_out") => return Resume::StepOut,
+            Some("evaluate") => pause
+                .evaluate(expression.unwrap_or("undefined"))
+                .map(|x| preview(&x).into())
+                .map_err(|err| match err {
+                    worthless_js_rt::Error::JsException(exc) => exc.message().to_string(),
+                    err => err.to_string(),
+                }),
+            // pausing again while paused does nothing useful
+            Some("pause") => Err("the plugin is paused already".to_string()),
+            Some(_) => {
+                let dispatcher = Dispatcher::current();
+                control_debugger(dispatcher.as_ref().and_then(|x| x.debugger()), &payload)
+                    .map_err(|err| err.to_string())
+            }
+        });
+    }
+}
+
+/// Describes a pause for the host.
+fn pause_to_value(pause: &Pause, result: Option<Result<Value, String>>) -> Value {
+    let reason = match pause.reason() {
+        PauseReason::Breakpoint => "breakpoint",
+        PauseReason::Step => "step",
+        PauseReason::Requested => "requested",
+        PauseReason::Exception => "exception",
+    };
+    let optional = |value: Option<u32>| value.map_or(Value::Null, |x| x.into());
+    let stack = pause
+        .stack()
+        .iter()
+        .map(|frame| {
+            Value::Map(vec![
+                ("function".into(), frame.function.as_str().into()),
+                (
+                    "file".into(),
+                    frame.filename.as_deref().map_or(Value::Null, |x| x.into()),
+                ),
+                ("line".into(), optional(frame.lineno)),
+                ("column".into(), optional(frame.colno)),
+            ])
+        })
+        .collect();
+    let scope = pause
+        .scope()
+        .into_iter()
+        .map(|(name, value)| (name.into(), preview(&value).into()))
+        .collect();
+
+    let mut rv = vec![
+        ("reason".into(), reason.into()),
+        ("file".into(), pause.filename().into()),
+        ("line".into(), pause.line().into()),
+        ("stack".into(), Value::Array(stack)),
+        ("scope".into(), Value::Map(scope)),
+    ];
+    if let Some(exception) = pause.exception() {
+        rv.push(("exception".into(), preview(exception).into()));
+    }
+    match result {
+        Some(Ok(result)) => rv.push(("result".into(), result)),
+        Some(Err(error)) => rv.push(("error".into(), error.into())),
+        None => {}
+    }
+    Value::Map(rv)
+}
+
+/// Formats a value for display in a debugger.
+fn preview(value: &worthless_js_rt::Value) -> String {
+    format!("{:?}", value.debug(DEFAULT_DEBUG_DEPTH))
+}
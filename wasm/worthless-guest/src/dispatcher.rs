@@ -0,0 +1,1033 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+use worthless_bridge::{
+    ErrorKind, RateLimiter, Request, Response, COVERAGE_ENDPOINT, COVERAGE_ENV_VAR, DEBUG_ENDPOINT,
+    DEBUG_ENV_VAR, ENDPOINTS_ENDPOINT, GC_ENDPOINT, MAX_REQUEST_SIZE_ENV_VAR, PROFILE_ENDPOINT,
+    PUBLISH_ENDPOINT, STREAM_ENDPOINT, TICK_ENDPOINT,
+};
+use worthless_js_rt::{
+    Context, Debugger, JsException, Primitive, Profile, Profiler, Runtime, Value, ValueKind,
+};
+
+use crate::config::{load_env_config, merge_config, meta_to_value};
+use crate::console::make_bridge_console;
+use crate::convert::{from_js, to_js, transfer_from_js};
+use crate::debugger::{attach_debugger, control_debugger};
+use crate::error::Error;
+use crate::exception::{exception_to_error, rejection_to_error};
+use crate::fetch::install_fetch;
+use crate::host::call_host;
+use crate::pubsub::{js_subscribe, js_unsubscribe, publish, Subscriptions};
+use crate::streams::install_streams;
+use crate::timers::{
+    install_timers, parse_tick, ImmediatePhase, MicrotaskCheckpoint, TaskOrder, Timers,
+};
+use crate::InitFunc;
+
+/// How long a handler may wait for its timers by default.
+const DEFAULT_AWAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The number of requests after which the memory watermark is checked.
+///
+/// Computing the memory usage walks the whole heap, so it's not done after
+/// every request.
+const GC_CHECK_INTERVAL: u32 = 16;
+
+/// A host call that is performed once the job queue ran dry.
+struct DeferredHostCall {
+    request: Request,
+    resolve: Value,
+    reject: Value,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Rc<Dispatcher>>> = const { RefCell::new(None) };
+}
+
+/// Dispatches requests from the host to JavaScript handlers.
+///
+/// A dispatcher owns the context that all requests are handled in.  There is
+/// at most one active dispatcher which can be retrieved with
+/// [`Dispatcher::current`].
+pub struct Dispatcher {
+    ctx: Context,
+    ns: Value,
+    stream_push: Value,
+    // the buffers handlers passed to `worthless.transfer`
+    transferable: Value,
+    // the config from the environment, read once when the dispatcher is
+    // created
+    env_config: BTreeMap<String, worthless_bridge::Value>,
+    handlers: RefCell<BTreeMap<String, Value>>,
+    subscriptions: RefCell<Subscriptions>,
+    init_error: RefCell<Option<String>>,
+    current_request_id: RefCell<Option<String>>,
+    unhandled_rejections: RefCell<Vec<(Value, Value)>>,
+    deferred_host_calls: RefCell<Vec<DeferredHostCall>>,
+    timers: RefCell<Timers>,
+    task_order: Cell<TaskOrder>,
+    rate_limiter: RefCell<Option<RateLimiter>>,
+    gc_watermark: Cell<Option<u64>>,
+    requests_since_gc_check: Cell<u32>,
+    max_request_size: Cell<Option<usize>>,
+    await_timeout: Cell<Duration>,
+    profiler: RefCell<Option<Profiler>>,
+    debugger: Option<Debugger>,
+}
+
+impl Dispatcher {
+    /// Creates a new dispatcher for a context.
+    ///
+    /// This installs the `worthless` namespace into the global object,
+    /// replaces the console with one that logs to the host and installs the
+    /// stack trace API, streams, `fetch` and the timer functions.  If the
+    /// `WORTHLESS_DEBUG` environment variable is set a debugger is attached
+    /// that reports to the host, if `WORTHLESS_COVERAGE` is set coverage is
+    /// recorded.
+    pub fn new(ctx: &Context) -> Result<Dispatcher, Error> {
+        let global = ctx.global();
+        let ns = Value::new_object(ctx);
+        ns.set_property("register", Value::from_func(ctx, "register", js_register)?)?;
+        ns.set_property(
+            "subscribe",
+            Value::from_func(ctx, "subscribe", js_subscribe)?,
+        )?;
+        ns.set_property(
+            "unsubscribe",
+            Value::from_func(ctx, "unsubscribe", js_unsubscribe)?,
+        )?;
+        ns.set_property("transfer", Value::from_func(ctx, "transfer", js_transfer)?)?;
+        global.set_property("worthless", ns.clone())?;
+        global.set_property("console", make_bridge_console(ctx)?)?;
+        let stream_push = install_streams(ctx)?;
+        let transferable = ctx.eval_with_filename("new WeakSet()", "<transfer>")?;
+        install_fetch(ctx, &ns)?;
+        install_timers(ctx, &global)?;
+        #[cfg(feature = "intl")]
+        ctx.install_intl()?;
+        ctx.rt()
+            .set_promise_rejection_tracker(Some(track_rejection));
+        let env_config = load_env_config();
+        ns.set_property(
+            "config",
+            to_js(ctx, &merge_config(&env_config, &BTreeMap::new()))?,
+        )?;
+        ns.set_property("meta", Value::new_object(ctx))?;
+        if std::env::var_os(COVERAGE_ENV_VAR).is_some() {
+            ctx.enable_coverage()?;
+        }
+        let debugger = match std::env::var_os(DEBUG_ENV_VAR) {
+            Some(_) => Some(attach_debugger(ctx)?),
+            None => None,
+        };
+        Ok(Dispatcher {
+            ctx: ctx.clone(),
+            ns,
+            stream_push,
+            transferable,
+            env_config,
+            handlers: RefCell::new(BTreeMap::new()),
+            subscriptions: RefCell::new(Subscriptions::default()),
+            init_error: RefCell::new(None),
+            current_request_id: RefCell::new(None),
+            unhandled_rejections: RefCell::new(Vec::new()),
+            deferred_host_calls: RefCell::new(Vec::new()),
+            timers: RefCell::new(Timers::default()),
+            task_order: Cell::new(TaskOrder::default()),
+            rate_limiter: RefCell::new(None),
+            gc_watermark: Cell::new(None),
+            requests_since_gc_check: Cell::new(0),
+            max_request_size: Cell::new(
+                std::env::var(MAX_REQUEST_SIZE_ENV_VAR)
+                    .ok()
+                    .and_then(|x| x.parse().ok()),
+            ),
+            await_timeout: Cell::new(DEFAULT_AWAIT_TIMEOUT),
+            profiler: RefCell::new(None),
+            debugger,
+        })
+    }
+
+    /// Creates and activates the dispatcher with a fresh context.
+    ///
+    /// The init function is invoked after the dispatcher was activated.  If it
+    /// fails, all requests are answered with an error.
+    pub fn initialize(init: InitFunc) -> Result<Rc<Dispatcher>, Error> {
+        let rt = Runtime::new()?;
+        let ctx = Context::new(&rt)?;
+        let dispatcher = Dispatcher::new(&ctx)?.activate();
+        if let Err(err) = init(&ctx) {
+            *dispatcher.init_error.borrow_mut() =
+                Some(worthless_bridge::Error::from(err).to_string());
+        }
+        Ok(dispatcher)
+    }
+
+    /// Makes this dispatcher the current one.
+    pub fn activate(self) -> Rc<Dispatcher> {
+        let rv = Rc::new(self);
+        CURRENT.with(|current| *current.borrow_mut() = Some(rv.clone()));
+        rv
+    }
+
+    /// Returns the current dispatcher.
+    ///
+    /// This never panics, so it can be used from the panic hook, which also
+    /// runs while the current dispatcher is being replaced.
+    pub fn current() -> Option<Rc<Dispatcher>> {
+        CURRENT
+            .try_with(|current| current.try_borrow().ok()?.clone())
+            .ok()
+            .flatten()
+    }
+
+    /// Returns the context requests are handled in.
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+
+    /// Returns the debugger if one is attached.
+    pub(crate) fn debugger(&self) -> Option<&Debugger> {
+        self.debugger.as_ref()
+    }
+
+    /// Returns the ID of the request that is currently being handled.
+    ///
+    /// Like [`current`](Self::current) this never panics, the ID is `None`
+    /// while it is being changed.
+    pub fn current_request_id(&self) -> Option<String> {
+        self.current_request_id.try_borrow().ok()?.clone()
+    }
+
+    /// Returns the timers of the context.
+    pub(crate) fn timers(&self) -> &RefCell<Timers> {
+        &self.timers
+    }
+
+    /// Returns the listeners of the topics the plugin subscribed to.
+    pub(crate) fn subscriptions(&self) -> &RefCell<Subscriptions> {
+        &self.subscriptions
+    }
+
+    /// Sets the order in which queued work runs.
+    pub fn set_task_order(&self, order: TaskOrder) {
+        self.task_order.set(order);
+    }
+
+    /// Returns the order in which queued work runs.
+    pub fn task_order(&self) -> TaskOrder {
+        self.task_order.get()
+    }
+
+    /// Sets or clears the rate limiter requests are checked against.
+    ///
+    /// Rejected requests are answered with a rate limited error without
+    /// invoking the handler.  Ticks are never limited.
+    pub fn set_rate_limiter(&self, limiter: Option<RateLimiter>) {
+        *self.rate_limiter.borrow_mut() = limiter;
+    }
+
+    /// Sets or clears the memory watermark for collecting garbage.
+    ///
+    /// The watermark is checked every 16 requests.  When the runtime holds
+    /// more than this many bytes by then, the garbage collector is run before
+    /// the next request is read.
+    pub fn set_gc_watermark(&self, watermark: Option<u64>) {
+        self.gc_watermark.set(watermark);
+        self.requests_since_gc_check.set(0);
+    }
+
+    /// Sets or clears the maximum size of a request in bytes.
+    ///
+    /// Larger requests are answered with a payload too large error.  At most
+    /// one byte past the limit is read, so the requests after one that could
+    /// not be decoded within it are discarded.  The host sets the initial
+    /// limit with [`MAX_REQUEST_SIZE_ENV_VAR`].
+    pub fn set_max_request_size(&self, limit: Option<usize>) {
+        self.max_request_size.set(limit);
+    }
+
+    /// Sets how long a handler may wait for its timers, 30 seconds by
+    /// default.
+    ///
+    /// A handler whose promise waits for a timer that is due later fails
+    /// right away instead of blocking the plugin until then.
+    pub fn set_await_timeout(&self, timeout: Duration) {
+        self.await_timeout.set(timeout);
+    }
+
+    /// Starts sampling where JavaScript code spends its time.
+    ///
+    /// A running profiler is replaced and its samples are discarded.
+    pub fn start_profiling(&self, interval: Duration) -> Result<(), Error> {
+        // the old profiler has to be gone before the new one hooks the runtime
+        self.profiler.borrow_mut().take();
+        *self.profiler.borrow_mut() = Some(Profiler::start(&self.ctx, interval)?);
+        Ok(())
+    }
+
+    /// Stops the profiler and returns its samples if it was running.
+    pub fn stop_profiling(&self) -> Option<Profile> {
+        self.profiler.borrow_mut().take().map(Profiler::stop)
+    }
+
+    /// Runs the garbage collector of the runtime.
+    ///
+    /// Returns the bytes held by the runtime before and after the collection.
+    pub fn collect_garbage(&self) -> (u64, u64) {
+        let rt = self.ctx.rt();
+        let before = rt.memory_usage().malloc_size;
+        rt.run_gc();
+        (before, rt.memory_usage().malloc_size)
+    }
+
+    fn collect_garbage_above_watermark(&self) {
+        let watermark = match self.gc_watermark.get() {
+            Some(watermark) => watermark,
+            None => return,
+        };
+        let requests = self.requests_since_gc_check.get() + 1;
+        if requests < GC_CHECK_INTERVAL {
+            self.requests_since_gc_check.set(requests);
+            return;
+        }
+        self.requests_since_gc_check.set(0);
+        if self.ctx.rt().memory_usage().malloc_size > watermark {
+            self.collect_garbage();
+        }
+    }
+
+    /// Registers a JavaScript function as handler for an endpoint.
+    pub fn register(&self, endpoint: &str, handler: Value) -> Result<(), Error> {
+        if !handler.is_function() {
+            return Err(worthless_js_rt::Error::InvalidArgument(format!(
+                "handler for '{}' is not a function",
+                endpoint
+            ))
+            .into());
+        }
+        self.handlers
+            .borrow_mut()
+            .insert(endpoint.to_string(), handler);
+        Ok(())
+    }
+
+    /// Lists the endpoints handlers are registered for.
+    fn endpoints(&self) -> worthless_bridge::Value {
+        let endpoints = self
+            .handlers
+            .borrow()
+            .keys()
+            .map(|endpoint| endpoint.as_str().into())
+            .collect();
+        worthless_bridge::Value::Map(vec![(
+            "endpoints".into(),
+            worthless_bridge::Value::Array(endpoints),
+        )])
+    }
+
+    /// Handles a single request.
+    ///
+    /// Before the handler is invoked `worthless.meta` is set to the meta of the
+    /// request and `worthless.config` to the environment config overlaid with
+    /// the `config` meta key.  If the handler returns a promise, pending jobs,
+    /// host calls and timers run until it settled.  Other promises created by
+    /// the handler are not necessarily settled when this returns.
+    ///
+    /// Requests to the `__tick` control endpoint are not dispatched to a
+    /// handler but fire the due timers instead, requests to `__gc` collect
+    /// garbage, requests to `__profile` control the profiler, requests to
+    /// `__debug` the debugger, requests to `__publish` pass events to the
+    /// listeners of their topic, requests to `__coverage` collect the
+    /// coverage and requests to `__endpoints` list the endpoints handlers are
+    /// registered for.
+    pub fn handle_request(&self, req: &Request) -> Response {
+        match self.invoke(req) {
+            Ok(payload) => Response::builder().raw_payload(payload).build(),
+            Err(err) => Response::builder().error(err).build(),
+        }
+    }
+
+    fn invoke(&self, req: &Request) -> Result<worthless_bridge::Value, worthless_bridge::Error> {
+        if req.endpoint() == TICK_ENDPOINT {
+            return self.tick(req);
+        }
+        if req.endpoint() == GC_ENDPOINT {
+            return self.gc(req);
+        }
+        if req.endpoint() == STREAM_ENDPOINT {
+            return self.push_stream(req);
+        }
+        if req.endpoint() == PROFILE_ENDPOINT {
+            return self.profile(req);
+        }
+        if req.endpoint() == DEBUG_ENDPOINT {
+            return control_debugger(self.debugger.as_ref(), req.payload());
+        }
+        if req.endpoint() == PUBLISH_ENDPOINT {
+            return publish(self, req);
+        }
+        if req.endpoint() == COVERAGE_ENDPOINT {
+            return self.coverage();
+        }
+        if let Some(ref msg) = *self.init_error.borrow() {
+            return Err(worthless_bridge::Error::new(
+                ErrorKind::InternalError,
+                format!("plugin failed to initialize: {}", msg),
+            ));
+        }
+        if req.endpoint() == ENDPOINTS_ENDPOINT {
+            return Ok(self.endpoints());
+        }
+        if let Some(ref limiter) = *self.rate_limiter.borrow() {
+            limiter.check(req)?;
+        }
+        // the handler is cloned out so that handlers can register other handlers
+        let handler = self.handlers.borrow().get(req.endpoint()).cloned();
+        let handler = handler.ok_or_else(|| {
+            worthless_bridge::Error::new(
+                ErrorKind::UnknownEndpoint,
+                format!("unknown endpoint '{}'", req.endpoint()),
+            )
+        })?;
+        self.expose_meta(req)?;
+        let payload = to_js(&self.ctx, req.payload())?;
+        let this = Value::from_primitive(&self.ctx, Primitive::Undefined);
+        let rv = handler.call(&this, &[payload]).map_err(Error::Runtime)?;
+        // buffers the handler gave up with `worthless.transfer` are moved out
+        if is_thenable(&rv) {
+            return Ok(transfer_from_js(
+                &self.await_promise(&rv)?,
+                &self.transferable,
+            )?);
+        }
+        Ok(transfer_from_js(&rv, &self.transferable)?)
+    }
+
+    /// Waits for a promise returned by a handler to settle.
+    ///
+    /// Pending jobs, deferred host calls and immediates run until the promise
+    /// settled.  If only timers are left the guest sleeps until the next one
+    /// is due, as the host cannot tick the plugin while it waits for the
+    /// response.  The time is measured on the estimated clock of the host
+    /// throughout, see [`Timers::estimated_now`].  A promise that cannot
+    /// settle anymore or only after the [await
+    /// timeout](Self::set_await_timeout) fails the request.
+    fn await_promise(&self, promise: &Value) -> Result<Value, worthless_bridge::Error> {
+        // the callbacks record the outcome on the slot they are bound to
+        let slot = Value::new_object(&self.ctx);
+        let undefined = Value::from_primitive(&self.ctx, Primitive::Undefined);
+        let settle = Value::from_func(&self.ctx, "settle", settle_slot).map_err(Error::Runtime)?;
+        let bind = |fulfilled: bool| {
+            settle
+                .bind(
+                    &undefined,
+                    [slot.clone(), Value::from_primitive(&self.ctx, fulfilled)],
+                )
+                .map_err(Error::Runtime)
+        };
+        promise
+            .call_method("then", [bind(true)?, bind(false)?])
+            .map_err(Error::Runtime)?;
+
+        let order = self.task_order.get();
+        let timeout = self.await_timeout.get().as_secs_f64() * 1000.0;
+        let give_up_at = self.timers.borrow().estimated_now() + timeout;
+        loop {
+            self.run_pending_jobs();
+            let fulfilled = slot.get_property("fulfilled").map_err(Error::Runtime)?;
+            if fulfilled.kind() != ValueKind::Undefined {
+                let value = slot.get_property("value").map_err(Error::Runtime)?;
+                if fulfilled.is_true() {
+                    return Ok(value);
+                }
+                return Err(exception_to_error(&JsException::from_value(&value)));
+            }
+            if self.timers.borrow().has_immediates() {
+                self.run_immediates();
+                continue;
+            }
+            let deadline = match self.timers.borrow().next_deadline() {
+                Some(deadline) => deadline,
+                None => break,
+            };
+            if deadline > give_up_at {
+                return Err(worthless_bridge::Error::new(
+                    ErrorKind::InternalError,
+                    "handler returned a promise that did not settle in time",
+                ));
+            }
+            let now = self.timers.borrow().estimated_now();
+            if deadline > now {
+                std::thread::sleep(Duration::from_secs_f64((deadline - now) / 1000.0));
+            }
+            let now = self.timers.borrow().estimated_now();
+            self.fire_timers(now.max(deadline), order);
+        }
+        Err(worthless_bridge::Error::new(
+            ErrorKind::InternalError,
+            "handler returned a promise that never settles",
+        ))
+    }
+
+    /// Fires all timers that are due at the time of the tick.
+    ///
+    /// Timers scheduled while the tick is processed fire on the next tick at
+    /// the earliest.  Immediates run before or after the timers as configured
+    /// with [`set_task_order`](Self::set_task_order).
+    fn tick(&self, req: &Request) -> Result<worthless_bridge::Value, worthless_bridge::Error> {
+        let now = parse_tick(req.payload()).ok_or_else(|| {
+            worthless_bridge::Error::new(ErrorKind::InternalError, "tick without a valid time")
+        })?;
+        let order = self.task_order.get();
+        if order.immediate_phase() == ImmediatePhase::BeforeTimers {
+            self.run_immediates();
+        }
+        self.fire_timers(now, order);
+        self.run_pending_jobs();
+        if order.immediate_phase() == ImmediatePhase::AfterTimers {
+            self.run_immediates();
+        }
+        let next_deadline = match self.timers.borrow().next_deadline() {
+            Some(deadline) => worthless_bridge::Value::from(deadline.ceil() as u64),
+            None => worthless_bridge::Value::Null,
+        };
+        Ok(worthless_bridge::Value::Map(vec![(
+            "next_deadline".into(),
+            next_deadline,
+        )]))
+    }
+
+    /// Advances the clock of the timers and fires the ones that are due.
+    ///
+    /// Timers scheduled by the callbacks fire the next time at the earliest.
+    fn fire_timers(&self, now: f64, order: TaskOrder) {
+        let max_id = {
+            let mut timers = self.timers.borrow_mut();
+            timers.advance_to(now);
+            timers.last_id()
+        };
+        let this = Value::from_primitive(&self.ctx, Primitive::Undefined);
+        loop {
+            // the borrow must end before the callback runs as it can schedule timers
+            let timer = self.timers.borrow_mut().pop_due(max_id);
+            let timer = match timer {
+                Some(timer) => timer,
+                None => break,
+            };
+            if let Err(err) = timer.callback.call(&this, &timer.args) {
+                eprintln!("[worthless] timer callback failed: {}", err);
+            }
+            if order.microtask_checkpoint() == MicrotaskCheckpoint::EveryTask {
+                self.run_pending_jobs();
+            }
+        }
+    }
+
+    /// Collects garbage and updates the watermark if the payload has one.
+    fn gc(&self, req: &Request) -> Result<worthless_bridge::Value, worthless_bridge::Error> {
+        if let worthless_bridge::Value::Map(items) = req.payload() {
+            for (key, value) in items {
+                if key.as_text() != Some("watermark") {
+                    continue;
+                }
+                let watermark = match value {
+                    worthless_bridge::Value::Null => None,
+                    value => Some(
+                        value
+                            .as_integer()
+                            .and_then(|x| u64::try_from(x).ok())
+                            .ok_or_else(|| {
+                                worthless_bridge::Error::new(
+                                    ErrorKind::InternalError,
+                                    "gc watermark is not a valid size",
+                                )
+                            })?,
+                    ),
+                };
+                self.set_gc_watermark(watermark);
+            }
+        }
+        let (before, after) = self.collect_garbage();
+        Ok(worthless_bridge::Value::Map(vec![
+            ("before".into(), before.into()),
+            ("after".into(), after.into()),
+        ]))
+    }
+
+    /// Collects the coverage of the scripts evaluated so far.
+    fn coverage(&self) -> Result<worthless_bridge::Value, worthless_bridge::Error> {
+        let coverage = self.ctx.coverage().map_err(Error::Runtime)?;
+        let scripts = coverage
+            .scripts()
+            .iter()
+            .map(|script| {
+                let lines = script
+                    .lines
+                    .iter()
+                    .map(|x| worthless_bridge::Value::Array(vec![x.line.into(), x.hits.into()]))
+                    .collect();
+                let functions = script
+                    .functions
+                    .iter()
+                    .map(|x| {
+                        worthless_bridge::Value::Array(vec![
+                            x.name.as_str().into(),
+                            x.line.into(),
+                            x.hits.into(),
+                        ])
+                    })
+                    .collect();
+                worthless_bridge::Value::Map(vec![
+                    ("filename".into(), script.filename.as_str().into()),
+                    ("lines".into(), worthless_bridge::Value::Array(lines)),
+                    (
+                        "functions".into(),
+                        worthless_bridge::Value::Array(functions),
+                    ),
+                ])
+            })
+            .collect();
+        Ok(worthless_bridge::Value::Map(vec![(
+            "scripts".into(),
+            worthless_bridge::Value::Array(scripts),
+        )]))
+    }
+
+    /// Pushes a chunk into a stream or closes it.
+    ///
+    /// Readers are resumed when the pending jobs run after the request.
+    fn push_stream(
+        &self,
+        req: &Request,
+    ) -> Result<worthless_bridge::Value, worthless_bridge::Error> {
+        let message = to_js(&self.ctx, req.payload())?;
+        let this = Value::from_primitive(&self.ctx, Primitive::Undefined);
+        let rv = self
+            .stream_push
+            .call(&this, &[message])
+            .map_err(Error::Runtime)?;
+        Ok(from_js(&rv)?)
+    }
+
+    /// Starts the profiler or stops it and exports the profile.
+    fn profile(&self, req: &Request) -> Result<worthless_bridge::Value, worthless_bridge::Error> {
+        let mut action = None;
+        let mut interval = Duration::from_millis(1);
+        let mut format = None;
+        if let worthless_bridge::Value::Map(items) = req.payload() {
+            for (key, value) in items {
+                match key.as_text() {
+                    Some("action") => action = value.as_text(),
+                    Some("interval") => {
+                        interval = value
+                            .as_integer()
+                            .and_then(|x| u64::try_from(x).ok())
+                            .map(Duration::from_micros)
+                            .ok_or_else(|| {
+                                worthless_bridge::Error::new(
+                                    ErrorKind::InternalError,
+                                    "profile interval is not a valid duration",
+                                )
+                            })?;
+                    }
+                    Some("format") => format = value.as_text(),
+                    _ => {}
+                }
+            }
+        }
+        match action {
+            Some("start") => {
+                self.start_profiling(interval)?;
+                Ok(worthless_bridge::Value::Null)
+            }
+            Some("stop") => {
+                let profile = self.stop_profiling().ok_or_else(|| {
+                    worthless_bridge::Error::new(
+                        ErrorKind::InternalError,
+                        "profiler is not running",
+                    )
+                })?;
+                let exported = match format.unwrap_or("collapsed") {
+                    "collapsed" => profile.to_collapsed(),
+                    "speedscope" => profile.to_speedscope("plugin"),
+                    format => {
+                        return Err(worthless_bridge::Error::new(
+                            ErrorKind::InternalError,
+                            format!("unknown profile format '{}'", format),
+                        ))
+                    }
+                };
+                Ok(worthless_bridge::Value::Map(vec![
+                    ("samples".into(), (profile.samples().len() as u64).into()),
+                    ("profile".into(), exported.into()),
+                ]))
+            }
+            _ => Err(worthless_bridge::Error::new(
+                ErrorKind::InternalError,
+                "profile action must be start or stop",
+            )),
+        }
+    }
+
+    fn expose_meta(&self, req: &Request) -> Result<(), Error> {
+        let config = merge_config(&self.env_config, req.meta());
+        self.ns.set_property("config", to_js(&self.ctx, &config)?)?;
+        self.ns
+            .set_property("meta", to_js(&self.ctx, &meta_to_value(req.meta()))?)?;
+        Ok(())
+    }
+
+    /// Sends a request to the host once the job queue ran dry.
+    ///
+    /// Returns a promise that is resolved with the payload of the response or
+    /// rejected with the error of the response.  The call itself blocks, it is
+    /// just deferred so that it does not run in the middle of a JavaScript
+    /// call.
+    pub fn call_host_deferred(&self, request: Request) -> Result<Value, Error> {
+        let (promise, resolve, reject) = Value::new_promise(&self.ctx)?;
+        self.deferred_host_calls
+            .borrow_mut()
+            .push(DeferredHostCall {
+                request,
+                resolve,
+                reject,
+            });
+        Ok(promise)
+    }
+
+    /// Runs all pending jobs.
+    ///
+    /// Deferred host calls are performed whenever the job queue ran dry and
+    /// the jobs they schedule are run as well.  Jobs that fail are reported to
+    /// stderr but do not stop the job queue.
+    pub fn run_pending_jobs(&self) {
+        loop {
+            match self.ctx.rt().run_pending_job() {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(err) => {
+                    eprintln!("[worthless] pending job failed: {}", err);
+                    continue;
+                }
+            }
+            let calls = std::mem::take(&mut *self.deferred_host_calls.borrow_mut());
+            if calls.is_empty() {
+                break;
+            }
+            for call in calls {
+                if let Err(err) = self.settle_host_call(call) {
+                    eprintln!("[worthless] failed to settle host call: {}", err);
+                }
+            }
+        }
+    }
+
+    /// Runs the queued immediates.
+    ///
+    /// Every round runs the immediates that were queued when it started, so
+    /// immediates queued by immediates run in the next round.  Rounds repeat
+    /// until no immediates are left or the limit of the task order is reached.
+    pub fn run_immediates(&self) {
+        let order = self.task_order.get();
+        let this = Value::from_primitive(&self.ctx, Primitive::Undefined);
+        for _ in 0..order.immediate_round_limit() {
+            if !self.timers.borrow().has_immediates() {
+                break;
+            }
+            let max_id = self.timers.borrow().last_id();
+            loop {
+                let immediate = self.timers.borrow_mut().pop_immediate(max_id);
+                let immediate = match immediate {
+                    Some(immediate) => immediate,
+                    None => break,
+                };
+                if let Err(err) = immediate.callback.call(&this, &immediate.args) {
+                    eprintln!("[worthless] immediate callback failed: {}", err);
+                }
+                if order.microtask_checkpoint() == MicrotaskCheckpoint::EveryTask {
+                    self.run_pending_jobs();
+                }
+            }
+            self.run_pending_jobs();
+        }
+    }
+
+    fn settle_host_call(&self, call: DeferredHostCall) -> Result<(), Error> {
+        let this = Value::from_primitive(&self.ctx, Primitive::Undefined);
+        let result = call_host(&call.request)
+            .map_err(worthless_bridge::Error::from)
+            .and_then(|response| response.into_payload());
+        match result {
+            Ok(payload) => {
+                call.resolve.call(&this, &[to_js(&self.ctx, &payload)?])?;
+            }
+            Err(err) => {
+                let error = self
+                    .ctx
+                    .with_global(|global| global.get_property("Error"))?
+                    .call(&this, [err.to_string().as_str()])?;
+                call.reject.call(&this, &[error])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns an error for the first unhandled promise rejection.
+    ///
+    /// This clears all unhandled rejections that were recorded.
+    fn take_unhandled_rejection(&self) -> Option<worthless_bridge::Error> {
+        let rejections = std::mem::take(&mut *self.unhandled_rejections.borrow_mut());
+        rejections
+            .into_iter()
+            .next()
+            .map(|(_, reason)| rejection_to_error(&reason))
+    }
+
+    /// Processes all requests available from a reader.
+    ///
+    /// Requests are handled in order and pending jobs and immediates are run
+    /// after each of them.  Responses are written to the writer in the same
+    /// order, fire and forget requests do not produce a response.  Returns
+    /// the number of requests handled.
+    pub fn process<R: Read, W: Write>(&self, input: R, output: W) -> Result<usize, Error> {
+        self.process_from(BufReader::new(input), output)
+    }
+
+    /// Processes the requests in a buffer like [`process`](Self::process).
+    pub fn process_bytes<W: Write>(&self, buf: &[u8], output: W) -> Result<usize, Error> {
+        self.process_from(buf, output)
+    }
+
+    fn process_from<R: BufRead, W: Write>(
+        &self,
+        mut input: R,
+        mut output: W,
+    ) -> Result<usize, Error> {
+        let mut count = 0;
+        while !input.fill_buf().map_err(Error::BridgeIo)?.is_empty() {
+            let req = match self.max_request_size.get() {
+                Some(limit) => {
+                    // never read more than one byte past the limit
+                    let mut limited = (&mut input).take(limit as u64 + 1);
+                    let rv = Request::deserialize_from(&mut limited);
+                    if limited.limit() > 0 {
+                        rv.map_err(Error::Protocol)?
+                    } else if let Ok(req) = rv {
+                        if !req.fire_and_forget() {
+                            request_too_large(Some(req.endpoint()), limit)
+                                .serialize_to(&mut output)
+                                .map_err(Error::Protocol)?;
+                        }
+                        count += 1;
+                        continue;
+                    } else {
+                        // the rest of the request is unread, so the requests
+                        // after it cannot be found anymore
+                        request_too_large(None, limit)
+                            .serialize_to(&mut output)
+                            .map_err(Error::Protocol)?;
+                        output.flush().map_err(Error::BridgeIo)?;
+                        return Err(Error::Protocol(worthless_bridge::Error::new(
+                            ErrorKind::PayloadTooLarge,
+                            "discarded the requests after an oversized one",
+                        )));
+                    }
+                }
+                None => Request::deserialize_from(&mut input).map_err(Error::Protocol)?,
+            };
+            *self.current_request_id.borrow_mut() = req.request_id().map(|x| x.to_string());
+            let mut response = self.handle_request(&req);
+            self.run_pending_jobs();
+            // ticks run the immediates in their own phase
+            if req.endpoint() != TICK_ENDPOINT {
+                self.run_immediates();
+            }
+            if let Some(err) = self.take_unhandled_rejection() {
+                if response.error_ref().is_none() {
+                    response = Response::builder().error(err).build();
+                } else {
+                    eprintln!("[worthless] {}", err);
+                }
+            }
+            *self.current_request_id.borrow_mut() = None;
+            if !req.fire_and_forget() {
+                response
+                    .serialize_to(&mut output)
+                    .map_err(Error::Protocol)?;
+            }
+            self.collect_garbage_above_watermark();
+            count += 1;
+        }
+
+        output.flush().map_err(Error::BridgeIo)?;
+        Ok(count)
+    }
+}
+
+/// Returns the response to a request above the size limit.
+fn request_too_large(endpoint: Option<&str>, limit: usize) -> Response {
+    let description = match endpoint {
+        Some(endpoint) => format!("request to '{}' has more than {} bytes", endpoint, limit),
+        None => format!("request has more than {} bytes", limit),
+    };
+    Response::builder()
+        .error(worthless_bridge::Error::new(
+            ErrorKind::PayloadTooLarge,
+            description,
+        ))
+        .build()
+}
+
+/// Checks if a value returned by a handler has to be awaited.
+fn is_thenable(value: &Value) -> bool {
+    value.kind() == ValueKind::Object
+        && value
+            .get_property("then")
+            .is_ok_and(|then| then.is_function())
+}
+
+/// Records the outcome of a promise on the slot the function is bound to.
+fn settle_slot(
+    ctx: &Context,
+    _this: &Value,
+    args: &[Value],
+) -> Result<Value, worthless_js_rt::Error> {
+    if let [slot, fulfilled, rest @ ..] = args {
+        let value = match rest.first() {
+            Some(value) => value.clone(),
+            None => Value::from_primitive(ctx, Primitive::Undefined),
+        };
+        slot.set_property("value", value)?;
+        slot.set_property("fulfilled", fulfilled.clone())?;
+    }
+    Ok(Value::from_primitive(ctx, Primitive::Undefined))
+}
+
+fn track_rejection(_ctx: &Context, promise: &Value, reason: &Value, is_handled: bool) {
+    if let Some(dispatcher) = Dispatcher::current() {
+        let mut rejections = dispatcher.unhandled_rejections.borrow_mut();
+        if is_handled {
+            rejections.retain(|(rejected, _)| !rejected.ptr_eq(promise));
+        } else {
+            rejections.push((promise.clone(), reason.clone()));
+        }
+    }
+}
+
+fn js_transfer(
+    _ctx: &Context,
+    _this: &Value,
+    args: &[Value],
+) -> Result<Value, worthless_js_rt::Error> {
+    let dispatcher = Dispatcher::current()
+        .ok_or_else(|| worthless_js_rt::Error::InvalidArgument("no active dispatcher".into()))?;
+    match args.first() {
+        Some(buffer) if buffer.is_array_buffer() => {
+            dispatcher.transferable.call_method("add", [buffer])?;
+            Ok(buffer.clone())
+        }
+        _ => Err(worthless_js_rt::Error::InvalidArgument(
+            "transfer requires an ArrayBuffer or typed array".into(),
+        )),
+    }
+}
+
+fn js_register(
+    ctx: &Context,
+    _this: &Value,
+    args: &[Value],
+) -> Result<Value, worthless_js_rt::Error> {
+    let dispatcher = Dispatcher::current()
+        .ok_or_else(|| worthless_js_rt::Error::InvalidArgument("no active dispatcher".into()))?;
+    let (endpoint, handler) = match args {
+        [endpoint, handler, ..] => (endpoint, handler),
+        _ => {
+            return Err(worthless_js_rt::Error::InvalidArgument(
+                "register requires an endpoint and a handler".into(),
+            ))
+        }
+    };
+    dispatcher
+        .register(&endpoint.to_string_lossy(), handler.clone())
+        .map_err(|err| err.into_js())?;
+    Ok(Value::from_primitive(ctx, Primitive::Undefined))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use worthless_bridge::{ErrorKind, Request, Response, Value as BridgeValue};
+    use worthless_js_rt::Context;
+
+    use super::Dispatcher;
+
+    fn init(ctx: &Context) -> Result<(), crate::Error> {
+        ctx.eval(
+            "worthless.register('soon', () => new Promise(r => setTimeout(() => r(1), 20)));
+             worthless.register('late', () => new Promise(r => setTimeout(() => r(2), 60000)));
+             worthless.register('never', () => new Promise(() => {}));
+             worthless.register('echo', (payload) => payload);",
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_await_timers() {
+        let dispatcher = Dispatcher::initialize(init).unwrap();
+        dispatcher.set_await_timeout(Duration::from_secs(1));
+        let call = |endpoint| {
+            dispatcher
+                .handle_request(&Request::new(endpoint, BridgeValue::Null))
+                .into_payload()
+        };
+
+        let started = Instant::now();
+        assert_eq!(call("soon").unwrap(), BridgeValue::from(1));
+        assert!(started.elapsed() >= Duration::from_millis(20));
+
+        // timers past the timeout fail without waiting for them
+        let started = Instant::now();
+        let err = call("late").unwrap_err();
+        assert!(err.description().contains("did not settle in time"));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let err = call("never").unwrap_err();
+        assert!(err.description().contains("never settles"));
+    }
+
+    #[test]
+    fn test_max_request_size() {
+        let dispatcher = Dispatcher::initialize(init).unwrap();
+        dispatcher.set_max_request_size(Some(256));
+        let mut input = Vec::new();
+        for payload in ["small".into(), "x".repeat(1024), "after".into()] {
+            Request::new("echo", payload)
+                .serialize_to(&mut input)
+                .unwrap();
+        }
+
+        // the oversized request is not read past the limit
+        let mut output = Vec::new();
+        assert!(dispatcher.process_bytes(&input, &mut output).is_err());
+        let mut rest = &output[..];
+        let first = Response::deserialize_from(&mut rest).unwrap();
+        assert_eq!(first.into_payload().unwrap(), BridgeValue::from("small"));
+        let second = Response::deserialize_from(&mut rest).unwrap();
+        assert_eq!(
+            second.into_payload().unwrap_err().kind(),
+            ErrorKind::PayloadTooLarge
+        );
+        assert!(rest.is_empty());
+
+        // without a limit the same batch goes through
+        dispatcher.set_max_request_size(None);
+        let mut output = Vec::new();
+        assert_eq!(dispatcher.process(&input[..], &mut output).unwrap(), 3);
+    }
+}
@@ -0,0 +1,39 @@
+use thiserror::Error;
+use worthless_bridge::ErrorKind;
+
+use crate::exception::exception_to_error;
+
+/// Represents an error on the guest side.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("JavaScript runtime error")]
+    Runtime(#[from] worthless_js_rt::Error),
+    #[error("protocol error")]
+    Protocol(#[source] worthless_bridge::Error),
+    #[error("bridge i/o error")]
+    BridgeIo(#[source] std::io::Error),
+    #[error("value cannot be sent over the bridge")]
+    UnsupportedValue,
+    #[error("value is nested too deeply")]
+    RecursionLimit,
+}
+
+impl Error {
+    /// Converts the error into one that can be thrown into JavaScript.
+    pub(crate) fn into_js(self) -> worthless_js_rt::Error {
+        match self {
+            Error::Runtime(err) => err,
+            err => worthless_js_rt::Error::InvalidArgument(err.to_string()),
+        }
+    }
+}
+
+impl From<Error> for worthless_bridge::Error {
+    fn from(err: Error) -> worthless_bridge::Error {
+        match err {
+            Error::Protocol(err) => err,
+            Error::Runtime(worthless_js_rt::Error::JsException(exc)) => exception_to_error(&exc),
+            err => worthless_bridge::Error::new(ErrorKind::InternalError, err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use worthless_bridge::{Error, ErrorKind, Value};
+use worthless_js_rt::{JsException, StackFrame, Value as JsValue};
+
+/// Converts a JavaScript exception into a bridge error.
+///
+/// The detail of the error carries the message, the stack and the parsed
+/// frames of the exception so that the host can render a stack trace.
+pub fn exception_to_error(exc: &JsException) -> Error {
+    make_error(exc.message().to_string(), exc)
+}
+
+/// Converts the reason of an unhandled promise rejection into a bridge error.
+pub fn rejection_to_error(reason: &JsValue) -> Error {
+    let exc = JsException::from_value(reason);
+    make_error(
+        format!("unhandled promise rejection: {}", exc.message()),
+        &exc,
+    )
+}
+
+fn make_error(description: String, exc: &JsException) -> Error {
+    let mut detail = vec![("message".into(), exc.message().into())];
+    if let Some(stack) = exc.stack() {
+        detail.push(("stack".into(), stack.into()));
+        detail.push((
+            "frames".into(),
+            Value::Array(exc.frames().iter().map(frame_to_value).collect()),
+        ));
+    }
+    Error::new(ErrorKind::InternalError, description).with_detail(Value::Map(detail))
+}
+
+fn frame_to_value(frame: &StackFrame) -> Value {
+    let optional = |value: Option<Value>| value.unwrap_or(Value::Null);
+    Value::Map(vec![
+        ("function".into(), frame.function.as_str().into()),
+        (
+            "filename".into(),
+            optional(frame.filename.as_deref().map(Into::into)),
+        ),
+        ("lineno".into(), optional(frame.lineno.map(Into::into))),
+        ("colno".into(), optional(frame.colno.map(Into::into))),
+    ])
+}
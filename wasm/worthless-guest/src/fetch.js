@@ -0,0 +1,139 @@
+// Implements `fetch` on top of the host's `http.fetch` endpoint.  The native
+// functions return promises that the dispatcher settles once the job queue
+// ran dry.
+(function (ns) {
+  "use strict";
+
+  const fetchHost = ns.__fetch;
+  const readChunk = ns.__readChunk;
+  const decodeUtf8 = ns.__decodeUtf8;
+  delete ns.__fetch;
+  delete ns.__readChunk;
+  delete ns.__decodeUtf8;
+
+  class Headers {
+    constructor(init) {
+      this._map = {};
+      if (init instanceof Headers) {
+        init = init._map;
+      }
+      if (init) {
+        const entries = Array.isArray(init) ? init : Object.entries(init);
+        for (const [name, value] of entries) {
+          this.set(name, value);
+        }
+      }
+    }
+
+    get(name) {
+      const value = this._map[String(name).toLowerCase()];
+      return value === undefined ? null : value;
+    }
+
+    set(name, value) {
+      this._map[String(name).toLowerCase()] = String(value);
+    }
+
+    has(name) {
+      return String(name).toLowerCase() in this._map;
+    }
+
+    delete(name) {
+      delete this._map[String(name).toLowerCase()];
+    }
+
+    entries() {
+      return Object.entries(this._map)[Symbol.iterator]();
+    }
+
+    [Symbol.iterator]() {
+      return this.entries();
+    }
+  }
+
+  class Response {
+    constructor(raw) {
+      this.status = raw.status;
+      this.statusText = raw.status_text || "";
+      this.ok = raw.status >= 200 && raw.status < 300;
+      this.url = raw.url || "";
+      this.headers = new Headers(raw.headers);
+      this.bodyUsed = false;
+      this._stream = raw.stream;
+    }
+
+    get body() {
+      if (this._body === undefined) {
+        const response = this;
+        const stream = this._stream;
+        this._body = new ReadableStream(
+          {
+            async pull(controller) {
+              response.bodyUsed = true;
+              const chunk = stream == null ? null : await readChunk(stream);
+              if (chunk == null) {
+                controller.close();
+              } else {
+                controller.enqueue(Uint8Array.from(chunk));
+              }
+            },
+          },
+          { highWaterMark: 0 }
+        );
+      }
+      return this._body;
+    }
+
+    async *_chunks() {
+      if (this.bodyUsed || this.body.locked) {
+        throw new TypeError("body has already been consumed");
+      }
+      this.bodyUsed = true;
+      const reader = this.body.getReader();
+      try {
+        while (true) {
+          const { value, done } = await reader.read();
+          if (done) {
+            return;
+          }
+          yield value;
+        }
+      } finally {
+        reader.releaseLock();
+      }
+    }
+
+    async bytes() {
+      const rv = [];
+      for await (const chunk of this._chunks()) {
+        for (const byte of chunk) {
+          rv.push(byte);
+        }
+      }
+      return rv;
+    }
+
+    async text() {
+      return decodeUtf8(await this.bytes());
+    }
+
+    async json() {
+      return JSON.parse(await this.text());
+    }
+  }
+
+  function fetch(input, init) {
+    init = init || {};
+    const headers = new Headers(init.headers);
+    return fetchHost({
+      url: String(input),
+      method: (init.method || "GET").toUpperCase(),
+      headers: headers._map,
+      body: init.body == null ? null : String(init.body),
+    }).then((raw) => new Response(raw));
+  }
+
+  globalThis.fetch = fetch;
+  globalThis.Headers = Headers;
+  globalThis.Response = Response;
+})(worthless);
@@ -0,0 +1,86 @@
+use worthless_bridge::{Request, Value as BridgeValue};
+use worthless_js_rt::{Context, Value};
+
+use crate::convert::from_js;
+use crate::dispatcher::Dispatcher;
+
+/// The host endpoint that performs HTTP requests.
+pub const FETCH_ENDPOINT: &str = "http.fetch";
+
+/// The host endpoint that returns the next chunk of a response body.
+pub const READ_CHUNK_ENDPOINT: &str = "http.read_chunk";
+
+const FETCH_JS: &str = include_str!("fetch.js");
+
+/// Installs `fetch`, `Headers` and `Response` into the global object.
+pub fn install_fetch(ctx: &Context, ns: &Value) -> Result<(), worthless_js_rt::Error> {
+    ns.set_property("__fetch", Value::from_func(ctx, "__fetch", js_fetch)?)?;
+    ns.set_property(
+        "__readChunk",
+        Value::from_func(ctx, "__readChunk", js_read_chunk)?,
+    )?;
+    ns.set_property(
+        "__decodeUtf8",
+        Value::from_func(ctx, "__decodeUtf8", js_decode_utf8)?,
+    )?;
+    ctx.eval_with_filename(FETCH_JS, "<worthless:fetch>")?;
+    Ok(())
+}
+
+fn defer_host_call(endpoint: &str, payload: BridgeValue) -> Result<Value, worthless_js_rt::Error> {
+    let dispatcher = Dispatcher::current()
+        .ok_or_else(|| worthless_js_rt::Error::InvalidArgument("no active dispatcher".into()))?;
+    let mut builder = Request::build(endpoint.into());
+    builder.raw_payload(payload);
+    if let Some(request_id) = dispatcher.current_request_id() {
+        builder.request_id(request_id);
+    }
+    dispatcher
+        .call_host_deferred(builder.build())
+        .map_err(|err| err.into_js())
+}
+
+fn js_fetch(
+    _ctx: &Context,
+    _this: &Value,
+    args: &[Value],
+) -> Result<Value, worthless_js_rt::Error> {
+    let request = args
+        .first()
+        .ok_or_else(|| worthless_js_rt::Error::InvalidArgument("missing request".into()))?;
+    let payload = from_js(request).map_err(|err| err.into_js())?;
+    defer_host_call(FETCH_ENDPOINT, payload)
+}
+
+fn js_read_chunk(
+    _ctx: &Context,
+    _this: &Value,
+    args: &[Value],
+) -> Result<Value, worthless_js_rt::Error> {
+    let stream = args
+        .first()
+        .and_then(|x| x.as_i64())
+        .ok_or_else(|| worthless_js_rt::Error::InvalidArgument("invalid stream".into()))?;
+    defer_host_call(
+        READ_CHUNK_ENDPOINT,
+        BridgeValue::Map(vec![("stream".into(), stream.into())]),
+    )
+}
+
+fn js_decode_utf8(
+    ctx: &Context,
+    _this: &Value,
+    args: &[Value],
+) -> Result<Value, worthless_js_rt::Error> {
+    let mut bytes = Vec::new();
+    if let Some(array) = args.first() {
+        for idx in 0..array.len().unwrap_or(0) {
+            let byte = array.get_by_index(idx)?.as_i32().unwrap_or(0);
+            bytes.push(byte as u8);
+        }
+    }
+    Ok(Value::from_primitive(
+        ctx,
+        String::from_utf8_lossy(&bytes).as_ref(),
+    ))
+}
@@ -0,0 +1,55 @@
+use std::cell::RefCell;
+use std::io::{Read, Write};
+
+use worthless_bridge::{Request, Response};
+
+use crate::error::Error;
+use crate::io::{host_request_pipe, host_response_pipe};
+
+#[link(wasm_import_module = "worthless")]
+extern "C" {
+    /// Makes the host handle the requests placed into the host request pipe.
+    fn host_call();
+}
+
+thread_local! {
+    // host calls are frequent, so the buffers are reused across calls.
+    static REQUEST_BUF: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    static RESPONSE_BUF: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Sends a request to the host and waits for the response.
+///
+/// This blocks until the host handled the request.
+pub fn call_host(req: &Request) -> Result<Response, Error> {
+    write_request(req)?;
+    unsafe { host_call() };
+    RESPONSE_BUF.with(|buf| {
+        let mut buf = buf.borrow_mut();
+        buf.clear();
+        host_response_pipe()
+            .read_to_end(&mut buf)
+            .map_err(Error::BridgeIo)?;
+        Response::deserialize(&buf).map_err(Error::Protocol)
+    })
+}
+
+/// Sends a fire and forget request to the host.
+///
+/// The request must have been built as fire and forget.
+pub fn emit_to_host(req: &Request) -> Result<(), Error> {
+    debug_assert!(req.fire_and_forget());
+    write_request(req)?;
+    unsafe { host_call() };
+    Ok(())
+}
+
+fn write_request(req: &Request) -> Result<(), Error> {
+    REQUEST_BUF.with(|buf| {
+        let mut buf = buf.borrow_mut();
+        req.serialize_into(&mut buf).map_err(Error::Protocol)?;
+        (&*host_request_pipe())
+            .write_all(&buf)
+            .map_err(Error::BridgeIo)
+    })
+}
@@ -0,0 +1,42 @@
+use std::fs::File;
+use std::mem::ManuallyDrop;
+use std::os::fd::FromRawFd;
+
+/// The file descriptor the host places requests into.
+const REQUEST_FD: i32 = 4;
+
+/// The file descriptor the host reads responses from.
+const RESPONSE_FD: i32 = 5;
+
+/// The file descriptor the guest places requests to the host into.
+const HOST_REQUEST_FD: i32 = 6;
+
+/// The file descriptor the guest reads responses from the host from.
+const HOST_RESPONSE_FD: i32 = 7;
+
+/// Opens one of the pipes provided by the host.
+///
+/// The file descriptors are owned by the host so they must never be closed.
+fn open_pipe(fd: i32) -> ManuallyDrop<File> {
+    ManuallyDrop::new(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Returns the pipe requests are read from.
+pub fn request_pipe() -> ManuallyDrop<File> {
+    open_pipe(REQUEST_FD)
+}
+
+/// Returns the pipe responses are written to.
+pub fn response_pipe() -> ManuallyDrop<File> {
+    open_pipe(RESPONSE_FD)
+}
+
+/// Returns the pipe requests to the host are written to.
+pub fn host_request_pipe() -> ManuallyDrop<File> {
+    open_pipe(HOST_REQUEST_FD)
+}
+
+/// Returns the pipe responses from the host are read from.
+pub fn host_response_pipe() -> ManuallyDrop<File> {
+    open_pipe(HOST_RESPONSE_FD)
+}
@@ -0,0 +1,112 @@
+//! Worthless-Guest implements the guest side of the bridge.  It manages a long
+//! lived JavaScript context, reads requests from the host, dispatches them to
+//! registered handlers and writes the responses back.
+mod bundle;
+mod config;
+mod console;
+mod convert;
+mod debugger;
+mod dispatcher;
+mod error;
+mod exception;
+mod fetch;
+mod host;
+mod io;
+mod panic;
+mod pubsub;
+#[doc(hidden)]
+pub mod shared;
+mod streams;
+mod timers;
+
+use std::rc::Rc;
+
+pub use self::bundle::Bundle;
+pub use self::config::{CONFIG_ENV_PREFIX, CONFIG_META_KEY};
+pub use self::convert::{from_js, to_js, transfer_from_js};
+pub use self::dispatcher::Dispatcher;
+pub use self::error::Error;
+pub use self::fetch::{FETCH_ENDPOINT, READ_CHUNK_ENDPOINT};
+pub use self::host::{call_host, emit_to_host};
+pub use self::panic::install_panic_hook;
+pub use self::timers::{ImmediatePhase, MicrotaskCheckpoint, TaskOrder};
+
+/// The signature of the function that initializes a plugin.
+pub type InitFunc = fn(&worthless_js_rt::Context) -> Result<(), Error>;
+
+/// Exports the entry points of a plugin.
+///
+/// The given init function is invoked once with the context that is used for
+/// all requests.  It is expected to register the handlers of the plugin.
+/// Panics are reported to the host, see [`install_panic_hook`].
+/// Besides the pipes, the host can pass large batches of requests through the
+/// linear memory of the plugin.  The protocol version is recorded in the
+/// [`PROTOCOL_SECTION`](worthless_bridge::PROTOCOL_SECTION) custom section.
+#[macro_export]
+macro_rules! export_plugin {
+    ($init:path) => {
+        // the section name has to be a literal, it matches `PROTOCOL_SECTION`
+        #[cfg(target_arch = "wasm32")]
+        #[used]
+        #[link_section = "worthless-protocol"]
+        static WORTHLESS_PROTOCOL: [u8; 4] = $crate::__private::PROTOCOL_VERSION.to_le_bytes();
+
+        #[no_mangle]
+        pub extern "C" fn worthless_handle_request() {
+            $crate::__handle_requests($init);
+        }
+
+        #[no_mangle]
+        pub extern "C" fn worthless_handle_shared(ptr: u32, len: u32) -> u64 {
+            $crate::__handle_shared_requests($init, ptr, len)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn worthless_alloc(len: u32) -> u32 {
+            $crate::shared::alloc_buffer(len)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn worthless_free(ptr: u32, len: u32) {
+            $crate::shared::free_buffer(ptr, len);
+        }
+    };
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use worthless_bridge::PROTOCOL_VERSION;
+    pub use worthless_js_rt::Context;
+}
+
+fn current_dispatcher(init: InitFunc) -> Rc<Dispatcher> {
+    install_panic_hook();
+    match Dispatcher::current() {
+        Some(dispatcher) => dispatcher,
+        None => match Dispatcher::initialize(init) {
+            Ok(dispatcher) => dispatcher,
+            Err(err) => panic!("failed to set up dispatcher: {}", err),
+        },
+    }
+}
+
+#[doc(hidden)]
+pub fn __handle_requests(init: InitFunc) {
+    let dispatcher = current_dispatcher(init);
+    let (input, output) = (io::request_pipe(), io::response_pipe());
+    if let Err(err) = dispatcher.process(&*input, &*output) {
+        eprintln!("[worthless] failed to process requests: {}", err);
+    }
+}
+
+#[doc(hidden)]
+pub fn __handle_shared_requests(init: InitFunc, ptr: u32, len: u32) -> u64 {
+    let dispatcher = current_dispatcher(init);
+    let input = unsafe { shared::take_buffer(ptr, len) };
+    let mut output = Vec::new();
+    if let Err(err) = dispatcher.process_bytes(&input, &mut output) {
+        eprintln!("[worthless] failed to process requests: {}", err);
+    }
+    drop(input);
+    shared::leak_buffer(output)
+}
@@ -0,0 +1,58 @@
+use std::io::Write;
+use std::panic::{self, Location};
+use std::sync::Once;
+
+use worthless_bridge::{Error, ErrorKind, Response, Value, PANIC_META_KEY};
+
+use crate::dispatcher::Dispatcher;
+use crate::io::response_pipe;
+
+static INSTALL: Once = Once::new();
+
+/// Installs a panic hook that reports panics to the host.
+///
+/// The request in flight is answered with an error carrying the message and
+/// location of the panic before the plugin aborts.  The previous hook still
+/// runs afterwards, so the panic is printed to stderr as well.
+pub fn install_panic_hook() {
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let message = match info.payload().downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => match info.payload().downcast_ref::<String>() {
+                    Some(message) => message.clone(),
+                    None => "Box<dyn Any>".to_string(),
+                },
+            };
+            report_panic(&message, info.location());
+            previous(info);
+        }));
+    });
+}
+
+fn report_panic(message: &str, location: Option<&Location<'_>>) {
+    let mut detail = vec![("message".into(), message.into())];
+    let description = match location {
+        Some(location) => {
+            detail.push(("file".into(), location.file().into()));
+            detail.push(("line".into(), location.line().into()));
+            detail.push(("column".into(), location.column().into()));
+            format!("plugin panicked at {}: {}", location, message)
+        }
+        None => format!("plugin panicked: {}", message),
+    };
+    let mut builder = Response::builder();
+    builder
+        .meta(PANIC_META_KEY, true)
+        .error(Error::new(ErrorKind::InternalError, description).with_detail(Value::Map(detail)));
+    // the response goes through the pipe even for shared memory batches as
+    // the host never gets to read their responses
+    if let Some(request_id) = Dispatcher::current().and_then(|x| x.current_request_id()) {
+        builder.request_id(request_id);
+    }
+    let mut output = response_pipe();
+    if builder.build().serialize_to(&mut *output).is_ok() {
+        output.flush().ok();
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+
+use worthless_bridge::{
+    ErrorKind, Request, Value as BridgeValue, SUBSCRIBE_ENDPOINT, UNSUBSCRIBE_ENDPOINT,
+};
+use worthless_js_rt::{Context, Primitive, Value};
+
+use crate::convert::to_js;
+use crate::dispatcher::Dispatcher;
+use crate::error::Error;
+use crate::host::emit_to_host;
+
+/// The listeners of the topics the plugin subscribed to.
+///
+/// The host is told when the first listener of a topic is added and when the
+/// last one is removed, so it only publishes events someone listens to.
+#[derive(Default)]
+pub struct Subscriptions {
+    topics: BTreeMap<String, Vec<Value>>,
+}
+
+impl Subscriptions {
+    /// Adds a listener, adding the same listener twice has no effect.
+    pub fn subscribe(&mut self, topic: &str, listener: Value) -> Result<(), Error> {
+        let listeners = self.topics.entry(topic.to_string()).or_default();
+        if listeners.iter().any(|x| x.ptr_eq(&listener)) {
+            return Ok(());
+        }
+        listeners.push(listener);
+        if listeners.len() == 1 {
+            notify_host(SUBSCRIBE_ENDPOINT, topic)?;
+        }
+        Ok(())
+    }
+
+    /// Removes a listener, returns `false` if it was not subscribed.
+    pub fn unsubscribe(&mut self, topic: &str, listener: &Value) -> Result<bool, Error> {
+        let listeners = match self.topics.get_mut(topic) {
+            Some(listeners) => listeners,
+            None => return Ok(false),
+        };
+        let len = listeners.len();
+        listeners.retain(|x| !x.ptr_eq(listener));
+        if listeners.len() == len {
+            return Ok(false);
+        }
+        if listeners.is_empty() {
+            self.topics.remove(topic);
+            notify_host(UNSUBSCRIBE_ENDPOINT, topic)?;
+        }
+        Ok(true)
+    }
+
+    /// Returns the listeners of a topic.
+    pub fn listeners(&self, topic: &str) -> Vec<Value> {
+        self.topics.get(topic).cloned().unwrap_or_default()
+    }
+}
+
+fn notify_host(endpoint: &str, topic: &str) -> Result<(), Error> {
+    let mut builder = Request::build(endpoint.into());
+    builder
+        .raw_payload(BridgeValue::Map(vec![("topic".into(), topic.into())]))
+        .fire_and_forget(true);
+    emit_to_host(&builder.build())
+}
+
+/// Passes an event published by the host to the listeners of its topic.
+///
+/// Listeners are called with the payload and the topic.  A listener that
+/// throws does not keep the others from seeing the event.  Responds with the
+/// number of listeners the event was `delivered` to.
+pub fn publish(
+    dispatcher: &Dispatcher,
+    req: &Request,
+) -> Result<BridgeValue, worthless_bridge::Error> {
+    let mut topic = None;
+    let mut payload = &BridgeValue::Null;
+    if let BridgeValue::Map(items) = req.payload() {
+        for (key, value) in items {
+            match key.as_text() {
+                Some("topic") => topic = value.as_text(),
+                Some("payload") => payload = value,
+                _ => {}
+            }
+        }
+    }
+    let topic = topic.ok_or_else(|| {
+        worthless_bridge::Error::new(ErrorKind::InternalError, "event without a topic")
+    })?;
+
+    let ctx = dispatcher.context();
+    // listeners can subscribe and unsubscribe, so they are cloned out
+    let listeners = dispatcher.subscriptions().borrow().listeners(topic);
+    let this = Value::from_primitive(ctx, Primitive::Undefined);
+    let args = [to_js(ctx, payload)?, Value::from_primitive(ctx, topic)];
+    for listener in &listeners {
+        if let Err(err) = listener.call(&this, &args) {
+            eprintln!("[worthless] listener of '{}' failed: {}", topic, err);
+        }
+    }
+    Ok(BridgeValue::Map(vec![(
+        "delivered".into(),
+        (listeners.len() as u64).into(),
+    )]))
+}
+
+fn topic_and_listener(args: &[Value]) -> Result<(String, &Value), worthless_js_rt::Error> {
+    match args {
+        [topic, listener, ..] if listener.is_function() => {
+            Ok((topic.to_string_lossy().to_string(), listener))
+        }
+        _ => Err(worthless_js_rt::Error::InvalidArgument(
+            "a topic and a listener function are required".into(),
+        )),
+    }
+}
+
+/// Implements `worthless.subscribe(topic, listener)`.
+pub fn js_subscribe(
+    ctx: &Context,
+    _this: &Value,
+    args: &[Value],
+) -> Result<Value, worthless_js_rt::Error> {
+    let dispatcher = Dispatcher::current()
+        .ok_or_else(|| worthless_js_rt::Error::InvalidArgument("no active dispatcher".into()))?;
+    let (topic, listener) = topic_and_listener(args)?;
+    dispatcher
+        .subscriptions()
+        .borrow_mut()
+        .subscribe(&topic, listener.clone())
+        .map_err(|err| err.into_js())?;
+    Ok(Value::from_primitive(ctx, Primitive::Undefined))
+}
+
+/// Implements `worthless.unsubscribe(topic, listener)`.
+pub fn js_unsubscribe(
+    ctx: &Context,
+    _this: &Value,
+    args: &[Value],
+) -> Result<Value, worthless_js_rt::Error> {
+    let dispatcher = Dispatcher::current()
+        .ok_or_else(|| worthless_js_rt::Error::InvalidArgument("no active dispatcher".into()))?;
+    let (topic, listener) = topic_and_listener(args)?;
+    let removed = dispatcher
+        .subscriptions()
+        .borrow_mut()
+        .unsubscribe(&topic, listener)
+        .map_err(|err| err.into_js())?;
+    Ok(Value::from_primitive(ctx, removed))
+}
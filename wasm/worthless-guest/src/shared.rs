@@ -0,0 +1,88 @@
+//! Buffers in linear memory the host passes large batches through.
+//!
+//! Instead of the request pipe the host can allocate a buffer with
+//! `worthless_alloc`, write the requests into it and hand it to
+//! `worthless_handle_shared`.  The responses are returned in a buffer the
+//! host reads straight out of memory and releases with `worthless_free`.
+use std::alloc::{alloc, Layout};
+use std::cell::RefCell;
+use std::ptr::{self, NonNull};
+
+thread_local! {
+    /// The buffers handed to the host that it did not free yet.
+    static HANDED_OUT: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Allocates a buffer for the host to write into.
+///
+/// Returns a null pointer if the memory is exhausted.
+pub fn alloc_buffer(len: u32) -> u32 {
+    if len == 0 {
+        return NonNull::<u8>::dangling().as_ptr() as u32;
+    }
+    match Layout::array::<u8>(len as usize) {
+        Ok(layout) => unsafe { alloc(layout) as u32 },
+        Err(_) => 0,
+    }
+}
+
+/// Takes ownership of a buffer allocated with [`alloc_buffer`].
+///
+/// # Safety
+///
+/// The buffer must come from [`alloc_buffer`] with the same length and must
+/// not be used afterwards.
+pub unsafe fn take_buffer(ptr: u32, len: u32) -> Box<[u8]> {
+    Box::from_raw(ptr::slice_from_raw_parts_mut(ptr as *mut u8, len as usize))
+}
+
+/// Hands a buffer to the host, returns its pointer and length packed into
+/// the high and low half.
+///
+/// The buffer is kept as is until the host frees it, so that its spare
+/// capacity does not have to be trimmed off by copying it.
+pub fn leak_buffer(buf: Vec<u8>) -> u64 {
+    let rv = ((buf.as_ptr() as u32 as u64) << 32) | buf.len() as u64;
+    HANDED_OUT.with(|bufs| bufs.borrow_mut().push(buf));
+    rv
+}
+
+/// Frees a buffer from [`alloc_buffer`] or [`leak_buffer`].
+///
+/// # Safety
+///
+/// The buffer must not be used afterwards.  Buffers from [`alloc_buffer`]
+/// must be passed with the same length.
+pub unsafe fn free_buffer(ptr: u32, len: u32) {
+    let handed_out = HANDED_OUT.with(|bufs| {
+        let mut bufs = bufs.borrow_mut();
+        let index = bufs.iter().position(|buf| buf.as_ptr() as u32 == ptr)?;
+        Some(bufs.swap_remove(index))
+    });
+    if handed_out.is_none() {
+        drop(take_buffer(ptr, len));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffers() {
+        let ptr = alloc_buffer(16);
+        assert_ne!(ptr, 0);
+        unsafe { free_buffer(ptr, 16) };
+
+        // handing out keeps the allocation with its spare capacity
+        let mut buf = Vec::with_capacity(64);
+        buf.extend_from_slice(b"responses");
+        let addr = buf.as_ptr() as u32;
+        let packed = leak_buffer(buf);
+        assert_eq!((packed >> 32) as u32, addr);
+        assert_eq!(packed as u32, 9);
+        HANDED_OUT.with(|bufs| assert_eq!(bufs.borrow()[0].capacity(), 64));
+        unsafe { free_buffer(addr, 9) };
+        HANDED_OUT.with(|bufs| assert!(bufs.borrow().is_empty()));
+    }
+}
@@ -0,0 +1,655 @@
+// Implements minimal WHATWG streams: `ReadableStream` with a default reader,
+// `WritableStream` with a default writer and `pipeTo`.  Byte streams, BYOB
+// readers, `tee` and transform streams are not supported.  The strategy only
+// counts chunks.
+//
+// It also provides `worthless.stream(id)` which opens a stream the host pushes
+// chunks into with the `__stream` control endpoint.  The function returned
+// from here receives these pushes.
+(function (ns) {
+  "use strict";
+
+  function deferred() {
+    const rv = {};
+    rv.promise = new Promise((resolve, reject) => {
+      rv.resolve = resolve;
+      rv.reject = reject;
+    });
+    // unobserved rejections of these are not errors of the plugin
+    rv.promise.catch(() => {});
+    return rv;
+  }
+
+  function highWaterMark(strategy, fallback) {
+    const value = strategy && strategy.highWaterMark;
+    return value === undefined ? fallback : Number(value);
+  }
+
+  class ReadableStreamDefaultController {
+    constructor(stream) {
+      this._stream = stream;
+    }
+
+    get desiredSize() {
+      return this._stream._desiredSize();
+    }
+
+    enqueue(chunk) {
+      const stream = this._stream;
+      if (stream._closeRequested || stream._state !== "readable") {
+        throw new TypeError("cannot enqueue into a closed stream");
+      }
+      if (stream._reads.length > 0) {
+        stream._reads.shift().resolve({ value: chunk, done: false });
+      } else {
+        stream._queue.push(chunk);
+      }
+      stream._pullIfNeeded();
+    }
+
+    close() {
+      const stream = this._stream;
+      if (stream._closeRequested || stream._state !== "readable") {
+        throw new TypeError("stream is already closed");
+      }
+      stream._closeRequested = true;
+      if (stream._queue.length === 0) {
+        stream._finishClose();
+      }
+    }
+
+    error(err) {
+      this._stream._error(err);
+    }
+  }
+
+  class ReadableStreamDefaultReader {
+    constructor(stream) {
+      if (stream.locked) {
+        throw new TypeError("stream is already locked to a reader");
+      }
+      stream._reader = this;
+      this._stream = stream;
+      this._closed = deferred();
+      if (stream._state === "closed") {
+        this._closed.resolve();
+      } else if (stream._state === "errored") {
+        this._closed.reject(stream._storedError);
+      }
+    }
+
+    get closed() {
+      return this._closed.promise;
+    }
+
+    read() {
+      if (!this._stream) {
+        return Promise.reject(new TypeError("reader has been released"));
+      }
+      return this._stream._read();
+    }
+
+    cancel(reason) {
+      if (!this._stream) {
+        return Promise.reject(new TypeError("reader has been released"));
+      }
+      return this._stream._cancel(reason);
+    }
+
+    releaseLock() {
+      if (!this._stream) {
+        return;
+      }
+      if (this._stream._reads.length > 0) {
+        throw new TypeError("cannot release a reader with pending reads");
+      }
+      this._stream._reader = null;
+      this._stream = null;
+    }
+  }
+
+  class ReadableStream {
+    constructor(source, strategy) {
+      source = source || {};
+      this._source = source;
+      this._state = "readable";
+      this._storedError = undefined;
+      this._queue = [];
+      this._reads = [];
+      this._reader = null;
+      this._closeRequested = false;
+      this._started = false;
+      this._pulling = false;
+      this._pullAgain = false;
+      this._highWaterMark = highWaterMark(strategy, 1);
+      this._controller = new ReadableStreamDefaultController(this);
+      const start = source.start ? source.start(this._controller) : undefined;
+      Promise.resolve(start).then(
+        () => {
+          this._started = true;
+          this._pullIfNeeded();
+        },
+        (err) => this._error(err)
+      );
+    }
+
+    static from(iterable) {
+      const iterator =
+        iterable[Symbol.asyncIterator] !== undefined
+          ? iterable[Symbol.asyncIterator]()
+          : iterable[Symbol.iterator]();
+      return new ReadableStream(
+        {
+          async pull(controller) {
+            const { value, done } = await iterator.next();
+            if (done) {
+              controller.close();
+            } else {
+              controller.enqueue(value);
+            }
+          },
+          async cancel(reason) {
+            if (iterator.return) {
+              await iterator.return(reason);
+            }
+          },
+        },
+        { highWaterMark: 0 }
+      );
+    }
+
+    get locked() {
+      return this._reader !== null;
+    }
+
+    getReader() {
+      return new ReadableStreamDefaultReader(this);
+    }
+
+    cancel(reason) {
+      if (this.locked) {
+        return Promise.reject(new TypeError("stream is locked to a reader"));
+      }
+      return this._cancel(reason);
+    }
+
+    values(options) {
+      const preventCancel = Boolean(options && options.preventCancel);
+      const reader = this.getReader();
+      return {
+        next() {
+          return reader.read().then((result) => {
+            if (result.done) {
+              reader.releaseLock();
+            }
+            return result;
+          });
+        },
+        return(value) {
+          const cancelled = preventCancel ? Promise.resolve() : reader.cancel(value);
+          reader.releaseLock();
+          return cancelled.then(() => ({ value, done: true }));
+        },
+        [Symbol.asyncIterator]() {
+          return this;
+        },
+      };
+    }
+
+    [Symbol.asyncIterator](options) {
+      return this.values(options);
+    }
+
+    async pipeTo(destination, options) {
+      options = options || {};
+      const reader = this.getReader();
+      const writer = destination.getWriter();
+      try {
+        while (true) {
+          const { value, done } = await reader.read();
+          if (done) {
+            break;
+          }
+          await writer.ready;
+          await writer.write(value);
+        }
+        if (!options.preventClose) {
+          await writer.close();
+        }
+      } catch (err) {
+        if (!options.preventAbort) {
+          await writer.abort(err).catch(() => {});
+        }
+        if (!options.preventCancel) {
+          await reader.cancel(err).catch(() => {});
+        }
+        throw err;
+      } finally {
+        reader.releaseLock();
+        writer.releaseLock();
+      }
+    }
+
+    _desiredSize() {
+      if (this._state === "errored") {
+        return null;
+      }
+      if (this._state === "closed") {
+        return 0;
+      }
+      return this._highWaterMark - this._queue.length;
+    }
+
+    _read() {
+      if (this._queue.length > 0) {
+        const value = this._queue.shift();
+        if (this._closeRequested && this._queue.length === 0) {
+          this._finishClose();
+        } else {
+          this._pullIfNeeded();
+        }
+        return Promise.resolve({ value, done: false });
+      }
+      if (this._state === "closed") {
+        return Promise.resolve({ value: undefined, done: true });
+      }
+      if (this._state === "errored") {
+        return Promise.reject(this._storedError);
+      }
+      const read = deferred();
+      this._reads.push(read);
+      this._pullIfNeeded();
+      return read.promise;
+    }
+
+    _cancel(reason) {
+      if (this._state === "closed") {
+        return Promise.resolve();
+      }
+      if (this._state === "errored") {
+        return Promise.reject(this._storedError);
+      }
+      this._queue = [];
+      this._finishClose();
+      const cancel = this._source.cancel ? this._source.cancel(reason) : undefined;
+      return Promise.resolve(cancel).then(() => undefined);
+    }
+
+    _pullIfNeeded() {
+      if (!this._started || this._state !== "readable" || this._closeRequested) {
+        return;
+      }
+      if (!this._source.pull) {
+        return;
+      }
+      if (this._reads.length === 0 && this._desiredSize() <= 0) {
+        return;
+      }
+      if (this._pulling) {
+        this._pullAgain = true;
+        return;
+      }
+      this._pulling = true;
+      Promise.resolve()
+        .then(() => this._source.pull(this._controller))
+        .then(
+          () => {
+            this._pulling = false;
+            if (this._pullAgain) {
+              this._pullAgain = false;
+              this._pullIfNeeded();
+            }
+          },
+          (err) => this._error(err)
+        );
+    }
+
+    _finishClose() {
+      this._state = "closed";
+      for (const read of this._reads.splice(0)) {
+        read.resolve({ value: undefined, done: true });
+      }
+      if (this._reader) {
+        this._reader._closed.resolve();
+      }
+    }
+
+    _error(err) {
+      if (this._state !== "readable") {
+        return;
+      }
+      this._state = "errored";
+      this._storedError = err;
+      this._queue = [];
+      for (const read of this._reads.splice(0)) {
+        read.reject(err);
+      }
+      if (this._reader) {
+        this._reader._closed.reject(err);
+      }
+    }
+  }
+
+  class WritableStreamDefaultWriter {
+    constructor(stream) {
+      if (stream.locked) {
+        throw new TypeError("stream is already locked to a writer");
+      }
+      stream._writer = this;
+      this._stream = stream;
+      this._closed = deferred();
+      this._ready = null;
+      if (stream._state === "closed") {
+        this._closed.resolve();
+      } else if (stream._state === "errored") {
+        this._closed.reject(stream._storedError);
+      }
+      stream._updateReady();
+    }
+
+    get closed() {
+      return this._closed.promise;
+    }
+
+    get ready() {
+      return this._ready ? this._ready.promise : Promise.resolve();
+    }
+
+    get desiredSize() {
+      return this._stream ? this._stream._desiredSize() : null;
+    }
+
+    write(chunk) {
+      if (!this._stream) {
+        return Promise.reject(new TypeError("writer has been released"));
+      }
+      return this._stream._write(chunk);
+    }
+
+    close() {
+      if (!this._stream) {
+        return Promise.reject(new TypeError("writer has been released"));
+      }
+      return this._stream._close();
+    }
+
+    abort(reason) {
+      if (!this._stream) {
+        return Promise.reject(new TypeError("writer has been released"));
+      }
+      return this._stream._abort(reason);
+    }
+
+    releaseLock() {
+      if (!this._stream) {
+        return;
+      }
+      this._stream._writer = null;
+      this._stream = null;
+    }
+  }
+
+  class WritableStream {
+    constructor(sink, strategy) {
+      sink = sink || {};
+      this._sink = sink;
+      this._state = "writable";
+      this._storedError = undefined;
+      this._queue = [];
+      this._writer = null;
+      this._writing = false;
+      this._closeRequest = null;
+      this._started = false;
+      this._highWaterMark = highWaterMark(strategy, 1);
+      this._controller = { error: (err) => this._error(err) };
+      const start = sink.start ? sink.start(this._controller) : undefined;
+      Promise.resolve(start).then(
+        () => {
+          this._started = true;
+          this._advance();
+        },
+        (err) => this._error(err)
+      );
+    }
+
+    get locked() {
+      return this._writer !== null;
+    }
+
+    getWriter() {
+      return new WritableStreamDefaultWriter(this);
+    }
+
+    close() {
+      if (this.locked) {
+        return Promise.reject(new TypeError("stream is locked to a writer"));
+      }
+      return this._close();
+    }
+
+    abort(reason) {
+      if (this.locked) {
+        return Promise.reject(new TypeError("stream is locked to a writer"));
+      }
+      return this._abort(reason);
+    }
+
+    _desiredSize() {
+      if (this._state === "errored") {
+        return null;
+      }
+      if (this._state === "closed") {
+        return 0;
+      }
+      return this._highWaterMark - this._queue.length - (this._writing ? 1 : 0);
+    }
+
+    _updateReady() {
+      const writer = this._writer;
+      if (!writer) {
+        return;
+      }
+      const size = this._desiredSize();
+      if (size !== null && size <= 0) {
+        if (!writer._ready) {
+          writer._ready = deferred();
+        }
+      } else if (writer._ready) {
+        writer._ready.resolve();
+        writer._ready = null;
+      }
+    }
+
+    _write(chunk) {
+      if (this._state === "errored") {
+        return Promise.reject(this._storedError);
+      }
+      if (this._state !== "writable" || this._closeRequest) {
+        return Promise.reject(new TypeError("cannot write to a closed stream"));
+      }
+      const write = deferred();
+      write.chunk = chunk;
+      this._queue.push(write);
+      this._updateReady();
+      this._advance();
+      return write.promise;
+    }
+
+    _close() {
+      if (this._state === "errored") {
+        return Promise.reject(this._storedError);
+      }
+      if (this._state !== "writable" || this._closeRequest) {
+        return Promise.reject(new TypeError("stream is already closed"));
+      }
+      this._closeRequest = deferred();
+      const rv = this._closeRequest.promise;
+      this._advance();
+      return rv;
+    }
+
+    _abort(reason) {
+      if (this._state !== "writable") {
+        return Promise.resolve();
+      }
+      this._error(reason);
+      const abort = this._sink.abort ? this._sink.abort(reason) : undefined;
+      return Promise.resolve(abort).then(() => undefined);
+    }
+
+    _advance() {
+      if (!this._started || this._writing || this._state !== "writable") {
+        return;
+      }
+      if (this._queue.length > 0) {
+        const write = this._queue.shift();
+        this._writing = true;
+        Promise.resolve()
+          .then(() => this._sink.write && this._sink.write(write.chunk, this._controller))
+          .then(
+            () => {
+              this._writing = false;
+              write.resolve();
+              this._updateReady();
+              this._advance();
+            },
+            (err) => {
+              this._writing = false;
+              write.reject(err);
+              this._error(err);
+            }
+          );
+      } else if (this._closeRequest) {
+        const close = this._closeRequest;
+        this._writing = true;
+        Promise.resolve()
+          .then(() => this._sink.close && this._sink.close())
+          .then(
+            () => {
+              this._writing = false;
+              this._state = "closed";
+              close.resolve();
+              if (this._writer) {
+                this._writer._closed.resolve();
+              }
+            },
+            (err) => {
+              this._writing = false;
+              this._error(err);
+            }
+          );
+      }
+    }
+
+    _error(err) {
+      if (this._state !== "writable") {
+        return;
+      }
+      this._state = "errored";
+      this._storedError = err;
+      for (const write of this._queue.splice(0)) {
+        write.reject(err);
+      }
+      if (this._closeRequest) {
+        this._closeRequest.reject(err);
+      }
+      if (this._writer) {
+        this._writer._closed.reject(err);
+      }
+      this._updateReady();
+    }
+  }
+
+  // Streams the host pushes into are opened by id.  Chunks that arrive before
+  // the plugin opened the stream are buffered up to the high water mark, the
+  // host has to wait for the plugin to open it before pushing more.
+  const PUSHED_HIGH_WATER_MARK = 16;
+  const pushed = new Map();
+
+  function pushedEntry(id) {
+    let entry = pushed.get(id);
+    if (entry === undefined) {
+      entry = { controller: null, chunks: [], done: false, error: undefined };
+      pushed.set(id, entry);
+    }
+    return entry;
+  }
+
+  function openStream(id) {
+    id = Number(id);
+    const entry = pushedEntry(id);
+    if (entry.opened) {
+      throw new TypeError(`stream ${id} is already open`);
+    }
+    entry.opened = true;
+    return new ReadableStream(
+      {
+        start(controller) {
+          entry.controller = controller;
+          for (const chunk of entry.chunks.splice(0)) {
+            controller.enqueue(chunk);
+          }
+          if (entry.error !== undefined) {
+            controller.error(entry.error);
+          } else if (entry.done) {
+            controller.close();
+          }
+          if (entry.error !== undefined || entry.done) {
+            pushed.delete(id);
+          }
+        },
+        cancel() {
+          entry.cancelled = true;
+        },
+      },
+      { highWaterMark: PUSHED_HIGH_WATER_MARK }
+    );
+  }
+
+  // Returns how many more chunks the stream wants which is zero or less once
+  // the host should hold off.
+  function push(message) {
+    const id = Number(message.stream);
+    const entry = pushedEntry(id);
+    const chunk = message.chunk == null ? null : Uint8Array.from(message.chunk);
+    const error = message.error == null ? undefined : new Error(String(message.error));
+    const finished = error !== undefined || Boolean(message.done);
+    if (entry.cancelled) {
+      if (finished) {
+        pushed.delete(id);
+      }
+      return { desired_size: 0, cancelled: true };
+    }
+    const controller = entry.controller;
+    if (controller === null) {
+      if (chunk !== null) {
+        if (entry.chunks.length >= PUSHED_HIGH_WATER_MARK) {
+          throw new RangeError(`stream ${id} is full until the plugin opens it`);
+        }
+        entry.chunks.push(chunk);
+      }
+      entry.error = error;
+      entry.done = Boolean(message.done);
+      return { desired_size: PUSHED_HIGH_WATER_MARK - entry.chunks.length, cancelled: false };
+    }
+    if (chunk !== null) {
+      controller.enqueue(chunk);
+    }
+    if (error !== undefined) {
+      controller.error(error);
+    } else if (message.done) {
+      controller.close();
+    }
+    if (finished) {
+      pushed.delete(id);
+    }
+    return { desired_size: controller.desiredSize || 0, cancelled: false };
+  }
+
+  globalThis.ReadableStream = ReadableStream;
+  globalThis.ReadableStreamDefaultReader = ReadableStreamDefaultReader;
+  globalThis.WritableStream = WritableStream;
+  globalThis.WritableStreamDefaultWriter = WritableStreamDefaultWriter;
+  ns.stream = openStream;
+  return push;
+})(worthless);
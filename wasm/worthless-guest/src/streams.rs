@@ -0,0 +1,12 @@
+use worthless_js_rt::{Context, Value};
+
+const STREAMS_JS: &str = include_str!("streams.js");
+
+/// Installs `ReadableStream` and `WritableStream` into the global object.
+///
+/// Returns the function that pushes messages of the
+/// [`STREAM_ENDPOINT`](worthless_bridge::STREAM_ENDPOINT) into the streams
+/// opened with `worthless.stream`.  The namespace must be installed already.
+pub fn install_streams(ctx: &Context) -> Result<Value, worthless_js_rt::Error> {
+    ctx.eval_with_filename(STREAMS_JS, "<worthless:streams>")
+}
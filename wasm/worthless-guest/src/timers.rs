@@ -0,0 +1,360 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+use std::time::Instant;
+
+use worthless_bridge::Value as BridgeValue;
+use worthless_js_rt::{Context, Primitive, Value};
+
+use crate::dispatcher::Dispatcher;
+
+/// A timer registered with `setTimeout` or `setInterval`.
+#[derive(Clone)]
+pub struct Timer {
+    pub callback: Value,
+    pub args: Vec<Value>,
+    due: f64,
+    interval: Option<f64>,
+}
+
+/// A callback queued with `setImmediate`.
+pub struct Immediate {
+    id: u32,
+    pub callback: Value,
+    pub args: Vec<Value>,
+}
+
+/// When microtasks run relative to macro tasks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MicrotaskCheckpoint {
+    /// After every timer and immediate callback, like browsers and Node 11+.
+    EveryTask,
+    /// Once after all due timers and once after every round of immediates,
+    /// like Node 10 and earlier.
+    EveryPhase,
+}
+
+/// Whether immediates run before or after the due timers of a tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImmediatePhase {
+    /// Due timers fire first, like in Node.
+    AfterTimers,
+    /// Queued immediates run before the due timers fire.  Immediates queued
+    /// by the timers run at the start of the next tick.
+    BeforeTimers,
+}
+
+/// Configures the order in which the dispatcher runs queued work.
+///
+/// After a request was handled or a timer fired the dispatcher drains the
+/// microtask queue, that is promise jobs and the deferred host calls.  Then it
+/// runs the immediates that were queued so far in order, immediates they
+/// queue run in the next round.  A turn ends once no immediates are left or
+/// the round limit was reached, the remaining ones run in the next turn.  On a
+/// tick the due timers form their own phase before or after the immediates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskOrder {
+    microtasks: MicrotaskCheckpoint,
+    immediates: ImmediatePhase,
+    max_immediate_rounds: usize,
+}
+
+impl Default for TaskOrder {
+    fn default() -> TaskOrder {
+        TaskOrder {
+            microtasks: MicrotaskCheckpoint::EveryTask,
+            immediates: ImmediatePhase::AfterTimers,
+            max_immediate_rounds: 1000,
+        }
+    }
+}
+
+impl TaskOrder {
+    /// Creates the default order.
+    ///
+    /// By default microtasks run after every task, immediates after the due
+    /// timers and up to 1000 rounds of immediates run per turn.
+    pub fn new() -> TaskOrder {
+        TaskOrder::default()
+    }
+
+    /// Sets when microtasks run.
+    pub fn microtasks(&mut self, checkpoint: MicrotaskCheckpoint) -> &mut TaskOrder {
+        self.microtasks = checkpoint;
+        self
+    }
+
+    /// Sets whether immediates run before or after the due timers.
+    pub fn immediates(&mut self, phase: ImmediatePhase) -> &mut TaskOrder {
+        self.immediates = phase;
+        self
+    }
+
+    /// Limits the rounds of immediates per turn so that immediates which
+    /// queue themselves cannot stall the plugin forever.
+    pub fn max_immediate_rounds(&mut self, rounds: usize) -> &mut TaskOrder {
+        self.max_immediate_rounds = rounds.max(1);
+        self
+    }
+
+    /// Returns when microtasks run.
+    pub fn microtask_checkpoint(&self) -> MicrotaskCheckpoint {
+        self.microtasks
+    }
+
+    /// Returns whether immediates run before or after the due timers.
+    pub fn immediate_phase(&self) -> ImmediatePhase {
+        self.immediates
+    }
+
+    /// Returns the rounds of immediates per turn.
+    pub fn immediate_round_limit(&self) -> usize {
+        self.max_immediate_rounds
+    }
+}
+
+/// Keeps track of the timers of a context.
+///
+/// The guest has no clock of its own for timers.  The time only advances
+/// when the host sends a tick, so timers fire under the host's control.
+/// The exception are handlers awaiting a timer, see
+/// [`estimated_now`](Self::estimated_now).
+#[derive(Default)]
+pub struct Timers {
+    next_id: u32,
+    now: f64,
+    advanced_at: Option<Instant>,
+    entries: BTreeMap<u32, Timer>,
+    immediates: VecDeque<Immediate>,
+}
+
+impl Timers {
+    /// Advances the clock.  The clock never goes backwards.
+    pub fn advance_to(&mut self, now: f64) {
+        if now > self.now {
+            self.now = now;
+        }
+        self.advanced_at = Some(Instant::now());
+    }
+
+    /// Estimates the time of the host's clock.
+    ///
+    /// This is the time of the last advance plus the time that passed since
+    /// then, which lets handlers that await a timer sleep until it is due.
+    pub fn estimated_now(&self) -> f64 {
+        let elapsed = self.advanced_at.map_or(0.0, |x| x.elapsed().as_secs_f64());
+        self.now + elapsed * 1000.0
+    }
+
+    /// Returns the ID of the timer that was scheduled last.
+    pub fn last_id(&self) -> u32 {
+        self.next_id
+    }
+
+    /// Schedules a timer and returns its ID.
+    pub fn schedule(&mut self, callback: Value, args: Vec<Value>, delay: f64, repeat: bool) -> u32 {
+        let delay = if delay.is_finite() {
+            delay.max(0.0)
+        } else {
+            0.0
+        };
+        self.next_id += 1;
+        self.entries.insert(
+            self.next_id,
+            Timer {
+                callback,
+                args,
+                due: self.now + delay,
+                // intervals must advance the deadline or they would fire forever
+                interval: if repeat { Some(delay.max(1.0)) } else { None },
+            },
+        );
+        self.next_id
+    }
+
+    /// Removes a timer.
+    pub fn clear(&mut self, id: u32) {
+        self.entries.remove(&id);
+    }
+
+    /// Takes the earliest due timer with an ID up to `max_id`.
+    ///
+    /// Intervals stay registered and are rescheduled relative to the current
+    /// time.
+    pub fn pop_due(&mut self, max_id: u32) -> Option<Timer> {
+        let (id, _) = self
+            .entries
+            .iter()
+            .filter(|(id, timer)| **id <= max_id && timer.due <= self.now)
+            .min_by(|a, b| a.1.due.total_cmp(&b.1.due).then(a.0.cmp(b.0)))?;
+        let id = *id;
+        match self.entries.get(&id).and_then(|x| x.interval) {
+            Some(interval) => {
+                let timer = self.entries.get_mut(&id)?;
+                let rv = timer.clone();
+                timer.due = self.now + interval;
+                Some(rv)
+            }
+            None => self.entries.remove(&id),
+        }
+    }
+
+    /// Queues an immediate and returns its ID.
+    ///
+    /// Immediates share the IDs with timers.
+    pub fn schedule_immediate(&mut self, callback: Value, args: Vec<Value>) -> u32 {
+        self.next_id += 1;
+        self.immediates.push_back(Immediate {
+            id: self.next_id,
+            callback,
+            args,
+        });
+        self.next_id
+    }
+
+    /// Removes an immediate.
+    pub fn clear_immediate(&mut self, id: u32) {
+        self.immediates.retain(|x| x.id != id);
+    }
+
+    /// Takes the next immediate if it has an ID up to `max_id`.
+    pub fn pop_immediate(&mut self, max_id: u32) -> Option<Immediate> {
+        match self.immediates.front() {
+            Some(immediate) if immediate.id <= max_id => self.immediates.pop_front(),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if immediates are queued.
+    pub fn has_immediates(&self) -> bool {
+        !self.immediates.is_empty()
+    }
+
+    /// Returns the time when the next timer is due.
+    ///
+    /// If immediates are queued that is right now.
+    pub fn next_deadline(&self) -> Option<f64> {
+        if self.has_immediates() {
+            return Some(self.now);
+        }
+        self.entries
+            .values()
+            .map(|x| x.due)
+            .min_by(|a, b| a.total_cmp(b))
+    }
+}
+
+/// Extracts the current time from the payload of a tick.
+pub fn parse_tick(payload: &BridgeValue) -> Option<f64> {
+    let items = match payload {
+        BridgeValue::Map(items) => items,
+        _ => return None,
+    };
+    items.iter().find_map(|(key, value)| match (key, value) {
+        (BridgeValue::Text(key), BridgeValue::Integer(now)) if key == "now" => {
+            Some(i128::from(*now) as f64)
+        }
+        (BridgeValue::Text(key), BridgeValue::Float(now)) if key == "now" => Some(*now),
+        _ => None,
+    })
+}
+
+/// Installs `setTimeout`, `setInterval`, `setImmediate` and their clear
+/// functions.
+pub fn install_timers(ctx: &Context, global: &Value) -> Result<(), worthless_js_rt::Error> {
+    global.set_property(
+        "setTimeout",
+        Value::from_func(ctx, "setTimeout", |ctx, _this, args| {
+            schedule(ctx, args, false)
+        })?,
+    )?;
+    global.set_property(
+        "setInterval",
+        Value::from_func(ctx, "setInterval", |ctx, _this, args| {
+            schedule(ctx, args, true)
+        })?,
+    )?;
+    global.set_property(
+        "clearTimeout",
+        Value::from_func(ctx, "clearTimeout", clear)?,
+    )?;
+    global.set_property(
+        "clearInterval",
+        Value::from_func(ctx, "clearInterval", clear)?,
+    )?;
+    global.set_property(
+        "setImmediate",
+        Value::from_func(ctx, "setImmediate", schedule_immediate)?,
+    )?;
+    global.set_property(
+        "clearImmediate",
+        Value::from_func(ctx, "clearImmediate", clear_immediate)?,
+    )?;
+    Ok(())
+}
+
+fn current_dispatcher() -> Result<Rc<Dispatcher>, worthless_js_rt::Error> {
+    Dispatcher::current()
+        .ok_or_else(|| worthless_js_rt::Error::InvalidArgument("no active dispatcher".into()))
+}
+
+fn callback_arg(args: &[Value]) -> Result<Value, worthless_js_rt::Error> {
+    match args.first() {
+        Some(callback) if callback.is_function() => Ok(callback.clone()),
+        _ => Err(worthless_js_rt::Error::InvalidArgument(
+            "timer callback is not a function".into(),
+        )),
+    }
+}
+
+fn schedule(ctx: &Context, args: &[Value], repeat: bool) -> Result<Value, worthless_js_rt::Error> {
+    let dispatcher = current_dispatcher()?;
+    let callback = callback_arg(args)?;
+    let delay = args.get(1).and_then(|x| x.as_f64()).unwrap_or(0.0);
+    let rest = args.iter().skip(2).cloned().collect();
+    let id = dispatcher
+        .timers()
+        .borrow_mut()
+        .schedule(callback, rest, delay, repeat);
+    Ok(Value::from_primitive(ctx, id as i64))
+}
+
+fn schedule_immediate(
+    ctx: &Context,
+    _this: &Value,
+    args: &[Value],
+) -> Result<Value, worthless_js_rt::Error> {
+    let dispatcher = current_dispatcher()?;
+    let callback = callback_arg(args)?;
+    let rest = args.iter().skip(1).cloned().collect();
+    let id = dispatcher
+        .timers()
+        .borrow_mut()
+        .schedule_immediate(callback, rest);
+    Ok(Value::from_primitive(ctx, id as i64))
+}
+
+fn clear_immediate(
+    ctx: &Context,
+    _this: &Value,
+    args: &[Value],
+) -> Result<Value, worthless_js_rt::Error> {
+    if let (Some(dispatcher), Some(id)) =
+        (Dispatcher::current(), args.first().and_then(|x| x.as_i64()))
+    {
+        if let Ok(id) = u32::try_from(id) {
+            dispatcher.timers().borrow_mut().clear_immediate(id);
+        }
+    }
+    Ok(Value::from_primitive(ctx, Primitive::Undefined))
+}
+
+fn clear(ctx: &Context, _this: &Value, args: &[Value]) -> Result<Value, worthless_js_rt::Error> {
+    if let (Some(dispatcher), Some(id)) =
+        (Dispatcher::current(), args.first().and_then(|x| x.as_i64()))
+    {
+        if let Ok(id) = u32::try_from(id) {
+            dispatcher.timers().borrow_mut().clear(id);
+        }
+    }
+    Ok(Value::from_primitive(ctx, Primitive::Undefined))
+}
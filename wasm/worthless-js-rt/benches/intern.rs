@@ -0,0 +1,43 @@
+//! Compares converting strings with and without interning.
+//!
+//! Run with `cargo bench --bench intern`.
+use std::hint::black_box;
+use std::time::Instant;
+
+use worthless_js_rt::{Context, Error, Value};
+
+const ITERATIONS: usize = 100_000;
+
+const STRINGS: &[&str] = &["pending", "running", "done", "failed", "cancelled"];
+
+fn bench<F: FnMut(usize)>(name: &str, mut f: F) {
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        f(i);
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:<24} {:>10.1?} ({:.0} ns/iter)",
+        name,
+        elapsed,
+        elapsed.as_nanos() as f64 / ITERATIONS as f64
+    );
+}
+
+fn main() -> Result<(), Error> {
+    Context::run(|ctx| {
+        bench("from_primitive", |i| {
+            black_box(Value::from_primitive(ctx, STRINGS[i % STRINGS.len()]));
+        });
+        bench("intern", |i| {
+            black_box(ctx.intern(STRINGS[i % STRINGS.len()]));
+        });
+
+        let obj = Value::new_object(ctx);
+        bench("set_property", |i| {
+            obj.set_property(STRINGS[i % STRINGS.len()], i as i32)
+                .unwrap();
+        });
+        Ok(())
+    })
+}
@@ -0,0 +1,275 @@
+use std::cell::RefCell;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+
+use crate::context::Context;
+use crate::error::{ActorError, Error};
+use crate::primitive::Primitive;
+use crate::runtime::Runtime;
+use crate::value::{PropertyFilter, Value, ValueKind};
+
+/// Objects nested deeper than this are not copied out of the runtime.
+const MAX_DEPTH: usize = 64;
+
+type Command = Box<dyn FnOnce(&Context) + Send>;
+
+/// A function registered with [`RuntimeActor::register_fn`].
+pub type ActorFunction = Box<dyn Fn(&[OwnedValue]) -> Result<OwnedValue, String> + Send>;
+
+thread_local! {
+    // the functions live on the thread of the actor that registered them
+    static FUNCTIONS: RefCell<Vec<ActorFunction>> = RefCell::new(Vec::new());
+}
+
+/// A value copied out of a runtime so that it can move between threads.
+///
+/// Only data survives the copy: objects lose their prototype and functions
+/// and symbols cannot be copied at all.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnedValue {
+    Undefined,
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<OwnedValue>),
+    Object(Vec<(String, OwnedValue)>),
+}
+
+impl OwnedValue {
+    /// Copies a value out of its runtime.
+    ///
+    /// Objects are copied with the properties `JSON.stringify` would
+    /// serialize.
+    pub fn from_value(value: &Value) -> Result<OwnedValue, Error> {
+        OwnedValue::copy(value, 0)
+    }
+
+    fn copy(value: &Value, depth: usize) -> Result<OwnedValue, Error> {
+        if depth > MAX_DEPTH {
+            return Err(Error::InvalidArgument(
+                "value is nested too deeply or cyclic".into(),
+            ));
+        }
+        Ok(match value.kind() {
+            ValueKind::Undefined => OwnedValue::Undefined,
+            ValueKind::Null => OwnedValue::Null,
+            ValueKind::Boolean => OwnedValue::Bool(value.is_true()),
+            ValueKind::Number => OwnedValue::Number(value.as_f64().unwrap_or(f64::NAN)),
+            ValueKind::String => OwnedValue::String(value.to_string_lossy().to_string()),
+            ValueKind::Symbol | ValueKind::Exception => {
+                return Err(Error::InvalidArgument("cannot copy symbols".into()))
+            }
+            ValueKind::Object if value.is_function() => {
+                return Err(Error::InvalidArgument("cannot copy functions".into()))
+            }
+            ValueKind::Object if value.is_array() => {
+                let len = value.len().ok_or(Error::InvalidLength)?;
+                let mut items = Vec::with_capacity(len);
+                for idx in 0..len {
+                    items.push(OwnedValue::copy(&value.get_by_index(idx)?, depth + 1)?);
+                }
+                OwnedValue::Array(items)
+            }
+            ValueKind::Object => {
+                let mut items = Vec::new();
+                for (key, item) in value.iter_properties_with(&PropertyFilter::json()) {
+                    items.push((
+                        key.to_string_lossy().to_string(),
+                        OwnedValue::copy(&item, depth + 1)?,
+                    ));
+                }
+                OwnedValue::Object(items)
+            }
+        })
+    }
+
+    /// Creates a value in a runtime from the copy.
+    pub fn to_value(&self, ctx: &Context) -> Result<Value, Error> {
+        Ok(match self {
+            OwnedValue::Undefined => Value::from_primitive(ctx, Primitive::Undefined),
+            OwnedValue::Null => Value::from_primitive(ctx, Primitive::Null),
+            OwnedValue::Bool(value) => Value::from_primitive(ctx, *value),
+            OwnedValue::Number(value) => Value::from_primitive(ctx, *value),
+            OwnedValue::String(value) => Value::from_primitive(ctx, value.as_str()),
+            OwnedValue::Array(items) => {
+                let rv = Value::new_array(ctx);
+                for item in items {
+                    rv.append(item.to_value(ctx)?)?;
+                }
+                rv
+            }
+            OwnedValue::Object(items) => {
+                let rv = Value::new_object(ctx);
+                for (key, item) in items {
+                    rv.set_property(key, item.to_value(ctx)?)?;
+                }
+                rv
+            }
+        })
+    }
+}
+
+/// Owns a runtime on a dedicated thread.
+///
+/// [`Runtime`] and [`Context`] cannot leave the thread they were created on.
+/// The actor keeps them on a thread of their own and is `Send + Sync`, so it
+/// can be shared by the threads of a host.  Every method sends a message to
+/// the runtime thread and blocks until it was handled, so calls are handled
+/// one at a time in the order they arrive.  Values cross over as
+/// [`OwnedValue`]s.
+///
+/// This needs a target with threads.  Dropping the actor stops the thread
+/// once the queued messages were handled.
+pub struct RuntimeActor {
+    sender: Mutex<Option<mpsc::Sender<Command>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RuntimeActor {
+    /// Starts a runtime with a fresh context on a new thread.
+    pub fn spawn() -> Result<RuntimeActor, ActorError> {
+        RuntimeActor::spawn_with(|_| Ok(()))
+    }
+
+    /// Starts a runtime and primes its context on the new thread.
+    pub fn spawn_with<F>(init: F) -> Result<RuntimeActor, ActorError>
+    where
+        F: FnOnce(&Context) -> Result<(), Error> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel::<Command>();
+        let (ready_sender, ready) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("worthless-js-rt".into())
+            .spawn(move || {
+                let ctx = match Runtime::new()
+                    .and_then(|rt| Context::new(&rt))
+                    .and_then(|ctx| init(&ctx).map(|_| ctx))
+                {
+                    Ok(ctx) => {
+                        ready_sender.send(Ok(())).ok();
+                        ctx
+                    }
+                    Err(err) => {
+                        ready_sender.send(Err(ActorError::from(err))).ok();
+                        return;
+                    }
+                };
+                for command in receiver {
+                    command(&ctx);
+                }
+                FUNCTIONS.with(|functions| functions.borrow_mut().clear());
+            })
+            .map_err(|err| ActorError::Runtime(format!("cannot spawn runtime thread: {}", err)))?;
+        ready.recv().map_err(|_| ActorError::ShutDown)??;
+        Ok(RuntimeActor {
+            sender: Mutex::new(Some(sender)),
+            thread: Some(thread),
+        })
+    }
+
+    /// Runs a function with the context on the runtime thread.
+    ///
+    /// This is the escape hatch for everything the other methods do not
+    /// cover.  The function must not leak values out of the runtime.
+    pub fn with<F, R>(&self, f: F) -> Result<R, ActorError>
+    where
+        F: FnOnce(&Context) -> Result<R, Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        let (result_sender, result) = mpsc::channel();
+        let command: Command = Box::new(move |ctx| {
+            result_sender.send(f(ctx).map_err(ActorError::from)).ok();
+        });
+        self.sender
+            .lock()
+            .unwrap()
+            .as_ref()
+            .ok_or(ActorError::ShutDown)?
+            .send(command)
+            .map_err(|_| ActorError::ShutDown)?;
+        result.recv().map_err(|_| ActorError::ShutDown)?
+    }
+
+    /// Evaluates code and returns a copy of the result.
+    pub fn eval(&self, code: &str) -> Result<OwnedValue, ActorError> {
+        let code = code.to_string();
+        self.with(move |ctx| OwnedValue::from_value(&ctx.eval(&code)?))
+    }
+
+    /// Calls a global function and returns a copy of the result.
+    pub fn call(&self, name: &str, args: Vec<OwnedValue>) -> Result<OwnedValue, ActorError> {
+        let name = name.to_string();
+        self.with(move |ctx| {
+            let func = ctx.with_global(|global| global.get_property(&name))?;
+            if !func.is_function() {
+                return Err(Error::InvalidArgument(format!(
+                    "'{}' is not a function",
+                    name
+                )));
+            }
+            let args = args
+                .iter()
+                .map(|arg| arg.to_value(ctx))
+                .collect::<Result<Vec<_>, _>>()?;
+            let this = Value::from_primitive(ctx, Primitive::Undefined);
+            OwnedValue::from_value(&func.call(&this, args)?)
+        })
+    }
+
+    /// Exposes a Rust function as a global function.
+    ///
+    /// The function runs on the runtime thread with copies of the arguments,
+    /// so it must not call back into the actor.  Errors are thrown as internal
+    /// errors with the message.
+    pub fn register_fn<F>(&self, name: &str, f: F) -> Result<(), ActorError>
+    where
+        F: Fn(&[OwnedValue]) -> Result<OwnedValue, String> + Send + 'static,
+    {
+        let name = name.to_string();
+        self.with(move |ctx| {
+            let idx = FUNCTIONS.with(|functions| {
+                let mut functions = functions.borrow_mut();
+                functions.push(Box::new(f));
+                functions.len() - 1
+            });
+            let this = Value::from_primitive(ctx, Primitive::Undefined);
+            let func = Value::from_host_fn(ctx, &name, invoke_registered)?.bind(&this, [idx])?;
+            ctx.with_global(|global| global.set_property(&name, func))
+        })
+    }
+
+    /// Runs all pending jobs and returns how many ran.
+    pub fn run_pending_jobs(&self) -> Result<usize, ActorError> {
+        self.with(|ctx| ctx.rt().run_pending_jobs())
+    }
+}
+
+impl Drop for RuntimeActor {
+    fn drop(&mut self) {
+        // dropping the sender ends the loop of the thread
+        self.sender.lock().unwrap().take();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Dispatches calls of registered functions, the first argument is the index.
+fn invoke_registered(ctx: &Context, _this: &Value, args: &[Value]) -> Result<Value, Error> {
+    let idx = args
+        .first()
+        .and_then(|x| x.as_i64())
+        .and_then(|x| usize::try_from(x).ok())
+        .ok_or_else(|| Error::InvalidArgument("invalid function index".into()))?;
+    let args = args[1..]
+        .iter()
+        .map(OwnedValue::from_value)
+        .collect::<Result<Vec<_>, _>>()?;
+    let rv = FUNCTIONS.with(|functions| match functions.borrow().get(idx) {
+        Some(func) => func(&args).map_err(Error::InvalidArgument),
+        None => Err(Error::InvalidArgument("unknown function".into())),
+    })?;
+    rv.to_value(ctx)
+}
@@ -0,0 +1,62 @@
+//! Moves byte buffers between rust and `ArrayBuffer`s without copying.
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::ffi::c_void;
+use std::mem::ManuallyDrop;
+
+use worthless_quickjs_sys::JSRuntime;
+
+thread_local! {
+    // the data pointers of the array buffers which were allocated by rust
+    // with the capacity of their allocations
+    static OWNED: RefCell<BTreeMap<usize, usize>> = const { RefCell::new(BTreeMap::new()) };
+    // the buffer being transferred, which is not freed when it is detached
+    static CLAIMING: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Leaks a non empty buffer for an array buffer.
+///
+/// Returns the data pointer and the opaque value to pass along with
+/// [`free_owned`].  Spare capacity is kept, as shrinking the buffer would
+/// move the bytes.
+pub(crate) fn leak(bytes: Vec<u8>) -> (*mut u8, *mut c_void) {
+    debug_assert!(!bytes.is_empty());
+    let mut bytes = ManuallyDrop::new(bytes);
+    let (data, capacity) = (bytes.as_mut_ptr(), bytes.capacity());
+    OWNED.with(|owned| owned.borrow_mut().insert(data as usize, capacity));
+    (data, capacity as *mut c_void)
+}
+
+/// Frees a buffer from [`leak`] when the array buffer is collected or
+/// detached.
+pub(crate) unsafe extern "C" fn free_owned(
+    _rt: *mut JSRuntime,
+    opaque: *mut c_void,
+    data: *mut c_void,
+) {
+    OWNED.with(|owned| owned.borrow_mut().remove(&(data as usize)));
+    if CLAIMING.with(|claiming| claiming.get()) != data as usize {
+        drop(Vec::from_raw_parts(data as *mut u8, 0, opaque as usize));
+    }
+}
+
+/// Checks if the data of an array buffer was allocated by rust.
+pub(crate) fn is_owned(data: *const u8) -> bool {
+    OWNED.with(|owned| owned.borrow().contains_key(&(data as usize)))
+}
+
+/// Takes a buffer back out of an array buffer while it is detached.
+///
+/// # Safety
+///
+/// `data` and `len` must describe a buffer for which [`is_owned`] holds and
+/// `detach` must detach the array buffer owning it.
+pub(crate) unsafe fn claim(data: *mut u8, len: usize, detach: impl FnOnce()) -> Vec<u8> {
+    let capacity = OWNED
+        .with(|owned| owned.borrow().get(&(data as usize)).copied())
+        .expect("buffer was not allocated by rust");
+    CLAIMING.with(|claiming| claiming.set(data as usize));
+    detach();
+    CLAIMING.with(|claiming| claiming.set(0));
+    Vec::from_raw_parts(data, len, capacity)
+}
@@ -0,0 +1,239 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+
+use crate::context::Context;
+use crate::error::Error;
+use crate::primitive::Primitive;
+use crate::value::Value;
+
+const CLONE_JS: &str = include_str!("clone.js");
+
+struct PortState {
+    ctx: Context,
+    object: Value,
+    structured_clone: Value,
+    peer: Cell<Option<u32>>,
+    queue: RefCell<VecDeque<Value>>,
+}
+
+thread_local! {
+    static PORTS: RefCell<BTreeMap<u32, Rc<PortState>>> = const { RefCell::new(BTreeMap::new()) };
+    static NEXT_PORT_ID: Cell<u32> = const { Cell::new(0) };
+}
+
+fn lookup_port(id: u32) -> Option<Rc<PortState>> {
+    PORTS.with(|ports| ports.borrow().get(&id).cloned())
+}
+
+/// Connects two contexts of the same runtime with a pair of ports.
+///
+/// Messages are structured clones: plain objects, arrays, primitives, dates,
+/// regular expressions, errors, maps, sets and binary data are copied into
+/// the receiving context, cycles included.  Functions and symbols cannot be
+/// sent.
+///
+/// In JavaScript a port is an object with `postMessage(data)`, `close()` and
+/// an `onmessage` handler which is invoked with an event whose `data` is the
+/// message.  Posting only queues the message.  It's handed to `onmessage`
+/// when the host dispatches the messages of the port, so delivery happens at
+/// a point the host controls.
+pub struct MessageChannel {
+    port1: MessagePort,
+    port2: MessagePort,
+}
+
+impl MessageChannel {
+    /// Creates a channel between two contexts.
+    ///
+    /// Both contexts must belong to the same runtime.
+    pub fn new(ctx1: &Context, ctx2: &Context) -> Result<MessageChannel, Error> {
+        if ctx1.rt().as_raw() != ctx2.rt().as_raw() {
+            return Err(Error::InvalidArgument(
+                "contexts of a channel must share a runtime".into(),
+            ));
+        }
+        let port1 = MessagePort::new(ctx1)?;
+        let port2 = MessagePort::new(ctx2)?;
+        port1.state.peer.set(Some(port2.id));
+        port2.state.peer.set(Some(port1.id));
+        Ok(MessageChannel { port1, port2 })
+    }
+
+    /// Returns the port in the first context.
+    pub fn port1(&self) -> &MessagePort {
+        &self.port1
+    }
+
+    /// Returns the port in the second context.
+    pub fn port2(&self) -> &MessagePort {
+        &self.port2
+    }
+
+    /// Splits the channel into its ports.
+    pub fn into_ports(self) -> (MessagePort, MessagePort) {
+        (self.port1, self.port2)
+    }
+
+    /// Dispatches the queued messages of both ports.
+    ///
+    /// Handlers can post further messages, so this repeats until both queues
+    /// are empty or no handler is listening.
+    pub fn dispatch(&self) -> Result<usize, Error> {
+        let mut rv = 0;
+        loop {
+            let count = self.port1.dispatch()? + self.port2.dispatch()?;
+            if count == 0 {
+                return Ok(rv);
+            }
+            rv += count;
+        }
+    }
+}
+
+/// One end of a [`MessageChannel`].
+///
+/// The port stays open as long as this handle is alive, dropping it closes
+/// the port for both sides.
+pub struct MessagePort {
+    id: u32,
+    state: Rc<PortState>,
+}
+
+impl MessagePort {
+    fn new(ctx: &Context) -> Result<MessagePort, Error> {
+        let id = NEXT_PORT_ID.with(|next| {
+            let id = next.get();
+            next.set(id.wrapping_add(1));
+            id
+        });
+        let undefined = Value::from_primitive(ctx, Primitive::Undefined);
+        let object = Value::new_object(ctx);
+        object.set_property(
+            "postMessage",
+            Value::from_host_fn(ctx, "postMessage", js_post_message)?
+                .bind(&undefined, [i64::from(id)])?,
+        )?;
+        object.set_property(
+            "close",
+            Value::from_host_fn(ctx, "close", js_close)?.bind(&undefined, [i64::from(id)])?,
+        )?;
+        object.set_property("onmessage", Primitive::Null)?;
+        let state = Rc::new(PortState {
+            ctx: ctx.clone(),
+            object,
+            structured_clone: ctx.eval_with_filename(CLONE_JS, "<structured-clone>")?,
+            peer: Cell::new(None),
+            queue: RefCell::new(VecDeque::new()),
+        });
+        PORTS.with(|ports| ports.borrow_mut().insert(id, state.clone()));
+        Ok(MessagePort { id, state })
+    }
+
+    /// Returns the context of the port.
+    pub fn context(&self) -> &Context {
+        &self.state.ctx
+    }
+
+    /// Returns the JavaScript object of the port.
+    ///
+    /// Expose it to the code in the context, eg: as a global.
+    pub fn object(&self) -> &Value {
+        &self.state.object
+    }
+
+    /// Sends a clone of a value to the other port.
+    pub fn post_message(&self, value: &Value) -> Result<(), Error> {
+        post_message(self.id, value)
+    }
+
+    /// Takes the next queued message without invoking `onmessage`.
+    pub fn try_recv(&self) -> Option<Value> {
+        self.state.queue.borrow_mut().pop_front()
+    }
+
+    /// Returns the number of queued messages.
+    pub fn pending(&self) -> usize {
+        self.state.queue.borrow().len()
+    }
+
+    /// Hands the queued messages to `onmessage`.
+    ///
+    /// Messages stay queued while no handler is set.  Returns the number of
+    /// messages that were delivered, an exception of the handler stops the
+    /// delivery.
+    pub fn dispatch(&self) -> Result<usize, Error> {
+        let mut count = 0;
+        loop {
+            let handler = self.state.object.get_property("onmessage")?;
+            if !handler.is_function() {
+                return Ok(count);
+            }
+            let data = match self.try_recv() {
+                Some(data) => data,
+                None => return Ok(count),
+            };
+            let event = Value::new_object(&self.state.ctx);
+            event.set_property("data", data)?;
+            handler.call(&self.state.object, [event])?;
+            count += 1;
+        }
+    }
+
+    /// Closes the port and its peer.  Queued messages are dropped.
+    pub fn close(&self) {
+        close_port(self.id);
+    }
+}
+
+impl Drop for MessagePort {
+    fn drop(&mut self) {
+        close_port(self.id);
+    }
+}
+
+fn post_message(id: u32, value: &Value) -> Result<(), Error> {
+    let peer = lookup_port(id)
+        .and_then(|port| port.peer.get())
+        .and_then(lookup_port)
+        .ok_or_else(|| Error::InvalidArgument("message port is closed".into()))?;
+    let undefined = Value::from_primitive(&peer.ctx, Primitive::Undefined);
+    let data = peer.structured_clone.call(&undefined, [value])?;
+    peer.queue.borrow_mut().push_back(data);
+    Ok(())
+}
+
+fn close_port(id: u32) {
+    // the states are dropped outside of the borrow as they hold values
+    let (port, peer) = PORTS.with(|ports| {
+        let mut ports = ports.borrow_mut();
+        let port = ports.remove(&id);
+        let peer = port
+            .as_ref()
+            .and_then(|port| port.peer.get())
+            .and_then(|peer| ports.remove(&peer));
+        (port, peer)
+    });
+    for state in port.iter().chain(peer.iter()) {
+        state.queue.borrow_mut().clear();
+        state.peer.set(None);
+    }
+}
+
+fn port_id(args: &[Value]) -> Result<u32, Error> {
+    args.first()
+        .and_then(|x| x.as_i64())
+        .and_then(|x| u32::try_from(x).ok())
+        .ok_or_else(|| Error::InvalidArgument("invalid message port".into()))
+}
+
+fn js_post_message(ctx: &Context, _this: &Value, args: &[Value]) -> Result<Value, Error> {
+    let undefined = Value::from_primitive(ctx, Primitive::Undefined);
+    post_message(port_id(args)?, args.get(1).unwrap_or(&undefined))?;
+    Ok(undefined)
+}
+
+fn js_close(ctx: &Context, _this: &Value, args: &[Value]) -> Result<Value, Error> {
+    close_port(port_id(args)?);
+    Ok(Value::from_primitive(ctx, Primitive::Undefined))
+}
@@ -0,0 +1,100 @@
+// Implements the structured clone algorithm for messages between contexts.
+// Evaluating this in the receiving context returns the clone function, so
+// clones are created with the constructors of that context.  The value may
+// come from another context of the same runtime.
+(function () {
+  "use strict";
+
+  const toString = Object.prototype.toString;
+
+  function dataCloneError(message) {
+    const err = new Error(message);
+    err.name = "DataCloneError";
+    return err;
+  }
+
+  function cloneValue(value, memory) {
+    const type = typeof value;
+    if (type === "function") {
+      throw dataCloneError("functions cannot be cloned");
+    }
+    if (type === "symbol") {
+      throw dataCloneError("symbols cannot be cloned");
+    }
+    if (value === null || type !== "object") {
+      return value;
+    }
+    if (memory.has(value)) {
+      return memory.get(value);
+    }
+
+    const tag = toString.call(value);
+    let rv;
+    switch (tag) {
+      case "[object Boolean]":
+      case "[object Number]":
+      case "[object String]":
+        rv = Object(value.valueOf());
+        break;
+      case "[object Date]":
+        rv = new Date(value.getTime());
+        break;
+      case "[object RegExp]":
+        rv = new RegExp(value.source, value.flags);
+        break;
+      case "[object ArrayBuffer]":
+        rv = value.slice(0);
+        break;
+      case "[object Error]":
+        rv = new Error(value.message);
+        rv.name = String(value.name);
+        if (value.stack !== undefined) {
+          rv.stack = String(value.stack);
+        }
+        break;
+      case "[object Map]":
+        rv = new Map();
+        memory.set(value, rv);
+        for (const [key, item] of value) {
+          rv.set(cloneValue(key, memory), cloneValue(item, memory));
+        }
+        return rv;
+      case "[object Set]":
+        rv = new Set();
+        memory.set(value, rv);
+        for (const item of value) {
+          rv.add(cloneValue(item, memory));
+        }
+        return rv;
+      case "[object Array]":
+        rv = new Array(value.length);
+        break;
+      case "[object Object]":
+        rv = {};
+        break;
+      default:
+        if (ArrayBuffer.isView(value)) {
+          const buffer = cloneValue(value.buffer, memory);
+          const Ctor = globalThis[tag.slice(8, -1)];
+          rv =
+            tag === "[object DataView]"
+              ? new DataView(buffer, value.byteOffset, value.byteLength)
+              : new Ctor(buffer, value.byteOffset, value.length);
+          memory.set(value, rv);
+          return rv;
+        }
+        throw dataCloneError(`${tag.slice(8, -1)} objects cannot be cloned`);
+    }
+    memory.set(value, rv);
+    if (tag === "[object Array]" || tag === "[object Object]") {
+      for (const key of Object.keys(value)) {
+        rv[key] = cloneValue(value[key], memory);
+      }
+    }
+    return rv;
+  }
+
+  return function structuredClone(value) {
+    return cloneValue(value, new Map());
+  };
+})();
@@ -1,20 +1,49 @@
-use std::ffi::CString;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::ffi::{c_char, CString};
 use std::fmt;
 use std::rc::Rc;
 
 use worthless_quickjs_sys::{
-    JSContext, JS_Eval, JS_FreeContext, JS_GetGlobalObject, JS_GetRuntime, JS_NewContext,
-    JS_EVAL_TYPE_GLOBAL,
+    JSContext, JSValue, JS_Eval, JS_EvalFunction, JS_FreeContext, JS_GetGlobalObject,
+    JS_GetRuntime, JS_NewContext, WL_JS_DupValue, WL_JS_FreeBuffer, WL_JS_FreeValue,
+    WL_JS_ReadBytecode, WL_JS_SetImportMeta, WL_JS_WriteBytecode, JS_EVAL_FLAG_COMPILE_ONLY,
+    JS_EVAL_TYPE_GLOBAL, JS_EVAL_TYPE_MODULE,
 };
 
 use crate::builtins::make_basic_console;
+use crate::coverage::{self, Coverage};
 use crate::error::Error;
+use crate::heap;
+use crate::instrument;
+use crate::intern;
 use crate::js_exception::JsException;
 use crate::runtime::Runtime;
-use crate::value::Value;
+use crate::source;
+use crate::value::{HostFunction, Value};
+use crate::value_ref::ValueRef;
+
+const LOCKDOWN_JS: &str = include_str!("lockdown.js");
+const STACK_TRACE_JS: &str = include_str!("stack_trace.js");
+#[cfg(feature = "intl")]
+const INTL_JS: &str = include_str!("intl.js");
 
 struct ContextHandle {
     ptr: *mut JSContext,
+    // only owned handles cache the global object as borrowed handles are
+    // never dropped and would leak the reference.
+    owned: bool,
+    global: Cell<Option<JSValue>>,
+}
+
+impl ContextHandle {
+    fn new(ptr: *mut JSContext, owned: bool) -> ContextHandle {
+        ContextHandle {
+            ptr,
+            owned,
+            global: Cell::new(None),
+        }
+    }
 }
 
 /// Wraps a QuickJS context.
@@ -40,18 +69,22 @@ impl Context {
         if ptr.is_null() {
             return Err(Error::ContextInit);
         }
+        heap::register(rt.as_raw(), ptr);
 
         Ok(Context {
-            handle: Rc::new(ContextHandle { ptr }),
+            handle: Rc::new(ContextHandle::new(ptr, true)),
             rt: rt.clone(),
         })
     }
 
     /// Creates a context populated with common utilities.
+    ///
+    /// This includes a basic `console` and the stack trace API, see
+    /// [`install_stack_trace_api`](Self::install_stack_trace_api).
     pub fn new(rt: &Runtime) -> Result<Context, Error> {
         let ctx = Context::empty(rt)?;
-        let global = ctx.global();
-        global.set_property("console", make_basic_console(&ctx)?)?;
+        ctx.with_global(|global| global.set_property("console", make_basic_console(&ctx)?))?;
+        ctx.install_stack_trace_api()?;
         Ok(ctx)
     }
 
@@ -60,7 +93,7 @@ impl Context {
             let rt_raw = JS_GetRuntime(ctx);
             let rt = Runtime::borrow_raw_unchecked(rt_raw);
             // leak one refcount so that we don't hit the gc
-            let mut handle = Rc::new(ContextHandle { ptr: ctx });
+            let mut handle = Rc::new(ContextHandle::new(ctx, false));
             std::mem::forget(Rc::clone(&mut handle));
             Context { handle, rt }
         }
@@ -82,30 +115,319 @@ impl Context {
     }
 
     /// Returns a reference to the root object.
+    ///
+    /// The global object is fetched once and cached, so this only increments
+    /// its refcount.
     pub fn global(&self) -> Value {
+        unsafe {
+            let raw = self.global_raw();
+            Value::from_raw_unchecked(self, WL_JS_DupValue(self.as_raw(), raw))
+        }
+    }
+
+    /// Returns a borrowed reference to the root object.
+    ///
+    /// Unlike [`global`](Self::global) this does not touch the refcount.
+    pub fn global_ref(&self) -> ValueRef<'_> {
+        unsafe { ValueRef::from_raw(self, self.global_raw()) }
+    }
+
+    /// Invokes a function with a borrowed reference to the root object.
+    pub fn with_global<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&Value) -> R,
+    {
+        f(&self.global_ref())
+    }
+
+    /// Returns the raw global object.
+    ///
+    /// For owned contexts the reference is held by the handle, otherwise it's
+    /// released again immediately.  The global object lives as long as the
+    /// context so the value stays valid either way.
+    unsafe fn global_raw(&self) -> JSValue {
+        if let Some(raw) = self.handle.global.get() {
+            return raw;
+        }
         // note: inside JS_GetGlobalObject the engine already performs a Js_DupValue
-        // so we do not need to do this here.
-        unsafe { Value::from_raw_unchecked(self, JS_GetGlobalObject(self.as_raw())) }
+        let raw = JS_GetGlobalObject(self.as_raw());
+        if self.handle.owned {
+            self.handle.global.set(Some(raw));
+        } else {
+            WL_JS_FreeValue(self.as_raw(), raw);
+        }
+        raw
+    }
+
+    /// Exposes host functions as a namespace object on the global object.
+    ///
+    /// The namespace is frozen so plugin code cannot replace the functions.
+    /// Returns the namespace object.
+    pub fn register_namespace(
+        &self,
+        name: &str,
+        funcs: &[(&str, HostFunction)],
+    ) -> Result<Value, Error> {
+        let namespace = Value::new_object(self);
+        for &(func_name, f) in funcs {
+            namespace.set_property(func_name, Value::from_host_fn(self, func_name, f)?)?;
+        }
+        self.with_global(|global| {
+            global
+                .get_property("Object")?
+                .call_method("freeze", [&namespace])?;
+            global.set_property(name, &namespace)
+        })?;
+        Ok(namespace)
+    }
+
+    /// Freezes the intrinsics of the context.
+    ///
+    /// This freezes the builtins of the global object and everything reachable
+    /// from them such as `Object.prototype` and `Array.prototype`, so code
+    /// running later cannot pollute the prototypes objects injected by the
+    /// host rely on.  Call this after the context was primed with all globals.
+    ///
+    /// Note that assigning to properties that shadow frozen ones (eg:
+    /// `obj.toString = ...`) fails afterwards and throws in strict mode.
+    pub fn lockdown(&self) -> Result<(), Error> {
+        let lockdown = self.eval_with_filename(LOCKDOWN_JS, "<lockdown>")?;
+        self.with_global(|global| lockdown.call(global, [global]))?;
+        Ok(())
+    }
+
+    /// Installs `Error.captureStackTrace` and `Error.stackTraceLimit`.
+    ///
+    /// [`Context::new`] does this already.  Installing it again keeps the
+    /// current limit.  The limit starts out unlimited like the backtraces of
+    /// QuickJS.  It applies to stacks captured with `captureStackTrace` and
+    /// to the stacks of [`JsException`](crate::JsException)s, errors created
+    /// by the engine keep their full stack in JavaScript.
+    pub fn install_stack_trace_api(&self) -> Result<(), Error> {
+        let install = self.eval_with_filename(STACK_TRACE_JS, "<stack-trace>")?;
+        self.with_global(|global| install.call(global, [global]))?;
+        Ok(())
+    }
+
+    /// Sets `Error.stackTraceLimit`, `None` removes the limit.
+    pub fn set_stack_trace_limit(&self, limit: Option<usize>) -> Result<(), Error> {
+        let limit = limit.map_or(f64::INFINITY, |x| x as f64);
+        self.with_global(|global| {
+            global
+                .get_property("Error")?
+                .set_property("stackTraceLimit", limit)
+        })
+    }
+
+    /// Returns `Error.stackTraceLimit` or `None` if stacks are not limited.
+    pub fn stack_trace_limit(&self) -> Option<usize> {
+        let limit = self
+            .with_global(|global| {
+                global
+                    .get_property("Error")?
+                    .get_property("stackTraceLimit")
+            })
+            .ok()?
+            .as_f64()?;
+        if limit.is_finite() && limit >= 0.0 {
+            Some(limit as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Installs a minimal `Intl` into the global object.
+    ///
+    /// Only `NumberFormat` and `DateTimeFormat` are provided for a few
+    /// locales, others fall back to `en-US`.  The `toLocaleString` methods of
+    /// numbers and dates are replaced to format with it.
+    #[cfg(feature = "intl")]
+    pub fn install_intl(&self) -> Result<(), Error> {
+        let intl = self.eval_with_filename(INTL_JS, "<intl>")?;
+        self.with_global(|global| intl.call(global, [global]))?;
+        Ok(())
     }
 
     /// Evaluates some code
     pub fn eval(&self, code: &str) -> Result<Value, Error> {
-        let input = CString::new(code)?;
-        let script_name = CString::new("<script>")?;
+        self.eval_with_filename(code, "<script>")
+    }
+
+    /// Evaluates some code with a filename.
+    ///
+    /// The filename shows up in stack traces.
+    pub fn eval_with_filename(&self, code: &str, filename: &str) -> Result<Value, Error> {
+        self.eval_bytes_with_filename(code.as_bytes(), filename)
+    }
+
+    /// Evaluates UTF-8 encoded code with a filename.
+    ///
+    /// QuickJS needs the source to be followed by a NUL byte.  If `code`
+    /// already ends in one it's passed to the engine as is, otherwise it's
+    /// copied once.  Embedding bundles with a trailing NUL thus avoids copying
+    /// them at startup.
+    pub fn eval_bytes_with_filename(&self, code: &[u8], filename: &str) -> Result<Value, Error> {
+        let instrumented = match std::str::from_utf8(code) {
+            Ok(source) => instrument::instrument(self, source.trim_end_matches('\0'), filename)?,
+            Err(_) => None,
+        };
+        let code = instrumented.as_ref().map_or(code, |x| x.as_bytes());
         unsafe {
             Value::from_raw(
                 self,
-                JS_Eval(
-                    self.handle.ptr,
-                    input.as_ptr(),
-                    code.len() as _,
-                    script_name.as_ptr(),
-                    JS_EVAL_TYPE_GLOBAL as i32,
-                ),
+                self.eval_raw(code, filename, JS_EVAL_TYPE_GLOBAL as i32)?,
             )
         }
     }
 
+    /// Invokes `JS_Eval` with NUL terminated source.
+    unsafe fn eval_raw(&self, code: &[u8], filename: &str, flags: i32) -> Result<JSValue, Error> {
+        let (input, len) = match code.last() {
+            Some(0) => (Cow::Borrowed(code), code.len() - 1),
+            _ => {
+                let mut buf = Vec::with_capacity(code.len() + 1);
+                buf.extend_from_slice(code);
+                buf.push(0);
+                (Cow::Owned(buf), code.len())
+            }
+        };
+        let script_name = CString::new(filename)?;
+        Ok(JS_Eval(
+            self.handle.ptr,
+            input.as_ptr() as *const c_char,
+            len as _,
+            script_name.as_ptr(),
+            flags,
+        ))
+    }
+
+    /// Evaluates code as a module.
+    ///
+    /// The module can import the synthetic modules registered on the runtime
+    /// and the modules its source loader provides.  `import.meta.url` is set
+    /// to the filename.
+    pub fn eval_module(&self, code: &str, filename: &str) -> Result<Value, Error> {
+        let func = self.compile_module(code, filename, true)?;
+        // JS_EvalFunction takes over the reference to the module
+        unsafe { Value::from_raw(self, JS_EvalFunction(self.as_raw(), func.into_raw())) }
+    }
+
+    /// Compiles module code without evaluating it.
+    pub(crate) fn compile_module(
+        &self,
+        code: &str,
+        filename: &str,
+        is_main: bool,
+    ) -> Result<Value, Error> {
+        let instrumented = instrument::instrument(self, code, filename)?;
+        let code = instrumented.as_deref().unwrap_or(code);
+        let url = CString::new(filename)?;
+        unsafe {
+            let func = Value::from_raw(
+                self,
+                self.eval_raw(
+                    code.as_bytes(),
+                    filename,
+                    (JS_EVAL_TYPE_MODULE | JS_EVAL_FLAG_COMPILE_ONLY) as i32,
+                )?,
+            )?;
+            if WL_JS_SetImportMeta(self.as_raw(), func.as_raw(), url.as_ptr(), is_main as i32) < 0 {
+                return Err(self.last_error());
+            }
+            Ok(func)
+        }
+    }
+
+    /// Compiles code into QuickJS bytecode without running it.
+    ///
+    /// The bytecode can later be evaluated with
+    /// [`eval_bytecode`](Self::eval_bytecode) by a runtime that was built from
+    /// the same QuickJS version and configuration.
+    pub fn compile(&self, code: &str, filename: &str) -> Result<Vec<u8>, Error> {
+        let func = unsafe {
+            Value::from_raw(
+                self,
+                self.eval_raw(
+                    code.as_bytes(),
+                    filename,
+                    (JS_EVAL_TYPE_GLOBAL | JS_EVAL_FLAG_COMPILE_ONLY) as i32,
+                )?,
+            )?
+        };
+        unsafe {
+            let mut len = 0;
+            let buf = WL_JS_WriteBytecode(self.as_raw(), &mut len, func.as_raw());
+            if buf.is_null() {
+                return Err(self.last_error());
+            }
+            let rv = std::slice::from_raw_parts(buf, len).to_vec();
+            WL_JS_FreeBuffer(self.as_raw(), buf);
+            Ok(rv)
+        }
+    }
+
+    /// Evaluates bytecode produced by [`compile`](Self::compile).
+    pub fn eval_bytecode(&self, bytecode: &[u8]) -> Result<Value, Error> {
+        unsafe {
+            let func = Value::from_raw(
+                self,
+                WL_JS_ReadBytecode(self.as_raw(), bytecode.as_ptr(), bytecode.len() as _),
+            )?;
+            // JS_EvalFunction takes over the reference to the function
+            Value::from_raw(self, JS_EvalFunction(self.as_raw(), func.into_raw()))
+        }
+    }
+
+    /// Turns on coverage for the code evaluated from now on.
+    ///
+    /// Scripts and modules evaluated with a filename are instrumented to
+    /// count how often their lines and functions run.  Filenames in angle
+    /// brackets like the `<script>` of [`eval`](Self::eval) mark internal code
+    /// which is not instrumented, nor is bytecode.  The counters live in the
+    /// `__worthless_coverage` global and shift the columns of stack traces,
+    /// line numbers stay intact.
+    pub fn enable_coverage(&self) -> Result<(), Error> {
+        coverage::enable(self)
+    }
+
+    /// Returns the coverage of the code evaluated since coverage was enabled.
+    pub fn coverage(&self) -> Result<Coverage, Error> {
+        coverage::collect(self)
+    }
+
+    /// Registers the original source of a file.
+    ///
+    /// [`JsException::render`] shows an excerpt of registered sources.
+    /// Evaluating code does not register it, as holding on to every source
+    /// costs memory.
+    pub fn register_source(&self, filename: &str, source: &str) {
+        source::register(self, filename, source);
+    }
+
+    /// Returns the source registered for a file.
+    pub fn source(&self, filename: &str) -> Option<Rc<str>> {
+        source::get(self, filename)
+    }
+
+    /// Returns a string value that is reused for the same string.
+    ///
+    /// Converting a `&str` allocates a new JavaScript string every time.
+    /// Interned strings are created once per context and shared afterwards,
+    /// which saves allocations and garbage for strings converted over and
+    /// over such as enum like values in hot loops.  Up to 1024 strings of at
+    /// most 256 bytes are kept, the least recently used one is dropped to
+    /// make room for a new one and longer strings are converted as usual.
+    /// Object keys set with [`Value::set_property`] and text converted by
+    /// the guest are interned as well.
+    pub fn intern(&self, s: &str) -> Value {
+        intern::intern(self, s)
+    }
+
+    /// Returns the number of strings interned with [`intern`](Self::intern).
+    pub fn interned_strings(&self) -> usize {
+        intern::len(self)
+    }
+
     /// Returns the last error.
     pub(crate) fn last_error(&self) -> Error {
         Error::JsException(unsafe { JsException::from_raw(self) })
@@ -114,12 +436,25 @@ impl Context {
     pub(crate) fn as_raw(&self) -> *mut JSContext {
         self.handle.ptr
     }
+
+    /// Returns the number of handles that keep the context alive.
+    #[cfg(test)]
+    pub(crate) fn ref_count(&self) -> usize {
+        Rc::strong_count(&self.handle)
+    }
 }
 
 impl Drop for ContextHandle {
     fn drop(&mut self) {
         unsafe {
+            if let Some(global) = self.global.take() {
+                WL_JS_FreeValue(self.ptr, global);
+            }
+            intern::forget(self.ptr);
             JS_FreeContext(self.ptr);
         }
+        coverage::forget(self.ptr);
+        heap::forget(self.ptr);
+        source::forget(self.ptr);
     }
 }
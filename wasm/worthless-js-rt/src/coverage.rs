@@ -0,0 +1,376 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use worthless_quickjs_sys::JSContext;
+
+use crate::context::Context;
+use crate::error::Error;
+use crate::instrument::{Inserts, Phase, Sites};
+use crate::value::Value;
+
+/// The global the counters of instrumented scripts are stored in.
+const COUNTERS_GLOBAL: &str = "__worthless_coverage";
+
+/// How often a line of a script was executed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineCoverage {
+    /// The line number, starting at 1.
+    pub line: u32,
+    /// How often a statement starting on the line was executed.
+    pub hits: u64,
+}
+
+/// How often a function of a script was called.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionCoverage {
+    /// The name of the function or `<anonymous>`.
+    pub name: String,
+    /// The line the function starts on.
+    pub line: u32,
+    /// How often the function was called.
+    pub hits: u64,
+}
+
+/// The coverage of one script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptCoverage {
+    /// The filename the script was evaluated with.
+    pub filename: String,
+    /// The instrumented lines in ascending order.
+    pub lines: Vec<LineCoverage>,
+    /// The instrumented functions in source order.
+    pub functions: Vec<FunctionCoverage>,
+}
+
+impl ScriptCoverage {
+    /// Returns the number of lines that were executed.
+    pub fn covered_lines(&self) -> usize {
+        self.lines.iter().filter(|x| x.hits > 0).count()
+    }
+
+    /// Returns the number of functions that were called.
+    pub fn covered_functions(&self) -> usize {
+        self.functions.iter().filter(|x| x.hits > 0).count()
+    }
+
+    /// Adds the hits of another run of the same script.
+    fn merge(&mut self, other: &ScriptCoverage) {
+        let mut lines = self
+            .lines
+            .iter()
+            .map(|x| (x.line, x.hits))
+            .collect::<BTreeMap<_, _>>();
+        for line in &other.lines {
+            *lines.entry(line.line).or_insert(0) += line.hits;
+        }
+        self.lines = lines
+            .into_iter()
+            .map(|(line, hits)| LineCoverage { line, hits })
+            .collect();
+        for function in &other.functions {
+            match self
+                .functions
+                .iter_mut()
+                .find(|x| x.name == function.name && x.line == function.line)
+            {
+                Some(existing) => existing.hits += function.hits,
+                None => self.functions.push(function.clone()),
+            }
+        }
+    }
+}
+
+/// The coverage of the scripts evaluated in a context.
+///
+/// Scripts evaluated more than once with the same filename are merged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    scripts: Vec<ScriptCoverage>,
+}
+
+impl Coverage {
+    /// Returns the scripts in the order they were first evaluated.
+    pub fn scripts(&self) -> &[ScriptCoverage] {
+        &self.scripts
+    }
+
+    /// Adds the coverage of another context, eg: of another test file.
+    pub fn merge(&mut self, other: &Coverage) {
+        for script in &other.scripts {
+            self.add_script(script.clone());
+        }
+    }
+
+    fn add_script(&mut self, script: ScriptCoverage) {
+        match self
+            .scripts
+            .iter_mut()
+            .find(|x| x.filename == script.filename)
+        {
+            Some(existing) => existing.merge(&script),
+            None => self.scripts.push(script),
+        }
+    }
+
+    /// Exports the coverage in the lcov tracefile format.
+    pub fn to_lcov(&self) -> String {
+        let mut rv = String::new();
+        for script in &self.scripts {
+            writeln!(rv, "TN:").unwrap();
+            writeln!(rv, "SF:{}", script.filename).unwrap();
+            for function in &script.functions {
+                writeln!(rv, "FN:{},{}", function.line, function.name).unwrap();
+            }
+            for function in &script.functions {
+                writeln!(rv, "FNDA:{},{}", function.hits, function.name).unwrap();
+            }
+            writeln!(rv, "FNF:{}", script.functions.len()).unwrap();
+            writeln!(rv, "FNH:{}", script.covered_functions()).unwrap();
+            for line in &script.lines {
+                writeln!(rv, "DA:{},{}", line.line, line.hits).unwrap();
+            }
+            writeln!(rv, "LF:{}", script.lines.len()).unwrap();
+            writeln!(rv, "LH:{}", script.covered_lines()).unwrap();
+            writeln!(rv, "end_of_record").unwrap();
+        }
+        rv
+    }
+}
+
+impl FromIterator<ScriptCoverage> for Coverage {
+    /// Collects scripts, eg: as reported by a plugin, merging those with the
+    /// same filename.
+    fn from_iter<I: IntoIterator<Item = ScriptCoverage>>(iter: I) -> Coverage {
+        let mut rv = Coverage::default();
+        for script in iter {
+            rv.add_script(script);
+        }
+        rv
+    }
+}
+
+/// The instrumented lines and functions of a script.
+struct ScriptMap {
+    filename: String,
+    lines: Vec<u32>,
+    functions: Vec<(String, u32)>,
+}
+
+thread_local! {
+    static SCRIPTS: RefCell<HashMap<usize, Vec<ScriptMap>>> = RefCell::new(HashMap::new());
+}
+
+/// Turns on coverage for a context.
+pub(crate) fn enable(ctx: &Context) -> Result<(), Error> {
+    let key = ctx.as_raw() as usize;
+    if SCRIPTS.with(|scripts| scripts.borrow().contains_key(&key)) {
+        return Ok(());
+    }
+    let define = ctx.eval_with_filename(
+        "(function (name) { Object.defineProperty(this, name, { value: [] }); })",
+        "<coverage>",
+    )?;
+    ctx.with_global(|global| define.call(global, [COUNTERS_GLOBAL]))?;
+    SCRIPTS.with(|scripts| scripts.borrow_mut().insert(key, Vec::new()));
+    Ok(())
+}
+
+/// Forgets the scripts of a context that is freed.
+pub(crate) fn forget(ctx: *mut JSContext) {
+    SCRIPTS.with(|scripts| scripts.borrow_mut().remove(&(ctx as usize)));
+}
+
+/// Checks if coverage is on for a context.
+pub(crate) fn is_enabled(ctx: &Context) -> bool {
+    SCRIPTS.with(|scripts| scripts.borrow().contains_key(&(ctx.as_raw() as usize)))
+}
+
+/// Adds the counters of a script if coverage is on for the context.
+pub(crate) fn add_script(
+    ctx: &Context,
+    filename: &str,
+    sites: &Sites,
+    inserts: &mut Inserts,
+) -> Result<(), Error> {
+    let key = ctx.as_raw() as usize;
+    let index = match SCRIPTS.with(|scripts| scripts.borrow().get(&key).map(|x| x.len())) {
+        Some(index) => index,
+        None => return Ok(()),
+    };
+    let (lines, functions) = add_counters(sites, index, inserts);
+
+    let zeros = |len: usize| -> Result<Value, Error> {
+        let rv = Value::new_array(ctx);
+        for _ in 0..len {
+            rv.append(0)?;
+        }
+        Ok(rv)
+    };
+    let counters = Value::new_object(ctx);
+    counters.set_property("l", zeros(lines.len())?)?;
+    counters.set_property("f", zeros(functions.len())?)?;
+    ctx.with_global(|global| global.get_property(COUNTERS_GLOBAL))?
+        .append(counters)?;
+    SCRIPTS.with(|scripts| {
+        if let Some(scripts) = scripts.borrow_mut().get_mut(&key) {
+            scripts.push(ScriptMap {
+                filename: filename.to_string(),
+                lines,
+                functions,
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Inserts counters before the statements and at the start of the function
+/// bodies of a script.
+fn add_counters(
+    sites: &Sites,
+    index: usize,
+    inserts: &mut Inserts,
+) -> (Vec<u32>, Vec<(String, u32)>) {
+    for (idx, function) in sites.functions.iter().enumerate() {
+        inserts.push(
+            function.body_start,
+            Phase::FunctionStart,
+            format!("{}[{}].f[{}]++;", COUNTERS_GLOBAL, index, idx),
+        );
+    }
+    for (idx, statement) in sites.statements.iter().enumerate() {
+        inserts.push(
+            statement.pos,
+            Phase::Statement,
+            format!("{}[{}].l[{}]++;", COUNTERS_GLOBAL, index, idx),
+        );
+    }
+    (
+        sites.statements.iter().map(|x| x.line).collect(),
+        sites
+            .functions
+            .iter()
+            .map(|x| (x.name.clone(), x.line))
+            .collect(),
+    )
+}
+
+/// Collects the coverage of a context.
+pub(crate) fn collect(ctx: &Context) -> Result<Coverage, Error> {
+    let counters = ctx.with_global(|global| global.get_property(COUNTERS_GLOBAL))?;
+    let hits = |script: &Value, kind: &str, idx: usize| -> Result<u64, Error> {
+        Ok(script
+            .get_property(kind)?
+            .get_by_index(idx)?
+            .as_f64()
+            .unwrap_or(0.0) as u64)
+    };
+    SCRIPTS.with(|scripts| {
+        let scripts = scripts.borrow();
+        let mut rv = Coverage::default();
+        for (script_idx, map) in scripts
+            .get(&(ctx.as_raw() as usize))
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            let script = counters.get_by_index(script_idx)?;
+            let mut lines = Vec::with_capacity(map.lines.len());
+            for (idx, &line) in map.lines.iter().enumerate() {
+                lines.push(LineCoverage {
+                    line,
+                    hits: hits(&script, "l", idx)?,
+                });
+            }
+            let mut functions = Vec::with_capacity(map.functions.len());
+            for (idx, (name, line)) in map.functions.iter().enumerate() {
+                functions.push(FunctionCoverage {
+                    name: name.clone(),
+                    line: *line,
+                    hits: hits(&script, "f", idx)?,
+                });
+            }
+            rv.add_script(ScriptCoverage {
+                filename: map.filename.clone(),
+                lines,
+                functions,
+            });
+        }
+        Ok(rv)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_counters, Coverage, FunctionCoverage, LineCoverage, ScriptCoverage};
+    use crate::instrument::{find_sites, Inserts};
+
+    fn counters(code: &str) -> (String, Vec<u32>, Vec<(String, u32)>) {
+        let mut inserts = Inserts::default();
+        let (lines, functions) = add_counters(&find_sites(code), 0, &mut inserts);
+        (
+            inserts.apply(code).replace("__worthless_coverage[0].", ""),
+            lines,
+            functions,
+        )
+    }
+
+    #[test]
+    fn test_instrument_statements() {
+        let (code, lines, _) = counters(
+            "let a = 1;\nif (a)\n  a++;\nconst o = {\n  b: 2,\n  c: [a,\n    a]\n};\nfoo()\n",
+        );
+        assert_eq!(
+            code,
+            "l[0]++;let a = 1;\nl[1]++;if (a)\n  a++;\nl[2]++;const o = {\n  b: 2,\n  c: [a,\n    a]\n};\nl[3]++;foo()\n"
+        );
+        assert_eq!(lines, [1, 2, 4, 9]);
+    }
+
+    #[test]
+    fn test_instrument_ambiguous_statements() {
+        let (code, lines, _) =
+            counters("do\n  a--\nwhile (a);\nswitch (a) {\n  case 1:\n    b()\n}\nc\n++\nd\n");
+        assert_eq!(
+            code,
+            "l[0]++;do\n  a--\nwhile (a);\nl[1]++;switch (a) {\n  case 1:\n    l[2]++;b()\n}\nl[3]++;c\n++\nd\n"
+        );
+        assert_eq!(lines, [1, 4, 6, 8]);
+    }
+
+    #[test]
+    fn test_instrument_functions() {
+        let (code, _, functions) = counters(
+            "function add(a, b) {\n  \"use strict\";\n  return a + b;\n}\nconst f = (x) => { return x; };\nclass A { get x() { return `${1}`; } }\n",
+        );
+        assert_eq!(
+            code,
+            "l[0]++;function add(a, b) {\n  \"use strict\";f[0]++;\n  l[1]++;return a + b;\n}\nl[2]++;const f = (x) => {f[1]++; return x; };\nl[3]++;class A { get x() {f[2]++; return `${1}`; } }\n"
+        );
+        assert_eq!(
+            functions,
+            [
+                ("add".to_string(), 1),
+                ("f".to_string(), 5),
+                ("x".to_string(), 6)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collect_merges_scripts() {
+        let script = |hits| ScriptCoverage {
+            filename: "a.js".into(),
+            lines: vec![LineCoverage { line: 1, hits }],
+            functions: vec![FunctionCoverage {
+                name: "f".into(),
+                line: 1,
+                hits,
+            }],
+        };
+        let coverage = [script(1), script(2)].into_iter().collect::<Coverage>();
+        assert_eq!(coverage.scripts().len(), 1);
+        assert_eq!(coverage.scripts()[0].lines[0].hits, 3);
+        assert_eq!(coverage.scripts()[0].functions[0].hits, 3);
+    }
+}
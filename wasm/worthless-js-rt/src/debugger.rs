@@ -0,0 +1,684 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::context::Context;
+use crate::error::Error;
+use crate::instrument::{Inserts, Phase, Sites};
+use crate::js_exception::StackFrame;
+use crate::primitive::Primitive;
+use crate::value::{Value, ValueKind};
+
+/// The global with the hooks instrumented scripts call.
+const HOOKS_GLOBAL: &str = "__worthless_debug";
+
+/// Evaluates an expression with the variables of a scope object.
+///
+/// The helper is sloppy code of its own so that instrumented scripts never
+/// contain `eval` or `with`, which would change how their scopes work.
+const EVALUATE: &str = "(function (__worthless_scope, __worthless_expr) { with (__worthless_scope) { return eval(__worthless_expr); } })";
+
+/// Why the debugger paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseReason {
+    /// A breakpoint was hit.
+    Breakpoint,
+    /// A step finished.
+    Step,
+    /// A pause was requested with [`Debugger::pause`].
+    Requested,
+    /// An exception is thrown out of a function.
+    Exception,
+}
+
+/// How the code continues after a pause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Resume {
+    /// Runs until the next breakpoint.
+    #[default]
+    Continue,
+    /// Pauses at the next statement of the function or its callers.
+    StepOver,
+    /// Pauses at the next statement, including those of called functions.
+    StepInto,
+    /// Pauses at the next statement once the function returned.
+    StepOut,
+}
+
+/// The paused code as seen by the pause handler.
+pub struct Pause {
+    reason: PauseReason,
+    filename: String,
+    line: u32,
+    exception: Option<Value>,
+    stack: Vec<StackFrame>,
+    names: Vec<String>,
+    scope: Value,
+}
+
+impl Pause {
+    /// Returns why the debugger paused.
+    pub fn reason(&self) -> PauseReason {
+        self.reason
+    }
+
+    /// Returns the filename of the paused script.
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// Returns the line of the statement that runs next.
+    ///
+    /// When pausing on an exception this is the line of the last statement
+    /// that ran in the function.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// Returns the thrown value when pausing on an exception.
+    pub fn exception(&self) -> Option<&Value> {
+        self.exception.as_ref()
+    }
+
+    /// Returns the stack of the paused code, the innermost frame comes first.
+    pub fn stack(&self) -> &[StackFrame] {
+        &self.stack
+    }
+
+    /// Evaluates an expression in the scope of the paused code.
+    ///
+    /// The expression can read and assign the variables of the scope, other
+    /// names resolve to globals and `this` is the global object.
+    /// Breakpoints are not hit while the expression runs.
+    pub fn evaluate(&self, expression: &str) -> Result<Value, Error> {
+        let ctx = self.scope.ctx();
+        let evaluate = ctx.eval_with_filename(EVALUATE, "<debugger>")?;
+        let undefined = Value::from_primitive(ctx, Primitive::Undefined);
+        evaluate.call(
+            &undefined,
+            [self.scope.clone(), Value::from_primitive(ctx, expression)],
+        )
+    }
+
+    /// Returns the variables in scope of the paused code, innermost first.
+    ///
+    /// Only variables from plain declarations and parameters are known,
+    /// destructured ones are not.  Variables that cannot be read yet are
+    /// skipped.
+    pub fn scope(&self) -> Vec<(String, Value)> {
+        self.names
+            .iter()
+            .filter_map(|name| Some((name.clone(), self.scope.get_property(name).ok()?)))
+            .collect()
+    }
+}
+
+/// The function called whenever the debugger pauses.
+pub type PauseHandler = Box<dyn FnMut(&Pause) -> Resume>;
+
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    Into,
+    Over(usize),
+    Out(usize),
+}
+
+/// A script instrumented for the debugger.
+struct Script {
+    filename: String,
+    sites: Sites,
+    /// The statements with a breakpoint.
+    breakpoints: HashSet<usize>,
+}
+
+#[derive(Default)]
+struct DebuggerState {
+    scripts: HashMap<usize, Script>,
+    breakpoints: Vec<(String, u32)>,
+    handler: Option<PauseHandler>,
+    pause_on_exceptions: bool,
+    pause_requested: bool,
+    step: Option<Step>,
+    pending: Option<PauseReason>,
+    paused: bool,
+    last_statement: Option<(usize, usize)>,
+    last_exception: Option<Value>,
+}
+
+impl DebuggerState {
+    /// Resolves the breakpoints of a script to its statements.
+    ///
+    /// A breakpoint on a line without a statement moves to the next one.
+    fn resolve_breakpoints(&mut self, script: usize) {
+        let script = match self.scripts.get_mut(&script) {
+            Some(script) => script,
+            None => return,
+        };
+        script.breakpoints.clear();
+        for (filename, line) in &self.breakpoints {
+            if *filename != script.filename {
+                continue;
+            }
+            if let Some(idx) = script.sites.statements.iter().position(|x| x.line >= *line) {
+                script.breakpoints.insert(idx);
+            }
+        }
+    }
+
+    fn scripts_named(&self, filename: &str) -> Vec<usize> {
+        let mut rv = self
+            .scripts
+            .iter()
+            .filter(|(_, script)| script.filename == filename)
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        rv.sort_unstable();
+        rv
+    }
+}
+
+thread_local! {
+    static DEBUGGERS: RefCell<HashMap<usize, Rc<RefCell<DebuggerState>>>> =
+        RefCell::new(HashMap::new());
+    // script ids are unique per thread so that scripts instrumented for a
+    // debugger that is gone do not hit the breakpoints of a new one
+    static NEXT_SCRIPT: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Pauses scripts at breakpoints and lets the host inspect them.
+///
+/// QuickJS has no debugging hooks, so scripts and modules evaluated after the
+/// debugger was attached are instrumented instead.  A hook runs before the
+/// first statement of every line and function bodies are wrapped to see the
+/// exceptions thrown out of them.  Every block declares a function that
+/// returns an object with accessors for the variables of its scope, which
+/// the hooks receive when they pause.  Pausing calls the pause handler on
+/// the stack of the paused code, it can inspect the stack and evaluate
+/// expressions against the paused scope before it decides how to resume.
+///
+/// Only lines with a statement start can be paused at, breakpoints on other
+/// lines move to the next statement.  Like with coverage, internal code
+/// with a filename in angle brackets is not instrumented.  Debugging is
+/// slow as every line calls into the host.  Dropping the debugger detaches
+/// it, scripts that were instrumented keep calling the hooks which do
+/// nothing from then on.
+pub struct Debugger {
+    ctx: Context,
+    state: Rc<RefCell<DebuggerState>>,
+}
+
+impl Debugger {
+    /// Attaches a debugger to a context.
+    ///
+    /// There can be one debugger per context.  Code evaluated before the
+    /// debugger was attached cannot be debugged.
+    pub fn attach(ctx: &Context) -> Result<Debugger, Error> {
+        if is_attached(ctx) {
+            return Err(Error::InvalidArgument(
+                "a debugger is already attached".into(),
+            ));
+        }
+        install_hooks(ctx)?;
+        let state = Rc::new(RefCell::new(DebuggerState::default()));
+        DEBUGGERS.with(|debuggers| {
+            debuggers
+                .borrow_mut()
+                .insert(ctx.as_raw() as usize, state.clone())
+        });
+        Ok(Debugger {
+            ctx: ctx.clone(),
+            state,
+        })
+    }
+
+    /// Sets the function that is called whenever the code pauses.
+    ///
+    /// Without a handler the code never pauses.
+    pub fn set_pause_handler<F>(&self, f: F)
+    where
+        F: FnMut(&Pause) -> Resume + 'static,
+    {
+        self.state.borrow_mut().handler = Some(Box::new(f));
+    }
+
+    /// Sets a breakpoint on a line of a script.
+    ///
+    /// The breakpoint also applies to scripts evaluated later.  Returns the
+    /// line the breakpoint moved to in the latest script evaluated with the
+    /// filename, if there is one.
+    pub fn set_breakpoint(&self, filename: &str, line: u32) -> Option<u32> {
+        let mut state = self.state.borrow_mut();
+        let breakpoint = (filename.to_string(), line);
+        if !state.breakpoints.contains(&breakpoint) {
+            state.breakpoints.push(breakpoint);
+        }
+        let scripts = state.scripts_named(filename);
+        for &script in &scripts {
+            state.resolve_breakpoints(script);
+        }
+        let statements = &state.scripts[scripts.last()?].sites.statements;
+        statements.iter().find(|x| x.line >= line).map(|x| x.line)
+    }
+
+    /// Removes a breakpoint, returns `false` if there was none.
+    pub fn remove_breakpoint(&self, filename: &str, line: u32) -> bool {
+        let mut state = self.state.borrow_mut();
+        let len = state.breakpoints.len();
+        state
+            .breakpoints
+            .retain(|x| !(x.0 == filename && x.1 == line));
+        for script in state.scripts_named(filename) {
+            state.resolve_breakpoints(script);
+        }
+        state.breakpoints.len() != len
+    }
+
+    /// Removes all breakpoints.
+    pub fn clear_breakpoints(&self) {
+        let mut state = self.state.borrow_mut();
+        state.breakpoints.clear();
+        for script in state.scripts.values_mut() {
+            script.breakpoints.clear();
+        }
+    }
+
+    /// Enables or disables pausing when an exception is thrown.
+    ///
+    /// The code pauses in the innermost function the exception is thrown out
+    /// of, whether or not it's caught further up.  Exceptions caught in the
+    /// function they were thrown in and exceptions thrown outside of
+    /// functions do not pause.
+    pub fn set_pause_on_exceptions(&self, yes: bool) {
+        self.state.borrow_mut().pause_on_exceptions = yes;
+    }
+
+    /// Pauses at the next statement that runs.
+    pub fn pause(&self) {
+        self.state.borrow_mut().pause_requested = true;
+    }
+}
+
+impl Drop for Debugger {
+    fn drop(&mut self) {
+        DEBUGGERS.with(|debuggers| debuggers.borrow_mut().remove(&(self.ctx.as_raw() as usize)));
+    }
+}
+
+/// Checks if a debugger is attached to a context.
+pub(crate) fn is_attached(ctx: &Context) -> bool {
+    DEBUGGERS.with(|debuggers| debuggers.borrow().contains_key(&(ctx.as_raw() as usize)))
+}
+
+fn get_state(ctx: &Context) -> Option<Rc<RefCell<DebuggerState>>> {
+    DEBUGGERS.with(|debuggers| debuggers.borrow().get(&(ctx.as_raw() as usize)).cloned())
+}
+
+/// Adds the hooks of a script if a debugger is attached to the context.
+pub(crate) fn add_script(
+    ctx: &Context,
+    filename: &str,
+    sites: &Sites,
+    inserts: &mut Inserts,
+) -> Result<(), Error> {
+    let state = match get_state(ctx) {
+        Some(state) => state,
+        None => return Ok(()),
+    };
+    let id = NEXT_SCRIPT.with(|next| next.replace(next.get() + 1));
+    for (idx, scope) in sites.scopes.iter().enumerate() {
+        let start = match scope.start {
+            Some(start) => start,
+            None => continue,
+        };
+        // function bodies declare theirs along with the try block below
+        if !sites
+            .functions
+            .iter()
+            .any(|x| x.scope == idx && x.body_end.is_some())
+        {
+            inserts.push(
+                start,
+                Phase::FunctionStart,
+                format!(
+                    "function {}(){{return {}}}",
+                    scope_function(id, idx),
+                    scope_object(sites, id, idx)
+                ),
+            );
+        }
+    }
+    for (idx, statement) in sites.statements.iter().enumerate() {
+        inserts.push(
+            statement.pos,
+            Phase::Statement,
+            format!(
+                "{0}.hit({1},{2})&&{0}.pause({1},{2},{3});",
+                HOOKS_GLOBAL,
+                id,
+                idx,
+                scope_expression(sites, id, statement.scope)
+            ),
+        );
+    }
+    for (idx, function) in sites.functions.iter().enumerate() {
+        // the catch clause rethrows so the exception still propagates, the
+        // scope function is declared outside of the try block for it
+        if let Some(end) = function.body_end {
+            let name = scope_function(id, function.scope);
+            inserts.push(
+                function.body_start,
+                Phase::FunctionStart,
+                format!(
+                    "let {0};try{{{0}=function(){{return {1}}};",
+                    name,
+                    scope_object(sites, id, function.scope)
+                ),
+            );
+            inserts.push(
+                end,
+                Phase::FunctionEnd,
+                format!(
+                    "}}catch(__worthless_error){{{}.exception({},{},__worthless_error,{}());throw __worthless_error}}",
+                    HOOKS_GLOBAL, id, idx, name
+                ),
+            );
+        }
+    }
+    let mut state = state.borrow_mut();
+    state.scripts.insert(
+        id,
+        Script {
+            filename: filename.to_string(),
+            sites: sites.clone(),
+            breakpoints: HashSet::new(),
+        },
+    );
+    state.resolve_breakpoints(id);
+    Ok(())
+}
+
+/// Returns the name of the function that returns the scope object of a scope.
+fn scope_function(id: usize, scope: usize) -> String {
+    format!("__worthless_scope_{}_{}", id, scope)
+}
+
+/// Returns an expression for the scope object of a scope.
+fn scope_expression(sites: &Sites, id: usize, scope: usize) -> String {
+    match sites.scopes[scope].start {
+        Some(_) => format!("{}()", scope_function(id, scope)),
+        None => scope_object(sites, id, scope),
+    }
+}
+
+/// Returns an object literal with accessors for the variables of a scope.
+///
+/// The object inherits those of the enclosing scopes from the scope object
+/// of the next scope that declares one.  Scopes that cannot declare one add
+/// their variables to the object of the scopes nested in them.
+fn scope_object(sites: &Sites, id: usize, scope: usize) -> String {
+    let mut names: Vec<&str> = Vec::new();
+    let mut current = Some(scope);
+    let mut parent = "null".to_string();
+    while let Some(idx) = current {
+        let binding = &sites.scopes[idx];
+        if idx != scope && binding.start.is_some() {
+            parent = format!("{}()", scope_function(id, idx));
+            break;
+        }
+        for name in &binding.names {
+            if name != "arguments" && !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        current = binding.parent;
+    }
+    let mut rv = format!("{{__proto__:{}", parent);
+    for name in names {
+        rv.push_str(&format!(
+            ",get {0}(){{return {0}}},set {0}(__worthless_value){{{0}=__worthless_value}}",
+            name
+        ));
+    }
+    rv.push('}');
+    rv
+}
+
+/// Defines the global with the hooks unless an earlier debugger did.
+fn install_hooks(ctx: &Context) -> Result<(), Error> {
+    let existing = ctx.with_global(|global| global.get_property(HOOKS_GLOBAL))?;
+    if existing.kind() == ValueKind::Undefined {
+        let hooks = Value::new_object(ctx);
+        hooks.set_property("hit", Value::from_host_fn(ctx, "hit", js_hit)?)?;
+        hooks.set_property("pause", Value::from_host_fn(ctx, "pause", js_pause)?)?;
+        hooks.set_property(
+            "exception",
+            Value::from_host_fn(ctx, "exception", js_exception)?,
+        )?;
+        let define = ctx.eval_with_filename(
+            "(function (name, hooks) { Object.defineProperty(this, name, { value: Object.freeze(hooks) }); })",
+            "<debugger>",
+        )?;
+        ctx.with_global(|global| {
+            define.call(global, [Value::from_primitive(ctx, HOOKS_GLOBAL), hooks])
+        })?;
+    }
+    Ok(())
+}
+
+fn index_arg(args: &[Value], idx: usize) -> Result<usize, Error> {
+    args.get(idx)
+        .and_then(|x| x.as_i64())
+        .and_then(|x| usize::try_from(x).ok())
+        .ok_or_else(|| Error::InvalidArgument("invalid debugger hook arguments".into()))
+}
+
+/// Captures the stack of the running code, the innermost frame comes first.
+fn capture_stack(ctx: &Context) -> Vec<StackFrame> {
+    // calling `Error` directly records the stack without its own frame
+    let undefined = Value::from_primitive(ctx, Primitive::Undefined);
+    let stack = match ctx
+        .with_global(|global| global.get_property("Error"))
+        .and_then(|error| error.call(&undefined, [] as [Value; 0]))
+        .and_then(|error| error.get_property("stack"))
+    {
+        Ok(stack) => stack,
+        Err(_) => return Vec::new(),
+    };
+    let stack = stack.to_string_lossy();
+    stack.lines().filter_map(StackFrame::parse).collect()
+}
+
+/// Returns the frames of the code that called a hook.
+fn paused_stack(ctx: &Context) -> Vec<StackFrame> {
+    // the hook itself is native code on top of the stack
+    capture_stack(ctx)
+        .into_iter()
+        .skip_while(|frame| frame.filename.is_none())
+        .collect()
+}
+
+/// Decides whether to pause before a statement.
+fn js_hit(ctx: &Context, _this: &Value, args: &[Value]) -> Result<Value, Error> {
+    let (script, statement) = (index_arg(args, 0)?, index_arg(args, 1)?);
+    let state = match get_state(ctx) {
+        Some(state) => state,
+        None => return Ok(Value::from_primitive(ctx, false)),
+    };
+    let step = {
+        let mut state = state.borrow_mut();
+        state.last_statement = Some((script, statement));
+        if state.paused || state.handler.is_none() {
+            return Ok(Value::from_primitive(ctx, false));
+        }
+        let reason = if state.pause_requested {
+            Some(PauseReason::Requested)
+        } else if state
+            .scripts
+            .get(&script)
+            .is_some_and(|x| x.breakpoints.contains(&statement))
+        {
+            Some(PauseReason::Breakpoint)
+        } else if let Some(Step::Into) = state.step {
+            Some(PauseReason::Step)
+        } else {
+            None
+        };
+        if reason.is_some() {
+            state.pending = reason;
+            return Ok(Value::from_primitive(ctx, true));
+        }
+        state.step
+    };
+    // the stack is only captured while stepping over or out
+    let pause = match step {
+        Some(Step::Over(depth)) => capture_stack(ctx).len() <= depth,
+        Some(Step::Out(depth)) => capture_stack(ctx).len() < depth,
+        _ => false,
+    };
+    if pause {
+        state.borrow_mut().pending = Some(PauseReason::Step);
+    }
+    Ok(Value::from_primitive(ctx, pause))
+}
+
+/// Pauses before a statement after [`js_hit`] said so.
+fn js_pause(ctx: &Context, _this: &Value, args: &[Value]) -> Result<Value, Error> {
+    let (script, statement) = (index_arg(args, 0)?, index_arg(args, 1)?);
+    let scope = args
+        .get(2)
+        .cloned()
+        .ok_or_else(|| Error::InvalidArgument("invalid debugger hook arguments".into()))?;
+    if let Some(state) = get_state(ctx) {
+        let pause = {
+            let mut state = state.borrow_mut();
+            let reason = state.pending.take().unwrap_or(PauseReason::Step);
+            let script = match state.scripts.get(&script) {
+                Some(script) => script,
+                None => return Ok(Value::from_primitive(ctx, Primitive::Undefined)),
+            };
+            let site = &script.sites.statements[statement];
+            Pause {
+                reason,
+                filename: script.filename.clone(),
+                line: site.line,
+                exception: None,
+                stack: Vec::new(),
+                names: script.sites.visible_names(site.scope),
+                scope,
+            }
+        };
+        run_handler(
+            ctx,
+            &state,
+            Pause {
+                stack: paused_stack(ctx),
+                ..pause
+            },
+        );
+    }
+    Ok(Value::from_primitive(ctx, Primitive::Undefined))
+}
+
+/// Pauses when an exception is thrown out of a function.
+fn js_exception(ctx: &Context, _this: &Value, args: &[Value]) -> Result<Value, Error> {
+    let (script, function) = (index_arg(args, 0)?, index_arg(args, 1)?);
+    let (exception, scope) = match (args.get(2), args.get(3)) {
+        (Some(exception), Some(scope)) => (exception.clone(), scope.clone()),
+        _ => {
+            return Err(Error::InvalidArgument(
+                "invalid debugger hook arguments".into(),
+            ))
+        }
+    };
+    if let Some(state) = get_state(ctx) {
+        let pause = {
+            let mut state = state.borrow_mut();
+            // an exception passes the hooks of every function it leaves
+            if !state.pause_on_exceptions
+                || state.paused
+                || state.handler.is_none()
+                || state
+                    .last_exception
+                    .as_ref()
+                    .is_some_and(|x| x.ptr_eq(&exception))
+            {
+                return Ok(Value::from_primitive(ctx, Primitive::Undefined));
+            }
+            state.last_exception = Some(exception.clone());
+            let last_statement = state.last_statement;
+            let script_id = script;
+            let script = match state.scripts.get(&script) {
+                Some(script) => script,
+                None => return Ok(Value::from_primitive(ctx, Primitive::Undefined)),
+            };
+            let site = &script.sites.functions[function];
+            // the last statement that ran is the best guess where it was thrown
+            let line = match last_statement {
+                Some((last_script, statement)) if last_script == script_id => {
+                    let statement = &script.sites.statements[statement];
+                    if is_within(&script.sites, statement.scope, site.scope) {
+                        statement.line
+                    } else {
+                        site.line
+                    }
+                }
+                _ => site.line,
+            };
+            Pause {
+                reason: PauseReason::Exception,
+                filename: script.filename.clone(),
+                line,
+                exception: Some(exception),
+                stack: Vec::new(),
+                names: script.sites.visible_names(site.scope),
+                scope,
+            }
+        };
+        run_handler(
+            ctx,
+            &state,
+            Pause {
+                stack: paused_stack(ctx),
+                ..pause
+            },
+        );
+    }
+    Ok(Value::from_primitive(ctx, Primitive::Undefined))
+}
+
+/// Checks if a binding scope is nested in another one.
+fn is_within(sites: &Sites, mut scope: usize, outer: usize) -> bool {
+    loop {
+        if scope == outer {
+            return true;
+        }
+        match sites.scopes[scope].parent {
+            Some(parent) => scope = parent,
+            None => return false,
+        }
+    }
+}
+
+/// Calls the pause handler and sets up the steps it asked for.
+fn run_handler(ctx: &Context, state: &Rc<RefCell<DebuggerState>>, pause: Pause) {
+    // the handler is taken out so it can use the debugger
+    let mut handler = match state.borrow_mut().handler.take() {
+        Some(handler) => handler,
+        None => return,
+    };
+    state.borrow_mut().paused = true;
+    let resume = handler(&pause);
+    let step = match resume {
+        Resume::Continue => None,
+        Resume::StepInto => Some(Step::Into),
+        Resume::StepOver => Some(Step::Over(capture_stack(ctx).len())),
+        Resume::StepOut => Some(Step::Out(capture_stack(ctx).len())),
+    };
+    let mut state = state.borrow_mut();
+    state.paused = false;
+    state.pause_requested = false;
+    state.step = step;
+    if state.handler.is_none() {
+        state.handler = Some(handler);
+    }
+}
@@ -1,3 +1,6 @@
+use std::any::Any;
+use std::fmt;
+
 use thiserror::Error;
 
 use crate::js_exception::JsException;
@@ -19,4 +22,172 @@ pub enum Error {
     IntOverflow(#[source] std::num::TryFromIntError),
     #[error("length property of object is invalid")]
     InvalidLength,
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("invalid value: {0}")]
+    InvalidValue(String),
+    #[error("{0}")]
+    Host(String),
+    #[error("{0}")]
+    Other(Box<dyn std::error::Error + Send + Sync + 'static>),
+    #[error("{msg}")]
+    Context {
+        msg: String,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Converts the error of a host function.
+    ///
+    /// Errors of this crate (and [`anyhow::Error`] with the `anyhow` feature)
+    /// are passed through, all other errors keep only their message.
+    pub fn from_host<E: fmt::Display + 'static>(err: E) -> Error {
+        let mut err = Some(err);
+        if let Some(err) = (&mut err as &mut dyn Any).downcast_mut::<Option<Error>>() {
+            return err.take().unwrap();
+        }
+        #[cfg(feature = "anyhow")]
+        if let Some(err) = (&mut err as &mut dyn Any).downcast_mut::<Option<anyhow::Error>>() {
+            return Error::from(err.take().unwrap());
+        }
+        Error::Host(err.unwrap().to_string())
+    }
+
+    /// Wraps the error with a message describing what failed.
+    ///
+    /// The message is displayed, the wrapped error becomes the source.
+    pub fn context<C: fmt::Display>(self, msg: C) -> Error {
+        Error::Context {
+            msg: msg.to_string(),
+            source: Box::new(self),
+        }
+    }
+
+    /// Returns the error without the context wrapped around it.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context { source, .. } => source.root(),
+            err => err,
+        }
+    }
+
+    /// Returns the JavaScript exception behind the error.
+    ///
+    /// This looks through context so the thrown value stays reachable.
+    pub fn js_exception(&self) -> Option<&JsException> {
+        match self.root() {
+            Error::JsException(exc) => Some(exc),
+            _ => None,
+        }
+    }
+
+    /// Returns the external error behind the error if it is of type `E`.
+    pub fn downcast_ref<E: std::error::Error + Send + Sync + 'static>(&self) -> Option<&E> {
+        match self.root() {
+            #[cfg(feature = "anyhow")]
+            Error::Other(err) if err.is::<AnyhowError>() => err
+                .downcast_ref::<AnyhowError>()
+                .and_then(|err| err.0.downcast_ref()),
+            Error::Other(err) => err.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// Converts the error into an [`anyhow::Error`].
+    ///
+    /// Unlike converting with `?` the context becomes context of the
+    /// [`anyhow::Error`] and external errors are unwrapped, so they can be
+    /// downcast from it.
+    #[cfg(feature = "anyhow")]
+    pub fn into_anyhow(self) -> anyhow::Error {
+        match self {
+            Error::Context { msg, source } => source.into_anyhow().context(msg),
+            Error::Other(err) => match err.downcast::<AnyhowError>() {
+                Ok(err) => err.0,
+                Err(err) => anyhow::anyhow!(err),
+            },
+            err => anyhow::Error::new(err),
+        }
+    }
+}
+
+/// Keeps an [`anyhow::Error`] intact so it can be downcast and unwrapped.
+#[cfg(feature = "anyhow")]
+#[derive(Debug)]
+struct AnyhowError(anyhow::Error);
+
+#[cfg(feature = "anyhow")]
+impl fmt::Display for AnyhowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[cfg(feature = "anyhow")]
+impl std::error::Error for AnyhowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+#[cfg(feature = "anyhow")]
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Error {
+        Error::Other(Box::new(AnyhowError(err)))
+    }
+}
+
+/// Adds context to the errors of results.
+pub trait ResultExt<T> {
+    /// Wraps the error with a message.
+    fn context<C: fmt::Display>(self, msg: C) -> Result<T, Error>;
+
+    /// Wraps the error with a message that is only created on failure.
+    fn with_context<C: fmt::Display, F: FnOnce() -> C>(self, f: F) -> Result<T, Error>;
+}
+
+impl<T> ResultExt<T> for Result<T, Error> {
+    fn context<C: fmt::Display>(self, msg: C) -> Result<T, Error> {
+        self.map_err(|err| err.context(msg))
+    }
+
+    fn with_context<C: fmt::Display, F: FnOnce() -> C>(self, f: F) -> Result<T, Error> {
+        self.map_err(|err| err.context(f()))
+    }
+}
+
+/// Represents an error of a [`RuntimeActor`](crate::RuntimeActor).
+#[derive(Error, Debug)]
+pub enum ActorError {
+    #[error("runtime actor has shut down")]
+    ShutDown,
+    #[error("JavaScript exception: {message}")]
+    JsException {
+        message: String,
+        stack: Option<String>,
+    },
+    #[error("{0}")]
+    Runtime(String),
+}
+
+impl From<Error> for ActorError {
+    fn from(err: Error) -> ActorError {
+        match err {
+            Error::JsException(exc) => ActorError::JsException {
+                message: exc.message().to_string(),
+                stack: exc.stack().map(|x| x.to_string()),
+            },
+            Error::Context { msg, source } => match ActorError::from(*source) {
+                ActorError::JsException { message, stack } => ActorError::JsException {
+                    message: format!("{}: {}", msg, message),
+                    stack,
+                },
+                ActorError::Runtime(err) => ActorError::Runtime(format!("{}: {}", msg, err)),
+                err => err,
+            },
+            err => ActorError::Runtime(err.to_string()),
+        }
+    }
 }
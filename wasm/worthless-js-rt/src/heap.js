@@ -0,0 +1,139 @@
+// Walks the objects reachable from a global object for a heap snapshot.
+// Evaluating this returns the walker.  Only own property descriptors are
+// read so getters do not run, the getter and setter functions themselves
+// are visited instead.  Proxy traps may still run, objects whose traps
+// throw are counted without their properties.
+(function () {
+  "use strict";
+
+  const apply = Reflect.apply;
+  const ownKeys = Reflect.ownKeys;
+  const getPrototypeOf = Object.getPrototypeOf;
+  const getOwnPropertyDescriptor = Object.getOwnPropertyDescriptor;
+  const isView = ArrayBuffer.isView;
+  const mapSize = getOwnPropertyDescriptor(Map.prototype, "size").get;
+  const mapForEach = Map.prototype.forEach;
+  const setSize = getOwnPropertyDescriptor(Set.prototype, "size").get;
+  const setForEach = Set.prototype.forEach;
+  const bufferByteLength = getOwnPropertyDescriptor(ArrayBuffer.prototype, "byteLength").get;
+  const typedArrayBuffer = getOwnPropertyDescriptor(
+    getPrototypeOf(Uint8Array.prototype),
+    "buffer"
+  ).get;
+  const dataViewBuffer = getOwnPropertyDescriptor(DataView.prototype, "buffer").get;
+  const PREVIEW_LENGTH = 64;
+  const LARGEST_STRINGS = 20;
+
+  function isA(getter, obj) {
+    try {
+      apply(getter, obj, []);
+      return true;
+    } catch (err) {
+      return false;
+    }
+  }
+
+  function className(obj) {
+    const proto = getPrototypeOf(obj);
+    if (proto === null) {
+      return "Object (null prototype)";
+    }
+    const ctor = getOwnPropertyDescriptor(proto, "constructor");
+    if (ctor && typeof ctor.value === "function") {
+      const name = getOwnPropertyDescriptor(ctor.value, "name");
+      if (name && typeof name.value === "string" && name.value !== "") {
+        return name.value;
+      }
+    }
+    return "Object";
+  }
+
+  return function (root) {
+    const seen = new Set();
+    const classes = new Map();
+    const strings = new Map();
+    const pending = [root];
+
+    function visit(value) {
+      const type = typeof value;
+      if (type === "string") {
+        strings.set(value, (strings.get(value) || 0) + 1);
+      } else if ((type === "object" && value !== null) || type === "function") {
+        if (!seen.has(value)) {
+          seen.add(value);
+          pending.push(value);
+        }
+      }
+    }
+
+    seen.add(root);
+    while (pending.length > 0) {
+      const obj = pending.pop();
+      let name = "Object";
+      let properties = 0;
+      let bytes = 0;
+      try {
+        name = className(obj);
+        visit(getPrototypeOf(obj));
+        if (isView(obj)) {
+          // the elements are numbers, only the buffer holds on to memory
+          const buffer = isA(dataViewBuffer, obj) ? dataViewBuffer : typedArrayBuffer;
+          visit(apply(buffer, obj, []));
+        } else {
+          const keys = ownKeys(obj);
+          properties = keys.length;
+          for (let i = 0; i < keys.length; i++) {
+            const desc = getOwnPropertyDescriptor(obj, keys[i]);
+            if (desc === undefined) {
+              continue;
+            }
+            if ("value" in desc) {
+              visit(desc.value);
+            } else {
+              visit(desc.get);
+              visit(desc.set);
+            }
+          }
+        }
+        if (isA(mapSize, obj)) {
+          properties += apply(mapSize, obj, []);
+          apply(mapForEach, obj, [function (value, key) {
+            visit(key);
+            visit(value);
+          }]);
+        } else if (isA(setSize, obj)) {
+          properties += apply(setSize, obj, []);
+          apply(setForEach, obj, [visit]);
+        } else if (isA(bufferByteLength, obj)) {
+          bytes = apply(bufferByteLength, obj, []);
+        }
+      } catch (err) {
+        // counted with what was found before the error
+      }
+      const stats = classes.get(name);
+      if (stats === undefined) {
+        classes.set(name, [name, 1, properties, bytes]);
+      } else {
+        stats[1] += 1;
+        stats[2] += properties;
+        stats[3] += bytes;
+      }
+    }
+
+    let stringLength = 0;
+    const largest = [];
+    strings.forEach(function (references, value) {
+      stringLength += value.length;
+      largest.push([value, value.length, references]);
+    });
+    largest.sort(function (a, b) {
+      return b[1] * b[2] - a[1] * a[2];
+    });
+    largest.length = Math.min(largest.length, LARGEST_STRINGS);
+    for (let i = 0; i < largest.length; i++) {
+      largest[i][0] = largest[i][0].slice(0, PREVIEW_LENGTH);
+    }
+
+    return [Array.from(classes.values()), strings.size, stringLength, largest];
+  };
+})()
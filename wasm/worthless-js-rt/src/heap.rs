@@ -0,0 +1,304 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::mem::MaybeUninit;
+
+use worthless_quickjs_sys::{JSContext, JSMemoryUsage, JSRuntime, JS_ComputeMemoryUsage};
+
+use crate::context::Context;
+use crate::error::Error;
+use crate::primitive::Primitive;
+use crate::profiler::write_json_str;
+use crate::runtime::{MemoryUsage, Runtime};
+use crate::value::Value;
+
+const HEAP_JS: &str = include_str!("heap.js");
+
+/// The number of largest strings kept in a [`HeapSnapshot`].
+const LARGEST_STRINGS: usize = 20;
+
+thread_local! {
+    // the owned contexts of every runtime as (runtime, context) pointers
+    static CONTEXTS: RefCell<Vec<(usize, usize)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Remembers a context so that snapshots of its runtime walk it.
+pub(crate) fn register(rt: *mut JSRuntime, ctx: *mut JSContext) {
+    CONTEXTS.with(|contexts| contexts.borrow_mut().push((rt as usize, ctx as usize)));
+}
+
+/// Forgets a context that is about to be freed.
+pub(crate) fn forget(ctx: *mut JSContext) {
+    CONTEXTS.with(|contexts| {
+        contexts
+            .borrow_mut()
+            .retain(|&(_, other)| other != ctx as usize)
+    });
+}
+
+/// The number and size of allocations of one kind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Allocations {
+    /// The number of live allocations.
+    pub count: u64,
+    /// The bytes they occupy.
+    pub size: u64,
+}
+
+/// The objects of one class that are reachable from a global object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassUsage {
+    /// The name of the constructor of the objects.
+    pub class: String,
+    /// The number of objects.
+    pub count: u64,
+    /// The number of own properties plus the entries of maps and sets.
+    pub properties: u64,
+    /// The estimated bytes of the objects, their properties and buffers.
+    pub size: u64,
+}
+
+/// A string that is reachable from a global object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StringUsage {
+    /// The first characters of the string.
+    pub preview: String,
+    /// The length of the string in UTF-16 code units.
+    pub length: u64,
+    /// The number of properties and entries that hold the string.
+    pub references: u64,
+}
+
+/// A summary of what the heap of a runtime holds.
+///
+/// The allocations are counted by QuickJS for the whole runtime.  The
+/// classes and strings are found by walking the objects reachable from the
+/// global objects of the contexts, so values only held by closures,
+/// promises or weak collections are missing from them.  A gap between the
+/// object count and [`HeapSnapshot::reachable_objects`] hints at those.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapSnapshot {
+    /// The summary that is also returned by [`Runtime::memory_usage`].
+    pub memory: MemoryUsage,
+    /// The interned property names and symbols.
+    pub atoms: Allocations,
+    /// The strings that are not atoms.
+    pub strings: Allocations,
+    /// The objects including functions and arrays.
+    pub objects: Allocations,
+    /// The property tables of the objects.
+    pub properties: Allocations,
+    /// The shapes that objects with the same properties share.
+    pub shapes: Allocations,
+    /// The compiled JavaScript functions.
+    pub functions: Allocations,
+    /// The bytes of bytecode of the compiled functions.
+    pub function_code_size: u64,
+    /// The number of native functions.
+    pub native_functions: u64,
+    /// The number of arrays.
+    pub arrays: u64,
+    /// The number of elements of arrays that store them densely.
+    pub fast_array_elements: u64,
+    /// The array buffers and typed arrays.
+    pub binary_objects: Allocations,
+    /// The number of objects reachable from the global objects.
+    pub reachable_objects: u64,
+    /// The reachable objects by class, the largest come first.
+    pub classes: Vec<ClassUsage>,
+    /// The number of distinct reachable strings.
+    pub reachable_strings: u64,
+    /// The combined length of the distinct reachable strings.
+    pub reachable_string_length: u64,
+    /// The strings that take up the most space with all their references.
+    pub largest_strings: Vec<StringUsage>,
+}
+
+impl HeapSnapshot {
+    /// Takes a snapshot of the heap of a runtime.
+    pub(crate) fn capture(rt: &Runtime) -> Result<HeapSnapshot, Error> {
+        // collect the cycles first so that only live objects are counted
+        rt.run_gc();
+        let usage = unsafe {
+            let mut usage = MaybeUninit::<JSMemoryUsage>::uninit();
+            JS_ComputeMemoryUsage(rt.as_raw(), usage.as_mut_ptr());
+            usage.assume_init()
+        };
+        let count = |x: i64| x.max(0) as u64;
+        let allocations = |count: i64, size: i64| Allocations {
+            count: count.max(0) as u64,
+            size: size.max(0) as u64,
+        };
+        let mut snapshot = HeapSnapshot {
+            memory: usage.into(),
+            atoms: allocations(usage.atom_count, usage.atom_size),
+            strings: allocations(usage.str_count, usage.str_size),
+            objects: allocations(usage.obj_count, usage.obj_size),
+            properties: allocations(usage.prop_count, usage.prop_size),
+            shapes: allocations(usage.shape_count, usage.shape_size),
+            functions: allocations(usage.js_func_count, usage.js_func_size),
+            function_code_size: count(usage.js_func_code_size),
+            native_functions: count(usage.c_func_count),
+            arrays: count(usage.array_count),
+            fast_array_elements: count(usage.fast_array_elements),
+            binary_objects: allocations(usage.binary_object_count, usage.binary_object_size),
+            reachable_objects: 0,
+            classes: Vec::new(),
+            reachable_strings: 0,
+            reachable_string_length: 0,
+            largest_strings: Vec::new(),
+        };
+
+        let contexts = CONTEXTS.with(|contexts| {
+            contexts
+                .borrow()
+                .iter()
+                .filter(|&&(other, _)| other == rt.as_raw() as usize)
+                .map(|&(_, ctx)| ctx)
+                .collect::<Vec<_>>()
+        });
+        let mut classes = HashMap::<String, ClassUsage>::new();
+        for ctx in contexts {
+            let ctx = unsafe { Context::borrow_raw_unchecked(ctx as *mut JSContext) };
+            let walk = ctx.eval_with_filename(HEAP_JS, "<heap-snapshot>")?;
+            let undefined = Value::from_primitive(&ctx, Primitive::Undefined);
+            let result = walk.call(&undefined, [ctx.global()])?;
+
+            let found = result.get_by_index(0)?;
+            for idx in 0..found.len().unwrap_or(0) {
+                let item = found.get_by_index(idx)?;
+                let class = item.get_by_index(0)?.to_string_lossy().to_string();
+                let usage = classes.entry(class.clone()).or_insert(ClassUsage {
+                    class,
+                    count: 0,
+                    properties: 0,
+                    size: 0,
+                });
+                usage.count += number(&item, 1)?;
+                usage.properties += number(&item, 2)?;
+                // the buffer bytes are kept in the size until the estimate
+                // for the objects is added below
+                usage.size += number(&item, 3)?;
+            }
+            snapshot.reachable_strings += number(&result, 1)?;
+            snapshot.reachable_string_length += number(&result, 2)?;
+            let largest = result.get_by_index(3)?;
+            for idx in 0..largest.len().unwrap_or(0) {
+                let item = largest.get_by_index(idx)?;
+                snapshot.largest_strings.push(StringUsage {
+                    preview: item.get_by_index(0)?.to_string_lossy().to_string(),
+                    length: number(&item, 1)?,
+                    references: number(&item, 2)?,
+                });
+            }
+        }
+
+        // QuickJS does not report sizes per object, the averages of the
+        // runtime are a good enough estimate to compare classes
+        let per_object = average(&snapshot.objects);
+        let per_property = average(&snapshot.properties);
+        snapshot.classes = classes
+            .into_values()
+            .map(|mut usage| {
+                usage.size += usage.count * per_object + usage.properties * per_property;
+                usage
+            })
+            .collect();
+        snapshot
+            .classes
+            .sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.class.cmp(&b.class)));
+        snapshot.reachable_objects = snapshot.classes.iter().map(|x| x.count).sum();
+        snapshot
+            .largest_strings
+            .sort_by_key(|x| std::cmp::Reverse(x.length * x.references));
+        snapshot.largest_strings.truncate(LARGEST_STRINGS);
+        Ok(snapshot)
+    }
+
+    /// Exports the snapshot as JSON.
+    ///
+    /// The keys are the field names of the snapshot, allocations are
+    /// objects with a `count` and a `size`.
+    pub fn to_json(&self) -> String {
+        let mut rv = String::new();
+        let memory = &self.memory;
+        write!(
+            rv,
+            r#"{{"memory":{{"malloc_size":{},"malloc_count":{},"memory_used_size":{},"obj_count":{},"str_count":{},"atom_count":{}}}"#,
+            memory.malloc_size,
+            memory.malloc_count,
+            memory.memory_used_size,
+            memory.obj_count,
+            memory.str_count,
+            memory.atom_count,
+        )
+        .unwrap();
+        for (key, allocations) in [
+            ("atoms", &self.atoms),
+            ("strings", &self.strings),
+            ("objects", &self.objects),
+            ("properties", &self.properties),
+            ("shapes", &self.shapes),
+            ("functions", &self.functions),
+            ("binary_objects", &self.binary_objects),
+        ] {
+            write!(
+                rv,
+                r#","{}":{{"count":{},"size":{}}}"#,
+                key, allocations.count, allocations.size
+            )
+            .unwrap();
+        }
+        write!(
+            rv,
+            r#","function_code_size":{},"native_functions":{},"arrays":{},"fast_array_elements":{},"reachable_objects":{},"reachable_strings":{},"reachable_string_length":{}"#,
+            self.function_code_size,
+            self.native_functions,
+            self.arrays,
+            self.fast_array_elements,
+            self.reachable_objects,
+            self.reachable_strings,
+            self.reachable_string_length,
+        )
+        .unwrap();
+        rv.push_str(r#","classes":["#);
+        for (idx, usage) in self.classes.iter().enumerate() {
+            if idx > 0 {
+                rv.push(',');
+            }
+            rv.push_str(r#"{"class":"#);
+            write_json_str(&mut rv, &usage.class);
+            write!(
+                rv,
+                r#","count":{},"properties":{},"size":{}}}"#,
+                usage.count, usage.properties, usage.size
+            )
+            .unwrap();
+        }
+        rv.push_str(r#"],"largest_strings":["#);
+        for (idx, usage) in self.largest_strings.iter().enumerate() {
+            if idx > 0 {
+                rv.push(',');
+            }
+            rv.push_str(r#"{"preview":"#);
+            write_json_str(&mut rv, &usage.preview);
+            write!(
+                rv,
+                r#","length":{},"references":{}}}"#,
+                usage.length, usage.references
+            )
+            .unwrap();
+        }
+        rv.push_str("]}");
+        rv
+    }
+}
+
+/// Reads a count from an array returned by the walker.
+fn number(array: &Value, idx: usize) -> Result<u64, Error> {
+    Ok(array.get_by_index(idx)?.as_f64().unwrap_or(0.0).max(0.0) as u64)
+}
+
+fn average(allocations: &Allocations) -> u64 {
+    allocations.size.checked_div(allocations.count).unwrap_or(0)
+}
@@ -0,0 +1,945 @@
+//! Source instrumentation shared by coverage and the debugger.
+//!
+//! QuickJS offers no hooks into the execution of statements, so code is
+//! rewritten before it's evaluated instead.  The instrumentation only ever
+//! inserts code, without line breaks, so line numbers stay intact.
+use crate::context::Context;
+use crate::coverage;
+use crate::debugger;
+use crate::error::Error;
+
+/// Keywords that cannot start a statement, or continue one when they start a line.
+const NON_STATEMENT_KEYWORDS: &[&str] = &[
+    "as",
+    "case",
+    "catch",
+    "default",
+    "else",
+    "extends",
+    "finally",
+    "from",
+    "in",
+    "instanceof",
+    "of",
+];
+
+/// Keywords after which a line break does not end the statement.
+const KEYWORDS: &[&str] = &[
+    "async",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "export",
+    "extends",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "import",
+    "in",
+    "instanceof",
+    "let",
+    "new",
+    "of",
+    "return",
+    "static",
+    "switch",
+    "throw",
+    "try",
+    "typeof",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+];
+
+/// Keywords after which a slash starts a regular expression.
+const REGEX_KEYWORDS: &[&str] = &[
+    "await",
+    "case",
+    "delete",
+    "do",
+    "else",
+    "in",
+    "instanceof",
+    "new",
+    "of",
+    "return",
+    "throw",
+    "typeof",
+    "void",
+    "yield",
+];
+
+const PUNCTUATORS: &[&str] = &[
+    ">>>=", "...", "===", "!==", "**=", "<<=", ">>=", ">>>", "&&=", "||=", "??=", "=>", "==", "!=",
+    "<=", ">=", "&&", "||", "??", "?.", "++", "--", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=",
+    "**", "<<", ">>",
+];
+
+/// A position before which a statement starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StatementSite {
+    pub pos: usize,
+    pub line: u32,
+    /// The index of the innermost binding scope.
+    pub scope: usize,
+}
+
+/// The body of a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FunctionSite {
+    pub name: String,
+    pub line: u32,
+    /// Where the body starts after the directives.
+    pub body_start: usize,
+    /// The position of the closing brace if it was found.
+    pub body_end: Option<usize>,
+    /// The index of the binding scope of the body.
+    pub scope: usize,
+}
+
+/// A block or function body and the names declared in it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct BindingScope {
+    pub parent: Option<usize>,
+    pub names: Vec<String>,
+    /// Where declarations of the scope can go, if anywhere.
+    pub start: Option<usize>,
+}
+
+/// The places of a script where code can be inserted.
+///
+/// There is at most one statement per line, the first one that starts on it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Sites {
+    pub statements: Vec<StatementSite>,
+    pub functions: Vec<FunctionSite>,
+    pub scopes: Vec<BindingScope>,
+}
+
+impl Sites {
+    /// Returns the names visible in a scope, innermost first.
+    ///
+    /// Only names declared with simple declarations and parameters are
+    /// known, destructuring patterns are skipped.
+    pub fn visible_names(&self, mut scope: usize) -> Vec<String> {
+        let mut rv: Vec<String> = Vec::new();
+        loop {
+            for name in &self.scopes[scope].names {
+                if !rv.contains(name) {
+                    rv.push(name.clone());
+                }
+            }
+            match self.scopes[scope].parent {
+                Some(parent) => scope = parent,
+                None => return rv,
+            }
+        }
+    }
+}
+
+/// When an insert goes relative to others at the same position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Phase {
+    FunctionStart,
+    Statement,
+    FunctionEnd,
+}
+
+/// Code to insert into a script.
+#[derive(Debug, Default)]
+pub(crate) struct Inserts {
+    items: Vec<(usize, Phase, String)>,
+}
+
+impl Inserts {
+    pub fn push(&mut self, pos: usize, phase: Phase, code: String) {
+        self.items.push((pos, phase, code));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Inserts the code into the source.
+    pub fn apply(mut self, code: &str) -> String {
+        // inserts at the same position keep the order they were pushed in
+        self.items.sort_by_key(|(pos, phase, _)| (*pos, *phase));
+        let mut rv = String::with_capacity(
+            code.len() + self.items.iter().map(|(_, _, x)| x.len()).sum::<usize>(),
+        );
+        let mut last = 0;
+        for (pos, _, insert) in self.items {
+            rv.push_str(&code[last..pos]);
+            rv.push_str(&insert);
+            last = pos;
+        }
+        rv.push_str(&code[last..]);
+        rv
+    }
+}
+
+/// Instruments a script for coverage and the debugger if they are on.
+///
+/// Filenames in angle brackets such as `<script>` are internal code that is
+/// never instrumented.
+pub(crate) fn instrument(
+    ctx: &Context,
+    code: &str,
+    filename: &str,
+) -> Result<Option<String>, Error> {
+    if (filename.starts_with('<') && filename.ends_with('>'))
+        || !(coverage::is_enabled(ctx) || debugger::is_attached(ctx))
+    {
+        return Ok(None);
+    }
+    let sites = find_sites(code);
+    let mut inserts = Inserts::default();
+    coverage::add_script(ctx, filename, &sites, &mut inserts)?;
+    debugger::add_script(ctx, filename, &sites, &mut inserts)?;
+    if inserts.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(inserts.apply(code)))
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+    Ident,
+    Punct,
+    String,
+    Number,
+    Template,
+    Regex,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    kind: TokenKind,
+    text: &'a str,
+    start: usize,
+    line: u32,
+    newline_before: bool,
+}
+
+impl Token<'_> {
+    fn is(&self, text: &str) -> bool {
+        self.text == text && matches!(self.kind, TokenKind::Ident | TokenKind::Punct)
+    }
+
+    fn is_keyword(&self) -> bool {
+        self.kind == TokenKind::Ident && KEYWORDS.contains(&self.text)
+    }
+}
+
+/// Splits JavaScript source into tokens.
+///
+/// This is not a full tokenizer.  It knows enough about strings, comments,
+/// templates and regular expressions to find the tokens the instrumentation
+/// needs, malformed source produces garbage tokens rather than errors.
+fn tokenize(code: &str) -> Vec<Token<'_>> {
+    let bytes = code.as_bytes();
+    let mut tokens: Vec<Token<'_>> = Vec::new();
+    let mut pos = 0;
+    let mut line = 1;
+    let mut newline_before = false;
+    // the brace depths at which template expressions continue the template
+    let mut templates = Vec::new();
+    let mut depth = 0usize;
+
+    let is_ident = |c: u8| c.is_ascii_alphanumeric() || c == b'_' || c == b'$' || c >= 0x80;
+
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        let start = pos;
+        let start_line = line;
+        let kind = match c {
+            b'\n' => {
+                line += 1;
+                newline_before = true;
+                pos += 1;
+                continue;
+            }
+            c if c.is_ascii_whitespace() => {
+                pos += 1;
+                continue;
+            }
+            b'/' if bytes.get(pos + 1) == Some(&b'/') => {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+            b'/' if bytes.get(pos + 1) == Some(&b'*') => {
+                pos += 2;
+                while pos < bytes.len() && !bytes[pos..].starts_with(b"*/") {
+                    if bytes[pos] == b'\n' {
+                        line += 1;
+                        newline_before = true;
+                    }
+                    pos += 1;
+                }
+                pos = (pos + 2).min(bytes.len());
+                continue;
+            }
+            b'\'' | b'"' => {
+                pos += 1;
+                while pos < bytes.len() && bytes[pos] != c && bytes[pos] != b'\n' {
+                    if bytes[pos] == b'\\' {
+                        if bytes.get(pos + 1) == Some(&b'\n') {
+                            line += 1;
+                        }
+                        pos += 1;
+                    }
+                    pos += 1;
+                }
+                pos = (pos + 1).min(bytes.len());
+                TokenKind::String
+            }
+            b'`' => {
+                pos = scan_template(bytes, pos + 1, &mut line);
+                if bytes[..pos].ends_with(b"${") {
+                    templates.push(depth);
+                }
+                TokenKind::Template
+            }
+            b'}' if templates.last() == Some(&depth) => {
+                templates.pop();
+                pos = scan_template(bytes, pos + 1, &mut line);
+                if bytes[..pos].ends_with(b"${") {
+                    templates.push(depth);
+                }
+                TokenKind::Template
+            }
+            b'/' if regex_allowed(tokens.last()) => {
+                pos += 1;
+                let mut in_class = false;
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    match bytes[pos] {
+                        b'\\' => pos += 1,
+                        b'[' => in_class = true,
+                        b']' => in_class = false,
+                        b'/' if !in_class => break,
+                        _ => {}
+                    }
+                    pos += 1;
+                }
+                pos = (pos + 1).min(bytes.len());
+                while pos < bytes.len() && is_ident(bytes[pos]) {
+                    pos += 1;
+                }
+                TokenKind::Regex
+            }
+            c if c.is_ascii_digit()
+                || (c == b'.' && bytes.get(pos + 1).is_some_and(u8::is_ascii_digit)) =>
+            {
+                while pos < bytes.len() {
+                    match bytes[pos] {
+                        b'e' | b'E'
+                            if matches!(bytes.get(pos + 1), Some(b'+') | Some(b'-'))
+                                && !code[start..pos].starts_with("0x") =>
+                        {
+                            pos += 2
+                        }
+                        c if is_ident(c) || c == b'.' => pos += 1,
+                        _ => break,
+                    }
+                }
+                TokenKind::Number
+            }
+            c if is_ident(c) || c == b'#' || c == b'\\' => {
+                pos += 1;
+                while pos < bytes.len() && (is_ident(bytes[pos]) || bytes[pos] == b'\\') {
+                    pos += 1;
+                }
+                TokenKind::Ident
+            }
+            _ => {
+                pos += PUNCTUATORS
+                    .iter()
+                    .find(|x| bytes[pos..].starts_with(x.as_bytes()))
+                    .map_or(1, |x| x.len());
+                match c {
+                    b'{' => depth += 1,
+                    b'}' => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                TokenKind::Punct
+            }
+        };
+        // multi-byte characters are consumed as a whole
+        while !code.is_char_boundary(pos) {
+            pos += 1;
+        }
+        tokens.push(Token {
+            kind,
+            text: &code[start..pos],
+            start,
+            line: start_line,
+            newline_before,
+        });
+        newline_before = false;
+    }
+    tokens
+}
+
+/// Scans the rest of a template literal up to the closing backtick or the
+/// start of an expression and returns the position after it.
+fn scan_template(bytes: &[u8], mut pos: usize, line: &mut u32) -> usize {
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'\\' => pos += 1,
+            b'\n' => *line += 1,
+            b'`' => return pos + 1,
+            b'$' if bytes.get(pos + 1) == Some(&b'{') => return pos + 2,
+            _ => {}
+        }
+        pos += 1;
+    }
+    pos
+}
+
+/// Checks if a slash after the token starts a regular expression.
+fn regex_allowed(prev: Option<&Token<'_>>) -> bool {
+    match prev {
+        None => true,
+        Some(prev) => match prev.kind {
+            TokenKind::Ident => REGEX_KEYWORDS.contains(&prev.text),
+            TokenKind::Punct => !matches!(prev.text, ")" | "]"),
+            TokenKind::Template => prev.text.ends_with("${"),
+            _ => false,
+        },
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Program,
+    Block,
+    Function,
+    Switch,
+    Class,
+    Object,
+    Template,
+    /// Parentheses and what precedes them.
+    Paren(usize),
+    Bracket,
+}
+
+impl Scope {
+    fn has_statements(self) -> bool {
+        matches!(
+            self,
+            Scope::Program | Scope::Block | Scope::Function | Scope::Switch
+        )
+    }
+}
+
+/// Finds where statements and function bodies start in a script.
+///
+/// A statement site is recorded before the first statement of a line.
+/// Nothing is recorded where it's ambiguous whether a statement starts, such
+/// lines cannot be instrumented.
+pub(crate) fn find_sites(code: &str) -> Sites {
+    let tokens = tokenize(code);
+    let mut sites = Sites {
+        scopes: vec![BindingScope::default()],
+        ..Sites::default()
+    };
+
+    let mut scopes = vec![Scope::Program];
+    // the binding scopes of the scopes with statements
+    let mut bindings = vec![0];
+    // the scope depths and indexes of the functions whose body is open
+    let mut open_functions: Vec<(usize, usize)> = Vec::new();
+    // the scope that the last closing token closed
+    let mut closed = None;
+    // the index of the opening parenthesis for closing ones
+    let mut matching = vec![0; tokens.len()];
+    let mut class_depth = None;
+    let mut case_label = false;
+    // the scope depths of `do` loops without braces, their `while` follows
+    // the body statement
+    let mut braceless_do = Vec::new();
+    // the scope depth of a declaration and whether a name comes next
+    let mut declaring = None;
+    let mut expect_name = false;
+
+    for (idx, token) in tokens.iter().enumerate() {
+        let prev = idx.checked_sub(1).map(|x| &tokens[x]);
+        let scope = *scopes.last().unwrap();
+        let do_tail = token.is("while") && braceless_do.last() == Some(&scopes.len());
+        let member = prev.is_some_and(|x| x.is(".") || x.is("?."));
+
+        if scope.has_statements()
+            && !do_tail
+            && sites.statements.last().map(|x| x.line) != Some(token.line)
+            && starts_statement(token, prev, closed, case_label, &tokens)
+        {
+            sites.statements.push(StatementSite {
+                pos: token.start,
+                line: token.line,
+                scope: *bindings.last().unwrap(),
+            });
+            declaring = None;
+        }
+
+        if std::mem::take(&mut expect_name) && token.kind == TokenKind::Ident && !token.is_keyword()
+        {
+            let names = &mut sites.scopes[*bindings.last().unwrap()].names;
+            if !names.iter().any(|x| x == token.text) {
+                names.push(token.text.to_string());
+            }
+        }
+
+        let this_closed = closed.take();
+        match token.kind {
+            TokenKind::Template => {
+                if token.text.starts_with('}') {
+                    scopes.pop();
+                }
+                if token.text.ends_with("${") {
+                    scopes.push(Scope::Template);
+                }
+                continue;
+            }
+            TokenKind::Ident => {
+                match token.text {
+                    // skip `x.class` and `{class: ...}`
+                    "class" if !member && !tokens.get(idx + 1).is_some_and(|x| x.is(":")) => {
+                        class_depth = Some(scopes.len());
+                        expect_name = true;
+                    }
+                    "case" | "default" if scope == Scope::Switch => case_label = true,
+                    "do" if !member && !tokens.get(idx + 1).is_some_and(|x| x.is("{")) => {
+                        braceless_do.push(scopes.len())
+                    }
+                    "while" if do_tail => {
+                        braceless_do.pop();
+                    }
+                    "var" | "let" | "const" if !member => {
+                        declaring = Some(scopes.len());
+                        expect_name = true;
+                    }
+                    "function" if !member => expect_name = true,
+                    _ => {}
+                }
+                continue;
+            }
+            TokenKind::Punct => {}
+            _ => continue,
+        }
+        match token.text {
+            ":" if case_label => {
+                case_label = false;
+                // the statements of the case follow the colon
+                closed = Some(Scope::Switch);
+            }
+            "," if declaring == Some(scopes.len()) => expect_name = true,
+            ";" if declaring == Some(scopes.len()) => declaring = None,
+            "(" => scopes.push(Scope::Paren(idx)),
+            "[" => scopes.push(Scope::Bracket),
+            ")" | "]" => {
+                if let Some(Scope::Paren(open)) = scopes.pop() {
+                    matching[idx] = open;
+                    closed = Some(Scope::Paren(open));
+                }
+            }
+            "{" => {
+                let kind = if class_depth == Some(scopes.len()) {
+                    class_depth = None;
+                    Scope::Class
+                } else {
+                    classify_brace(prev, scope, this_closed, &tokens)
+                };
+                if kind.has_statements() {
+                    // declarations cannot go before the first case of a switch
+                    let start = match kind {
+                        Scope::Function => Some(function_body_start(&tokens, idx)),
+                        Scope::Switch => None,
+                        _ => Some(token.start + 1),
+                    };
+                    sites.scopes.push(BindingScope {
+                        parent: bindings.last().copied(),
+                        names: parameter_names(&tokens, idx, kind, &matching),
+                        start,
+                    });
+                    bindings.push(sites.scopes.len() - 1);
+                }
+                if kind == Scope::Function {
+                    let (name, line) = function_name(&tokens, idx, &matching);
+                    open_functions.push((scopes.len(), sites.functions.len()));
+                    sites.functions.push(FunctionSite {
+                        name,
+                        line,
+                        body_start: function_body_start(&tokens, idx),
+                        body_end: None,
+                        scope: sites.scopes.len() - 1,
+                    });
+                }
+                scopes.push(kind);
+            }
+            "}" if scopes.len() > 1 => {
+                closed = scopes.pop();
+                if closed.is_some_and(Scope::has_statements) && bindings.len() > 1 {
+                    bindings.pop();
+                }
+                if open_functions.last().is_some_and(|x| x.0 == scopes.len()) {
+                    let (_, function) = open_functions.pop().unwrap();
+                    sites.functions[function].body_end = Some(token.start);
+                }
+            }
+            _ => {}
+        }
+        if declaring.is_some_and(|x| x > scopes.len()) {
+            declaring = None;
+        }
+    }
+    // the first statement follows the directives
+    sites.scopes[0].start = sites
+        .statements
+        .iter()
+        .find(|x| x.scope == 0)
+        .map(|x| x.pos);
+    sites
+}
+
+/// Checks if a statement starts at the token so that code can go first.
+fn starts_statement(
+    token: &Token<'_>,
+    prev: Option<&Token<'_>>,
+    closed: Option<Scope>,
+    case_label: bool,
+    tokens: &[Token<'_>],
+) -> bool {
+    if token.kind != TokenKind::Ident
+        || case_label
+        || NON_STATEMENT_KEYWORDS.contains(&token.text)
+        || token.text.starts_with('#')
+    {
+        return false;
+    }
+    let prev = match prev {
+        None => return true,
+        Some(prev) => prev,
+    };
+    match prev.kind {
+        TokenKind::Punct => match prev.text {
+            ";" | "{" => true,
+            // `do { ... } while (x)`
+            "}" => token.text != "while",
+            ":" => closed == Some(Scope::Switch),
+            ")" => {
+                token.newline_before
+                    && match closed {
+                        Some(Scope::Paren(open)) => !is_control_paren(tokens, open),
+                        _ => false,
+                    }
+            }
+            "]" => token.newline_before,
+            // a line break before the operator makes it a prefix one
+            "++" | "--" => token.newline_before && !prev.newline_before,
+            _ => false,
+        },
+        TokenKind::Ident => token.newline_before && !prev.is_keyword(),
+        TokenKind::Template => token.newline_before && prev.text.ends_with('`'),
+        TokenKind::String | TokenKind::Number | TokenKind::Regex => token.newline_before,
+    }
+}
+
+/// Checks if the parenthesis at the index belongs to a control statement.
+fn is_control_paren(tokens: &[Token<'_>], open: usize) -> bool {
+    open.checked_sub(1).is_some_and(|idx| {
+        let token = &tokens[idx];
+        token.kind == TokenKind::Ident
+            && matches!(
+                token.text,
+                "if" | "for" | "while" | "with" | "switch" | "catch" | "await"
+            )
+    })
+}
+
+/// Decides what an opening brace starts from the tokens before it.
+fn classify_brace(
+    prev: Option<&Token<'_>>,
+    scope: Scope,
+    closed: Option<Scope>,
+    tokens: &[Token<'_>],
+) -> Scope {
+    let prev = match prev {
+        None => return Scope::Block,
+        Some(prev) => prev,
+    };
+    if prev.kind == TokenKind::Ident {
+        return match prev.text {
+            "else" | "try" | "finally" | "do" | "catch" => Scope::Block,
+            "static" if scope == Scope::Class => Scope::Block,
+            _ => Scope::Object,
+        };
+    }
+    if prev.kind != TokenKind::Punct {
+        return Scope::Object;
+    }
+    match prev.text {
+        "=>" => Scope::Function,
+        ")" => match closed {
+            Some(Scope::Paren(open)) if is_control_paren(tokens, open) => {
+                if tokens[open - 1].text == "switch" {
+                    Scope::Switch
+                } else {
+                    Scope::Block
+                }
+            }
+            _ => Scope::Function,
+        },
+        ";" | "{" if scope.has_statements() => Scope::Block,
+        "}" if scope.has_statements() && closed != Some(Scope::Object) => Scope::Block,
+        ":" if closed == Some(Scope::Switch) => Scope::Block,
+        _ => Scope::Object,
+    }
+}
+
+/// Finds the name and line of the function whose body starts at the index.
+fn function_name(tokens: &[Token<'_>], brace: usize, matching: &[usize]) -> (String, u32) {
+    let anonymous = |line| ("<anonymous>".to_string(), line);
+    let ident = |idx: usize| {
+        tokens
+            .get(idx)
+            .filter(|x| x.kind == TokenKind::Ident && !x.is_keyword())
+            .map(|x| x.text.to_string())
+    };
+    // the name a function expression is assigned to
+    let assigned = |mut idx: Option<usize>| {
+        if idx.is_some_and(|x| tokens[x].is("async")) {
+            idx = idx.and_then(|x| x.checked_sub(1));
+        }
+        match idx {
+            Some(idx) if tokens[idx].is("=") || tokens[idx].is(":") => {
+                idx.checked_sub(1).and_then(ident)
+            }
+            _ => None,
+        }
+    };
+
+    let prev = match brace.checked_sub(1) {
+        Some(prev) => prev,
+        None => return anonymous(tokens[brace].line),
+    };
+    if tokens[prev].is("=>") {
+        let params = match prev.checked_sub(1) {
+            Some(idx) if tokens[idx].is(")") => matching[idx],
+            Some(idx) => idx,
+            None => return anonymous(tokens[brace].line),
+        };
+        let line = tokens[params].line;
+        return assigned(params.checked_sub(1)).map_or_else(|| anonymous(line), |x| (x, line));
+    }
+
+    let open = matching[prev];
+    let line = tokens[open].line;
+    let mut before = open.checked_sub(1);
+    if before.is_some_and(|x| tokens[x].is("*")) {
+        before = before.and_then(|x| x.checked_sub(1));
+    }
+    match before {
+        Some(idx) if tokens[idx].is("function") => {
+            let mut idx = idx.checked_sub(1);
+            if idx.is_some_and(|x| tokens[x].is("async")) {
+                idx = idx.and_then(|x| x.checked_sub(1));
+            }
+            assigned(idx).map_or_else(|| anonymous(line), |x| (x, line))
+        }
+        Some(idx) => {
+            // declarations and methods are named before the parameters
+            ident(idx).map_or_else(|| anonymous(line), |x| (x, line))
+        }
+        None => anonymous(line),
+    }
+}
+
+/// Returns where code at the start of a function goes, after the directives.
+fn function_body_start(tokens: &[Token<'_>], brace: usize) -> usize {
+    let mut pos = tokens[brace].start + 1;
+    let mut idx = brace + 1;
+    while let Some(token) = tokens.get(idx) {
+        if token.kind != TokenKind::String {
+            break;
+        }
+        match tokens.get(idx + 1) {
+            Some(next) if next.is(";") => {
+                pos = next.start + 1;
+                idx += 2;
+            }
+            Some(next)
+                if next.is("}") || (next.newline_before && next.kind != TokenKind::Punct) =>
+            {
+                // the code goes on the line of the next statement
+                pos = next.start;
+                idx += 1;
+            }
+            _ => break,
+        }
+    }
+    pos
+}
+
+/// Returns the parameters of the function or catch clause whose body starts
+/// at the index.
+///
+/// Only plain identifiers are returned, destructured parameters are skipped.
+fn parameter_names(
+    tokens: &[Token<'_>],
+    brace: usize,
+    kind: Scope,
+    matching: &[usize],
+) -> Vec<String> {
+    let ident = |token: &Token<'_>| token.kind == TokenKind::Ident && !token.is_keyword();
+    let close = match brace.checked_sub(1) {
+        Some(prev) if kind == Scope::Function && tokens[prev].is("=>") => match prev.checked_sub(1)
+        {
+            Some(idx) if tokens[idx].is(")") => idx,
+            Some(idx) if ident(&tokens[idx]) => return vec![tokens[idx].text.to_string()],
+            _ => return Vec::new(),
+        },
+        Some(prev) if tokens[prev].is(")") => prev,
+        _ => return Vec::new(),
+    };
+    let open = matching[close];
+    if kind != Scope::Function && !open.checked_sub(1).is_some_and(|x| tokens[x].is("catch")) {
+        return Vec::new();
+    }
+
+    let mut rv = Vec::new();
+    let mut depth = 0usize;
+    for idx in open + 1..close {
+        let token = &tokens[idx];
+        match token.text {
+            "(" | "[" | "{" if token.kind == TokenKind::Punct => depth += 1,
+            ")" | "]" | "}" if token.kind == TokenKind::Punct => depth = depth.saturating_sub(1),
+            _ if depth == 0 && ident(token) => {
+                let before = &tokens[idx - 1];
+                if before.is("(") || before.is(",") || before.is("...") {
+                    rv.push(token.text.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    rv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_sites;
+
+    #[test]
+    fn test_binding_scopes() {
+        let sites = find_sites(
+            "const a = 1, b = 2;\nfunction f(x, { y }, ...z) {\n  let c = x;\n  try {\n    g();\n  } catch (e) {\n    h(e);\n  }\n}\nconst k = (m) => {\n  return m;\n};\n",
+        );
+        let names = |line: u32| {
+            let statement = sites.statements.iter().find(|x| x.line == line).unwrap();
+            sites.visible_names(statement.scope)
+        };
+        assert_eq!(names(1), ["a", "b", "f", "k"]);
+        assert_eq!(names(3), ["x", "z", "c", "a", "b", "f", "k"]);
+        assert_eq!(names(7), ["e", "x", "z", "c", "a", "b", "f", "k"]);
+        assert_eq!(names(11), ["m", "a", "b", "f", "k"]);
+
+        let functions = sites
+            .functions
+            .iter()
+            .map(|x| (x.name.as_str(), x.line, x.body_end.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(functions, [("f", 2, true), ("k", 10, true)]);
+    }
+
+    #[test]
+    fn test_scope_starts() {
+        let code = "\"use strict\";\nfunction f() {\n  \"use strict\";\n  switch (x) {\n    case 1: {\n      g();\n    }\n  }\n}\n";
+        let sites = find_sites(code);
+        let starts = sites
+            .scopes
+            .iter()
+            .map(|x| {
+                x.start
+                    .map(|pos| code[pos..].trim_start().split('(').next().unwrap())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            starts,
+            [Some("function f"), Some("switch "), None, Some("g")]
+        );
+    }
+
+    fn statement_lines(code: &str) -> Vec<u32> {
+        find_sites(code).statements.iter().map(|x| x.line).collect()
+    }
+
+    #[test]
+    fn test_tokenizer_edge_cases() {
+        // division and regular expressions containing braces
+        assert_eq!(
+            statement_lines("let a = b / c / d;\nlet r = /}/g;\nfoo();\n"),
+            [1, 2, 3]
+        );
+        // braces in strings, comments and template expressions
+        assert_eq!(
+            statement_lines("const s = '{';\n// }\n/* { */\nconst t = `${ {a: 1}.a }}`;\nfoo();\n"),
+            [1, 4, 5]
+        );
+        assert_eq!(
+            statement_lines("const t = `a\n${`b${c}`}\n`;\nfoo();\n"),
+            [1, 4]
+        );
+        // object literals are not blocks
+        assert_eq!(
+            statement_lines("const o = {\n  a: 1,\n  b: 2,\n};\nif (x) {\n  y();\n}\n"),
+            [1, 5, 6]
+        );
+        assert_eq!(
+            statement_lines("class A {\n  m() {\n    z();\n  }\n  static {\n    w();\n  }\n}\n"),
+            [1, 3, 6]
+        );
+        assert_eq!(
+            statement_lines("switch (x) {\n  case 1:\n    a();\n  default:\n    b();\n}\n"),
+            [1, 3, 5]
+        );
+    }
+
+    #[test]
+    fn test_automatic_semicolons() {
+        // a line starting with a parenthesis continues the call
+        assert_eq!(statement_lines("let x = a\n(b)\nfoo()\n"), [1, 3]);
+        // a statement after a call on a new line
+        assert_eq!(statement_lines("foo(a)\nbar()\n"), [1, 2]);
+        // the bodies of control statements without braces are skipped
+        assert_eq!(statement_lines("if (x)\n  foo()\nbar()\n"), [1, 3]);
+        assert_eq!(statement_lines("if (x) a()\nelse\n  b()\nc()\n"), [1, 4]);
+        // operators and arrows continue the expression on the next line
+        assert_eq!(statement_lines("const y = a +\n  b;\nfoo();\n"), [1, 3]);
+        assert_eq!(
+            statement_lines("const f = (x) =>\n  x + 1;\ng();\n"),
+            [1, 3]
+        );
+        // prefix operators on a new line belong to the next statement
+        assert_eq!(statement_lines("a\n++b\nc\n"), [1, 3]);
+        // the `while` of a `do` loop without braces is not a statement
+        assert_eq!(statement_lines("do x++;\nwhile (y);\nz();\n"), [1, 3]);
+        // the value of a `return` on the next line is ambiguous
+        assert_eq!(
+            statement_lines("function f() {\n  return\n  x;\n}\n"),
+            [1, 2]
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Strings interned per context so that converting them again is cheap.
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+use worthless_quickjs_sys::{JSContext, JSValue, WL_JS_DupValue, WL_JS_FreeValue};
+
+use crate::context::Context;
+use crate::primitive::Primitive;
+use crate::value::Value;
+
+/// The number of strings kept per context.
+const MAX_STRINGS: usize = 1024;
+
+/// The length in bytes up to which strings are interned.
+const MAX_STRING_LEN: usize = 256;
+
+thread_local! {
+    static STRINGS: RefCell<HashMap<usize, Strings>> = RefCell::new(HashMap::new());
+}
+
+/// The strings of a context, evicting the least recently used one when full.
+#[derive(Default)]
+struct Strings {
+    values: HashMap<Box<str>, (JSValue, u64)>,
+    by_use: BTreeMap<u64, Box<str>>,
+    clock: u64,
+}
+
+impl Strings {
+    /// Returns the value of a string and marks it as used.
+    fn get(&mut self, s: &str) -> Option<JSValue> {
+        let (raw, last_use) = self.values.get_mut(s)?;
+        let key = self.by_use.remove(last_use)?;
+        self.clock += 1;
+        *last_use = self.clock;
+        self.by_use.insert(self.clock, key);
+        Some(*raw)
+    }
+
+    /// Adds a string and returns the value it evicted.
+    fn insert(&mut self, s: &str, raw: JSValue) -> Option<JSValue> {
+        let evicted = if self.values.len() >= MAX_STRINGS {
+            self.by_use
+                .pop_first()
+                .and_then(|(_, key)| self.values.remove(&key))
+                .map(|(raw, _)| raw)
+        } else {
+            None
+        };
+        self.clock += 1;
+        self.values.insert(s.into(), (raw, self.clock));
+        self.by_use.insert(self.clock, s.into());
+        evicted
+    }
+}
+
+/// Returns the interned string value, creating it on first use.
+///
+/// Long strings are not interned and a new value is returned for them.
+pub(crate) fn intern(ctx: &Context, s: &str) -> Value {
+    if s.len() > MAX_STRING_LEN {
+        return Value::from_primitive(ctx, Primitive::Str(s));
+    }
+
+    let (value, evicted) = STRINGS.with(|strings| {
+        let mut strings = strings.borrow_mut();
+        let strings = strings.entry(ctx.as_raw() as usize).or_default();
+        if let Some(raw) = strings.get(s) {
+            let value =
+                unsafe { Value::from_raw_unchecked(ctx, WL_JS_DupValue(ctx.as_raw(), raw)) };
+            return (value, None);
+        }
+        let value = Value::from_primitive(ctx, Primitive::Str(s));
+        let raw = unsafe { WL_JS_DupValue(ctx.as_raw(), value.as_raw()) };
+        (value, strings.insert(s, raw))
+    });
+
+    // freed outside of the borrow in case this ends up running a finalizer
+    if let Some(raw) = evicted {
+        unsafe { WL_JS_FreeValue(ctx.as_raw(), raw) };
+    }
+    value
+}
+
+/// Returns the number of strings interned with a context.
+pub(crate) fn len(ctx: &Context) -> usize {
+    STRINGS.with(|strings| {
+        strings
+            .borrow()
+            .get(&(ctx.as_raw() as usize))
+            .map_or(0, |strings| strings.values.len())
+    })
+}
+
+/// Releases the strings interned with a context.
+///
+/// This must happen before the context is freed.
+pub(crate) fn forget(ctx: *mut JSContext) {
+    let strings = STRINGS.with(|strings| strings.borrow_mut().remove(&(ctx as usize)));
+    for (raw, _) in strings
+        .into_iter()
+        .flat_map(|strings| strings.values.into_values())
+    {
+        unsafe { WL_JS_FreeValue(ctx, raw) };
+    }
+}
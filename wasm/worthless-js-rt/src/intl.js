@@ -0,0 +1,554 @@
+// A minimal `Intl` with `NumberFormat` and `DateTimeFormat` for a handful of
+// locales.  QuickJS has no `Intl` at all, this covers the common cases of
+// formatting numbers, currencies, percentages and dates for display.  Unknown
+// locales fall back to their language and then to `en-US`.  Dates are always
+// formatted in UTC which is the only time zone a WASI guest knows about.
+(function (global) {
+  "use strict";
+
+  const NBSP = "\u00a0";
+  const NNBSP = "\u202f";
+
+  const EN_MONTHS = [
+    "January", "February", "March", "April", "May", "June", "July",
+    "August", "September", "October", "November", "December",
+  ];
+  const EN_WEEKDAYS = [
+    "Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday",
+  ];
+  const EN = {
+    decimal: ".",
+    group: ",",
+    minGroup: 1,
+    currency: "¤#",
+    percent: "#%",
+    months: EN_MONTHS,
+    shortMonths: EN_MONTHS.map((x) => x.slice(0, 3)),
+    weekdays: EN_WEEKDAYS,
+    shortWeekdays: EN_WEEKDAYS.map((x) => x.slice(0, 3)),
+    hour12: false,
+    padHour: true,
+    am: "AM",
+    pm: "PM",
+    dateTimeSep: ", ",
+    weekdaySep: ", ",
+  };
+
+  // Dates are assembled from fields in the listed order.  The text after a
+  // field is only emitted if another field follows unless `keepSuffix` is
+  // set for languages where it belongs to the field.
+  const LOCALES = {
+    "en-US": Object.assign({}, EN, {
+      hour12: true,
+      padHour: false,
+      numericDate: [["month", "/"], ["day", "/"], ["year", ""]],
+      textDate: [["month", " "], ["day", ", "], ["year", ""]],
+      dateStyles: { short: { year: "2-digit", month: "numeric", day: "numeric" } },
+    }),
+    "en-GB": Object.assign({}, EN, {
+      numericDate: [["day", "/"], ["month", "/"], ["year", ""]],
+      padDate: true,
+      textDate: [["day", " "], ["month", " "], ["year", ""]],
+      currencySymbols: { USD: "US$", JPY: "JP¥" },
+    }),
+    "de-DE": Object.assign({}, EN, {
+      decimal: ",",
+      group: ".",
+      currency: "#" + NBSP + "¤",
+      percent: "#" + NBSP + "%",
+      months: [
+        "Januar", "Februar", "März", "April", "Mai", "Juni", "Juli",
+        "August", "September", "Oktober", "November", "Dezember",
+      ],
+      shortMonths: [
+        "Jan.", "Feb.", "März", "Apr.", "Mai", "Juni", "Juli",
+        "Aug.", "Sept.", "Okt.", "Nov.", "Dez.",
+      ],
+      weekdays: [
+        "Sonntag", "Montag", "Dienstag", "Mittwoch", "Donnerstag", "Freitag",
+        "Samstag",
+      ],
+      shortWeekdays: ["So.", "Mo.", "Di.", "Mi.", "Do.", "Fr.", "Sa."],
+      numericDate: [["day", "."], ["month", "."], ["year", ""]],
+      textDate: [["day", ". "], ["month", " "], ["year", ""]],
+      dateStyles: {
+        medium: { year: "numeric", month: "2-digit", day: "2-digit" },
+        short: { year: "2-digit", month: "2-digit", day: "2-digit" },
+      },
+    }),
+    "fr-FR": Object.assign({}, EN, {
+      decimal: ",",
+      group: NNBSP,
+      currency: "#" + NBSP + "¤",
+      percent: "#" + NBSP + "%",
+      months: [
+        "janvier", "février", "mars", "avril", "mai", "juin", "juillet",
+        "août", "septembre", "octobre", "novembre", "décembre",
+      ],
+      shortMonths: [
+        "janv.", "févr.", "mars", "avr.", "mai", "juin", "juil.",
+        "août", "sept.", "oct.", "nov.", "déc.",
+      ],
+      weekdays: [
+        "dimanche", "lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi",
+      ],
+      shortWeekdays: ["dim.", "lun.", "mar.", "mer.", "jeu.", "ven.", "sam."],
+      numericDate: [["day", "/"], ["month", "/"], ["year", ""]],
+      padDate: true,
+      textDate: [["day", " "], ["month", " "], ["year", ""]],
+      currencySymbols: { USD: "$US", JPY: "JPY" },
+      dateTimeSep: " ",
+      styleDateTimeSep: ", ",
+      weekdaySep: " ",
+    }),
+    "es-ES": Object.assign({}, EN, {
+      decimal: ",",
+      group: ".",
+      minGroup: 2,
+      currency: "#" + NBSP + "¤",
+      percent: "#" + NBSP + "%",
+      months: [
+        "enero", "febrero", "marzo", "abril", "mayo", "junio", "julio",
+        "agosto", "septiembre", "octubre", "noviembre", "diciembre",
+      ],
+      shortMonths: [
+        "ene", "feb", "mar", "abr", "may", "jun", "jul",
+        "ago", "sept", "oct", "nov", "dic",
+      ],
+      weekdays: [
+        "domingo", "lunes", "martes", "miércoles", "jueves", "viernes", "sábado",
+      ],
+      shortWeekdays: ["dom", "lun", "mar", "mié", "jue", "vie", "sáb"],
+      currencySymbols: { USD: "US$", JPY: "JPY" },
+      padHour: false,
+      numericDate: [["day", "/"], ["month", "/"], ["year", ""]],
+      textDate: [["day", " de "], ["month", " de "], ["year", ""]],
+      shortTextDate: [["day", " "], ["month", " "], ["year", ""]],
+      dateStyles: { short: { year: "2-digit", month: "numeric", day: "numeric" } },
+    }),
+    "ja-JP": Object.assign({}, EN, {
+      months: EN_MONTHS.map((_, idx) => idx + 1 + "月"),
+      shortMonths: EN_MONTHS.map((_, idx) => idx + 1 + "月"),
+      weekdays: ["日曜日", "月曜日", "火曜日", "水曜日", "木曜日", "金曜日", "土曜日"],
+      shortWeekdays: ["日", "月", "火", "水", "木", "金", "土"],
+      currencySymbols: { JPY: "￥" },
+      padHour: false,
+      am: "午前",
+      pm: "午後",
+      numericDate: [["year", "/"], ["month", "/"], ["day", ""]],
+      textDate: [["year", "年"], ["month", ""], ["day", "日"]],
+      keepSuffix: true,
+      dateTimeSep: " ",
+      weekdaySep: "",
+      weekdayLast: true,
+      dateStyles: {
+        medium: { year: "numeric", month: "2-digit", day: "2-digit" },
+        short: { year: "numeric", month: "2-digit", day: "2-digit" },
+      },
+    }),
+  };
+  const DEFAULT_LOCALE = "en-US";
+
+  const CURRENCY_SYMBOLS = { USD: "$", EUR: "€", GBP: "£", JPY: "¥" };
+  const CURRENCY_DIGITS = { JPY: 0 };
+
+  function resolveLocale(locales) {
+    const requested = locales === undefined ? [] : [].concat(locales);
+    for (const locale of requested) {
+      const tag = String(locale);
+      for (const known of Object.keys(LOCALES)) {
+        if (known.toLowerCase() === tag.toLowerCase()) {
+          return known;
+        }
+      }
+      const language = tag.split("-")[0].toLowerCase();
+      for (const known of Object.keys(LOCALES)) {
+        if (known.split("-")[0] === language) {
+          return known;
+        }
+      }
+    }
+    return DEFAULT_LOCALE;
+  }
+
+  function supportedLocalesOf(locales) {
+    const requested = locales === undefined ? [] : [].concat(locales);
+    return requested
+      .map(String)
+      .filter((tag) => resolveLocale(tag) !== DEFAULT_LOCALE || /^en\b/i.test(tag));
+  }
+
+  function getOption(options, name, allowed, fallback) {
+    let value = options[name];
+    if (value === undefined) {
+      return fallback;
+    }
+    value = String(value);
+    if (allowed && allowed.indexOf(value) < 0) {
+      throw new RangeError(`Value ${value} out of range for ${name}`);
+    }
+    return value;
+  }
+
+  function getDigits(options, name, min, max, fallback) {
+    const value = options[name];
+    if (value === undefined) {
+      return fallback;
+    }
+    const number = Math.floor(Number(value));
+    if (!(number >= min && number <= max)) {
+      throw new RangeError(`${name} value is out of range.`);
+    }
+    return number;
+  }
+
+  function groupDigits(digits, data) {
+    if (digits.length < 4 + data.minGroup - 1) {
+      return digits;
+    }
+    let rv = "";
+    for (let idx = 0; idx < digits.length; idx++) {
+      if (idx > 0 && (digits.length - idx) % 3 === 0) {
+        rv += data.group;
+      }
+      rv += digits[idx];
+    }
+    return rv;
+  }
+
+  class NumberFormat {
+    constructor(locales, options) {
+      options = options === undefined ? {} : Object(options);
+      const locale = resolveLocale(locales);
+      const style = getOption(options, "style", ["decimal", "percent", "currency"], "decimal");
+      let currency;
+      let currencyDisplay;
+      let fractionDigits = [0, 3];
+      if (style === "currency") {
+        if (options.currency === undefined) {
+          throw new TypeError("Currency code is required with currency style.");
+        }
+        currency = String(options.currency).toUpperCase();
+        currencyDisplay = getOption(options, "currencyDisplay", ["symbol", "narrowSymbol", "code"], "symbol");
+        const digits = currency in CURRENCY_DIGITS ? CURRENCY_DIGITS[currency] : 2;
+        fractionDigits = [digits, digits];
+      } else if (style === "percent") {
+        fractionDigits = [0, 0];
+      }
+      const minimumFractionDigits = getDigits(options, "minimumFractionDigits", 0, 20, fractionDigits[0]);
+      const maximumFractionDigits = getDigits(
+        options,
+        "maximumFractionDigits",
+        minimumFractionDigits,
+        20,
+        Math.max(minimumFractionDigits, fractionDigits[1])
+      );
+      this._options = {
+        locale,
+        numberingSystem: "latn",
+        style,
+        currency,
+        currencyDisplay,
+        minimumIntegerDigits: getDigits(options, "minimumIntegerDigits", 1, 21, 1),
+        minimumFractionDigits,
+        maximumFractionDigits,
+        useGrouping: options.useGrouping === undefined ? true : Boolean(options.useGrouping),
+      };
+      if (style !== "currency") {
+        delete this._options.currency;
+        delete this._options.currencyDisplay;
+      }
+      this.format = this.format.bind(this);
+    }
+
+    format(value) {
+      const opts = this._options;
+      const data = LOCALES[opts.locale];
+      let number = Number(value);
+      if (opts.style === "percent") {
+        number *= 100;
+      }
+      const negative = number < 0 || Object.is(number, -0);
+      number = Math.abs(number);
+      let body;
+      if (Number.isNaN(number)) {
+        body = "NaN";
+      } else if (!Number.isFinite(number)) {
+        body = "∞";
+      } else {
+        let [int, frac = ""] = number.toFixed(opts.maximumFractionDigits).split(".");
+        while (frac.length > opts.minimumFractionDigits && frac.endsWith("0")) {
+          frac = frac.slice(0, -1);
+        }
+        int = int.padStart(opts.minimumIntegerDigits, "0");
+        if (opts.useGrouping) {
+          int = groupDigits(int, data);
+        }
+        body = frac ? int + data.decimal + frac : int;
+      }
+      let pattern = "#";
+      if (opts.style === "percent") {
+        pattern = data.percent;
+      } else if (opts.style === "currency") {
+        let symbol = opts.currency;
+        const symbols = Object.assign({}, CURRENCY_SYMBOLS, data.currencySymbols);
+        if (opts.currencyDisplay !== "code" && opts.currency in symbols) {
+          symbol = symbols[opts.currency];
+        }
+        pattern = data.currency.replace("¤", symbol);
+        // ICU separates symbols that end in a letter from the number
+        if (/[A-Za-z]$/.test(symbol) && pattern.startsWith(symbol)) {
+          pattern = pattern.replace(symbol, symbol + NBSP);
+        }
+      }
+      const rv = pattern.replace("#", body);
+      return negative && !Number.isNaN(number) && number !== 0 ? "-" + rv : rv;
+    }
+
+    resolvedOptions() {
+      return Object.assign({}, this._options);
+    }
+
+    static supportedLocalesOf(locales) {
+      return supportedLocalesOf(locales);
+    }
+  }
+
+  const DATE_STYLES = {
+    full: { weekday: "long", year: "numeric", month: "long", day: "numeric" },
+    long: { year: "numeric", month: "long", day: "numeric" },
+    medium: { year: "numeric", month: "short", day: "numeric" },
+    short: { year: "numeric", month: "numeric", day: "numeric" },
+  };
+  const TIME_STYLES = {
+    full: { hour: "numeric", minute: "2-digit", second: "2-digit", timeZoneName: "short" },
+    long: { hour: "numeric", minute: "2-digit", second: "2-digit", timeZoneName: "short" },
+    medium: { hour: "numeric", minute: "2-digit", second: "2-digit" },
+    short: { hour: "numeric", minute: "2-digit" },
+  };
+  const UTC_NAMES = ["UTC", "ETC/UTC", "GMT", "ETC/GMT"];
+
+  function pad2(value) {
+    return String(value).padStart(2, "0");
+  }
+
+  function joinFields(pattern, values, keepSuffix) {
+    const present = pattern.filter(([field]) => values[field] !== undefined);
+    return present
+      .map(([field, suffix], idx) =>
+        values[field] + (keepSuffix || idx < present.length - 1 ? suffix : "")
+      )
+      .join("");
+  }
+
+  class DateTimeFormat {
+    constructor(locales, options) {
+      options = options === undefined ? {} : Object(options);
+      const locale = resolveLocale(locales);
+      const data = LOCALES[locale];
+      const timeZone = options.timeZone === undefined ? "UTC" : String(options.timeZone);
+      if (UTC_NAMES.indexOf(timeZone.toUpperCase()) < 0) {
+        throw new RangeError(`Unsupported time zone specified ${timeZone}`);
+      }
+      const dateStyle = getOption(options, "dateStyle", Object.keys(DATE_STYLES));
+      const timeStyle = getOption(options, "timeStyle", Object.keys(TIME_STYLES));
+      let fields = {};
+      if (dateStyle !== undefined || timeStyle !== undefined) {
+        const dateStyles = Object.assign({}, DATE_STYLES, data.dateStyles);
+        Object.assign(fields, dateStyles[dateStyle], TIME_STYLES[timeStyle]);
+      } else {
+        const numeric = ["numeric", "2-digit"];
+        const text = ["long", "short", "narrow"];
+        fields = {
+          weekday: getOption(options, "weekday", text),
+          year: getOption(options, "year", numeric),
+          month: getOption(options, "month", numeric.concat(text)),
+          day: getOption(options, "day", numeric),
+          hour: getOption(options, "hour", numeric),
+          minute: getOption(options, "minute", numeric),
+          second: getOption(options, "second", numeric),
+          timeZoneName: getOption(options, "timeZoneName", ["short", "long"]),
+        };
+        if (Object.keys(fields).every((key) => fields[key] === undefined)) {
+          Object.assign(fields, DATE_STYLES.short);
+        }
+      }
+      for (const key of Object.keys(fields)) {
+        if (fields[key] === undefined) {
+          delete fields[key];
+        }
+      }
+      const hour12 = options.hour12 === undefined ? data.hour12 : Boolean(options.hour12);
+      // the style presets pad the hour where the locale does
+      if (timeStyle !== undefined && !hour12 && data.padHour) {
+        fields.hour = "2-digit";
+      }
+      this._options = Object.assign(
+        { locale, calendar: "gregory", numberingSystem: "latn", timeZone: "UTC" },
+        fields.hour !== undefined ? { hourCycle: hour12 ? "h12" : "h23", hour12 } : {},
+        fields,
+        dateStyle !== undefined ? { dateStyle } : {},
+        timeStyle !== undefined ? { timeStyle } : {}
+      );
+      this.format = this.format.bind(this);
+    }
+
+    format(date) {
+      const value = date === undefined ? Date.now() : Number(date);
+      if (!Number.isFinite(value)) {
+        throw new RangeError("Invalid time value");
+      }
+      const d = new Date(value);
+      const opts = this._options;
+      const data = LOCALES[opts.locale];
+      const parts = [];
+
+      const textMonth = opts.month === "long" || opts.month === "short" || opts.month === "narrow";
+      const values = {};
+      if (opts.year !== undefined) {
+        const year = d.getUTCFullYear();
+        values.year = opts.year === "2-digit" ? pad2(year % 100) : String(year);
+      }
+      if (opts.month !== undefined) {
+        const month = d.getUTCMonth();
+        if (opts.month === "long") {
+          values.month = data.months[month];
+        } else if (opts.month === "short") {
+          values.month = data.shortMonths[month];
+        } else if (opts.month === "narrow") {
+          values.month = data.months[month][0];
+        } else {
+          values.month = opts.month === "2-digit" || data.padDate ? pad2(month + 1) : String(month + 1);
+        }
+      }
+      if (opts.day !== undefined) {
+        const day = d.getUTCDate();
+        values.day = opts.day === "2-digit" || (data.padDate && !textMonth) ? pad2(day) : String(day);
+      }
+      const shortMonth = opts.month === "short" || opts.month === "narrow";
+      let dateText = joinFields(
+        textMonth ? (shortMonth && data.shortTextDate) || data.textDate : data.numericDate,
+        values,
+        textMonth && data.keepSuffix
+      );
+      if (opts.weekday !== undefined) {
+        const weekday = d.getUTCDay();
+        const name =
+          opts.weekday === "long"
+            ? data.weekdays[weekday]
+            : opts.weekday === "short"
+            ? data.shortWeekdays[weekday]
+            : data.weekdays[weekday][0];
+        if (!dateText) {
+          dateText = name;
+        } else if (data.weekdayLast) {
+          dateText = textMonth ? dateText + name : dateText + "(" + name + ")";
+        } else {
+          dateText = name + data.weekdaySep + dateText;
+        }
+      }
+      if (dateText) {
+        parts.push(dateText);
+      }
+
+      if (opts.hour !== undefined || opts.minute !== undefined || opts.second !== undefined) {
+        const time = [];
+        let period;
+        if (opts.hour !== undefined) {
+          let hour = d.getUTCHours();
+          if (opts.hour12) {
+            period = hour < 12 ? data.am : data.pm;
+            hour = hour % 12 || 12;
+          }
+          time.push(opts.hour === "2-digit" ? pad2(hour) : String(hour));
+        }
+        if (opts.minute !== undefined) {
+          const minute = d.getUTCMinutes();
+          time.push(time.length || opts.minute === "2-digit" ? pad2(minute) : String(minute));
+        }
+        if (opts.second !== undefined) {
+          const second = d.getUTCSeconds();
+          time.push(time.length || opts.second === "2-digit" ? pad2(second) : String(second));
+        }
+        let timeText = time.join(":");
+        if (period !== undefined) {
+          timeText = opts.locale === "ja-JP" ? period + timeText : timeText + " " + period;
+        }
+        if (opts.timeZoneName !== undefined) {
+          timeText += " UTC";
+        }
+        parts.push(timeText);
+      }
+      const styled = opts.dateStyle !== undefined && opts.timeStyle !== undefined;
+      return parts.join((styled && data.styleDateTimeSep) || data.dateTimeSep);
+    }
+
+    resolvedOptions() {
+      return Object.assign({}, this._options);
+    }
+
+    static supportedLocalesOf(locales) {
+      return supportedLocalesOf(locales);
+    }
+  }
+
+  function getCanonicalLocales(locales) {
+    const requested = locales === undefined ? [] : [].concat(locales);
+    return requested.map((tag) =>
+      String(tag)
+        .split("-")
+        .map((part, idx) =>
+          idx === 0 ? part.toLowerCase() : part.length === 2 ? part.toUpperCase() : part
+        )
+        .join("-")
+    );
+  }
+
+  const Intl = { NumberFormat, DateTimeFormat, getCanonicalLocales };
+  Object.defineProperty(Intl, Symbol.toStringTag, { value: "Intl" });
+  Object.defineProperty(global, "Intl", {
+    value: Intl,
+    writable: true,
+    configurable: true,
+  });
+
+  function defineMethod(proto, name, fn) {
+    Object.defineProperty(proto, name, {
+      value: fn,
+      writable: true,
+      configurable: true,
+    });
+  }
+  defineMethod(Number.prototype, "toLocaleString", function (locales, options) {
+    return new NumberFormat(locales, options).format(Number.prototype.valueOf.call(this));
+  });
+
+  // the date methods fill in the fields of their kind unless some are given
+  function withDefaults(options, dateFields, timeFields) {
+    options = options === undefined ? {} : Object(options);
+    const keys = ["weekday", "year", "month", "day", "hour", "minute", "second", "dateStyle", "timeStyle"];
+    if (keys.some((key) => options[key] !== undefined)) {
+      return options;
+    }
+    const defaults = {};
+    if (dateFields) {
+      Object.assign(defaults, { year: "numeric", month: "numeric", day: "numeric" });
+    }
+    if (timeFields) {
+      Object.assign(defaults, { hour: "numeric", minute: "2-digit", second: "2-digit" });
+    }
+    return Object.assign({}, options, defaults);
+  }
+  function defineDateMethod(name, dateFields, timeFields) {
+    defineMethod(Date.prototype, name, function (locales, options) {
+      const time = Date.prototype.getTime.call(this);
+      if (Number.isNaN(time)) {
+        return "Invalid Date";
+      }
+      return new DateTimeFormat(locales, withDefaults(options, dateFields, timeFields)).format(time);
+    });
+  }
+  defineDateMethod("toLocaleString", true, true);
+  defineDateMethod("toLocaleDateString", true, false);
+  defineDateMethod("toLocaleTimeString", false, true);
+});
@@ -1,14 +1,79 @@
 use worthless_quickjs_sys::JS_GetException;
 use worthless_quickjs_sys::JS_IsError;
 
+use crate::actor::OwnedValue;
 use crate::context::Context;
+use crate::source;
 use crate::value::{Value, ValueKind};
 
 /// Represents a JavaScript exception.
-#[derive(Debug)]
+///
+/// The exception is copied out of the runtime when it is caught, so errors
+/// holding it can move between threads.  Next to the message and the stack
+/// the data of the thrown value is kept, eg: custom properties of errors.
+#[derive(Debug, Clone)]
 pub struct JsException {
-    pub(crate) msg: String,
-    pub(crate) stack: Option<String>,
+    msg: String,
+    stack: Option<String>,
+    value: Option<OwnedValue>,
+    // sources are registered with the context so the excerpt is kept
+    excerpt: Option<String>,
+}
+
+/// A single frame of a JavaScript stack trace.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StackFrame {
+    /// The name of the function or `<anonymous>`.
+    pub function: String,
+    /// The filename if the frame is not native code.
+    pub filename: Option<String>,
+    /// The line number if known.
+    pub lineno: Option<u32>,
+    /// The column number if known.
+    pub colno: Option<u32>,
+}
+
+impl StackFrame {
+    /// Parses a single line of a QuickJS stack trace.
+    ///
+    /// The format is `at function (filename:line:column)` where the location
+    /// can also be `native` and the column is not always available.
+    pub(crate) fn parse(line: &str) -> Option<StackFrame> {
+        let line = line.trim().strip_prefix("at ")?;
+        let (function, location) = match line.rfind(" (") {
+            Some(idx) if line.ends_with(')') => (&line[..idx], &line[idx + 2..line.len() - 1]),
+            _ => (line, ""),
+        };
+        let mut frame = StackFrame {
+            function: function.to_string(),
+            filename: None,
+            lineno: None,
+            colno: None,
+        };
+        if location.is_empty() || location == "native" {
+            return Some(frame);
+        }
+
+        // split off the line and column number from the end
+        let mut filename = location;
+        let mut numbers = Vec::with_capacity(2);
+        while numbers.len() < 2 {
+            match filename
+                .rsplit_once(':')
+                .map(|(rest, num)| (rest, num.parse()))
+            {
+                Some((rest, Ok(num))) => {
+                    numbers.insert(0, num);
+                    filename = rest;
+                }
+                _ => break,
+            }
+        }
+        frame.filename = Some(filename.to_string());
+        frame.lineno = numbers.first().copied();
+        frame.colno = numbers.get(1).copied();
+        Some(frame)
+    }
 }
 
 impl JsException {
@@ -18,28 +83,113 @@ impl JsException {
     }
 
     /// Returns the stringified stack if available
+    ///
+    /// The stack is cut to `Error.stackTraceLimit` frames.
     pub fn stack(&self) -> Option<&str> {
         self.stack.as_deref()
     }
+
+    /// Returns a copy of the thrown value.
+    ///
+    /// This gives access to custom properties of errors.  It is `None` if
+    /// the value could not be copied, eg: as it holds functions.
+    pub fn value(&self) -> Option<&OwnedValue> {
+        self.value.as_ref()
+    }
+
+    /// Returns the parsed frames of the stack.
+    ///
+    /// The innermost frame comes first.  Lines of the stack that cannot be
+    /// parsed are skipped.
+    pub fn frames(&self) -> Vec<StackFrame> {
+        self.stack()
+            .unwrap_or("")
+            .lines()
+            .filter_map(StackFrame::parse)
+            .collect()
+    }
+
+    /// Renders the exception for humans.
+    ///
+    /// Below the message the lines around the innermost location with a
+    /// source registered with [`Context::register_source`] are shown with
+    /// a caret under the column, followed by the stack.
+    pub fn render(&self) -> String {
+        source::render_exception(self, self.excerpt.as_deref())
+    }
+
+    /// Creates an exception from a thrown value.
+    ///
+    /// This is useful for values that were not thrown but are known to
+    /// represent errors such as promise rejection reasons.
+    pub fn from_value(exc_val: &Value) -> JsException {
+        let mut rv = JsException {
+            msg: exc_val.to_string_lossy().to_string(),
+            stack: stack(exc_val),
+            value: OwnedValue::from_value(exc_val).ok(),
+            excerpt: None,
+        };
+        rv.excerpt = source::excerpt(exc_val.ctx(), &rv.frames());
+        rv
+    }
 }
 
 impl JsException {
     pub(crate) unsafe fn from_raw(ctx: &Context) -> JsException {
         let exc_val = unsafe { Value::from_raw_unchecked(ctx, JS_GetException(ctx.as_raw())) };
-        let msg = exc_val.to_string_lossy().to_string();
-        let mut stack = None;
-        let is_error = unsafe { JS_IsError(ctx.as_raw(), exc_val.as_raw()) } != 0;
-        if is_error {
-            if let Ok(stack_value) = exc_val.get_property("stack") {
-                if stack_value.kind() != ValueKind::Undefined {
-                    stack.replace(stack_value.to_string_lossy().to_string());
-                }
-            }
-        }
+        JsException::from_value(&exc_val)
+    }
+}
 
-        JsException {
-            msg: msg.to_string(),
-            stack,
-        }
+/// Returns the stack of an error cut to `Error.stackTraceLimit` frames.
+fn stack(exc_val: &Value) -> Option<String> {
+    let ctx = exc_val.ctx();
+    if unsafe { JS_IsError(ctx.as_raw(), exc_val.as_raw()) } == 0 {
+        return None;
+    }
+    let stack = match exc_val.get_property("stack") {
+        Ok(stack) if stack.kind() != ValueKind::Undefined => stack.to_string_lossy().to_string(),
+        _ => return None,
+    };
+    match ctx.stack_trace_limit() {
+        Some(limit) => Some(stack.split_inclusive('\n').take(limit).collect()),
+        None => Some(stack),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StackFrame;
+
+    #[test]
+    fn test_parse_frames() {
+        assert_eq!(
+            StackFrame::parse("    at foo (<script>:3:5)"),
+            Some(StackFrame {
+                function: "foo".into(),
+                filename: Some("<script>".into()),
+                lineno: Some(3),
+                colno: Some(5),
+            })
+        );
+        assert_eq!(
+            StackFrame::parse("    at <anonymous> (bundle.js:42)"),
+            Some(StackFrame {
+                function: "<anonymous>".into(),
+                filename: Some("bundle.js".into()),
+                lineno: Some(42),
+                colno: None,
+            })
+        );
+        assert_eq!(
+            StackFrame::parse("    at JSON.parse (native)"),
+            Some(StackFrame {
+                function: "JSON.parse".into(),
+                filename: None,
+                lineno: None,
+                colno: None,
+            })
+        );
+        assert_eq!(StackFrame::parse("not a frame"), None);
     }
 }
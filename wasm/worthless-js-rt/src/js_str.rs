@@ -0,0 +1,143 @@
+use std::ffi::c_char;
+use std::ops::Deref;
+use std::{fmt, ptr, slice, str};
+
+use worthless_quickjs_sys::{JSValue, JS_FreeCString, JS_ToCStringLen2};
+
+use crate::context::Context;
+use crate::error::Error;
+
+/// A string borrowed from the engine.
+///
+/// The underlying C string is freed when the guard is dropped.  Strings that
+/// are not valid UTF-8 (lone surrogates) only exist in repaired form which is
+/// the only case where the string is copied.
+pub struct JsStr<'a> {
+    ctx: &'a Context,
+    ptr: *const c_char,
+    len: usize,
+    repaired: Option<String>,
+}
+
+impl<'a> JsStr<'a> {
+    /// Converts a raw value into a string with `JS_ToCStringLen2`.
+    pub(crate) unsafe fn from_raw(ctx: &'a Context, raw: JSValue) -> Result<JsStr<'a>, Error> {
+        let mut len: usize = 0;
+        let ptr = JS_ToCStringLen2(ctx.as_raw(), &mut len, raw, 0);
+        // this is needed because some values such as symbols for some
+        // reason cannot be converted to strings.
+        if ptr.is_null() {
+            return Err(ctx.last_error());
+        }
+        let rv = JsStr {
+            ctx,
+            ptr,
+            len,
+            repaired: None,
+        };
+        str::from_utf8(rv.as_bytes()).map_err(Error::Utf8Error)?;
+        Ok(rv)
+    }
+
+    /// Like [`from_raw`](Self::from_raw) but repairs invalid unicode.
+    ///
+    /// Values that cannot be converted result in an empty string.
+    pub(crate) unsafe fn from_raw_lossy(ctx: &'a Context, raw: JSValue) -> JsStr<'a> {
+        let mut len: usize = 0;
+        let ptr = JS_ToCStringLen2(ctx.as_raw(), &mut len, raw, 0);
+        if ptr.is_null() {
+            return JsStr {
+                ctx,
+                ptr: ptr::null(),
+                len: 0,
+                repaired: None,
+            };
+        }
+        let mut rv = JsStr {
+            ctx,
+            ptr,
+            len,
+            repaired: None,
+        };
+        if str::from_utf8(rv.as_bytes()).is_err() {
+            rv.repaired = Some(String::from_utf8_lossy(rv.as_bytes()).into_owned());
+        }
+        rv
+    }
+
+    /// Returns the raw bytes as produced by the engine.
+    ///
+    /// This is not necessarily valid UTF-8 if the string was repaired.
+    pub fn as_bytes(&self) -> &[u8] {
+        if self.ptr.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+        }
+    }
+
+    /// Returns the string.
+    pub fn as_str(&self) -> &str {
+        match self.repaired {
+            Some(ref repaired) => repaired,
+            None => unsafe { str::from_utf8_unchecked(self.as_bytes()) },
+        }
+    }
+
+    /// Returns `true` if invalid unicode had to be replaced.
+    pub fn is_lossy(&self) -> bool {
+        self.repaired.is_some()
+    }
+}
+
+impl<'a> Drop for JsStr<'a> {
+    fn drop(&mut self) {
+        if !self.ptr.is_null() {
+            unsafe { JS_FreeCString(self.ctx.as_raw(), self.ptr) }
+        }
+    }
+}
+
+impl<'a> Deref for JsStr<'a> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<'a> AsRef<str> for JsStr<'a> {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<'a> fmt::Debug for JsStr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<'a> fmt::Display for JsStr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl<'a, 'b> PartialEq<JsStr<'b>> for JsStr<'a> {
+    fn eq(&self, other: &JsStr<'b>) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl<'a> PartialEq<str> for JsStr<'a> {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<'a, 'b> PartialEq<&'b str> for JsStr<'a> {
+    fn eq(&self, other: &&'b str) -> bool {
+        self.as_str() == *other
+    }
+}
@@ -1,16 +1,47 @@
 //! Worthless-JS-RT is a QuickJS based runtime environment for WASI.  It's provided as
 //! a crate with a basic API that can be wrapped.
+mod actor;
+mod array_buffer;
 mod builtins;
+mod channel;
 mod context;
+mod coverage;
+mod debugger;
 mod error;
+mod heap;
+mod instrument;
+mod intern;
 mod js_exception;
+mod js_str;
+mod module;
 mod primitive;
+mod profiler;
 mod runtime;
+mod source;
 mod value;
+mod value_ref;
 
+pub use self::actor::{ActorFunction, OwnedValue, RuntimeActor};
+pub use self::channel::{MessageChannel, MessagePort};
 pub use self::context::Context;
-pub use self::error::Error;
-pub use self::js_exception::JsException;
+pub use self::coverage::{Coverage, FunctionCoverage, LineCoverage, ScriptCoverage};
+pub use self::debugger::{Debugger, Pause, PauseHandler, PauseReason, Resume};
+pub use self::error::{ActorError, Error, ResultExt};
+pub use self::heap::{Allocations, ClassUsage, HeapSnapshot, StringUsage};
+pub use self::js_exception::{JsException, StackFrame};
+pub use self::js_str::JsStr;
+pub use self::module::SyntheticModule;
 pub use self::primitive::Primitive;
-pub use self::runtime::Runtime;
-pub use self::value::{IntoValue, PropertiesIter, Value, ValueKind};
+pub use self::profiler::{FunctionStats, Profile, Profiler, Sample};
+pub use self::runtime::{MemoryUsage, PromiseRejectionTracker, Runtime};
+pub use self::value::{
+    DebugValue, FromValue, HostFunction, IntoValue, PropertiesIter, PropertyFilter, Value,
+    ValueKind, DEFAULT_DEBUG_DEPTH,
+};
+pub use self::value_ref::ValueRef;
+#[cfg(feature = "derive")]
+pub use worthless_macros::{function, FromValue, IntoValue};
+
+// lets the tests use the derives which refer to this crate by name
+#[cfg(all(test, feature = "derive"))]
+extern crate self as worthless_js_rt;
@@ -0,0 +1,51 @@
+// Freezes the shared intrinsics so that plugin code cannot pollute the
+// prototypes host provided objects rely on.  Freezing is transitive over
+// properties, accessors and prototypes like `harden` in SES.  The global
+// object itself stays extensible so plugins can still define globals.
+(function (global) {
+  "use strict";
+
+  const getProto = Object.getPrototypeOf;
+  const intrinsics = [
+    getProto(function* () {}),
+    getProto(async function () {}),
+    getProto(async function* () {}),
+    getProto([][Symbol.iterator]()),
+    getProto(new Map()[Symbol.iterator]()),
+    getProto(new Set()[Symbol.iterator]()),
+    getProto(""[Symbol.iterator]()),
+    getProto(/x/[Symbol.matchAll]("")),
+    getProto(Uint8Array),
+  ];
+
+  const seen = new WeakSet();
+  const pending = intrinsics.slice();
+  for (const key of Reflect.ownKeys(global)) {
+    const desc = Object.getOwnPropertyDescriptor(global, key);
+    // only the builtins are non enumerable, globals defined by the host or
+    // plugin code are left alone.
+    if (!desc.enumerable && "value" in desc && desc.value !== global) {
+      pending.push(desc.value);
+    }
+  }
+  while (pending.length > 0) {
+    const value = pending.pop();
+    if ((typeof value !== "object" && typeof value !== "function") || value === null) {
+      continue;
+    }
+    if (seen.has(value)) {
+      continue;
+    }
+    seen.add(value);
+    Object.freeze(value);
+    pending.push(getProto(value));
+    for (const key of Reflect.ownKeys(value)) {
+      const desc = Object.getOwnPropertyDescriptor(value, key);
+      if ("value" in desc) {
+        pending.push(desc.value);
+      } else {
+        pending.push(desc.get, desc.set);
+      }
+    }
+  }
+})
@@ -0,0 +1,175 @@
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::fmt;
+use std::rc::Rc;
+
+use worthless_quickjs_sys::{
+    JSContext, JSModuleDef, JS_AddModuleExport, JS_AtomToCString, JS_FreeAtom, JS_FreeCString,
+    JS_GetModuleName, JS_GetRuntime, JS_GetRuntimeOpaque, JS_NewCModule, JS_SetModuleExport,
+    JS_ThrowReferenceError, WL_JS_GetModuleDef,
+};
+
+use crate::context::Context;
+use crate::error::Error;
+use crate::runtime::RuntimeHandle;
+use crate::value::{throw_error, Value};
+
+type ExportFunc = dyn Fn(&Context) -> Result<Value, Error>;
+
+/// Provides the source of a module by its name.
+pub(crate) type SourceLoader = dyn Fn(&str) -> Option<String>;
+
+/// A module whose exports are provided from Rust.
+///
+/// Modules are registered with [`Runtime::register_module`](crate::Runtime::register_module)
+/// and can then be imported by name from module code, eg:
+/// `import { get } from "worthless:kv"`.  The exports are created once per
+/// context when the module is first imported.
+pub struct SyntheticModule {
+    name: String,
+    exports: Vec<(CString, Box<ExportFunc>)>,
+}
+
+impl fmt::Debug for SyntheticModule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyntheticModule")
+            .field("name", &self.name)
+            .field(
+                "exports",
+                &self.exports.iter().map(|x| &x.0).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl SyntheticModule {
+    /// Creates a module without exports.
+    pub fn new(name: &str) -> SyntheticModule {
+        SyntheticModule {
+            name: name.to_string(),
+            exports: Vec::new(),
+        }
+    }
+
+    /// Returns the name the module is imported by.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Adds an export whose value is created by a function.
+    ///
+    /// `default` exports the default binding.
+    pub fn export<F>(&mut self, name: &str, f: F) -> Result<&mut Self, Error>
+    where
+        F: Fn(&Context) -> Result<Value, Error> + 'static,
+    {
+        self.exports.push((CString::new(name)?, Box::new(f)));
+        Ok(self)
+    }
+
+    /// Adds a function export.
+    ///
+    /// Like [`Value::from_func`] this can only wrap zero sized functions.
+    pub fn export_func<F>(&mut self, name: &str, f: F) -> Result<&mut Self, Error>
+    where
+        F: Fn(&Context, &Value, &[Value]) -> Result<Value, Error> + Copy + 'static,
+    {
+        let func_name = name.to_string();
+        self.export(name, move |ctx| Value::from_func(ctx, &func_name, f))
+    }
+
+    /// Creates the module definition in a context.
+    unsafe fn define(&self, ctx: *mut JSContext) -> *mut JSModuleDef {
+        let name = match CString::new(self.name.as_str()) {
+            Ok(name) => name,
+            Err(_) => return std::ptr::null_mut(),
+        };
+        let m = JS_NewCModule(ctx, name.as_ptr(), Some(init_module));
+        if m.is_null() {
+            return m;
+        }
+        for (export, _) in &self.exports {
+            if JS_AddModuleExport(ctx, m, export.as_ptr()) < 0 {
+                return std::ptr::null_mut();
+            }
+        }
+        m
+    }
+
+    /// Sets the exports of the module definition.
+    unsafe fn init(&self, ctx: &Context, m: *mut JSModuleDef) -> Result<(), Error> {
+        for (export, f) in &self.exports {
+            let value = f(ctx)?;
+            if JS_SetModuleExport(ctx.as_raw(), m, export.as_ptr(), value.into_raw()) < 0 {
+                return Err(ctx.last_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+unsafe fn runtime_handle<'a>(ctx: *mut JSContext) -> Option<&'a RuntimeHandle> {
+    (JS_GetRuntimeOpaque(JS_GetRuntime(ctx)) as *const RuntimeHandle).as_ref()
+}
+
+/// Looks up a registered module by name.
+unsafe fn find_module(ctx: *mut JSContext, name: &CStr) -> Option<Rc<SyntheticModule>> {
+    let name = name.to_str().ok()?;
+    runtime_handle(ctx)?.modules.borrow().get(name).cloned()
+}
+
+/// Loads the source of a module with the source loader of the runtime.
+unsafe fn find_source(ctx: *mut JSContext, name: &CStr) -> Option<String> {
+    let name = name.to_str().ok()?;
+    // the loader is cloned out so it can import modules itself
+    let loader = runtime_handle(ctx)?.source_loader.borrow().clone()?;
+    loader(name)
+}
+
+/// The module loader of runtimes with synthetic modules or a source loader.
+pub(crate) unsafe extern "C" fn load_module(
+    ctx: *mut JSContext,
+    name: *const c_char,
+    _opaque: *mut c_void,
+) -> *mut JSModuleDef {
+    let module_name = CStr::from_ptr(name);
+    if let Some(module) = find_module(ctx, module_name) {
+        return module.define(ctx);
+    }
+    if let Some(source) = find_source(ctx, module_name) {
+        let borrowed = Context::borrow_raw_unchecked(ctx);
+        return match borrowed.compile_module(&source, &module_name.to_string_lossy(), false) {
+            Ok(func) => WL_JS_GetModuleDef(func.as_raw()),
+            Err(err) => {
+                throw_error(ctx, err);
+                std::ptr::null_mut()
+            }
+        };
+    }
+    JS_ThrowReferenceError(
+        ctx,
+        "could not load module '%s'\x00".as_ptr() as *const c_char,
+        name,
+    );
+    std::ptr::null_mut()
+}
+
+unsafe extern "C" fn init_module(raw_ctx: *mut JSContext, m: *mut JSModuleDef) -> i32 {
+    let atom = JS_GetModuleName(raw_ctx, m);
+    let name = JS_AtomToCString(raw_ctx, atom);
+    JS_FreeAtom(raw_ctx, atom);
+    if name.is_null() {
+        return -1;
+    }
+    let module = find_module(raw_ctx, CStr::from_ptr(name));
+    JS_FreeCString(raw_ctx, name);
+
+    let ctx = Context::borrow_raw_unchecked(raw_ctx);
+    match module.map(|module| module.init(&ctx, m)) {
+        Some(Ok(())) => 0,
+        Some(Err(err)) => {
+            throw_error(raw_ctx, err);
+            -1
+        }
+        None => -1,
+    }
+}
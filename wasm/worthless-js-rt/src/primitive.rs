@@ -1,3 +1,7 @@
+use std::borrow::Cow;
+
+use crate::js_str::JsStr;
+
 /// Alternative value representation on the Rust side.
 #[derive(Debug, PartialEq)]
 pub enum Primitive<'a> {
@@ -6,10 +10,21 @@ pub enum Primitive<'a> {
     Bool(bool),
     I32(i32),
     I64(i64),
+    /// Created as a number up to `Number.MAX_SAFE_INTEGER`, as a `BigInt`
+    /// beyond that so that no precision is lost.
+    U64(u64),
     F64(f64),
     Str(&'a str),
+    String(String),
     InvalidStr(String),
-    Symbol(&'a str),
+    JsStr(JsStr<'a>),
+    Symbol(JsStr<'a>),
+}
+
+impl From<()> for Primitive<'static> {
+    fn from(_value: ()) -> Primitive<'static> {
+        Primitive::Undefined
+    }
 }
 
 impl From<bool> for Primitive<'static> {
@@ -18,9 +33,33 @@ impl From<bool> for Primitive<'static> {
     }
 }
 
+impl From<u8> for Primitive<'static> {
+    fn from(value: u8) -> Primitive<'static> {
+        Primitive::I32(value.into())
+    }
+}
+
+impl From<u16> for Primitive<'static> {
+    fn from(value: u16) -> Primitive<'static> {
+        Primitive::I32(value.into())
+    }
+}
+
+impl From<u32> for Primitive<'static> {
+    fn from(value: u32) -> Primitive<'static> {
+        Primitive::I64(value.into())
+    }
+}
+
+impl From<u64> for Primitive<'static> {
+    fn from(value: u64) -> Primitive<'static> {
+        Primitive::U64(value)
+    }
+}
+
 impl From<usize> for Primitive<'static> {
     fn from(value: usize) -> Primitive<'static> {
-        Primitive::F64(value as f64)
+        Primitive::U64(value as u64)
     }
 }
 
@@ -47,3 +86,30 @@ impl<'a> From<&'a str> for Primitive<'a> {
         Primitive::Str(value)
     }
 }
+
+impl<'a> From<&'a String> for Primitive<'a> {
+    fn from(value: &'a String) -> Self {
+        Primitive::Str(value)
+    }
+}
+
+impl From<String> for Primitive<'static> {
+    fn from(value: String) -> Primitive<'static> {
+        Primitive::String(value)
+    }
+}
+
+impl<'a> From<Cow<'a, str>> for Primitive<'a> {
+    fn from(value: Cow<'a, str>) -> Self {
+        match value {
+            Cow::Borrowed(value) => Primitive::Str(value),
+            Cow::Owned(value) => Primitive::String(value),
+        }
+    }
+}
+
+impl From<char> for Primitive<'static> {
+    fn from(value: char) -> Primitive<'static> {
+        Primitive::String(value.to_string())
+    }
+}
@@ -0,0 +1,430 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::c_void;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use worthless_quickjs_sys::{JSRuntime, WL_JS_ClearInterruptHandler, WL_JS_SetInterruptHandler};
+
+use crate::context::Context;
+use crate::error::Error;
+use crate::js_exception::StackFrame;
+use crate::primitive::Primitive;
+use crate::value::Value;
+
+/// A stack that was recorded by the [`Profiler`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    /// The time since the profiler was started.
+    pub timestamp: Duration,
+    /// Indexes into [`Profile::frames`], the innermost frame comes first.
+    pub stack: Vec<usize>,
+}
+
+/// The samples of one function in a [`Profile`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionStats {
+    /// The name of the function or `<anonymous>`.
+    pub function: String,
+    /// The filename if the function is not native code.
+    pub filename: Option<String>,
+    /// The number of samples in which the function was running.
+    pub self_samples: usize,
+    /// The number of samples in which the function was on the stack.
+    pub total_samples: usize,
+}
+
+/// The result of a profiling session.
+///
+/// Identical frames are stored once and referenced by the samples by index.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    interval: Duration,
+    duration: Duration,
+    frames: Vec<StackFrame>,
+    samples: Vec<Sample>,
+}
+
+impl Profile {
+    /// Returns the interval the samples were taken at.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the time from starting to stopping the profiler.
+    ///
+    /// This includes the time no JavaScript code was running.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Returns the distinct frames of all samples.
+    pub fn frames(&self) -> &[StackFrame] {
+        &self.frames
+    }
+
+    /// Returns the samples in the order they were taken.
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    /// Returns the frames of a sample, the innermost frame comes first.
+    pub fn stack<'a>(&'a self, sample: &'a Sample) -> impl Iterator<Item = &'a StackFrame> + 'a {
+        sample.stack.iter().map(move |&idx| &self.frames[idx])
+    }
+
+    /// Aggregates the samples by function.
+    ///
+    /// Functions are identified by name and filename.  The functions that
+    /// were running the most come first.
+    pub fn functions(&self) -> Vec<FunctionStats> {
+        let mut rv: Vec<FunctionStats> = Vec::new();
+        let mut index = HashMap::new();
+        let mut seen = Vec::new();
+        for sample in &self.samples {
+            seen.clear();
+            for (depth, frame) in self.stack(sample).enumerate() {
+                let key = (frame.function.as_str(), frame.filename.as_deref());
+                let idx = *index.entry(key).or_insert_with(|| {
+                    rv.push(FunctionStats {
+                        function: frame.function.clone(),
+                        filename: frame.filename.clone(),
+                        self_samples: 0,
+                        total_samples: 0,
+                    });
+                    rv.len() - 1
+                });
+                if depth == 0 {
+                    rv[idx].self_samples += 1;
+                }
+                // recursive calls only count once per sample
+                if !seen.contains(&idx) {
+                    seen.push(idx);
+                    rv[idx].total_samples += 1;
+                }
+            }
+        }
+        rv.sort_by(|a, b| {
+            b.self_samples
+                .cmp(&a.self_samples)
+                .then(b.total_samples.cmp(&a.total_samples))
+        });
+        rv
+    }
+
+    /// Exports the samples as collapsed stacks.
+    ///
+    /// Every line holds the functions of a stack from the outermost to the
+    /// innermost separated by semicolons followed by the number of samples.
+    /// This is the input format of `flamegraph.pl`, inferno and speedscope.
+    pub fn to_collapsed(&self) -> String {
+        let mut counts = BTreeMap::new();
+        for sample in &self.samples {
+            let mut stack = String::new();
+            for frame in self.stack(sample).collect::<Vec<_>>().into_iter().rev() {
+                if !stack.is_empty() {
+                    stack.push(';');
+                }
+                stack.push_str(&function_label(frame).replace(';', ":"));
+            }
+            *counts.entry(stack).or_insert(0usize) += 1;
+        }
+        let mut rv = String::new();
+        for (stack, count) in counts {
+            writeln!(rv, "{} {}", stack, count).unwrap();
+        }
+        rv
+    }
+
+    /// Exports the samples in the speedscope file format.
+    ///
+    /// The profile is a sampled profile with the given name.  As the samples
+    /// are not evenly spaced, every sample weighs the microseconds until the
+    /// next sample was taken and the last one weighs the interval.  Frames
+    /// are merged by function, the line numbers of the samples are not
+    /// retained.
+    pub fn to_speedscope(&self, name: &str) -> String {
+        let mut frames = Vec::new();
+        let mut index = HashMap::new();
+        let stacks = self
+            .samples
+            .iter()
+            .map(|sample| {
+                let mut stack = self
+                    .stack(sample)
+                    .map(|frame| {
+                        let key = (frame.function.as_str(), frame.filename.as_deref());
+                        *index.entry(key).or_insert_with(|| {
+                            frames.push(key);
+                            frames.len() - 1
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                stack.reverse();
+                stack
+            })
+            .collect::<Vec<_>>();
+        let weights = self
+            .samples
+            .iter()
+            .enumerate()
+            .map(|(idx, sample)| match self.samples.get(idx + 1) {
+                Some(next) => next.timestamp.saturating_sub(sample.timestamp),
+                None => self.interval,
+            })
+            .map(|weight| weight.as_micros())
+            .collect::<Vec<_>>();
+        let start = self
+            .samples
+            .first()
+            .map_or(0, |sample| sample.timestamp.as_micros());
+
+        let mut rv = String::new();
+        rv.push_str(r#"{"$schema":"https://www.speedscope.app/file-format-schema.json","#);
+        rv.push_str(r#""shared":{"frames":["#);
+        for (idx, (function, filename)) in frames.iter().enumerate() {
+            if idx > 0 {
+                rv.push(',');
+            }
+            rv.push_str(r#"{"name":"#);
+            write_json_str(&mut rv, function);
+            if let Some(filename) = filename {
+                rv.push_str(r#","file":"#);
+                write_json_str(&mut rv, filename);
+            }
+            rv.push('}');
+        }
+        rv.push_str(r#"]},"profiles":[{"type":"sampled","name":"#);
+        write_json_str(&mut rv, name);
+        write!(
+            rv,
+            r#","unit":"microseconds","startValue":{},"endValue":{},"samples":["#,
+            start,
+            start + weights.iter().sum::<u128>()
+        )
+        .unwrap();
+        for (idx, stack) in stacks.iter().enumerate() {
+            if idx > 0 {
+                rv.push(',');
+            }
+            rv.push('[');
+            for (idx, frame) in stack.iter().enumerate() {
+                if idx > 0 {
+                    rv.push(',');
+                }
+                write!(rv, "{}", frame).unwrap();
+            }
+            rv.push(']');
+        }
+        rv.push_str(r#"],"weights":["#);
+        for (idx, weight) in weights.iter().enumerate() {
+            if idx > 0 {
+                rv.push(',');
+            }
+            write!(rv, "{}", weight).unwrap();
+        }
+        rv.push_str(r#"]}],"name":"#);
+        write_json_str(&mut rv, name);
+        rv.push_str(r#","activeProfileIndex":0,"exporter":"worthless-js-rt"}"#);
+        rv
+    }
+}
+
+/// Formats a frame as `function (filename)` for collapsed stacks.
+fn function_label(frame: &StackFrame) -> String {
+    match frame.filename {
+        Some(ref filename) => format!("{} ({})", frame.function, filename),
+        None => frame.function.clone(),
+    }
+}
+
+/// Writes a string as a quoted JSON string.
+pub(crate) fn write_json_str(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct ProfilerState {
+    ctx: Context,
+    error_ctor: Value,
+    interval: Duration,
+    started: Instant,
+    last_sample: Option<Instant>,
+    frame_index: HashMap<StackFrame, usize>,
+    profile: Profile,
+}
+
+impl ProfilerState {
+    fn sample(&mut self) {
+        let now = Instant::now();
+        if let Some(last_sample) = self.last_sample {
+            if now.duration_since(last_sample) < self.interval {
+                return;
+            }
+        }
+        self.last_sample = Some(now);
+
+        // calling `Error` directly records the stack without its own frame
+        let undefined = Value::from_primitive(&self.ctx, Primitive::Undefined);
+        let stack = match self
+            .error_ctor
+            .call(&undefined, [] as [Value; 0])
+            .and_then(|err| err.get_property("stack"))
+        {
+            Ok(stack) => stack,
+            Err(_) => return,
+        };
+        let stack = stack.to_string_lossy();
+        let mut sample = Sample {
+            timestamp: now.duration_since(self.started),
+            stack: Vec::new(),
+        };
+        for frame in stack.lines().filter_map(StackFrame::parse) {
+            let idx = match self.frame_index.get(&frame) {
+                Some(&idx) => idx,
+                None => {
+                    self.profile.frames.push(frame.clone());
+                    self.frame_index
+                        .insert(frame, self.profile.frames.len() - 1);
+                    self.profile.frames.len() - 1
+                }
+            };
+            sample.stack.push(idx);
+        }
+        if !sample.stack.is_empty() {
+            self.profile.samples.push(sample);
+        }
+    }
+}
+
+/// Records where JavaScript code spends its time by sampling the stack.
+///
+/// The profiler takes over the interrupt handler of the runtime which
+/// QuickJS invokes every few thousand instructions.  Whenever the interval
+/// has passed since the last sample, the current stack is recorded.  This
+/// means that the interval is a lower bound and that time spent in host
+/// functions or waiting between calls is not sampled.
+///
+/// Profiling is opt-in as every sample allocates an error to capture the
+/// stack.  Only one profiler can be active per runtime, dropping the
+/// profiler stops it and discards the samples.
+pub struct Profiler {
+    state: Box<RefCell<ProfilerState>>,
+}
+
+impl Profiler {
+    /// Starts sampling the JavaScript code of a runtime.
+    ///
+    /// The stacks are captured through the context, but code running in
+    /// other contexts of the runtime is sampled too.
+    pub fn start(ctx: &Context, interval: Duration) -> Result<Profiler, Error> {
+        let error_ctor = ctx.with_global(|global| global.get_property("Error"))?;
+        if !error_ctor.is_function() {
+            return Err(Error::InvalidArgument("Error is not a function".into()));
+        }
+        let state = Box::new(RefCell::new(ProfilerState {
+            ctx: ctx.clone(),
+            error_ctor,
+            interval,
+            started: Instant::now(),
+            last_sample: None,
+            frame_index: HashMap::new(),
+            profile: Profile {
+                interval,
+                ..Profile::default()
+            },
+        }));
+        // the state is boxed so its address stays stable while it's registered
+        unsafe {
+            WL_JS_SetInterruptHandler(
+                ctx.rt().as_raw(),
+                Some(interrupt_handler),
+                &*state as *const RefCell<ProfilerState> as *mut c_void,
+            );
+        }
+        Ok(Profiler { state })
+    }
+
+    /// Returns the number of samples taken so far.
+    pub fn sample_count(&self) -> usize {
+        self.state.borrow().profile.samples.len()
+    }
+
+    /// Stops the profiler and returns the samples.
+    pub fn stop(self) -> Profile {
+        let mut state = self.state.borrow_mut();
+        unsafe { WL_JS_ClearInterruptHandler(state.ctx.rt().as_raw()) };
+        let mut profile = std::mem::take(&mut state.profile);
+        profile.duration = state.started.elapsed();
+        profile
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        unsafe { WL_JS_ClearInterruptHandler(self.state.borrow().ctx.rt().as_raw()) };
+    }
+}
+
+unsafe extern "C" fn interrupt_handler(_rt: *mut JSRuntime, opaque: *mut c_void) -> i32 {
+    let state = unsafe { &*(opaque as *const RefCell<ProfilerState>) };
+    // a host function that inspects the profiler can run code that is
+    // interrupted, such samples are skipped.
+    if let Ok(mut state) = state.try_borrow_mut() {
+        state.sample();
+    }
+    // never interrupt the execution
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(function: &str) -> StackFrame {
+        StackFrame {
+            function: function.to_string(),
+            filename: Some("test.js".to_string()),
+            lineno: Some(1),
+            colno: Some(1),
+        }
+    }
+
+    #[test]
+    fn test_speedscope_weights() {
+        let profile = Profile {
+            interval: Duration::from_millis(1),
+            duration: Duration::from_millis(20),
+            frames: vec![frame("inner"), frame("outer")],
+            samples: vec![
+                Sample {
+                    timestamp: Duration::from_millis(2),
+                    stack: vec![0, 1],
+                },
+                Sample {
+                    timestamp: Duration::from_millis(3),
+                    stack: vec![1],
+                },
+                Sample {
+                    timestamp: Duration::from_millis(8),
+                    stack: vec![0, 1],
+                },
+            ],
+        };
+        let speedscope = profile.to_speedscope("test");
+        assert!(speedscope.contains(
+            r#""unit":"microseconds","startValue":2000,"endValue":9000,"samples":[[1,0],[1],[1,0]],"weights":[1000,5000,1000]"#
+        ));
+    }
+}
@@ -1,9 +1,63 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::c_void;
 use std::fmt;
+use std::mem::MaybeUninit;
+use std::ptr;
 use std::rc::Rc;
 
-use worthless_quickjs_sys::{JSRuntime, JS_FreeRuntime, JS_NewRuntime};
+use worthless_quickjs_sys::{
+    JSContext, JSMemoryUsage, JSRuntime, JSValue, JS_ComputeMemoryUsage, JS_ExecutePendingJob,
+    JS_FreeRuntime, JS_IsJobPending, JS_NewRuntime, JS_RunGC, JS_SetModuleLoaderFunc,
+    JS_SetRuntimeOpaque, WL_JS_SetPromiseRejectionTracker, WL_PromiseRejectionTrackerData,
+};
 
+use crate::context::Context;
 use crate::error::Error;
+use crate::heap::HeapSnapshot;
+use crate::module::{load_module, SourceLoader, SyntheticModule};
+use crate::value::Value;
+
+/// A function that is notified about promise rejections.
+///
+/// It's invoked with the promise, the rejection reason and a flag that
+/// indicates if the rejection is handled.  The tracker is invoked with
+/// `false` when a promise is rejected without a handler and again with
+/// `true` if a handler is attached later.
+pub type PromiseRejectionTracker = fn(&Context, &Value, &Value, bool);
+
+/// The memory usage of a runtime.
+///
+/// Sizes are in bytes.  This is a summary of what QuickJS tracks, the
+/// malloc size is the closest to the memory the runtime holds on to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// The bytes allocated by the runtime including allocator overhead.
+    pub malloc_size: u64,
+    /// The number of live allocations.
+    pub malloc_count: u64,
+    /// The bytes used by the runtime excluding allocator overhead.
+    pub memory_used_size: u64,
+    /// The number of live objects.
+    pub obj_count: u64,
+    /// The number of live strings.
+    pub str_count: u64,
+    /// The number of live atoms.
+    pub atom_count: u64,
+}
+
+impl From<JSMemoryUsage> for MemoryUsage {
+    fn from(usage: JSMemoryUsage) -> MemoryUsage {
+        MemoryUsage {
+            malloc_size: usage.malloc_size.max(0) as u64,
+            malloc_count: usage.malloc_count.max(0) as u64,
+            memory_used_size: usage.memory_used_size.max(0) as u64,
+            obj_count: usage.obj_count.max(0) as u64,
+            str_count: usage.str_count.max(0) as u64,
+            atom_count: usage.atom_count.max(0) as u64,
+        }
+    }
+}
 
 /// Wraps a QuickJS runtime.
 ///
@@ -14,8 +68,23 @@ pub struct Runtime {
     handle: Rc<RuntimeHandle>,
 }
 
-struct RuntimeHandle {
+pub(crate) struct RuntimeHandle {
     ptr: *mut JSRuntime,
+    pub(crate) modules: RefCell<BTreeMap<String, Rc<SyntheticModule>>>,
+    pub(crate) source_loader: RefCell<Option<Rc<SourceLoader>>>,
+    // QuickJS holds on to the tracker data while the tracker is installed
+    rejection_tracker: RefCell<Option<Box<WL_PromiseRejectionTrackerData>>>,
+}
+
+impl RuntimeHandle {
+    fn new(ptr: *mut JSRuntime) -> RuntimeHandle {
+        RuntimeHandle {
+            ptr,
+            modules: Default::default(),
+            source_loader: Default::default(),
+            rejection_tracker: Default::default(),
+        }
+    }
 }
 
 impl fmt::Debug for Runtime {
@@ -33,22 +102,157 @@ impl Runtime {
         }
 
         Ok(Runtime {
-            handle: Rc::new(RuntimeHandle { ptr }),
+            handle: Rc::new(RuntimeHandle::new(ptr)),
         })
     }
 
     /// Returns a runtime instance borrowing from a low-level runtime.
     pub(crate) unsafe fn borrow_raw_unchecked(rt: *mut JSRuntime) -> Runtime {
         // leak one refcount so that we don't hit the gc
-        let mut handle = Rc::new(RuntimeHandle { ptr: rt });
+        let mut handle = Rc::new(RuntimeHandle::new(rt));
         std::mem::forget(Rc::clone(&mut handle));
         Runtime { handle }
     }
 
+    /// Returns `true` if there are jobs (eg: promise reactions) waiting to run.
+    pub fn is_job_pending(&self) -> bool {
+        unsafe { JS_IsJobPending(self.as_raw()) != 0 }
+    }
+
+    /// Runs a single pending job.
+    ///
+    /// Returns `true` if a job was executed or `false` if the job queue was
+    /// empty.  If the job raised an exception the error is returned.
+    pub fn run_pending_job(&self) -> Result<bool, Error> {
+        let mut raw_ctx: *mut JSContext = ptr::null_mut();
+        let rv = unsafe { JS_ExecutePendingJob(self.as_raw(), &mut raw_ctx) };
+        if rv < 0 {
+            let ctx = unsafe { Context::borrow_raw_unchecked(raw_ctx) };
+            Err(ctx.last_error())
+        } else {
+            Ok(rv > 0)
+        }
+    }
+
+    /// Runs pending jobs until the job queue is empty.
+    ///
+    /// Returns the number of jobs that were executed.  Execution stops at
+    /// the first job that raises an exception.
+    pub fn run_pending_jobs(&self) -> Result<usize, Error> {
+        let mut count = 0;
+        while self.run_pending_job()? {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Sets or clears the promise rejection tracker.
+    pub fn set_promise_rejection_tracker(&self, tracker: Option<PromiseRejectionTracker>) {
+        // the WL_ tracker passes references to the promise and reason that we own
+        unsafe extern "C" fn trampoline(
+            raw_ctx: *mut JSContext,
+            promise: JSValue,
+            reason: JSValue,
+            is_handled: i32,
+            opaque: *mut c_void,
+        ) {
+            let tracker: PromiseRejectionTracker = unsafe { std::mem::transmute(opaque) };
+            let ctx = Context::borrow_raw_unchecked(raw_ctx);
+            let promise = unsafe { Value::from_raw_unchecked(&ctx, promise) };
+            let reason = unsafe { Value::from_raw_unchecked(&ctx, reason) };
+            tracker(&ctx, &promise, &reason, is_handled != 0);
+        }
+
+        let mut data = tracker.map(|tracker| {
+            Box::new(WL_PromiseRejectionTrackerData {
+                tracker: Some(trampoline),
+                opaque: tracker as *mut c_void,
+            })
+        });
+        let ptr = data
+            .as_mut()
+            .map_or(ptr::null_mut(), |data| &mut **data as *mut _);
+        unsafe { WL_JS_SetPromiseRejectionTracker(self.as_raw(), ptr) };
+        // the previous data is only freed once QuickJS let go of it
+        *self.handle.rejection_tracker.borrow_mut() = data;
+    }
+
+    /// Runs the garbage collector.
+    ///
+    /// QuickJS frees most values by reference counting, this collects the
+    /// cycles that reference counting cannot free.
+    pub fn run_gc(&self) {
+        unsafe { JS_RunGC(self.as_raw()) }
+    }
+
+    /// Computes the current memory usage.
+    ///
+    /// This walks all objects of the runtime so it should not be called in
+    /// a hot loop.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let mut usage = MaybeUninit::<JSMemoryUsage>::uninit();
+        unsafe {
+            JS_ComputeMemoryUsage(self.as_raw(), usage.as_mut_ptr());
+            usage.assume_init().into()
+        }
+    }
+
+    /// Takes a snapshot of what the heap holds to diagnose memory leaks.
+    ///
+    /// This runs the garbage collector and walks the objects reachable
+    /// from the global objects of all contexts of the runtime, it is a lot
+    /// slower than [`Runtime::memory_usage`].
+    pub fn heap_snapshot(&self) -> Result<HeapSnapshot, Error> {
+        HeapSnapshot::capture(self)
+    }
+
+    /// Registers a synthetic module that module code can import.
+    ///
+    /// Registering a module with the same name replaces it for contexts that
+    /// did not import it yet.
+    pub fn register_module(&self, module: SyntheticModule) {
+        self.handle
+            .modules
+            .borrow_mut()
+            .insert(module.name().to_string(), Rc::new(module));
+        self.install_module_loader();
+    }
+
+    /// Sets the function that provides the source of modules that are not
+    /// synthetic.
+    ///
+    /// The function receives the module name with relative imports resolved
+    /// against the importing module, eg: `./util.js` imported by
+    /// `tests/a.js` is `tests/util.js`.  Importing fails if it returns
+    /// `None`.  The modules are instrumented for coverage and the debugger
+    /// like code evaluated with [`Context::eval_module`].
+    pub fn set_module_source_loader<F>(&self, f: F)
+    where
+        F: Fn(&str) -> Option<String> + 'static,
+    {
+        *self.handle.source_loader.borrow_mut() = Some(Rc::new(f));
+        self.install_module_loader();
+    }
+
+    fn install_module_loader(&self) {
+        // the module loader finds the registered modules via the opaque.
+        // The handle is reference counted so its address is stable.
+        unsafe {
+            JS_SetRuntimeOpaque(self.as_raw(), Rc::as_ptr(&self.handle) as *mut c_void);
+            JS_SetModuleLoaderFunc(self.as_raw(), None, Some(load_module), ptr::null_mut());
+        }
+    }
+
     /// Returns the internal pointer
     pub(crate) fn as_raw(&self) -> *mut JSRuntime {
         self.handle.ptr
     }
+
+    /// Returns the number of handles that keep the runtime alive.
+    #[cfg(test)]
+    pub(crate) fn ref_count(&self) -> usize {
+        Rc::strong_count(&self.handle)
+    }
 }
 
 impl Drop for RuntimeHandle {
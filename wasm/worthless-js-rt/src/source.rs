@@ -0,0 +1,174 @@
+//! Sources registered with a context to render exceptions with excerpts.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use worthless_quickjs_sys::JSContext;
+
+use crate::context::Context;
+use crate::js_exception::{JsException, StackFrame};
+
+/// The number of lines shown before and after the offending line.
+const CONTEXT_LINES: u32 = 2;
+
+thread_local! {
+    static SOURCES: RefCell<HashMap<usize, HashMap<String, Rc<str>>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Registers the source of a file with a context.
+pub(crate) fn register(ctx: &Context, filename: &str, source: &str) {
+    SOURCES.with(|sources| {
+        sources
+            .borrow_mut()
+            .entry(ctx.as_raw() as usize)
+            .or_default()
+            .insert(filename.to_string(), Rc::from(source));
+    });
+}
+
+/// Looks up the source of a file registered with a context.
+pub(crate) fn get(ctx: &Context, filename: &str) -> Option<Rc<str>> {
+    SOURCES.with(|sources| {
+        sources
+            .borrow()
+            .get(&(ctx.as_raw() as usize))?
+            .get(filename)
+            .cloned()
+    })
+}
+
+/// Forgets the sources of a context that is freed.
+pub(crate) fn forget(ctx: *mut JSContext) {
+    SOURCES.with(|sources| sources.borrow_mut().remove(&(ctx as usize)));
+}
+
+/// Returns the location of a frame.
+///
+/// Syntax errors have frames without a function like `at file.js:3:5`
+/// which end up as the function name of the frame.
+fn frame_location(frame: &StackFrame) -> Option<(&str, u32, Option<u32>)> {
+    if let (Some(filename), Some(lineno)) = (frame.filename.as_deref(), frame.lineno) {
+        return Some((filename, lineno, frame.colno));
+    }
+    let (rest, last) = frame.function.rsplit_once(':')?;
+    let last = last.parse().ok()?;
+    match rest.rsplit_once(':').map(|(name, x)| (name, x.parse())) {
+        Some((filename, Ok(lineno))) => Some((filename, lineno, Some(last))),
+        _ => Some((rest, last, None)),
+    }
+}
+
+/// Renders the excerpt of the innermost frame with a registered source.
+pub(crate) fn excerpt(ctx: &Context, frames: &[StackFrame]) -> Option<String> {
+    for frame in frames {
+        let (filename, lineno, colno) = match frame_location(frame) {
+            Some(location) => location,
+            None => continue,
+        };
+        if let Some(source) = get(ctx, filename) {
+            let mut rv = String::new();
+            render_excerpt(&mut rv, filename, &source, lineno, colno);
+            return Some(rv);
+        }
+    }
+    None
+}
+
+/// Renders an exception with an excerpt of the source it was thrown from.
+pub(crate) fn render_exception(exc: &JsException, excerpt: Option<&str>) -> String {
+    let mut rv = exc.message().to_string();
+    if let Some(excerpt) = excerpt {
+        rv.push('\n');
+        rv.push_str(excerpt);
+    }
+    if let Some(stack) = exc.stack() {
+        for line in stack.lines() {
+            write!(rv, "\n{}", line).ok();
+        }
+    }
+    rv
+}
+
+/// Renders the lines around a location with a caret under the column.
+///
+/// Lines and columns start at 1.  The caret is left out if the column is
+/// not known and nothing is rendered for lines beyond the source.
+pub(crate) fn render_excerpt(
+    out: &mut String,
+    filename: &str,
+    source: &str,
+    lineno: u32,
+    colno: Option<u32>,
+) {
+    let lines = source.lines().collect::<Vec<_>>();
+    if lineno == 0 || lineno as usize > lines.len() {
+        return;
+    }
+    let first = lineno.saturating_sub(CONTEXT_LINES).max(1);
+    let last = (lineno + CONTEXT_LINES).min(lines.len() as u32);
+    let width = last.to_string().len();
+    let gutter = " ".repeat(width);
+
+    match colno {
+        Some(colno) => writeln!(out, "{}--> {}:{}:{}", gutter, filename, lineno, colno),
+        None => writeln!(out, "{}--> {}:{}", gutter, filename, lineno),
+    }
+    .ok();
+    writeln!(out, "{} |", gutter).ok();
+    for current in first..=last {
+        let line = lines[current as usize - 1];
+        writeln!(out, "{:>width$} | {}", current, line, width = width).ok();
+        if let (true, Some(colno)) = (current == lineno, colno) {
+            // keep tabs so the caret lines up with the code above
+            let indent = line
+                .chars()
+                .take(colno.saturating_sub(1) as usize)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect::<String>();
+            writeln!(out, "{} | {}^", gutter, indent).ok();
+        }
+    }
+    write!(out, "{} |", gutter).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_excerpt() {
+        let source = "function a() {\n\tlet x = 1;\n\tthrow new Error('boom');\n}\na();\n";
+        let mut out = String::new();
+        render_excerpt(&mut out, "a.js", source, 3, Some(2));
+        assert_eq!(
+            out,
+            " --> a.js:3:2\n  |\n1 | function a() {\n2 | \tlet x = 1;\n\
+             3 | \tthrow new Error('boom');\n  | \t^\n4 | }\n5 | a();\n  |"
+        );
+
+        let mut out = String::new();
+        render_excerpt(&mut out, "a.js", source, 1, None);
+        assert_eq!(
+            out,
+            " --> a.js:1\n  |\n1 | function a() {\n2 | \tlet x = 1;\n3 | \tthrow new Error('boom');\n  |"
+        );
+
+        let mut out = String::new();
+        render_excerpt(&mut out, "a.js", source, 42, Some(1));
+        assert_eq!(out, "");
+    }
+
+    #[test]
+    fn test_frame_location() {
+        let frame = StackFrame::parse("    at a.js:3:5").unwrap();
+        assert_eq!(frame_location(&frame), Some(("a.js", 3, Some(5))));
+        let frame = StackFrame::parse("    at a.js:3").unwrap();
+        assert_eq!(frame_location(&frame), Some(("a.js", 3, None)));
+        let frame = StackFrame::parse("    at foo (a.js:7:1)").unwrap();
+        assert_eq!(frame_location(&frame), Some(("a.js", 7, Some(1))));
+        let frame = StackFrame::parse("    at JSON.parse (native)").unwrap();
+        assert_eq!(frame_location(&frame), None);
+    }
+}
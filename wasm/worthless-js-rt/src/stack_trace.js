@@ -0,0 +1,61 @@
+// Installs the V8 stack trace API that Node-oriented libraries expect:
+// `Error.captureStackTrace` and `Error.stackTraceLimit`.  The stacks keep the
+// QuickJS format, that is one `at function (location)` line per frame without
+// a leading message line.
+(function () {
+  "use strict";
+
+  const FRAME_NAME = /^\s*at (.*?)(?: \(.*\))?$/;
+
+  function stackTraceLimit(Error) {
+    const limit = Error.stackTraceLimit;
+    return typeof limit === "number" && limit >= 0 ? limit : Infinity;
+  }
+
+  return function (global) {
+    const Error = global.Error;
+    if (!("stackTraceLimit" in Error)) {
+      Error.stackTraceLimit = Infinity;
+    }
+    if (typeof Error.captureStackTrace === "function") {
+      return;
+    }
+
+    function captureStackTrace(target, constructorOpt) {
+      if (target === null || (typeof target !== "object" && typeof target !== "function")) {
+        throw new TypeError("invalid target for captureStackTrace");
+      }
+      // the first frame is this function
+      let frames = (new Error().stack || "")
+        .split("\n")
+        .filter((line) => line.trim() !== "")
+        .slice(1);
+      if (typeof constructorOpt === "function" && constructorOpt.name) {
+        const idx = frames.findIndex((line) => {
+          const match = FRAME_NAME.exec(line);
+          return match !== null && match[1] === constructorOpt.name;
+        });
+        if (idx >= 0) {
+          frames = frames.slice(idx + 1);
+        }
+      }
+      const limit = stackTraceLimit(Error);
+      if (frames.length > limit) {
+        frames = frames.slice(0, limit);
+      }
+      Object.defineProperty(target, "stack", {
+        value: frames.map((line) => line + "\n").join(""),
+        writable: true,
+        enumerable: false,
+        configurable: true,
+      });
+    }
+
+    Object.defineProperty(Error, "captureStackTrace", {
+      value: captureStackTrace,
+      writable: true,
+      enumerable: false,
+      configurable: true,
+    });
+  };
+})();
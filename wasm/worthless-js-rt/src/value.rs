@@ -1,25 +1,34 @@
-use std::borrow::Cow;
-use std::ffi::CString;
+use std::ffi::{c_char, c_void, CString};
 use std::mem::ManuallyDrop;
 use std::{fmt, ptr};
 
 use smallvec::SmallVec;
 use worthless_quickjs_sys::{
-    JSAtom, JSContext, JSPropertyEnum, JSValue, JS_AtomToString, JS_Call,
-    JS_DefinePropertyValueStr, JS_DefinePropertyValueUint32, JS_GetOwnPropertyNames,
-    JS_GetPropertyInternal, JS_GetPropertyStr, JS_GetPropertyUint32, JS_IsArray, JS_IsFunction,
-    JS_NewArray, JS_NewCFunction2, JS_NewObject, JS_NewStringLen, JS_ThrowInternalError,
-    JS_ToCStringLen2, JS_ToFloat64, JS_ToInt64Ext, WL_JS_DupValue, WL_JS_FreeValue, WL_JS_NewBool,
-    WL_JS_NewFloat64, WL_JS_NewInt32, JS_GPN_ENUM_ONLY, JS_GPN_STRING_MASK, JS_GPN_SYMBOL_MASK,
-    JS_PROP_C_W_E, JS_TAG_BIG_INT, JS_TAG_BOOL, JS_TAG_EXCEPTION, JS_TAG_FIRST, JS_TAG_FLOAT64,
-    JS_TAG_INT, JS_TAG_NULL, JS_TAG_STRING, JS_TAG_SYMBOL, JS_TAG_UNDEFINED, WL_JS_NULL,
-    WL_JS_TRUE, WL_JS_UNDEFINED,
+    JSAtom, JSContext, JSPropertyEnum, JSValue, JS_AtomToValue, JS_Call, JS_DefinePropertyValue,
+    JS_DefinePropertyValueStr, JS_DefinePropertyValueUint32, JS_DetachArrayBuffer, JS_DupAtom,
+    JS_FreeAtom, JS_GetArrayBuffer, JS_GetOwnPropertyNames, JS_GetPropertyInternal,
+    JS_GetPropertyStr, JS_GetPropertyUint32, JS_GetPrototype, JS_GetTypedArrayBuffer, JS_IsArray,
+    JS_IsFunction, JS_IsInstanceOf, JS_NewArray, JS_NewArrayBuffer, JS_NewArrayBufferCopy,
+    JS_NewCFunction2, JS_NewCFunctionData, JS_NewObject, JS_NewPromiseCapability, JS_NewStringLen,
+    JS_ThrowInternalError, JS_ToFloat64, JS_ToInt64Ext, JS_ValueToAtom, WL_JS_DupValue,
+    WL_JS_FreePropertyEnum, WL_JS_FreeValue, WL_JS_GetPointer, WL_JS_IsIdentical, WL_JS_NewBool,
+    WL_JS_NewFloat64, WL_JS_NewInt32, WL_JS_NewPointer, WL_JS_ValueGetBool, WL_JS_ValueGetInt,
+    WL_JS_ValueGetTag, JS_ATOM_NULL, JS_GPN_ENUM_ONLY, JS_GPN_SET_ENUM, JS_GPN_STRING_MASK,
+    JS_GPN_SYMBOL_MASK, JS_PROP_CONFIGURABLE, JS_PROP_C_W_E, JS_TAG_BIG_INT, JS_TAG_BOOL,
+    JS_TAG_EXCEPTION, JS_TAG_FLOAT64, JS_TAG_INT, JS_TAG_NULL, JS_TAG_OBJECT, JS_TAG_STRING,
+    JS_TAG_SYMBOL, JS_TAG_UNDEFINED, WL_JS_NULL, WL_JS_UNDEFINED,
 };
 
+use crate::array_buffer;
 use crate::context::Context;
 use crate::error::Error;
 use crate::js_exception::JsException;
+use crate::js_str::JsStr;
 use crate::primitive::Primitive;
+use crate::value_ref::ValueRef;
+
+/// The largest integer a number holds without losing precision.
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
 
 /// An enum that indicates of what type a value is
 #[derive(Debug, PartialEq, Eq)]
@@ -34,10 +43,15 @@ pub enum ValueKind {
     Object,
 }
 
+/// A host function that can be exposed to JavaScript.
+///
+/// It's invoked with the context, the receiver and the arguments.
+pub type HostFunction = fn(&Context, &Value, &[Value]) -> Result<Value, Error>;
+
 /// A wrapper around a value from the JS engine.
 pub struct Value {
-    // note on JSValue here.  We're assuming that JSValue is 64bit because
-    // internally it uses JS_NAN_BOXING when compiling to wasi
+    // note on JSValue here.  On wasi it's a NaN-boxed 64bit integer, on native
+    // 64bit targets it's a struct.  Only the WL_ accessors look into it.
     raw: JSValue,
     // TODO: it's quite annoying to carry a huge context object in here and it's
     // also unnecessary.  It's however quite convenient to be able to always refer
@@ -46,44 +60,105 @@ pub struct Value {
     ctx: Context,
 }
 
+/// The depth up to which nested values are formatted by [`Debug`](fmt::Debug).
+pub const DEFAULT_DEBUG_DEPTH: usize = 8;
+
 impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.debug(DEFAULT_DEBUG_DEPTH), f)
+    }
+}
+
+impl fmt::Display for Value {
+    /// Formats the value like `String(value)` in JavaScript.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // unlike the string conversion of the engine, String() accepts symbols
+        if self.kind() == ValueKind::Symbol {
+            let undefined = Value::from_primitive(&self.ctx, Primitive::Undefined);
+            if let Ok(rv) = self
+                .ctx
+                .with_global(|global| global.get_property("String"))
+                .and_then(|func| func.call(&undefined, [self]))
+            {
+                return fmt::Display::fmt(&rv.to_string_lossy(), f);
+            }
+        }
+        match self.as_str() {
+            Ok(value) => fmt::Display::fmt(&value, f),
+            Err(Error::Utf8Error(_)) => fmt::Display::fmt(&self.to_string_lossy(), f),
+            // the conversion threw, the exception was already cleared.
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+/// Formats a value with [`Debug`](fmt::Debug) with a depth limit.
+///
+/// Created by [`Value::debug`].  Arrays nested deeper than the limit are
+/// elided and arrays that contain themselves are formatted as `Circular`.
+pub struct DebugValue<'a> {
+    value: &'a Value,
+    depth: usize,
+    parent: Option<&'a DebugValue<'a>>,
+}
+
+impl<'a> DebugValue<'a> {
+    /// Checks if the value is already being formatted further up.
+    fn is_circular(&self) -> bool {
+        let mut parent = self.parent;
+        while let Some(item) = parent {
+            if item.value.ptr_eq(self.value) {
+                return true;
+            }
+            parent = item.parent;
+        }
+        false
+    }
+}
+
+impl<'a> fmt::Debug for DebugValue<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         #[derive(Debug)]
         struct Invalid;
 
-        let kind = self.kind();
+        let value = self.value;
+        let kind = value.kind();
         match kind {
             ValueKind::Null => return f.debug_struct("Null").finish(),
             ValueKind::Undefined => return f.debug_struct("Undefined").finish(),
             ValueKind::Object => {
-                if self.is_array() {
+                if value.is_array() {
+                    if self.is_circular() {
+                        return f.debug_struct("Circular").finish();
+                    } else if self.depth == 0 {
+                        return f.debug_struct("Array").finish_non_exhaustive();
+                    }
                     let mut t = f.debug_tuple("Array");
-                    for idx in 0..self.len().unwrap_or(0) {
-                        match self.get_by_index(idx) {
-                            Ok(value) => t.field(&value),
+                    for idx in 0..value.len().unwrap_or(0) {
+                        match value.get_by_index(idx) {
+                            Ok(item) => t.field(&DebugValue {
+                                value: &item,
+                                depth: self.depth - 1,
+                                parent: Some(self),
+                            }),
                             Err(_) => t.field(&Invalid),
                         };
                     }
                     return t.finish();
-                } else if self.is_function() {
-                    if let Ok(name) = self.get_property("name") {
-                        if name.kind() != ValueKind::Undefined {
-                            return f
-                                .debug_tuple("Function")
-                                .field(&name.to_string_lossy())
-                                .finish();
-                        }
-                    }
-                    return f.debug_struct("Function").finish();
+                } else if value.is_function() {
+                    return match value.function_name() {
+                        Some(name) => f.debug_tuple("Function").field(&name).finish(),
+                        None => f.debug_struct("Function").finish(),
+                    };
                 }
             }
             _ => {}
         };
-        if let Some(x) = self.as_primitive() {
+        if let Some(x) = value.as_primitive() {
             fmt::Debug::fmt(&x, f)
         } else {
             f.debug_struct(&format!("{:?}", kind))
-                .field("to_string", &self.to_string_lossy())
+                .field("to_string", &value.to_string_lossy())
                 .finish()
         }
     }
@@ -114,12 +189,25 @@ impl Value {
         }
     }
 
+    /// Constructs a value that borrows both the raw value and the context.
+    ///
+    /// Neither refcount is incremented, so the value must never be dropped
+    /// and must not outlive the context.
+    pub(crate) unsafe fn borrow_raw(ctx: &Context, raw: JSValue) -> ManuallyDrop<Value> {
+        ManuallyDrop::new(Value {
+            raw,
+            ctx: ptr::read(ctx),
+        })
+    }
+
     /// Creates a value from a primitive.
     ///
     /// # Panics
     ///
     /// Panics when a symbol primitive was attempted to be converted into a
-    /// value which is not supported.
+    /// value which is not supported.  Without the `bignum` feature this also
+    /// panics for unsigned integers above `Number.MAX_SAFE_INTEGER` as they
+    /// cannot be represented without losing precision.
     pub fn from_primitive<'a, I>(ctx: &Context, value: I) -> Value
     where
         I: Into<Primitive<'a>>,
@@ -142,6 +230,21 @@ impl Value {
                     Primitive::F64(value as f64)
                 },
             ),
+            Primitive::U64(value) if value <= MAX_SAFE_INTEGER => {
+                Value::from_primitive(ctx, Primitive::I64(value as i64))
+            }
+            #[cfg(feature = "bignum")]
+            Primitive::U64(value) => unsafe {
+                Value::from_raw_unchecked(
+                    ctx,
+                    worthless_quickjs_sys::JS_NewBigUint64(ctx.as_raw(), value),
+                )
+            },
+            #[cfg(not(feature = "bignum"))]
+            Primitive::U64(value) => panic!(
+                "cannot create {} without BigInt as it exceeds Number.MAX_SAFE_INTEGER",
+                value
+            ),
             Primitive::F64(value) => unsafe {
                 Value::from_raw_unchecked(ctx, WL_JS_NewFloat64(ctx.as_raw(), value))
             },
@@ -150,17 +253,19 @@ impl Value {
                     ctx,
                     JS_NewStringLen(
                         ctx.as_raw(),
-                        value.as_bytes().as_ptr() as *const i8,
+                        value.as_bytes().as_ptr() as *const c_char,
                         value.len(),
                     ),
                 )
             },
+            Primitive::String(value) => Value::from_primitive(ctx, value.as_str()),
+            Primitive::JsStr(value) => Value::from_primitive(ctx, value.as_str()),
             Primitive::InvalidStr(value) => unsafe {
                 Value::from_raw_unchecked(
                     ctx,
                     JS_NewStringLen(
                         ctx.as_raw(),
-                        value.as_bytes().as_ptr() as *const i8,
+                        value.as_bytes().as_ptr() as *const c_char,
                         value.len(),
                     ),
                 )
@@ -209,35 +314,7 @@ impl Value {
         {
             // we invoke the function purely based on the fact that it's a known zero type
             let func: F = unsafe { std::mem::zeroed() };
-
-            let ctx = Context::borrow_raw_unchecked(raw_ctx);
-            let this_val =
-                unsafe { Value::from_raw_unchecked(&ctx, WL_JS_DupValue(raw_ctx, this_val)) };
-            let args = (0..argc as usize)
-                .map(|idx| unsafe {
-                    Value::from_raw_unchecked(&ctx, WL_JS_DupValue(raw_ctx, *argv.add(idx)))
-                })
-                .collect::<SmallVec<[Value; 8]>>();
-
-            match func(&ctx, &this_val, &args) {
-                Ok(value) => value.into_raw(),
-                Err(err) => {
-                    let err_msg = err.to_string();
-                    let msg = match CString::new(err_msg) {
-                        Ok(msg) => msg,
-                        Err(err) => CString::new(
-                            err.into_vec()
-                                .into_iter()
-                                .filter(|x| *x != 0)
-                                .collect::<Vec<_>>(),
-                        )
-                        .unwrap(),
-                    };
-                    unsafe {
-                        JS_ThrowInternalError(raw_ctx, "%s\x00".as_ptr() as *const i8, msg.as_ptr())
-                    }
-                }
-            }
+            unsafe { call_host(raw_ctx, this_val, argc, argv, func) }
         }
 
         let name = CString::new(name).unwrap();
@@ -245,15 +322,62 @@ impl Value {
             let func = JS_NewCFunction2(
                 ctx.as_raw(),
                 Some(trampoline::<F>),
-                name.as_ptr() as *const i8,
+                name.as_ptr() as *const c_char,
                 0, // length
                 0, // JS_CFUNC_generic
                 0, // magic
             );
-            if func == 0 {
+            Value::from_raw(ctx, func)
+        }
+    }
+
+    /// Wraps a host function pointer in a JavaScript function.
+    ///
+    /// Unlike [`from_func`](Self::from_func) this is not limited to zero sized
+    /// functions as the pointer is stored alongside the function.
+    pub fn from_host_fn(ctx: &Context, name: &str, f: HostFunction) -> Result<Value, Error> {
+        unsafe extern "C" fn trampoline(
+            raw_ctx: *mut JSContext,
+            this_val: JSValue,
+            argc: i32,
+            argv: *mut JSValue,
+            _magic: i32,
+            func_data: *mut JSValue,
+        ) -> JSValue {
+            let ptr = unsafe { WL_JS_GetPointer(raw_ctx, *func_data) };
+            let func: HostFunction = unsafe { std::mem::transmute(ptr) };
+            unsafe { call_host(raw_ctx, this_val, argc, argv, func) }
+        }
+
+        let name = CString::new(name)?;
+        unsafe {
+            // function pointers are static so no finalizer is needed
+            let data =
+                Value::from_raw(ctx, WL_JS_NewPointer(ctx.as_raw(), f as *mut c_void, None))?;
+            let mut raw_data = [data.as_raw()];
+            let func = Value::from_raw(
+                ctx,
+                JS_NewCFunctionData(
+                    ctx.as_raw(),
+                    Some(trampoline),
+                    0,
+                    0,
+                    1,
+                    raw_data.as_mut_ptr(),
+                ),
+            )?;
+            let raw_name = JS_NewStringLen(ctx.as_raw(), name.as_ptr(), name.as_bytes().len() as _);
+            if JS_DefinePropertyValueStr(
+                ctx.as_raw(),
+                func.as_raw(),
+                "name\x00".as_ptr() as *const c_char,
+                raw_name,
+                JS_PROP_CONFIGURABLE as i32,
+            ) < 0
+            {
                 return Err(ctx.last_error());
             }
-            Ok(Value::from_raw_unchecked(&ctx, func))
+            Ok(func)
         }
     }
 
@@ -267,6 +391,52 @@ impl Value {
         unsafe { Value::from_raw_unchecked(ctx, JS_NewObject(ctx.as_raw())) }
     }
 
+    /// Creates a new pending promise.
+    ///
+    /// Returns the promise together with its `resolve` and `reject` functions.
+    pub fn new_promise(ctx: &Context) -> Result<(Value, Value, Value), Error> {
+        unsafe {
+            let mut funcs: [JSValue; 2] = [WL_JS_UNDEFINED; 2];
+            let promise = Value::from_raw(
+                ctx,
+                JS_NewPromiseCapability(ctx.as_raw(), funcs.as_mut_ptr()),
+            )?;
+            Ok((
+                promise,
+                Value::from_raw_unchecked(ctx, funcs[0]),
+                Value::from_raw_unchecked(ctx, funcs[1]),
+            ))
+        }
+    }
+
+    /// Creates an `ArrayBuffer` that takes ownership of the bytes.
+    ///
+    /// The bytes are not copied and can be moved back out with
+    /// [`transfer_bytes`](Self::transfer_bytes).
+    pub fn from_bytes(ctx: &Context, bytes: Vec<u8>) -> Result<Value, Error> {
+        unsafe {
+            if bytes.is_empty() {
+                return Value::from_raw(
+                    ctx,
+                    JS_NewArrayBufferCopy(ctx.as_raw(), bytes.as_ptr(), 0),
+                );
+            }
+            let len = bytes.len();
+            let (data, opaque) = array_buffer::leak(bytes);
+            Value::from_raw(
+                ctx,
+                JS_NewArrayBuffer(
+                    ctx.as_raw(),
+                    data,
+                    len,
+                    Some(array_buffer::free_owned),
+                    opaque,
+                    0,
+                ),
+            )
+        }
+    }
+
     /// Returns the kind of value.
     pub fn kind(&self) -> ValueKind {
         match self.tag() {
@@ -289,10 +459,7 @@ impl Value {
             ValueKind::Number if self.tag() == JS_TAG_INT => Primitive::I32(self.as_i32().unwrap()),
             ValueKind::Number => Primitive::F64(self.as_f64().unwrap_or(f64::NAN)),
             ValueKind::Boolean => Primitive::Bool(self.is_true()),
-            ValueKind::String => match self.to_string_lossy() {
-                Cow::Borrowed(val) => Primitive::Str(val),
-                Cow::Owned(invalid_str) => Primitive::InvalidStr(invalid_str),
-            },
+            ValueKind::String => Primitive::JsStr(self.to_string_lossy()),
             ValueKind::Symbol => match self.as_str() {
                 Ok(val) => Primitive::Symbol(val),
                 Err(_) => return None,
@@ -301,36 +468,28 @@ impl Value {
         })
     }
 
-    /// Returns the value as string.
-    pub fn as_str(&self) -> Result<&str, Error> {
-        unsafe {
-            let mut len: usize = 0;
-            let ptr = JS_ToCStringLen2(self.ctx.as_raw(), &mut len, self.raw, 0);
-            // this is needed because some values such as symbols for some
-            // reason cannot be converted to strings.
-            if ptr == ptr::null() {
-                return Err(self.ctx.last_error());
-            }
-            let ptr = ptr as *const u8;
-            let len = len as usize;
-            let buffer = std::slice::from_raw_parts(ptr, len);
-            std::str::from_utf8(buffer).map_err(Error::Utf8Error)
+    /// Returns a [`Debug`](fmt::Debug) formatter with a custom depth limit.
+    ///
+    /// The `Debug` impl of the value itself uses [`DEFAULT_DEBUG_DEPTH`].
+    pub fn debug(&self, max_depth: usize) -> DebugValue<'_> {
+        DebugValue {
+            value: self,
+            depth: max_depth,
+            parent: None,
         }
     }
 
+    /// Returns the value as string.
+    ///
+    /// The returned guard borrows the string from the engine and frees it
+    /// when dropped.
+    pub fn as_str(&self) -> Result<JsStr<'_>, Error> {
+        unsafe { JsStr::from_raw(&self.ctx, self.raw) }
+    }
+
     /// Returns the value as string with lossy unicode recovery.
-    pub fn to_string_lossy(&self) -> Cow<'_, str> {
-        unsafe {
-            let mut len: usize = 0;
-            let ptr = JS_ToCStringLen2(self.ctx.as_raw(), &mut len, self.raw, 0);
-            if ptr == ptr::null() {
-                return Cow::Borrowed("");
-            }
-            let ptr = ptr as *const u8;
-            let len = len as usize;
-            let buffer = std::slice::from_raw_parts(ptr, len);
-            String::from_utf8_lossy(buffer)
-        }
+    pub fn to_string_lossy(&self) -> JsStr<'_> {
+        unsafe { JsStr::from_raw_lossy(&self.ctx, self.raw) }
     }
 
     /// If the value is a float, returns it.
@@ -392,18 +551,39 @@ impl Value {
         }
     }
 
+    /// Returns the value as u64 if it is a non-negative integer in range.
+    ///
+    /// Numbers above `Number.MAX_SAFE_INTEGER` are rejected as they might
+    /// have been rounded, larger integers have to be passed as `BigInt`.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.tag() {
+            JS_TAG_INT => u64::try_from(self.i32_unchecked()).ok(),
+            JS_TAG_FLOAT64 => self
+                .as_f64()
+                .filter(|x| x.fract() == 0.0 && *x >= 0.0 && *x <= MAX_SAFE_INTEGER as f64)
+                .map(|x| x as u64),
+            // the 64-bit conversions of QuickJS wrap around
+            JS_TAG_BIG_INT => self.to_string_lossy().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as u32 if it is a non-negative integer in range.
+    pub fn as_u32(&self) -> Option<u32> {
+        self.as_u64().and_then(|x| u32::try_from(x).ok())
+    }
+
+    /// Returns the value as usize if it is a non-negative integer in range.
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_u64().and_then(|x| usize::try_from(x).ok())
+    }
+
     /// Returns `true` if this value is truthy.
     pub fn is_true(&self) -> bool {
         match self.kind() {
             ValueKind::Undefined | ValueKind::Null => false,
-            ValueKind::Number => {
-                if self.raw == 0 {
-                    false
-                } else {
-                    self.as_f64() != Some(0.0)
-                }
-            }
-            ValueKind::Boolean => unsafe { self.raw == WL_JS_TRUE },
+            ValueKind::Number => self.as_f64() != Some(0.0),
+            ValueKind::Boolean => unsafe { WL_JS_ValueGetBool(self.raw) != 0 },
             ValueKind::String => self.as_str().map_or(false, |x| !x.is_empty()),
             ValueKind::Symbol | ValueKind::Exception | ValueKind::Object => true,
         }
@@ -426,16 +606,24 @@ impl Value {
     }
 
     fn _set_property(&self, key: &str, value: Value) -> Result<(), Error> {
-        let key = CString::new(key)?;
+        // keys are interned so that marshalling many objects with the same
+        // keys does not allocate a string for every key
+        let key = self.ctx.intern(key);
         let rv = unsafe {
+            let atom = JS_ValueToAtom(self.ctx.as_raw(), key.raw);
+            if atom == JS_ATOM_NULL {
+                return Err(self.ctx.last_error());
+            }
             WL_JS_DupValue(self.ctx.as_raw(), value.raw);
-            JS_DefinePropertyValueStr(
+            let rv = JS_DefinePropertyValue(
                 self.ctx.as_raw(),
                 self.raw,
-                key.as_ptr(),
+                atom,
                 value.raw,
                 JS_PROP_C_W_E as i32,
-            )
+            );
+            JS_FreeAtom(self.ctx.as_raw(), atom);
+            rv
         };
 
         if rv < 0 {
@@ -445,35 +633,32 @@ impl Value {
         }
     }
 
-    /// Iterates over all properties.
+    /// Iterates over the own enumerable properties of the object.
     ///
-    /// The iterator yields key, value pairs where the key is always a string in
-    /// true JavaScript mannor.
+    /// Only string keys are yielded.  To change which properties are yielded,
+    /// eg: to include symbol keys, use [`iter_properties_with`](Self::iter_properties_with).
     pub fn iter_properties(&self) -> PropertiesIter<'_> {
-        let mut property_enum: *mut JSPropertyEnum = ptr::null_mut();
-        let mut len = 0;
-        let rv = unsafe {
-            JS_GetOwnPropertyNames(
-                self.ctx.as_raw(),
-                &mut property_enum,
-                &mut len,
-                self.raw,
-                (JS_GPN_STRING_MASK | JS_GPN_SYMBOL_MASK | JS_GPN_ENUM_ONLY) as i32,
-            )
-        };
-
-        // swallow iteration setup errors
-        if rv < 0 {
-            len = 0;
-        }
+        self.iter_properties_with(&PropertyFilter::new())
+    }
 
-        PropertiesIter {
+    /// Iterates over the properties of the object that match a filter.
+    ///
+    /// Keys are yielded as strings, or as symbols if the filter asks for them.
+    /// When the prototype chain is included, properties shadowed by an object
+    /// further down the chain are skipped like in a `for ... in` loop.
+    pub fn iter_properties_with(&self, filter: &PropertyFilter) -> PropertiesIter<'_> {
+        let mut rv = PropertiesIter {
             value: self,
-            property_enum,
-            len: len as usize,
-            offset: 0,
+            filter: *filter,
+            object: Some(self.clone()),
+            property_enum: ptr::null_mut(),
             current_key: 0,
-        }
+            len: 0,
+            offset: 0,
+            seen: Vec::new(),
+        };
+        rv.load_properties();
+        rv
     }
 
     /// Looks up a property by index (eg: array).
@@ -543,35 +728,235 @@ impl Value {
         unsafe { JS_IsFunction(self.ctx.as_raw(), self.raw) != 0 }
     }
 
+    /// Returns the name of a function.
+    ///
+    /// This is the `name` property which is empty for anonymous functions.
+    /// Returns `None` if the value is not a function or the name is not a
+    /// string.
+    pub fn function_name(&self) -> Option<JsStr<'_>> {
+        if !self.is_function() {
+            return None;
+        }
+        let name = self.get_property("name").ok()?;
+        if name.kind() != ValueKind::String {
+            return None;
+        }
+        // the string is copied out of the value, so it can outlive it
+        Some(unsafe { JsStr::from_raw_lossy(&self.ctx, name.raw) })
+    }
+
+    /// Returns the number of declared parameters of a function.
+    ///
+    /// This is the `length` property of the function.
+    pub fn function_length(&self) -> Option<usize> {
+        if !self.is_function() {
+            return None;
+        }
+        self.get_property("length")
+            .ok()?
+            .as_i64()
+            .and_then(|x| usize::try_from(x).ok())
+    }
+
+    /// Returns the source text of a function.
+    ///
+    /// The source is only available for functions compiled from source.
+    /// Native functions and functions loaded from bytecode without debug
+    /// information return `None`.
+    pub fn function_source(&self) -> Option<JsStr<'_>> {
+        if !self.is_function() {
+            return None;
+        }
+        let source = self
+            .function_prototype_method("toString")
+            .and_then(|to_string| to_string.call(self, None::<Value>))
+            .ok()?;
+        let source = unsafe { JsStr::from_raw(&self.ctx, source.raw) }.ok()?;
+        if source.ends_with("[native code]\n}") {
+            None
+        } else {
+            Some(source)
+        }
+    }
+
     /// Checks if this object is an array
     pub fn is_array(&self) -> bool {
         unsafe { JS_IsArray(self.ctx.as_raw(), self.raw) == 1 }
     }
 
+    /// Checks if this object is an `ArrayBuffer` or a typed array.
+    ///
+    /// These are the values [`to_bytes`](Self::to_bytes) and
+    /// [`transfer_bytes`](Self::transfer_bytes) accept.
+    pub fn is_array_buffer(&self) -> bool {
+        if self.kind() != ValueKind::Object {
+            return false;
+        }
+        let is_instance = |ctor: Result<Value, Error>| {
+            ctor.is_ok_and(|ctor| unsafe {
+                JS_IsInstanceOf(self.ctx.as_raw(), self.raw, ctor.raw) == 1
+            })
+        };
+        // the prototype of the typed array constructors is `%TypedArray%`
+        is_instance(
+            self.ctx
+                .with_global(|global| global.get_property("ArrayBuffer")),
+        ) || is_instance(
+            self.ctx
+                .with_global(|global| global.get_property("Uint8Array"))
+                .and_then(|ctor| ctor.get_property("__proto__")),
+        )
+    }
+
+    /// Copies the bytes of an `ArrayBuffer` or the bytes a typed array views.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let (buffer, offset, len) = self.array_buffer_range()?;
+        let (data, size) = buffer.array_buffer_data()?;
+        let len = len.unwrap_or(size - offset);
+        Ok(unsafe { std::slice::from_raw_parts(data.add(offset), len) }.to_vec())
+    }
+
+    /// Moves the bytes out of an `ArrayBuffer` or typed array.
+    ///
+    /// The buffer is detached afterwards, so JavaScript sees it as empty and
+    /// can no longer change the bytes.  Buffers created with
+    /// [`from_bytes`](Self::from_bytes) are handed back without copying,
+    /// others are copied before they are detached.  A typed array that views
+    /// only a part of its buffer is copied and leaves the buffer alone, as is
+    /// a `SharedArrayBuffer`.
+    pub fn transfer_bytes(&self) -> Result<Vec<u8>, Error> {
+        let (buffer, offset, len) = self.array_buffer_range()?;
+        let (data, size) = buffer.array_buffer_data()?;
+        let len = len.unwrap_or(size - offset);
+        // the rest of the buffer might still be in use by other views
+        if offset != 0 || len != size {
+            return Ok(unsafe { std::slice::from_raw_parts(data.add(offset), len) }.to_vec());
+        }
+        if !array_buffer::is_owned(data) {
+            let bytes = unsafe { std::slice::from_raw_parts(data, len) }.to_vec();
+            buffer.detach_array_buffer();
+            return Ok(bytes);
+        }
+        Ok(unsafe { array_buffer::claim(data, size, || buffer.detach_array_buffer()) })
+    }
+
+    /// Detaches an `ArrayBuffer` and frees its bytes.
+    ///
+    /// Does nothing for other values and already detached buffers.
+    pub fn detach_array_buffer(&self) {
+        unsafe { JS_DetachArrayBuffer(self.ctx.as_raw(), self.raw) }
+    }
+
+    /// Returns the data pointer and size of an `ArrayBuffer`.
+    fn array_buffer_data(&self) -> Result<(*mut u8, usize), Error> {
+        let mut size = 0;
+        let data = unsafe { JS_GetArrayBuffer(self.ctx.as_raw(), &mut size, self.raw) };
+        // even empty buffers have data, so this is always an exception
+        if data.is_null() {
+            return Err(self.ctx.last_error());
+        }
+        Ok((data, size))
+    }
+
+    /// Resolves the `ArrayBuffer` behind the value with the offset and length
+    /// of the bytes it views.
+    ///
+    /// The length is `None` if the value is the buffer itself.
+    fn array_buffer_range(&self) -> Result<(Value, usize, Option<usize>), Error> {
+        let (mut offset, mut len) = (0, 0);
+        let rv = unsafe {
+            JS_GetTypedArrayBuffer(
+                self.ctx.as_raw(),
+                self.raw,
+                &mut offset,
+                &mut len,
+                ptr::null_mut(),
+            )
+        };
+        match unsafe { Value::from_raw(&self.ctx, rv) } {
+            Ok(buffer) => Ok((buffer, offset, Some(len))),
+            // not a typed array, so it has to be the buffer itself
+            Err(_) => Ok((self.clone(), 0, None)),
+        }
+    }
+
+    /// Returns `true` if both values are the same object or primitive.
+    ///
+    /// This compares the identity of the values, not their contents.
+    pub fn ptr_eq(&self, other: &Value) -> bool {
+        unsafe { WL_JS_IsIdentical(self.raw, other.raw) != 0 }
+    }
+
     /// Calls the object.
-    pub fn call(&self, receiver: &Value, args: &[Value]) -> Result<Value, Error> {
-        let args: SmallVec<[JSValue; 10]> = args.iter().map(|v| v.raw).collect();
+    ///
+    /// Arguments can be values or anything that converts into a value.
+    pub fn call<I, V>(&self, receiver: &Value, args: I) -> Result<Value, Error>
+    where
+        I: IntoIterator<Item = V>,
+        V: IntoValue,
+    {
+        let args: SmallVec<[Value; 10]> =
+            args.into_iter().map(|v| v.into_value(&self.ctx)).collect();
+        let mut raw_args: SmallVec<[JSValue; 10]> = args.iter().map(|v| v.raw).collect();
         let rv = unsafe {
             JS_Call(
                 self.ctx.as_raw(),
                 self.raw,
                 receiver.raw,
-                args.len() as i32,
-                args.as_slice().as_ptr() as *mut JSValue,
+                raw_args.len() as i32,
+                raw_args.as_mut_ptr(),
             )
         };
         unsafe { Value::from_raw(&self.ctx, rv) }
     }
 
+    /// Calls a method of the object with the object as receiver.
+    pub fn call_method<I, V>(&self, name: &str, args: I) -> Result<Value, Error>
+    where
+        I: IntoIterator<Item = V>,
+        V: IntoValue,
+    {
+        self.get_property(name)?.call(self, args)
+    }
+
+    /// Creates a bound function like `Function.prototype.bind`.
+    ///
+    /// The returned function invokes this function with `this` as receiver
+    /// and the given arguments prepended to the ones it is called with.
+    pub fn bind<I, V>(&self, this: &Value, args: I) -> Result<Value, Error>
+    where
+        I: IntoIterator<Item = V>,
+        V: IntoValue,
+    {
+        let args: SmallVec<[Value; 10]> = std::iter::once(this.clone())
+            .chain(args.into_iter().map(|v| v.into_value(&self.ctx)))
+            .collect();
+        self.function_prototype_method("bind")?.call(self, args)
+    }
+
+    /// Calls the function with the arguments from an array like value.
+    ///
+    /// This mirrors `Function.prototype.apply`.  `null` and `undefined`
+    /// are accepted as no arguments.
+    pub fn apply(&self, this: &Value, args: &Value) -> Result<Value, Error> {
+        self.function_prototype_method("apply")?
+            .call(self, [this, args])
+    }
+
+    /// Looks up a method of `Function.prototype`.
+    ///
+    /// This is used over looking up the method on the value itself so that
+    /// functions overriding them are not invoked by accident.
+    fn function_prototype_method(&self, name: &str) -> Result<Value, Error> {
+        self.ctx
+            .with_global(|global| global.get_property("Function"))?
+            .get_property("prototype")?
+            .get_property(name)
+    }
+
     /// Returns the internal tag of the value.
     fn tag(&self) -> i32 {
-        // TODO: not happy that this is inlined
-        let tag = (self.raw >> 32) as i32;
-        if (tag - JS_TAG_FIRST) as u32 >= (JS_TAG_FLOAT64 - JS_TAG_FIRST) as u32 {
-            JS_TAG_FLOAT64
-        } else {
-            tag
-        }
+        unsafe { WL_JS_ValueGetTag(self.raw) }
     }
 
     /// Returns the length of the value.
@@ -595,13 +980,15 @@ impl Value {
 
     /// Interprets the value unsafe as i32
     fn i32_unchecked(&self) -> i32 {
-        (self.raw & 0xffffffff) as i32
+        unsafe { WL_JS_ValueGetInt(self.raw) }
     }
 
     /// Downgrades the value into the lower type
     pub(crate) fn into_raw(self) -> JSValue {
-        // consume the refcount
-        ManuallyDrop::new(self).raw
+        // consume the refcount of the value but release the context
+        let this = ManuallyDrop::new(self);
+        drop(unsafe { ptr::read(&this.ctx) });
+        this.raw
     }
 
     /// Returns the internal raw value.
@@ -628,6 +1015,50 @@ impl Drop for Value {
     }
 }
 
+/// Calls a host function from the trampoline of a JavaScript function.
+///
+/// Errors of the function are thrown into the context.
+unsafe fn call_host<F>(
+    raw_ctx: *mut JSContext,
+    this_val: JSValue,
+    argc: i32,
+    argv: *mut JSValue,
+    func: F,
+) -> JSValue
+where
+    F: Fn(&Context, &Value, &[Value]) -> Result<Value, Error>,
+{
+    let ctx = Context::borrow_raw_unchecked(raw_ctx);
+    // the engine keeps this and the arguments alive for the duration of
+    // the call so they are only borrowed.
+    let this_val = unsafe { ValueRef::from_raw(&ctx, this_val) };
+    let args = (0..argc as usize)
+        .map(|idx| unsafe { ValueRef::from_raw(&ctx, *argv.add(idx)) })
+        .collect::<SmallVec<[ValueRef; 8]>>();
+
+    match func(&ctx, &this_val, ValueRef::as_values(&args)) {
+        Ok(value) => value.into_raw(),
+        Err(err) => unsafe { throw_error(raw_ctx, err) },
+    }
+}
+
+/// Throws an error as internal error into the context.
+///
+/// Returns the exception marker value.
+pub(crate) unsafe fn throw_error(raw_ctx: *mut JSContext, err: Error) -> JSValue {
+    let msg = match CString::new(err.to_string()) {
+        Ok(msg) => msg,
+        Err(err) => CString::new(
+            err.into_vec()
+                .into_iter()
+                .filter(|x| *x != 0)
+                .collect::<Vec<_>>(),
+        )
+        .unwrap(),
+    };
+    JS_ThrowInternalError(raw_ctx, "%s\x00".as_ptr() as *const c_char, msg.as_ptr())
+}
+
 /// Utility trait to convert things into values.
 pub trait IntoValue {
     /// Wraps something in a value within the given JS context.
@@ -640,72 +1071,415 @@ impl IntoValue for Value {
     }
 }
 
+impl IntoValue for &Value {
+    fn into_value(self, _ctx: &Context) -> Value {
+        self.clone()
+    }
+}
+
 impl<'a, T: Into<Primitive<'a>>> IntoValue for T {
     fn into_value(self, ctx: &Context) -> Value {
         Value::from_primitive(ctx, self)
     }
 }
 
-/// Iterates over properties of an object.
-pub struct PropertiesIter<'a> {
-    value: &'a Value,
-    property_enum: *mut JSPropertyEnum,
-    current_key: JSAtom,
-    len: usize,
-    offset: usize,
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self, ctx: &Context) -> Value {
+        match self {
+            Some(value) => value.into_value(ctx),
+            None => Value::from_primitive(ctx, Primitive::Null),
+        }
+    }
 }
 
-impl<'a> Iterator for PropertiesIter<'a> {
-    type Item = (Value, Value);
-
-    fn next(&mut self) -> Option<(Value, Value)> {
-        if self.offset >= self.len {
-            return None;
-        }
-        let ctx = self.value.ctx();
-        let key = unsafe { self.property_enum.add(self.offset) };
-        self.offset += 1;
-        self.current_key = unsafe { (*key).atom };
-        let val = unsafe {
-            JS_GetPropertyInternal(
-                ctx.as_raw(),
-                self.value.as_raw(),
-                self.current_key,
-                self.value.as_raw(),
-                0,
-            )
-        };
-        unsafe {
-            Some((
-                Value::from_raw_unchecked(ctx, JS_AtomToString(ctx.as_raw(), self.current_key)),
-                Value::from_raw_unchecked(ctx, val),
-            ))
-        }
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self, ctx: &Context) -> Value {
+        Value::from_iter(ctx, self.into_iter())
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::Value;
-    use crate::{Context, Error, Primitive, ValueKind};
+/// Utility trait to convert values into Rust types.
+///
+/// Unlike the `as_*` accessors of [`Value`] this does not coerce, a value
+/// of the wrong type fails with [`Error::InvalidValue`].
+pub trait FromValue: Sized {
+    /// Converts a value into the Rust type.
+    fn from_value(value: &Value) -> Result<Self, Error>;
+}
 
-    #[test]
-    fn test_null() {
-        Context::run(|ctx| {
-            let val = Value::from_primitive(ctx, Primitive::Null);
-            assert_eq!(val.kind(), ValueKind::Null);
-            assert_eq!(val.as_primitive(), Some(Primitive::Null));
-            assert!(!val.is_true());
-            assert_eq!(val.to_string_lossy(), "null");
-            assert_eq!(val.len(), None);
-            Ok(())
-        })
-        .unwrap()
+fn invalid_value(expected: &str, value: &Value) -> Error {
+    Error::InvalidValue(format!("expected {}, got {:?}", expected, value.kind()))
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Value, Error> {
+        Ok(value.clone())
     }
+}
 
-    #[test]
-    fn test_undefined() {
-        Context::run(|ctx| {
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<bool, Error> {
+        match value.kind() {
+            ValueKind::Boolean => Ok(value.is_true()),
+            _ => Err(invalid_value("a boolean", value)),
+        }
+    }
+}
+
+impl FromValue for i32 {
+    fn from_value(value: &Value) -> Result<i32, Error> {
+        value
+            .as_i32()
+            .ok_or_else(|| invalid_value("a 32-bit integer", value))
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Result<i64, Error> {
+        value
+            .as_i64()
+            .or_else(|| {
+                // numbers beyond the 32-bit range are stored as floats
+                value
+                    .as_f64()
+                    .filter(|x| x.fract() == 0.0 && x.abs() < i64::MAX as f64)
+                    .map(|x| x as i64)
+            })
+            .ok_or_else(|| invalid_value("an integer", value))
+    }
+}
+
+impl FromValue for u8 {
+    fn from_value(value: &Value) -> Result<u8, Error> {
+        value
+            .as_u64()
+            .and_then(|x| u8::try_from(x).ok())
+            .ok_or_else(|| invalid_value("an unsigned 8-bit integer", value))
+    }
+}
+
+impl FromValue for u16 {
+    fn from_value(value: &Value) -> Result<u16, Error> {
+        value
+            .as_u64()
+            .and_then(|x| u16::try_from(x).ok())
+            .ok_or_else(|| invalid_value("an unsigned 16-bit integer", value))
+    }
+}
+
+impl FromValue for u32 {
+    fn from_value(value: &Value) -> Result<u32, Error> {
+        value
+            .as_u32()
+            .ok_or_else(|| invalid_value("an unsigned 32-bit integer", value))
+    }
+}
+
+impl FromValue for u64 {
+    fn from_value(value: &Value) -> Result<u64, Error> {
+        value
+            .as_u64()
+            .ok_or_else(|| invalid_value("an unsigned integer", value))
+    }
+}
+
+impl FromValue for usize {
+    fn from_value(value: &Value) -> Result<usize, Error> {
+        value
+            .as_usize()
+            .ok_or_else(|| invalid_value("an unsigned integer", value))
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<f64, Error> {
+        match value.kind() {
+            ValueKind::Number => value
+                .as_f64()
+                .ok_or_else(|| invalid_value("a number", value)),
+            _ => Err(invalid_value("a number", value)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<String, Error> {
+        match value.kind() {
+            ValueKind::String => Ok(value.as_str()?.to_string()),
+            _ => Err(invalid_value("a string", value)),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Option<T>, Error> {
+        match value.kind() {
+            ValueKind::Undefined | ValueKind::Null => Ok(None),
+            _ => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Vec<T>, Error> {
+        if !value.is_array() {
+            return Err(invalid_value("an array", value));
+        }
+        (0..value.len().unwrap_or(0))
+            .map(|idx| {
+                T::from_value(&value.get_by_index(idx)?).map_err(|err| match err {
+                    Error::InvalidValue(msg) => Error::InvalidValue(format!("[{}]: {}", idx, msg)),
+                    err => err,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Controls which properties [`Value::iter_properties_with`] yields.
+///
+/// The default yields the own enumerable properties with string keys which
+/// are the properties `JSON.stringify` serializes.  Symbol keys are only
+/// yielded when asked for with [`symbols`](Self::symbols), [`all`](Self::all)
+/// is meant for introspection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PropertyFilter {
+    own_only: bool,
+    enumerable_only: bool,
+    strings: bool,
+    symbols: bool,
+}
+
+impl Default for PropertyFilter {
+    fn default() -> PropertyFilter {
+        PropertyFilter {
+            own_only: true,
+            enumerable_only: true,
+            strings: true,
+            symbols: false,
+        }
+    }
+}
+
+impl PropertyFilter {
+    /// Creates the default filter.
+    pub fn new() -> PropertyFilter {
+        PropertyFilter::default()
+    }
+
+    /// Own enumerable properties with string keys, same as the default.
+    pub fn json() -> PropertyFilter {
+        PropertyFilter::default()
+    }
+
+    /// All properties including the prototype chain.
+    pub fn all() -> PropertyFilter {
+        PropertyFilter {
+            own_only: false,
+            enumerable_only: false,
+            strings: true,
+            symbols: true,
+        }
+    }
+
+    /// Only yield own properties and not the ones of the prototype chain.
+    pub fn own_only(&mut self, yes: bool) -> &mut Self {
+        self.own_only = yes;
+        self
+    }
+
+    /// Only yield enumerable properties.
+    pub fn enumerable_only(&mut self, yes: bool) -> &mut Self {
+        self.enumerable_only = yes;
+        self
+    }
+
+    /// Yield properties with string keys.
+    pub fn strings(&mut self, yes: bool) -> &mut Self {
+        self.strings = yes;
+        self
+    }
+
+    /// Yield properties with symbol keys.
+    pub fn symbols(&mut self, yes: bool) -> &mut Self {
+        self.symbols = yes;
+        self
+    }
+
+    fn flags(&self) -> i32 {
+        let mut rv = 0;
+        if self.strings {
+            rv |= JS_GPN_STRING_MASK;
+        }
+        if self.symbols {
+            rv |= JS_GPN_SYMBOL_MASK;
+        }
+        if self.enumerable_only {
+            // on the prototype chain non enumerable properties still shadow
+            // the ones further up, so they are filtered out afterwards.
+            rv |= if self.own_only {
+                JS_GPN_ENUM_ONLY
+            } else {
+                JS_GPN_SET_ENUM
+            };
+        }
+        rv as i32
+    }
+}
+
+/// Iterates over properties of an object.
+pub struct PropertiesIter<'a> {
+    value: &'a Value,
+    filter: PropertyFilter,
+    // the object on the prototype chain the properties are enumerated from
+    object: Option<Value>,
+    property_enum: *mut JSPropertyEnum,
+    current_key: JSAtom,
+    len: usize,
+    offset: usize,
+    // keys already seen further down the prototype chain.  The atoms are
+    // referenced as they outlive the property enum they came from.
+    seen: Vec<JSAtom>,
+}
+
+impl<'a> PropertiesIter<'a> {
+    /// Enumerates the properties of the current object.
+    fn load_properties(&mut self) {
+        self.free_properties();
+        let object = match self.object {
+            Some(ref object) => object,
+            None => return,
+        };
+        let mut len = 0;
+        let rv = unsafe {
+            JS_GetOwnPropertyNames(
+                object.ctx.as_raw(),
+                &mut self.property_enum,
+                &mut len,
+                object.raw,
+                self.filter.flags(),
+            )
+        };
+
+        // swallow iteration setup errors
+        if rv < 0 {
+            self.property_enum = ptr::null_mut();
+            len = 0;
+        }
+        self.len = len as usize;
+        self.offset = 0;
+    }
+
+    /// Moves on to the next object of the prototype chain.
+    fn advance_prototype(&mut self) -> bool {
+        if self.filter.own_only {
+            self.object = None;
+        } else if let Some(object) = self.object.take() {
+            let ctx = object.ctx();
+            // the prototype is returned without incrementing the refcount
+            let proto = unsafe { JS_GetPrototype(ctx.as_raw(), object.raw) };
+            if unsafe { WL_JS_ValueGetTag(proto) } == JS_TAG_OBJECT {
+                self.object = Some(unsafe {
+                    Value::from_raw_unchecked(ctx, WL_JS_DupValue(ctx.as_raw(), proto))
+                });
+            }
+        }
+        self.load_properties();
+        self.object.is_some()
+    }
+
+    fn free_properties(&mut self) {
+        if !self.property_enum.is_null() {
+            unsafe {
+                WL_JS_FreePropertyEnum(
+                    self.value.ctx().as_raw(),
+                    self.property_enum,
+                    self.len as u32,
+                );
+            }
+            self.property_enum = ptr::null_mut();
+        }
+        self.len = 0;
+    }
+}
+
+impl<'a> Iterator for PropertiesIter<'a> {
+    type Item = (Value, Value);
+
+    fn next(&mut self) -> Option<(Value, Value)> {
+        let ctx = self.value.ctx();
+        loop {
+            if self.offset >= self.len {
+                if self.advance_prototype() {
+                    continue;
+                }
+                return None;
+            }
+            let key = unsafe { &*self.property_enum.add(self.offset) };
+            self.offset += 1;
+            self.current_key = key.atom;
+            if !self.filter.own_only {
+                if self.seen.contains(&key.atom) {
+                    continue;
+                }
+                self.seen
+                    .push(unsafe { JS_DupAtom(ctx.as_raw(), key.atom) });
+                if self.filter.enumerable_only && key.is_enumerable == 0 {
+                    continue;
+                }
+            }
+            break;
+        }
+        let object = self.object.as_ref()?;
+        let val = unsafe {
+            JS_GetPropertyInternal(
+                ctx.as_raw(),
+                object.as_raw(),
+                self.current_key,
+                self.value.as_raw(),
+                0,
+            )
+        };
+        unsafe {
+            Some((
+                Value::from_raw_unchecked(ctx, JS_AtomToValue(ctx.as_raw(), self.current_key)),
+                Value::from_raw_unchecked(ctx, val),
+            ))
+        }
+    }
+}
+
+impl<'a> Drop for PropertiesIter<'a> {
+    fn drop(&mut self) {
+        self.free_properties();
+        for atom in self.seen.drain(..) {
+            unsafe { JS_FreeAtom(self.value.ctx().as_raw(), atom) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PropertyFilter, Value};
+    use crate::{Context, Error, Primitive, Runtime, ValueKind};
+
+    #[test]
+    fn test_null() {
+        Context::run(|ctx| {
+            let val = Value::from_primitive(ctx, Primitive::Null);
+            assert_eq!(val.kind(), ValueKind::Null);
+            assert_eq!(val.as_primitive(), Some(Primitive::Null));
+            assert!(!val.is_true());
+            assert_eq!(val.to_string_lossy(), "null");
+            assert_eq!(val.len(), None);
+            Ok(())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_undefined() {
+        Context::run(|ctx| {
             let val = Value::from_primitive(ctx, Primitive::Undefined);
             assert_eq!(val.kind(), ValueKind::Undefined);
             assert_eq!(val.as_primitive(), Some(Primitive::Undefined));
@@ -770,15 +1544,161 @@ mod tests {
         .unwrap()
     }
 
+    #[test]
+    fn test_array_buffer_transfer() {
+        Context::run(|ctx| {
+            let bytes = b"hello world".to_vec();
+            let data = bytes.as_ptr();
+            let buffer = Value::from_bytes(ctx, bytes)?;
+            ctx.with_global(|global| global.set_property("buffer", &buffer))?;
+            assert_eq!(
+                ctx.eval("new Uint8Array(buffer)[4]")?.as_i32(),
+                Some(b'o' as i32)
+            );
+
+            // the bytes come back without a copy and javascript loses them
+            let bytes = buffer.transfer_bytes()?;
+            assert_eq!(bytes, b"hello world");
+            assert_eq!(bytes.as_ptr(), data);
+            assert_eq!(ctx.eval("buffer.byteLength")?.as_i32(), Some(0));
+            assert!(buffer.transfer_bytes().is_err());
+
+            // spare capacity does not force a copy either
+            let mut bytes = Vec::with_capacity(64);
+            bytes.extend_from_slice(b"abc");
+            let data = bytes.as_ptr();
+            let bytes = Value::from_bytes(ctx, bytes)?.transfer_bytes()?;
+            assert_eq!(bytes, b"abc");
+            assert_eq!(bytes.as_ptr(), data);
+
+            // views of a part of a buffer are copied and leave the buffer alone
+            let view = ctx.eval(
+                "globalThis.parent = new Uint8Array([1, 2, 3, 4, 5]); parent.subarray(1, 4)",
+            )?;
+            assert_eq!(view.to_bytes()?, [2, 3, 4]);
+            assert_eq!(view.transfer_bytes()?, [2, 3, 4]);
+            assert_eq!(view.len(), Some(3));
+            assert_eq!(ctx.eval("parent.length")?.as_i32(), Some(5));
+
+            let view = Value::from_bytes(ctx, vec![1, 2, 3, 4, 5])?;
+            ctx.with_global(|global| global.set_property("buffer", &view))?;
+            let view = ctx.eval("new Uint8Array(buffer, 1, 2)")?;
+            assert_eq!(view.transfer_bytes()?, [2, 3]);
+            assert_eq!(ctx.eval("buffer.byteLength")?.as_i32(), Some(5));
+
+            // a view of the whole buffer takes it over
+            let view = ctx.eval("new Uint8Array(buffer)")?;
+            assert_eq!(view.transfer_bytes()?, [1, 2, 3, 4, 5]);
+            assert_eq!(ctx.eval("buffer.byteLength")?.as_i32(), Some(0));
+
+            assert_eq!(Value::from_bytes(ctx, vec![])?.transfer_bytes()?, b"");
+            assert!(ctx.eval("new Float64Array(2)")?.is_array_buffer());
+            assert!(!ctx
+                .eval("new DataView(new ArrayBuffer(2))")?
+                .is_array_buffer());
+            assert!(!ctx.eval("[1, 2, 3]")?.is_array_buffer());
+            assert!(ctx.eval("[1, 2, 3]")?.to_bytes().is_err());
+            Ok(())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_unsigned() {
+        use crate::FromValue;
+
+        Context::run(|ctx| {
+            let val = Value::from_primitive(ctx, 200u8);
+            assert_eq!(val.as_primitive(), Some(Primitive::I32(200)));
+            let val = Value::from_primitive(ctx, u32::MAX);
+            assert_eq!(val.as_u32(), Some(u32::MAX));
+            assert_eq!(val.as_i32(), None);
+
+            let max_safe_integer = (1usize << 53) - 1;
+            let val = Value::from_primitive(ctx, max_safe_integer);
+            assert_eq!(val.kind(), ValueKind::Number);
+            assert_eq!(val.as_usize(), Some(max_safe_integer));
+            assert_eq!(val.as_u32(), None);
+            let val = Value::from_primitive(ctx, 1u64);
+            assert_eq!(val.kind(), ValueKind::Number);
+            assert_eq!(val.as_u64(), Some(1));
+
+            assert_eq!(ctx.eval("-1")?.as_u64(), None);
+            assert_eq!(ctx.eval("1.5")?.as_u64(), None);
+            assert_eq!(ctx.eval("2 ** 60")?.as_u64(), None);
+            assert_eq!(ctx.eval("2 ** 40")?.as_u64(), Some(1 << 40));
+            assert_eq!(ctx.eval("'1'")?.as_u64(), None);
+
+            assert_eq!(u8::from_value(&ctx.eval("255")?)?, 255);
+            assert!(u8::from_value(&ctx.eval("256")?).is_err());
+            assert_eq!(u16::from_value(&ctx.eval("65535")?)?, 65535);
+            assert!(u16::from_value(&ctx.eval("-1")?).is_err());
+            Ok(())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "bignum")]
+    fn test_unsigned_bigint() {
+        use worthless_quickjs_sys::JS_TAG_BIG_INT;
+
+        Context::run(|ctx| {
+            // beyond the safe range values become bigints instead of rounding
+            let val = Value::from_primitive(ctx, u64::MAX);
+            assert_eq!(val.tag(), JS_TAG_BIG_INT);
+            assert_eq!(val.to_string_lossy(), "18446744073709551615");
+            assert_eq!(val.as_u64(), Some(u64::MAX));
+            let val = Value::from_primitive(ctx, usize::MAX);
+            assert_eq!(val.as_usize(), Some(usize::MAX));
+            assert_eq!(ctx.eval("2n ** 64n")?.as_u64(), None);
+            assert_eq!(ctx.eval("-1n")?.as_u64(), None);
+            Ok(())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    #[cfg(not(feature = "bignum"))]
+    #[should_panic(expected = "without BigInt")]
+    fn test_unsigned_without_bignum() {
+        Context::run(|ctx| {
+            let max_safe_integer = (1u64 << 53) - 1;
+            let val = Value::from_primitive(ctx, max_safe_integer);
+            assert_eq!(val.kind(), ValueKind::Number);
+            assert_eq!(val.as_u64(), Some(max_safe_integer));
+            // rounding would silently corrupt the value
+            Value::from_primitive(ctx, max_safe_integer + 1);
+            Ok(())
+        })
+        .unwrap()
+    }
+
     #[test]
     fn test_str() {
         Context::run(|ctx| {
             let val = Value::from_primitive(ctx, "Hello World!");
             assert_eq!(val.kind(), ValueKind::String);
-            assert_eq!(val.as_primitive(), Some(Primitive::Str("Hello World!")));
+            assert!(matches!(
+                val.as_primitive(),
+                Some(Primitive::JsStr(x)) if x == "Hello World!"
+            ));
             assert_eq!(val.to_string_lossy(), "Hello World!");
             assert_eq!(val.len(), Some(12));
 
+            use std::borrow::Cow;
+
+            use crate::IntoValue;
+
+            let owned = String::from("owned");
+            assert_eq!(Value::from_primitive(ctx, &owned).as_str()?, "owned");
+            assert_eq!(Value::from_primitive(ctx, owned).as_str()?, "owned");
+            let cow: Cow<str> = Cow::Owned("cow".into());
+            assert_eq!(Value::from_primitive(ctx, cow).as_str()?, "cow");
+            let cow: Cow<str> = Cow::Borrowed("cow");
+            assert_eq!(cow.into_value(ctx).as_str()?, "cow");
+            assert_eq!('ä'.into_value(ctx).as_str()?, "ä");
+
             Ok(())
         })
         .unwrap()
@@ -852,11 +1772,887 @@ mod tests {
 
             let obj = Value::new_object(ctx);
             obj.set_property("testProperty", Value::from_primitive(ctx, 42))?;
-            let rv = func.call(&obj, &[Value::from_primitive(ctx, true)])?;
+            let rv = func.call(&obj, [true])?;
             assert_eq!(rv.as_primitive(), Some(Primitive::Bool(true)));
 
             Ok(())
         })
         .unwrap();
     }
+
+    #[test]
+    fn test_debug() {
+        Context::run(|ctx| {
+            let val = ctx.eval("let a = [1, [2, [3]]]; a.push(a); a")?;
+            assert_eq!(
+                format!("{:?}", val),
+                "Array(I32(1), Array(I32(2), Array(I32(3))), Circular)"
+            );
+            assert_eq!(
+                format!("{:?}", val.debug(1)),
+                "Array(I32(1), Array { .. }, Circular)"
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_display() {
+        Context::run(|ctx| {
+            assert_eq!(ctx.eval("[1, [2, 3]]")?.to_string(), "1,2,3");
+            assert_eq!(ctx.eval("null")?.to_string(), "null");
+            assert_eq!(ctx.eval("Symbol('foo')")?.to_string(), "Symbol(foo)");
+            assert_eq!(ctx.eval("({toString() { throw 1; }})")?.to_string(), "");
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_property_filter() {
+        Context::run(|ctx| {
+            let val = ctx.eval(
+                "let sym = Symbol('s');
+                let proto = {inherited: 1, shadowed: 2};
+                let obj = Object.create(proto, {hidden: {value: 3, enumerable: false}});
+                obj.own = 4;
+                obj[sym] = 5;
+                Object.defineProperty(obj, 'shadowed', {value: 6, enumerable: false});
+                obj",
+            )?;
+            let keys = |filter: &PropertyFilter| {
+                val.iter_properties_with(filter)
+                    .map(|(key, _)| key.to_string())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(keys(&PropertyFilter::new()), ["own"]);
+            assert_eq!(keys(&PropertyFilter::json()), ["own"]);
+            assert_eq!(
+                keys(PropertyFilter::new().symbols(true)),
+                ["own", "Symbol(s)"]
+            );
+            assert_eq!(
+                keys(PropertyFilter::new().own_only(false)),
+                ["own", "inherited"]
+            );
+            assert_eq!(
+                keys(PropertyFilter::new().enumerable_only(false)),
+                ["hidden", "own", "shadowed"]
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_function_metadata() {
+        Context::run(|ctx| {
+            let func = ctx.eval("(function add(a, b) { return a + b; })")?;
+            assert_eq!(func.function_name().unwrap(), "add");
+            assert_eq!(func.function_length(), Some(2));
+            assert_eq!(
+                func.function_source().unwrap(),
+                "function add(a, b) { return a + b; }"
+            );
+
+            let native = ctx.eval("Math.max")?;
+            assert_eq!(native.function_name().unwrap(), "max");
+            assert_eq!(native.function_length(), Some(2));
+            assert!(native.function_source().is_none());
+
+            let obj = Value::new_object(ctx);
+            assert!(obj.function_name().is_none());
+            assert_eq!(obj.function_length(), None);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_bind_apply() {
+        Context::run(|ctx| {
+            let func = ctx.eval("(function(a, b, c) { return [this.x, a, b, c].join(); })")?;
+            let this = ctx.eval("({x: 'x'})")?;
+            let bound = func.bind(&this, [1, 2])?;
+            assert_eq!(bound.function_length(), Some(1));
+            assert_eq!(
+                bound.call(&Value::new_object(ctx), [3])?.to_string(),
+                "x,1,2,3"
+            );
+
+            let args = ctx.eval("[4, 5, 6]")?;
+            assert_eq!(func.apply(&this, &args)?.to_string(), "x,4,5,6");
+            let none = Value::from_primitive(ctx, Primitive::Null);
+            assert_eq!(func.apply(&this, &none)?.to_string(), "x,,,");
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_register_namespace() {
+        fn add(ctx: &Context, _this: &Value, args: &[Value]) -> Result<Value, Error> {
+            let sum: i64 = args.iter().filter_map(|x| x.as_i64()).sum();
+            Ok(Value::from_primitive(ctx, sum))
+        }
+
+        Context::run(|ctx| {
+            ctx.register_namespace("host", &[("add", add)])?;
+            assert_eq!(ctx.eval("host.add(1, 2)")?.as_i64(), Some(3));
+            assert_eq!(ctx.eval("host.add.name")?.to_string(), "add");
+            assert!(ctx.eval("Object.isFrozen(host)")?.is_true());
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_lockdown() {
+        Context::run(|ctx| {
+            ctx.lockdown()?;
+            assert!(ctx.eval("Object.isFrozen(Object.prototype)")?.is_true());
+            assert!(ctx.eval("Object.isFrozen(Array.prototype.map)")?.is_true());
+            assert!(!ctx.eval("Object.isFrozen(globalThis)")?.is_true());
+            assert!(ctx.eval("'use strict'; Array.prototype.evil = 1").is_err());
+            assert_eq!(ctx.eval("var x = 42; x")?.as_i32(), Some(42));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_run_gc() {
+        Context::run(|ctx| {
+            let before = ctx.rt().memory_usage();
+            ctx.eval("for (var i = 0; i < 1000; i++) { let a = {}; let b = {a}; a.b = b; }")?;
+            let grown = ctx.rt().memory_usage();
+            assert!(grown.obj_count > before.obj_count);
+            ctx.rt().run_gc();
+            let after = ctx.rt().memory_usage();
+            assert!(after.obj_count < grown.obj_count);
+            assert!(after.malloc_size < grown.malloc_size);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive_value_conversions() {
+        use crate::{FromValue, IntoValue};
+
+        #[derive(IntoValue, FromValue, Debug, PartialEq)]
+        #[worthless(rename_all = "camelCase")]
+        struct Event {
+            event_id: String,
+            #[worthless(rename = "ts")]
+            timestamp: f64,
+            release: Option<String>,
+            #[worthless(default)]
+            tags: Vec<Tag>,
+            level: Level,
+            #[worthless(skip)]
+            cached: i32,
+        }
+
+        #[derive(IntoValue, FromValue, Debug, PartialEq)]
+        struct Tag(String, String);
+
+        #[derive(IntoValue, FromValue, Debug, PartialEq)]
+        #[worthless(rename_all = "lowercase")]
+        enum Level {
+            Error,
+            Custom(i32),
+        }
+
+        #[derive(IntoValue, FromValue, Debug, PartialEq)]
+        #[worthless(tag = "type", rename_all = "snake_case")]
+        enum Breadcrumb {
+            Navigation { from: String, to: String },
+            UserAction,
+        }
+
+        Context::run(|ctx| {
+            let event = Event {
+                event_id: "abc".into(),
+                timestamp: 1.5,
+                release: None,
+                tags: vec![Tag("os".into(), "linux".into())],
+                level: Level::Custom(42),
+                cached: 23,
+            };
+            let value = event.into_value(ctx);
+            let json = ctx.global().get_property("JSON")?;
+            let stringify = json.get_property("stringify")?;
+            assert_eq!(
+                stringify.call(&json, [&value])?.as_str()?,
+                r#"{"eventId":"abc","ts":1.5,"tags":[["os","linux"]],"level":{"custom":42}}"#
+            );
+            assert_eq!(
+                Event::try_from(value)?,
+                Event {
+                    event_id: "abc".into(),
+                    timestamp: 1.5,
+                    release: None,
+                    tags: vec![Tag("os".into(), "linux".into())],
+                    level: Level::Custom(42),
+                    cached: 0,
+                }
+            );
+
+            let value = ctx.eval("({eventId: 'x', ts: 2, release: 'v1', level: 'error'})")?;
+            let event = Event::from_value(&value)?;
+            assert_eq!(event.release.as_deref(), Some("v1"));
+            assert_eq!(event.tags, vec![]);
+            assert_eq!(event.level, Level::Error);
+
+            let value = ctx.eval("({eventId: 'x', ts: 2, level: 'fatal'})")?;
+            let err = Event::from_value(&value).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "invalid value: Event.level: expected a variant of `Level`"
+            );
+            let value = ctx.eval("({eventId: 1, ts: 2, level: 'error'})")?;
+            let err = Event::from_value(&value).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "invalid value: Event.eventId: expected a string, got Number"
+            );
+
+            let crumb = Breadcrumb::Navigation {
+                from: "/".into(),
+                to: "/about".into(),
+            };
+            let value = crumb.into_value(ctx);
+            assert_eq!(
+                stringify.call(&json, [&value])?.as_str()?,
+                r#"{"type":"navigation","from":"/","to":"/about"}"#
+            );
+            assert_eq!(
+                Breadcrumb::from_value(&value)?,
+                Breadcrumb::Navigation {
+                    from: "/".into(),
+                    to: "/about".into(),
+                }
+            );
+            let value = Breadcrumb::UserAction.into_value(ctx);
+            assert_eq!(Breadcrumb::from_value(&value)?, Breadcrumb::UserAction);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_function_attribute() {
+        use std::num::ParseIntError;
+
+        use crate::{FromValue, IntoValue};
+
+        #[crate::function(name = "parseVersion")]
+        fn parse_version(version: String) -> Result<Vec<i32>, ParseIntError> {
+            version.split('.').map(|x| x.parse()).collect()
+        }
+
+        #[crate::function]
+        fn greet(ctx: &Context, name: String, greeting: Option<String>) -> Value {
+            let greeting = greeting.as_deref().unwrap_or("Hello");
+            format!("{}, {}!", greeting, name).into_value(ctx)
+        }
+
+        Context::run(|ctx| {
+            parse_version::register(&ctx.global())?;
+            greet::register(&ctx.global())?;
+            assert_eq!(parse_version::NAME, "parseVersion");
+
+            let rv = ctx.eval("parseVersion('1.2.3')")?;
+            assert_eq!(Vec::<i32>::from_value(&rv)?, vec![1, 2, 3]);
+            let rv = ctx.eval("greet('World')")?;
+            assert_eq!(rv.as_str()?, "Hello, World!");
+            let rv = ctx.eval("greet('World', 'Hi')")?;
+            assert_eq!(rv.as_str()?, "Hi, World!");
+
+            let rv = ctx.eval(
+                "[() => parseVersion('1.x'), () => greet(), () => greet(1), \
+                 () => parseVersion('1', 2)].map(f => { try { f() } catch (e) { return e.message } })",
+            )?;
+            assert_eq!(
+                Vec::<String>::from_value(&rv)?,
+                vec![
+                    "invalid digit found in string",
+                    "invalid argument: `greet` expects 1 to 2 arguments, got 0",
+                    "invalid argument: argument `name` of `greet`: expected a string, got Number",
+                    "invalid argument: `parseVersion` expects 1 argument, got 2",
+                ]
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_heap_snapshot() {
+        Context::run(|ctx| {
+            ctx.eval(
+                "class Session { get fail() { throw new Error('getter ran'); } }
+                 globalThis.cache = new Map();
+                 for (let i = 0; i < 50; i++) {
+                     const session = new Session();
+                     session.token = 'x'.repeat(1000) + i;
+                     cache.set(i, session);
+                 }
+                 globalThis.buffer = new Uint8Array(4096);",
+            )?;
+            let snapshot = ctx.rt().heap_snapshot()?;
+            assert_eq!(snapshot.memory.obj_count, snapshot.objects.count);
+            assert!(snapshot.reachable_objects <= snapshot.objects.count);
+
+            let session = snapshot
+                .classes
+                .iter()
+                .find(|x| x.class == "Session")
+                .unwrap();
+            assert_eq!(session.count, 50);
+            assert_eq!(session.properties, 50);
+            let buffer = snapshot
+                .classes
+                .iter()
+                .find(|x| x.class == "ArrayBuffer")
+                .unwrap();
+            assert!(buffer.size >= 4096);
+
+            assert!(snapshot.reachable_string_length >= 50 * 1000);
+            assert_eq!(snapshot.largest_strings[0].length, 1002);
+            assert_eq!(snapshot.largest_strings[0].preview.len(), 64);
+
+            let json = snapshot.to_json();
+            assert!(json.starts_with(r#"{"memory":{"malloc_size":"#));
+            assert!(json.contains(r#"{"class":"Session","count":50,"properties":50,"#));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_message_channel() {
+        use crate::{MessageChannel, Runtime};
+
+        let rt = Runtime::new().unwrap();
+        let supervisor = Context::new(&rt).unwrap();
+        let worker = Context::new(&rt).unwrap();
+        let channel = MessageChannel::new(&supervisor, &worker).unwrap();
+        supervisor
+            .with_global(|global| global.set_property("port", channel.port1().object()))
+            .unwrap();
+        worker
+            .with_global(|global| global.set_property("port", channel.port2().object()))
+            .unwrap();
+
+        worker
+            .eval(
+                r#"
+                port.onmessage = (event) => {
+                    const data = event.data;
+                    port.postMessage({
+                        sum: data.values.reduce((a, b) => a + b, 0),
+                        cyclic: data.self === data,
+                        date: data.date instanceof Date,
+                    });
+                };
+                "#,
+            )
+            .unwrap();
+        supervisor
+            .eval(
+                r#"
+                globalThis.results = [];
+                port.onmessage = (event) => results.push(event.data);
+                const msg = { values: [1, 2, 3], date: new Date(0) };
+                msg.self = msg;
+                port.postMessage(msg);
+                "#,
+            )
+            .unwrap();
+        assert_eq!(channel.dispatch().unwrap(), 2);
+        let result = supervisor.eval("JSON.stringify(results)").unwrap();
+        assert_eq!(
+            result.to_string_lossy(),
+            r#"[{"sum":6,"cyclic":true,"date":true}]"#
+        );
+
+        let err = supervisor.eval("port.postMessage(() => 1)").unwrap_err();
+        assert!(err.to_string().contains("exception"));
+        channel.port2().close();
+        assert!(supervisor.eval("port.postMessage(1)").is_err());
+    }
+
+    #[test]
+    fn test_runtime_actor() {
+        use crate::{ActorError, OwnedValue, RuntimeActor};
+        use std::sync::Arc;
+
+        let actor = Arc::new(RuntimeActor::spawn().unwrap());
+        actor
+            .register_fn("double", |args| match args.first() {
+                Some(OwnedValue::Number(x)) => Ok(OwnedValue::Number(x * 2.0)),
+                _ => Err("expected a number".into()),
+            })
+            .unwrap();
+        actor.eval("function add(a, b) { return a + b; }").unwrap();
+
+        let other = actor.clone();
+        let rv = std::thread::spawn(move || {
+            other.call(
+                "add",
+                vec![OwnedValue::Number(20.0), OwnedValue::Number(1.0)],
+            )
+        })
+        .join()
+        .unwrap()
+        .unwrap();
+        assert_eq!(rv, OwnedValue::Number(21.0));
+        assert_eq!(
+            actor.eval("({a: [double(2), 'x'], b: null})").unwrap(),
+            OwnedValue::Object(vec![
+                (
+                    "a".into(),
+                    OwnedValue::Array(vec![
+                        OwnedValue::Number(4.0),
+                        OwnedValue::String("x".into())
+                    ])
+                ),
+                ("b".into(), OwnedValue::Null),
+            ])
+        );
+        assert!(matches!(
+            actor.eval("double('x')"),
+            Err(ActorError::JsException { .. })
+        ));
+    }
+
+    #[test]
+    fn test_stack_trace_limit() {
+        Context::run(|ctx| {
+            assert_eq!(ctx.stack_trace_limit(), None);
+            let limit = ctx.eval("Error.stackTraceLimit")?;
+            assert_eq!(limit.as_f64(), Some(f64::INFINITY));
+            ctx.eval("Error.stackTraceLimit = 2")?;
+            assert_eq!(ctx.stack_trace_limit(), Some(2));
+
+            // installing the API again keeps the limit
+            ctx.install_stack_trace_api()?;
+            assert_eq!(ctx.stack_trace_limit(), Some(2));
+            ctx.set_stack_trace_limit(None)?;
+            assert_eq!(ctx.stack_trace_limit(), None);
+
+            // empty contexts stay empty
+            let empty = Context::empty(ctx.rt())?;
+            assert_eq!(empty.stack_trace_limit(), None);
+            let capture = empty.eval("typeof Error.captureStackTrace")?;
+            assert_eq!(capture.to_string_lossy(), "undefined");
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_capture_stack_trace() {
+        Context::run(|ctx| {
+            let stack = ctx.eval(
+                r#"
+                function MyError() { Error.captureStackTrace(this, MyError); }
+                function outer() { return new MyError(); }
+                outer().stack
+                "#,
+            )?;
+            let stack = stack.to_string_lossy().to_string();
+            assert!(stack.trim_start().starts_with("at outer"), "{}", stack);
+
+            ctx.set_stack_trace_limit(Some(1))?;
+            assert_eq!(ctx.stack_trace_limit(), Some(1));
+            let err = ctx
+                .eval("function a() { throw new Error('x'); } function b() { a(); } b()")
+                .unwrap_err();
+            match err {
+                Error::JsException(exc) => assert_eq!(exc.frames().len(), 1),
+                err => panic!("unexpected error: {}", err),
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_error_context() {
+        use crate::{ActorError, OwnedValue, ResultExt};
+
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Error>();
+
+        Context::run(|ctx| {
+            let err = ctx
+                .eval("throw Object.assign(new Error('boom'), {code: 42})")
+                .context("failed to load config")
+                .unwrap_err();
+            assert_eq!(err.to_string(), "failed to load config");
+            assert_eq!(
+                std::error::Error::source(&err).unwrap().to_string(),
+                "JavaScript exception"
+            );
+            let exc = err.js_exception().unwrap();
+            assert_eq!(exc.message(), "Error: boom");
+            assert_eq!(
+                exc.value(),
+                Some(&OwnedValue::Object(vec![(
+                    "code".into(),
+                    OwnedValue::Number(42.0)
+                )]))
+            );
+            assert!(matches!(err.root(), Error::JsException(_)));
+
+            match ActorError::from(err) {
+                ActorError::JsException { message, .. } => {
+                    assert_eq!(message, "failed to load config: Error: boom")
+                }
+                err => panic!("unexpected error: {}", err),
+            }
+
+            let err = Error::Other(Box::new(std::fmt::Error)).context("formatting");
+            assert!(err.downcast_ref::<std::fmt::Error>().is_some());
+            assert!(err.js_exception().is_none());
+
+            #[cfg(feature = "anyhow")]
+            {
+                let err = ctx
+                    .eval("null.x")
+                    .with_context(|| format!("evaluating {}", "null.x"))
+                    .unwrap_err()
+                    .into_anyhow();
+                assert_eq!(err.to_string(), "evaluating null.x");
+                assert!(err
+                    .downcast_ref::<Error>()
+                    .and_then(Error::js_exception)
+                    .is_some());
+
+                let err = Error::from(anyhow::Error::new(std::fmt::Error));
+                assert!(err.downcast_ref::<std::fmt::Error>().is_some());
+                assert!(err
+                    .into_anyhow()
+                    .downcast_ref::<std::fmt::Error>()
+                    .is_some());
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_render_exception() {
+        Context::run(|ctx| {
+            let source = "function fail() {\n  throw new Error('boom');\n}\nfail();\n";
+            ctx.register_source("app.js", source);
+            assert_eq!(ctx.source("app.js").as_deref(), Some(source));
+            let exc = match ctx.eval_with_filename(source, "app.js").unwrap_err() {
+                Error::JsException(exc) => exc,
+                err => panic!("unexpected error: {}", err),
+            };
+            let rendered = exc.render();
+            let mut lines = rendered.lines();
+            assert_eq!(lines.next(), Some("Error: boom"));
+            assert!(lines.next().unwrap().starts_with(" --> app.js:2"));
+            assert!(rendered.contains("\n2 |   throw new Error('boom');\n"));
+            assert!(rendered.ends_with(&format!("  |\n{}", exc.stack().unwrap().trim_end())));
+
+            // without a registered source only the stack is shown
+            let exc = match ctx.eval_with_filename(source, "other.js").unwrap_err() {
+                Error::JsException(exc) => exc,
+                err => panic!("unexpected error: {}", err),
+            };
+            assert_eq!(
+                exc.render(),
+                format!("Error: boom\n{}", exc.stack().unwrap().trim_end())
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_module_source_loader() {
+        Context::run(|ctx| {
+            ctx.rt().set_module_source_loader(|name| {
+                (name == "lib/util.js")
+                    .then(|| "export function answer() {\n  return 42;\n}\n".into())
+            });
+            ctx.enable_coverage()?;
+            ctx.eval_module(
+                "import { answer } from './util.js';\nglobalThis.result = answer();\n",
+                "lib/main.js",
+            )?;
+            assert_eq!(ctx.eval("result")?.as_i64(), Some(42));
+            assert!(ctx
+                .eval_module("import { x } from './missing.js';\n", "lib/other.js")
+                .is_err());
+            let coverage = ctx.coverage()?;
+            let util = coverage
+                .scripts()
+                .iter()
+                .find(|x| x.filename == "lib/util.js")
+                .unwrap();
+            assert_eq!(util.covered_functions(), 1);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_coverage() {
+        Context::run(|ctx| {
+            ctx.enable_coverage()?;
+            ctx.eval_with_filename(
+                "function used(x) {\n  if (x) {\n    return 1;\n  }\n  return 2;\n}\nfunction unused() {\n  return 3;\n}\nused(false);\nused(false);\n",
+                "lib.js",
+            )?;
+            ctx.eval("used(true)")?;
+            let coverage = ctx.coverage()?;
+            assert_eq!(coverage.scripts().len(), 1);
+            let script = &coverage.scripts()[0];
+            assert_eq!(script.filename, "lib.js");
+            let hits = script
+                .lines
+                .iter()
+                .map(|x| (x.line, x.hits))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                hits,
+                [(1, 1), (2, 3), (3, 1), (5, 2), (7, 1), (8, 0), (10, 1), (11, 1)]
+            );
+            let functions = script
+                .functions
+                .iter()
+                .map(|x| (x.name.as_str(), x.hits))
+                .collect::<Vec<_>>();
+            assert_eq!(functions, [("used", 3), ("unused", 0)]);
+            assert!(coverage.to_lcov().contains("SF:lib.js\nFN:1,used\nFN:7,unused\n"));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_debugger() {
+        use crate::{Debugger, PauseReason, Resume};
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        Context::run(|ctx| {
+            let debugger = Debugger::attach(ctx)?;
+            assert!(Debugger::attach(ctx).is_err());
+            debugger.set_breakpoint("lib.js", 2);
+            debugger.set_pause_on_exceptions(true);
+            let pauses = Rc::new(RefCell::new(Vec::new()));
+            let recorded = pauses.clone();
+            debugger.set_pause_handler(move |pause| {
+                let scope = pause
+                    .scope()
+                    .into_iter()
+                    .filter(|(_, value)| !value.is_function())
+                    .map(|(name, value)| format!("{}={}", name, value.to_string_lossy()))
+                    .collect::<Vec<_>>();
+                recorded.borrow_mut().push((
+                    pause.reason(),
+                    pause.line(),
+                    pause.stack().first().map(|x| x.function.clone()),
+                    scope,
+                ));
+                match pause.reason() {
+                    PauseReason::Breakpoint => {
+                        assert_eq!(pause.evaluate("a + b").unwrap().as_i64(), Some(3));
+                        pause.evaluate("sum = 100").unwrap();
+                        Resume::StepOver
+                    }
+                    _ => Resume::Continue,
+                }
+            });
+            ctx.eval_with_filename(
+                "function add(a, b) {\n  let sum = a + b;\n  return sum;\n}\nfunction fail(x) {\n  throw new Error('boom ' + x);\n}\n",
+                "lib.js",
+            )?;
+            assert_eq!(ctx.eval("add(1, 2)")?.as_i64(), Some(100));
+            assert!(ctx.eval("fail(42)").is_err());
+            assert_eq!(
+                *pauses.borrow(),
+                [
+                    (
+                        PauseReason::Breakpoint,
+                        2,
+                        Some("add".to_string()),
+                        vec!["a=1".to_string(), "b=2".to_string()]
+                    ),
+                    (
+                        PauseReason::Step,
+                        3,
+                        Some("add".to_string()),
+                        vec!["a=1".to_string(), "b=2".to_string(), "sum=100".to_string()]
+                    ),
+                    (
+                        PauseReason::Exception,
+                        6,
+                        Some("fail".to_string()),
+                        vec!["x=42".to_string()]
+                    ),
+                ]
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_profiler() {
+        use crate::Profiler;
+        use std::time::Duration;
+
+        Context::run(|ctx| {
+            ctx.eval_with_filename(
+                r#"
+                function busy(n) { let x = 0; for (let i = 0; i < n; i++) { x += i % 7; } return x; }
+                function run() { for (let i = 0; i < 20; i++) { busy(100000); } }
+                "#,
+                "bench.js",
+            )?;
+            let profiler = Profiler::start(ctx, Duration::ZERO)?;
+            ctx.eval("run()")?;
+            let profile = profiler.stop();
+            assert!(!profile.samples().is_empty());
+            let functions = profile.functions();
+            assert_eq!(functions[0].function, "busy");
+            assert_eq!(functions[0].filename.as_deref(), Some("bench.js"));
+            let run = functions.iter().find(|x| x.function == "run").unwrap();
+            assert!(run.total_samples >= functions[0].self_samples);
+
+            let collapsed = profile.to_collapsed();
+            assert!(collapsed.contains("run (bench.js);busy (bench.js) "));
+            let total: usize = collapsed
+                .lines()
+                .filter_map(|line| line.rsplit_once(' ')?.1.parse::<usize>().ok())
+                .sum();
+            assert_eq!(total, profile.samples().len());
+            let speedscope = profile.to_speedscope("bench");
+            assert!(speedscope.contains(r#"{"name":"busy","file":"bench.js"}"#));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "intl")]
+    fn test_intl() {
+        Context::run(|ctx| {
+            ctx.install_intl()?;
+            let format = |code: &str| -> Result<String, Error> {
+                Ok(ctx.eval(code)?.to_string_lossy().to_string())
+            };
+            assert_eq!(
+                format("new Intl.NumberFormat('en-US').format(1234.5)")?,
+                "1,234.5"
+            );
+            assert_eq!(
+                format("(1234.5).toLocaleString('de-DE', {style: 'currency', currency: 'EUR'})")?,
+                "1.234,50\u{a0}€"
+            );
+            assert_eq!(
+                format("new Intl.DateTimeFormat('en-US', {dateStyle: 'long'}).format(Date.UTC(2024, 0, 5))")?,
+                "January 5, 2024"
+            );
+            assert_eq!(
+                format("new Date(Date.UTC(2024, 0, 5, 15, 4, 5)).toLocaleString('en-GB')")?,
+                "05/01/2024, 15:04:05"
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_borrowed_values_release_context() {
+        let rt = Runtime::new().unwrap();
+        let ctx = Context::new(&rt).unwrap();
+        let func = Value::from_func(&ctx, "id", |_, _, args| Ok(args[0].clone())).unwrap();
+        ctx.with_global(|global| global.set_property("id", func))
+            .unwrap();
+        assert_eq!(ctx.eval("id(42)").unwrap().as_i64(), Some(42));
+        ctx.with_global(|global| assert!(global.get_property("id").unwrap().is_function()));
+        assert!(ctx.global_ref().ptr_eq(&ctx.global()));
+        assert_eq!(ctx.ref_count(), 1);
+
+        assert_eq!(rt.ref_count(), 2);
+        drop(ctx);
+        assert_eq!(rt.ref_count(), 1);
+    }
+
+    #[test]
+    fn test_intern() {
+        Context::run(|ctx| {
+            // keys set while creating the context are interned already
+            let base = ctx.interned_strings();
+            let a = ctx.intern("pending");
+            let b = ctx.intern("pending");
+            assert!(a.ptr_eq(&b));
+            assert_eq!(a.to_string_lossy(), "pending");
+            assert!(!a.ptr_eq(&Value::from_primitive(ctx, "pending")));
+            assert!(!a.ptr_eq(&ctx.intern("done")));
+            assert_eq!(ctx.interned_strings(), base + 2);
+
+            // long strings are not kept
+            let long = "x".repeat(1000);
+            assert!(!ctx.intern(&long).ptr_eq(&ctx.intern(&long)));
+            assert_eq!(ctx.interned_strings(), base + 2);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_intern_evicts_least_recently_used() {
+        Context::run(|ctx| {
+            let base = ctx.interned_strings();
+            let pending = ctx.intern("pending");
+            let done = ctx.intern("done");
+            for i in 0..1022 - base {
+                ctx.intern(&i.to_string());
+            }
+            assert_eq!(ctx.interned_strings(), 1024);
+
+            // using "pending" again makes "done" the oldest string after
+            // the ones interned while creating the context
+            assert!(pending.ptr_eq(&ctx.intern("pending")));
+            for i in 0..=base {
+                ctx.intern(&format!("new {}", i));
+            }
+            assert_eq!(ctx.interned_strings(), 1024);
+            assert!(pending.ptr_eq(&ctx.intern("pending")));
+            assert!(!done.ptr_eq(&ctx.intern("done")));
+            assert_eq!(done.to_string_lossy(), "done");
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_intern_property_keys() {
+        Context::run(|ctx| {
+            let base = ctx.interned_strings();
+            let obj = Value::new_object(ctx);
+            obj.set_property("status", 1)?;
+            obj.set_property("status", 2)?;
+            obj.set_property("", 3)?;
+            assert_eq!(ctx.interned_strings(), base + 2);
+            assert_eq!(obj.get_property("status")?.as_i32(), Some(2));
+            assert_eq!(obj.get_property("")?.as_i32(), Some(3));
+            Ok(())
+        })
+        .unwrap();
+    }
 }
@@ -0,0 +1,60 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+
+use worthless_quickjs_sys::JSValue;
+
+use crate::context::Context;
+use crate::value::Value;
+
+/// A borrowed value that does not own a reference.
+///
+/// This is used for the arguments of functions invoked from JavaScript and
+/// other short-lived reads where the engine already keeps the value alive.
+/// It dereferences to [`Value`] and [`to_value`](Self::to_value) turns it
+/// into an owned value by incrementing the refcount.
+#[repr(transparent)]
+pub struct ValueRef<'a> {
+    // refers to the borrowed context without holding a reference to it and
+    // is never dropped, so neither the value nor the context are touched.
+    value: ManuallyDrop<Value>,
+    _marker: PhantomData<&'a Context>,
+}
+
+impl<'a> ValueRef<'a> {
+    /// Borrows a raw value without touching its refcount.
+    ///
+    /// The caller must ensure the value stays alive for `'a`.
+    pub(crate) unsafe fn from_raw(ctx: &'a Context, raw: JSValue) -> ValueRef<'a> {
+        ValueRef {
+            value: Value::borrow_raw(ctx, raw),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Converts a slice of borrowed values into a slice of values.
+    pub(crate) fn as_values<'b>(refs: &'b [ValueRef<'a>]) -> &'b [Value] {
+        // both ValueRef and ManuallyDrop are transparent over Value
+        unsafe { std::slice::from_raw_parts(refs.as_ptr() as *const Value, refs.len()) }
+    }
+
+    /// Creates an owned value.
+    pub fn to_value(&self) -> Value {
+        Value::clone(&self.value)
+    }
+}
+
+impl<'a> Deref for ValueRef<'a> {
+    type Target = Value;
+
+    fn deref(&self) -> &Value {
+        &self.value
+    }
+}
+
+impl<'a> fmt::Debug for ValueRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.value, f)
+    }
+}
@@ -0,0 +1,215 @@
+use syn::{Attribute, Error, Lit, LitStr, Meta, NestedMeta, Result};
+
+/// How the names of fields and variants are turned into keys.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RenameRule {
+    None,
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+}
+
+impl RenameRule {
+    fn parse(lit: &LitStr) -> Result<RenameRule> {
+        Ok(match lit.value().as_str() {
+            "lowercase" => RenameRule::Lower,
+            "UPPERCASE" => RenameRule::Upper,
+            "PascalCase" => RenameRule::Pascal,
+            "camelCase" => RenameRule::Camel,
+            "snake_case" => RenameRule::Snake,
+            "SCREAMING_SNAKE_CASE" => RenameRule::ScreamingSnake,
+            "kebab-case" => RenameRule::Kebab,
+            _ => return Err(Error::new_spanned(lit, "unknown rename rule")),
+        })
+    }
+
+    /// Renames a field (snake case) or variant (pascal case) name.
+    pub fn apply(self, name: &str) -> String {
+        let name = name.strip_prefix("r#").unwrap_or(name);
+        let words = split_words(name);
+        let capitalize = |word: &str| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
+                .unwrap_or_default()
+        };
+        match self {
+            RenameRule::None => name.to_string(),
+            RenameRule::Lower => words.concat(),
+            RenameRule::Upper => words.concat().to_uppercase(),
+            RenameRule::Pascal => words.iter().map(|x| capitalize(x)).collect(),
+            RenameRule::Camel => words
+                .iter()
+                .enumerate()
+                .map(|(idx, x)| if idx == 0 { x.clone() } else { capitalize(x) })
+                .collect(),
+            RenameRule::Snake => words.join("_"),
+            RenameRule::ScreamingSnake => words.join("_").to_uppercase(),
+            RenameRule::Kebab => words.join("-"),
+        }
+    }
+}
+
+/// Splits a name at underscores and where a lowercase letter or digit is
+/// followed by an uppercase letter.  The words are lowercased.
+fn split_words(name: &str) -> Vec<String> {
+    let mut rv = Vec::new();
+    let mut word = String::new();
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if c == '_' {
+            if !word.is_empty() {
+                rv.push(std::mem::take(&mut word));
+            }
+            prev_lower = false;
+            continue;
+        }
+        if c.is_uppercase() && prev_lower && !word.is_empty() {
+            rv.push(std::mem::take(&mut word));
+        }
+        prev_lower = c.is_lowercase() || c.is_ascii_digit();
+        word.extend(c.to_lowercase());
+    }
+    if !word.is_empty() {
+        rv.push(word);
+    }
+    rv
+}
+
+/// The `#[worthless(...)]` attributes of a struct or enum.
+pub struct ContainerAttrs {
+    pub rename_all: RenameRule,
+    pub tag: Option<String>,
+}
+
+impl ContainerAttrs {
+    pub fn parse(attrs: &[Attribute]) -> Result<ContainerAttrs> {
+        let mut rv = ContainerAttrs {
+            rename_all: RenameRule::None,
+            tag: None,
+        };
+        for meta in worthless_metas(attrs)? {
+            if meta.path().is_ident("rename_all") {
+                rv.rename_all = RenameRule::parse(&string_value(&meta)?)?;
+            } else if meta.path().is_ident("tag") {
+                rv.tag = Some(key_value(&meta)?);
+            } else {
+                return Err(Error::new_spanned(meta, "unknown attribute"));
+            }
+        }
+        Ok(rv)
+    }
+}
+
+/// The `#[worthless(...)]` attributes of a field.
+#[derive(Default)]
+pub struct FieldAttrs {
+    pub rename: Option<String>,
+    pub default: bool,
+    pub skip: bool,
+}
+
+impl FieldAttrs {
+    pub fn parse(attrs: &[Attribute]) -> Result<FieldAttrs> {
+        let mut rv = FieldAttrs::default();
+        for meta in worthless_metas(attrs)? {
+            match meta {
+                Meta::NameValue(_) if meta.path().is_ident("rename") => {
+                    rv.rename = Some(key_value(&meta)?);
+                }
+                Meta::Path(ref path) if path.is_ident("default") => rv.default = true,
+                Meta::Path(ref path) if path.is_ident("skip") => rv.skip = true,
+                _ => return Err(Error::new_spanned(meta, "unknown attribute")),
+            }
+        }
+        Ok(rv)
+    }
+}
+
+/// The `#[worthless(...)]` attributes of an enum variant.
+pub struct VariantAttrs {
+    pub rename: Option<String>,
+}
+
+impl VariantAttrs {
+    pub fn parse(attrs: &[Attribute]) -> Result<VariantAttrs> {
+        let mut rv = VariantAttrs { rename: None };
+        for meta in worthless_metas(attrs)? {
+            if meta.path().is_ident("rename") {
+                rv.rename = Some(key_value(&meta)?);
+            } else {
+                return Err(Error::new_spanned(meta, "unknown attribute"));
+            }
+        }
+        Ok(rv)
+    }
+}
+
+fn worthless_metas(attrs: &[Attribute]) -> Result<Vec<Meta>> {
+    let mut rv = Vec::new();
+    for attr in attrs {
+        if !attr.path.is_ident("worthless") {
+            continue;
+        }
+        match attr.parse_meta()? {
+            Meta::List(list) => {
+                for nested in list.nested {
+                    match nested {
+                        NestedMeta::Meta(meta) => rv.push(meta),
+                        NestedMeta::Lit(lit) => {
+                            return Err(Error::new_spanned(lit, "expected an attribute"))
+                        }
+                    }
+                }
+            }
+            meta => return Err(Error::new_spanned(meta, "expected #[worthless(...)]")),
+        }
+    }
+    Ok(rv)
+}
+
+fn string_value(meta: &Meta) -> Result<LitStr> {
+    match meta {
+        Meta::NameValue(nv) => match nv.lit {
+            Lit::Str(ref lit) => Ok(lit.clone()),
+            ref lit => Err(Error::new_spanned(lit, "expected a string")),
+        },
+        meta => Err(Error::new_spanned(meta, "expected a value")),
+    }
+}
+
+/// Reads a string that is used as a property key.
+fn key_value(meta: &Meta) -> Result<String> {
+    let lit = string_value(meta)?;
+    let value = lit.value();
+    if value.contains('\0') {
+        return Err(Error::new_spanned(lit, "keys cannot contain null bytes"));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename_rules() {
+        assert_eq!(RenameRule::Camel.apply("user_id"), "userId");
+        assert_eq!(RenameRule::Camel.apply("UserId"), "userId");
+        assert_eq!(RenameRule::Pascal.apply("user_id"), "UserId");
+        assert_eq!(
+            RenameRule::Snake.apply("HttpRequest2Xx"),
+            "http_request2_xx"
+        );
+        assert_eq!(RenameRule::ScreamingSnake.apply("NotFound"), "NOT_FOUND");
+        assert_eq!(RenameRule::Kebab.apply("r#type_name"), "type-name");
+        assert_eq!(RenameRule::Lower.apply("NotFound"), "notfound");
+        assert_eq!(RenameRule::Upper.apply("not_found"), "NOTFOUND");
+        assert_eq!(RenameRule::None.apply("r#type"), "type");
+    }
+}
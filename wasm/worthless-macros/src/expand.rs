@@ -0,0 +1,452 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{
+    parse_quote, Data, DeriveInput, Error, Fields, GenericParam, Generics, Ident, Path, Result,
+    Type,
+};
+
+use crate::attr::{ContainerAttrs, FieldAttrs, RenameRule, VariantAttrs};
+
+/// A field with its key and attributes.
+struct Field<'a> {
+    member: TokenStream,
+    binding: Ident,
+    key: String,
+    ty: &'a Type,
+    attrs: FieldAttrs,
+}
+
+fn collect_fields(fields: &Fields, rename_all: RenameRule) -> Result<Vec<Field<'_>>> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(idx, field)| {
+            let attrs = FieldAttrs::parse(&field.attrs)?;
+            let (member, name) = match field.ident {
+                Some(ref ident) => (quote!(#ident), ident.to_string()),
+                None => {
+                    let idx = syn::Index::from(idx);
+                    (quote!(#idx), idx.index.to_string())
+                }
+            };
+            Ok(Field {
+                member,
+                binding: format_ident!("__field{}", idx),
+                key: attrs
+                    .rename
+                    .clone()
+                    .unwrap_or_else(|| rename_all.apply(&name)),
+                ty: &field.ty,
+                attrs,
+            })
+        })
+        .collect()
+}
+
+/// Returns `true` if the type is spelled as an `Option`.
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(ty) => {
+            ty.qself.is_none() && ty.path.segments.last().is_some_and(|x| x.ident == "Option")
+        }
+        _ => false,
+    }
+}
+
+fn add_bounds(generics: &Generics, bound: TokenStream) -> Generics {
+    let mut generics = generics.clone();
+    for param in generics.params.iter_mut() {
+        if let GenericParam::Type(ref mut param) = *param {
+            param.bounds.push(parse_quote!(#bound));
+        }
+    }
+    generics
+}
+
+fn variant_key(variant: &syn::Variant, rename_all: RenameRule) -> Result<String> {
+    let attrs = VariantAttrs::parse(&variant.attrs)?;
+    Ok(attrs
+        .rename
+        .unwrap_or_else(|| rename_all.apply(&variant.ident.to_string())))
+}
+
+/// Generates the `IntoValue` implementation.
+pub fn into_value(input: &DeriveInput) -> Result<TokenStream> {
+    let name = &input.ident;
+    let container = ContainerAttrs::parse(&input.attrs)?;
+    let generics = add_bounds(&input.generics, quote!(::worthless_js_rt::IntoValue));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let body = match input.data {
+        Data::Struct(ref data) => {
+            if container.tag.is_some() {
+                return Err(Error::new(Span::call_site(), "only enums can be tagged"));
+            }
+            let fields = collect_fields(&data.fields, container.rename_all)?;
+            let (pattern, value) =
+                fields_into_value(&parse_quote!(Self), &data.fields, &fields, None);
+            quote! {
+                let #pattern = self;
+                #value
+            }
+        }
+        Data::Enum(ref data) => {
+            let arms = data
+                .variants
+                .iter()
+                .map(|variant| {
+                    let ident = &variant.ident;
+                    let key = variant_key(variant, container.rename_all)?;
+                    let fields = collect_fields(&variant.fields, RenameRule::None)?;
+                    let path = parse_quote!(Self::#ident);
+                    Ok(match (&container.tag, &variant.fields) {
+                        (None, Fields::Unit) => quote! {
+                            Self::#ident => ::worthless_js_rt::Value::from_primitive(ctx, #key),
+                        },
+                        (None, _) => {
+                            let (pattern, value) =
+                                fields_into_value(&path, &variant.fields, &fields, None);
+                            quote! {
+                                #pattern => {
+                                    let __obj = ::worthless_js_rt::Value::new_object(ctx);
+                                    __obj.set_property(#key, #value).unwrap();
+                                    __obj
+                                }
+                            }
+                        }
+                        (Some(tag), Fields::Named(_) | Fields::Unit) => {
+                            let (pattern, value) = fields_into_value(
+                                &path,
+                                &variant.fields,
+                                &fields,
+                                Some((tag, &key)),
+                            );
+                            quote!(#pattern => #value,)
+                        }
+                        (Some(_), Fields::Unnamed(_)) => {
+                            return Err(Error::new_spanned(
+                                variant,
+                                "tagged enums only support unit and struct variants",
+                            ))
+                        }
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => return Err(Error::new(Span::call_site(), "unions are not supported")),
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::worthless_js_rt::IntoValue for #name #ty_generics #where_clause {
+            fn into_value(self, ctx: &::worthless_js_rt::Context) -> ::worthless_js_rt::Value {
+                #body
+            }
+        }
+    })
+}
+
+/// Returns the pattern that binds the fields and the expression that
+/// converts them.
+///
+/// Named fields become an object, a single unnamed field is converted as
+/// is and multiple unnamed fields become an array.  The tag is only set
+/// for named fields and units.
+fn fields_into_value(
+    path: &Path,
+    kind: &Fields,
+    fields: &[Field],
+    tag: Option<(&String, &String)>,
+) -> (TokenStream, TokenStream) {
+    let bindings = fields.iter().map(|field| {
+        let member = &field.member;
+        let binding = &field.binding;
+        if field.attrs.skip {
+            quote!(#member: _)
+        } else {
+            quote!(#member: #binding)
+        }
+    });
+    let pattern = quote!(#path { #(#bindings),* });
+    let value = match kind {
+        Fields::Unnamed(_) if fields.len() == 1 && tag.is_none() => {
+            let binding = &fields[0].binding;
+            quote!(::worthless_js_rt::IntoValue::into_value(#binding, ctx))
+        }
+        Fields::Unnamed(_) => {
+            let items = fields.iter().filter(|x| !x.attrs.skip).map(|field| {
+                let binding = &field.binding;
+                quote!(__array.append(#binding).unwrap();)
+            });
+            quote! {{
+                let __array = ::worthless_js_rt::Value::new_array(ctx);
+                #(#items)*
+                __array
+            }}
+        }
+        Fields::Unit if tag.is_none() => {
+            quote!(::worthless_js_rt::Value::from_primitive(
+                ctx,
+                ::worthless_js_rt::Primitive::Null
+            ))
+        }
+        Fields::Named(_) | Fields::Unit => {
+            let tag = tag.map(|(tag, key)| quote!(__obj.set_property(#tag, #key).unwrap();));
+            let items = fields.iter().filter(|x| !x.attrs.skip).map(|field| {
+                let binding = &field.binding;
+                let key = &field.key;
+                // optional fields are left out instead of set to null
+                if is_option(field.ty) {
+                    quote! {
+                        if let ::std::option::Option::Some(__value) = #binding {
+                            __obj.set_property(#key, __value).unwrap();
+                        }
+                    }
+                } else {
+                    quote!(__obj.set_property(#key, #binding).unwrap();)
+                }
+            });
+            quote! {{
+                let __obj = ::worthless_js_rt::Value::new_object(ctx);
+                #tag
+                #(#items)*
+                __obj
+            }}
+        }
+    };
+    (pattern, value)
+}
+
+/// Generates the `FromValue` and `TryFrom<Value>` implementations.
+pub fn from_value(input: &DeriveInput) -> Result<TokenStream> {
+    let name = &input.ident;
+    let type_name = name.to_string();
+    let container = ContainerAttrs::parse(&input.attrs)?;
+    let generics = add_bounds(&input.generics, quote!(::worthless_js_rt::FromValue));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let source = format_ident!("__value");
+
+    let body = match input.data {
+        Data::Struct(ref data) => {
+            if container.tag.is_some() {
+                return Err(Error::new(Span::call_site(), "only enums can be tagged"));
+            }
+            let fields = collect_fields(&data.fields, container.rename_all)?;
+            let value = fields_from_value(
+                &parse_quote!(Self),
+                &data.fields,
+                &fields,
+                &source,
+                &type_name,
+            );
+            quote!(::std::result::Result::Ok(#value))
+        }
+        Data::Enum(ref data) => {
+            let mut units = Vec::new();
+            let mut objects = Vec::new();
+            for variant in data.variants.iter() {
+                let ident = &variant.ident;
+                let key = variant_key(variant, container.rename_all)?;
+                let fields = collect_fields(&variant.fields, RenameRule::None)?;
+                let context = format!("{}::{}", type_name, ident);
+                match (&container.tag, &variant.fields) {
+                    (None, Fields::Unit) => units.push(quote! {
+                        #key => return ::std::result::Result::Ok(Self::#ident),
+                    }),
+                    (None, _) => {
+                        let inner = format_ident!("__inner");
+                        let value = fields_from_value(
+                            &parse_quote!(Self::#ident),
+                            &variant.fields,
+                            &fields,
+                            &inner,
+                            &context,
+                        );
+                        objects.push(quote! {
+                            let #inner = #source.get_property(#key)?;
+                            if #inner.kind() != ::worthless_js_rt::ValueKind::Undefined {
+                                return ::std::result::Result::Ok(#value);
+                            }
+                        });
+                    }
+                    (Some(_), Fields::Named(_) | Fields::Unit) => {
+                        let value = fields_from_value(
+                            &parse_quote!(Self::#ident),
+                            &variant.fields,
+                            &fields,
+                            &source,
+                            &context,
+                        );
+                        units.push(quote! {
+                            #key => return ::std::result::Result::Ok(#value),
+                        });
+                    }
+                    (Some(_), Fields::Unnamed(_)) => {
+                        return Err(Error::new_spanned(
+                            variant,
+                            "tagged enums only support unit and struct variants",
+                        ))
+                    }
+                }
+            }
+            let dispatch = match container.tag {
+                None => quote! {
+                    match #source.kind() {
+                        ::worthless_js_rt::ValueKind::String => {
+                            match &*#source.as_str()? {
+                                #(#units)*
+                                _ => {}
+                            }
+                        }
+                        ::worthless_js_rt::ValueKind::Object => {
+                            #(#objects)*
+                        }
+                        _ => {}
+                    }
+                },
+                Some(ref tag) => quote! {
+                    if #source.kind() == ::worthless_js_rt::ValueKind::Object {
+                        let __tag = #source.get_property(#tag)?;
+                        if __tag.kind() == ::worthless_js_rt::ValueKind::String {
+                            match &*__tag.as_str()? {
+                                #(#units)*
+                                _ => {}
+                            }
+                        }
+                    }
+                },
+            };
+            quote! {
+                #dispatch
+                ::std::result::Result::Err(::worthless_js_rt::Error::InvalidValue(
+                    ::std::format!("expected a variant of `{}`", #type_name),
+                ))
+            }
+        }
+        Data::Union(_) => return Err(Error::new(Span::call_site(), "unions are not supported")),
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::worthless_js_rt::FromValue for #name #ty_generics #where_clause {
+            fn from_value(
+                #source: &::worthless_js_rt::Value,
+            ) -> ::std::result::Result<Self, ::worthless_js_rt::Error> {
+                #body
+            }
+        }
+
+        impl #impl_generics ::std::convert::TryFrom<::worthless_js_rt::Value>
+            for #name #ty_generics #where_clause
+        {
+            type Error = ::worthless_js_rt::Error;
+
+            fn try_from(
+                value: ::worthless_js_rt::Value,
+            ) -> ::std::result::Result<Self, ::worthless_js_rt::Error> {
+                <Self as ::worthless_js_rt::FromValue>::from_value(&value)
+            }
+        }
+    })
+}
+
+/// Returns the expression that converts the source value into the fields.
+///
+/// This is the reverse of [`fields_into_value`], the expression uses `?`
+/// to fail.
+fn fields_from_value(
+    path: &Path,
+    kind: &Fields,
+    fields: &[Field],
+    source: &Ident,
+    context: &str,
+) -> TokenStream {
+    let convert = |value: TokenStream, key: &str| {
+        quote! {
+            ::worthless_js_rt::FromValue::from_value(&#value).map_err(|__err| match __err {
+                ::worthless_js_rt::Error::InvalidValue(__msg) => {
+                    ::worthless_js_rt::Error::InvalidValue(
+                        ::std::format!("{}.{}: {}", #context, #key, __msg),
+                    )
+                }
+                __err => __err,
+            })?
+        }
+    };
+    let expected = |what: &str| {
+        let message = format!("expected {} for `{}`, got {{:?}}", what, context);
+        quote! {
+            ::worthless_js_rt::Error::InvalidValue(
+                ::std::format!(#message, #source.kind()),
+            )
+        }
+    };
+
+    match kind {
+        Fields::Unnamed(_) if fields.len() == 1 => {
+            let member = &fields[0].member;
+            let value = convert(quote!(#source), "0");
+            quote!(#path { #member: #value })
+        }
+        Fields::Unnamed(_) => {
+            let error = expected("an array");
+            let mut idx = 0usize;
+            let items = fields.iter().map(|field| {
+                let member = &field.member;
+                if field.attrs.skip {
+                    return quote!(#member: ::std::default::Default::default());
+                }
+                let value = convert(quote!(#source.get_by_index(#idx)?), &idx.to_string());
+                idx += 1;
+                quote!(#member: #value)
+            });
+            let items = items.collect::<Vec<_>>();
+            quote! {{
+                if !#source.is_array() {
+                    return ::std::result::Result::Err(#error);
+                }
+                #path { #(#items),* }
+            }}
+        }
+        Fields::Unit => quote!(#path),
+        Fields::Named(_) => {
+            let error = expected("an object");
+            let items = fields.iter().map(|field| {
+                let member = &field.member;
+                if field.attrs.skip {
+                    return quote!(#member: ::std::default::Default::default());
+                }
+                let key = &field.key;
+                let value = convert(quote!(__field), key);
+                if field.attrs.default {
+                    quote! {
+                        #member: {
+                            let __field = #source.get_property(#key)?;
+                            if __field.kind() == ::worthless_js_rt::ValueKind::Undefined {
+                                ::std::default::Default::default()
+                            } else {
+                                #value
+                            }
+                        }
+                    }
+                } else {
+                    quote! {
+                        #member: {
+                            let __field = #source.get_property(#key)?;
+                            #value
+                        }
+                    }
+                }
+            });
+            quote! {{
+                if #source.kind() != ::worthless_js_rt::ValueKind::Object {
+                    return ::std::result::Result::Err(#error);
+                }
+                #path { #(#items),* }
+            }}
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    AttributeArgs, Error, FnArg, ItemFn, Lit, Meta, NestedMeta, Pat, Result, ReturnType, Type,
+};
+
+/// Returns the last segment of a type spelled as a path.
+fn last_segment(ty: &Type) -> Option<&syn::PathSegment> {
+    match ty {
+        Type::Path(ty) if ty.qself.is_none() => ty.path.segments.last(),
+        _ => None,
+    }
+}
+
+/// Returns `true` if the type is a reference to a `Context`.
+fn is_context(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(ty) => last_segment(&ty.elem).is_some_and(|x| x.ident == "Context"),
+        _ => false,
+    }
+}
+
+fn is_option(ty: &Type) -> bool {
+    last_segment(ty).is_some_and(|x| x.ident == "Option")
+}
+
+fn is_result(ty: &Type) -> bool {
+    last_segment(ty).is_some_and(|x| x.ident == "Result")
+}
+
+fn parse_name(args: &AttributeArgs) -> Result<Option<String>> {
+    let mut rv = None;
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("name") => match nv.lit {
+                Lit::Str(ref lit) if !lit.value().contains('\0') => rv = Some(lit.value()),
+                ref lit => return Err(Error::new_spanned(lit, "expected a name")),
+            },
+            arg => return Err(Error::new_spanned(arg, "unknown attribute")),
+        }
+    }
+    Ok(rv)
+}
+
+/// Generates the struct that exports a function to JavaScript.
+pub fn function(args: AttributeArgs, item: ItemFn) -> Result<TokenStream> {
+    let sig = &item.sig;
+    let ident = &sig.ident;
+    let vis = &item.vis;
+    let name = parse_name(&args)?.unwrap_or_else(|| ident.to_string());
+    if let Some(asyncness) = sig.asyncness {
+        return Err(Error::new_spanned(
+            asyncness,
+            "async functions are not supported",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &sig.generics,
+            "generic functions are not supported",
+        ));
+    }
+
+    let mut call_args = Vec::new();
+    let mut extract = Vec::new();
+    let mut params = 0usize;
+    let mut required = 0usize;
+    for input in sig.inputs.iter() {
+        let arg = match input {
+            FnArg::Typed(arg) => arg,
+            FnArg::Receiver(receiver) => {
+                return Err(Error::new_spanned(receiver, "methods are not supported"))
+            }
+        };
+        if is_context(&arg.ty) {
+            call_args.push(quote!(ctx));
+            continue;
+        }
+        let ty = &arg.ty;
+        let binding = format_ident!("__arg{}", params);
+        let label = match *arg.pat {
+            Pat::Ident(ref pat) => format!("`{}`", pat.ident),
+            _ => format!("{}", params + 1),
+        };
+        extract.push(quote! {
+            let #binding = <#ty as ::worthless_js_rt::FromValue>::from_value(
+                args.get(#params).unwrap_or(&undefined),
+            )
+            .map_err(|__err| match __err {
+                ::worthless_js_rt::Error::InvalidValue(__msg) => {
+                    ::worthless_js_rt::Error::InvalidArgument(::std::format!(
+                        "argument {} of `{}`: {}",
+                        #label,
+                        Self::NAME,
+                        __msg
+                    ))
+                }
+                __err => __err,
+            })?;
+        });
+        call_args.push(quote!(#binding));
+        params += 1;
+        // trailing optional arguments may be left out
+        if !is_option(ty) {
+            required = params;
+        }
+    }
+
+    let arity = if required != params {
+        format!("{} to {} arguments", required, params)
+    } else if params == 1 {
+        "1 argument".to_string()
+    } else {
+        format!("{} arguments", params)
+    };
+    let returns_result = match sig.output {
+        ReturnType::Type(_, ref ty) => is_result(ty),
+        ReturnType::Default => false,
+    };
+    let call = quote!(#ident(#(#call_args),*));
+    let convert = if returns_result {
+        quote! {
+            match #call {
+                ::std::result::Result::Ok(__rv) => {
+                    ::std::result::Result::Ok(::worthless_js_rt::IntoValue::into_value(__rv, ctx))
+                }
+                ::std::result::Result::Err(__err) => {
+                    ::std::result::Result::Err(::worthless_js_rt::Error::from_host(__err))
+                }
+            }
+        }
+    } else {
+        quote!(::std::result::Result::Ok(
+            ::worthless_js_rt::IntoValue::into_value(#call, ctx)
+        ))
+    };
+    let doc = format!(" Exports [`{}`] to JavaScript as `{}`.", ident, name);
+
+    Ok(quote! {
+        #item
+
+        // a braced struct only takes the type namespace so it can share
+        // the name of the function and does not need to reach it by path
+        #[doc = #doc]
+        #[allow(non_camel_case_types)]
+        #vis struct #ident {}
+
+        impl #ident {
+            /// The name of the function in JavaScript.
+            pub const NAME: &'static str = #name;
+
+            /// Creates the JavaScript function.
+            pub fn to_value(
+                ctx: &::worthless_js_rt::Context,
+            ) -> ::std::result::Result<::worthless_js_rt::Value, ::worthless_js_rt::Error> {
+                ::worthless_js_rt::Value::from_host_fn(ctx, Self::NAME, Self::call)
+            }
+
+            /// Defines the function on a namespace object, eg: the global object.
+            pub fn register(
+                namespace: &::worthless_js_rt::Value,
+            ) -> ::std::result::Result<(), ::worthless_js_rt::Error> {
+                namespace.set_property(Self::NAME, Self::to_value(namespace.ctx())?)
+            }
+
+            fn call(
+                ctx: &::worthless_js_rt::Context,
+                _this: &::worthless_js_rt::Value,
+                args: &[::worthless_js_rt::Value],
+            ) -> ::std::result::Result<::worthless_js_rt::Value, ::worthless_js_rt::Error> {
+                if args.len() < #required || args.len() > #params {
+                    return ::std::result::Result::Err(::worthless_js_rt::Error::InvalidArgument(
+                        ::std::format!(
+                            "`{}` expects {}, got {}",
+                            Self::NAME,
+                            #arity,
+                            args.len()
+                        ),
+                    ));
+                }
+                #[allow(unused_variables)]
+                let undefined = ::worthless_js_rt::Value::from_primitive(
+                    ctx,
+                    ::worthless_js_rt::Primitive::Undefined,
+                );
+                #(#extract)*
+                #convert
+            }
+        }
+    })
+}
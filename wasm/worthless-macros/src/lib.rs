@@ -0,0 +1,87 @@
+//! Macros that convert Rust types from and to JavaScript values of
+//! `worthless-js-rt` and export Rust functions to JavaScript.
+//!
+//! `#[derive(IntoValue)]` implements `IntoValue` and `#[derive(FromValue)]`
+//! implements `FromValue` and `TryFrom<Value>`.  The generated code refers
+//! to `worthless_js_rt`, so the crate has to be a direct dependency.
+//!
+//! Structs with named fields become objects, newtype structs are converted
+//! as their field and other tuple structs become arrays.  Fields of type
+//! `Option` are left out when they are `None` and may be missing or null.
+//! Unit variants of enums become strings, other variants become an object
+//! with the variant name as only key unless a tag is set.
+//!
+//! The conversions are configured with `#[worthless(...)]` attributes:
+//!
+//! * `rename_all = "..."` on the type renames all fields or variants with
+//!   one of `lowercase`, `UPPERCASE`, `PascalCase`, `camelCase`,
+//!   `snake_case`, `SCREAMING_SNAKE_CASE` or `kebab-case`.
+//! * `tag = "..."` on an enum stores the variant name under this key of the
+//!   object with the fields, this works for unit and struct variants.
+//! * `rename = "..."` on a field or variant sets its key.
+//! * `default` on a field uses the default value if the key is missing.
+//! * `skip` on a field leaves it out and uses the default value.
+//!
+//! ```ignore
+//! use worthless_js_rt::{FromValue, IntoValue};
+//!
+//! #[derive(IntoValue, FromValue)]
+//! #[worthless(rename_all = "camelCase")]
+//! struct Event {
+//!     event_id: String,
+//!     #[worthless(rename = "ts")]
+//!     timestamp: f64,
+//!     release: Option<String>,
+//! }
+//! ```
+//!
+//! `#[function]` exports a plain Rust function to JavaScript.  Arguments are
+//! converted with `FromValue`, the return value with `IntoValue` and errors
+//! of a `Result` become exceptions.  A `&Context` argument receives the
+//! calling context.  The function is called with exactly as many arguments
+//! as it takes, trailing `Option` arguments may be left out.  Next to the
+//! function a struct of the same name is generated with `NAME`, `to_value`
+//! and `register`.  `#[function(name = "...")]` sets the name in JavaScript.
+//!
+//! ```ignore
+//! #[worthless_js_rt::function(name = "parseVersion")]
+//! fn parse_version(version: String) -> Result<Vec<i32>, ParseIntError> {
+//!     version.split('.').map(|x| x.parse()).collect()
+//! }
+//!
+//! parse_version::register(&ctx.global())?;
+//! ```
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, AttributeArgs, DeriveInput, ItemFn};
+
+mod attr;
+mod expand;
+mod function;
+
+/// Derives `IntoValue` for a struct or enum.
+#[proc_macro_derive(IntoValue, attributes(worthless))]
+pub fn derive_into_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand::into_value(&input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// Derives `FromValue` and `TryFrom<Value>` for a struct or enum.
+#[proc_macro_derive(FromValue, attributes(worthless))]
+pub fn derive_from_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand::from_value(&input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// Exports a Rust function to JavaScript.
+#[proc_macro_attribute]
+pub fn function(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let input = parse_macro_input!(input as ItemFn);
+    function::function(args, input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
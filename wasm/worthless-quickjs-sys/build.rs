@@ -1,28 +1,106 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{env, fs};
 
 fn main() {
     let here = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
-    let wasi_sdk_path = here.join("wasi-sdk");
+    let target = env::var("TARGET").unwrap();
+    let is_wasi = target == "wasm32-wasi";
+    let bignum = env::var_os("CARGO_FEATURE_BIGNUM").is_some();
+    let opt_size = env::var_os("CARGO_FEATURE_OPT_SIZE").is_some();
+    let libc = env::var_os("CARGO_FEATURE_LIBC").is_some();
+    let reproducible = env::var_os("CARGO_FEATURE_REPRODUCIBLE").is_some();
+    let wasi_sdk_path = env::var_os("WASI_SDK_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| here.join("wasi-sdk"));
+    println!("cargo:rerun-if-env-changed=WASI_SDK_PATH");
 
-    if fs::metadata(wasi_sdk_path.join("share/wasi-sysroot")).is_err() {
-        panic!("cannot build: wasi-sdk not found, run make download-wasi-sdk in root folder")
-    }
+    let mut build = cc::Build::new();
+    let mut clang_args = vec![
+        "-fvisibility=default".to_string(),
+        format!("--target={}", target),
+    ];
+
+    // wasm32-wasi is compiled with the WASI SDK.  Every other target is a
+    // native build for running tests and examples on the host which uses the
+    // default C compiler without a sysroot.
+    if is_wasi {
+        if fs::metadata(wasi_sdk_path.join("share/wasi-sysroot")).is_err() {
+            panic!(
+                "cannot build: wasi-sdk not found at {}, run make download-wasi-sdk in root \
+                 folder or set WASI_SDK_PATH",
+                wasi_sdk_path.display()
+            )
+        }
 
-    env::set_var("CC", wasi_sdk_path.join("bin/clang"));
-    env::set_var("AR", wasi_sdk_path.join("bin/ar"));
-    env::set_var(
-        "CFLAGS",
-        &format!(
+        env::set_var("CC", wasi_sdk_path.join("bin/clang"));
+        env::set_var("AR", wasi_sdk_path.join("bin/ar"));
+        let sysroot = format!(
             "--sysroot={}",
             wasi_sdk_path.join("share/wasi-sysroot").display()
-        ),
-    );
+        );
+        env::set_var("CFLAGS", &sysroot);
+        build.target("wasm32-wasi");
+        clang_args.push(sysroot);
+    }
 
-    cc::Build::new()
+    // libbf is only needed for the big number types and is the largest part
+    // of the build that can be dropped.
+    if bignum {
+        build.file("quickjs/libbf.c").define("CONFIG_BIGNUM", None);
+        clang_args.push("-DCONFIG_BIGNUM".to_string());
+    }
+
+    // quickjs-libc provides the std and os modules.  On WASI the process,
+    // terminal and dynamic loading functions it needs do not exist, so
+    // headers and stubs that fail with ENOSYS are compiled in.
+    if libc {
+        build
+            .file("quickjs/quickjs-libc.c")
+            .define("WL_QUICKJS_LIBC", None);
+        clang_args.push("-DWL_QUICKJS_LIBC".to_string());
+        if is_wasi {
+            build
+                .include("quickjs-api/wasi-compat")
+                .flag("-include")
+                .flag("quickjs-api/wasi-compat/compat.h")
+                .file("quickjs-api/wasi-compat/stubs.c")
+                .define("_WASI_EMULATED_SIGNAL", None)
+                .define("_WASI_EMULATED_PROCESS_CLOCKS", None);
+            println!("cargo:rustc-link-lib=wasi-emulated-signal");
+            println!("cargo:rustc-link-lib=wasi-emulated-process-clocks");
+        }
+    }
+
+    // optimizes for size, drops debug info and assertions and places every
+    // function in its own section so that the linker can strip unused code.
+    if opt_size {
+        build
+            .opt_level_str("z")
+            .debug(false)
+            .define("NDEBUG", None)
+            .flag_if_supported("-ffunction-sections")
+            .flag_if_supported("-fdata-sections");
+    } else {
+        build.opt_level(2).debug(true);
+    }
+
+    // the same sources must give the same object files no matter where and
+    // when they are built: no debug info (which records the build folder),
+    // no absolute paths in `__FILE__` and a fixed `__DATE__` and `__TIME__`.
+    // The clang of the WASI SDK does not honour `SOURCE_DATE_EPOCH`, so the
+    // macros are redefined instead.
+    if reproducible {
+        build
+            .debug(false)
+            .flag_if_supported(&format!("-ffile-prefix-map={}=.", here.display()))
+            .flag("-Wno-builtin-macro-redefined")
+            .define("__DATE__", "\"Jan  1 1970\"")
+            .define("__TIME__", "\"00:00:00\"");
+    }
+
+    build
         .files(&[
             "quickjs/cutils.c",
-            "quickjs/libbf.c",
             "quickjs/libregexp.c",
             "quickjs/libunicode.c",
             "quickjs/quickjs.c",
@@ -39,11 +117,9 @@ fn main() {
             )
             .as_str(),
         )
-        .define("CONFIG_BIGNUM", None)
         .define("WORTHLESS_PATCHES", None)
         //.define("DUMP_LEAKS", None)
         .cargo_metadata(true)
-        .debug(true)
         .flag_if_supported("-Wextra")
         .flag_if_supported("-Wno-sign-compare")
         .flag_if_supported("-Wno-missing-field-initializers")
@@ -55,27 +131,40 @@ fn main() {
         .flag_if_supported("-Wchar-subscripts")
         .flag_if_supported("-funsigned-char")
         .flag_if_supported("-Wno-implicit-const-int-float-conversion")
-        .target("wasm32-wasi")
-        .opt_level(2)
         .compile("quickjs");
 
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    write_bindings(&here, &target, &clang_args, &out_dir.join("bindings.rs"));
+
+    // the version is only ever taken from the file so it's stable across
+    // builds of the same sources
+    println!("cargo:rerun-if-changed=quickjs/VERSION");
+
+    // pick up `make trigger-rebuild`
+    println!("cargo:rerun-if-changed=.rebuild");
+}
+
+#[cfg(feature = "bindgen")]
+fn write_bindings(_here: &Path, _target: &str, clang_args: &[String], out: &Path) {
     let bindings = bindgen::Builder::default()
         .header("quickjs-api/api.h")
         .parse_callbacks(Box::new(bindgen::CargoCallbacks))
-        .clang_args(&[
-            "-fvisibility=default",
-            &format!("--target={}", env::var("TARGET").unwrap()),
-            &format!(
-                "--sysroot={}",
-                wasi_sdk_path.join("share/wasi-sysroot").display()
-            ),
-        ])
+        .clang_args(clang_args)
         .generate()
         .unwrap();
+    bindings.write_to_file(out).unwrap();
+}
 
-    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
-    bindings.write_to_file(out_dir.join("bindings.rs")).unwrap();
-
-    // pick up `make trigger-rebuild`
-    println!("cargo:rerun-if-changed=.rebuild");
+/// Uses the pregenerated bindings for the target from the `bindings` folder.
+#[cfg(not(feature = "bindgen"))]
+fn write_bindings(here: &Path, target: &str, _clang_args: &[String], out: &Path) {
+    let prebuilt = here.join("bindings").join(format!("{}.rs", target));
+    println!("cargo:rerun-if-changed={}", prebuilt.display());
+    if let Err(err) = fs::copy(&prebuilt, out) {
+        panic!(
+            "cannot build: no pregenerated bindings for {} ({}), enable the bindgen feature \
+             or run make update-bindings",
+            target, err
+        );
+    }
 }
@@ -1,3 +1,5 @@
+#include <string.h>
+
 #include "api.h"
 
 const JSValue WL_JS_NULL = JS_NULL;
@@ -37,4 +39,249 @@ JSValue WL_JS_NewInt32(JSContext *ctx, int32_t val)
 JSValue WL_JS_NewBool(JSContext *ctx, int32_t val)
 {
     return JS_NewBool(ctx, val); 
-}
\ No newline at end of file
+}
+
+uint8_t *WL_JS_WriteBytecode(JSContext *ctx, size_t *psize, JSValueConst obj)
+{
+    return JS_WriteObject(ctx, psize, obj, JS_WRITE_OBJ_BYTECODE);
+}
+
+JSValue WL_JS_ReadBytecode(JSContext *ctx, const uint8_t *buf, size_t buf_len)
+{
+    return JS_ReadObject(ctx, buf, buf_len, JS_READ_OBJ_BYTECODE);
+}
+
+void WL_JS_FreeBuffer(JSContext *ctx, uint8_t *buf)
+{
+    js_free(ctx, buf);
+}
+
+static const char *const WL_TYPED_ARRAY_NAMES[WL_TYPED_ARRAY_COUNT] = {
+    "Uint8ClampedArray",
+    "Int8Array",
+    "Uint8Array",
+    "Int16Array",
+    "Uint16Array",
+    "Int32Array",
+    "Uint32Array",
+    "BigInt64Array",
+    "BigUint64Array",
+    "Float32Array",
+    "Float64Array",
+};
+
+/* QuickJS has no C API to create typed arrays so this invokes the constructor
+   from the global object with (buffer, offset, length). */
+JSValue WL_JS_NewTypedArray(JSContext *ctx, JSValueConst buffer, int kind,
+                            size_t offset, size_t length)
+{
+    JSValue global, ctor, rv;
+    JSValueConst args[3];
+
+    if (kind < 0 || kind >= WL_TYPED_ARRAY_COUNT) {
+        return JS_ThrowRangeError(ctx, "invalid typed array kind");
+    }
+    global = JS_GetGlobalObject(ctx);
+    ctor = JS_GetPropertyStr(ctx, global, WL_TYPED_ARRAY_NAMES[kind]);
+    JS_FreeValue(ctx, global);
+    if (JS_IsException(ctor)) {
+        return ctor;
+    }
+    args[0] = buffer;
+    args[1] = JS_NewInt64(ctx, offset);
+    args[2] = JS_NewInt64(ctx, length);
+    rv = JS_CallConstructor(ctx, ctor, 3, args);
+    JS_FreeValue(ctx, ctor);
+    return rv;
+}
+
+/* A non-zero return value of the handler interrupts the running code with an
+   uncatchable "interrupted" error. */
+void WL_JS_SetInterruptHandler(JSRuntime *rt, WL_InterruptHandler *handler, void *opaque)
+{
+    JS_SetInterruptHandler(rt, handler, opaque);
+}
+
+void WL_JS_ClearInterruptHandler(JSRuntime *rt)
+{
+    JS_SetInterruptHandler(rt, NULL, NULL);
+}
+
+/* Classes are registered with the regular API (JS_NewClassID, JS_NewClass,
+   JS_SetClassProto, JS_SetOpaque and JS_GetOpaque2).  The tag accessors are
+   macros that depend on the value layout so they are wrapped here.  Floats
+   are normalized to JS_TAG_FLOAT64. */
+int WL_JS_ValueGetTag(JSValueConst v)
+{
+    return JS_VALUE_GET_NORM_TAG(v);
+}
+
+JS_BOOL WL_JS_IsObject(JSValueConst v)
+{
+    return JS_IsObject(v);
+}
+
+/* Compiling a module with JS_EVAL_FLAG_COMPILE_ONLY returns a value that
+   wraps the module definition.  Getting the pointer out is a macro. */
+JSModuleDef *WL_JS_GetModuleDef(JSValueConst func_val)
+{
+    if (JS_VALUE_GET_TAG(func_val) != JS_TAG_MODULE) {
+        return NULL;
+    }
+    return JS_VALUE_GET_PTR(func_val);
+}
+
+/* Equivalent of js_module_set_import_meta from quickjs-libc which is not
+   part of this build. */
+int WL_JS_SetImportMeta(JSContext *ctx, JSValueConst func_val, const char *url, JS_BOOL is_main)
+{
+    JSModuleDef *m;
+    JSValue meta_obj;
+
+    m = WL_JS_GetModuleDef(func_val);
+    if (!m) {
+        JS_ThrowTypeError(ctx, "not a module");
+        return -1;
+    }
+    meta_obj = JS_GetImportMeta(ctx, m);
+    if (JS_IsException(meta_obj)) {
+        return -1;
+    }
+    JS_DefinePropertyValueStr(ctx, meta_obj, "url", JS_NewString(ctx, url), JS_PROP_C_W_E);
+    JS_DefinePropertyValueStr(ctx, meta_obj, "main", JS_NewBool(ctx, is_main), JS_PROP_C_W_E);
+    JS_FreeValue(ctx, meta_obj);
+    return 0;
+}
+
+/* JSValue is NaN-boxed on wasm32 but a struct on 64bit native targets.  These
+   accessors keep the Rust side independent of the layout. */
+int32_t WL_JS_ValueGetInt(JSValueConst v)
+{
+    return JS_VALUE_GET_INT(v);
+}
+
+JS_BOOL WL_JS_ValueGetBool(JSValueConst v)
+{
+    return JS_VALUE_GET_BOOL(v);
+}
+
+JS_BOOL WL_JS_IsException(JSValueConst v)
+{
+    return JS_IsException(v);
+}
+
+JS_BOOL WL_JS_IsIdentical(JSValueConst a, JSValueConst b)
+{
+    if (JS_VALUE_GET_TAG(a) != JS_VALUE_GET_TAG(b)) {
+        return 0;
+    }
+    if (JS_VALUE_HAS_REF_COUNT(a)) {
+        return JS_VALUE_GET_PTR(a) == JS_VALUE_GET_PTR(b);
+    }
+#if defined(JS_NAN_BOXING)
+    return a == b;
+#else
+    return memcmp(&a.u, &b.u, sizeof(a.u)) == 0;
+#endif
+}
+
+/* Pointers are wrapped in an ArrayBuffer so that they can be stored in the
+   func_data array of JS_NewCFunctionData independently of the value layout.
+   The finalizer is invoked when the buffer is garbage collected. */
+typedef struct {
+    void *ptr;
+    WL_PointerFinalizer *finalizer;
+} WL_Pointer;
+
+static void wl_free_pointer(JSRuntime *rt, void *opaque, void *buf)
+{
+    WL_Pointer *p = buf;
+    if (p->finalizer) {
+        p->finalizer(rt, p->ptr);
+    }
+    js_free_rt(rt, p);
+}
+
+JSValue WL_JS_NewPointer(JSContext *ctx, void *ptr, WL_PointerFinalizer *finalizer)
+{
+    WL_Pointer *p;
+    JSValue rv;
+
+    p = js_malloc(ctx, sizeof(WL_Pointer));
+    if (!p) {
+        return JS_EXCEPTION;
+    }
+    p->ptr = ptr;
+    p->finalizer = finalizer;
+    rv = JS_NewArrayBuffer(ctx, (uint8_t *)p, sizeof(WL_Pointer), wl_free_pointer, NULL, 0);
+    if (JS_IsException(rv)) {
+        js_free(ctx, p);
+    }
+    return rv;
+}
+
+void *WL_JS_GetPointer(JSContext *ctx, JSValueConst val)
+{
+    size_t size;
+    WL_Pointer *p;
+
+    p = (WL_Pointer *)JS_GetArrayBuffer(ctx, &size, val);
+    if (!p || size != sizeof(WL_Pointer)) {
+        return NULL;
+    }
+    return p->ptr;
+}
+
+/* Atoms themselves are handled with the regular API (JS_NewAtom,
+   JS_NewAtomLen, JS_DupAtom and JS_FreeAtom) but property access by atom is
+   inline. */
+JSValue WL_JS_GetProperty(JSContext *ctx, JSValueConst this_obj, JSAtom prop)
+{
+    return JS_GetProperty(ctx, this_obj, prop);
+}
+
+int WL_JS_SetProperty(JSContext *ctx, JSValueConst this_obj, JSAtom prop, JSValue val)
+{
+    return JS_SetProperty(ctx, this_obj, prop, val);
+}
+
+static void wl_promise_rejection_tracker(JSContext *ctx, JSValueConst promise,
+                                         JSValueConst reason, JS_BOOL is_handled,
+                                         void *opaque)
+{
+    WL_PromiseRejectionTrackerData *data = opaque;
+    data->tracker(ctx, JS_DupValue(ctx, promise), JS_DupValue(ctx, reason), is_handled,
+                  data->opaque);
+}
+
+/* Unlike the raw tracker the WL_ tracker receives its own references to the
+   promise and reason which it has to free.  The data must stay alive until
+   the tracker is replaced or cleared by passing NULL. */
+void WL_JS_SetPromiseRejectionTracker(JSRuntime *rt, WL_PromiseRejectionTrackerData *data)
+{
+    if (data && data->tracker) {
+        JS_SetHostPromiseRejectionTracker(rt, wl_promise_rejection_tracker, data);
+    } else {
+        JS_SetHostPromiseRejectionTracker(rt, NULL, NULL);
+    }
+}
+
+/* Frees the result of JS_GetOwnPropertyNames (js_free_prop_enum is static). */
+void WL_JS_FreePropertyEnum(JSContext *ctx, JSPropertyEnum *tab, uint32_t len)
+{
+    uint32_t i;
+    if (tab) {
+        for (i = 0; i < len; i++) {
+            JS_FreeAtom(ctx, tab[i].atom);
+        }
+        js_free(ctx, tab);
+    }
+}
+
+/* Frees the values of a descriptor filled by JS_GetOwnProperty. */
+void WL_JS_FreePropertyDescriptor(JSContext *ctx, JSPropertyDescriptor *desc)
+{
+    JS_FreeValue(ctx, desc->getter);
+    JS_FreeValue(ctx, desc->setter);
+    JS_FreeValue(ctx, desc->value);
+}
@@ -1,4 +1,7 @@
 #include "../quickjs/quickjs.h"
+#ifdef WL_QUICKJS_LIBC
+#include "../quickjs/quickjs-libc.h"
+#endif
 
 int WL_GetRefCount(JSValue value);
 
@@ -11,4 +14,63 @@ JSValue WL_JS_NewBool(JSContext *ctx, JS_BOOL val);
 
 const JSValue WL_JS_NULL;
 const JSValue WL_JS_UNDEFINED;
-const JSValue WL_JS_TRUE;
\ No newline at end of file
+const JSValue WL_JS_TRUE;
+
+uint8_t *WL_JS_WriteBytecode(JSContext *ctx, size_t *psize, JSValueConst obj);
+JSValue WL_JS_ReadBytecode(JSContext *ctx, const uint8_t *buf, size_t buf_len);
+void WL_JS_FreeBuffer(JSContext *ctx, uint8_t *buf);
+
+enum {
+    WL_TYPED_ARRAY_UINT8_CLAMPED,
+    WL_TYPED_ARRAY_INT8,
+    WL_TYPED_ARRAY_UINT8,
+    WL_TYPED_ARRAY_INT16,
+    WL_TYPED_ARRAY_UINT16,
+    WL_TYPED_ARRAY_INT32,
+    WL_TYPED_ARRAY_UINT32,
+    WL_TYPED_ARRAY_BIG_INT64,
+    WL_TYPED_ARRAY_BIG_UINT64,
+    WL_TYPED_ARRAY_FLOAT32,
+    WL_TYPED_ARRAY_FLOAT64,
+    WL_TYPED_ARRAY_COUNT,
+};
+
+JSValue WL_JS_NewTypedArray(JSContext *ctx, JSValueConst buffer, int kind,
+                            size_t offset, size_t length);
+
+typedef int WL_InterruptHandler(JSRuntime *rt, void *opaque);
+
+void WL_JS_SetInterruptHandler(JSRuntime *rt, WL_InterruptHandler *handler, void *opaque);
+void WL_JS_ClearInterruptHandler(JSRuntime *rt);
+
+int WL_JS_ValueGetTag(JSValueConst v);
+JS_BOOL WL_JS_IsObject(JSValueConst v);
+
+JSModuleDef *WL_JS_GetModuleDef(JSValueConst func_val);
+int WL_JS_SetImportMeta(JSContext *ctx, JSValueConst func_val, const char *url, JS_BOOL is_main);
+
+int32_t WL_JS_ValueGetInt(JSValueConst v);
+JS_BOOL WL_JS_ValueGetBool(JSValueConst v);
+JS_BOOL WL_JS_IsException(JSValueConst v);
+JS_BOOL WL_JS_IsIdentical(JSValueConst a, JSValueConst b);
+
+typedef void WL_PointerFinalizer(JSRuntime *rt, void *ptr);
+
+JSValue WL_JS_NewPointer(JSContext *ctx, void *ptr, WL_PointerFinalizer *finalizer);
+void *WL_JS_GetPointer(JSContext *ctx, JSValueConst val);
+
+JSValue WL_JS_GetProperty(JSContext *ctx, JSValueConst this_obj, JSAtom prop);
+int WL_JS_SetProperty(JSContext *ctx, JSValueConst this_obj, JSAtom prop, JSValue val);
+
+typedef void WL_PromiseRejectionTracker(JSContext *ctx, JSValue promise, JSValue reason,
+                                        JS_BOOL is_handled, void *opaque);
+
+typedef struct {
+    WL_PromiseRejectionTracker *tracker;
+    void *opaque;
+} WL_PromiseRejectionTrackerData;
+
+void WL_JS_SetPromiseRejectionTracker(JSRuntime *rt, WL_PromiseRejectionTrackerData *data);
+
+void WL_JS_FreePropertyEnum(JSContext *ctx, JSPropertyEnum *tab, uint32_t len);
+void WL_JS_FreePropertyDescriptor(JSContext *ctx, JSPropertyDescriptor *desc);
@@ -0,0 +1,19 @@
+/* Declarations of POSIX functions that quickjs-libc uses but wasi-libc does
+   not provide.  This is force-included into the build on WASI. */
+#ifndef WL_WASI_COMPAT_H
+#define WL_WASI_COMPAT_H
+
+#include <stdio.h>
+#include <sys/types.h>
+
+pid_t fork(void);
+int execv(const char *path, char *const argv[]);
+int execvp(const char *file, char *const argv[]);
+int pipe(int fds[2]);
+int dup(int fd);
+int dup2(int oldfd, int newfd);
+int kill(pid_t pid, int sig);
+FILE *popen(const char *command, const char *type);
+int pclose(FILE *stream);
+
+#endif
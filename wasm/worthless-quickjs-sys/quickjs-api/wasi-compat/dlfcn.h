@@ -0,0 +1,16 @@
+/* Minimal dlfcn.h for building quickjs-libc against WASI.  Native modules
+   cannot be loaded, dlopen always fails. */
+#ifndef WL_WASI_COMPAT_DLFCN_H
+#define WL_WASI_COMPAT_DLFCN_H
+
+#define RTLD_LAZY 1
+#define RTLD_NOW 2
+#define RTLD_LOCAL 0
+#define RTLD_GLOBAL 256
+
+void *dlopen(const char *filename, int flags);
+void *dlsym(void *handle, const char *symbol);
+char *dlerror(void);
+int dlclose(void *handle);
+
+#endif
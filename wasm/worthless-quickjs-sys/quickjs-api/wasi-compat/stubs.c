@@ -0,0 +1,110 @@
+/* Stubs for the parts of quickjs-libc that would reach beyond the WASI
+   sandbox (processes, terminals and native modules).  All of them fail with
+   ENOSYS so the corresponding std/os functions throw or return an error. */
+#include <errno.h>
+#include <stdio.h>
+#include <sys/types.h>
+
+#include "compat.h"
+#include "dlfcn.h"
+#include "sys/ioctl.h"
+#include "sys/wait.h"
+#include "termios.h"
+
+pid_t fork(void)
+{
+    errno = ENOSYS;
+    return -1;
+}
+
+int execv(const char *path, char *const argv[])
+{
+    errno = ENOSYS;
+    return -1;
+}
+
+int execvp(const char *file, char *const argv[])
+{
+    errno = ENOSYS;
+    return -1;
+}
+
+int pipe(int fds[2])
+{
+    errno = ENOSYS;
+    return -1;
+}
+
+int dup(int fd)
+{
+    errno = ENOSYS;
+    return -1;
+}
+
+int dup2(int oldfd, int newfd)
+{
+    errno = ENOSYS;
+    return -1;
+}
+
+int kill(pid_t pid, int sig)
+{
+    errno = ENOSYS;
+    return -1;
+}
+
+FILE *popen(const char *command, const char *type)
+{
+    errno = ENOSYS;
+    return NULL;
+}
+
+int pclose(FILE *stream)
+{
+    errno = ENOSYS;
+    return -1;
+}
+
+pid_t waitpid(pid_t pid, int *status, int options)
+{
+    errno = ECHILD;
+    return -1;
+}
+
+int tcgetattr(int fd, struct termios *termios_p)
+{
+    errno = ENOTTY;
+    return -1;
+}
+
+int tcsetattr(int fd, int optional_actions, const struct termios *termios_p)
+{
+    errno = ENOTTY;
+    return -1;
+}
+
+int ioctl(int fd, unsigned long request, ...)
+{
+    errno = ENOTTY;
+    return -1;
+}
+
+void *dlopen(const char *filename, int flags)
+{
+    return NULL;
+}
+
+void *dlsym(void *handle, const char *symbol)
+{
+    return NULL;
+}
+
+char *dlerror(void)
+{
+    return "native modules are not supported on WASI";
+}
+
+int dlclose(void *handle)
+{
+    return 0;
+}
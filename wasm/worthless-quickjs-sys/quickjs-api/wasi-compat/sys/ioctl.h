@@ -0,0 +1,16 @@
+/* Minimal sys/ioctl.h for building quickjs-libc against WASI. */
+#ifndef WL_WASI_COMPAT_SYS_IOCTL_H
+#define WL_WASI_COMPAT_SYS_IOCTL_H
+
+struct winsize {
+    unsigned short ws_row;
+    unsigned short ws_col;
+    unsigned short ws_xpixel;
+    unsigned short ws_ypixel;
+};
+
+#define TIOCGWINSZ 0x5413
+
+int ioctl(int fd, unsigned long request, ...);
+
+#endif
@@ -0,0 +1,17 @@
+/* Minimal sys/wait.h for building quickjs-libc against WASI.  WASI cannot
+   spawn processes so there is nothing to wait for. */
+#ifndef WL_WASI_COMPAT_SYS_WAIT_H
+#define WL_WASI_COMPAT_SYS_WAIT_H
+
+#include <sys/types.h>
+
+#define WNOHANG 1
+
+#define WEXITSTATUS(s) (((s) & 0xff00) >> 8)
+#define WTERMSIG(s) ((s) & 0x7f)
+#define WIFEXITED(s) (!WTERMSIG(s))
+#define WIFSIGNALED(s) (((s) & 0xffff) - 1U < 0xffu)
+
+pid_t waitpid(pid_t pid, int *status, int options);
+
+#endif
@@ -0,0 +1,43 @@
+/* Minimal termios.h for building quickjs-libc against WASI.  There are no
+   terminals on WASI, the functions are stubbed in stubs.c. */
+#ifndef WL_WASI_COMPAT_TERMIOS_H
+#define WL_WASI_COMPAT_TERMIOS_H
+
+typedef unsigned int tcflag_t;
+typedef unsigned char cc_t;
+
+#define NCCS 32
+
+struct termios {
+    tcflag_t c_iflag;
+    tcflag_t c_oflag;
+    tcflag_t c_cflag;
+    tcflag_t c_lflag;
+    cc_t c_cc[NCCS];
+};
+
+#define IGNBRK 0000001
+#define BRKINT 0000002
+#define PARMRK 0000010
+#define ISTRIP 0000040
+#define INLCR 0000100
+#define IGNCR 0000200
+#define ICRNL 0000400
+#define IXON 0002000
+#define OPOST 0000001
+#define CSIZE 0000060
+#define CS8 0000060
+#define PARENB 0000400
+#define ISIG 0000001
+#define ICANON 0000002
+#define ECHO 0000010
+#define ECHONL 0000100
+#define IEXTEN 0100000
+#define VTIME 5
+#define VMIN 6
+#define TCSANOW 0
+
+int tcgetattr(int fd, struct termios *termios_p);
+int tcsetattr(int fd, int optional_actions, const struct termios *termios_p);
+
+#endif
@@ -0,0 +1,231 @@
+//! Rust equivalents of the `JS_*_DEF` macros for `JS_SetPropertyFunctionList`.
+
+use std::ffi::CStr;
+use std::os::raw::c_int;
+
+use crate::{
+    JSCFunction, JSCFunctionEnum_JS_CFUNC_generic, JSCFunctionEnum_JS_CFUNC_generic_magic,
+    JSCFunctionListEntry, JSCFunctionListEntry__bindgen_ty_1,
+    JSCFunctionListEntry__bindgen_ty_1__bindgen_ty_1,
+    JSCFunctionListEntry__bindgen_ty_1__bindgen_ty_2,
+    JSCFunctionListEntry__bindgen_ty_1__bindgen_ty_3,
+    JSCFunctionListEntry__bindgen_ty_1__bindgen_ty_4, JSCFunctionMagic, JSCFunctionType, JSContext,
+    JSValue, JS_SetPropertyFunctionList, JS_DEF_ALIAS, JS_DEF_CFUNC, JS_DEF_CGETSET, JS_DEF_OBJECT,
+    JS_DEF_PROP_DOUBLE, JS_DEF_PROP_INT32, JS_DEF_PROP_INT64, JS_DEF_PROP_STRING,
+    JS_DEF_PROP_UNDEFINED, JS_PROP_CONFIGURABLE, JS_PROP_WRITABLE,
+};
+
+/// The getter of a `JS_CGETSET_DEF`.
+pub type JSCGetter =
+    Option<unsafe extern "C" fn(ctx: *mut JSContext, this_val: JSValue) -> JSValue>;
+
+/// The setter of a `JS_CGETSET_DEF`.
+pub type JSCSetter =
+    Option<unsafe extern "C" fn(ctx: *mut JSContext, this_val: JSValue, val: JSValue) -> JSValue>;
+
+impl JSCFunctionListEntry {
+    const fn new(
+        name: &'static CStr,
+        prop_flags: u32,
+        def_type: u32,
+        magic: i16,
+        u: JSCFunctionListEntry__bindgen_ty_1,
+    ) -> JSCFunctionListEntry {
+        JSCFunctionListEntry {
+            name: name.as_ptr(),
+            prop_flags: prop_flags as u8,
+            def_type: def_type as u8,
+            magic,
+            u,
+        }
+    }
+
+    /// Equivalent of `JS_CFUNC_DEF`.
+    pub const fn cfunc(name: &'static CStr, length: u8, func: JSCFunction) -> JSCFunctionListEntry {
+        JSCFunctionListEntry::new(
+            name,
+            JS_PROP_WRITABLE | JS_PROP_CONFIGURABLE,
+            JS_DEF_CFUNC,
+            0,
+            JSCFunctionListEntry__bindgen_ty_1 {
+                func: JSCFunctionListEntry__bindgen_ty_1__bindgen_ty_1 {
+                    length,
+                    cproto: JSCFunctionEnum_JS_CFUNC_generic as u8,
+                    cfunc: JSCFunctionType { generic: func },
+                },
+            },
+        )
+    }
+
+    /// Equivalent of `JS_CFUNC_MAGIC_DEF`.
+    pub const fn cfunc_magic(
+        name: &'static CStr,
+        length: u8,
+        func: JSCFunctionMagic,
+        magic: i16,
+    ) -> JSCFunctionListEntry {
+        JSCFunctionListEntry::new(
+            name,
+            JS_PROP_WRITABLE | JS_PROP_CONFIGURABLE,
+            JS_DEF_CFUNC,
+            magic,
+            JSCFunctionListEntry__bindgen_ty_1 {
+                func: JSCFunctionListEntry__bindgen_ty_1__bindgen_ty_1 {
+                    length,
+                    cproto: JSCFunctionEnum_JS_CFUNC_generic_magic as u8,
+                    cfunc: JSCFunctionType {
+                        generic_magic: func,
+                    },
+                },
+            },
+        )
+    }
+
+    /// Equivalent of `JS_CGETSET_DEF`.
+    pub const fn getset(
+        name: &'static CStr,
+        getter: JSCGetter,
+        setter: JSCSetter,
+    ) -> JSCFunctionListEntry {
+        JSCFunctionListEntry::new(
+            name,
+            JS_PROP_CONFIGURABLE,
+            JS_DEF_CGETSET,
+            0,
+            JSCFunctionListEntry__bindgen_ty_1 {
+                getset: JSCFunctionListEntry__bindgen_ty_1__bindgen_ty_2 {
+                    get: JSCFunctionType { getter },
+                    set: JSCFunctionType { setter },
+                },
+            },
+        )
+    }
+
+    /// Equivalent of `JS_PROP_STRING_DEF`.
+    pub const fn prop_string(
+        name: &'static CStr,
+        value: &'static CStr,
+        prop_flags: u32,
+    ) -> JSCFunctionListEntry {
+        JSCFunctionListEntry::new(
+            name,
+            prop_flags,
+            JS_DEF_PROP_STRING,
+            0,
+            JSCFunctionListEntry__bindgen_ty_1 {
+                str_: value.as_ptr(),
+            },
+        )
+    }
+
+    /// Equivalent of `JS_PROP_INT32_DEF`.
+    pub const fn prop_int32(
+        name: &'static CStr,
+        value: i32,
+        prop_flags: u32,
+    ) -> JSCFunctionListEntry {
+        JSCFunctionListEntry::new(
+            name,
+            prop_flags,
+            JS_DEF_PROP_INT32,
+            0,
+            JSCFunctionListEntry__bindgen_ty_1 { i32_: value },
+        )
+    }
+
+    /// Equivalent of `JS_PROP_INT64_DEF`.
+    pub const fn prop_int64(
+        name: &'static CStr,
+        value: i64,
+        prop_flags: u32,
+    ) -> JSCFunctionListEntry {
+        JSCFunctionListEntry::new(
+            name,
+            prop_flags,
+            JS_DEF_PROP_INT64,
+            0,
+            JSCFunctionListEntry__bindgen_ty_1 { i64_: value },
+        )
+    }
+
+    /// Equivalent of `JS_PROP_DOUBLE_DEF`.
+    pub const fn prop_double(
+        name: &'static CStr,
+        value: f64,
+        prop_flags: u32,
+    ) -> JSCFunctionListEntry {
+        JSCFunctionListEntry::new(
+            name,
+            prop_flags,
+            JS_DEF_PROP_DOUBLE,
+            0,
+            JSCFunctionListEntry__bindgen_ty_1 { f64_: value },
+        )
+    }
+
+    /// Equivalent of `JS_PROP_UNDEFINED_DEF`.
+    pub const fn prop_undefined(name: &'static CStr, prop_flags: u32) -> JSCFunctionListEntry {
+        JSCFunctionListEntry::new(
+            name,
+            prop_flags,
+            JS_DEF_PROP_UNDEFINED,
+            0,
+            JSCFunctionListEntry__bindgen_ty_1 { i32_: 0 },
+        )
+    }
+
+    /// Equivalent of `JS_OBJECT_DEF`.
+    pub const fn object(
+        name: &'static CStr,
+        list: &'static [JSCFunctionListEntry],
+        prop_flags: u32,
+    ) -> JSCFunctionListEntry {
+        JSCFunctionListEntry::new(
+            name,
+            prop_flags,
+            JS_DEF_OBJECT,
+            0,
+            JSCFunctionListEntry__bindgen_ty_1 {
+                prop_list: JSCFunctionListEntry__bindgen_ty_1__bindgen_ty_4 {
+                    tab: list.as_ptr(),
+                    len: list.len() as c_int,
+                },
+            },
+        )
+    }
+
+    /// Equivalent of `JS_ALIAS_DEF`.
+    pub const fn alias(name: &'static CStr, from: &'static CStr) -> JSCFunctionListEntry {
+        JSCFunctionListEntry::new(
+            name,
+            JS_PROP_WRITABLE | JS_PROP_CONFIGURABLE,
+            JS_DEF_ALIAS,
+            0,
+            JSCFunctionListEntry__bindgen_ty_1 {
+                alias: JSCFunctionListEntry__bindgen_ty_1__bindgen_ty_3 {
+                    name: from.as_ptr(),
+                    base: -1,
+                },
+            },
+        )
+    }
+}
+
+/// A list of entries that can be stored in a `static`.
+///
+/// The entries only point to static data, so it's safe to share them.
+#[repr(transparent)]
+pub struct FunctionList<const N: usize>(pub [JSCFunctionListEntry; N]);
+
+unsafe impl<const N: usize> Sync for FunctionList<N> {}
+
+impl<const N: usize> FunctionList<N> {
+    /// Defines all entries on an object in one call.
+    ///
+    /// # Safety
+    ///
+    /// The context and object must be valid.
+    pub unsafe fn apply(&self, ctx: *mut JSContext, obj: JSValue) {
+        JS_SetPropertyFunctionList(ctx, obj, self.0.as_ptr(), N as c_int);
+    }
+}
@@ -6,3 +6,7 @@
 #![allow(non_upper_case_globals)]
 
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+
+mod function_list;
+
+pub use self::function_list::{FunctionList, JSCGetter, JSCSetter};
@@ -0,0 +1,81 @@
+//! The prebuilt runtime for plugins created with `worthless bundle`.
+//!
+//! Unlike a regular plugin the runtime does not embed a bundle.  It loads the
+//! bundle from the host which finds it in a custom section of the module.
+//! Bundles in the `module` format are evaluated as a module that loads its
+//! imports from the host as well.
+use worthless_bridge::{
+    Error as BridgeError, ErrorKind, Request, Value, BUNDLE_ENDPOINT, MODULE_ENDPOINT,
+};
+use worthless_guest::{call_host, Bundle, Error};
+use worthless_js_rt::Context;
+
+/// Loads the source of a module from the host.
+fn load_module(name: &str) -> Option<String> {
+    let req = Request::new(
+        MODULE_ENDPOINT,
+        Value::Map(vec![("name".into(), name.into())]),
+    );
+    match call_host(&req).ok()?.into_payload().ok()? {
+        Value::Map(items) => items
+            .into_iter()
+            .find_map(|(key, value)| match (key, value) {
+                (Value::Text(key), Value::Text(source)) if key == "source" => Some(source),
+                _ => None,
+            }),
+        _ => None,
+    }
+}
+
+fn init(ctx: &Context) -> Result<(), Error> {
+    let response = call_host(&Request::new(BUNDLE_ENDPOINT, Value::Null))?;
+    let payload = response.into_payload().map_err(Error::Protocol)?;
+    let field = |name: &str| match payload {
+        Value::Map(ref items) => items.iter().find_map(|(key, value)| match key {
+            Value::Text(key) if key == name => Some(value),
+            _ => None,
+        }),
+        _ => None,
+    };
+    let (name, format, data) = match (field("name"), field("format"), field("data")) {
+        (Some(Value::Text(name)), Some(Value::Text(format)), Some(Value::Bytes(data))) => {
+            (name, format, data)
+        }
+        _ => {
+            return Err(Error::Protocol(BridgeError::new(
+                ErrorKind::InternalError,
+                "host did not provide a bundle",
+            )))
+        }
+    };
+
+    if format == "module" {
+        let source = std::str::from_utf8(data).map_err(|_| {
+            Error::Protocol(BridgeError::new(
+                ErrorKind::InternalError,
+                "module bundle is not valid UTF-8",
+            ))
+        })?;
+        ctx.rt().set_module_source_loader(load_module);
+        ctx.eval_module(source, name)?;
+        // the module has evaluated once its imports and top level awaits ran
+        ctx.rt().run_pending_jobs()?;
+        return Ok(());
+    }
+
+    // the bundle is loaded once and lives as long as the plugin
+    let name: &'static str = Box::leak(name.clone().into_boxed_str());
+    let bundle = if format == "bytecode" {
+        Bundle::from_bytecode(name, Box::leak(data.clone().into_boxed_slice()))
+    } else {
+        // with the trailing NUL the source is evaluated without another copy
+        let mut source = Vec::with_capacity(data.len() + 1);
+        source.extend_from_slice(data);
+        source.push(0);
+        Bundle::new(name, Box::leak(source.into_boxed_slice()))
+    };
+    bundle.eval(ctx)?;
+    Ok(())
+}
+
+worthless_guest::export_plugin!(init);